@@ -1,20 +1,26 @@
-use rustyblue::{GattClient, HciSocket};
+use rustyblue::l2cap::ConnectionType;
+use rustyblue::{GattClient, HciSocket, L2capManager};
+use std::sync::Arc;
 use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Open the first HCI device
     println!("Opening HCI socket...");
-    let socket = HciSocket::open(0)?;
+    let socket = Arc::new(HciSocket::open(0)?);
 
-    // Initialize GATT client
+    // Initialize GATT client, sharing the socket with the L2CAP layer
     println!("Initializing GATT client...");
-    let mut client = GattClient::new(socket);
+    let l2cap_manager = Arc::new(L2capManager::with_hci_socket(
+        ConnectionType::LE,
+        socket.clone(),
+    ));
+    let mut client = GattClient::new(socket, l2cap_manager);
 
     // Scan for devices
     println!("Scanning for devices...");
-    rustyblue::scan_le(&client.socket(), Duration::from_secs(5), |report| {
+    rustyblue::scan_le(client.socket(), Duration::from_secs(5), |report| {
         println!(
-            "Device found: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} (RSSI: {})",
+            "Device found: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} (RSSI: {:?})",
             report.address[5],
             report.address[4],
             report.address[3],
@@ -97,6 +103,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Handle events for a while, stopping early if the connection is lost
+    println!("Processing events...");
+    for _ in 0..10 {
+        if let Err(e) = client.process_events(Some(Duration::from_secs(1))) {
+            println!("Event processing failed: {}", e);
+            if e.is_fatal() {
+                break;
+            }
+        }
+    }
+
     // Disconnect
     println!("Disconnecting...");
     client.disconnect()?;
@@ -5,14 +5,15 @@
 
 use rustyblue::att::{AttPermissions, AttServer, AttributeDatabase, SecurityLevel};
 use rustyblue::gatt::{CharacteristicProperty, GattServer, GattServerConfig, Uuid};
+use rustyblue::hci::constants::{OCF_WRITE_LOCAL_NAME, OCF_WRITE_SCAN_ENABLE, OGF_HOST_CTL};
 use rustyblue::hci::{HciCommand, HciSocket};
-use rustyblue::l2cap::L2capManager;
+use rustyblue::l2cap::{ConnectionType, L2capManager};
 use std::sync::Arc;
 use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Open HCI socket
-    let socket = HciSocket::open(0)?;
+    let socket = Arc::new(HciSocket::open(0)?);
     println!("Opened HCI socket");
 
     // Reset and initialize HCI
@@ -21,7 +22,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Reset HCI controller");
 
     // Create L2CAP manager
-    let l2cap_manager = Arc::new(L2capManager::new(socket.clone()));
+    let l2cap_manager = Arc::new(L2capManager::with_hci_socket(
+        ConnectionType::LE,
+        socket.clone(),
+    ));
 
     // Create ATT database and server
     let database = Arc::new(AttributeDatabase::new());
@@ -42,16 +46,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create a custom service
     let service_uuid = Uuid::from_u16(0x1800); // Generic Access service UUID
-    let service_handle = gatt_server.add_service(service_uuid.clone(), true)?;
+    let service_handle = gatt_server.add_service(service_uuid, true)?;
     println!("Added Generic Access service: {}", service_uuid);
 
     // Device Name characteristic
     let device_name_uuid = Uuid::from_u16(0x2A00); // Device Name characteristic UUID
-    let device_name_properties = CharacteristicProperty(CharacteristicProperty::READ);
+    let device_name_properties = CharacteristicProperty::READ;
     let device_name_perms = AttPermissions::read_only();
-    let device_name_handle = gatt_server.add_characteristic(
+    gatt_server.add_characteristic(
         service_handle,
-        device_name_uuid.clone(),
+        device_name_uuid,
         device_name_properties,
         device_name_perms,
         b"RustyBlue Server".to_vec(),
@@ -60,11 +64,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Appearance characteristic
     let appearance_uuid = Uuid::from_u16(0x2A01); // Appearance characteristic UUID
-    let appearance_properties = CharacteristicProperty(CharacteristicProperty::READ);
+    let appearance_properties = CharacteristicProperty::READ;
     let appearance_perms = AttPermissions::read_only();
-    let appearance_handle = gatt_server.add_characteristic(
+    gatt_server.add_characteristic(
         service_handle,
-        appearance_uuid.clone(),
+        appearance_uuid,
         appearance_properties,
         appearance_perms,
         // Generic Computer (0x0080)
@@ -74,20 +78,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create a custom service
     let custom_service_uuid = Uuid::from_u16(0x1234); // Custom service UUID
-    let custom_service_handle = gatt_server.add_service(custom_service_uuid.clone(), true)?;
+    let custom_service_handle = gatt_server.add_service(custom_service_uuid, true)?;
     println!("Added custom service: {}", custom_service_uuid);
 
     // Add a characteristic to the custom service
     let custom_char_uuid = Uuid::from_u16(0x5678); // Custom characteristic UUID
-    let custom_char_properties = CharacteristicProperty(
-        CharacteristicProperty::READ
-            | CharacteristicProperty::WRITE
-            | CharacteristicProperty::NOTIFY,
-    );
+    let custom_char_properties = CharacteristicProperty::READ
+        | CharacteristicProperty::WRITE
+        | CharacteristicProperty::NOTIFY;
     let custom_char_perms = AttPermissions::read_write();
     let custom_char_handle = gatt_server.add_characteristic(
         custom_service_handle,
-        custom_char_uuid.clone(),
+        custom_char_uuid,
         custom_char_properties,
         custom_char_perms,
         b"Hello, world!".to_vec(),
@@ -95,34 +97,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Added custom characteristic: {}", custom_char_uuid);
 
     // Add CCCD to the custom characteristic
-    let cccd_handle = gatt_server.add_cccd(custom_char_handle)?;
+    gatt_server.add_cccd(custom_char_handle)?;
     println!("Added CCCD to custom characteristic");
 
     // Set the controller to be discoverable and connectable
-    socket.send_command(&HciCommand::WriteScanEnable {
-        scan_enable: 0x03, // Inquiry and page scan enabled
-    })?;
+    socket.send_command(&HciCommand::new(
+        OGF_HOST_CTL,
+        OCF_WRITE_SCAN_ENABLE,
+        vec![0x03], // Inquiry and page scan enabled
+    ))?;
     socket.read_event()?;
     println!("Set controller to be discoverable and connectable");
 
     // Set the device name
-    socket.send_command(&HciCommand::WriteLocalName {
-        name: "RustyBlue Server".to_string(),
-    })?;
+    let mut local_name = b"RustyBlue Server".to_vec();
+    local_name.resize(248, 0); // The name parameter is always 248 bytes
+    socket.send_command(&HciCommand::new(
+        OGF_HOST_CTL,
+        OCF_WRITE_LOCAL_NAME,
+        local_name,
+    ))?;
     socket.read_event()?;
     println!("Set device name to 'RustyBlue Server'");
 
     // Enable LE advertising
     // Reset advertising parameters
     socket.send_command(&HciCommand::LeSetAdvertisingParameters {
-        min_interval: 0x0800, // 1.28s
-        max_interval: 0x0800, // 1.28s
-        adv_type: 0x00,       // Connectable, undirected
-        own_addr_type: 0x00,  // Public
-        peer_addr_type: 0x00, // Public
-        peer_addr: [0; 6],    // Not used
-        channel_map: 0x07,    // All channels
-        filter_policy: 0x00,  // No filtering
+        advertising_interval_min: 0x0800, // 1.28s
+        advertising_interval_max: 0x0800, // 1.28s
+        advertising_type: 0x00,           // Connectable, undirected
+        own_address_type: 0x00,           // Public
+        peer_address_type: 0x00,          // Public
+        peer_address: [0; 6],             // Not used
+        advertising_channel_map: 0x07,    // All channels
+        advertising_filter_policy: 0x00,  // No filtering
     })?;
     socket.read_event()?;
 
@@ -143,19 +151,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 16-bit service UUIDs (0x03)
     adv_data.push(0x03); // Length
     adv_data.push(0x03); // Complete 16-bit service UUIDs
-    adv_data.extend_from_slice(&custom_service_uuid.as_bytes());
-
-    // Pad to 31 bytes
-    while adv_data.len() < 31 {
-        adv_data.push(0);
-    }
+    adv_data.extend_from_slice(&0x1234u16.to_le_bytes());
 
-    socket.send_command(&HciCommand::LeSetAdvertisingData { data: adv_data })?;
+    socket.send_command(&HciCommand::le_set_advertising_data(&adv_data)?)?;
     socket.read_event()?;
 
     // Enable advertising
     socket.send_command(&HciCommand::LeSetAdvertiseEnable {
-        enable: 0x01, // Enable
+        advertising_enable: true,
     })?;
     socket.read_event()?;
     println!("Enabled LE advertising");
@@ -179,7 +182,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 // Handle connection events
                 if event.event_code == 0x3E
-                    && event.parameters.len() > 0
+                    && !event.parameters.is_empty()
                     && event.parameters[0] == 0x01
                 {
                     println!("Client connected");
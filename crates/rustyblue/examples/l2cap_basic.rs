@@ -23,7 +23,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Create L2CAP manager for Classic Bluetooth
-    let l2cap_manager = L2capManager::new(ConnectionType::Classic);
+    let l2cap_manager = L2capManager::with_hci_socket(ConnectionType::Classic, Arc::new(socket));
     println!("Created L2CAP manager");
 
     // Data callback function - this is called when data is received on the channel
@@ -71,7 +71,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Register a PSM (Protocol/Service Multiplexer) for SDP
     println!("Registering SDP PSM (0x0001)...");
     let policy = ConnectionPolicy {
-        min_security_level: SecurityLevel::None,
+        min_security_level: l2cap::SecurityLevel::None,
         authorization_required: false,
         auto_accept: true,
     };
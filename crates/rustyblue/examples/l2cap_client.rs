@@ -1,5 +1,3 @@
-use rustyblue::gap::*;
-use rustyblue::hci::*;
 use rustyblue::l2cap::*;
 /// Example demonstrating an L2CAP client that connects to a server
 use rustyblue::*;
@@ -12,11 +10,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("L2CAP Client Example");
     println!("--------------------");
 
+    // The ACL link is set up outside this example, e.g. with `hcitool cc`
+    let hci_handle = match std::env::args().nth(1).map(|arg| parse_handle(&arg)) {
+        Some(Some(handle)) => handle,
+        _ => {
+            eprintln!("Usage: l2cap_client <hci-handle>");
+            eprintln!("Connect to the server first, e.g. with `hcitool cc <bdaddr>`");
+            return Ok(());
+        }
+    };
+
     // Open HCI socket
     let socket = match HciSocket::open(0) {
         Ok(socket) => {
             println!("Successfully opened HCI socket");
-            socket
+            Arc::new(socket)
         }
         Err(err) => {
             eprintln!("Failed to open HCI socket: {}", err);
@@ -25,11 +33,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    // Create a GAP adapter for device discovery
-    let gap_adapter = GapAdapter::new(socket.clone());
-
     // Create L2CAP manager for Classic Bluetooth
-    let l2cap_manager = L2capManager::new(ConnectionType::Classic);
+    let l2cap_manager = L2capManager::with_hci_socket(ConnectionType::Classic, socket);
     println!("Created L2CAP manager");
 
     // Data callback function - called when data is received on the channel
@@ -73,7 +78,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Register for RFCOMM PSM (we will connect to this on the server)
     println!("Registering RFCOMM PSM (0x0003)...");
     let policy = ConnectionPolicy {
-        min_security_level: SecurityLevel::None,
+        min_security_level: l2cap::SecurityLevel::None,
         authorization_required: false,
         auto_accept: true,
     };
@@ -87,59 +92,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("RFCOMM PSM registered successfully");
 
-    // Discover nearby devices
-    println!("\nScanning for devices...");
-    let devices = match gap_adapter.discover_devices(Duration::from_secs(5)) {
-        Ok(devices) => {
-            println!("Found {} devices:", devices.len());
-            for (i, device) in devices.iter().enumerate() {
-                println!("{}: {} - {:?}", i + 1, device.address, device.name);
-            }
-            devices
-        }
-        Err(err) => {
-            eprintln!("Failed to discover devices: {}", err);
-            return Err(err.into());
-        }
-    };
-
-    if devices.is_empty() {
-        println!("No devices found. Exiting.");
-        return Ok(());
-    }
-
-    // Ask the user to select a device
-    print!("Select a device (1-{}): ", devices.len());
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let selection = input.trim().parse::<usize>().unwrap_or(0);
-
-    if selection < 1 || selection > devices.len() {
-        println!("Invalid selection. Exiting.");
-        return Ok(());
-    }
-
-    let selected_device = &devices[selection - 1];
-    println!(
-        "Selected device: {} - {:?}",
-        selected_device.address, selected_device.name
-    );
-
-    // Connect to the device
-    println!("Connecting to device...");
-    let hci_handle = match gap_adapter.connect(&selected_device.address) {
-        Ok(handle) => {
-            println!("Connected to device, HCI handle: 0x{:04X}", handle);
-            handle
-        }
-        Err(err) => {
-            eprintln!("Failed to connect to device: {}", err);
-            return Err(err.into());
-        }
-    };
-
     // Now establish an L2CAP connection
     println!("Establishing L2CAP connection to RFCOMM PSM...");
     let channel_id = match l2cap_manager.connect(PSM::RFCOMM, hci_handle) {
@@ -149,8 +101,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         Err(err) => {
             eprintln!("Failed to establish L2CAP connection: {}", err);
-            // Disconnect HCI
-            let _ = gap_adapter.disconnect(hci_handle);
             return Err(err.into());
         }
     };
@@ -158,6 +108,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Send and receive data loop
     println!("\nConnection established. Type messages to send or 'quit' to exit.");
 
+    let mut input = String::new();
     loop {
         print!("> ");
         io::stdout().flush()?;
@@ -190,13 +141,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(e) => println!("Failed to disconnect L2CAP channel: {}", e),
     }
 
-    // Disconnect HCI connection
-    println!("Disconnecting HCI connection...");
-    match gap_adapter.disconnect(hci_handle) {
-        Ok(_) => println!("HCI connection disconnected"),
-        Err(e) => println!("Failed to disconnect HCI connection: {}", e),
-    }
-
     // Clean up
     println!("Unregistering PSM...");
     l2cap_manager.unregister_psm(PSM::RFCOMM)?;
@@ -204,3 +148,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Example completed successfully.");
     Ok(())
 }
+
+/// Parse a connection handle given in decimal or as 0x-prefixed hex
+fn parse_handle(arg: &str) -> Option<u16> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
@@ -1,5 +1,3 @@
-use rustyblue::gap::*;
-use rustyblue::hci::*;
 use rustyblue::l2cap::*;
 /// Example demonstrating L2CAP Credit-Based Flow Control for BLE
 use rustyblue::*;
@@ -16,7 +14,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let socket = match HciSocket::open(0) {
         Ok(socket) => {
             println!("Successfully opened HCI socket");
-            socket
+            Arc::new(socket)
         }
         Err(err) => {
             eprintln!("Failed to open HCI socket: {}", err);
@@ -26,7 +24,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Create L2CAP manager for BLE
-    let l2cap_manager = L2capManager::new(ConnectionType::LE);
+    let l2cap_manager = Arc::new(L2capManager::with_hci_socket(
+        ConnectionType::LE,
+        socket.clone(),
+    ));
     println!("Created L2CAP manager for LE");
 
     // Data callback function - this is called when data is received on the channel
@@ -60,13 +61,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("  MTU: {}", mtu);
                 }
             }
-            ChannelEvent::ConnectionParameterUpdateRequest { identifier, params } => {
+            ChannelEvent::ConnectionParameterUpdateRequest { params, .. } => {
                 println!("Connection parameter update request:");
                 println!(
                     "  Interval: {}-{} (1.25ms units)",
-                    params.conn_interval_min, params.conn_interval_max
+                    params.interval_min, params.interval_max
                 );
-                println!("  Latency: {} events", params.conn_latency);
+                println!("  Latency: {} events", params.peripheral_latency);
                 println!("  Timeout: {} (10ms units)", params.supervision_timeout);
             }
             _ => {
@@ -79,7 +80,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Register the ATT protocol PSM (0x001F)
     println!("Registering ATT PSM (0x001F)...");
     let policy = ConnectionPolicy {
-        min_security_level: SecurityLevel::None,
+        min_security_level: l2cap::SecurityLevel::None,
         authorization_required: false,
         auto_accept: true,
     };
@@ -93,24 +94,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("ATT PSM registered successfully");
 
-    // Create a GAP adapter
-    let gap_adapter = GapAdapter::new(socket.clone());
+    // The GATT client handles LE connection setup on the shared socket
+    let mut client = GattClient::new(socket.clone(), l2cap_manager.clone());
 
     // Scan for BLE devices
     println!("\nScanning for BLE devices...");
-    let devices = match gap_adapter.scan_le(Duration::from_secs(5)) {
-        Ok(devices) => {
-            println!("Found {} BLE devices:", devices.len());
-            for (i, device) in devices.iter().enumerate() {
-                println!("{}: {} - {:?}", i + 1, device.address, device.name);
-            }
-            devices
-        }
-        Err(err) => {
-            eprintln!("Failed to scan for BLE devices: {}", err);
-            return Err(err.into());
+    let mut devices: Vec<LeAdvertisingReport> = Vec::new();
+    if let Err(err) = rustyblue::scan_le(&socket, Duration::from_secs(5), |report| {
+        if !devices.iter().any(|d| d.address == report.address) {
+            devices.push(report.clone());
         }
-    };
+    }) {
+        eprintln!("Failed to scan for BLE devices: {}", err);
+        return Err(err.into());
+    }
+
+    println!("Found {} BLE devices:", devices.len());
+    for (i, device) in devices.iter().enumerate() {
+        println!("{}: {}", i + 1, BdAddr::new(device.address));
+    }
 
     if devices.is_empty() {
         println!("No BLE devices found. Exiting.");
@@ -131,21 +133,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let selected_device = &devices[selection - 1];
-    println!(
-        "Selected device: {} - {:?}",
-        selected_device.address, selected_device.name
-    );
+    println!("Selected device: {}", BdAddr::new(selected_device.address));
 
-    // Connect to the device
+    // Connect to the device and wait for the connection handle
     println!("Connecting to BLE device...");
-    let hci_handle = match gap_adapter.connect_le(&selected_device.address) {
-        Ok(handle) => {
+    client.connect(selected_device.address, selected_device.address_type)?;
+    for _ in 0..10 {
+        if client.connection_handle().is_some() {
+            break;
+        }
+        client.process_events(Some(Duration::from_secs(1)))?;
+    }
+    let hci_handle = match client.connection_handle() {
+        Some(handle) => {
             println!("Connected to BLE device, HCI handle: 0x{:04X}", handle);
             handle
         }
-        Err(err) => {
-            eprintln!("Failed to connect to BLE device: {}", err);
-            return Err(err.into());
+        None => {
+            eprintln!("Failed to connect to BLE device");
+            return Err("connection timed out".into());
         }
     };
 
@@ -159,7 +165,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(err) => {
             eprintln!("Failed to establish L2CAP connection: {}", err);
             // Disconnect HCI
-            let _ = gap_adapter.disconnect(hci_handle);
+            let _ = client.disconnect();
             return Err(err.into());
         }
     };
@@ -220,7 +226,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Disconnect HCI connection
     println!("Disconnecting HCI connection...");
-    match gap_adapter.disconnect(hci_handle) {
+    match client.disconnect() {
         Ok(_) => println!("HCI connection disconnected"),
         Err(e) => println!("Failed to disconnect HCI connection: {}", e),
     }
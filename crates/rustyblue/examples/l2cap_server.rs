@@ -4,8 +4,6 @@ use rustyblue::*;
 use std::collections::HashMap;
 use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("L2CAP Server Example");
@@ -25,7 +23,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Create L2CAP manager for Classic Bluetooth
-    let l2cap_manager = L2capManager::new(ConnectionType::Classic);
+    let l2cap_manager = L2capManager::with_hci_socket(ConnectionType::Classic, Arc::new(socket));
     println!("Created L2CAP manager");
 
     // Keep track of connected channels
@@ -86,7 +84,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Register an RFCOMM PSM (most commonly used for profiles)
     println!("Registering RFCOMM PSM (0x0003)...");
     let policy = ConnectionPolicy {
-        min_security_level: SecurityLevel::None,
+        min_security_level: l2cap::SecurityLevel::None,
         authorization_required: false,
         auto_accept: true, // Automatically accept incoming connections
     };
@@ -94,8 +92,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     l2cap_manager.register_psm(
         PSM::RFCOMM,
         Some(Arc::new(Mutex::new(data_callback))),
-        Some(Arc::new(Mutex::new(event_callback))),
-        policy,
+        Some(Arc::new(Mutex::new(event_callback.clone()))),
+        policy.clone(),
     )?;
 
     println!("RFCOMM PSM registered successfully");
@@ -110,7 +108,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     l2cap_manager.register_psm(
         custom_psm,
-        Some(Arc::new(Mutex::new(data_callback.clone()))),
+        Some(Arc::new(Mutex::new(data_callback))),
         Some(Arc::new(Mutex::new(event_callback.clone()))),
         policy,
     )?;
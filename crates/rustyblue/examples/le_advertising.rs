@@ -28,14 +28,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Configure advertising parameters
     socket.send_command(&HciCommand::LeSetAdvertisingParameters {
-        min_interval: 0x0020,                               // 32 * 0.625ms = 20ms
-        max_interval: 0x0020,                               // 32 * 0.625ms = 20ms
-        advertising_type: 0x00,                             // Connectable undirected advertising
-        own_address_type: 0x01,                             // Random device address
-        peer_address_type: 0x00,                            // Public device address
+        advertising_interval_min: 0x0020, // 32 * 0.625ms = 20ms
+        advertising_interval_max: 0x0020, // 32 * 0.625ms = 20ms
+        advertising_type: 0x00,           // Connectable undirected advertising
+        own_address_type: 0x01,           // Random device address
+        peer_address_type: 0x00,          // Public device address
         peer_address: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // Not used
-        channel_map: 0x07,                                  // All channels
-        filter_policy: 0x00,                                // Allow all connections
+        advertising_channel_map: 0x07,    // All channels
+        advertising_filter_policy: 0x00,  // Allow all connections
     })?;
     println!("Set advertising parameters");
 
@@ -45,11 +45,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         0x01, // Type (Flags)
         0x06, // Value (LE General Discoverable Mode, BR/EDR Not Supported)
     ];
-    socket.send_command(&HciCommand::LeSetAdvertisingData { data: adv_data })?;
+    socket.send_command(&HciCommand::le_set_advertising_data(&adv_data)?)?;
     println!("Set advertising data");
 
     // Enable advertising
-    socket.send_command(&HciCommand::LeSetAdvertisingEnable { enable: true })?;
+    socket.send_command(&HciCommand::LeSetAdvertiseEnable {
+        advertising_enable: true,
+    })?;
     println!("Started advertising");
 
     // Wait for user input to stop advertising
@@ -58,7 +60,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::io::stdin().read_line(&mut input)?;
 
     // Disable advertising
-    socket.send_command(&HciCommand::LeSetAdvertisingEnable { enable: false })?;
+    socket.send_command(&HciCommand::LeSetAdvertiseEnable {
+        advertising_enable: false,
+    })?;
     println!("Stopped advertising");
 
     Ok(())
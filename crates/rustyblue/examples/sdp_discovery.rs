@@ -1,4 +1,4 @@
-use rustyblue::{SdpClient, Uuid};
+use rustyblue::SdpClient;
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -1,8 +1,9 @@
+use rustyblue::l2cap::ConnectionType;
 use rustyblue::smp::*;
 /// Example demonstrating SMP pairing between devices
 use rustyblue::*;
 use std::io::{self, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -13,7 +14,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let socket = match HciSocket::open(0) {
         Ok(socket) => {
             println!("Successfully opened HCI socket");
-            socket
+            Arc::new(socket)
         }
         Err(err) => {
             eprintln!("Failed to open HCI socket: {}", err);
@@ -23,13 +24,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Create an L2CAP manager
-    let l2cap_manager = Arc::new(L2capManager::new(ConnectionType::Le));
+    let l2cap_manager = Arc::new(L2capManager::with_hci_socket(
+        ConnectionType::LE,
+        socket.clone(),
+    ));
 
     // Create a key store
     let key_store = Box::new(MemoryKeyStore::new()) as Box<dyn KeyStore + Send + Sync>;
 
     // Create SMP manager
-    let smp_manager = SmpManager::new(l2cap_manager.clone(), Arc::new(socket.clone()), key_store);
+    let smp_manager = SmpManager::new(l2cap_manager.clone(), socket.clone(), key_store);
     println!("Created SMP manager");
 
     // Configure SMP features
@@ -96,10 +100,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     smp_manager.set_passkey_callback(|addr| -> SmpResult<u32> {
         println!("Enter passkey for device {}:", addr);
         print!("> ");
-        io::stdout().flush()?;
+        io::stdout()
+            .flush()
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        io::stdin()
+            .read_line(&mut input)
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
 
         let passkey = input.trim().parse::<u32>().unwrap_or(0);
 
@@ -110,34 +118,39 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     smp_manager.set_comparison_callback(|addr, value| -> SmpResult<bool> {
         println!("Does the value {} match on device {}? (y/n)", value, addr);
         print!("> ");
-        io::stdout().flush()?;
+        io::stdout()
+            .flush()
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        io::stdin()
+            .read_line(&mut input)
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
 
         let confirmation = input.trim().to_lowercase();
 
         Ok(confirmation == "y" || confirmation == "yes")
     });
 
-    // Create a GAP adapter for device discovery
-    let gap_adapter = GapAdapter::new(socket.clone());
+    // The GATT client handles LE connection setup on the shared socket
+    let mut client = GattClient::new(socket.clone(), l2cap_manager.clone());
 
     // Scan for devices
     println!("\nScanning for BLE devices...");
-    let devices = match gap_adapter.scan_le(Duration::from_secs(5)) {
-        Ok(devices) => {
-            println!("Found {} BLE devices:", devices.len());
-            for (i, device) in devices.iter().enumerate() {
-                println!("{}: {} - {:?}", i + 1, device.address, device.name);
-            }
-            devices
-        }
-        Err(err) => {
-            eprintln!("Failed to scan for devices: {}", err);
-            return Err(err.into());
+    let mut devices: Vec<LeAdvertisingReport> = Vec::new();
+    if let Err(err) = rustyblue::scan_le(&socket, Duration::from_secs(5), |report| {
+        if !devices.iter().any(|d| d.address == report.address) {
+            devices.push(report.clone());
         }
-    };
+    }) {
+        eprintln!("Failed to scan for devices: {}", err);
+        return Err(err.into());
+    }
+
+    println!("Found {} BLE devices:", devices.len());
+    for (i, device) in devices.iter().enumerate() {
+        println!("{}: {}", i + 1, BdAddr::new(device.address));
+    }
 
     if devices.is_empty() {
         println!("No devices found. Exiting.");
@@ -158,26 +171,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let selected_device = &devices[selection - 1];
-    println!(
-        "Selected device: {} - {:?}",
-        selected_device.address, selected_device.name
-    );
+    let selected_address = BdAddr::new(selected_device.address);
+    println!("Selected device: {}", selected_address);
 
-    // Connect to the device
+    // Connect to the device and wait for the connection handle
     println!("Connecting to device...");
-    let hci_handle = match gap_adapter.connect_le(&selected_device.address) {
-        Ok(handle) => {
-            println!("Connected to device, HCI handle: 0x{:04X}", handle);
-            handle
+    client.connect(selected_device.address, selected_device.address_type)?;
+    for _ in 0..10 {
+        if client.connection_handle().is_some() {
+            break;
         }
-        Err(err) => {
-            eprintln!("Failed to connect to device: {}", err);
-            return Err(err.into());
+        client.process_events(Some(Duration::from_secs(1)))?;
+    }
+    match client.connection_handle() {
+        Some(handle) => println!("Connected to device, HCI handle: 0x{:04X}", handle),
+        None => {
+            eprintln!("Failed to connect to device");
+            return Err("connection timed out".into());
         }
-    };
+    }
 
     // Check if already paired
-    let is_paired = match smp_manager.is_paired(&selected_device.address) {
+    let is_paired = match smp_manager.is_paired(&selected_address) {
         Ok(paired) => paired,
         Err(err) => {
             println!("Error checking pairing status: {}", err);
@@ -189,7 +204,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Device is already paired.");
 
         // Get security level
-        match smp_manager.security_level(&selected_device.address) {
+        match smp_manager.security_level(&selected_address) {
             Ok(level) => println!("Current security level: {:?}", level),
             Err(err) => println!("Error getting security level: {}", err),
         }
@@ -202,7 +217,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         io::stdin().read_line(&mut input)?;
 
         if input.trim().to_lowercase() == "y" {
-            match smp_manager.remove_pairing(&selected_device.address) {
+            match smp_manager.remove_pairing(&selected_address) {
                 Ok(_) => println!("Device unpaired successfully."),
                 Err(err) => println!("Error unpairing device: {}", err),
             }
@@ -210,7 +225,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         // Initiate pairing
         println!("Initiating pairing...");
-        match smp_manager.initiate_pairing(selected_device.address) {
+        match smp_manager.initiate_pairing(selected_address) {
             Ok(_) => println!("Pairing process started."),
             Err(err) => println!("Error starting pairing: {}", err),
         }
@@ -225,7 +240,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Disconnect from the device
     println!("Disconnecting...");
-    match gap_adapter.disconnect(hci_handle) {
+    match client.disconnect() {
         Ok(_) => println!("Disconnected successfully."),
         Err(err) => println!("Error disconnecting: {}", err),
     }
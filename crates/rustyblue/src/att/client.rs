@@ -3,129 +3,239 @@ use super::constants::*;
 use super::error::{AttError, AttErrorCode, AttResult};
 use super::types::*;
 use crate::gap::BdAddr;
-use crate::gatt::Uuid;
-use crate::l2cap::{ConnectionType, L2capError, L2capManager};
-use std::collections::HashMap;
+use crate::l2cap::constants::L2CAP_ATT_CID;
+use crate::l2cap::{L2capError, L2capManager};
+use crate::uuid::Uuid;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
-/// Value notification callback
-pub type NotificationCallback = Arc<Mutex<dyn FnMut(u16, &[u8]) -> AttResult<()> + Send + Sync>>;
+/// Value notification callback, called with the peer address, handle and value
+pub type NotificationCallback =
+    Arc<Mutex<dyn FnMut(BdAddr, u16, &[u8]) -> AttResult<()> + Send + Sync>>;
 
-/// Indication callback
-pub type IndicationCallback = Arc<Mutex<dyn FnMut(u16, &[u8]) -> AttResult<()> + Send + Sync>>;
+/// Indication callback, called with the peer address, handle and value
+pub type IndicationCallback =
+    Arc<Mutex<dyn FnMut(BdAddr, u16, &[u8]) -> AttResult<()> + Send + Sync>>;
 
-/// Transaction timeout (ms)
+/// Default transaction timeout (ms)
 const ATT_TRANSACTION_TIMEOUT: u64 = 30000;
 
-/// ATT Transaction
-struct AttTransaction {
-    /// Transaction opcode
-    opcode: u8,
+/// A request waiting for its response
+pub(super) struct PendingRequest {
+    /// Request opcode
+    pub(super) opcode: u8,
     /// Response data
-    response: Option<Vec<u8>>,
+    pub(super) response: Option<Vec<u8>>,
     /// Transaction start time
-    start_time: Instant,
+    pub(super) start_time: Instant,
     /// Error
-    error: Option<AttError>,
+    pub(super) error: Option<AttError>,
 }
 
+impl PendingRequest {
+    /// Create a request sent now
+    pub(super) fn new(opcode: u8) -> Self {
+        Self {
+            opcode,
+            response: None,
+            start_time: Instant::now(),
+            error: None,
+        }
+    }
+
+    /// Whether the response or an error has arrived
+    pub(super) fn is_answered(&self) -> bool {
+        self.response.is_some() || self.error.is_some()
+    }
+
+    /// Store a response PDU, or the error of an Error Response
+    pub(super) fn answer(&mut self, data: &[u8]) {
+        if data[0] == ATT_ERROR_RSP {
+            // Parse the error
+            if data.len() < 4 {
+                self.error = Some(AttError::InvalidPdu);
+            } else {
+                let error_code: AttErrorCode = data[3].into();
+                let handle = ((data[2] as u16) << 8) | (data[1] as u16);
+                self.error = Some(AttError::from_error_response(error_code, handle));
+            }
+        } else {
+            // Store the response data
+            self.response = Some(data.to_vec());
+        }
+    }
+
+    /// Take the result, parsing the response
+    pub(super) fn into_result<Resp: AttPacket>(self) -> Option<AttResult<Resp>> {
+        if let Some(error) = self.error {
+            return Some(Err(error));
+        }
+        self.response.map(|response| Resp::parse(&response))
+    }
+}
+
+/// Get the opcode of the request a response PDU answers
+pub(super) fn request_opcode(data: &[u8]) -> AttResult<u8> {
+    let opcode = *data.first().ok_or(AttError::InvalidPdu)?;
+    match opcode {
+        // For error responses, the request opcode is in the PDU
+        ATT_ERROR_RSP => data.get(1).copied().ok_or(AttError::InvalidPdu),
+        ATT_EXCHANGE_MTU_RSP => Ok(ATT_EXCHANGE_MTU_REQ),
+        ATT_FIND_INFO_RSP => Ok(ATT_FIND_INFO_REQ),
+        ATT_FIND_BY_TYPE_VALUE_RSP => Ok(ATT_FIND_BY_TYPE_VALUE_REQ),
+        ATT_READ_BY_TYPE_RSP => Ok(ATT_READ_BY_TYPE_REQ),
+        ATT_READ_RSP => Ok(ATT_READ_REQ),
+        ATT_READ_BLOB_RSP => Ok(ATT_READ_BLOB_REQ),
+        ATT_READ_MULTIPLE_RSP => Ok(ATT_READ_MULTIPLE_REQ),
+        ATT_READ_BY_GROUP_TYPE_RSP => Ok(ATT_READ_BY_GROUP_TYPE_REQ),
+        ATT_WRITE_RSP => Ok(ATT_WRITE_REQ),
+        ATT_PREPARE_WRITE_RSP => Ok(ATT_PREPARE_WRITE_REQ),
+        ATT_EXECUTE_WRITE_RSP => Ok(ATT_EXECUTE_WRITE_REQ),
+        _ => Err(AttError::InvalidPdu),
+    }
+}
+
+/// ATT state of a single connection
+struct PerConnectionAttState {
+    /// L2CAP channel ID
+    channel_id: u16,
+    /// HCI connection handle
+    hci_handle: u16,
+    /// Effective MTU
+    mtu: u16,
+    /// Requests sent and not yet answered, oldest first
+    pending_requests: VecDeque<PendingRequest>,
+    /// When the indication awaiting confirmation was sent
+    pending_indication: Option<Instant>,
+}
+
+/// An ATT bearer to one or more peers, usable in both client and server role
+pub type AttPeer = AttClient;
+
 /// ATT Client
+///
+/// A single client can talk to several ATT servers at once. Each peer is
+/// identified by its address, which every request takes as first parameter.
+///
+/// The fixed ATT channel is bidirectional, so a device that runs a GATT
+/// server as well can also send notifications and indications to its peers
+/// through the client, see [`AttClient::send_notification`].
 pub struct AttClient {
-    /// Remote device address
-    remote_addr: BdAddr,
     /// L2CAP manager
     l2cap_manager: Arc<L2capManager>,
-    /// L2CAP channel ID
-    channel_id: RwLock<Option<u16>>,
-    /// Client MTU
-    client_mtu: RwLock<u16>,
-    /// Server MTU
-    server_mtu: RwLock<u16>,
-    /// Pending transactions
-    transactions: RwLock<HashMap<u8, AttTransaction>>,
+    /// Per-connection state, by peer address
+    connections: RwLock<HashMap<BdAddr, PerConnectionAttState>>,
     /// Notification callback
     notification_callback: RwLock<Option<NotificationCallback>>,
     /// Indication callback
     indication_callback: RwLock<Option<IndicationCallback>>,
-    /// Whether the client is connected
-    connected: RwLock<bool>,
+    /// Time to wait for the response to a request
+    timeout: RwLock<Duration>,
 }
 
 impl AttClient {
     /// Create a new ATT client
-    pub fn new(remote_addr: BdAddr, l2cap_manager: Arc<L2capManager>) -> Self {
+    pub fn new(l2cap_manager: Arc<L2capManager>) -> Self {
         Self {
-            remote_addr,
             l2cap_manager,
-            channel_id: RwLock::new(None),
-            client_mtu: RwLock::new(ATT_DEFAULT_MTU),
-            server_mtu: RwLock::new(ATT_DEFAULT_MTU),
-            transactions: RwLock::new(HashMap::new()),
+            connections: RwLock::new(HashMap::new()),
             notification_callback: RwLock::new(None),
             indication_callback: RwLock::new(None),
-            connected: RwLock::new(false),
+            timeout: RwLock::new(Duration::from_millis(ATT_TRANSACTION_TIMEOUT)),
         }
     }
 
-    /// Connect to the ATT server
-    pub fn connect(&self, hci_handle: u16) -> AttResult<()> {
+    /// Set how long to wait for the response to a request
+    ///
+    /// Requests that aren't answered in time fail with `AttError::Timeout`.
+    /// Defaults to the 30 second ATT transaction timeout.
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    /// Get how long to wait for the response to a request
+    pub fn timeout(&self) -> Duration {
+        *self.timeout.read().unwrap()
+    }
+
+    /// Connect to the ATT server of a peer
+    ///
+    /// Opens the fixed ATT channel for `hci_handle` and routes incoming
+    /// PDUs from that connection to [`AttClient::handle_att_pdu`].
+    pub fn connect(self: &Arc<Self>, addr: BdAddr, hci_handle: u16) -> AttResult<()> {
         // Check if already connected
-        if *self.connected.read().unwrap() {
+        if self.is_connected(addr) {
             return Ok(());
         }
 
-        // Connect L2CAP channel for ATT
-        let channel_id = match self
+        // Get the fixed L2CAP channel for ATT
+        let channel_id = self
             .l2cap_manager
-            .connect_fixed_channel(ATT_CID, hci_handle)
-        {
-            Ok(cid) => cid,
-            Err(e) => return Err(AttError::from(e)),
-        };
-
-        // Store channel ID
-        *self.channel_id.write().unwrap() = Some(channel_id);
-        *self.connected.write().unwrap() = true;
+            .get_fixed_channel(L2CAP_ATT_CID, hci_handle)
+            .map_err(AttError::from)?;
+
+        // Route incoming PDUs to this client. A weak reference avoids a
+        // cycle between the client and the L2CAP manager.
+        let client = Arc::downgrade(self);
+        self.l2cap_manager
+            .set_fixed_channel_callback(channel_id, hci_handle, move |data| {
+                if let Some(client) = client.upgrade() {
+                    client
+                        .handle_att_pdu(addr, data)
+                        .map_err(|e| L2capError::ProtocolError(e.to_string()))?;
+                }
+                Ok(())
+            })
+            .map_err(AttError::from)?;
+
+        let mut connections = self.connections.write().unwrap();
+        connections.insert(
+            addr,
+            PerConnectionAttState {
+                channel_id,
+                hci_handle,
+                mtu: ATT_DEFAULT_MTU,
+                pending_requests: VecDeque::new(),
+                pending_indication: None,
+            },
+        );
 
         Ok(())
     }
 
-    /// Disconnect from the ATT server
-    pub fn disconnect(&self) -> AttResult<()> {
-        // Check if connected
-        if !*self.connected.read().unwrap() {
-            return Ok(());
-        }
-
-        // Get channel ID
-        let channel_id = match *self.channel_id.read().unwrap() {
-            Some(cid) => cid,
-            None => return Ok(()),
+    /// Disconnect from the ATT server of a peer
+    pub fn disconnect(&self, addr: BdAddr) -> AttResult<()> {
+        let state = {
+            let mut connections = self.connections.write().unwrap();
+            match connections.remove(&addr) {
+                Some(state) => state,
+                None => return Ok(()),
+            }
         };
 
-        // Disconnect L2CAP channel
-        match self.l2cap_manager.disconnect(channel_id) {
-            Ok(_) => {}
-            Err(e) => return Err(AttError::from(e)),
-        }
-
-        // Clear channel ID and state
-        *self.channel_id.write().unwrap() = None;
-        *self.connected.write().unwrap() = false;
+        // Stop routing PDUs to this client; the fixed channel itself lives
+        // as long as the HCI connection
+        self.l2cap_manager
+            .clear_fixed_channel_callback(state.channel_id, state.hci_handle);
 
         Ok(())
     }
 
-    /// Check if connected
-    pub fn is_connected(&self) -> bool {
-        *self.connected.read().unwrap()
+    /// Check if connected to a peer
+    pub fn is_connected(&self, addr: BdAddr) -> bool {
+        self.connections.read().unwrap().contains_key(&addr)
+    }
+
+    /// Get the addresses of all connected peers
+    pub fn connected_peers(&self) -> Vec<BdAddr> {
+        self.connections.read().unwrap().keys().copied().collect()
     }
 
     /// Set notification callback
     pub fn set_notification_callback<F>(&self, callback: F)
     where
-        F: FnMut(u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
+        F: FnMut(BdAddr, u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
     {
         let mut notification_callback = self.notification_callback.write().unwrap();
         *notification_callback = Some(Arc::new(Mutex::new(callback)));
@@ -134,51 +244,53 @@ impl AttClient {
     /// Set indication callback
     pub fn set_indication_callback<F>(&self, callback: F)
     where
-        F: FnMut(u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
+        F: FnMut(BdAddr, u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
     {
         let mut indication_callback = self.indication_callback.write().unwrap();
         *indication_callback = Some(Arc::new(Mutex::new(callback)));
     }
 
-    /// Get the current MTU
-    pub fn mtu(&self) -> u16 {
-        std::cmp::min(
-            *self.client_mtu.read().unwrap(),
-            *self.server_mtu.read().unwrap(),
-        )
+    /// Get the current MTU of a connection
+    ///
+    /// Returns the default MTU if not connected to `addr`.
+    pub fn mtu(&self, addr: BdAddr) -> u16 {
+        let connections = self.connections.read().unwrap();
+        connections
+            .get(&addr)
+            .map_or(ATT_DEFAULT_MTU, |state| state.mtu)
     }
 
     /// Exchange MTU
-    pub fn exchange_mtu(&self, client_mtu: u16) -> AttResult<u16> {
+    pub fn exchange_mtu(&self, addr: BdAddr, client_mtu: u16) -> AttResult<u16> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
         // Create MTU exchange request
         let req = ExchangeMtuRequest { client_mtu };
 
-        // Store our requested MTU
-        *self.client_mtu.write().unwrap() = client_mtu;
-
         // Send request
-        let response = self.send_request::<ExchangeMtuRequest, ExchangeMtuResponse>(req)?;
-
-        // Update server MTU
-        *self.server_mtu.write().unwrap() = response.server_mtu;
+        let response = self.send_request::<ExchangeMtuRequest, ExchangeMtuResponse>(addr, req)?;
 
         // Return effective MTU
-        Ok(self.mtu())
+        let mtu = client_mtu.min(response.server_mtu);
+        let mut connections = self.connections.write().unwrap();
+        let state = connections.get_mut(&addr).ok_or(AttError::InvalidState)?;
+        state.mtu = mtu;
+
+        Ok(mtu)
     }
 
     /// Find information
     pub fn find_information(
         &self,
+        addr: BdAddr,
         start_handle: u16,
         end_handle: u16,
     ) -> AttResult<Vec<(u16, Uuid)>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -189,7 +301,8 @@ impl AttClient {
         };
 
         // Send request
-        let response = self.send_request::<FindInformationRequest, FindInformationResponse>(req)?;
+        let response =
+            self.send_request::<FindInformationRequest, FindInformationResponse>(addr, req)?;
 
         // Convert response to handle-UUID pairs
         let mut results = Vec::new();
@@ -211,13 +324,14 @@ impl AttClient {
     /// Find by type value
     pub fn find_by_type_value(
         &self,
+        addr: BdAddr,
         start_handle: u16,
         end_handle: u16,
         type_uuid: u16,
         value: &[u8],
     ) -> AttResult<Vec<(u16, u16)>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -230,7 +344,8 @@ impl AttClient {
         };
 
         // Send request
-        let response = self.send_request::<FindByTypeValueRequest, FindByTypeValueResponse>(req)?;
+        let response =
+            self.send_request::<FindByTypeValueRequest, FindByTypeValueResponse>(addr, req)?;
 
         // Convert response to handle ranges
         let results = response
@@ -245,12 +360,13 @@ impl AttClient {
     /// Read by type
     pub fn read_by_type(
         &self,
+        addr: BdAddr,
         start_handle: u16,
         end_handle: u16,
         attr_type: &Uuid,
     ) -> AttResult<Vec<(u16, Vec<u8>)>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -258,11 +374,11 @@ impl AttClient {
         let req = ReadByTypeRequest {
             start_handle,
             end_handle,
-            attribute_type: attr_type.clone(),
+            attribute_type: *attr_type,
         };
 
         // Send request
-        let response = self.send_request::<ReadByTypeRequest, ReadByTypeResponse>(req)?;
+        let response = self.send_request::<ReadByTypeRequest, ReadByTypeResponse>(addr, req)?;
 
         // Convert response to handle-value pairs
         let results = response
@@ -275,9 +391,9 @@ impl AttClient {
     }
 
     /// Read attribute
-    pub fn read(&self, handle: u16) -> AttResult<Vec<u8>> {
+    pub fn read(&self, addr: BdAddr, handle: u16) -> AttResult<Vec<u8>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -285,15 +401,15 @@ impl AttClient {
         let req = ReadRequest { handle };
 
         // Send request
-        let response = self.send_request::<ReadRequest, ReadResponse>(req)?;
+        let response = self.send_request::<ReadRequest, ReadResponse>(addr, req)?;
 
         Ok(response.value)
     }
 
     /// Read blob
-    pub fn read_blob(&self, handle: u16, offset: u16) -> AttResult<Vec<u8>> {
+    pub fn read_blob(&self, addr: BdAddr, handle: u16, offset: u16) -> AttResult<Vec<u8>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -301,15 +417,15 @@ impl AttClient {
         let req = ReadBlobRequest { handle, offset };
 
         // Send request
-        let response = self.send_request::<ReadBlobRequest, ReadBlobResponse>(req)?;
+        let response = self.send_request::<ReadBlobRequest, ReadBlobResponse>(addr, req)?;
 
         Ok(response.value)
     }
 
     /// Read multiple attributes
-    pub fn read_multiple(&self, handles: &[u16]) -> AttResult<Vec<u8>> {
+    pub fn read_multiple(&self, addr: BdAddr, handles: &[u16]) -> AttResult<Vec<u8>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -319,7 +435,7 @@ impl AttClient {
         };
 
         // Send request
-        let response = self.send_request::<ReadMultipleRequest, ReadMultipleResponse>(req)?;
+        let response = self.send_request::<ReadMultipleRequest, ReadMultipleResponse>(addr, req)?;
 
         Ok(response.values)
     }
@@ -327,12 +443,13 @@ impl AttClient {
     /// Read by group type
     pub fn read_by_group_type(
         &self,
+        addr: BdAddr,
         start_handle: u16,
         end_handle: u16,
         group_type: &Uuid,
     ) -> AttResult<Vec<(u16, u16, Vec<u8>)>> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -340,11 +457,12 @@ impl AttClient {
         let req = ReadByGroupTypeRequest {
             start_handle,
             end_handle,
-            group_type: group_type.clone(),
+            group_type: *group_type,
         };
 
         // Send request
-        let response = self.send_request::<ReadByGroupTypeRequest, ReadByGroupTypeResponse>(req)?;
+        let response =
+            self.send_request::<ReadByGroupTypeRequest, ReadByGroupTypeResponse>(addr, req)?;
 
         // Convert response to handle-end_handle-value tuples
         let results = response
@@ -357,14 +475,14 @@ impl AttClient {
     }
 
     /// Write request
-    pub fn write(&self, handle: u16, value: &[u8]) -> AttResult<()> {
+    pub fn write(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
         // Check if value is too long
-        let mtu = self.mtu();
+        let mtu = self.mtu(addr);
         if value.len() > (mtu as usize - 3) {
             return Err(AttError::InvalidAttributeValueLength);
         }
@@ -376,20 +494,20 @@ impl AttClient {
         };
 
         // Send request
-        let _ = self.send_request::<WriteRequest, WriteResponse>(req)?;
+        let _ = self.send_request::<WriteRequest, WriteResponse>(addr, req)?;
 
         Ok(())
     }
 
     /// Write command (no response)
-    pub fn write_command(&self, handle: u16, value: &[u8]) -> AttResult<()> {
+    pub fn write_command(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
         // Check if value is too long
-        let mtu = self.mtu();
+        let mtu = self.mtu(addr);
         if value.len() > (mtu as usize - 3) {
             return Err(AttError::InvalidAttributeValueLength);
         }
@@ -401,20 +519,26 @@ impl AttClient {
         };
 
         // Send command
-        self.send_command::<WriteCommand>(cmd)?;
+        self.send_command::<WriteCommand>(addr, cmd)?;
 
         Ok(())
     }
 
     /// Prepare write request
-    pub fn prepare_write(&self, handle: u16, offset: u16, value: &[u8]) -> AttResult<()> {
+    pub fn prepare_write(
+        &self,
+        addr: BdAddr,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+    ) -> AttResult<()> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
         // Check if value is too long
-        let mtu = self.mtu();
+        let mtu = self.mtu(addr);
         if value.len() > (mtu as usize - 5) {
             return Err(AttError::InvalidAttributeValueLength);
         }
@@ -427,20 +551,20 @@ impl AttClient {
         };
 
         // Send request
-        let response = self.send_request::<PrepareWriteRequest, PrepareWriteResponse>(req)?;
+        let response = self.send_request::<PrepareWriteRequest, PrepareWriteResponse>(addr, req)?;
 
         // Verify the response matches the request
         if response.handle != handle || response.offset != offset || response.value != value {
-            return Err(AttError::UnlikelyError);
+            return Err(AttError::Unlikely);
         }
 
         Ok(())
     }
 
     /// Execute write request
-    pub fn execute_write(&self, flags: u8) -> AttResult<()> {
+    pub fn execute_write(&self, addr: BdAddr, flags: u8) -> AttResult<()> {
         // Check if connected
-        if !*self.connected.read().unwrap() {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
 
@@ -448,13 +572,42 @@ impl AttClient {
         let req = ExecuteWriteRequest { flags };
 
         // Send request
-        let _ = self.send_request::<ExecuteWriteRequest, ExecuteWriteResponse>(req)?;
+        let _ = self.send_request::<ExecuteWriteRequest, ExecuteWriteResponse>(addr, req)?;
 
         Ok(())
     }
 
-    /// Handle ATT PDU received from server
-    pub fn handle_att_pdu(&self, data: &[u8]) -> AttResult<()> {
+    /// Write a value longer than a single Prepare Write Request
+    ///
+    /// The value is queued in chunks of `mtu - 5` bytes at increasing
+    /// offsets and then committed with an Execute Write Request. If any
+    /// prepare write fails, the queued writes are cancelled before the error
+    /// is returned.
+    pub fn write_long(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
+        // Check if connected
+        if !self.is_connected(addr) {
+            return Err(AttError::InvalidState);
+        }
+
+        if value.len() > ATT_MAX_ATTRIBUTE_LEN {
+            return Err(AttError::InvalidAttributeValueLength);
+        }
+
+        let chunk_len = self.mtu(addr) as usize - 5;
+        for (index, chunk) in value.chunks(chunk_len).enumerate() {
+            let offset = (index * chunk_len) as u16;
+            if let Err(e) = self.prepare_write(addr, handle, offset, chunk) {
+                // Discard whatever the server has queued so far
+                let _ = self.execute_write(addr, ATT_EXEC_WRITE_CANCEL);
+                return Err(e);
+            }
+        }
+
+        self.execute_write(addr, ATT_EXEC_WRITE_COMMIT)
+    }
+
+    /// Handle ATT PDU received from the server of a peer
+    pub fn handle_att_pdu(&self, addr: BdAddr, data: &[u8]) -> AttResult<()> {
         if data.is_empty() {
             return Err(AttError::InvalidPdu);
         }
@@ -475,15 +628,19 @@ impl AttClient {
             | ATT_PREPARE_WRITE_RSP
             | ATT_EXECUTE_WRITE_RSP => {
                 // Response to a request, find the transaction
-                self.handle_response(opcode, data)
+                self.handle_response(addr, data)
             }
             ATT_HANDLE_VALUE_NTF => {
                 // Notification
-                self.handle_notification(data)
+                self.handle_notification(addr, data)
             }
             ATT_HANDLE_VALUE_IND => {
                 // Indication
-                self.handle_indication(data)
+                self.handle_indication(addr, data)
+            }
+            ATT_HANDLE_VALUE_CONF => {
+                // Confirmation of an indication we sent
+                self.handle_confirmation(addr)
             }
             _ => {
                 // Unknown/unexpected PDU
@@ -493,59 +650,27 @@ impl AttClient {
     }
 
     /// Handle response from server
-    fn handle_response(&self, opcode: u8, data: &[u8]) -> AttResult<()> {
-        let mut transactions = self.transactions.write().unwrap();
+    fn handle_response(&self, addr: BdAddr, data: &[u8]) -> AttResult<()> {
+        let mut connections = self.connections.write().unwrap();
+        let state = connections.get_mut(&addr).ok_or(AttError::InvalidState)?;
 
         // Find the transaction this is a response to
-        let req_opcode = if opcode == ATT_ERROR_RSP {
-            // For error responses, the request opcode is in the PDU
-            if data.len() < 2 {
-                return Err(AttError::InvalidPdu);
-            }
-            data[1]
-        } else {
-            // For other responses, it's the corresponding request opcode
-            match opcode {
-                ATT_EXCHANGE_MTU_RSP => ATT_EXCHANGE_MTU_REQ,
-                ATT_FIND_INFO_RSP => ATT_FIND_INFO_REQ,
-                ATT_FIND_BY_TYPE_VALUE_RSP => ATT_FIND_BY_TYPE_VALUE_REQ,
-                ATT_READ_BY_TYPE_RSP => ATT_READ_BY_TYPE_REQ,
-                ATT_READ_RSP => ATT_READ_REQ,
-                ATT_READ_BLOB_RSP => ATT_READ_BLOB_REQ,
-                ATT_READ_MULTIPLE_RSP => ATT_READ_MULTIPLE_REQ,
-                ATT_READ_BY_GROUP_TYPE_RSP => ATT_READ_BY_GROUP_TYPE_REQ,
-                ATT_WRITE_RSP => ATT_WRITE_REQ,
-                ATT_PREPARE_WRITE_RSP => ATT_PREPARE_WRITE_REQ,
-                ATT_EXECUTE_WRITE_RSP => ATT_EXECUTE_WRITE_REQ,
-                _ => return Err(AttError::InvalidPdu),
-            }
-        };
+        let req_opcode = request_opcode(data)?;
 
-        // Find and update the transaction
-        if let Some(transaction) = transactions.get_mut(&req_opcode) {
-            if opcode == ATT_ERROR_RSP {
-                // Parse the error
-                if data.len() < 4 {
-                    transaction.error = Some(AttError::InvalidPdu);
-                } else {
-                    let error_code: AttErrorCode = data[3].into();
-                    let handle = ((data[2] as u16) << 8) | (data[1] as u16);
-                    transaction.error = Some(AttError::Protocol(error_code, handle));
-                }
-            } else {
-                // Store the response data
-                transaction.response = Some(data.to_vec());
-            }
+        // Find and update the oldest unanswered request with this opcode
+        let request = state
+            .pending_requests
+            .iter_mut()
+            .find(|request| request.opcode == req_opcode && !request.is_answered())
+            .ok_or(AttError::InvalidPdu)?;
 
-            Ok(())
-        } else {
-            // Unexpected response
-            Err(AttError::InvalidPdu)
-        }
+        request.answer(data);
+
+        Ok(())
     }
 
     /// Handle notification from server
-    fn handle_notification(&self, data: &[u8]) -> AttResult<()> {
+    fn handle_notification(&self, addr: BdAddr, data: &[u8]) -> AttResult<()> {
         // Parse notification
         if data.len() < 3 {
             return Err(AttError::InvalidPdu);
@@ -558,14 +683,14 @@ impl AttClient {
         let notification_callback = self.notification_callback.read().unwrap();
         if let Some(ref callback) = *notification_callback {
             let mut callback = callback.lock().unwrap();
-            (*callback)(handle, value)?;
+            (*callback)(addr, handle, value)?;
         }
 
         Ok(())
     }
 
     /// Handle indication from server
-    fn handle_indication(&self, data: &[u8]) -> AttResult<()> {
+    fn handle_indication(&self, addr: BdAddr, data: &[u8]) -> AttResult<()> {
         // Parse indication
         if data.len() < 3 {
             return Err(AttError::InvalidPdu);
@@ -578,88 +703,188 @@ impl AttClient {
         let indication_callback = self.indication_callback.read().unwrap();
         if let Some(ref callback) = *indication_callback {
             let mut callback = callback.lock().unwrap();
-            (*callback)(handle, value)?;
+            (*callback)(addr, handle, value)?;
         }
 
-        // Send confirmation
-        let conf = HandleValueConfirmation;
-        self.send_command::<HandleValueConfirmation>(conf)?;
+        self.send_confirmation(addr)
+    }
 
-        Ok(())
+    /// Confirm an indication received from a peer
+    ///
+    /// Sends a Handle Value Confirmation PDU. The server does not send
+    /// another indication until the previous one has been confirmed.
+    pub fn send_confirmation(&self, addr: BdAddr) -> AttResult<()> {
+        self.send_command(addr, HandleValueConfirmation)
     }
 
-    /// Send a request and wait for the response
-    fn send_request<Req: AttPacket, Resp: AttPacket>(&self, request: Req) -> AttResult<Resp> {
-        // Check if connected
-        if !*self.connected.read().unwrap() {
+    /// Send a notification to a peer, acting as server
+    pub fn send_notification(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
+        self.check_value_length(addr, value)?;
+        self.send_command(
+            addr,
+            HandleValueNotification {
+                handle,
+                value: value.to_vec(),
+            },
+        )
+    }
+
+    /// Send an indication to a peer, acting as server
+    ///
+    /// Waits for the Handle Value Confirmation and fails with
+    /// `AttError::Timeout` if it doesn't arrive in time. Only one indication
+    /// may be outstanding per peer, so concurrent calls fail with
+    /// `AttError::Busy`.
+    pub fn send_indication(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
+        self.check_value_length(addr, value)?;
+
+        let start_time = Instant::now();
+        {
+            let mut connections = self.connections.write().unwrap();
+            let state = connections.get_mut(&addr).ok_or(AttError::InvalidState)?;
+            if state.pending_indication.is_some() {
+                return Err(AttError::Busy);
+            }
+            state.pending_indication = Some(start_time);
+        }
+
+        let indication = HandleValueIndication {
+            handle,
+            value: value.to_vec(),
+        };
+        if let Err(e) = self.send_command(addr, indication) {
+            self.clear_indication(addr, start_time);
+            return Err(e);
+        }
+
+        // Wait for the confirmation or timeout
+        let timeout = self.timeout();
+        loop {
+            {
+                let connections = self.connections.read().unwrap();
+                match connections.get(&addr) {
+                    Some(state) if state.pending_indication == Some(start_time) => {}
+                    Some(_) => return Ok(()),
+                    // Disconnected while waiting
+                    None => return Err(AttError::InvalidState),
+                }
+            }
+
+            if start_time.elapsed() > timeout {
+                self.clear_indication(addr, start_time);
+                return Err(AttError::Timeout);
+            }
+
+            // Small sleep to avoid busy loop
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Handle a confirmation of an indication sent to a peer
+    fn handle_confirmation(&self, addr: BdAddr) -> AttResult<()> {
+        let mut connections = self.connections.write().unwrap();
+        let state = connections.get_mut(&addr).ok_or(AttError::InvalidState)?;
+        match state.pending_indication.take() {
+            Some(_) => Ok(()),
+            None => Err(AttError::UnexpectedResponse),
+        }
+    }
+
+    /// Stop waiting for the confirmation of an indication
+    fn clear_indication(&self, addr: BdAddr, start_time: Instant) {
+        let mut connections = self.connections.write().unwrap();
+        if let Some(state) = connections.get_mut(&addr) {
+            if state.pending_indication == Some(start_time) {
+                state.pending_indication = None;
+            }
+        }
+    }
+
+    /// Check that a value fits into a notification or indication
+    fn check_value_length(&self, addr: BdAddr, value: &[u8]) -> AttResult<()> {
+        if !self.is_connected(addr) {
             return Err(AttError::InvalidState);
         }
+        if value.len() > self.mtu(addr) as usize - 3 {
+            return Err(AttError::InvalidAttributeValueLength);
+        }
+        Ok(())
+    }
+
+    /// Get the channel and HCI handle used for a peer
+    fn route(&self, addr: BdAddr) -> AttResult<(u16, u16)> {
+        let connections = self.connections.read().unwrap();
+        connections
+            .get(&addr)
+            .map(|state| (state.channel_id, state.hci_handle))
+            .ok_or(AttError::InvalidState)
+    }
 
+    /// Send a request and wait for the response
+    fn send_request<Req: AttPacket, Resp: AttPacket>(
+        &self,
+        addr: BdAddr,
+        request: Req,
+    ) -> AttResult<Resp> {
         // Get channel ID
-        let channel_id = match *self.channel_id.read().unwrap() {
-            Some(cid) => cid,
-            None => return Err(AttError::InvalidState),
-        };
+        let (channel_id, hci_handle) = self.route(addr)?;
 
         // Serialize the request
         let request_data = request.serialize();
 
-        // Create a transaction
+        // Queue the request
         let req_opcode = Req::opcode();
-        let transaction = AttTransaction {
-            opcode: req_opcode,
-            response: None,
-            start_time: Instant::now(),
-            error: None,
-        };
-
-        // Store the transaction
+        let timeout = self.timeout();
+        let pending = PendingRequest::new(req_opcode);
+        let start_time = pending.start_time;
         {
-            let mut transactions = self.transactions.write().unwrap();
-            transactions.insert(req_opcode, transaction);
+            let mut connections = self.connections.write().unwrap();
+            let state = connections.get_mut(&addr).ok_or(AttError::InvalidState)?;
+            state.pending_requests.push_back(pending);
         }
 
         // Send the request
-        match self.l2cap_manager.send_data(channel_id, &request_data) {
-            Ok(_) => {}
-            Err(e) => return Err(AttError::from(e)),
+        if let Err(e) = self
+            .l2cap_manager
+            .send_data_on(channel_id, hci_handle, &request_data)
+        {
+            self.remove_request(addr, req_opcode, start_time);
+            return Err(AttError::from(e));
         }
 
         // Wait for the response or timeout
-        let start_time = Instant::now();
         loop {
             // Check if response has arrived
-            let mut transaction_opt = None;
+            let mut request_opt = None;
             {
-                let mut transactions = self.transactions.write().unwrap();
-                if let Some(transaction) = transactions.get(&req_opcode) {
-                    if transaction.response.is_some() || transaction.error.is_some() {
-                        transaction_opt = Some(transactions.remove(&req_opcode).unwrap());
+                let mut connections = self.connections.write().unwrap();
+                let Some(state) = connections.get_mut(&addr) else {
+                    // Disconnected while waiting
+                    return Err(AttError::InvalidState);
+                };
+
+                let position = state.pending_requests.iter().position(|request| {
+                    request.opcode == req_opcode && request.start_time == start_time
+                });
+                match position {
+                    Some(position) => {
+                        if state.pending_requests[position].is_answered() {
+                            request_opt = state.pending_requests.remove(position);
+                        }
                     }
+                    // Dropped by process_timeouts
+                    None => return Err(AttError::Timeout),
                 }
             }
 
-            if let Some(transaction) = transaction_opt {
-                // Process the result
-                if let Some(error) = transaction.error {
-                    return Err(error);
-                }
-
-                if let Some(response_data) = transaction.response {
-                    // Parse the response
-                    return Resp::parse(&response_data);
-                }
+            if let Some(result) = request_opt.and_then(PendingRequest::into_result) {
+                return result;
             }
 
             // Check for timeout
-            if start_time.elapsed().as_millis() > ATT_TRANSACTION_TIMEOUT as u128 {
-                // Remove the transaction
-                {
-                    let mut transactions = self.transactions.write().unwrap();
-                    transactions.remove(&req_opcode);
-                }
-
-                return Err(AttError::Unknown("Transaction timeout".into()));
+            if start_time.elapsed() > timeout {
+                self.remove_request(addr, req_opcode, start_time);
+                return Err(AttError::Timeout);
             }
 
             // Small sleep to avoid busy loop
@@ -667,49 +892,38 @@ impl AttClient {
         }
     }
 
-    /// Send a command (no response)
-    fn send_command<Cmd: AttPacket>(&self, command: Cmd) -> AttResult<()> {
-        // Check if connected
-        if !*self.connected.read().unwrap() {
-            return Err(AttError::InvalidState);
+    /// Remove a pending request that will not be waited for
+    fn remove_request(&self, addr: BdAddr, opcode: u8, start_time: Instant) {
+        let mut connections = self.connections.write().unwrap();
+        if let Some(state) = connections.get_mut(&addr) {
+            state
+                .pending_requests
+                .retain(|request| request.opcode != opcode || request.start_time != start_time);
         }
+    }
 
+    /// Send a command (no response)
+    fn send_command<Cmd: AttPacket>(&self, addr: BdAddr, command: Cmd) -> AttResult<()> {
         // Get channel ID
-        let channel_id = match *self.channel_id.read().unwrap() {
-            Some(cid) => cid,
-            None => return Err(AttError::InvalidState),
-        };
+        let (channel_id, hci_handle) = self.route(addr)?;
 
         // Serialize the command
         let command_data = command.serialize();
 
         // Send the command
-        match self.l2cap_manager.send_data(channel_id, &command_data) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(AttError::from(e)),
-        }
+        self.l2cap_manager
+            .send_data_on(channel_id, hci_handle, &command_data)
+            .map_err(AttError::from)
     }
 
-    /// Process timeouts for pending transactions
+    /// Process timeouts for pending transactions on every connection
     pub fn process_timeouts(&self) -> AttResult<()> {
-        let mut expired_transactions = Vec::new();
-
-        // Find expired transactions
-        {
-            let transactions = self.transactions.read().unwrap();
-            for (&opcode, transaction) in transactions.iter() {
-                if transaction.start_time.elapsed().as_millis() > ATT_TRANSACTION_TIMEOUT as u128 {
-                    expired_transactions.push(opcode);
-                }
-            }
-        }
-
-        // Remove expired transactions
-        {
-            let mut transactions = self.transactions.write().unwrap();
-            for opcode in expired_transactions {
-                transactions.remove(&opcode);
-            }
+        let timeout = self.timeout();
+        let mut connections = self.connections.write().unwrap();
+        for state in connections.values_mut() {
+            state
+                .pending_requests
+                .retain(|request| request.start_time.elapsed() <= timeout);
         }
 
         Ok(())
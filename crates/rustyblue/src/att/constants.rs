@@ -77,6 +77,7 @@ pub const ATT_HANDLE_MAX: u16 = 0xFFFF;
 // ATT attribute value length limits
 pub const ATT_DEFAULT_MTU: u16 = 23;
 pub const ATT_MAX_MTU: u16 = 517;
+pub const ATT_MAX_ATTRIBUTE_LEN: usize = 512;
 
 // ATT value length limits based on MTU
 pub const ATT_MTU_HEADER_SIZE: usize = 3; // Opcode (1) + handle (2)
@@ -1,13 +1,14 @@
 //! Attribute database implementation for ATT server
 use super::constants::*;
-use super::error::{AttError, AttErrorCode, AttResult};
+use super::error::{AttError, AttResult};
 use super::types::{AttPermissions, SecurityLevel};
 use crate::gatt::Uuid;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::{Arc, RwLock};
 
 /// An attribute in the database
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Attribute {
     /// Attribute handle
     pub handle: u16,
@@ -17,6 +18,10 @@ pub struct Attribute {
     pub value: Vec<u8>,
     /// Attribute permissions
     pub permissions: AttPermissions,
+    /// Read handler, moved to the database by `AttributeDatabase::add_attribute`
+    pub read_handler: Option<AttributeReadHandler>,
+    /// Write handler, moved to the database by `AttributeDatabase::add_attribute`
+    pub write_handler: Option<AttributeWriteCallback>,
 }
 
 impl Attribute {
@@ -27,6 +32,8 @@ impl Attribute {
             type_,
             value,
             permissions,
+            read_handler: None,
+            write_handler: None,
         }
     }
 
@@ -84,24 +91,141 @@ impl Attribute {
     }
 }
 
+impl fmt::Debug for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attribute")
+            .field("handle", &self.handle)
+            .field("type_", &self.type_)
+            .field("value", &self.value)
+            .field("permissions", &self.permissions)
+            .field("has_read_handler", &self.read_handler.is_some())
+            .field("has_write_handler", &self.write_handler.is_some())
+            .finish()
+    }
+}
+
+/// Builder for an [`Attribute`]
+///
+/// An attribute needs a value or a read handler, and a writable attribute
+/// needs a value to write to or a write handler. `build` checks both.
+pub struct AttributeBuilder {
+    handle: u16,
+    type_: Uuid,
+    permissions: AttPermissions,
+    value: Option<Vec<u8>>,
+    read_handler: Option<AttributeReadHandler>,
+    write_handler: Option<AttributeWriteCallback>,
+}
+
+impl AttributeBuilder {
+    /// Start an attribute with read-only permissions
+    pub fn new(handle: u16, uuid: Uuid) -> Self {
+        Self {
+            handle,
+            type_: uuid,
+            permissions: AttPermissions::read_only(),
+            value: None,
+            read_handler: None,
+            write_handler: None,
+        }
+    }
+
+    /// Set the permissions
+    pub fn with_permissions(mut self, permissions: AttPermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Set the stored value
+    pub fn with_value(mut self, value: Vec<u8>) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /// Produce the value with a read handler, see [`AttributeReadHandler`]
+    pub fn with_read_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(u16, usize) -> AttResult<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.read_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Handle written values with a callback instead of storing them
+    pub fn with_write_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
+    {
+        self.write_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Build the attribute
+    pub fn build(self) -> AttResult<Attribute> {
+        if self.value.is_none() && self.read_handler.is_none() {
+            return Err(AttError::InvalidParameter(format!(
+                "Attribute 0x{:04X} needs a value or a read handler",
+                self.handle
+            )));
+        }
+
+        if self.permissions.can_write() && self.value.is_none() && self.write_handler.is_none() {
+            return Err(AttError::InvalidParameter(format!(
+                "Writable attribute 0x{:04X} needs a value or a write handler",
+                self.handle
+            )));
+        }
+
+        Ok(Attribute {
+            handle: self.handle,
+            type_: self.type_,
+            value: self.value.unwrap_or_default(),
+            permissions: self.permissions,
+            read_handler: self.read_handler,
+            write_handler: self.write_handler,
+        })
+    }
+}
+
 /// Attribute write callback
 pub type AttributeWriteCallback = Arc<dyn Fn(u16, &[u8]) -> AttResult<()> + Send + Sync>;
 
 /// Attribute read callback
 pub type AttributeReadCallback = Arc<dyn Fn(u16) -> AttResult<Vec<u8>> + Send + Sync>;
 
+/// Attribute read handler
+///
+/// Called with the offset into the value and the most bytes that fit into
+/// the response. Returning fewer than `max_len` bytes ends the value;
+/// returning `max_len` bytes lets a client read on from the next offset.
+pub type AttributeReadHandler = Arc<dyn Fn(u16, usize) -> AttResult<Vec<u8>> + Send + Sync>;
+
 /// Attribute database
+///
+/// Attributes are kept ordered by handle, so looking up a handle takes
+/// O(log n) and handle-range requests (Find Information, Read By Type,
+/// Read By Group Type) only visit the attributes inside the range.
+/// Attributes can be added and removed at any time, e.g. when services are
+/// registered while the server is running.
 pub struct AttributeDatabase {
-    /// Map of handles to attributes
+    /// Map of handles to attributes, ordered by handle
     attributes: RwLock<BTreeMap<u16, Attribute>>,
     /// Map of handles to write callbacks
     write_callbacks: RwLock<BTreeMap<u16, AttributeWriteCallback>>,
     /// Map of handles to read callbacks
     read_callbacks: RwLock<BTreeMap<u16, AttributeReadCallback>>,
+    /// Map of handles to read handlers
+    read_handlers: RwLock<BTreeMap<u16, AttributeReadHandler>>,
     /// Next available handle
     next_handle: RwLock<u16>,
 }
 
+impl Default for AttributeDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AttributeDatabase {
     /// Create a new empty attribute database
     pub fn new() -> Self {
@@ -109,30 +233,48 @@ impl AttributeDatabase {
             attributes: RwLock::new(BTreeMap::new()),
             write_callbacks: RwLock::new(BTreeMap::new()),
             read_callbacks: RwLock::new(BTreeMap::new()),
+            read_handlers: RwLock::new(BTreeMap::new()),
             next_handle: RwLock::new(ATT_HANDLE_MIN),
         }
     }
 
     /// Add an attribute to the database
-    pub fn add_attribute(&self, attr: Attribute) -> AttResult<u16> {
+    ///
+    /// Read and write handlers of the attribute are registered for its
+    /// handle, as with `register_read_handler` and `register_write_callback`.
+    pub fn add_attribute(&self, mut attr: Attribute) -> AttResult<u16> {
         let handle = attr.handle;
+        let read_handler = attr.read_handler.take();
+        let write_handler = attr.write_handler.take();
 
-        // Check for duplicate handle
-        let mut attributes = self.attributes.write().unwrap();
-        if attributes.contains_key(&handle) {
-            return Err(AttError::InvalidParameter(format!(
-                "Duplicate handle: {}",
-                handle
-            )));
+        {
+            // Check for duplicate handle
+            let mut attributes = self.attributes.write().unwrap();
+            if attributes.contains_key(&handle) {
+                return Err(AttError::InvalidParameter(format!(
+                    "Duplicate handle: {}",
+                    handle
+                )));
+            }
+
+            // Update next_handle if needed
+            if handle >= *self.next_handle.read().unwrap() {
+                *self.next_handle.write().unwrap() = handle + 1;
+            }
+
+            // Add the attribute
+            attributes.insert(handle, attr);
         }
 
-        // Update next_handle if needed
-        if handle >= *self.next_handle.read().unwrap() {
-            *self.next_handle.write().unwrap() = handle + 1;
+        if let Some(handler) = read_handler {
+            let mut handlers = self.read_handlers.write().unwrap();
+            handlers.insert(handle, handler);
         }
 
-        // Add the attribute
-        attributes.insert(handle, attr);
+        if let Some(handler) = write_handler {
+            let mut callbacks = self.write_callbacks.write().unwrap();
+            callbacks.insert(handle, handler);
+        }
 
         Ok(handle)
     }
@@ -153,6 +295,18 @@ impl AttributeDatabase {
         Ok(handle)
     }
 
+    /// Get the handle that the next added attribute will use
+    pub fn next_handle(&self) -> u16 {
+        *self.next_handle.read().unwrap()
+    }
+
+    /// Check if a read callback or read handler is registered for a handle
+    pub fn has_read_callback(&self, handle: u16) -> bool {
+        let callbacks = self.read_callbacks.read().unwrap();
+        let handlers = self.read_handlers.read().unwrap();
+        callbacks.contains_key(&handle) || handlers.contains_key(&handle)
+    }
+
     /// Register a write callback for a handle
     pub fn register_write_callback(
         &self,
@@ -191,6 +345,29 @@ impl AttributeDatabase {
         Ok(())
     }
 
+    /// Register a read handler for a handle
+    ///
+    /// The handler produces the value piece by piece for Read and Read Blob
+    /// Requests, see [`AttributeDatabase::read_part`]. The attribute's read
+    /// permissions are checked before it is called.
+    pub fn register_read_handler(
+        &self,
+        handle: u16,
+        handler: AttributeReadHandler,
+    ) -> AttResult<()> {
+        let mut handlers = self.read_handlers.write().unwrap();
+
+        // Check if the attribute exists
+        let attributes = self.attributes.read().unwrap();
+        if !attributes.contains_key(&handle) {
+            return Err(AttError::InvalidHandle(handle));
+        }
+
+        handlers.insert(handle, handler);
+
+        Ok(())
+    }
+
     /// Find attributes in a range by type
     pub fn find_by_type(
         &self,
@@ -205,12 +382,7 @@ impl AttributeDatabase {
         for (_handle, attr) in attributes.range(start_handle..=end_handle) {
             if attr.type_ == *type_ && attr.can_read(security_level) {
                 // Clone the attribute to avoid referencing the attributes map
-                results.push(Attribute {
-                    handle: attr.handle,
-                    type_: attr.type_.clone(),
-                    permissions: attr.permissions,
-                    value: attr.value.clone(),
-                });
+                results.push(attr.clone());
             }
         }
 
@@ -279,13 +451,47 @@ impl AttributeDatabase {
 
         for (&handle, attr) in attributes.range(start_handle..=end_handle) {
             if attr.can_read(security_level) {
-                results.push((handle, attr.type_.clone()));
+                results.push((handle, attr.type_));
             }
         }
 
         Ok(results)
     }
 
+    /// Read at most `max_len` bytes of an attribute value from `offset`
+    ///
+    /// Read handlers are given the offset and limit; other values are read
+    /// whole and cut to size. Longer results from a handler are truncated.
+    pub fn read_part(
+        &self,
+        handle: u16,
+        offset: u16,
+        max_len: usize,
+        security_level: SecurityLevel,
+    ) -> AttResult<Vec<u8>> {
+        let handler = {
+            let handlers = self.read_handlers.read().unwrap();
+            handlers.get(&handle).cloned()
+        };
+
+        let mut value = match handler {
+            Some(handler) => {
+                {
+                    let attributes = self.attributes.read().unwrap();
+                    let attr = attributes
+                        .get(&handle)
+                        .ok_or(AttError::InvalidHandle(handle))?;
+                    attr.read(security_level)?;
+                }
+                handler(offset, max_len)?
+            }
+            None => self.read_blob_by_handle(handle, offset, security_level)?,
+        };
+
+        value.truncate(max_len);
+        Ok(value)
+    }
+
     /// Read an attribute value by handle
     pub fn read_by_handle(&self, handle: u16, security_level: SecurityLevel) -> AttResult<Vec<u8>> {
         // Check if there's a read callback
@@ -294,6 +500,11 @@ impl AttributeDatabase {
             return callback(handle);
         }
 
+        // Read handlers produce the whole value in one call here
+        if self.read_handlers.read().unwrap().contains_key(&handle) {
+            return self.read_part(handle, 0, ATT_MAX_ATTRIBUTE_LEN, security_level);
+        }
+
         // Otherwise, read directly from the attribute
         let attributes = self.attributes.read().unwrap();
 
@@ -390,6 +601,19 @@ impl AttributeDatabase {
         Ok(results)
     }
 
+    /// Set an attribute value on behalf of the local device
+    ///
+    /// Unlike `write_by_handle` this bypasses permission checks and write
+    /// callbacks, so read-only attributes can be updated by the server.
+    pub fn set_value(&self, handle: u16, value: &[u8]) -> AttResult<()> {
+        let mut attributes = self.attributes.write().unwrap();
+        let attr = attributes
+            .get_mut(&handle)
+            .ok_or(AttError::InvalidHandle(handle))?;
+        attr.value = value.to_vec();
+        Ok(())
+    }
+
     /// Check if an attribute exists
     pub fn has_attribute(&self, handle: u16) -> bool {
         let attributes = self.attributes.read().unwrap();
@@ -415,6 +639,11 @@ impl AttributeDatabase {
             read_callbacks.remove(&handle);
         }
 
+        {
+            let mut read_handlers = self.read_handlers.write().unwrap();
+            read_handlers.remove(&handle);
+        }
+
         Ok(())
     }
 
@@ -429,6 +658,9 @@ impl AttributeDatabase {
         let mut read_callbacks = self.read_callbacks.write().unwrap();
         read_callbacks.clear();
 
+        let mut read_handlers = self.read_handlers.write().unwrap();
+        read_handlers.clear();
+
         *self.next_handle.write().unwrap() = ATT_HANDLE_MIN;
     }
 
@@ -476,6 +708,10 @@ impl AttributeDatabase {
     }
 
     /// Find attributes by type in a range
+    ///
+    /// If the first attribute of the type can't be read, the error is
+    /// returned as a `Protocol` error carrying its handle. Otherwise the
+    /// attributes up to the first unreadable one are returned.
     pub fn read_by_type(
         &self,
         start_handle: u16,
@@ -486,16 +722,18 @@ impl AttributeDatabase {
         let attributes = self.attributes.read().unwrap();
         let mut results = Vec::new();
 
-        for (&handle, attr) in attributes.range(start_handle..=end_handle) {
-            if attr.type_ == *attr_type && attr.can_read(security_level) {
-                match attr.read(security_level) {
-                    Ok(value) => {
-                        results.push((handle, value.to_vec()));
-                    }
-                    Err(_) => {
-                        // Skip attributes that can't be read
-                    }
+        for (&handle, attr) in attributes
+            .range(start_handle..=end_handle)
+            .filter(|(_, attr)| attr.type_ == *attr_type)
+        {
+            match attr.read(security_level) {
+                Ok(value) => results.push((handle, value.to_vec())),
+                // Only the first attribute in the range is reported, so the
+                // response doesn't reveal any restricted attributes after it
+                Err(e) if results.is_empty() => {
+                    return Err(AttError::Protocol(e.to_error_code(), handle))
                 }
+                Err(_) => break,
             }
         }
 
@@ -0,0 +1,339 @@
+//! Enhanced ATT (EATT) client implementation
+//!
+//! EATT runs ATT over up to five LE Credit-based channels on PSM 0x0027
+//! (BT Core Spec 5.2, Vol 3, Part F, 3.2.11). Each channel is a separate
+//! bearer with its own request/response sequence, so requests on different
+//! bearers proceed in parallel.
+use super::client::{request_opcode, NotificationCallback, PendingRequest};
+use super::constants::*;
+use super::error::{AttError, AttResult};
+use super::types::*;
+use crate::gap::BdAddr;
+use crate::l2cap::{L2capError, L2capManager, PSM};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Default transaction timeout (ms)
+const ATT_TRANSACTION_TIMEOUT: u64 = 30000;
+
+/// An EATT bearer
+struct EattBearer {
+    /// L2CAP channel ID
+    channel_id: u16,
+    /// Requests sent and not yet answered, oldest first
+    pending_requests: VecDeque<PendingRequest>,
+}
+
+/// EATT Client
+///
+/// Talks to the ATT server of one peer over several bearers. A bearer
+/// carries one request at a time, so each request is sent on an idle
+/// bearer, and waits for one when all are busy. Idle bearers take turns.
+pub struct EattClient {
+    /// L2CAP manager
+    l2cap_manager: Arc<L2capManager>,
+    /// Bearers, in the order the channels were requested
+    bearers: Mutex<Vec<EattBearer>>,
+    /// Bearer to try first when several are equally loaded
+    next_bearer: Mutex<usize>,
+    /// Notification and indication callback
+    notification_callback: RwLock<Option<NotificationCallback>>,
+    /// Time to wait for the response to a request
+    timeout: RwLock<Duration>,
+}
+
+impl EattClient {
+    /// Open EATT bearers to the peer of an LE connection
+    ///
+    /// Requests `num_channels` channels on PSM 0x0027 with
+    /// `L2capManager::connect_enhanced`. Requests fail until the peer has
+    /// accepted the channels.
+    pub fn connect(
+        l2cap_manager: Arc<L2capManager>,
+        hci_handle: u16,
+        num_channels: u8,
+    ) -> AttResult<Arc<Self>> {
+        let channel_ids = l2cap_manager
+            .connect_enhanced(PSM::EATT, hci_handle, num_channels)
+            .map_err(AttError::from)?;
+
+        Self::new(l2cap_manager, channel_ids)
+    }
+
+    /// Create a client on already requested EATT channels
+    ///
+    /// PDUs received on the channels are routed to
+    /// [`EattClient::handle_att_pdu`].
+    pub fn new(l2cap_manager: Arc<L2capManager>, channel_ids: Vec<u16>) -> AttResult<Arc<Self>> {
+        if channel_ids.is_empty() {
+            return Err(AttError::InvalidParameter(
+                "At least one EATT channel is required".into(),
+            ));
+        }
+
+        let client = Arc::new(Self {
+            l2cap_manager,
+            bearers: Mutex::new(
+                channel_ids
+                    .iter()
+                    .map(|&channel_id| EattBearer {
+                        channel_id,
+                        pending_requests: VecDeque::new(),
+                    })
+                    .collect(),
+            ),
+            next_bearer: Mutex::new(0),
+            notification_callback: RwLock::new(None),
+            timeout: RwLock::new(Duration::from_millis(ATT_TRANSACTION_TIMEOUT)),
+        });
+
+        // A weak reference avoids a cycle between the client and the L2CAP
+        // manager
+        for channel_id in channel_ids {
+            let weak = Arc::downgrade(&client);
+            client
+                .l2cap_manager
+                .set_channel_data_callback(channel_id, move |data| {
+                    if let Some(client) = weak.upgrade() {
+                        client
+                            .handle_att_pdu(channel_id, data)
+                            .map_err(|e| L2capError::ProtocolError(e.to_string()))?;
+                    }
+                    Ok(())
+                })
+                .map_err(AttError::from)?;
+        }
+
+        Ok(client)
+    }
+
+    /// Get the channel IDs of the bearers
+    pub fn channel_ids(&self) -> Vec<u16> {
+        let bearers = self.bearers.lock().unwrap();
+        bearers.iter().map(|bearer| bearer.channel_id).collect()
+    }
+
+    /// Get the number of requests waiting for a response on a bearer
+    pub fn pending_requests(&self, channel_id: u16) -> usize {
+        let bearers = self.bearers.lock().unwrap();
+        bearers
+            .iter()
+            .find(|bearer| bearer.channel_id == channel_id)
+            .map_or(0, |bearer| bearer.pending_requests.len())
+    }
+
+    /// Set how long to wait for the response to a request
+    ///
+    /// Defaults to the 30 second ATT transaction timeout.
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    /// Get how long to wait for the response to a request
+    pub fn timeout(&self) -> Duration {
+        *self.timeout.read().unwrap()
+    }
+
+    /// Set the callback for notifications and indications
+    ///
+    /// Indications are confirmed on the bearer they arrived on after the
+    /// callback returns.
+    pub fn set_notification_callback<F>(&self, callback: F)
+    where
+        F: FnMut(BdAddr, u16, &[u8]) -> AttResult<()> + Send + Sync + 'static,
+    {
+        *self.notification_callback.write().unwrap() = Some(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Read attribute
+    pub fn read(&self, handle: u16) -> AttResult<Vec<u8>> {
+        let response = self.send_request::<ReadRequest, ReadResponse>(ReadRequest { handle })?;
+        Ok(response.value)
+    }
+
+    /// Write request
+    pub fn write(&self, handle: u16, value: &[u8]) -> AttResult<()> {
+        let req = WriteRequest {
+            handle,
+            value: value.to_vec(),
+        };
+        self.send_request::<WriteRequest, WriteResponse>(req)?;
+        Ok(())
+    }
+
+    /// Handle an ATT PDU received on one of the bearers
+    pub fn handle_att_pdu(&self, channel_id: u16, data: &[u8]) -> AttResult<()> {
+        let opcode = *data.first().ok_or(AttError::InvalidPdu)?;
+
+        match opcode {
+            ATT_HANDLE_VALUE_NTF | ATT_HANDLE_VALUE_IND => {
+                if data.len() < 3 {
+                    return Err(AttError::InvalidPdu);
+                }
+                let handle = u16::from_le_bytes([data[1], data[2]]);
+
+                let callback = self.notification_callback.read().unwrap().clone();
+                if let Some(callback) = callback {
+                    let addr = self
+                        .l2cap_manager
+                        .channel_peer_address(channel_id)
+                        .ok_or(AttError::ChannelNotFound)?;
+                    let mut callback = callback.lock().unwrap();
+                    (*callback)(addr, handle, &data[3..])?;
+                }
+
+                if opcode == ATT_HANDLE_VALUE_IND {
+                    self.l2cap_manager
+                        .send_data(channel_id, &HandleValueConfirmation.serialize())
+                        .map_err(AttError::from)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let req_opcode = request_opcode(data)?;
+
+                let mut bearers = self.bearers.lock().unwrap();
+                let bearer = bearers
+                    .iter_mut()
+                    .find(|bearer| bearer.channel_id == channel_id)
+                    .ok_or(AttError::ChannelNotFound)?;
+
+                // Responses on a bearer come in request order
+                let request = bearer
+                    .pending_requests
+                    .iter_mut()
+                    .find(|request| request.opcode == req_opcode && !request.is_answered())
+                    .ok_or(AttError::UnexpectedResponse)?;
+                request.answer(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Drop requests that have not been answered in time
+    pub fn process_timeouts(&self) -> AttResult<()> {
+        let timeout = self.timeout();
+        let mut bearers = self.bearers.lock().unwrap();
+        for bearer in bearers.iter_mut() {
+            bearer
+                .pending_requests
+                .retain(|request| request.start_time.elapsed() <= timeout);
+        }
+
+        Ok(())
+    }
+
+    /// Queue a request on an idle bearer
+    ///
+    /// Waits up to the transaction timeout for a bearer without an
+    /// outstanding request. Returns the bearer's channel ID and the
+    /// request's start time.
+    fn assign_request(&self, opcode: u8) -> AttResult<(u16, Instant)> {
+        let timeout = self.timeout();
+        let start = Instant::now();
+
+        loop {
+            {
+                let mut bearers = self.bearers.lock().unwrap();
+                let mut next_bearer = self.next_bearer.lock().unwrap();
+
+                let count = bearers.len();
+                let index = (0..count)
+                    .map(|offset| (*next_bearer + offset) % count)
+                    .find(|&index| bearers[index].pending_requests.is_empty());
+
+                if let Some(index) = index {
+                    *next_bearer = (index + 1) % count;
+
+                    let request = PendingRequest::new(opcode);
+                    let start_time = request.start_time;
+                    let bearer = &mut bearers[index];
+                    bearer.pending_requests.push_back(request);
+
+                    return Ok((bearer.channel_id, start_time));
+                }
+            }
+
+            if start.elapsed() > timeout {
+                return Err(AttError::Timeout);
+            }
+
+            // Small sleep to avoid busy loop
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Take a request off its bearer if it has been answered
+    ///
+    /// Returns `None` while the request is waiting, and `Err(Timeout)` if it
+    /// was dropped by `process_timeouts`.
+    fn take_answered(
+        &self,
+        channel_id: u16,
+        opcode: u8,
+        start_time: Instant,
+    ) -> AttResult<Option<PendingRequest>> {
+        let mut bearers = self.bearers.lock().unwrap();
+        let bearer = bearers
+            .iter_mut()
+            .find(|bearer| bearer.channel_id == channel_id)
+            .ok_or(AttError::ChannelNotFound)?;
+
+        let position = bearer
+            .pending_requests
+            .iter()
+            .position(|request| request.opcode == opcode && request.start_time == start_time)
+            .ok_or(AttError::Timeout)?;
+
+        if bearer.pending_requests[position].is_answered() {
+            Ok(bearer.pending_requests.remove(position))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Remove a pending request that will not be waited for
+    fn remove_request(&self, channel_id: u16, opcode: u8, start_time: Instant) {
+        let mut bearers = self.bearers.lock().unwrap();
+        if let Some(bearer) = bearers
+            .iter_mut()
+            .find(|bearer| bearer.channel_id == channel_id)
+        {
+            bearer
+                .pending_requests
+                .retain(|request| request.opcode != opcode || request.start_time != start_time);
+        }
+    }
+
+    /// Send a request and wait for the response
+    fn send_request<Req: AttPacket, Resp: AttPacket>(&self, request: Req) -> AttResult<Resp> {
+        let req_opcode = Req::opcode();
+        let timeout = self.timeout();
+        let (channel_id, start_time) = self.assign_request(req_opcode)?;
+
+        if let Err(e) = self
+            .l2cap_manager
+            .send_data(channel_id, &request.serialize())
+        {
+            self.remove_request(channel_id, req_opcode, start_time);
+            return Err(AttError::from(e));
+        }
+
+        // Wait for the response or timeout
+        loop {
+            let answered = self.take_answered(channel_id, req_opcode, start_time)?;
+            if let Some(result) = answered.and_then(PendingRequest::into_result) {
+                return result;
+            }
+
+            if start_time.elapsed() > timeout {
+                self.remove_request(channel_id, req_opcode, start_time);
+                return Err(AttError::Timeout);
+            }
+
+            // Small sleep to avoid busy loop
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
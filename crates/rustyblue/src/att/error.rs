@@ -79,11 +79,13 @@ impl From<u8> for AttErrorCode {
             ATT_ERROR_INSUFFICIENT_RESOURCES => AttErrorCode::InsufficientResources,
             ATT_ERROR_DATABASE_OUT_OF_SYNC => AttErrorCode::DatabaseOutOfSync,
             ATT_ERROR_VALUE_NOT_ALLOWED => AttErrorCode::ValueNotAllowed,
-            c if c >= ATT_ERROR_APPLICATION_ERROR_START && c <= ATT_ERROR_APPLICATION_ERROR_END => {
+            c if (ATT_ERROR_APPLICATION_ERROR_START..=ATT_ERROR_APPLICATION_ERROR_END)
+                .contains(&c) =>
+            {
                 AttErrorCode::ApplicationError(c)
             }
-            c if c >= ATT_ERROR_COMMON_PROFILE_ERROR_START
-                && c <= ATT_ERROR_COMMON_PROFILE_ERROR_END =>
+            c if (ATT_ERROR_COMMON_PROFILE_ERROR_START..=ATT_ERROR_COMMON_PROFILE_ERROR_END)
+                .contains(&c) =>
             {
                 AttErrorCode::CommonProfileError(c)
             }
@@ -92,9 +94,9 @@ impl From<u8> for AttErrorCode {
     }
 }
 
-impl Into<u8> for AttErrorCode {
-    fn into(self) -> u8 {
-        match self {
+impl From<AttErrorCode> for u8 {
+    fn from(val: AttErrorCode) -> Self {
+        match val {
             AttErrorCode::NoError => 0,
             AttErrorCode::InvalidHandle => ATT_ERROR_INVALID_HANDLE,
             AttErrorCode::ReadNotPermitted => ATT_ERROR_READ_NOT_PERMITTED,
@@ -190,8 +192,32 @@ pub enum AttError {
     #[error("Application error: {0}")]
     ApplicationError(u8),
 
+    #[error("Common profile error: {0}")]
+    CommonProfileError(u8),
+
+    #[error("Invalid opcode: 0x{0:02X}")]
+    InvalidOpcode(u8),
+
+    #[error("Unsupported opcode: 0x{0:02X}")]
+    UnsupportedOpcode(u8),
+
+    #[error("Unexpected response")]
+    UnexpectedResponse,
+
+    #[error("Unknown response: {0}")]
+    UnknownResponse(String),
+
+    #[error("L2CAP channel not found")]
+    ChannelNotFound,
+
+    #[error("L2CAP MTU exceeded: {requested} bytes requested, {allowed} allowed")]
+    L2capMtuExceeded { requested: usize, allowed: usize },
+
+    #[error("L2CAP link disconnected")]
+    L2capDisconnected,
+
     #[error("L2CAP error: {0}")]
-    L2capError(#[from] L2capError),
+    L2capError(L2capError),
 
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
@@ -199,21 +225,63 @@ pub enum AttError {
     #[error("Invalid state for operation")]
     InvalidState,
 
+    #[error("Indication already pending")]
+    Busy,
+
+    #[error("Transaction timeout")]
+    Timeout,
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
+impl From<L2capError> for AttError {
+    fn from(err: L2capError) -> Self {
+        match err {
+            L2capError::ChannelNotFound => AttError::ChannelNotFound,
+            L2capError::MtuExceeded { requested, allowed } => {
+                AttError::L2capMtuExceeded { requested, allowed }
+            }
+            L2capError::NotConnected | L2capError::ConnectionTerminated => {
+                AttError::L2capDisconnected
+            }
+            L2capError::InvalidParameter(_)
+            | L2capError::ProtocolError(_)
+            | L2capError::Timeout
+            | L2capError::ConnectionRejected(_)
+            | L2capError::NotSupported
+            | L2capError::InvalidState
+            | L2capError::ResourceLimitReached
+            | L2capError::PsmNotRegistered
+            | L2capError::SecurityRequirementsNotMet
+            | L2capError::IoError(_)
+            | L2capError::HciError(_)
+            | L2capError::QueueFull => AttError::L2capError(err),
+        }
+    }
+}
+
 impl From<AttErrorCode> for AttError {
     fn from(code: AttErrorCode) -> Self {
+        AttError::from_error_response(code, 0)
+    }
+}
+
+impl AttError {
+    /// Create an error from the code and handle of an Error Response PDU
+    ///
+    /// Every standard code maps to its own variant so that the code survives
+    /// a round trip through `to_error_code`. Reserved codes are kept as
+    /// `Protocol` errors.
+    pub fn from_error_response(code: AttErrorCode, handle: u16) -> Self {
         match code {
-            AttErrorCode::NoError => AttError::Unknown("No error".into()),
-            AttErrorCode::InvalidHandle => AttError::InvalidHandle(0),
+            AttErrorCode::InvalidHandle => AttError::InvalidHandle(handle),
             AttErrorCode::ReadNotPermitted => AttError::ReadNotPermitted,
             AttErrorCode::WriteNotPermitted => AttError::WriteNotPermitted,
             AttErrorCode::InvalidPdu => AttError::InvalidPdu,
             AttErrorCode::InsufficientAuthentication => AttError::InsufficientAuthentication,
             AttErrorCode::RequestNotSupported => AttError::RequestNotSupported,
-            AttErrorCode::InvalidOffset => AttError::InvalidOffset(0),
+            AttErrorCode::InvalidOffset => AttError::InvalidOffset(handle),
             AttErrorCode::InsufficientAuthorization => AttError::InsufficientAuthorization,
             AttErrorCode::PrepareQueueFull => AttError::PrepareQueueFull,
             AttErrorCode::AttributeNotFound => AttError::AttributeNotFound,
@@ -227,15 +295,11 @@ impl From<AttErrorCode> for AttError {
             AttErrorCode::DatabaseOutOfSync => AttError::DatabaseOutOfSync,
             AttErrorCode::ValueNotAllowed => AttError::ValueNotAllowed,
             AttErrorCode::ApplicationError(code) => AttError::ApplicationError(code),
-            AttErrorCode::CommonProfileError(code) => AttError::ApplicationError(code),
-            AttErrorCode::Unknown(code) => {
-                AttError::Unknown(format!("Unknown error code: {}", code))
-            }
+            AttErrorCode::CommonProfileError(code) => AttError::CommonProfileError(code),
+            AttErrorCode::NoError | AttErrorCode::Unknown(_) => AttError::Protocol(code, handle),
         }
     }
-}
 
-impl AttError {
     /// Convert to ATT error code
     pub fn to_error_code(&self) -> AttErrorCode {
         match self {
@@ -260,9 +324,19 @@ impl AttError {
             AttError::DatabaseOutOfSync => AttErrorCode::DatabaseOutOfSync,
             AttError::ValueNotAllowed => AttErrorCode::ValueNotAllowed,
             AttError::ApplicationError(code) => AttErrorCode::ApplicationError(*code),
+            AttError::CommonProfileError(code) => AttErrorCode::CommonProfileError(*code),
+            AttError::InvalidOpcode(_) => AttErrorCode::RequestNotSupported,
+            AttError::UnsupportedOpcode(_) => AttErrorCode::RequestNotSupported,
+            AttError::UnexpectedResponse => AttErrorCode::Unlikely,
+            AttError::UnknownResponse(_) => AttErrorCode::Unlikely,
+            AttError::ChannelNotFound => AttErrorCode::Unlikely,
+            AttError::L2capMtuExceeded { .. } => AttErrorCode::Unlikely,
+            AttError::L2capDisconnected => AttErrorCode::Unlikely,
             AttError::L2capError(_) => AttErrorCode::Unlikely,
             AttError::InvalidParameter(_) => AttErrorCode::InvalidPdu,
             AttError::InvalidState => AttErrorCode::RequestNotSupported,
+            AttError::Busy => AttErrorCode::Unlikely,
+            AttError::Timeout => AttErrorCode::Unlikely,
             AttError::Unknown(_) => AttErrorCode::Unlikely,
         }
     }
@@ -7,15 +7,21 @@
 pub mod client;
 pub mod constants;
 pub mod database;
+pub mod eatt;
 pub mod error;
 pub mod server;
+#[cfg(test)]
+mod tests;
 pub mod types;
+pub mod xml;
 // pub mod pdu; // Assuming pdu module doesn't exist or isn't needed publicly
 
 // Re-export the public API
-pub use self::client::AttClient;
+pub use self::client::{AttClient, AttPeer};
 pub use self::constants::*;
-pub use self::database::{Attribute, AttributeDatabase};
+pub use self::database::{Attribute, AttributeBuilder, AttributeDatabase, AttributeReadHandler};
+pub use self::eatt::EattClient;
 pub use self::error::{AttError, AttErrorCode, AttResult};
-pub use self::server::{AttServer, AttServerConfig};
+pub use self::server::{AttServer, AttServerConfig, WriteValidator};
 pub use self::types::*; // Ensure types are re-exported
+pub use self::xml::XmlError;
@@ -1,26 +1,48 @@
 //! ATT Server implementation
 use super::constants::*;
-use super::database::{Attribute, AttributeDatabase};
+use super::database::AttributeDatabase;
 use super::error::{AttError, AttErrorCode, AttResult};
 use super::types::*;
 use crate::gap::BdAddr;
 use crate::gatt::Uuid;
-use crate::l2cap::{ConnectionType, L2capError, L2capManager};
-use std::collections::HashMap;
+use crate::l2cap::{
+    core::ChannelEvent, ConnectionPolicy, L2capError, L2capManager,
+    SecurityLevel as L2capSecurityLevel, PSM,
+};
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, RwLock};
 
 /// Client connection information
 struct ClientConnection {
-    /// BD address
-    addr: BdAddr,
+    /// HCI connection handle
+    hci_handle: u16,
     /// L2CAP channel ID
     channel_id: u16,
     /// MTU size
     mtu: u16,
     /// Security level
     security_level: SecurityLevel,
+    /// Indication sent but not yet confirmed
+    pending_indication: Option<PendingIndication>,
+    /// Indications waiting for the pending one to be confirmed
+    indication_queue: VecDeque<PendingIndication>,
+    /// Channel IDs of the client's EATT bearers
+    eatt_channels: Vec<u16>,
 }
 
+/// An indication value for a client
+struct PendingIndication {
+    /// Attribute handle
+    handle: u16,
+    /// Attribute value
+    value: Vec<u8>,
+}
+
+/// Application check of a value written by a client
+///
+/// Returns the error code sent to the client if the value is rejected.
+pub type WriteValidator = Arc<dyn Fn(&[u8]) -> Result<(), AttErrorCode> + Send + Sync>;
+
 /// ATT Server
 pub struct AttServer {
     /// L2CAP manager
@@ -30,9 +52,15 @@ pub struct AttServer {
     /// Server configuration
     config: RwLock<AttServerConfig>,
     /// Connected clients
-    clients: RwLock<HashMap<BdAddr, ClientConnection>>,
+    ///
+    /// Each client has its own lock so that the state of one client is
+    /// read and updated consistently while PDUs from other clients are
+    /// being handled.
+    clients: RwLock<HashMap<BdAddr, Arc<Mutex<ClientConnection>>>>,
     /// Prepared writes
     prepared_writes: RwLock<HashMap<BdAddr, Vec<PrepareWriteRequest>>>,
+    /// Validators of client writes, by attribute handle
+    write_validators: RwLock<HashMap<u16, WriteValidator>>,
 }
 
 /// ATT Server configuration
@@ -62,6 +90,38 @@ impl AttServer {
             config: RwLock::new(AttServerConfig::default()),
             clients: RwLock::new(HashMap::new()),
             prepared_writes: RwLock::new(HashMap::new()),
+            write_validators: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Check values clients write to an attribute before they are stored
+    ///
+    /// The validator runs for Write Requests, Write Commands and executed
+    /// prepared writes. A rejected value is not written, and the returned
+    /// code is sent in the Error Response. Validators must return an
+    /// application error (0x80-0x9F); other codes are sent as Unlikely Error.
+    pub fn set_write_validator(&self, handle: u16, validator: WriteValidator) {
+        let mut validators = self.write_validators.write().unwrap();
+        validators.insert(handle, validator);
+    }
+
+    /// Remove the validator of an attribute
+    pub fn clear_write_validator(&self, handle: u16) {
+        let mut validators = self.write_validators.write().unwrap();
+        validators.remove(&handle);
+    }
+
+    /// Run the validator of an attribute on a value written by a client
+    fn validate_write(&self, handle: u16, value: &[u8]) -> Result<(), AttErrorCode> {
+        let validator = {
+            let validators = self.write_validators.read().unwrap();
+            validators.get(&handle).cloned()
+        };
+
+        match validator.map_or(Ok(()), |validator| validator(value)) {
+            Ok(()) => Ok(()),
+            Err(code @ AttErrorCode::ApplicationError(_)) => Err(code),
+            Err(_) => Err(AttErrorCode::Unlikely),
         }
     }
 
@@ -77,41 +137,134 @@ impl AttServer {
     }
 
     /// Start the server
-    pub fn start(&self) -> AttResult<()> {
-        // Register for the ATT fixed channel
+    ///
+    /// PDUs received on the ATT fixed channel are passed to
+    /// `handle_att_pdu`, and every connection registered with the L2CAP
+    /// manager from now on is accepted as a client.
+    pub fn start(self: &Arc<Self>) -> AttResult<()> {
+        // Register for the ATT fixed channel. Weak references avoid a cycle
+        // between the server and the L2CAP manager.
+        let server = Arc::downgrade(self);
         self.l2cap_manager
             .register_fixed_channel_callback(
                 ATT_CID,
                 move |remote_addr: BdAddr, data: &[u8]| -> Result<(), L2capError> {
-                    // Handle incoming ATT data
-                    // In a real implementation, this would dispatch to the ATT server
-                    println!("Received ATT data from {}: {:?}", remote_addr, data);
+                    if let Some(server) = server.upgrade() {
+                        server
+                            .handle_att_pdu(remote_addr, data)
+                            .map_err(|e| L2capError::ProtocolError(e.to_string()))?;
+                    }
                     Ok(())
                 },
             )
-            .map_err(|e| AttError::from(e))?;
+            .map_err(AttError::from)?;
+
+        let server = Arc::downgrade(self);
+        self.l2cap_manager
+            .set_connection_opened_callback(Box::new(move |hci_handle, addr| {
+                if let Some(server) = server.upgrade() {
+                    // Clients that were already accepted are left as they are
+                    if let Ok(channel_id) =
+                        server.l2cap_manager.get_fixed_channel(ATT_CID, hci_handle)
+                    {
+                        let _ = server.accept_client(addr, hci_handle, channel_id);
+                    }
+                }
+            }));
 
         Ok(())
     }
 
+    /// Accept EATT bearers from clients
+    ///
+    /// Registers PSM 0x0027 with the L2CAP manager. Every channel a client
+    /// opens on it becomes an EATT bearer of that client, and PDUs received
+    /// on it are passed to `handle_eatt_pdu`. The client must already have
+    /// been accepted on the fixed ATT channel. Channels opened by a local
+    /// `EattClient` are left alone.
+    pub fn start_eatt(self: &Arc<Self>) -> AttResult<()> {
+        let server = Arc::downgrade(self);
+        let event_callback = move |event: ChannelEvent| -> Result<(), L2capError> {
+            if let ChannelEvent::Connected { cid, .. } = event {
+                if let Some(server) = server.upgrade() {
+                    if server.l2cap_manager.is_initiator(cid) {
+                        return Ok(());
+                    }
+                    server
+                        .accept_eatt_channel(cid)
+                        .map_err(|e| L2capError::ProtocolError(e.to_string()))?;
+                }
+            }
+            Ok(())
+        };
+
+        self.l2cap_manager
+            .register_psm(
+                PSM::EATT,
+                None,
+                Some(Arc::new(Mutex::new(event_callback))),
+                ConnectionPolicy {
+                    min_security_level: L2capSecurityLevel::None,
+                    authorization_required: false,
+                    auto_accept: true,
+                },
+            )
+            .map_err(AttError::from)
+    }
+
+    /// Add an open EATT channel as bearer of the client it belongs to
+    fn accept_eatt_channel(self: &Arc<Self>, channel_id: u16) -> AttResult<()> {
+        let addr = self
+            .l2cap_manager
+            .channel_peer_address(channel_id)
+            .ok_or(AttError::ChannelNotFound)?;
+        self.client(addr)?
+            .lock()
+            .unwrap()
+            .eatt_channels
+            .push(channel_id);
+
+        let server = Arc::downgrade(self);
+        self.l2cap_manager
+            .set_channel_data_callback(channel_id, move |data| {
+                if let Some(server) = server.upgrade() {
+                    server
+                        .handle_eatt_pdu(addr, channel_id, data)
+                        .map_err(|e| L2capError::ProtocolError(e.to_string()))?;
+                }
+                Ok(())
+            })
+            .map_err(AttError::from)
+    }
+
+    /// Get the channel IDs of a client's EATT bearers
+    pub fn eatt_channels(&self, addr: BdAddr) -> Vec<u16> {
+        self.client(addr)
+            .map(|client| client.lock().unwrap().eatt_channels.clone())
+            .unwrap_or_default()
+    }
+
     /// Stop the server
     pub fn stop(&self) -> AttResult<()> {
         // Unregister from the ATT fixed channel
         self.l2cap_manager
             .unregister_fixed_channel_callback(ATT_CID)
-            .map_err(|e| AttError::from(e))?;
+            .map_err(AttError::from)?;
+
+        // Stop accepting EATT bearers, if they were accepted at all
+        let _ = self.l2cap_manager.unregister_psm(PSM::EATT);
 
         // Disconnect all clients
-        let clients = self.clients.read().unwrap().clone();
-        for (addr, client) in clients {
+        let addrs: Vec<BdAddr> = self.clients.read().unwrap().keys().copied().collect();
+        for addr in addrs {
             self.disconnect_client(addr)?;
         }
 
         Ok(())
     }
 
-    /// Accept a client connection
-    pub fn accept_client(&self, addr: BdAddr, channel_id: u16) -> AttResult<()> {
+    /// Accept a client connected on `hci_handle`, using ATT channel `channel_id`
+    pub fn accept_client(&self, addr: BdAddr, hci_handle: u16, channel_id: u16) -> AttResult<()> {
         // Check if client is already connected
         let mut clients = self.clients.write().unwrap();
         if clients.contains_key(&addr) {
@@ -120,14 +273,17 @@ impl AttServer {
 
         // Create new client connection
         let client = ClientConnection {
-            addr,
+            hci_handle,
             channel_id,
             mtu: ATT_DEFAULT_MTU,
             security_level: SecurityLevel::None,
+            pending_indication: None,
+            indication_queue: VecDeque::new(),
+            eatt_channels: Vec::new(),
         };
 
         // Add to connected clients
-        clients.insert(addr, client);
+        clients.insert(addr, Arc::new(Mutex::new(client)));
 
         Ok(())
     }
@@ -139,6 +295,7 @@ impl AttServer {
             let mut clients = self.clients.write().unwrap();
             clients.remove(&addr).ok_or(AttError::InvalidState)?
         };
+        let channel_id = client.lock().unwrap().channel_id;
 
         // Clear any prepared writes
         {
@@ -148,16 +305,33 @@ impl AttServer {
 
         // Disconnect L2CAP channel
         self.l2cap_manager
-            .disconnect(client.channel_id)
-            .map_err(|e| AttError::from(e))?;
+            .disconnect(channel_id)
+            .map_err(AttError::from)?;
 
         Ok(())
     }
 
+    /// Drop client state when the underlying HCI connection closes
+    ///
+    /// Registers a connection closed callback on the L2CAP manager that
+    /// disconnects the client whose address is mapped to the closed handle.
+    pub fn register_connection_closed_handler(self: &Arc<Self>) {
+        let server = Arc::downgrade(self);
+        self.l2cap_manager
+            .set_connection_closed_callback(Box::new(move |hci_handle| {
+                if let Some(server) = server.upgrade() {
+                    if let Some(addr) = server.l2cap_manager.peer_address(hci_handle) {
+                        // The channel is already gone, so only the local state matters
+                        let _ = server.disconnect_client(addr);
+                    }
+                }
+            }));
+    }
+
     /// Set client security level
     pub fn set_client_security_level(&self, addr: BdAddr, level: SecurityLevel) -> AttResult<()> {
-        let mut clients = self.clients.write().unwrap();
-        let client = clients.get_mut(&addr).ok_or(AttError::InvalidState)?;
+        let client = self.client(addr)?;
+        let mut client = client.lock().unwrap();
         client.security_level = level;
 
         Ok(())
@@ -165,8 +339,8 @@ impl AttServer {
 
     /// Get client security level
     pub fn client_security_level(&self, addr: BdAddr) -> AttResult<SecurityLevel> {
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
+        let client = self.client(addr)?;
+        let client = client.lock().unwrap();
 
         Ok(client.security_level)
     }
@@ -174,8 +348,8 @@ impl AttServer {
     /// Send a notification to a client
     pub fn send_notification(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
         // Check if client is connected
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
+        let client = self.client(addr)?;
+        let client = client.lock().unwrap();
 
         // Check value length against MTU
         if value.len() > (client.mtu as usize - 3) {
@@ -191,38 +365,89 @@ impl AttServer {
         // Send notification
         let data = notification.serialize();
         self.l2cap_manager
-            .send_data(client.channel_id, &data)
-            .map_err(|e| AttError::from(e))?;
+            .send_data_on(client.channel_id, client.hci_handle, &data)
+            .map_err(AttError::from)?;
 
         Ok(())
     }
 
     /// Send an indication to a client
+    ///
+    /// Only one indication may be outstanding per client, so this fails with
+    /// `AttError::Busy` until the previous one has been confirmed.
     pub fn send_indication(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
-        // Check if client is connected
+        let client = self.client(addr)?;
+        let mut client = client.lock().unwrap();
+
+        if client.pending_indication.is_some() {
+            return Err(AttError::Busy);
+        }
+
+        let indication = Self::new_indication(&client, handle, value)?;
+        self.transmit_indication(&mut client, indication)
+    }
+
+    /// Send an indication to a client, queueing it if one is outstanding
+    ///
+    /// Queued indications are sent in order as confirmations arrive.
+    pub fn send_indication_queued(&self, addr: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
+        let client = self.client(addr)?;
+        let mut client = client.lock().unwrap();
+
+        let indication = Self::new_indication(&client, handle, value)?;
+        if client.pending_indication.is_some() {
+            client.indication_queue.push_back(indication);
+            return Ok(());
+        }
+
+        self.transmit_indication(&mut client, indication)
+    }
+
+    /// Check whether a client has an unconfirmed indication
+    pub fn has_pending_indication(&self, addr: BdAddr) -> bool {
+        self.client(addr)
+            .is_ok_and(|client| client.lock().unwrap().pending_indication.is_some())
+    }
+
+    /// Get the connection state of a client
+    fn client(&self, addr: BdAddr) -> AttResult<Arc<Mutex<ClientConnection>>> {
         let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
+        clients.get(&addr).cloned().ok_or(AttError::InvalidState)
+    }
 
-        // Check value length against MTU
+    /// Validate an indication value against the client MTU
+    fn new_indication(
+        client: &ClientConnection,
+        handle: u16,
+        value: &[u8],
+    ) -> AttResult<PendingIndication> {
         if value.len() > (client.mtu as usize - 3) {
             return Err(AttError::InvalidAttributeValueLength);
         }
 
-        // Create indication
-        let indication = HandleValueIndication {
+        Ok(PendingIndication {
             handle,
             value: value.to_vec(),
+        })
+    }
+
+    /// Send an indication and mark it as pending until confirmed
+    fn transmit_indication(
+        &self,
+        client: &mut ClientConnection,
+        indication: PendingIndication,
+    ) -> AttResult<()> {
+        let pdu = HandleValueIndication {
+            handle: indication.handle,
+            value: indication.value.clone(),
         };
 
-        // Send indication
-        let data = indication.serialize();
+        let data = pdu.serialize();
         self.l2cap_manager
-            .send_data(client.channel_id, &data)
-            .map_err(|e| AttError::from(e))?;
+            .send_data_on(client.channel_id, client.hci_handle, &data)
+            .map_err(AttError::from)?;
 
-        // Wait for confirmation
-        // In a real implementation, we would wait for a confirmation
-        // and potentially retry or timeout
+        client.pending_indication = Some(indication);
 
         Ok(())
     }
@@ -233,13 +458,90 @@ impl AttServer {
             return Err(AttError::InvalidPdu);
         }
 
-        // Check if client is connected
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
-        let channel_id = client.channel_id;
-        let security_level = client.security_level;
-        drop(clients); // Release lock
+        // Take the channel and security level of this client together, so
+        // the PDU is handled with a consistent view of its connection
+        let (channel_id, security_level) = {
+            let client = self.client(addr)?;
+            let client = client.lock().unwrap();
+            (client.channel_id, client.security_level)
+        };
+
+        self.handle_bearer_pdu(addr, data, channel_id, security_level)
+    }
+
+    /// Handle an ATT PDU received on one of a client's EATT bearers
+    ///
+    /// The response is sent on the same bearer. The MTU of an EATT bearer
+    /// is that of its L2CAP channel, so Exchange MTU Requests are refused.
+    /// EATT is only used on encrypted links, so requests on an unencrypted
+    /// one are answered with Insufficient Encryption and other PDUs dropped.
+    pub fn handle_eatt_pdu(&self, addr: BdAddr, channel_id: u16, data: &[u8]) -> AttResult<()> {
+        if data.is_empty() {
+            return Err(AttError::InvalidPdu);
+        }
+
+        let security_level = {
+            let client = self.client(addr)?;
+            let client = client.lock().unwrap();
+            if !client.eatt_channels.contains(&channel_id) {
+                return Err(AttError::ChannelNotFound);
+            }
+            client.security_level
+        };
 
+        if security_level == SecurityLevel::None {
+            let opcode = data[0];
+            if matches!(
+                opcode,
+                ATT_WRITE_CMD | ATT_SIGNED_WRITE_CMD | ATT_HANDLE_VALUE_CONF
+            ) {
+                return Err(AttError::InsufficientEncryption);
+            }
+            return self.send_error_response(
+                addr,
+                channel_id,
+                opcode,
+                0,
+                AttErrorCode::InsufficientEncryption,
+            );
+        }
+
+        if data[0] == ATT_EXCHANGE_MTU_REQ {
+            return self.send_error_response(
+                addr,
+                channel_id,
+                ATT_EXCHANGE_MTU_REQ,
+                0,
+                AttErrorCode::RequestNotSupported,
+            );
+        }
+
+        self.handle_bearer_pdu(addr, data, channel_id, security_level)
+    }
+
+    /// Get the MTU of the bearer a client's PDU arrived on
+    ///
+    /// The fixed channel uses the MTU exchanged with the client, an EATT
+    /// bearer that of its L2CAP channel.
+    fn bearer_mtu(&self, addr: BdAddr, channel_id: u16) -> AttResult<u16> {
+        let client = self.client(addr)?;
+        let client = client.lock().unwrap();
+        if channel_id == client.channel_id {
+            return Ok(client.mtu);
+        }
+        self.l2cap_manager
+            .channel_mtu(channel_id)
+            .ok_or(AttError::ChannelNotFound)
+    }
+
+    /// Handle a PDU from a client, responding on `channel_id`
+    fn handle_bearer_pdu(
+        &self,
+        addr: BdAddr,
+        data: &[u8],
+        channel_id: u16,
+        security_level: SecurityLevel,
+    ) -> AttResult<()> {
         // Parse opcode
         let opcode = data[0];
 
@@ -276,7 +578,13 @@ impl AttServer {
             ATT_HANDLE_VALUE_CONF => self.handle_handle_value_confirmation(addr),
             _ => {
                 // Unknown or unsupported opcode
-                self.send_error_response(channel_id, opcode, 0, AttErrorCode::RequestNotSupported)
+                self.send_error_response(
+                    addr,
+                    channel_id,
+                    opcode,
+                    0,
+                    AttErrorCode::RequestNotSupported,
+                )
             }
         }
     }
@@ -293,6 +601,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_EXCHANGE_MTU_REQ,
                     0,
@@ -306,8 +615,8 @@ impl AttServer {
 
         // Update client MTU
         {
-            let mut clients = self.clients.write().unwrap();
-            if let Some(client) = clients.get_mut(&addr) {
+            if let Ok(client) = self.client(addr) {
+                let mut client = client.lock().unwrap();
                 client.mtu = std::cmp::min(request.client_mtu, server_mtu);
             }
         }
@@ -316,15 +625,13 @@ impl AttServer {
         let response = ExchangeMtuResponse { server_mtu };
         let response_data = response.serialize();
 
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Find Information Request
     fn handle_find_information_request(
         &self,
-        _addr: BdAddr,
+        addr: BdAddr,
         data: &[u8],
         channel_id: u16,
         security_level: SecurityLevel,
@@ -334,6 +641,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_FIND_INFO_REQ,
                     0,
@@ -345,6 +653,7 @@ impl AttServer {
         // Validate handles
         if request.start_handle > request.end_handle {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_FIND_INFO_REQ,
                 request.start_handle,
@@ -361,6 +670,7 @@ impl AttServer {
             Ok(info) => info,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_FIND_INFO_REQ,
                     request.start_handle,
@@ -372,6 +682,7 @@ impl AttServer {
         // Check if any attributes were found
         if info.is_empty() {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_FIND_INFO_REQ,
                 request.start_handle,
@@ -406,9 +717,7 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Find By Type Value Request
@@ -424,6 +733,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_FIND_BY_TYPE_VALUE_REQ,
                     0,
@@ -435,6 +745,7 @@ impl AttServer {
         // Validate handles
         if request.start_handle > request.end_handle {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_FIND_BY_TYPE_VALUE_REQ,
                 request.start_handle,
@@ -454,6 +765,7 @@ impl AttServer {
             Ok(handles) => handles,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_FIND_BY_TYPE_VALUE_REQ,
                     request.start_handle,
@@ -465,6 +777,7 @@ impl AttServer {
         // Check if any attributes were found
         if handles.is_empty() {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_FIND_BY_TYPE_VALUE_REQ,
                 request.start_handle,
@@ -488,9 +801,7 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Read By Type Request
@@ -506,6 +817,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_BY_TYPE_REQ,
                     0,
@@ -517,6 +829,7 @@ impl AttServer {
         // Validate handles
         if request.start_handle > request.end_handle {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_READ_BY_TYPE_REQ,
                 request.start_handle,
@@ -534,9 +847,10 @@ impl AttServer {
             Ok(attrs) => attrs,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_BY_TYPE_REQ,
-                    request.start_handle,
+                    e.handle().unwrap_or(request.start_handle),
                     e.to_error_code(),
                 )
             }
@@ -545,6 +859,7 @@ impl AttServer {
         // Check if any attributes were found
         if attributes.is_empty() {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_READ_BY_TYPE_REQ,
                 request.start_handle,
@@ -553,8 +868,8 @@ impl AttServer {
         }
 
         // Get client MTU
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
+        let client = self.client(addr)?;
+        let client = client.lock().unwrap();
         let _mtu = client.mtu;
 
         // Determine length (must be the same for all entries)
@@ -589,9 +904,7 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Read Request
@@ -606,15 +919,28 @@ impl AttServer {
         let request = match ReadRequest::parse(data) {
             Ok(req) => req,
             Err(e) => {
-                return self.send_error_response(channel_id, ATT_READ_REQ, 0, e.to_error_code())
+                return self.send_error_response(
+                    addr,
+                    channel_id,
+                    ATT_READ_REQ,
+                    0,
+                    e.to_error_code(),
+                )
             }
         };
 
+        // The value must fit into MTU - 1 bytes after the opcode
+        let max_len = self.bearer_mtu(addr, channel_id)? as usize - 1;
+
         // Read attribute
-        let value = match self.database.read_by_handle(request.handle, security_level) {
+        let value = match self
+            .database
+            .read_part(request.handle, 0, max_len, security_level)
+        {
             Ok(value) => value,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_REQ,
                     request.handle,
@@ -623,27 +949,12 @@ impl AttServer {
             }
         };
 
-        // Get client MTU
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
-        let _mtu = client.mtu;
-
-        // Truncate value if larger than MTU - 1
-        let max_len = client.mtu as usize - 1;
-        let value = if value.len() > max_len {
-            value[..max_len].to_vec()
-        } else {
-            value
-        };
-
         // Create response
         let response = ReadResponse { value };
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Read Blob Request
@@ -659,6 +970,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_BLOB_REQ,
                     0,
@@ -667,15 +979,19 @@ impl AttServer {
             }
         };
 
+        // Read Blob Responses carry no offset, so each part is MTU - 1 bytes
+        let max_len = self.bearer_mtu(addr, channel_id)? as usize - 1;
+
         // Read blob
         let value =
             match self
                 .database
-                .read_blob_by_handle(request.handle, request.offset, security_level)
+                .read_part(request.handle, request.offset, max_len, security_level)
             {
                 Ok(value) => value,
                 Err(e) => {
                     return self.send_error_response(
+                        addr,
                         channel_id,
                         ATT_READ_BLOB_REQ,
                         request.handle,
@@ -684,27 +1000,12 @@ impl AttServer {
                 }
             };
 
-        // Get client MTU
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
-        let _mtu = client.mtu;
-
-        // Truncate value if larger than MTU - 1
-        let max_len = client.mtu as usize - 1;
-        let value = if value.len() > max_len {
-            value[..max_len].to_vec()
-        } else {
-            value
-        };
-
         // Create response
         let response = ReadBlobResponse { value };
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Read Multiple Request
@@ -720,6 +1021,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_MULTIPLE_REQ,
                     0,
@@ -737,8 +1039,13 @@ impl AttServer {
             Err(e) => {
                 // Find the handle that caused the error
                 for &handle in &request.handles {
-                    if let Err(_) = self.database.read_by_handle(handle, security_level) {
+                    if self
+                        .database
+                        .read_by_handle(handle, security_level)
+                        .is_err()
+                    {
                         return self.send_error_response(
+                            addr,
                             channel_id,
                             ATT_READ_MULTIPLE_REQ,
                             handle,
@@ -749,6 +1056,7 @@ impl AttServer {
 
                 // If we can't determine which handle caused the error, use the first one
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_MULTIPLE_REQ,
                     request.handles[0],
@@ -757,13 +1065,8 @@ impl AttServer {
             }
         };
 
-        // Get client MTU
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
-        let _mtu = client.mtu;
-
         // Truncate values if larger than MTU - 1
-        let max_len = client.mtu as usize - 1;
+        let max_len = self.bearer_mtu(addr, channel_id)? as usize - 1;
         let values = if values.len() > max_len {
             values[..max_len].to_vec()
         } else {
@@ -775,9 +1078,7 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Read By Group Type Request
@@ -793,6 +1094,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_BY_GROUP_TYPE_REQ,
                     0,
@@ -804,6 +1106,7 @@ impl AttServer {
         // Validate handles
         if request.start_handle > request.end_handle {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_READ_BY_GROUP_TYPE_REQ,
                 request.start_handle,
@@ -816,6 +1119,7 @@ impl AttServer {
             && request.group_type != Uuid::from_u16(SECONDARY_SERVICE_UUID)
         {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_READ_BY_GROUP_TYPE_REQ,
                 request.start_handle,
@@ -833,6 +1137,7 @@ impl AttServer {
             Ok(groups) => groups,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_READ_BY_GROUP_TYPE_REQ,
                     request.start_handle,
@@ -844,6 +1149,7 @@ impl AttServer {
         // Check if any groups were found
         if groups.is_empty() {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_READ_BY_GROUP_TYPE_REQ,
                 request.start_handle,
@@ -852,8 +1158,8 @@ impl AttServer {
         }
 
         // Get client MTU
-        let clients = self.clients.read().unwrap();
-        let client = clients.get(&addr).ok_or(AttError::InvalidState)?;
+        let client = self.client(addr)?;
+        let client = client.lock().unwrap();
         let _mtu = client.mtu;
 
         // Determine length (must be the same for all entries)
@@ -882,9 +1188,7 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Write Request
@@ -899,10 +1203,20 @@ impl AttServer {
         let request = match WriteRequest::parse(data) {
             Ok(req) => req,
             Err(e) => {
-                return self.send_error_response(channel_id, ATT_WRITE_REQ, 0, e.to_error_code())
+                return self.send_error_response(
+                    addr,
+                    channel_id,
+                    ATT_WRITE_REQ,
+                    0,
+                    e.to_error_code(),
+                )
             }
         };
 
+        if let Err(code) = self.validate_write(request.handle, &request.value) {
+            return self.send_error_response(addr, channel_id, ATT_WRITE_REQ, request.handle, code);
+        }
+
         // Write to attribute
         match self
             .database
@@ -911,6 +1225,7 @@ impl AttServer {
             Ok(_) => {}
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_WRITE_REQ,
                     request.handle,
@@ -922,15 +1237,13 @@ impl AttServer {
         // Send response
         let response = WriteResponse;
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Write Command
     fn handle_write_command(
         &self,
-        addr: BdAddr,
+        _addr: BdAddr,
         data: &[u8],
         security_level: SecurityLevel,
     ) -> AttResult<()> {
@@ -940,6 +1253,11 @@ impl AttServer {
             Err(_) => return Ok(()), // Ignore invalid commands
         };
 
+        // Rejected values are dropped, there is no response to report them
+        if self.validate_write(command.handle, &command.value).is_err() {
+            return Ok(());
+        }
+
         // Write to attribute (ignore errors)
         let _ = self
             .database
@@ -962,6 +1280,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_PREPARE_WRITE_REQ,
                     0,
@@ -975,6 +1294,7 @@ impl AttServer {
             Ok(attr) => attr,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_PREPARE_WRITE_REQ,
                     request.handle,
@@ -985,6 +1305,7 @@ impl AttServer {
 
         if !attr.can_write(security_level) {
             return self.send_error_response(
+                addr,
                 channel_id,
                 ATT_PREPARE_WRITE_REQ,
                 request.handle,
@@ -995,11 +1316,12 @@ impl AttServer {
         // Store the prepared write
         {
             let mut prepared_writes = self.prepared_writes.write().unwrap();
-            let client_writes = prepared_writes.entry(addr).or_insert_with(Vec::new);
+            let client_writes = prepared_writes.entry(addr).or_default();
 
             // Check queue size
             if client_writes.len() >= ATT_PREPARE_WRITE_QUEUE_SIZE {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_PREPARE_WRITE_REQ,
                     request.handle,
@@ -1019,9 +1341,7 @@ impl AttServer {
         };
 
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Execute Write Request
@@ -1037,6 +1357,7 @@ impl AttServer {
             Ok(req) => req,
             Err(e) => {
                 return self.send_error_response(
+                    addr,
                     channel_id,
                     ATT_EXECUTE_WRITE_REQ,
                     0,
@@ -1053,51 +1374,42 @@ impl AttServer {
 
         // Execute or cancel
         if request.flags == ATT_EXEC_WRITE_COMMIT {
-            // Execute writes
-            let mut attribute_values = HashMap::new();
-
-            // Collect values by handle
-            for write in &prepared_writes {
-                let entry = attribute_values
-                    .entry(write.handle)
-                    .or_insert_with(Vec::new);
-                entry.push((write.offset, write.value.clone()));
-            }
-
-            // Execute writes for each handle
-            for (handle, parts) in attribute_values {
-                // Sort by offset
-                let mut sorted_parts = parts;
-                sorted_parts.sort_by_key(|(offset, _)| *offset);
-
-                // Check if parts are contiguous
-                let mut expected_offset = 0;
-                for (offset, part) in &sorted_parts {
-                    if *offset != expected_offset {
-                        return self.send_error_response(
-                            channel_id,
-                            ATT_EXECUTE_WRITE_REQ,
-                            handle,
-                            AttErrorCode::InvalidOffset,
-                        );
-                    }
-                    expected_offset = *offset + part.len() as u16;
+            // Validate every value before writing any of them
+            let values = match assemble_prepared_writes(&prepared_writes) {
+                Ok(values) => values,
+                Err((handle, code)) => {
+                    return self.send_error_response(
+                        addr,
+                        channel_id,
+                        ATT_EXECUTE_WRITE_REQ,
+                        handle,
+                        code,
+                    )
                 }
+            };
 
-                // Combine parts
-                let mut combined_value = Vec::new();
-                for (_, part) in sorted_parts {
-                    combined_value.extend_from_slice(&part);
+            for (handle, value) in &values {
+                if let Err(code) = self.validate_write(*handle, value) {
+                    return self.send_error_response(
+                        addr,
+                        channel_id,
+                        ATT_EXECUTE_WRITE_REQ,
+                        *handle,
+                        code,
+                    );
                 }
+            }
 
-                // Write to attribute
+            // Execute writes in the order the handles were first prepared
+            for (handle, value) in values {
                 match self
                     .database
-                    .write_by_handle(handle, &combined_value, security_level)
+                    .write_by_handle(handle, &value, security_level)
                 {
                     Ok(_) => {}
                     Err(e) => {
                         return self.send_error_response(
+                            addr,
                             channel_id,
                             ATT_EXECUTE_WRITE_REQ,
                             handle,
@@ -1111,22 +1423,26 @@ impl AttServer {
         // Send response
         let response = ExecuteWriteResponse;
         let response_data = response.serialize();
-        self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+        self.send_pdu(addr, channel_id, &response_data)
     }
 
     /// Handle Handle Value Confirmation
     fn handle_handle_value_confirmation(&self, addr: BdAddr) -> AttResult<()> {
-        // Process indication confirmation
-        // In a real implementation, this would release any pending indication
-
-        Ok(())
+        let client = self.client(addr)?;
+        let mut client = client.lock().unwrap();
+
+        // Release the pending indication and flush the next queued one
+        client.pending_indication = None;
+        match client.indication_queue.pop_front() {
+            Some(indication) => self.transmit_indication(&mut client, indication),
+            None => Ok(()),
+        }
     }
 
     /// Send an error response
     fn send_error_response(
         &self,
+        addr: BdAddr,
         channel_id: u16,
         request_opcode: u8,
         handle: u16,
@@ -1141,8 +1457,66 @@ impl AttServer {
 
         // Send response
         let response_data = response.serialize();
+        self.send_pdu(addr, channel_id, &response_data)
+    }
+
+    /// Send a PDU on one of a client's bearers
+    ///
+    /// The ATT fixed channel exists on every connection, so the PDU is sent
+    /// on the client's own HCI connection.
+    fn send_pdu(&self, addr: BdAddr, channel_id: u16, pdu: &[u8]) -> AttResult<()> {
+        let hci_handle = self.client(addr)?.lock().unwrap().hci_handle;
         self.l2cap_manager
-            .send_data(channel_id, &response_data)
-            .map_err(|e| AttError::from(e))
+            .send_data_on(channel_id, hci_handle, pdu)
+            .map_err(AttError::from)
+    }
+}
+
+/// Values assembled from prepared writes, or the handle that failed and why
+type AssembledWrites = Result<Vec<(u16, Vec<u8>)>, (u16, AttErrorCode)>;
+
+/// Assemble queued prepared writes into one value per handle
+///
+/// Handles are returned in the order they were first prepared. The parts for
+/// each handle must start at offset 0 and be contiguous; a gap, an overlap or
+/// a value running past the 16-bit offset range fails with `InvalidOffset`
+/// for that handle.
+fn assemble_prepared_writes(writes: &[PrepareWriteRequest]) -> AssembledWrites {
+    let mut parts_by_handle: Vec<(u16, Vec<&PrepareWriteRequest>)> = Vec::new();
+    for write in writes {
+        match parts_by_handle
+            .iter_mut()
+            .find(|(handle, _)| *handle == write.handle)
+        {
+            Some((_, parts)) => parts.push(write),
+            None => parts_by_handle.push((write.handle, vec![write])),
+        }
+    }
+
+    let mut values = Vec::with_capacity(parts_by_handle.len());
+    for (handle, mut parts) in parts_by_handle {
+        // Stable, so parts at the same offset keep their queue order
+        parts.sort_by_key(|part| part.offset);
+
+        if parts.first().map(|part| part.offset) != Some(0) {
+            return Err((handle, AttErrorCode::InvalidOffset));
+        }
+
+        let mut expected_offset: u32 = 0;
+        let mut value = Vec::new();
+        for part in parts {
+            if part.offset as u32 != expected_offset {
+                return Err((handle, AttErrorCode::InvalidOffset));
+            }
+            expected_offset += part.value.len() as u32;
+            if expected_offset > u16::MAX as u32 + 1 {
+                return Err((handle, AttErrorCode::InvalidOffset));
+            }
+            value.extend_from_slice(&part.value);
+        }
+
+        values.push((handle, value));
     }
+
+    Ok(values)
 }
@@ -0,0 +1,1231 @@
+//! Unit tests for ATT functionality
+
+use super::client::AttClient;
+use super::constants::*;
+use super::database::{AttributeBuilder, AttributeDatabase};
+use super::eatt::EattClient;
+use super::error::{AttError, AttErrorCode};
+use super::server::AttServer;
+use super::types::{AttPermissions, SecurityLevel};
+use super::xml::XmlError;
+use crate::gap::BdAddr;
+use crate::l2cap::constants::L2CAP_ATT_CID;
+use crate::l2cap::signaling::SignalingMessage;
+use crate::l2cap::ConnectionType;
+use crate::testing::MockL2capManager;
+use crate::uuid::Uuid;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn peer() -> BdAddr {
+    BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06])
+}
+
+fn connected_client(hci_handle: u16) -> (MockL2capManager, Arc<AttClient>) {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let client = Arc::new(AttClient::new(mock.manager()));
+    client.connect(peer(), hci_handle).unwrap();
+    (mock, client)
+}
+
+#[test]
+fn test_att_client_connect_uses_fixed_channel() {
+    let (mock, client) = connected_client(0x0040);
+    assert!(client.is_connected(peer()));
+
+    // The fixed channel is shared, so asking again yields the same CID
+    assert_eq!(
+        mock.manager()
+            .get_fixed_channel(L2CAP_ATT_CID, 0x0040)
+            .unwrap(),
+        L2CAP_ATT_CID
+    );
+
+    // Connecting twice is a no-op
+    assert!(client.connect(peer(), 0x0040).is_ok());
+}
+
+#[test]
+fn test_att_client_receives_channel_data() {
+    let (mock, client) = connected_client(0x0040);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    client.set_notification_callback(move |addr, handle, value| {
+        assert_eq!(addr, peer());
+        received_clone
+            .lock()
+            .unwrap()
+            .push((handle, value.to_vec()));
+        Ok(())
+    });
+
+    // Handle Value Notification for handle 0x0010 arriving on the ATT channel
+    let pdu = vec![ATT_HANDLE_VALUE_NTF, 0x10, 0x00, 0xAA, 0xBB];
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0], (0x0010, vec![0xAA, 0xBB]));
+}
+
+#[test]
+fn test_att_client_disconnect_deregisters_callback() {
+    let (mock, client) = connected_client(0x0040);
+
+    let count = Arc::new(Mutex::new(0));
+    let count_clone = count.clone();
+    client.set_notification_callback(move |_, _, _| {
+        *count_clone.lock().unwrap() += 1;
+        Ok(())
+    });
+
+    client.disconnect(peer()).unwrap();
+    assert!(!client.is_connected(peer()));
+
+    let pdu = vec![ATT_HANDLE_VALUE_NTF, 0x10, 0x00, 0xAA];
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+
+    assert_eq!(*count.lock().unwrap(), 0);
+}
+
+#[test]
+fn test_att_client_routes_pdus_by_peer() {
+    let (mock, client) = connected_client(0x0040);
+    let other = BdAddr::new([0x11, 0x12, 0x13, 0x14, 0x15, 0x16]);
+    client.connect(other, 0x0041).unwrap();
+    assert_eq!(client.connected_peers().len(), 2);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    client.set_notification_callback(move |addr, handle, _| {
+        received_clone.lock().unwrap().push((addr, handle));
+        Ok(())
+    });
+
+    // Both connections use the fixed ATT channel
+    for (hci_handle, attr_handle) in [(0x0041, 0x0020), (0x0040, 0x0010)] {
+        let mut pdu = vec![ATT_HANDLE_VALUE_NTF];
+        pdu.extend_from_slice(&(attr_handle as u16).to_le_bytes());
+        mock.inject(hci_handle, L2CAP_ATT_CID, &pdu).unwrap();
+    }
+
+    // Disconnecting one peer leaves the other connected
+    client.disconnect(other).unwrap();
+    assert!(client.is_connected(peer()));
+    assert!(!client.is_connected(other));
+
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![(other, 0x0020), (peer(), 0x0010)]
+    );
+}
+
+#[test]
+fn test_att_error_code_roundtrip() {
+    for code in 0..=u8::MAX {
+        let error_code = AttErrorCode::from(code);
+        let raw: u8 = error_code.into();
+        assert_eq!(raw, code);
+
+        // Error responses keep their code through AttError
+        let error = AttError::from_error_response(error_code, 0x0010);
+        let raw: u8 = error.to_error_code().into();
+        assert_eq!(raw, code);
+    }
+
+    assert_eq!(
+        AttErrorCode::from(0x85),
+        AttErrorCode::ApplicationError(0x85)
+    );
+    assert_eq!(
+        AttErrorCode::from(0xFD),
+        AttErrorCode::CommonProfileError(0xFD)
+    );
+    assert_eq!(AttErrorCode::from(0x50), AttErrorCode::Unknown(0x50));
+
+    let error = AttError::from_error_response(AttErrorCode::InvalidHandle, 0x0010);
+    assert!(matches!(error, AttError::InvalidHandle(0x0010)));
+    assert_eq!(error.handle(), Some(0x0010));
+}
+
+#[test]
+fn test_att_server_indication_queueing() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let cid = manager.get_fixed_channel(L2CAP_ATT_CID, 0x0040).unwrap();
+    let server = AttServer::new(manager, Arc::new(AttributeDatabase::new()));
+
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    server.accept_client(addr, 0x0040, cid).unwrap();
+
+    server.send_indication(addr, 0x0010, &[0x01]).unwrap();
+    assert!(server.has_pending_indication(addr));
+
+    // A second indication must wait for the confirmation
+    assert!(matches!(
+        server.send_indication(addr, 0x0010, &[0x02]),
+        Err(AttError::Busy)
+    ));
+    server
+        .send_indication_queued(addr, 0x0010, &[0x02])
+        .unwrap();
+
+    // The confirmation flushes the queued indication, which is then pending
+    server
+        .handle_att_pdu(addr, &[ATT_HANDLE_VALUE_CONF])
+        .unwrap();
+    assert!(server.has_pending_indication(addr));
+
+    server
+        .handle_att_pdu(addr, &[ATT_HANDLE_VALUE_CONF])
+        .unwrap();
+    assert!(!server.has_pending_indication(addr));
+    server.send_indication(addr, 0x0010, &[0x03]).unwrap();
+}
+
+#[test]
+fn test_attribute_database_gatt_xml_roundtrip() {
+    let database = AttributeDatabase::new();
+    let read_only = AttPermissions::read_only();
+    database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(PRIMARY_SERVICE_UUID),
+            vec![0x0F, 0x18],
+            read_only,
+        )
+        .unwrap();
+    database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(CHARACTERISTIC_UUID),
+            vec![0x12, 0x03, 0x00, 0x19, 0x2A],
+            read_only,
+        )
+        .unwrap();
+    database
+        .add_attribute_with_next_handle(Uuid::from_u16(0x2A19), vec![0x64], read_only)
+        .unwrap();
+    database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID),
+            vec![0x00, 0x00],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+
+    let xml = database.to_gatt_xml();
+    assert!(xml.contains("<Service uuid=\"180F\" type=\"primary\" handle=\"0x0001\">"));
+    assert!(xml.contains("<Value>64</Value>"));
+
+    let imported = AttributeDatabase::from_gatt_xml(&xml).unwrap();
+    let original = database.get_attributes_in_range(0x0001, 0xFFFF).unwrap();
+    let imported = imported.get_attributes_in_range(0x0001, 0xFFFF).unwrap();
+    assert_eq!(original.len(), imported.len());
+    for (a, b) in original.iter().zip(&imported) {
+        assert_eq!(a.handle, b.handle);
+        assert_eq!(a.type_, b.type_);
+        assert_eq!(a.value, b.value);
+        assert_eq!(a.permissions, b.permissions);
+    }
+
+    assert!(
+        AttributeDatabase::from_gatt_xml("<Database><Service uuid=\"180F\"></Database>").is_err()
+    );
+}
+
+#[test]
+fn test_attribute_database_gatt_xml_markup() {
+    // A quoted `>`, a DOCTYPE subset, references and CDATA are all XML that
+    // a hand-written definition may contain
+    let xml = r#"<?xml version="1.0"?>
+<!DOCTYPE Database [ <!ELEMENT Database (Service*)> ]>
+<!-- Battery service -> one characteristic -->
+<Database>
+  <Service uuid="&#x31;80F" name="level > 0 &amp; &lt; 100" handle="0x0001">
+    <Characteristic uuid="2A19" handle="0x0002" properties="0x12">
+      <Value><![CDATA[64]]></Value>
+    </Characteristic>
+  </Service>
+</Database>
+"#;
+
+    let database = AttributeDatabase::from_gatt_xml(xml).unwrap();
+    let attributes = database.get_attributes_in_range(0x0001, 0xFFFF).unwrap();
+    assert_eq!(attributes.len(), 3);
+    assert_eq!(attributes[0].value, vec![0x0F, 0x18]);
+    assert_eq!(attributes[1].value, vec![0x12, 0x03, 0x00, 0x19, 0x2A]);
+    assert_eq!(attributes[2].value, vec![0x64]);
+
+    // An unterminated quote or CDATA section is an error, not a short read
+    assert!(AttributeDatabase::from_gatt_xml("<Database name=\"a>\n</Database>").is_err());
+    assert!(AttributeDatabase::from_gatt_xml("<Database><![CDATA[</Database>").is_err());
+}
+
+#[test]
+fn test_attribute_database_imports_sig_service_definition() {
+    // Trimmed from the Bluetooth SIG Battery Service definition
+    let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<Service xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="http://schemas.bluetooth.org/Documents/service.xsd" type="org.bluetooth.service.battery_service" uuid="180F" name="Battery Service">
+  <InformativeText>
+    <Abstract>The Battery Service exposes the state of a battery within a device.</Abstract>
+  </InformativeText>
+  <Characteristics>
+    <Characteristic type="org.bluetooth.characteristic.battery_level" name="Battery Level">
+      <Requirement>Mandatory</Requirement>
+      <Properties>
+        <Read>Mandatory</Read>
+        <Write>Excluded</Write>
+        <Notify>Optional</Notify>
+      </Properties>
+      <Descriptors>
+        <Descriptor name="Characteristic Presentation Format" type="org.bluetooth.descriptor.gatt.characteristic_presentation_format">
+          <Requirement>Excluded</Requirement>
+        </Descriptor>
+        <Descriptor name="Client Characteristic Configuration" type="org.bluetooth.descriptor.gatt.client_characteristic_configuration">
+          <Requirement>if_notify_or_indicate_supported</Requirement>
+          <Properties>
+            <Read>Mandatory</Read>
+            <Write>Mandatory</Write>
+          </Properties>
+        </Descriptor>
+      </Descriptors>
+    </Characteristic>
+  </Characteristics>
+</Service>
+"#;
+
+    let database = AttributeDatabase::from_gatt_xml(xml).unwrap();
+    let attributes = database.get_attributes_in_range(0x0001, 0xFFFF).unwrap();
+    assert_eq!(attributes.len(), 4);
+    assert_eq!(attributes[0].type_, Uuid::from_u16(PRIMARY_SERVICE_UUID));
+    assert_eq!(attributes[0].value, vec![0x0F, 0x18]);
+    assert_eq!(attributes[1].value, vec![0x12, 0x03, 0x00, 0x19, 0x2A]);
+    assert_eq!(attributes[2].type_, Uuid::from_u16(0x2A19));
+    assert_eq!(attributes[2].permissions, AttPermissions::read_only());
+    assert_eq!(attributes[3].type_, Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID));
+    assert_eq!(attributes[3].value, vec![0x00, 0x00]);
+    assert_eq!(attributes[3].permissions, AttPermissions::read_write());
+
+    // The export lists characteristics and descriptors as SIG definitions do
+    let exported = database.to_gatt_xml();
+    assert!(exported.contains("<Characteristics>"));
+    assert!(exported.contains("<Descriptors>"));
+    assert!(exported.contains("<Notify>Mandatory</Notify>"));
+
+    assert!(matches!(
+        AttributeDatabase::from_gatt_xml("<Characteristic uuid=\"2A19\"/>"),
+        Err(XmlError::UnknownRoot(root)) if root == "Characteristic"
+    ));
+    assert!(matches!(
+        AttributeDatabase::from_gatt_xml(
+            "<Service uuid=\"180F\"><Characteristic type=\"org.bluetooth.characteristic.unknown\"/></Service>"
+        ),
+        Err(XmlError::UnknownType(_))
+    ));
+}
+
+#[test]
+fn test_att_server_execute_write_rejects_gaps() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let cid = manager.get_fixed_channel(L2CAP_ATT_CID, 0x0040).unwrap();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00; 4],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+    let server = AttServer::new(manager, database.clone());
+
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    server.accept_client(addr, 0x0040, cid).unwrap();
+
+    let prepare = |offset: u16, value: &[u8]| {
+        let mut pdu = vec![ATT_PREPARE_WRITE_REQ];
+        pdu.extend_from_slice(&handle.to_le_bytes());
+        pdu.extend_from_slice(&offset.to_le_bytes());
+        pdu.extend_from_slice(value);
+        server.handle_att_pdu(addr, &pdu).unwrap();
+    };
+
+    // Parts that leave a hole are not written
+    prepare(0, &[0x01, 0x02]);
+    prepare(3, &[0x04]);
+    server
+        .handle_att_pdu(addr, &[ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_COMMIT])
+        .unwrap();
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![0x00; 4]);
+
+    // Parts not starting at offset 0 are not written either
+    prepare(1, &[0x02, 0x03]);
+    server
+        .handle_att_pdu(addr, &[ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_COMMIT])
+        .unwrap();
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![0x00; 4]);
+
+    // Contiguous parts are assembled regardless of queue order
+    prepare(2, &[0x03, 0x04]);
+    prepare(0, &[0x01, 0x02]);
+    server
+        .handle_att_pdu(addr, &[ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_COMMIT])
+        .unwrap();
+    assert_eq!(
+        database.get_attribute(handle).unwrap().value,
+        vec![0x01, 0x02, 0x03, 0x04]
+    );
+}
+
+#[test]
+fn test_attribute_database_read_by_type_stops_at_unreadable() {
+    let database = AttributeDatabase::new();
+    let uuid = Uuid::from_u16(0x2A19);
+    let readable = database
+        .add_attribute_with_next_handle(uuid, vec![0x01], AttPermissions::read_only())
+        .unwrap();
+    let restricted = database
+        .add_attribute_with_next_handle(uuid, vec![0x02], AttPermissions::write_only())
+        .unwrap();
+    database
+        .add_attribute_with_next_handle(uuid, vec![0x03], AttPermissions::read_only())
+        .unwrap();
+
+    // Attributes after the first unreadable one are not returned
+    let results = database
+        .read_by_type(readable, 0xFFFF, &uuid, SecurityLevel::None)
+        .unwrap();
+    assert_eq!(results, vec![(readable, vec![0x01])]);
+
+    // An unreadable first attribute is reported with its handle
+    match database.read_by_type(restricted, 0xFFFF, &uuid, SecurityLevel::None) {
+        Err(AttError::Protocol(AttErrorCode::ReadNotPermitted, handle)) => {
+            assert_eq!(handle, restricted)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_att_server_routes_fixed_channel_data() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00; 2],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+    let server = Arc::new(AttServer::new(manager.clone(), database.clone()));
+    server.start().unwrap();
+
+    // Registering the connection accepts the peer as a client
+    mock.connect(0x0040, peer());
+    assert!(server.client_security_level(peer()).is_ok());
+
+    // A Write Request arriving on the ATT channel reaches the server
+    let mut pdu = vec![ATT_WRITE_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(&[0x12, 0x34]);
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+    assert_eq!(
+        database.get_attribute(handle).unwrap().value,
+        vec![0x12, 0x34]
+    );
+
+    // Responses go back on the same connection, in request order
+    let mut pdu = vec![ATT_READ_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+    assert_eq!(
+        mock.sent_pdus(0x0040, L2CAP_ATT_CID),
+        vec![vec![ATT_WRITE_RSP], vec![ATT_READ_RSP, 0x12, 0x34]]
+    );
+
+    // Data from a connection without a known peer is not delivered
+    let pdu = vec![ATT_WRITE_REQ, 0x01, 0x00, 0x00];
+    assert!(mock.inject(0x0041, L2CAP_ATT_CID, &pdu).is_err());
+    assert!(mock.sent_pdus(0x0041, L2CAP_ATT_CID).is_empty());
+}
+
+#[test]
+fn test_att_channel_shared_by_client_and_server() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00; 2],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+    let server = Arc::new(AttServer::new(manager.clone(), database.clone()));
+    server.start().unwrap();
+    mock.connect(0x0040, peer());
+
+    let client = Arc::new(AttClient::new(manager));
+    client.connect(peer(), 0x0040).unwrap();
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let notified_clone = notified.clone();
+    client.set_notification_callback(move |_, handle, value| {
+        notified_clone
+            .lock()
+            .unwrap()
+            .push((handle, value.to_vec()));
+        Ok(())
+    });
+
+    // Requests from the peer still reach the server with a client attached
+    let mut pdu = vec![ATT_WRITE_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(&[0x12, 0x34]);
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+    assert_eq!(
+        database.get_attribute(handle).unwrap().value,
+        vec![0x12, 0x34]
+    );
+    assert_eq!(
+        mock.sent_pdus(0x0040, L2CAP_ATT_CID),
+        vec![vec![ATT_WRITE_RSP]]
+    );
+
+    // Notifications from the peer's server go to the client
+    mock.inject(
+        0x0040,
+        L2CAP_ATT_CID,
+        &[ATT_HANDLE_VALUE_NTF, 0x10, 0x00, 0xAB],
+    )
+    .unwrap();
+    assert_eq!(*notified.lock().unwrap(), vec![(0x0010, vec![0xAB])]);
+
+    // The confirmation of a server indication reaches the server
+    server.send_indication(peer(), handle, &[0x56]).unwrap();
+    assert!(server.has_pending_indication(peer()));
+    mock.inject(0x0040, L2CAP_ATT_CID, &[ATT_HANDLE_VALUE_CONF])
+        .unwrap();
+    assert!(!server.has_pending_indication(peer()));
+}
+
+#[test]
+fn test_att_client_read_over_mock_l2cap() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, peer());
+    let client = Arc::new(AttClient::new(mock.manager()));
+    client.connect(peer(), 0x0040).unwrap();
+
+    let reader = {
+        let client = client.clone();
+        std::thread::spawn(move || client.read(peer(), 0x0003))
+    };
+
+    // Answer the Read Request once it has been sent
+    let start = Instant::now();
+    while mock.sent_pdus(0x0040, L2CAP_ATT_CID).is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(
+        mock.sent_pdus(0x0040, L2CAP_ATT_CID),
+        vec![vec![ATT_READ_REQ, 0x03, 0x00]]
+    );
+    mock.inject(0x0040, L2CAP_ATT_CID, &[ATT_READ_RSP, 0xCA, 0xFE])
+        .unwrap();
+    assert_eq!(reader.join().unwrap().unwrap(), vec![0xCA, 0xFE]);
+}
+
+#[test]
+fn test_att_server_responds_on_the_clients_connection() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x42],
+            AttPermissions::read_only(),
+        )
+        .unwrap();
+    let server = Arc::new(AttServer::new(mock.manager(), database));
+    server.start().unwrap();
+
+    // Both clients use the ATT fixed channel, on their own connections
+    let first = peer();
+    let second = BdAddr::new([0x11, 0x12, 0x13, 0x14, 0x15, 0x16]);
+    mock.connect(0x0040, first);
+    mock.connect(0x0041, second);
+
+    let mut read = vec![ATT_READ_REQ];
+    read.extend_from_slice(&handle.to_le_bytes());
+    server.handle_att_pdu(second, &read).unwrap();
+    assert!(mock.sent_pdus(0x0040, L2CAP_ATT_CID).is_empty());
+    assert_eq!(
+        mock.sent_pdus(0x0041, L2CAP_ATT_CID),
+        vec![vec![ATT_READ_RSP, 0x42]]
+    );
+
+    server.send_notification(first, handle, &[0x43]).unwrap();
+    let mut notification = vec![ATT_HANDLE_VALUE_NTF];
+    notification.extend_from_slice(&handle.to_le_bytes());
+    notification.push(0x43);
+    assert_eq!(mock.sent_pdus(0x0040, L2CAP_ATT_CID), vec![notification]);
+    assert_eq!(mock.sent_pdus(0x0041, L2CAP_ATT_CID).len(), 1);
+}
+
+#[test]
+fn test_att_server_isolates_client_security_levels() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let encrypted_cid = manager.get_fixed_channel(L2CAP_ATT_CID, 0x0040).unwrap();
+    let plain_cid = manager.get_fixed_channel(L2CAP_ATT_CID, 0x0041).unwrap();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00],
+            AttPermissions::new(ATT_PERM_WRITE | ATT_PERM_WRITE_ENCRYPTED),
+        )
+        .unwrap();
+    let server = Arc::new(AttServer::new(manager, database.clone()));
+
+    let encrypted = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let plain = BdAddr::new([0x11, 0x12, 0x13, 0x14, 0x15, 0x16]);
+    server
+        .accept_client(encrypted, 0x0040, encrypted_cid)
+        .unwrap();
+    server.accept_client(plain, 0x0041, plain_cid).unwrap();
+    server
+        .set_client_security_level(encrypted, SecurityLevel::EncryptionOnly)
+        .unwrap();
+
+    let write_command = |value: u8| {
+        let mut pdu = vec![ATT_WRITE_CMD];
+        pdu.extend_from_slice(&handle.to_le_bytes());
+        pdu.push(value);
+        pdu
+    };
+
+    // Interleave write commands from both clients; only the encrypted
+    // client's writes may reach the attribute
+    let threads: Vec<_> = [(encrypted, 0xAA), (plain, 0xBB)]
+        .into_iter()
+        .map(|(addr, value)| {
+            let server = server.clone();
+            let database = database.clone();
+            let pdu = write_command(value);
+            std::thread::spawn(move || {
+                for _ in 0..500 {
+                    server.handle_att_pdu(addr, &pdu).unwrap();
+                    assert_ne!(database.get_attribute(handle).unwrap().value, vec![0xBB]);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![0xAA]);
+    assert_eq!(
+        server.client_security_level(encrypted).unwrap(),
+        SecurityLevel::EncryptionOnly
+    );
+    assert_eq!(
+        server.client_security_level(plain).unwrap(),
+        SecurityLevel::None
+    );
+}
+
+#[test]
+fn test_att_client_request_timeout() {
+    let (_mock, client) = connected_client(0x0040);
+    assert_eq!(client.timeout(), Duration::from_secs(30));
+
+    // Nobody answers, so the request gives up after the configured time
+    client.set_timeout(Duration::from_millis(20));
+    let start = Instant::now();
+    assert!(matches!(
+        client.read(peer(), 0x0003),
+        Err(AttError::Timeout)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_att_client_confirms_indications() {
+    let (_mock, client) = connected_client(0x0040);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    client.set_indication_callback(move |_, handle, value| {
+        received_clone
+            .lock()
+            .unwrap()
+            .push((handle, value.to_vec()));
+        Ok(())
+    });
+
+    // The confirmation goes out on the ATT channel after the callback ran
+    let pdu = vec![ATT_HANDLE_VALUE_IND, 0x10, 0x00, 0x01];
+    client.handle_att_pdu(peer(), &pdu).unwrap();
+    assert_eq!(*received.lock().unwrap(), vec![(0x0010, vec![0x01])]);
+
+    // Without a connection there is nowhere to send the confirmation
+    let stranger = BdAddr::new([0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F]);
+    assert!(matches!(
+        client.send_confirmation(stranger),
+        Err(AttError::InvalidState)
+    ));
+}
+
+#[test]
+fn test_att_client_sends_indications_as_server() {
+    let (mock, client) = connected_client(0x0040);
+
+    // Values must fit into the default MTU
+    assert!(client.send_notification(peer(), 0x0010, &[0x01]).is_ok());
+    assert!(matches!(
+        client.send_notification(peer(), 0x0010, &[0; 21]),
+        Err(AttError::InvalidAttributeValueLength)
+    ));
+
+    // The indication completes once the peer confirms it; until then a
+    // second one is refused
+    let sender = {
+        let client = client.clone();
+        std::thread::spawn(move || client.send_indication(peer(), 0x0010, &[0x02]))
+    };
+    assert_eq!(
+        wait_for_pdu(&mock, 2),
+        vec![ATT_HANDLE_VALUE_IND, 0x10, 0x00, 0x02]
+    );
+    assert!(matches!(
+        client.send_indication(peer(), 0x0010, &[0x03]),
+        Err(AttError::Busy)
+    ));
+    client
+        .handle_att_pdu(peer(), &[ATT_HANDLE_VALUE_CONF])
+        .unwrap();
+    assert!(sender.join().unwrap().is_ok());
+
+    // A confirmation without an indication is unexpected
+    assert!(matches!(
+        client.handle_att_pdu(peer(), &[ATT_HANDLE_VALUE_CONF]),
+        Err(AttError::UnexpectedResponse)
+    ));
+
+    // Without a confirmation the indication times out
+    client.set_timeout(Duration::from_millis(20));
+    assert!(matches!(
+        client.send_indication(peer(), 0x0010, &[0x04]),
+        Err(AttError::Timeout)
+    ));
+}
+
+#[test]
+fn test_att_server_write_validator() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let cid = manager.get_fixed_channel(L2CAP_ATT_CID, 0x0040).unwrap();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+    let server = AttServer::new(manager, database.clone());
+    server.accept_client(peer(), 0x0040, cid).unwrap();
+
+    // Speeds above 100 are rejected with an application error
+    server.set_write_validator(
+        handle,
+        Arc::new(|value: &[u8]| match value {
+            [speed] if *speed <= 100 => Ok(()),
+            _ => Err(AttErrorCode::ApplicationError(0x80)),
+        }),
+    );
+
+    let write = |value: u8| {
+        let mut pdu = vec![ATT_WRITE_REQ];
+        pdu.extend_from_slice(&handle.to_le_bytes());
+        pdu.push(value);
+        server.handle_att_pdu(peer(), &pdu).unwrap();
+    };
+
+    write(150);
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![0x00]);
+    write(42);
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![42]);
+
+    // Prepared writes are validated before any of them is executed
+    let mut pdu = vec![ATT_PREPARE_WRITE_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(&0u16.to_le_bytes());
+    pdu.push(200);
+    server.handle_att_pdu(peer(), &pdu).unwrap();
+    server
+        .handle_att_pdu(peer(), &[ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_COMMIT])
+        .unwrap();
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![42]);
+
+    server.clear_write_validator(handle);
+    write(150);
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![150]);
+}
+
+#[test]
+fn test_eatt_client_spreads_requests_over_bearers() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    manager.register_connection(0x0040, peer());
+    let client = EattClient::connect(manager.clone(), 0x0040, 2).unwrap();
+    let cids = client.channel_ids();
+    assert_eq!(cids.len(), 2);
+
+    // The peer accepts both channels of the first signaling request
+    let response = SignalingMessage::EnhancedCreditBasedConnectionResponse {
+        identifier: 1,
+        mtu: 64,
+        mps: 64,
+        initial_credits: 5,
+        result: 0,
+        destination_cids: vec![0x0080, 0x0081],
+    };
+    manager
+        .handle_packet(response.to_packet(true), 0x0040)
+        .unwrap();
+
+    // Two parallel reads go out on different bearers
+    let readers: Vec<_> = [0x0003, 0x0005]
+        .into_iter()
+        .map(|handle| {
+            let client = client.clone();
+            std::thread::spawn(move || client.read(handle))
+        })
+        .collect();
+    let start = Instant::now();
+    while cids.iter().any(|&cid| client.pending_requests(cid) != 1) {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    client
+        .handle_att_pdu(cids[0], &[ATT_READ_RSP, 0x01])
+        .unwrap();
+    client
+        .handle_att_pdu(cids[1], &[ATT_READ_RSP, 0x02])
+        .unwrap();
+    let mut values: Vec<_> = readers
+        .into_iter()
+        .map(|reader| reader.join().unwrap().unwrap())
+        .collect();
+    values.sort();
+    assert_eq!(values, vec![vec![0x01], vec![0x02]]);
+
+    // Responses without a request on that bearer are unexpected
+    assert!(matches!(
+        client.handle_att_pdu(cids[0], &[ATT_WRITE_RSP]),
+        Err(AttError::UnexpectedResponse)
+    ));
+
+    // Notifications are passed on with the peer address
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    client.set_notification_callback(move |addr, handle, value| {
+        received_clone
+            .lock()
+            .unwrap()
+            .push((addr, handle, value.to_vec()));
+        Ok(())
+    });
+    client
+        .handle_att_pdu(cids[1], &[ATT_HANDLE_VALUE_NTF, 0x10, 0x00, 0xAA])
+        .unwrap();
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![(peer(), 0x0010, vec![0xAA])]
+    );
+}
+
+#[test]
+fn test_att_server_accepts_eatt_bearers() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            vec![0x00; 2],
+            AttPermissions::read_write(),
+        )
+        .unwrap();
+    let server = Arc::new(AttServer::new(manager.clone(), database.clone()));
+    server.start().unwrap();
+    server.start_eatt().unwrap();
+    mock.connect(0x0040, peer());
+
+    // The client opens two EATT bearers in one request
+    let request = SignalingMessage::EnhancedCreditBasedConnectionRequest {
+        identifier: 7,
+        spsm: 0x0027,
+        mtu: 64,
+        mps: 64,
+        initial_credits: 5,
+        source_cids: vec![0x0050, 0x0051],
+    };
+    manager
+        .handle_packet(request.to_packet(true), 0x0040)
+        .unwrap();
+    let cids = server.eatt_channels(peer());
+    assert_eq!(cids.len(), 2);
+    let [handle_lo, handle_hi] = handle.to_le_bytes();
+
+    // Nothing is done on a bearer of an unencrypted link
+    let mut pdu = vec![ATT_WRITE_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(&[0x12, 0x34]);
+    server.handle_eatt_pdu(peer(), cids[1], &pdu).unwrap();
+    let mut command = pdu.clone();
+    command[0] = ATT_WRITE_CMD;
+    assert!(matches!(
+        server.handle_eatt_pdu(peer(), cids[1], &command),
+        Err(AttError::InsufficientEncryption)
+    ));
+    assert_eq!(database.get_attribute(handle).unwrap().value, vec![0x00; 2]);
+    assert_eq!(
+        mock.sent_pdus(0x0040, 0x0051),
+        vec![vec![
+            0x05,
+            0x00, // SDU length
+            ATT_ERROR_RSP,
+            ATT_WRITE_REQ,
+            0x00,
+            0x00,
+            ATT_ERROR_INSUFFICIENT_ENCRYPTION,
+        ]]
+    );
+
+    // Once encrypted, a Write Request on a bearer reaches the database and
+    // is answered on that bearer
+    server
+        .set_client_security_level(peer(), SecurityLevel::EncryptionOnly)
+        .unwrap();
+    server.handle_eatt_pdu(peer(), cids[1], &pdu).unwrap();
+    assert_eq!(
+        database.get_attribute(handle).unwrap().value,
+        vec![0x12, 0x34]
+    );
+    assert_eq!(
+        mock.sent_pdus(0x0040, 0x0051)[1],
+        vec![0x01, 0x00, ATT_WRITE_RSP]
+    );
+    assert!(mock.sent_pdus(0x0040, 0x0050).is_empty());
+
+    // Reads on a bearer are limited by its MTU, not the fixed channel's
+    let value: Vec<u8> = (0..40).collect();
+    database.set_value(handle, &value).unwrap();
+    server
+        .handle_eatt_pdu(peer(), cids[0], &[ATT_READ_REQ, handle_lo, handle_hi])
+        .unwrap();
+    let mut expected = vec![41, 0x00, ATT_READ_RSP];
+    expected.extend_from_slice(&value);
+    assert_eq!(mock.sent_pdus(0x0040, 0x0050), vec![expected]);
+
+    server
+        .handle_att_pdu(peer(), &[ATT_READ_REQ, handle_lo, handle_hi])
+        .unwrap();
+    let mut expected = vec![ATT_READ_RSP];
+    expected.extend_from_slice(&value[..ATT_DEFAULT_MTU as usize - 1]);
+    assert_eq!(mock.sent_pdus(0x0040, L2CAP_ATT_CID), vec![expected]);
+
+    // Only the client's bearers are accepted
+    assert!(matches!(
+        server.handle_eatt_pdu(peer(), 0x0099, &pdu),
+        Err(AttError::ChannelNotFound)
+    ));
+}
+
+#[test]
+fn test_eatt_client_waits_for_free_bearer() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let manager = mock.manager();
+    manager.register_connection(0x0040, peer());
+    let client = EattClient::connect(manager.clone(), 0x0040, 1).unwrap();
+    let cid = client.channel_ids()[0];
+
+    let response = SignalingMessage::EnhancedCreditBasedConnectionResponse {
+        identifier: 1,
+        mtu: 64,
+        mps: 64,
+        initial_credits: 5,
+        result: 0,
+        destination_cids: vec![0x0080],
+    };
+    manager
+        .handle_packet(response.to_packet(true), 0x0040)
+        .unwrap();
+
+    let wait_for_request = || {
+        let start = Instant::now();
+        while client.pending_requests(cid) != 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    // The first read occupies the only bearer
+    let first = {
+        let client = client.clone();
+        std::thread::spawn(move || client.read(0x0003))
+    };
+    wait_for_request();
+
+    // The second waits instead of queueing behind it on the same bearer
+    let second = {
+        let client = client.clone();
+        std::thread::spawn(move || client.read(0x0005))
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(client.pending_requests(cid), 1);
+
+    client.handle_att_pdu(cid, &[ATT_READ_RSP, 0x01]).unwrap();
+    assert_eq!(first.join().unwrap().unwrap(), vec![0x01]);
+
+    wait_for_request();
+    client.handle_att_pdu(cid, &[ATT_READ_RSP, 0x02]).unwrap();
+    assert_eq!(second.join().unwrap().unwrap(), vec![0x02]);
+
+    // With the bearer busy for longer than the timeout, a request gives up
+    client.set_timeout(Duration::from_millis(20));
+    let blocked = {
+        let client = client.clone();
+        std::thread::spawn(move || client.read(0x0007))
+    };
+    wait_for_request();
+    assert!(matches!(client.read(0x0009), Err(AttError::Timeout)));
+    assert!(matches!(blocked.join().unwrap(), Err(AttError::Timeout)));
+}
+
+#[test]
+fn test_att_server_read_handler_follows_mtu() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let database = Arc::new(AttributeDatabase::new());
+    let handle = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A00),
+            Vec::new(),
+            AttPermissions::read_only(),
+        )
+        .unwrap();
+
+    // A 30 byte value produced on demand
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let calls_clone = calls.clone();
+    database
+        .register_read_handler(
+            handle,
+            Arc::new(move |offset, max_len| {
+                calls_clone.lock().unwrap().push((offset, max_len));
+                let value: Vec<u8> = (0..30).collect();
+                let start = (offset as usize).min(value.len());
+                let end = (start + max_len).min(value.len());
+                Ok(value[start..end].to_vec())
+            }),
+        )
+        .unwrap();
+    assert!(database.has_read_callback(handle));
+
+    let server = Arc::new(AttServer::new(mock.manager(), database.clone()));
+    server.start().unwrap();
+    mock.connect(0x0040, peer());
+
+    // With the default MTU of 23, each response holds 22 bytes of the value
+    let mut pdu = vec![ATT_READ_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+
+    let mut pdu = vec![ATT_READ_BLOB_REQ];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.extend_from_slice(&22u16.to_le_bytes());
+    mock.inject(0x0040, L2CAP_ATT_CID, &pdu).unwrap();
+
+    let sent = mock.sent_pdus(0x0040, L2CAP_ATT_CID);
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0][0], ATT_READ_RSP);
+    assert_eq!(sent[0][1..], (0..22).collect::<Vec<u8>>()[..]);
+    assert_eq!(sent[1][0], ATT_READ_BLOB_RSP);
+    assert_eq!(sent[1][1..], (22..30).collect::<Vec<u8>>()[..]);
+    assert_eq!(*calls.lock().unwrap(), vec![(0, 22), (22, 22)]);
+
+    // Permissions are checked before the handler runs
+    let secret = database
+        .add_attribute_with_next_handle(
+            Uuid::from_u16(0x2A01),
+            Vec::new(),
+            AttPermissions::write_only(),
+        )
+        .unwrap();
+    database
+        .register_read_handler(secret, Arc::new(|_, _| Ok(vec![0xFF])))
+        .unwrap();
+    assert!(matches!(
+        database.read_part(secret, 0, 22, SecurityLevel::None),
+        Err(AttError::ReadNotPermitted)
+    ));
+}
+
+#[test]
+fn test_attribute_builder() {
+    // An attribute needs something to read
+    assert!(AttributeBuilder::new(0x0001, Uuid::from_u16(0x2A00))
+        .build()
+        .is_err());
+
+    // Writable attributes need somewhere for written values to go
+    assert!(AttributeBuilder::new(0x0001, Uuid::from_u16(0x2A00))
+        .with_permissions(AttPermissions::read_write())
+        .with_read_handler(|_, _| Ok(vec![0x01]))
+        .build()
+        .is_err());
+
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let written_clone = written.clone();
+    let attribute = AttributeBuilder::new(0x0001, Uuid::from_u16(0x2A00))
+        .with_permissions(AttPermissions::read_write())
+        .with_read_handler(|offset, _| Ok(vec![offset as u8]))
+        .with_write_handler(move |_, value| {
+            written_clone.lock().unwrap().extend_from_slice(value);
+            Ok(())
+        })
+        .build()
+        .unwrap();
+    assert!(attribute.value.is_empty());
+
+    // Adding the attribute registers its handlers
+    let database = AttributeDatabase::new();
+    database.add_attribute(attribute).unwrap();
+    assert!(database.has_read_callback(0x0001));
+    assert_eq!(
+        database
+            .read_part(0x0001, 3, 22, SecurityLevel::None)
+            .unwrap(),
+        vec![0x03]
+    );
+    database
+        .write_by_handle(0x0001, &[0xAB], SecurityLevel::None)
+        .unwrap();
+    assert_eq!(*written.lock().unwrap(), vec![0xAB]);
+
+    // A plain value works like Attribute::new
+    let attribute = AttributeBuilder::new(0x0002, Uuid::from_u16(0x2A01))
+        .with_value(vec![0x10, 0x20])
+        .build()
+        .unwrap();
+    database.add_attribute(attribute).unwrap();
+    assert_eq!(
+        database
+            .read_by_handle(0x0002, SecurityLevel::None)
+            .unwrap(),
+        vec![0x10, 0x20]
+    );
+}
+
+/// Wait until the client has sent `count` PDUs and return the last one
+fn wait_for_pdu(mock: &MockL2capManager, count: usize) -> Vec<u8> {
+    let start = Instant::now();
+    loop {
+        let pdus = mock.sent_pdus(0x0040, L2CAP_ATT_CID);
+        if pdus.len() >= count {
+            return pdus[count - 1].clone();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_att_client_write_long_prepares_and_executes() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, peer());
+    let client = Arc::new(AttClient::new(mock.manager()));
+    client.connect(peer(), 0x0040).unwrap();
+
+    // 40 bytes at the default MTU of 23 take three prepare writes
+    let value: Vec<u8> = (0..40).collect();
+    let writer = {
+        let client = client.clone();
+        let value = value.clone();
+        std::thread::spawn(move || client.write_long(peer(), 0x0003, &value))
+    };
+
+    // Act as the server: echo each prepared chunk and assemble the value
+    let mut assembled = Vec::new();
+    for (count, offset) in [(1, 0u16), (2, 18), (3, 36)] {
+        let pdu = wait_for_pdu(&mock, count);
+        assert_eq!(pdu[0], ATT_PREPARE_WRITE_REQ);
+        assert_eq!(&pdu[1..3], &0x0003u16.to_le_bytes());
+        assert_eq!(&pdu[3..5], &offset.to_le_bytes());
+        assert!(pdu.len() - 5 <= 18);
+        assembled.extend_from_slice(&pdu[5..]);
+
+        let mut response = pdu.clone();
+        response[0] = ATT_PREPARE_WRITE_RSP;
+        mock.inject(0x0040, L2CAP_ATT_CID, &response).unwrap();
+    }
+
+    assert_eq!(
+        wait_for_pdu(&mock, 4),
+        vec![ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_COMMIT]
+    );
+    mock.inject(0x0040, L2CAP_ATT_CID, &[ATT_EXECUTE_WRITE_RSP])
+        .unwrap();
+
+    writer.join().unwrap().unwrap();
+    assert_eq!(assembled, value);
+    assert_eq!(mock.sent_pdus(0x0040, L2CAP_ATT_CID).len(), 4);
+}
+
+#[test]
+fn test_att_client_write_long_cancels_on_error() {
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, peer());
+    let client = Arc::new(AttClient::new(mock.manager()));
+    client.connect(peer(), 0x0040).unwrap();
+
+    let writer = {
+        let client = client.clone();
+        std::thread::spawn(move || client.write_long(peer(), 0x0003, &[0x55; 30]))
+    };
+
+    // Accept the first chunk and refuse the second
+    let mut response = wait_for_pdu(&mock, 1);
+    response[0] = ATT_PREPARE_WRITE_RSP;
+    mock.inject(0x0040, L2CAP_ATT_CID, &response).unwrap();
+
+    wait_for_pdu(&mock, 2);
+    mock.inject(
+        0x0040,
+        L2CAP_ATT_CID,
+        &[
+            ATT_ERROR_RSP,
+            ATT_PREPARE_WRITE_REQ,
+            0x03,
+            0x00,
+            ATT_ERROR_PREPARE_QUEUE_FULL,
+        ],
+    )
+    .unwrap();
+
+    // The queued chunk is discarded before the error is returned
+    assert_eq!(
+        wait_for_pdu(&mock, 3),
+        vec![ATT_EXECUTE_WRITE_REQ, ATT_EXEC_WRITE_CANCEL]
+    );
+    mock.inject(0x0040, L2CAP_ATT_CID, &[ATT_EXECUTE_WRITE_RSP])
+        .unwrap();
+
+    assert!(writer.join().unwrap().is_err());
+}
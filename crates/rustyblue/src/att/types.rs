@@ -1,11 +1,10 @@
 //! Type definitions for the ATT protocol
 use super::constants::*;
 use super::error::{AttError, AttErrorCode, AttResult};
-use crate::gap::BdAddr;
 use crate::uuid::Uuid;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 use std::convert::TryFrom;
-use std::io::{Cursor, Read, Write};
+use std::io::Cursor;
 
 /// ATT Permission flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -199,6 +198,82 @@ pub enum SecurityLevel {
     SecureConnections,
 }
 
+/// ATT opcodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AttOpcode {
+    ErrorResponse = ATT_ERROR_RSP,
+    ExchangeMtuRequest = ATT_EXCHANGE_MTU_REQ,
+    ExchangeMtuResponse = ATT_EXCHANGE_MTU_RSP,
+    FindInformationRequest = ATT_FIND_INFO_REQ,
+    FindInformationResponse = ATT_FIND_INFO_RSP,
+    FindByTypeValueRequest = ATT_FIND_BY_TYPE_VALUE_REQ,
+    FindByTypeValueResponse = ATT_FIND_BY_TYPE_VALUE_RSP,
+    ReadByTypeRequest = ATT_READ_BY_TYPE_REQ,
+    ReadByTypeResponse = ATT_READ_BY_TYPE_RSP,
+    ReadRequest = ATT_READ_REQ,
+    ReadResponse = ATT_READ_RSP,
+    ReadBlobRequest = ATT_READ_BLOB_REQ,
+    ReadBlobResponse = ATT_READ_BLOB_RSP,
+    ReadMultipleRequest = ATT_READ_MULTIPLE_REQ,
+    ReadMultipleResponse = ATT_READ_MULTIPLE_RSP,
+    ReadByGroupTypeRequest = ATT_READ_BY_GROUP_TYPE_REQ,
+    ReadByGroupTypeResponse = ATT_READ_BY_GROUP_TYPE_RSP,
+    WriteRequest = ATT_WRITE_REQ,
+    WriteResponse = ATT_WRITE_RSP,
+    WriteCommand = ATT_WRITE_CMD,
+    SignedWriteCommand = ATT_SIGNED_WRITE_CMD,
+    PrepareWriteRequest = ATT_PREPARE_WRITE_REQ,
+    PrepareWriteResponse = ATT_PREPARE_WRITE_RSP,
+    ExecuteWriteRequest = ATT_EXECUTE_WRITE_REQ,
+    ExecuteWriteResponse = ATT_EXECUTE_WRITE_RSP,
+    HandleValueNotification = ATT_HANDLE_VALUE_NTF,
+    HandleValueIndication = ATT_HANDLE_VALUE_IND,
+    HandleValueConfirmation = ATT_HANDLE_VALUE_CONF,
+    MultipleHandleValueNotification = ATT_MULTIPLE_HANDLE_VALUE_NTF,
+}
+
+impl TryFrom<u8> for AttOpcode {
+    type Error = AttError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let opcode = match value {
+            ATT_ERROR_RSP => Self::ErrorResponse,
+            ATT_EXCHANGE_MTU_REQ => Self::ExchangeMtuRequest,
+            ATT_EXCHANGE_MTU_RSP => Self::ExchangeMtuResponse,
+            ATT_FIND_INFO_REQ => Self::FindInformationRequest,
+            ATT_FIND_INFO_RSP => Self::FindInformationResponse,
+            ATT_FIND_BY_TYPE_VALUE_REQ => Self::FindByTypeValueRequest,
+            ATT_FIND_BY_TYPE_VALUE_RSP => Self::FindByTypeValueResponse,
+            ATT_READ_BY_TYPE_REQ => Self::ReadByTypeRequest,
+            ATT_READ_BY_TYPE_RSP => Self::ReadByTypeResponse,
+            ATT_READ_REQ => Self::ReadRequest,
+            ATT_READ_RSP => Self::ReadResponse,
+            ATT_READ_BLOB_REQ => Self::ReadBlobRequest,
+            ATT_READ_BLOB_RSP => Self::ReadBlobResponse,
+            ATT_READ_MULTIPLE_REQ => Self::ReadMultipleRequest,
+            ATT_READ_MULTIPLE_RSP => Self::ReadMultipleResponse,
+            ATT_READ_BY_GROUP_TYPE_REQ => Self::ReadByGroupTypeRequest,
+            ATT_READ_BY_GROUP_TYPE_RSP => Self::ReadByGroupTypeResponse,
+            ATT_WRITE_REQ => Self::WriteRequest,
+            ATT_WRITE_RSP => Self::WriteResponse,
+            ATT_WRITE_CMD => Self::WriteCommand,
+            ATT_SIGNED_WRITE_CMD => Self::SignedWriteCommand,
+            ATT_PREPARE_WRITE_REQ => Self::PrepareWriteRequest,
+            ATT_PREPARE_WRITE_RSP => Self::PrepareWriteResponse,
+            ATT_EXECUTE_WRITE_REQ => Self::ExecuteWriteRequest,
+            ATT_EXECUTE_WRITE_RSP => Self::ExecuteWriteResponse,
+            ATT_HANDLE_VALUE_NTF => Self::HandleValueNotification,
+            ATT_HANDLE_VALUE_IND => Self::HandleValueIndication,
+            ATT_HANDLE_VALUE_CONF => Self::HandleValueConfirmation,
+            ATT_MULTIPLE_HANDLE_VALUE_NTF => Self::MultipleHandleValueNotification,
+            _ => return Err(AttError::InvalidPdu),
+        };
+
+        Ok(opcode)
+    }
+}
+
 /// ATT packet formats
 pub trait AttPacket: Sized {
     /// Opcode for this packet
@@ -589,7 +664,7 @@ impl AttPacket for FindByTypeValueResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -807,7 +882,7 @@ impl AttPacket for ReadResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -881,7 +956,7 @@ impl AttPacket for ReadBlobResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -913,7 +988,7 @@ impl AttPacket for ReadMultipleRequest {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 3 || data[0] != Self::opcode() || (data.len() - 1) % 2 != 0 {
+        if data.len() < 3 || data[0] != Self::opcode() || !(data.len() - 1).is_multiple_of(2) {
             return Err(AttError::InvalidPdu);
         }
 
@@ -959,7 +1034,7 @@ impl AttPacket for ReadMultipleResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -1167,7 +1242,7 @@ impl AttPacket for WriteResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -1344,12 +1419,7 @@ impl AttPacket for ExecuteWriteRequest {
     }
 
     fn serialize(&self) -> Vec<u8> {
-        let mut packet = Vec::with_capacity(2);
-
-        packet.push(Self::opcode());
-        packet.push(self.flags);
-
-        packet
+        vec![Self::opcode(), self.flags]
     }
 }
 
@@ -1363,7 +1433,7 @@ impl AttPacket for ExecuteWriteResponse {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -1465,7 +1535,7 @@ impl AttPacket for HandleValueConfirmation {
     }
 
     fn parse(data: &[u8]) -> AttResult<Self> {
-        if data.len() < 1 || data[0] != Self::opcode() {
+        if data.is_empty() || data[0] != Self::opcode() {
             return Err(AttError::InvalidPdu);
         }
 
@@ -0,0 +1,779 @@
+//! GATT XML import and export for the attribute database
+//!
+//! The database is written as `<Service>` elements holding a
+//! `<Characteristics>` list, each `<Characteristic>` holding its
+//! `<Properties>` and a `<Descriptors>` list, as in the Bluetooth SIG
+//! service definitions. Every element also carries its UUID, handle and
+//! permissions, characteristics carry their properties as a number, and
+//! static values are stored hex-encoded in a `<Value>` child element:
+//!
+//! ```xml
+//! <Database>
+//!   <Service uuid="180F" type="primary" handle="0x0001">
+//!     <Characteristics>
+//!       <Characteristic uuid="2A19" handle="0x0002" value_handle="0x0003" properties="0x12" permissions="0x0001">
+//!         <Properties>
+//!           <Read>Mandatory</Read>
+//!           <Notify>Mandatory</Notify>
+//!         </Properties>
+//!         <Value>64</Value>
+//!         <Descriptors>
+//!           <Descriptor uuid="2902" handle="0x0004" permissions="0x0003">
+//!             <Value>0000</Value>
+//!           </Descriptor>
+//!         </Descriptors>
+//!       </Characteristic>
+//!     </Characteristics>
+//!   </Service>
+//! </Database>
+//! ```
+//!
+//! A SIG service definition, with a `<Service>` root and
+//! `type="org.bluetooth.…"` names instead of UUIDs, imports as a database
+//! holding that service. Characteristics and descriptors whose
+//! `<Requirement>` is `Excluded` are left out.
+
+use super::constants::*;
+use super::database::{Attribute, AttributeDatabase};
+use super::error::AttError;
+use super::types::AttPermissions;
+use crate::gatt::CharacteristicProperty;
+use crate::uuid::Uuid;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fmt::Write;
+use thiserror::Error;
+
+/// Errors produced while importing a GATT XML definition
+#[derive(Debug, Error)]
+pub enum XmlError {
+    #[error("Malformed XML: {0}")]
+    Syntax(String),
+
+    #[error("Unrecognized root element <{0}>")]
+    UnknownRoot(String),
+
+    #[error("Missing attribute `{attribute}` on <{element}>")]
+    MissingAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+
+    #[error("Invalid value `{value}` for `{attribute}`")]
+    InvalidValue { attribute: String, value: String },
+
+    #[error("Unknown GATT type `{0}`")]
+    UnknownType(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] AttError),
+}
+
+/// Characteristic properties as named in SIG service definitions
+const SIG_PROPERTIES: &[(&str, CharacteristicProperty)] = &[
+    ("Broadcast", CharacteristicProperty::BROADCAST),
+    ("Read", CharacteristicProperty::READ),
+    (
+        "WriteWithoutResponse",
+        CharacteristicProperty::WRITE_WITHOUT_RESPONSE,
+    ),
+    ("Write", CharacteristicProperty::WRITE),
+    ("Notify", CharacteristicProperty::NOTIFY),
+    ("Indicate", CharacteristicProperty::INDICATE),
+    (
+        "SignedWrite",
+        CharacteristicProperty::AUTHENTICATED_SIGNED_WRITES,
+    ),
+    ("ReliableWrite", CharacteristicProperty::EXTENDED_PROPERTIES),
+    (
+        "WritableAuxiliaries",
+        CharacteristicProperty::EXTENDED_PROPERTIES,
+    ),
+];
+
+/// UUIDs of the SIG types that service definitions refer to by name,
+/// without the `org.bluetooth.` prefix
+const SIG_TYPES: &[(&str, u16)] = &[
+    ("service.generic_access", 0x1800),
+    ("service.generic_attribute", 0x1801),
+    ("service.immediate_alert", 0x1802),
+    ("service.link_loss", 0x1803),
+    ("service.tx_power", 0x1804),
+    ("service.current_time", 0x1805),
+    ("service.health_thermometer", 0x1809),
+    ("service.device_information", 0x180A),
+    ("service.heart_rate", 0x180D),
+    ("service.battery_service", 0x180F),
+    ("service.blood_pressure", 0x1810),
+    ("service.human_interface_device", 0x1812),
+    ("service.running_speed_and_cadence", 0x1814),
+    ("service.cycling_speed_and_cadence", 0x1816),
+    ("service.environmental_sensing", 0x181A),
+    ("characteristic.gap.device_name", 0x2A00),
+    ("characteristic.gap.appearance", 0x2A01),
+    ("characteristic.gap.peripheral_privacy_flag", 0x2A02),
+    ("characteristic.gap.reconnection_address", 0x2A03),
+    (
+        "characteristic.gap.peripheral_preferred_connection_parameters",
+        0x2A04,
+    ),
+    ("characteristic.gatt.service_changed", 0x2A05),
+    ("characteristic.alert_level", 0x2A06),
+    ("characteristic.tx_power_level", 0x2A07),
+    ("characteristic.date_time", 0x2A08),
+    ("characteristic.battery_level", 0x2A19),
+    ("characteristic.temperature_measurement", 0x2A1C),
+    ("characteristic.temperature_type", 0x2A1D),
+    ("characteristic.intermediate_temperature", 0x2A1E),
+    ("characteristic.measurement_interval", 0x2A21),
+    ("characteristic.boot_keyboard_input_report", 0x2A22),
+    ("characteristic.system_id", 0x2A23),
+    ("characteristic.model_number_string", 0x2A24),
+    ("characteristic.serial_number_string", 0x2A25),
+    ("characteristic.firmware_revision_string", 0x2A26),
+    ("characteristic.hardware_revision_string", 0x2A27),
+    ("characteristic.software_revision_string", 0x2A28),
+    ("characteristic.manufacturer_name_string", 0x2A29),
+    (
+        "characteristic.ieee_11073-20601_regulatory_certification_data_list",
+        0x2A2A,
+    ),
+    ("characteristic.current_time", 0x2A2B),
+    ("characteristic.boot_keyboard_output_report", 0x2A32),
+    ("characteristic.boot_mouse_input_report", 0x2A33),
+    ("characteristic.blood_pressure_measurement", 0x2A35),
+    ("characteristic.intermediate_cuff_pressure", 0x2A36),
+    ("characteristic.heart_rate_measurement", 0x2A37),
+    ("characteristic.body_sensor_location", 0x2A38),
+    ("characteristic.heart_rate_control_point", 0x2A39),
+    ("characteristic.blood_pressure_feature", 0x2A49),
+    ("characteristic.hid_information", 0x2A4A),
+    ("characteristic.report_map", 0x2A4B),
+    ("characteristic.hid_control_point", 0x2A4C),
+    ("characteristic.report", 0x2A4D),
+    ("characteristic.protocol_mode", 0x2A4E),
+    ("characteristic.pnp_id", 0x2A50),
+    ("characteristic.rsc_measurement", 0x2A53),
+    ("characteristic.rsc_feature", 0x2A54),
+    ("characteristic.sc_control_point", 0x2A55),
+    ("characteristic.csc_measurement", 0x2A5B),
+    ("characteristic.csc_feature", 0x2A5C),
+    ("characteristic.sensor_location", 0x2A5D),
+    ("characteristic.pressure", 0x2A6D),
+    ("characteristic.temperature", 0x2A6E),
+    ("characteristic.humidity", 0x2A6F),
+    (
+        "descriptor.gatt.characteristic_extended_properties",
+        CHAR_EXTENDED_PROPS_UUID,
+    ),
+    (
+        "descriptor.gatt.characteristic_user_description",
+        CHAR_USER_DESC_UUID,
+    ),
+    (
+        "descriptor.gatt.client_characteristic_configuration",
+        CLIENT_CHAR_CONFIG_UUID,
+    ),
+    (
+        "descriptor.gatt.server_characteristic_configuration",
+        SERVER_CHAR_CONFIG_UUID,
+    ),
+    (
+        "descriptor.gatt.characteristic_presentation_format",
+        CHAR_FORMAT_UUID,
+    ),
+    (
+        "descriptor.gatt.characteristic_aggregate_format",
+        CHAR_AGGREGATE_FORMAT_UUID,
+    ),
+    ("descriptor.valid_range", 0x2906),
+    ("descriptor.external_report_reference", 0x2907),
+    ("descriptor.report_reference", 0x2908),
+];
+
+impl AttributeDatabase {
+    /// Export the database as a GATT XML definition
+    ///
+    /// Attributes backed by a read callback are exported without a value.
+    /// Attributes that are not part of a service are skipped.
+    pub fn to_gatt_xml(&self) -> String {
+        let attributes = self
+            .get_attributes_in_range(ATT_HANDLE_MIN, ATT_HANDLE_MAX)
+            .unwrap_or_default();
+
+        let mut writer = XmlWriter::default();
+        writer.start("Database", String::new());
+        let mut value_handle = None;
+
+        for attr in &attributes {
+            if Some(attr.handle) == value_handle {
+                continue;
+            }
+
+            if attr.type_ == PRIMARY_SERVICE_UUID || attr.type_ == SECONDARY_SERVICE_UUID {
+                let service_type = if attr.type_ == PRIMARY_SERVICE_UUID {
+                    "primary"
+                } else {
+                    "secondary"
+                };
+                let uuid = Uuid::try_from_slice_le(&attr.value)
+                    .map(|uuid| format_uuid(&uuid))
+                    .unwrap_or_default();
+
+                writer.close_to(1);
+                writer.start(
+                    "Service",
+                    format!(
+                        " uuid=\"{}\" type=\"{}\" handle=\"0x{:04X}\"",
+                        uuid, service_type, attr.handle
+                    ),
+                );
+            } else if !writer.is_open(1, "Service") {
+                continue;
+            } else if attr.type_ == INCLUDE_UUID {
+                writer.close_to(2);
+                writer.empty(
+                    "Include",
+                    format!(
+                        " handle=\"0x{:04X}\" value=\"{}\"",
+                        attr.handle,
+                        hex::encode_upper(&attr.value)
+                    ),
+                );
+            } else if attr.type_ == CHARACTERISTIC_UUID && attr.value.len() >= 5 {
+                if writer.is_open(2, "Characteristics") {
+                    writer.close_to(3);
+                } else {
+                    writer.close_to(2);
+                    writer.start("Characteristics", String::new());
+                }
+
+                // Declaration value: properties, value handle, UUID
+                let properties = attr.value[0];
+                let handle = u16::from_le_bytes([attr.value[1], attr.value[2]]);
+                let uuid = Uuid::try_from_slice_le(&attr.value[3..])
+                    .map(|uuid| format_uuid(&uuid))
+                    .unwrap_or_default();
+                let value_attr = attributes.iter().find(|a| a.handle == handle);
+                let permissions = value_attr.map_or(ATT_PERM_NONE, |a| a.permissions.value());
+
+                writer.start(
+                    "Characteristic",
+                    format!(
+                        " uuid=\"{}\" handle=\"0x{:04X}\" value_handle=\"0x{:04X}\" properties=\"0x{:02X}\" permissions=\"0x{:04X}\"",
+                        uuid, attr.handle, handle, properties, permissions
+                    ),
+                );
+                write_properties(&mut writer, properties);
+                if let Some(value_attr) = value_attr {
+                    self.write_static_value(&mut writer, value_attr);
+                }
+
+                value_handle = Some(handle);
+            } else if writer.is_open(3, "Characteristic") {
+                if writer.is_open(4, "Descriptors") {
+                    writer.close_to(5);
+                } else {
+                    writer.close_to(4);
+                    writer.start("Descriptors", String::new());
+                }
+
+                writer.start(
+                    "Descriptor",
+                    format!(
+                        " uuid=\"{}\" handle=\"0x{:04X}\" permissions=\"0x{:04X}\"",
+                        format_uuid(&attr.type_),
+                        attr.handle,
+                        attr.permissions.value()
+                    ),
+                );
+                self.write_static_value(&mut writer, attr);
+                writer.close_to(5);
+            }
+        }
+
+        writer.close_to(0);
+        writer.xml
+    }
+
+    /// Write the `<Value>` element of an attribute unless it has a read callback
+    fn write_static_value(&self, writer: &mut XmlWriter, attr: &Attribute) {
+        if !self.has_read_callback(attr.handle) {
+            writer.text("Value", &hex::encode_upper(&attr.value));
+        }
+    }
+
+    /// Build a database from a GATT XML definition
+    ///
+    /// The root is either a `<Database>` of services or a single SIG
+    /// `<Service>` definition. Elements without a `handle` attribute are
+    /// assigned the next free handle. A characteristic value defaults to
+    /// the handle following its declaration.
+    pub fn from_gatt_xml(xml: &str) -> Result<Self, XmlError> {
+        let root = parse_document(xml)?;
+        let services: Vec<&XmlElement> = match root.name.as_str() {
+            "Database" => root.children_named("Service").collect(),
+            "Service" => vec![&root],
+            other => return Err(XmlError::UnknownRoot(other.into())),
+        };
+
+        let database = AttributeDatabase::new();
+        for service in services {
+            let uuid = service.uuid()?;
+            let service_type = match service.attr("type").unwrap_or("primary") {
+                "primary" => PRIMARY_SERVICE_UUID,
+                "secondary" => SECONDARY_SERVICE_UUID,
+                // SIG definitions name the service instead
+                other if other.starts_with("org.bluetooth.service.") => PRIMARY_SERVICE_UUID,
+                other => return Err(invalid_value("type", &other)),
+            };
+            database.add_xml_attribute(
+                service.handle()?,
+                Uuid::from_u16(service_type),
+                uuid_value(&uuid),
+                AttPermissions::read_only(),
+            )?;
+
+            for child in service.children_in("Characteristics") {
+                match child.name.as_str() {
+                    "Include" => {
+                        let value = decode_hex("value", child.required("value")?)?;
+                        database.add_xml_attribute(
+                            child.handle()?,
+                            Uuid::from_u16(INCLUDE_UUID),
+                            value,
+                            AttPermissions::read_only(),
+                        )?;
+                    }
+                    "Characteristic" if !child.excluded() => {
+                        database.add_xml_characteristic(child)?
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(database)
+    }
+
+    /// Add a characteristic declaration, value and descriptors
+    fn add_xml_characteristic(&self, element: &XmlElement) -> Result<(), XmlError> {
+        let uuid = element.uuid()?;
+        let properties = element.properties()?;
+
+        let declaration_handle = match element.handle()? {
+            Some(handle) => handle,
+            None => self.next_handle(),
+        };
+        let value_handle = match element.attr("value_handle") {
+            Some(value) => parse_handle("value_handle", value)?,
+            None => declaration_handle
+                .checked_add(1)
+                .ok_or_else(|| invalid_value("handle", &declaration_handle))?,
+        };
+
+        let mut declaration = vec![properties.bits()];
+        declaration.extend_from_slice(&value_handle.to_le_bytes());
+        declaration.extend_from_slice(&uuid_value(&uuid));
+        self.add_xml_attribute(
+            Some(declaration_handle),
+            Uuid::from_u16(CHARACTERISTIC_UUID),
+            declaration,
+            AttPermissions::read_only(),
+        )?;
+
+        // Without explicit permissions the value is readable and writable
+        // as its properties allow
+        let mut default_permissions = ATT_PERM_NONE;
+        if properties.contains(CharacteristicProperty::READ) {
+            default_permissions |= ATT_PERM_READ;
+        }
+        if properties.intersects(
+            CharacteristicProperty::WRITE
+                | CharacteristicProperty::WRITE_WITHOUT_RESPONSE
+                | CharacteristicProperty::AUTHENTICATED_SIGNED_WRITES,
+        ) {
+            default_permissions |= ATT_PERM_WRITE;
+        }
+        self.add_xml_attribute(
+            Some(value_handle),
+            uuid,
+            element.value()?,
+            element.permissions(default_permissions)?,
+        )?;
+
+        for descriptor in element.children_in("Descriptors") {
+            if descriptor.name != "Descriptor" || descriptor.excluded() {
+                continue;
+            }
+
+            let uuid = descriptor.uuid()?;
+            let mut value = descriptor.value()?;
+            if value.is_empty() && uuid == CLIENT_CHAR_CONFIG_UUID {
+                value = vec![0x00, 0x00];
+            }
+
+            // SIG definitions list whether a descriptor is readable and
+            // writable as properties
+            let mut default_permissions = ATT_PERM_READ;
+            if let Some(properties) = descriptor.children_named("Properties").next() {
+                default_permissions = ATT_PERM_NONE;
+                if properties.is_included("Read") {
+                    default_permissions |= ATT_PERM_READ;
+                }
+                if properties.is_included("Write") {
+                    default_permissions |= ATT_PERM_WRITE;
+                }
+            }
+
+            self.add_xml_attribute(
+                descriptor.handle()?,
+                uuid,
+                value,
+                descriptor.permissions(default_permissions)?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Add an attribute at the given handle, or the next free one
+    fn add_xml_attribute(
+        &self,
+        handle: Option<u16>,
+        type_: Uuid,
+        value: Vec<u8>,
+        permissions: AttPermissions,
+    ) -> Result<u16, XmlError> {
+        let handle = match handle {
+            Some(handle) => {
+                self.add_attribute(Attribute::new(handle, type_, value, permissions))?
+            }
+            None => self.add_attribute_with_next_handle(type_, value, permissions)?,
+        };
+
+        Ok(handle)
+    }
+}
+
+/// Write the SIG `<Properties>` element of a characteristic
+fn write_properties(writer: &mut XmlWriter, properties: u8) {
+    if properties == 0 {
+        return;
+    }
+
+    let depth = writer.open.len();
+    writer.start("Properties", String::new());
+    let mut written = CharacteristicProperty::empty();
+    for (name, property) in SIG_PROPERTIES {
+        if properties & property.bits() != 0 && !written.contains(*property) {
+            writer.text(name, "Mandatory");
+            written |= *property;
+        }
+    }
+    writer.close_to(depth);
+}
+
+/// Writes indented XML, keeping track of the open elements
+#[derive(Default)]
+struct XmlWriter {
+    xml: String,
+    open: Vec<&'static str>,
+}
+
+impl XmlWriter {
+    fn indent(&mut self) {
+        for _ in 0..self.open.len() {
+            self.xml.push_str("  ");
+        }
+    }
+
+    fn start(&mut self, name: &'static str, attributes: String) {
+        if self.open.is_empty() {
+            self.xml
+                .push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        }
+        self.indent();
+        let _ = writeln!(self.xml, "<{}{}>", name, attributes);
+        self.open.push(name);
+    }
+
+    fn empty(&mut self, name: &str, attributes: String) {
+        self.indent();
+        let _ = writeln!(self.xml, "<{}{}/>", name, attributes);
+    }
+
+    fn text(&mut self, name: &str, text: &str) {
+        self.indent();
+        let _ = writeln!(self.xml, "<{}>{}</{}>", name, text, name);
+    }
+
+    /// Check whether the element open at `depth` is `name`
+    fn is_open(&self, depth: usize, name: &str) -> bool {
+        self.open.get(depth) == Some(&name)
+    }
+
+    /// Close elements until only `depth` are open
+    fn close_to(&mut self, depth: usize) {
+        while self.open.len() > depth {
+            let name = self.open.pop().unwrap_or_default();
+            self.indent();
+            let _ = writeln!(self.xml, "</{}>", name);
+        }
+    }
+}
+
+/// Format a UUID in its shortest form
+fn format_uuid(uuid: &Uuid) -> String {
+    match uuid.as_u16() {
+        Some(uuid16) => format!("{:04X}", uuid16),
+        None => uuid.to_string().to_uppercase(),
+    }
+}
+
+/// Encode a UUID as used in declaration values (16 or 128 bit)
+fn uuid_value(uuid: &Uuid) -> Vec<u8> {
+    match uuid.as_u16() {
+        Some(uuid16) => uuid16.to_le_bytes().to_vec(),
+        None => uuid.as_bytes_le().to_vec(),
+    }
+}
+
+fn invalid_value(attribute: &str, value: &dyn std::fmt::Display) -> XmlError {
+    XmlError::InvalidValue {
+        attribute: attribute.into(),
+        value: value.to_string(),
+    }
+}
+
+fn parse_number(attribute: &str, value: &str) -> Result<u32, XmlError> {
+    let result = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    result.map_err(|_| invalid_value(attribute, &value))
+}
+
+fn parse_handle(attribute: &str, value: &str) -> Result<u16, XmlError> {
+    let handle = parse_number(attribute, value)?;
+    match u16::try_from(handle) {
+        Ok(handle) if handle >= ATT_HANDLE_MIN => Ok(handle),
+        _ => Err(invalid_value(attribute, &value)),
+    }
+}
+
+fn decode_hex(attribute: &str, value: &str) -> Result<Vec<u8>, XmlError> {
+    let cleaned: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    hex::decode(&cleaned).map_err(|_| invalid_value(attribute, &value))
+}
+
+/// A parsed XML element
+#[derive(Debug, Default)]
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl XmlElement {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required(&self, name: &'static str) -> Result<&str, XmlError> {
+        self.attr(name).ok_or_else(|| XmlError::MissingAttribute {
+            element: self.name.clone(),
+            attribute: name,
+        })
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The children, with those of a `wrapper` child (such as the SIG
+    /// `<Characteristics>` list) in its place
+    fn children_in<'a>(&'a self, wrapper: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().flat_map(move |child| {
+            if child.name == wrapper {
+                child.children.iter().collect::<Vec<_>>()
+            } else {
+                vec![child]
+            }
+        })
+    }
+
+    /// Check whether a SIG `<Requirement>` excludes the element
+    fn excluded(&self) -> bool {
+        self.children_named("Requirement")
+            .any(|requirement| requirement.text.trim() == "Excluded")
+    }
+
+    /// Check whether a SIG property child is present and not excluded
+    fn is_included(&self, name: &str) -> bool {
+        self.children_named(name)
+            .any(|property| property.text.trim() != "Excluded")
+    }
+
+    /// The `uuid` attribute, or the UUID of the SIG type named by `type`
+    fn uuid(&self) -> Result<Uuid, XmlError> {
+        if let Some(value) = self.attr("uuid") {
+            return value.parse().map_err(|_| invalid_value("uuid", &value));
+        }
+
+        let type_ = self.attr("type");
+        let Some(sig_name) = type_.and_then(|name| name.strip_prefix("org.bluetooth.")) else {
+            return Err(XmlError::MissingAttribute {
+                element: self.name.clone(),
+                attribute: "uuid",
+            });
+        };
+
+        SIG_TYPES
+            .iter()
+            .find(|(name, _)| *name == sig_name)
+            .map(|(_, uuid)| Uuid::from_u16(*uuid))
+            .ok_or_else(|| XmlError::UnknownType(format!("org.bluetooth.{}", sig_name)))
+    }
+
+    fn handle(&self) -> Result<Option<u16>, XmlError> {
+        self.attr("handle")
+            .map(|value| parse_handle("handle", value))
+            .transpose()
+    }
+
+    /// The `properties` attribute, or the SIG `<Properties>` element
+    fn properties(&self) -> Result<CharacteristicProperty, XmlError> {
+        if let Some(value) = self.attr("properties") {
+            let properties = parse_number("properties", value)?;
+            return u8::try_from(properties)
+                .map(CharacteristicProperty::from_bits_retain)
+                .map_err(|_| invalid_value("properties", &value));
+        }
+
+        let mut properties = CharacteristicProperty::empty();
+        if let Some(element) = self.children_named("Properties").next() {
+            for (name, property) in SIG_PROPERTIES {
+                if element.is_included(name) {
+                    properties |= *property;
+                }
+            }
+        }
+        Ok(properties)
+    }
+
+    fn permissions(&self, default: u16) -> Result<AttPermissions, XmlError> {
+        let Some(value) = self.attr("permissions") else {
+            return Ok(AttPermissions::new(default));
+        };
+        let permissions = parse_number("permissions", value)?;
+        u16::try_from(permissions)
+            .map(AttPermissions::new)
+            .map_err(|_| invalid_value("permissions", &value))
+    }
+
+    /// The hex-encoded `<Value>` child, empty if absent
+    fn value(&self) -> Result<Vec<u8>, XmlError> {
+        match self.children_named("Value").next() {
+            Some(value) => decode_hex("Value", &value.text),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Parse a document into its root element
+///
+/// The XML declaration, processing instructions, comments and DOCTYPE
+/// are skipped. Text and CDATA sections are collected into the element
+/// they appear in.
+fn parse_document(xml: &str) -> Result<XmlElement, XmlError> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<XmlElement> = Vec::new();
+    let mut root = None;
+
+    loop {
+        match reader.read_event().map_err(syntax_error)? {
+            Event::Start(start) => stack.push(start_element(&start)?),
+            Event::Empty(start) => close_element(&mut stack, &mut root, start_element(&start)?)?,
+            Event::End(_) => {
+                // The reader checks that end tags match their start tags
+                let element = stack
+                    .pop()
+                    .ok_or_else(|| XmlError::Syntax("Unexpected end tag".into()))?;
+                close_element(&mut stack, &mut root, element)?;
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(syntax_error)?;
+                push_text(&mut stack, &text)?;
+            }
+            Event::CData(cdata) => {
+                let text = std::str::from_utf8(&cdata).map_err(syntax_error)?;
+                push_text(&mut stack, text)?;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if let Some(element) = stack.last() {
+        return Err(XmlError::Syntax(format!("Unclosed <{}>", element.name)));
+    }
+
+    root.ok_or_else(|| XmlError::Syntax("Missing root element".into()))
+}
+
+fn syntax_error(error: impl std::fmt::Display) -> XmlError {
+    XmlError::Syntax(error.to_string())
+}
+
+fn start_element(start: &BytesStart) -> Result<XmlElement, XmlError> {
+    let name = start.name();
+    let name = std::str::from_utf8(name.as_ref()).map_err(syntax_error)?;
+    let mut element = XmlElement {
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(syntax_error)?;
+        let key = std::str::from_utf8(attribute.key.as_ref()).map_err(syntax_error)?;
+        let value = attribute.unescape_value().map_err(syntax_error)?;
+        element
+            .attributes
+            .push((key.to_string(), value.into_owned()));
+    }
+
+    Ok(element)
+}
+
+fn push_text(stack: &mut [XmlElement], text: &str) -> Result<(), XmlError> {
+    match stack.last_mut() {
+        Some(element) => element.text.push_str(text),
+        None if text.trim().is_empty() => {}
+        None => return Err(XmlError::Syntax("Text outside of the root element".into())),
+    }
+
+    Ok(())
+}
+
+fn close_element(
+    stack: &mut [XmlElement],
+    root: &mut Option<XmlElement>,
+    element: XmlElement,
+) -> Result<(), XmlError> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(element),
+        None if root.is_none() => *root = Some(element),
+        None => return Err(XmlError::Syntax("Multiple root elements".into())),
+    }
+
+    Ok(())
+}
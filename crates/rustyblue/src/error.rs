@@ -27,6 +27,12 @@ pub enum HciError {
 
     #[error("Unsupported operation")]
     Unsupported,
+
+    #[error("HCI command failed with status 0x{0:02X}")]
+    CommandFailed(u8),
+
+    #[error("Failed to write HCI capture: {0}")]
+    CaptureError(std::io::Error),
 }
 
 /// General errors that can occur in the library
@@ -1,22 +1,36 @@
 use crate::error::{Error, HciError};
+use crate::gap::advertising::{AdvertisingConfig, AdvertisingData, PeriodicAdvertisingParams};
 use crate::gap::constants::*;
 use crate::gap::types::*;
-use crate::hci::{HciCommand, HciEvent, HciSocket, LeAdvertisingReport};
-use crate::scan::parse_advertising_data;
+use crate::gatt::{ConnectionState, GenericAccessService};
+use crate::hci::{HciCommand, HciEvent, HciSocket};
+#[cfg(feature = "blocking")]
+use crate::hci::{HciPacket, LeAdvertisingReport};
+use crate::l2cap::L2capManager;
+#[cfg(feature = "blocking")]
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::sync::Arc;
+#[cfg(feature = "blocking")]
 use std::time::{Duration, Instant};
 
+/// Length of the local name parameter of Write/Read Local Name
+const LOCAL_NAME_LEN: usize = 248;
+
 /// A callback function for device discovery
 pub type DeviceDiscoveryCallback = Box<dyn Fn(&Device) + Send + 'static>;
 
 /// GAP adapter for Bluetooth operations
 pub struct GapAdapter {
     socket: HciSocket,
+    device_id: u16,
     devices: HashMap<BdAddr, Device>,
     discovery_callback: Option<DeviceDiscoveryCallback>,
     discovery_active: bool,
     local_name: Option<String>,
-    local_address: Option<BdAddr>,
+    local_address: BdAddr,
+    generic_access: Option<Arc<GenericAccessService>>,
+    l2cap_manager: Option<Arc<L2capManager>>,
 }
 
 impl GapAdapter {
@@ -26,103 +40,147 @@ impl GapAdapter {
 
         Ok(Self {
             socket,
+            device_id,
             devices: HashMap::new(),
             discovery_callback: None,
             discovery_active: false,
             local_name: None,
-            local_address: None,
+            local_address: BdAddr::new([0; 6]),
+            generic_access: None,
+            l2cap_manager: None,
         })
     }
 
-    /// Sets the local device name
-    pub fn set_local_name(&mut self, name: &str) -> Result<(), Error> {
-        let mut params = Vec::new();
+    /// Use the given Generic Access Service to mirror the local name
+    ///
+    /// Once set, `set_local_name` also updates its Device Name characteristic.
+    pub fn set_generic_access_service(&mut self, service: Arc<GenericAccessService>) {
+        self.generic_access = Some(service);
+    }
 
-        // Add name bytes (up to 248 bytes)
-        let name_bytes = name.as_bytes();
-        let name_len = std::cmp::min(name_bytes.len(), 248);
-        params.extend_from_slice(&name_bytes[0..name_len]);
+    /// Pass ACL data read by `process_events` to the given L2CAP manager
+    ///
+    /// Without one, ACL data is dropped.
+    pub fn set_l2cap_manager(&mut self, l2cap_manager: Arc<L2capManager>) {
+        self.l2cap_manager = Some(l2cap_manager);
+    }
 
-        // Pad with zeros if necessary
-        if name_len < 248 {
-            params.resize(248, 0);
+    /// Sets the local device name (HCI Write Local Name)
+    ///
+    /// The name is null-padded to 248 bytes. If a Generic Access Service is
+    /// attached its Device Name characteristic is updated as well.
+    pub fn set_local_name(&mut self, name: &str) -> Result<(), HciError> {
+        let name_bytes = name.as_bytes();
+        if name_bytes.len() > LOCAL_NAME_LEN {
+            return Err(HciError::InvalidParamLength(name_bytes.len()));
         }
 
-        // Create and send HCI command
+        let mut params = name_bytes.to_vec();
+        params.resize(LOCAL_NAME_LEN, 0);
+
         let cmd = HciCommand::new(OGF_HOST_CTL, OCF_WRITE_LOCAL_NAME, params);
-        self.socket.send_command(&cmd).map_err(Error::Hci)?;
+        self.execute_command(&cmd)?;
+        self.local_name = Some(name.to_string());
 
-        // Read command complete event
-        let event = self.socket.read_event().map_err(Error::Hci)?;
-        if event.is_command_complete(OGF_HOST_CTL, OCF_WRITE_LOCAL_NAME) {
-            self.local_name = Some(name.to_string());
-            Ok(())
-        } else {
-            Err(Error::ProtocolError("Failed to set local name".into()))
+        if let Some(service) = &self.generic_access {
+            // The name fits the characteristic, so this cannot fail
+            let _ = service.set_name(name);
         }
+
+        Ok(())
+    }
+
+    /// Reads the local device name from the controller (HCI Read Local Name)
+    pub fn read_local_name(&mut self) -> Result<String, HciError> {
+        let cmd = HciCommand::new(OGF_HOST_CTL, OCF_READ_LOCAL_NAME, Vec::new());
+        let event = self.execute_command_with_response(&cmd)?;
+
+        // Command Complete parameters: num packets, opcode, status, name
+        let name_bytes = event
+            .get_parameters()
+            .get(4..)
+            .ok_or(HciError::InvalidPacketFormat)?;
+        let name_len = name_bytes
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(name_bytes.len());
+        let name = String::from_utf8_lossy(&name_bytes[..name_len]).into_owned();
+
+        self.local_name = Some(name.clone());
+        Ok(name)
     }
 
-    /// Gets the local device name
+    /// Gets the local device name, reading it from the controller if needed
     pub fn get_local_name(&mut self) -> Result<String, Error> {
         if let Some(name) = &self.local_name {
             return Ok(name.clone());
         }
 
-        // Read the local name from the controller
-        let cmd = HciCommand::new(OGF_HOST_CTL, OCF_READ_LOCAL_NAME, Vec::new());
-        self.socket.send_command(&cmd).map_err(Error::Hci)?;
+        Ok(self.read_local_name()?)
+    }
 
-        // Read command complete event
-        let event = self.socket.read_event().map_err(Error::Hci)?;
-        if event.is_command_complete(OGF_HOST_CTL, OCF_READ_LOCAL_NAME) {
-            if event.get_status() == 0 {
-                let mut name = String::new();
-                for &b in &event.get_parameters()[1..] {
-                    if b == 0 {
-                        break;
-                    }
-                    name.push(b as char);
-                }
-                self.local_name = Some(name.clone());
-                Ok(name)
-            } else {
-                Err(Error::ProtocolError("Failed to get local name".into()))
-            }
-        } else {
-            Err(Error::ProtocolError("Unexpected event received".into()))
-        }
+    /// Initializes the adapter by reading the controller's public address
+    pub fn initialize(&mut self) -> Result<(), HciError> {
+        self.local_address = self.socket.read_bd_addr()?;
+        Ok(())
     }
 
-    /// Gets the local device address
-    pub fn get_local_address(&mut self) -> Result<BdAddr, Error> {
-        if let Some(addr) = &self.local_address {
-            return Ok(addr.clone());
+    /// Resets the controller with HCI Reset and initializes it again
+    ///
+    /// The controller forgets its connections, scan and advertising state,
+    /// so the cached state of the adapter is cleared too.
+    pub fn reset(&mut self) -> Result<(), HciError> {
+        self.execute_command(&HciCommand::Reset)?;
+        self.clear_controller_state();
+        self.initialize()
+    }
+
+    /// Powers the adapter off with the HCIDEVDOWN ioctl
+    ///
+    /// Usually needs `CAP_NET_ADMIN`. Cached connection and discovery state
+    /// is cleared.
+    pub fn power_off(&mut self) -> Result<(), HciError> {
+        self.socket.device_down(self.device_id)?;
+        self.clear_controller_state();
+        Ok(())
+    }
+
+    /// Powers the adapter on with the HCIDEVUP ioctl and initializes it
+    ///
+    /// Usually needs `CAP_NET_ADMIN`. Powering on an adapter that is already
+    /// on is not an error.
+    pub fn power_on(&mut self) -> Result<(), HciError> {
+        self.socket.device_up(self.device_id)?;
+        self.clear_controller_state();
+        self.initialize()
+    }
+
+    /// Forget state the controller loses on reset or power cycle
+    ///
+    /// Discovered devices are kept, but none of them is connected anymore.
+    fn clear_controller_state(&mut self) {
+        self.discovery_active = false;
+        self.local_name = None;
+        self.local_address = BdAddr::new([0; 6]);
+        for device in self.devices.values_mut() {
+            device.connection_state = ConnectionState::Disconnected;
         }
+    }
 
-        // Read the local address from the controller
-        let cmd = HciCommand::new(OGF_INFO_PARAM, OCF_READ_BD_ADDR, Vec::new());
-        self.socket.send_command(&cmd).map_err(Error::Hci)?;
+    /// Returns the local device address read by `initialize`
+    ///
+    /// This is all zeros until the adapter has been initialized.
+    pub fn local_address(&self) -> BdAddr {
+        self.local_address
+    }
 
-        // Read command complete event
-        let event = self.socket.read_event().map_err(Error::Hci)?;
-        if event.is_command_complete(OGF_INFO_PARAM, OCF_READ_BD_ADDR) {
-            if event.get_status() == 0 {
-                let params = event.get_parameters();
-                if params.len() >= 7 {
-                    let mut bytes = [0u8; 6];
-                    bytes.copy_from_slice(&params[1..7]);
-                    let addr = BdAddr::new(bytes);
-                    self.local_address = Some(addr.clone());
-                    Ok(addr)
-                } else {
-                    Err(Error::InvalidPacket("BD ADDR response too short".into()))
-                }
-            } else {
-                Err(Error::ProtocolError("Failed to get BD ADDR".into()))
-            }
-        } else {
-            Err(Error::ProtocolError("Unexpected event received".into()))
+    /// Gets the local device address, initializing the adapter if needed
+    pub fn get_local_address(&mut self) -> Result<BdAddr, Error> {
+        if self.local_address == BdAddr::new([0; 6]) {
+            self.initialize()?;
         }
+
+        Ok(self.local_address)
     }
 
     /// Starts device discovery
@@ -151,9 +209,10 @@ impl GapAdapter {
         }
 
         // Enable scanning
-        params = Vec::new();
-        params.push(0x01); // Enable scanning
-        params.push(0x00); // Filter duplicates: disabled
+        params = vec![
+            0x01, // Enable scanning
+            0x00, // Filter duplicates: disabled
+        ];
 
         let cmd = HciCommand::new(OGF_LE_CTL, OCF_LE_SET_SCAN_ENABLE, params);
         self.socket.send_command(&cmd).map_err(Error::Hci)?;
@@ -178,9 +237,10 @@ impl GapAdapter {
         }
 
         // Disable scanning
-        let mut params = Vec::new();
-        params.push(0x00); // Disable scanning
-        params.push(0x00); // Filter duplicates: disabled
+        let params = vec![
+            0x00, // Disable scanning
+            0x00, // Filter duplicates: disabled
+        ];
 
         let cmd = HciCommand::new(OGF_LE_CTL, OCF_LE_SET_SCAN_ENABLE, params);
         self.socket.send_command(&cmd).map_err(Error::Hci)?;
@@ -198,6 +258,98 @@ impl GapAdapter {
         Ok(())
     }
 
+    /// Starts LE advertising
+    ///
+    /// Sends LE Set Advertising Parameters, LE Set Advertising Data and
+    /// LE Set Advertise Enable in sequence, waiting for each to complete.
+    pub fn start_advertising(&mut self, config: AdvertisingConfig) -> Result<(), HciError> {
+        let data_cmd = HciCommand::le_set_advertising_data(&config.data)?;
+
+        self.execute_command(&HciCommand::LeSetAdvertisingParameters {
+            advertising_interval_min: config.interval_min,
+            advertising_interval_max: config.interval_max,
+            advertising_type: config.advertising_type,
+            own_address_type: config.own_address_type,
+            peer_address_type: config.peer_address_type,
+            peer_address: config.peer_address,
+            advertising_channel_map: config.channel_map,
+            advertising_filter_policy: config.filter_policy,
+        })?;
+
+        self.execute_command(&data_cmd)?;
+
+        self.execute_command(&HciCommand::LeSetAdvertiseEnable {
+            advertising_enable: true,
+        })
+    }
+
+    /// Sets the data sent in response to scan requests
+    ///
+    /// Lets a peripheral move fields such as its complete name out of the
+    /// 31 byte advertising data. Fails with `HciError::InvalidParamLength`
+    /// if the encoded data doesn't fit in 31 bytes.
+    pub fn set_scan_response_data(&mut self, data: &AdvertisingData) -> Result<(), HciError> {
+        let command = HciCommand::le_set_scan_response_data(&data.to_bytes())?;
+        self.execute_command(&command)
+    }
+
+    /// Starts periodic advertising on an extended advertising set
+    ///
+    /// Sends LE Set Periodic Advertising Parameters, LE Set Periodic
+    /// Advertising Data and LE Set Periodic Advertising Enable in sequence,
+    /// waiting for each to complete. Data longer than a single command can
+    /// carry is sent in fragments. The advertising set must already have
+    /// been configured with non-connectable, non-scannable extended
+    /// advertising parameters.
+    pub fn start_periodic_advertising(
+        &mut self,
+        params: PeriodicAdvertisingParams,
+        data: &[u8],
+    ) -> Result<(), HciError> {
+        if data.len() > PERIODIC_ADV_DATA_MAX_LEN {
+            return Err(HciError::InvalidParamLength(data.len()));
+        }
+
+        self.execute_command(&HciCommand::LeSetPeriodicAdvertisingParameters {
+            advertising_handle: params.advertising_handle,
+            periodic_advertising_interval_min: params.interval_min,
+            periodic_advertising_interval_max: params.interval_max,
+            periodic_advertising_properties: if params.include_tx_power {
+                0x0040
+            } else {
+                0x0000
+            },
+        })?;
+
+        if data.len() <= PERIODIC_ADV_DATA_FRAGMENT_LEN {
+            self.execute_command(&HciCommand::LeSetPeriodicAdvertisingData {
+                advertising_handle: params.advertising_handle,
+                operation: PERIODIC_ADV_DATA_COMPLETE,
+                data: data.to_vec(),
+            })?;
+        } else {
+            let fragments: Vec<&[u8]> = data.chunks(PERIODIC_ADV_DATA_FRAGMENT_LEN).collect();
+            let last = fragments.len() - 1;
+            for (index, fragment) in fragments.into_iter().enumerate() {
+                let operation = match index {
+                    0 => PERIODIC_ADV_DATA_FIRST,
+                    i if i == last => PERIODIC_ADV_DATA_LAST,
+                    _ => PERIODIC_ADV_DATA_INTERMEDIATE,
+                };
+                self.execute_command(&HciCommand::LeSetPeriodicAdvertisingData {
+                    advertising_handle: params.advertising_handle,
+                    operation,
+                    data: fragment.to_vec(),
+                })?;
+            }
+        }
+
+        self.execute_command(&HciCommand::LeSetPeriodicAdvertisingEnable {
+            enable: true,
+            advertising_handle: params.advertising_handle,
+        })
+    }
+
     /// Connects to a device
     pub fn connect(&mut self, address: &BdAddr, address_type: AddressType) -> Result<(), Error> {
         let mut params = Vec::new();
@@ -255,6 +407,7 @@ impl GapAdapter {
     }
 
     /// Process incoming HCI events
+    #[cfg(feature = "blocking")]
     pub fn process_events(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
         let start_time = Instant::now();
 
@@ -276,23 +429,28 @@ impl GapAdapter {
                 }
             });
 
-            let event_result = self
+            let packet_result = self
                 .socket
-                .read_event_timeout(remaining_timeout)
+                .read_packet_timeout(remaining_timeout)
                 .map_err(Error::Hci);
 
             // Handle timeout
-            if let Err(Error::Hci(HciError::ReceiveError(e))) = &event_result {
+            if let Err(Error::Hci(HciError::ReceiveError(e))) = &packet_result {
                 if e.kind() == std::io::ErrorKind::TimedOut {
                     break;
                 }
             }
 
             // Process event
-            if let Ok(event) = event_result {
-                self.handle_event(event)?;
-            } else if let Err(e) = event_result {
-                return Err(e);
+            match packet_result? {
+                HciPacket::Event(event) => self.handle_event(event)?,
+                HciPacket::Acl(acl) => {
+                    if let Some(l2cap_manager) = &self.l2cap_manager {
+                        l2cap_manager
+                            .handle_acl_data(&acl)
+                            .map_err(|e| Error::ProtocolError(e.to_string()))?;
+                    }
+                }
             }
         }
 
@@ -300,6 +458,7 @@ impl GapAdapter {
     }
 
     /// Handle HCI events
+    #[cfg(feature = "blocking")]
     fn handle_event(&mut self, event: HciEvent) -> Result<(), Error> {
         match event.get_event_code() {
             EVT_LE_META_EVENT => {
@@ -327,7 +486,29 @@ impl GapAdapter {
         Ok(())
     }
 
+    /// Send a command and wait for its Command Complete event
+    fn execute_command(&self, cmd: &HciCommand) -> Result<(), HciError> {
+        self.execute_command_with_response(cmd).map(|_| ())
+    }
+
+    /// Send a command and return its successful Command Complete event
+    fn execute_command_with_response(&self, cmd: &HciCommand) -> Result<HciEvent, HciError> {
+        let (ogf, ocf) = cmd.opcode_parts();
+        self.socket.send_command(cmd)?;
+
+        let event = self.socket.read_event()?;
+        if !event.is_command_complete(ogf, ocf) {
+            return Err(HciError::InvalidPacketFormat);
+        }
+
+        match event.get_status() {
+            0 => Ok(event),
+            status => Err(HciError::CommandFailed(status)),
+        }
+    }
+
     /// Handle LE advertising reports
+    #[cfg(feature = "blocking")]
     fn handle_advertising_report(&mut self, event: &HciEvent) -> Result<(), Error> {
         if !self.discovery_active {
             return Ok(());
@@ -336,52 +517,17 @@ impl GapAdapter {
         let reports = LeAdvertisingReport::parse_from_event(event)?;
 
         for report in reports {
-            let addr = BdAddr::from_slice(&report.address).unwrap();
-            let addr_type = AddressType::from(report.address_type);
+            let addr = BdAddr::new(report.address);
 
             // Update or create device
-            let device = self
-                .devices
-                .entry(addr.clone())
-                .or_insert_with(|| Device::new(addr.clone(), addr_type));
-
-            // Update RSSI
-            device.rssi = Some(report.rssi);
-
-            // Parse advertising data
-            if !report.data.is_empty() {
-                let ad_data = parse_advertising_data(&report.data);
-
-                for (data_type, data) in ad_data {
-                    match data_type {
-                        ADV_TYPE_SHORT_LOCAL_NAME | ADV_TYPE_COMPLETE_LOCAL_NAME => {
-                            if let Ok(name) = String::from_utf8(data.to_vec()) {
-                                device.name = Some(name);
-                            }
-                        }
-                        ADV_TYPE_TX_POWER_LEVEL => {
-                            if data.len() == 1 {
-                                device.tx_power = Some(data[0] as i8);
-                            }
-                        }
-                        ADV_TYPE_MANUFACTURER_SPECIFIC => {
-                            device.manufacturer_data = Some(data.to_vec());
-                        }
-                        ADV_TYPE_FLAGS => {
-                            if data.len() == 1 {
-                                device.flags = Some(data[0]);
-                            }
-                        }
-                        ADV_TYPE_APPEARANCE => {
-                            if data.len() == 2 {
-                                device.appearance = Some(u16::from_le_bytes([data[0], data[1]]));
-                            }
-                        }
-                        // TODO: Handle more data types like service UUIDs
-                        _ => {}
-                    }
+            let device = match self.devices.entry(addr) {
+                Entry::Occupied(entry) => {
+                    let device = entry.into_mut();
+                    device.update_from_advertising_report(&report);
+                    device
                 }
-            }
+                Entry::Vacant(entry) => entry.insert(Device::from_advertising_report(&report)),
+            };
 
             // Call discovery callback
             if let Some(callback) = &self.discovery_callback {
@@ -0,0 +1,579 @@
+//! Typed LE advertising data
+//!
+//! This module decodes the raw AD structures carried in advertising reports
+//! into typed values, and encodes typed values into AD structures.
+
+use crate::gap::constants::*;
+use crate::hci::constants::LE_MAX_ADVERTISING_DATA_LEN;
+use crate::hci::HciCommand;
+use crate::scan::parse_ad_structures;
+use crate::uuid::Uuid;
+use thiserror::Error;
+
+/// A single decoded AD structure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdStructure {
+    /// Flags (AD type 0x01)
+    Flags(u8),
+    /// List of service UUIDs, `complete` is false for incomplete lists
+    ServiceUuids { uuids: Vec<Uuid>, complete: bool },
+    /// Shortened or complete local name
+    LocalName { name: String, complete: bool },
+    /// TX power level in dBm
+    TxPowerLevel(i8),
+    /// Appearance value
+    Appearance(u16),
+    /// Service data associated with a service UUID
+    ServiceData { uuid: Uuid, data: Vec<u8> },
+    /// Manufacturer specific data prefixed by a company identifier
+    ManufacturerData { company_id: u16, data: Vec<u8> },
+    /// Any AD structure that is not decoded or is malformed
+    Unknown { ad_type: u8, data: Vec<u8> },
+}
+
+impl AdStructure {
+    /// Decode a single AD structure from its type and payload
+    pub fn parse(ad_type: u8, data: &[u8]) -> Self {
+        let unknown = || AdStructure::Unknown {
+            ad_type,
+            data: data.to_vec(),
+        };
+
+        match ad_type {
+            ADV_TYPE_FLAGS if data.len() == 1 => AdStructure::Flags(data[0]),
+            ADV_TYPE_16BIT_SERVICE_UUID_PARTIAL | ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE => {
+                match parse_uuid_list(data, 2) {
+                    Some(uuids) => AdStructure::ServiceUuids {
+                        uuids,
+                        complete: ad_type == ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE,
+                    },
+                    None => unknown(),
+                }
+            }
+            ADV_TYPE_32BIT_SERVICE_UUID_PARTIAL | ADV_TYPE_32BIT_SERVICE_UUID_COMPLETE => {
+                match parse_uuid_list(data, 4) {
+                    Some(uuids) => AdStructure::ServiceUuids {
+                        uuids,
+                        complete: ad_type == ADV_TYPE_32BIT_SERVICE_UUID_COMPLETE,
+                    },
+                    None => unknown(),
+                }
+            }
+            ADV_TYPE_128BIT_SERVICE_UUID_PARTIAL | ADV_TYPE_128BIT_SERVICE_UUID_COMPLETE => {
+                match parse_uuid_list(data, 16) {
+                    Some(uuids) => AdStructure::ServiceUuids {
+                        uuids,
+                        complete: ad_type == ADV_TYPE_128BIT_SERVICE_UUID_COMPLETE,
+                    },
+                    None => unknown(),
+                }
+            }
+            ADV_TYPE_SHORT_LOCAL_NAME | ADV_TYPE_COMPLETE_LOCAL_NAME => {
+                match String::from_utf8(data.to_vec()) {
+                    Ok(name) => AdStructure::LocalName {
+                        name,
+                        complete: ad_type == ADV_TYPE_COMPLETE_LOCAL_NAME,
+                    },
+                    Err(_) => unknown(),
+                }
+            }
+            ADV_TYPE_TX_POWER_LEVEL if data.len() == 1 => AdStructure::TxPowerLevel(data[0] as i8),
+            ADV_TYPE_APPEARANCE if data.len() == 2 => {
+                AdStructure::Appearance(u16::from_le_bytes([data[0], data[1]]))
+            }
+            ADV_TYPE_SERVICE_DATA_16BIT if data.len() >= 2 => AdStructure::ServiceData {
+                uuid: Uuid::from_u16(u16::from_le_bytes([data[0], data[1]])),
+                data: data[2..].to_vec(),
+            },
+            ADV_TYPE_SERVICE_DATA_32BIT if data.len() >= 4 => AdStructure::ServiceData {
+                uuid: Uuid::from_u32(u32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+                data: data[4..].to_vec(),
+            },
+            ADV_TYPE_SERVICE_DATA_128BIT if data.len() >= 16 => {
+                match Uuid::try_from_slice_le(&data[..16]) {
+                    Some(uuid) => AdStructure::ServiceData {
+                        uuid,
+                        data: data[16..].to_vec(),
+                    },
+                    None => unknown(),
+                }
+            }
+            ADV_TYPE_MANUFACTURER_SPECIFIC if data.len() >= 2 => AdStructure::ManufacturerData {
+                company_id: u16::from_le_bytes([data[0], data[1]]),
+                data: data[2..].to_vec(),
+            },
+            _ => unknown(),
+        }
+    }
+}
+
+/// Append an AD structure (length, type, payload) to `out`
+fn push_ad_structure(out: &mut Vec<u8>, ad_type: u8, payload: &[u8]) {
+    out.push(payload.len() as u8 + 1);
+    out.push(ad_type);
+    out.extend_from_slice(payload);
+}
+
+/// Encode a UUID in its shortest little-endian form
+fn uuid_to_le_bytes(uuid: &Uuid) -> Vec<u8> {
+    if let Some(uuid16) = uuid.as_u16() {
+        uuid16.to_le_bytes().to_vec()
+    } else if let Some(uuid32) = uuid.as_u32() {
+        uuid32.to_le_bytes().to_vec()
+    } else {
+        let mut bytes = uuid.as_bytes_be();
+        bytes.reverse();
+        bytes.to_vec()
+    }
+}
+
+fn parse_uuid_list(data: &[u8], uuid_size: usize) -> Option<Vec<Uuid>> {
+    if !data.len().is_multiple_of(uuid_size) {
+        return None;
+    }
+
+    data.chunks(uuid_size)
+        .map(|chunk| match uuid_size {
+            2 => Some(Uuid::from_u16(u16::from_le_bytes([chunk[0], chunk[1]]))),
+            4 => Some(Uuid::from_u32(u32::from_le_bytes([
+                chunk[0], chunk[1], chunk[2], chunk[3],
+            ]))),
+            _ => Uuid::try_from_slice_le(chunk),
+        })
+        .collect()
+}
+
+/// Advertising data decoded from an advertising report or scan response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisingData {
+    /// Flags AD structure
+    pub flags: Option<u8>,
+    /// Local name, either shortened or complete
+    pub local_name: Option<String>,
+    /// Whether `local_name` is the complete local name
+    pub complete_local_name: bool,
+    /// TX power level in dBm
+    pub tx_power: Option<i8>,
+    /// Advertised service UUIDs of all sizes
+    pub service_uuids: Vec<Uuid>,
+    /// Service data entries
+    pub service_data: Vec<(Uuid, Vec<u8>)>,
+    /// Appearance value
+    pub appearance: Option<u16>,
+    /// Manufacturer specific data entries as (company identifier, payload)
+    pub manufacturer_data: Vec<(u16, Vec<u8>)>,
+    /// All decoded AD structures in the order they were received
+    pub structures: Vec<AdStructure>,
+}
+
+impl AdvertisingData {
+    /// Parse raw advertising data into typed fields
+    pub fn parse(data: &[u8]) -> Self {
+        let mut ad = AdvertisingData::default();
+
+        for (ad_type, payload) in parse_ad_structures(data) {
+            let structure = AdStructure::parse(ad_type, &payload);
+
+            match &structure {
+                AdStructure::Flags(flags) => ad.flags = Some(*flags),
+                AdStructure::ServiceUuids { uuids, .. } => {
+                    ad.service_uuids.extend(uuids.iter().copied())
+                }
+                AdStructure::LocalName { name, complete } => {
+                    // A complete name always wins over a shortened one
+                    if *complete || !ad.complete_local_name {
+                        ad.local_name = Some(name.clone());
+                        ad.complete_local_name = *complete;
+                    }
+                }
+                AdStructure::TxPowerLevel(power) => ad.tx_power = Some(*power),
+                AdStructure::Appearance(appearance) => ad.appearance = Some(*appearance),
+                AdStructure::ServiceData { uuid, data } => {
+                    ad.service_data.push((*uuid, data.clone()))
+                }
+                AdStructure::ManufacturerData { company_id, data } => {
+                    ad.manufacturer_data.push((*company_id, data.clone()))
+                }
+                AdStructure::Unknown { .. } => {}
+            }
+
+            ad.structures.push(structure);
+        }
+
+        ad
+    }
+
+    /// Encode the typed fields as raw AD structures
+    ///
+    /// Service UUIDs are grouped into complete lists by size. `structures`
+    /// is not used, so fields changed after parsing are encoded as set.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        if let Some(flags) = self.flags {
+            push_ad_structure(&mut out, ADV_TYPE_FLAGS, &[flags]);
+        }
+
+        for (size, ad_type) in [
+            (2, ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE),
+            (4, ADV_TYPE_32BIT_SERVICE_UUID_COMPLETE),
+            (16, ADV_TYPE_128BIT_SERVICE_UUID_COMPLETE),
+        ] {
+            let uuids: Vec<u8> = self
+                .service_uuids
+                .iter()
+                .map(uuid_to_le_bytes)
+                .filter(|bytes| bytes.len() == size)
+                .flatten()
+                .collect();
+            if !uuids.is_empty() {
+                push_ad_structure(&mut out, ad_type, &uuids);
+            }
+        }
+
+        if let Some(name) = &self.local_name {
+            let ad_type = if self.complete_local_name {
+                ADV_TYPE_COMPLETE_LOCAL_NAME
+            } else {
+                ADV_TYPE_SHORT_LOCAL_NAME
+            };
+            push_ad_structure(&mut out, ad_type, name.as_bytes());
+        }
+
+        if let Some(tx_power) = self.tx_power {
+            push_ad_structure(&mut out, ADV_TYPE_TX_POWER_LEVEL, &[tx_power as u8]);
+        }
+
+        if let Some(appearance) = self.appearance {
+            push_ad_structure(&mut out, ADV_TYPE_APPEARANCE, &appearance.to_le_bytes());
+        }
+
+        for (uuid, data) in &self.service_data {
+            let mut payload = uuid_to_le_bytes(uuid);
+            let ad_type = match payload.len() {
+                2 => ADV_TYPE_SERVICE_DATA_16BIT,
+                4 => ADV_TYPE_SERVICE_DATA_32BIT,
+                _ => ADV_TYPE_SERVICE_DATA_128BIT,
+            };
+            payload.extend_from_slice(data);
+            push_ad_structure(&mut out, ad_type, &payload);
+        }
+
+        for (company_id, data) in &self.manufacturer_data {
+            let mut payload = company_id.to_le_bytes().to_vec();
+            payload.extend_from_slice(data);
+            push_ad_structure(&mut out, ADV_TYPE_MANUFACTURER_SPECIFIC, &payload);
+        }
+
+        out
+    }
+
+    /// Get the local name, if advertised
+    pub fn local_name(&self) -> Option<&str> {
+        self.local_name.as_deref()
+    }
+
+    /// Get the first manufacturer specific data entry as (company identifier, payload)
+    pub fn manufacturer_data(&self) -> Option<(u16, &[u8])> {
+        self.manufacturer_data
+            .first()
+            .map(|(company_id, data)| (*company_id, data.as_slice()))
+    }
+
+    /// Get the manufacturer specific data of a company
+    ///
+    /// Returns the first entry with that company identifier.
+    pub fn manufacturer_data_for_company(&self, company_id: u16) -> Option<&[u8]> {
+        self.manufacturer_data
+            .iter()
+            .find(|(id, _)| *id == company_id)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+/// Errors building advertising or scan response data
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingDataError {
+    #[error("AD structure does not fit in the remaining advertising data")]
+    Overflow,
+}
+
+/// Builder for the 31 bytes of legacy advertising data
+///
+/// Each method appends one AD structure and fails with
+/// `AdvertisingDataError::Overflow`, leaving nothing appended, if it would
+/// not fit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisingDataBuilder {
+    data: Vec<u8>,
+}
+
+impl AdvertisingDataBuilder {
+    /// Start with empty advertising data
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the flags, e.g. `ADV_FLAG_LE_GENERAL_DISC`
+    pub fn set_flags(self, flags: u8) -> Result<Self, AdvertisingDataError> {
+        self.push(ADV_TYPE_FLAGS, &[flags])
+    }
+
+    /// Add the complete or shortened local name
+    pub fn set_local_name(self, name: &str, complete: bool) -> Result<Self, AdvertisingDataError> {
+        let ad_type = if complete {
+            ADV_TYPE_COMPLETE_LOCAL_NAME
+        } else {
+            ADV_TYPE_SHORT_LOCAL_NAME
+        };
+        self.push(ad_type, name.as_bytes())
+    }
+
+    /// Add a 16-bit service UUID
+    ///
+    /// Consecutive 16-bit UUIDs share one complete list structure.
+    pub fn add_service_uuid_16(self, uuid: u16) -> Result<Self, AdvertisingDataError> {
+        self.push_to_list(ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE, &uuid.to_le_bytes())
+    }
+
+    /// Add a 128-bit service UUID
+    ///
+    /// Consecutive 128-bit UUIDs share one complete list structure.
+    pub fn add_service_uuid_128(self, uuid: &Uuid) -> Result<Self, AdvertisingDataError> {
+        let mut bytes = uuid.as_bytes_be();
+        bytes.reverse();
+        self.push_to_list(ADV_TYPE_128BIT_SERVICE_UUID_COMPLETE, &bytes)
+    }
+
+    /// Add manufacturer specific data for a company identifier
+    pub fn set_manufacturer_data(
+        self,
+        company_id: u16,
+        data: &[u8],
+    ) -> Result<Self, AdvertisingDataError> {
+        let mut payload = company_id.to_le_bytes().to_vec();
+        payload.extend_from_slice(data);
+        self.push(ADV_TYPE_MANUFACTURER_SPECIFIC, &payload)
+    }
+
+    /// Add the TX power level in dBm
+    pub fn set_tx_power(self, dbm: i8) -> Result<Self, AdvertisingDataError> {
+        self.push(ADV_TYPE_TX_POWER_LEVEL, &[dbm as u8])
+    }
+
+    /// Add service data for a 16-bit service UUID
+    pub fn add_service_data_16(self, uuid: u16, data: &[u8]) -> Result<Self, AdvertisingDataError> {
+        let mut payload = uuid.to_le_bytes().to_vec();
+        payload.extend_from_slice(data);
+        self.push(ADV_TYPE_SERVICE_DATA_16BIT, &payload)
+    }
+
+    /// Number of bytes used so far
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether no AD structure has been added
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Get the data, zero-padded to 31 bytes
+    pub fn build(&self) -> [u8; LE_MAX_ADVERTISING_DATA_LEN] {
+        let mut data = [0u8; LE_MAX_ADVERTISING_DATA_LEN];
+        data[..self.data.len()].copy_from_slice(&self.data);
+        data
+    }
+
+    /// Get an LE Set Advertising Data command carrying the data
+    pub fn command(&self) -> HciCommand {
+        HciCommand::LeSetAdvertisingData {
+            advertising_data_length: self.data.len() as u8,
+            advertising_data: self.build(),
+        }
+    }
+
+    /// Append an AD structure if it fits
+    fn push(mut self, ad_type: u8, payload: &[u8]) -> Result<Self, AdvertisingDataError> {
+        if self.data.len() + 2 + payload.len() > LE_MAX_ADVERTISING_DATA_LEN {
+            return Err(AdvertisingDataError::Overflow);
+        }
+        push_ad_structure(&mut self.data, ad_type, payload);
+        Ok(self)
+    }
+
+    /// Append a list entry, extending the last structure if it is that list
+    fn push_to_list(mut self, ad_type: u8, entry: &[u8]) -> Result<Self, AdvertisingDataError> {
+        let Some(start) = self.last_structure() else {
+            return self.push(ad_type, entry);
+        };
+        if self.data[start + 1] != ad_type {
+            return self.push(ad_type, entry);
+        }
+
+        if self.data.len() + entry.len() > LE_MAX_ADVERTISING_DATA_LEN {
+            return Err(AdvertisingDataError::Overflow);
+        }
+        self.data[start] += entry.len() as u8;
+        self.data.extend_from_slice(entry);
+        Ok(self)
+    }
+
+    /// Offset of the last AD structure
+    fn last_structure(&self) -> Option<usize> {
+        let mut offset = 0;
+        let mut last = None;
+        while offset < self.data.len() {
+            last = Some(offset);
+            offset += 1 + self.data[offset] as usize;
+        }
+        last
+    }
+}
+
+/// Builder for the 31 bytes of legacy scan response data
+///
+/// Offers the same AD structures as [`AdvertisingDataBuilder`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanResponseBuilder {
+    data: AdvertisingDataBuilder,
+}
+
+impl ScanResponseBuilder {
+    /// Start with an empty scan response
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the flags
+    pub fn set_flags(self, flags: u8) -> Result<Self, AdvertisingDataError> {
+        self.data.set_flags(flags).map(Self::wrap)
+    }
+
+    /// Add the complete or shortened local name
+    pub fn set_local_name(self, name: &str, complete: bool) -> Result<Self, AdvertisingDataError> {
+        self.data.set_local_name(name, complete).map(Self::wrap)
+    }
+
+    /// Add a 16-bit service UUID
+    pub fn add_service_uuid_16(self, uuid: u16) -> Result<Self, AdvertisingDataError> {
+        self.data.add_service_uuid_16(uuid).map(Self::wrap)
+    }
+
+    /// Add a 128-bit service UUID
+    pub fn add_service_uuid_128(self, uuid: &Uuid) -> Result<Self, AdvertisingDataError> {
+        self.data.add_service_uuid_128(uuid).map(Self::wrap)
+    }
+
+    /// Add manufacturer specific data for a company identifier
+    pub fn set_manufacturer_data(
+        self,
+        company_id: u16,
+        data: &[u8],
+    ) -> Result<Self, AdvertisingDataError> {
+        self.data
+            .set_manufacturer_data(company_id, data)
+            .map(Self::wrap)
+    }
+
+    /// Add the TX power level in dBm
+    pub fn set_tx_power(self, dbm: i8) -> Result<Self, AdvertisingDataError> {
+        self.data.set_tx_power(dbm).map(Self::wrap)
+    }
+
+    /// Add service data for a 16-bit service UUID
+    pub fn add_service_data_16(self, uuid: u16, data: &[u8]) -> Result<Self, AdvertisingDataError> {
+        self.data.add_service_data_16(uuid, data).map(Self::wrap)
+    }
+
+    /// Number of bytes used so far
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether no AD structure has been added
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Get the data, zero-padded to 31 bytes
+    pub fn build(&self) -> [u8; LE_MAX_ADVERTISING_DATA_LEN] {
+        self.data.build()
+    }
+
+    /// Get an LE Set Scan Response Data command carrying the data
+    pub fn command(&self) -> HciCommand {
+        HciCommand::LeSetScanResponseData {
+            scan_response_data_length: self.data.len() as u8,
+            scan_response_data: self.data.build(),
+        }
+    }
+
+    fn wrap(data: AdvertisingDataBuilder) -> Self {
+        Self { data }
+    }
+}
+
+/// Parameters used by [`GapAdapter::start_advertising`](crate::gap::GapAdapter::start_advertising)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvertisingConfig {
+    /// Minimum advertising interval in 0.625 ms units
+    pub interval_min: u16,
+    /// Maximum advertising interval in 0.625 ms units
+    pub interval_max: u16,
+    /// Advertising type (e.g. `ADV_IND`)
+    pub advertising_type: u8,
+    /// Own address type
+    pub own_address_type: u8,
+    /// Peer address type, only used for directed advertising
+    pub peer_address_type: u8,
+    /// Peer address, only used for directed advertising
+    pub peer_address: [u8; 6],
+    /// Bitmask of advertising channels 37, 38 and 39
+    pub channel_map: u8,
+    /// Advertising filter policy
+    pub filter_policy: u8,
+    /// Raw AD structures to advertise, at most 31 bytes
+    pub data: Vec<u8>,
+}
+
+impl Default for AdvertisingConfig {
+    fn default() -> Self {
+        Self {
+            interval_min: 0x0800, // 1.28 s
+            interval_max: 0x0800, // 1.28 s
+            advertising_type: ADV_IND,
+            own_address_type: PUBLIC_DEVICE_ADDRESS,
+            peer_address_type: PUBLIC_DEVICE_ADDRESS,
+            peer_address: [0; 6],
+            channel_map: 0x07, // All channels
+            filter_policy: 0x00,
+            data: Vec::new(),
+        }
+    }
+}
+
+/// Parameters used by [`GapAdapter::start_periodic_advertising`](crate::gap::GapAdapter::start_periodic_advertising)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeriodicAdvertisingParams {
+    /// Advertising set to advertise on, which must already be configured
+    /// for extended advertising
+    pub advertising_handle: u8,
+    /// Minimum periodic advertising interval in 1.25 ms units
+    pub interval_min: u16,
+    /// Maximum periodic advertising interval in 1.25 ms units
+    pub interval_max: u16,
+    /// Include the TX power in the periodic advertising PDUs
+    pub include_tx_power: bool,
+}
+
+impl Default for PeriodicAdvertisingParams {
+    fn default() -> Self {
+        Self {
+            advertising_handle: 0x00,
+            interval_min: 0x0050, // 100 ms
+            interval_max: 0x0050, // 100 ms
+            include_tx_power: false,
+        }
+    }
+}
@@ -52,5 +52,30 @@ pub const ADV_TYPE_CLASS_OF_DEVICE: u8 = 0x0D;
 pub const ADV_TYPE_SIMPLE_PAIRING_HASH: u8 = 0x0E;
 pub const ADV_TYPE_SIMPLE_PAIRING_RANDOMIZER: u8 = 0x0F;
 pub const ADV_TYPE_DEVICE_ID: u8 = 0x10;
+pub const ADV_TYPE_SERVICE_DATA_16BIT: u8 = 0x16;
 pub const ADV_TYPE_APPEARANCE: u8 = 0x19;
+pub const ADV_TYPE_SERVICE_DATA_32BIT: u8 = 0x20;
+pub const ADV_TYPE_SERVICE_DATA_128BIT: u8 = 0x21;
 pub const ADV_TYPE_MANUFACTURER_SPECIFIC: u8 = 0xFF;
+
+// Advertising Data Flags
+pub const ADV_FLAG_LE_LIMITED_DISC: u8 = 0x01;
+pub const ADV_FLAG_LE_GENERAL_DISC: u8 = 0x02;
+pub const ADV_FLAG_BR_EDR_NOT_SUPPORTED: u8 = 0x04;
+
+// Advertising Types
+pub const ADV_IND: u8 = 0x00;
+pub const ADV_DIRECT_IND: u8 = 0x01;
+pub const ADV_SCAN_IND: u8 = 0x02;
+pub const ADV_NONCONN_IND: u8 = 0x03;
+pub const ADV_DIRECT_IND_LOW_DUTY: u8 = 0x04;
+
+// Periodic Advertising Data operations
+pub const PERIODIC_ADV_DATA_INTERMEDIATE: u8 = 0x00;
+pub const PERIODIC_ADV_DATA_FIRST: u8 = 0x01;
+pub const PERIODIC_ADV_DATA_LAST: u8 = 0x02;
+pub const PERIODIC_ADV_DATA_COMPLETE: u8 = 0x03;
+
+// Periodic Advertising Data limits
+pub const PERIODIC_ADV_DATA_FRAGMENT_LEN: usize = 252;
+pub const PERIODIC_ADV_DATA_MAX_LEN: usize = 1650;
@@ -1,7 +1,15 @@
 pub mod adapter;
+pub mod advertising;
 pub mod constants;
 pub mod types;
 
+#[cfg(test)]
+mod tests;
+
 pub use adapter::GapAdapter;
+pub use advertising::{
+    AdStructure, AdvertisingConfig, AdvertisingData, AdvertisingDataBuilder, AdvertisingDataError,
+    PeriodicAdvertisingParams, ScanResponseBuilder,
+};
 pub use constants::*;
 pub use types::*;
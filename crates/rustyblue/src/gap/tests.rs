@@ -0,0 +1,196 @@
+//! Unit tests for GAP functionality
+
+use super::*;
+use crate::hci::{HciCommand, LeAdvertisingReport};
+use crate::uuid::Uuid;
+
+#[test]
+fn test_device_from_advertising_report() {
+    #[rustfmt::skip]
+    let data = vec![
+        0x02, ADV_TYPE_FLAGS, 0x06,
+        0x05, ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE, 0x0D, 0x18, 0x0F, 0x18,
+        0x05, ADV_TYPE_COMPLETE_LOCAL_NAME, b'T', b'e', b's', b't',
+        0x02, ADV_TYPE_TX_POWER_LEVEL, 0xF6,
+        0x05, ADV_TYPE_MANUFACTURER_SPECIFIC, 0x4C, 0x00, 0x01, 0x02,
+    ];
+    let report = LeAdvertisingReport {
+        event_type: 0x00,
+        address_type: 0x01,
+        address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        data_length: data.len() as u8,
+        data,
+        rssi: Some(-60),
+    };
+
+    let device = Device::from_advertising_report(&report);
+
+    assert_eq!(device.address, BdAddr::new(report.address));
+    assert_eq!(device.address_type, AddressType::Random);
+    assert_eq!(device.rssi, Some(-60));
+    assert_eq!(device.tx_power, Some(-10));
+    assert_eq!(device.flags, Some(0x06));
+    assert_eq!(device.local_name(), Some("Test"));
+    assert_eq!(
+        device.manufacturer_data(),
+        Some((0x004C, &[0x01, 0x02][..]))
+    );
+    assert_eq!(
+        device.service_uuids(),
+        vec![Uuid::from_u16(0x180D), Uuid::from_u16(0x180F)]
+    );
+    assert_eq!(
+        device.connection_state,
+        crate::gatt::ConnectionState::Disconnected
+    );
+    assert!(device.last_seen.is_some());
+    assert!(device.ad_data.is_some());
+}
+
+#[test]
+fn test_advertising_data_prefers_complete_name() {
+    #[rustfmt::skip]
+    let data = [
+        0x03, ADV_TYPE_COMPLETE_LOCAL_NAME, b'A', b'B',
+        0x02, ADV_TYPE_SHORT_LOCAL_NAME, b'A',
+    ];
+
+    let ad = AdvertisingData::parse(&data);
+
+    assert_eq!(ad.local_name(), Some("AB"));
+    assert!(ad.complete_local_name);
+    assert_eq!(ad.structures.len(), 2);
+}
+
+#[test]
+fn test_advertising_data_keeps_all_manufacturer_data() {
+    #[rustfmt::skip]
+    let data = vec![
+        0x05, ADV_TYPE_MANUFACTURER_SPECIFIC, 0x59, 0x00, 0x01, 0x02,
+        0x04, ADV_TYPE_MANUFACTURER_SPECIFIC, 0x4C, 0x00, 0x03,
+    ];
+
+    let ad = AdvertisingData::parse(&data);
+    assert_eq!(
+        ad.manufacturer_data,
+        vec![(0x0059, vec![0x01, 0x02]), (0x004C, vec![0x03])]
+    );
+
+    // The single entry accessor returns the first entry
+    assert_eq!(ad.manufacturer_data(), Some((0x0059, &[0x01, 0x02][..])));
+    assert_eq!(ad.manufacturer_data_for_company(0x004C), Some(&[0x03][..]));
+    assert_eq!(ad.manufacturer_data_for_company(0x0006), None);
+
+    // Every entry is encoded again
+    assert_eq!(ad.to_bytes(), data);
+}
+
+#[test]
+fn test_address_type_hci_conversion() {
+    for byte in 0x00..=0x03 {
+        let addr_type = AddressType::from_hci_byte(byte).unwrap();
+        assert_eq!(addr_type.to_hci_byte(), byte);
+        assert_ne!(addr_type.is_public(), addr_type.is_random());
+    }
+    assert_eq!(AddressType::from_hci_byte(0x00), Some(AddressType::Public));
+    assert_eq!(AddressType::from_hci_byte(0x01), Some(AddressType::Random));
+    assert_eq!(AddressType::from_hci_byte(0x04), None);
+
+    assert!(AddressType::PublicIdentity.is_public());
+    assert!(AddressType::RandomIdentity.is_random());
+
+    let (addr, addr_type) = BdAddr::new_with_type([1, 2, 3, 4, 5, 6], AddressType::Random);
+    assert_eq!(addr, BdAddr::new([1, 2, 3, 4, 5, 6]));
+    assert_eq!(addr_type, AddressType::Random);
+}
+
+#[test]
+fn test_advertising_data_to_bytes_round_trip() {
+    let data = AdvertisingData {
+        flags: Some(0x06),
+        local_name: Some("Sensor".into()),
+        complete_local_name: true,
+        tx_power: Some(-4),
+        service_uuids: vec![Uuid::from_u16(0x180D), Uuid::from_u16(0x180F)],
+        manufacturer_data: vec![(0x004C, vec![0x01, 0x02])],
+        ..AdvertisingData::default()
+    };
+
+    let bytes = data.to_bytes();
+    #[rustfmt::skip]
+    let expected = vec![
+        0x02, ADV_TYPE_FLAGS, 0x06,
+        0x05, ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE, 0x0D, 0x18, 0x0F, 0x18,
+        0x07, ADV_TYPE_COMPLETE_LOCAL_NAME, b'S', b'e', b'n', b's', b'o', b'r',
+        0x02, ADV_TYPE_TX_POWER_LEVEL, 0xFC,
+        0x05, ADV_TYPE_MANUFACTURER_SPECIFIC, 0x4C, 0x00, 0x01, 0x02,
+    ];
+    assert_eq!(bytes, expected);
+
+    let parsed = AdvertisingData::parse(&bytes);
+    assert_eq!(parsed.flags, data.flags);
+    assert_eq!(parsed.local_name(), Some("Sensor"));
+    assert!(parsed.complete_local_name);
+    assert_eq!(parsed.tx_power, Some(-4));
+    assert_eq!(parsed.service_uuids, data.service_uuids);
+    assert_eq!(
+        parsed.manufacturer_data(),
+        Some((0x004C, &[0x01, 0x02][..]))
+    );
+}
+
+#[test]
+fn test_advertising_data_builder_packs_and_pads() {
+    let builder = AdvertisingDataBuilder::new()
+        .set_flags(ADV_FLAG_LE_GENERAL_DISC | ADV_FLAG_BR_EDR_NOT_SUPPORTED)
+        .and_then(|b| b.add_service_uuid_16(0x180D))
+        .and_then(|b| b.add_service_uuid_16(0x180F))
+        .and_then(|b| b.set_tx_power(-4))
+        .unwrap();
+    assert_eq!(builder.len(), 12);
+
+    let data = builder.build();
+    #[rustfmt::skip]
+    let expected = [
+        0x02, ADV_TYPE_FLAGS, 0x06,
+        0x05, ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE, 0x0D, 0x18, 0x0F, 0x18,
+        0x02, ADV_TYPE_TX_POWER_LEVEL, 0xFC,
+    ];
+    assert_eq!(&data[..12], &expected[..]);
+    assert!(data[12..].iter().all(|&b| b == 0));
+
+    let builder = builder.set_local_name("A very long device name", true);
+    assert_eq!(builder, Err(AdvertisingDataError::Overflow));
+}
+
+#[test]
+fn test_scan_response_builder_command() {
+    let uuid = Uuid::from_bytes_le([
+        0xF0, 0xDE, 0xBC, 0x9A, 0x78, 0x56, 0x34, 0x12, 0xF0, 0xDE, 0xBC, 0x9A, 0x78, 0x56, 0x34,
+        0x12,
+    ]);
+    let response = ScanResponseBuilder::new()
+        .add_service_uuid_128(&uuid)
+        .and_then(|b| b.set_manufacturer_data(0x004C, &[0x01]))
+        .unwrap();
+    assert_eq!(response.len(), 18 + 5);
+
+    match response.command() {
+        HciCommand::LeSetScanResponseData {
+            scan_response_data_length,
+            scan_response_data,
+        } => {
+            assert_eq!(scan_response_data_length, 23);
+            assert_eq!(scan_response_data[0], 17);
+            assert_eq!(scan_response_data[1], ADV_TYPE_128BIT_SERVICE_UUID_COMPLETE);
+            assert_eq!(scan_response_data[2], 0xF0);
+            assert_eq!(&scan_response_data[18..23], &[0x04, 0xFF, 0x4C, 0x00, 0x01]);
+        }
+        other => panic!("unexpected command {:?}", other),
+    }
+
+    assert_eq!(
+        response.set_manufacturer_data(0x004C, &[0; 8]),
+        Err(AdvertisingDataError::Overflow)
+    );
+}
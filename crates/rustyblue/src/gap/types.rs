@@ -1,5 +1,10 @@
+use crate::gap::advertising::AdvertisingData;
 use crate::gap::constants::*;
+use crate::gatt::ConnectionState;
+use crate::hci::LeAdvertisingReport;
+use crate::uuid::Uuid;
 use std::fmt;
+use std::time::Instant;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
@@ -30,14 +35,58 @@ pub enum AuthenticationMode {
     BondingAuthentication,
 }
 
+/// Type of a Bluetooth device address as used in HCI commands and events
+///
+/// A public address is assigned by the IEEE and never changes. A random
+/// address is chosen by the device and is either static (fixed until the
+/// next power cycle) or private (resolvable with the device's IRK, or
+/// non-resolvable). The address bits tell the random subtypes apart; this
+/// type only records whether the address is public or random.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressType {
+    /// Public device address (HCI value 0x00)
     Public,
+    /// Random device address, static or private (HCI value 0x01)
     Random,
+    /// Public identity address the controller resolved from a resolvable
+    /// private address (HCI value 0x02)
     PublicIdentity,
+    /// Random static identity address the controller resolved from a
+    /// resolvable private address (HCI value 0x03)
     RandomIdentity,
 }
 
+impl AddressType {
+    /// Convert an HCI address type byte, or `None` if it is not valid
+    ///
+    /// Unlike the `From<u8>` conversion, unknown values are not mapped to
+    /// `Public`.
+    pub fn from_hci_byte(byte: u8) -> Option<AddressType> {
+        match byte {
+            PUBLIC_DEVICE_ADDRESS => Some(AddressType::Public),
+            RANDOM_DEVICE_ADDRESS => Some(AddressType::Random),
+            PUBLIC_IDENTITY_ADDRESS => Some(AddressType::PublicIdentity),
+            RANDOM_IDENTITY_ADDRESS => Some(AddressType::RandomIdentity),
+            _ => None,
+        }
+    }
+
+    /// Get the HCI address type byte
+    pub fn to_hci_byte(&self) -> u8 {
+        (*self).into()
+    }
+
+    /// Check whether the address is a public address
+    pub fn is_public(&self) -> bool {
+        matches!(self, AddressType::Public | AddressType::PublicIdentity)
+    }
+
+    /// Check whether the address is a random address
+    pub fn is_random(&self) -> bool {
+        matches!(self, AddressType::Random | AddressType::RandomIdentity)
+    }
+}
+
 impl From<u8> for AddressType {
     fn from(value: u8) -> Self {
         match value {
@@ -62,6 +111,7 @@ impl From<AddressType> for u8 {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BdAddr {
     pub bytes: [u8; 6],
 }
@@ -71,6 +121,11 @@ impl BdAddr {
         Self { bytes }
     }
 
+    /// Create an address paired with its type, as connection targets need
+    pub fn new_with_type(bytes: [u8; 6], addr_type: AddressType) -> (BdAddr, AddressType) {
+        (Self::new(bytes), addr_type)
+    }
+
     pub fn from_slice(slice: &[u8]) -> Option<Self> {
         if slice.len() >= 6 {
             let mut bytes = [0u8; 6];
@@ -109,10 +164,16 @@ pub struct Device {
     pub rssi: Option<i8>,
     pub tx_power: Option<i8>,
     pub manufacturer_data: Option<Vec<u8>>,
-    pub service_uuids: Vec<crate::gatt::Uuid>,
-    pub service_data: Vec<(crate::gatt::Uuid, Vec<u8>)>,
+    pub service_uuids: Vec<Uuid>,
+    pub service_data: Vec<(Uuid, Vec<u8>)>,
     pub appearance: Option<u16>,
     pub flags: Option<u8>,
+    /// Typed advertising data from the most recent advertising report
+    pub ad_data: Option<AdvertisingData>,
+    /// Current connection state of the device
+    pub connection_state: ConnectionState,
+    /// When the device was last seen advertising
+    pub last_seen: Option<Instant>,
 }
 
 impl Device {
@@ -128,6 +189,86 @@ impl Device {
             service_data: Vec::new(),
             appearance: None,
             flags: None,
+            ad_data: None,
+            connection_state: ConnectionState::Disconnected,
+            last_seen: None,
+        }
+    }
+
+    /// Create a device from an LE advertising report
+    pub fn from_advertising_report(report: &LeAdvertisingReport) -> Self {
+        let mut device = Self::new(
+            BdAddr::new(report.address),
+            AddressType::from(report.address_type),
+        );
+        device.update_from_advertising_report(report);
+        device
+    }
+
+    /// Update the device with the contents of a new advertising report
+    ///
+    /// Fields that are absent from the report keep their previous values, so
+    /// a scan response does not erase what the advertisement carried.
+    pub fn update_from_advertising_report(&mut self, report: &LeAdvertisingReport) {
+        if report.rssi.is_some() {
+            self.rssi = report.rssi;
+        }
+        self.last_seen = Some(Instant::now());
+
+        let ad = AdvertisingData::parse(&report.data);
+
+        if let Some(name) = &ad.local_name {
+            self.name = Some(name.clone());
+        }
+        if let Some(tx_power) = ad.tx_power {
+            self.tx_power = Some(tx_power);
+        }
+        if let Some((company_id, data)) = ad.manufacturer_data() {
+            let mut raw = company_id.to_le_bytes().to_vec();
+            raw.extend_from_slice(data);
+            self.manufacturer_data = Some(raw);
+        }
+        for uuid in &ad.service_uuids {
+            if !self.service_uuids.contains(uuid) {
+                self.service_uuids.push(*uuid);
+            }
+        }
+        for entry in &ad.service_data {
+            self.service_data.retain(|(uuid, _)| *uuid != entry.0);
+            self.service_data.push(entry.clone());
+        }
+        if let Some(appearance) = ad.appearance {
+            self.appearance = Some(appearance);
+        }
+        if let Some(flags) = ad.flags {
+            self.flags = Some(flags);
+        }
+
+        self.ad_data = Some(ad);
+    }
+
+    /// Get the advertised local name of the device
+    pub fn local_name(&self) -> Option<&str> {
+        self.ad_data
+            .as_ref()
+            .and_then(|ad| ad.local_name())
+            .or(self.name.as_deref())
+    }
+
+    /// Get the manufacturer specific data as (company identifier, payload)
+    pub fn manufacturer_data(&self) -> Option<(u16, &[u8])> {
+        if let Some(data) = self.ad_data.as_ref().and_then(|ad| ad.manufacturer_data()) {
+            return Some(data);
         }
+
+        match &self.manufacturer_data {
+            Some(raw) if raw.len() >= 2 => Some((u16::from_le_bytes([raw[0], raw[1]]), &raw[2..])),
+            _ => None,
+        }
+    }
+
+    /// Get all service UUIDs advertised by the device
+    pub fn service_uuids(&self) -> Vec<Uuid> {
+        self.service_uuids.clone()
     }
 }
@@ -3,32 +3,45 @@
 //! This module provides a client for interacting with GATT servers.
 
 use crate::att::{
-    AttClient, AttError, AttErrorCode, AttPermissions, AttResult, AttributeData,
-    ExecuteWriteRequest, ExecuteWriteResponse, FindByTypeValueRequest, FindByTypeValueResponse,
-    FindInformationRequest, HandleUuidPair, HandleValueConfirmation, HandleValueIndication,
-    HandleValueNotification, PrepareWriteRequest, PrepareWriteResponse, ReadBlobRequest,
-    ReadBlobResponse, ReadByGroupTypeRequest, ReadByTypeRequest, ReadMultipleRequest,
-    ReadMultipleResponse, ReadRequest, ReadResponse, SecurityLevel, WriteRequest, ATT_CID,
-    ATT_DEFAULT_MTU, ATT_HANDLE_MAX, ATT_HANDLE_MIN, ATT_MAX_MTU, CHARACTERISTIC_UUID,
-    CLIENT_CHAR_CONFIG_UUID, PRIMARY_SERVICE_UUID,
+    AttClient, AttError, AttErrorCode, AttResult, ATT_HANDLE_MAX, ATT_HANDLE_MIN, ATT_MAX_MTU,
+    CHARACTERISTIC_UUID, CHAR_EXTENDED_PROPS_UUID, CHAR_FORMAT_UUID, CLIENT_CHAR_CONFIG_UUID,
+    PRIMARY_SERVICE_UUID, SECONDARY_SERVICE_UUID,
 };
 use crate::error::Error;
-use crate::gap::BdAddr;
-use crate::gatt::server::Descriptor;
-use crate::gatt::types::{Characteristic, CharacteristicProperty, Service, Uuid};
+use crate::gap::{AdvertisingData, BdAddr};
+use crate::gatt::types::{
+    Characteristic, CharacteristicExtendedProperties, CharacteristicPresentationFormat,
+    CharacteristicProperty, Descriptor, DiscoveryPhase, DiscoveryProgress, FullCharacteristic,
+    FullService, PhyPreference, PhyType, Service, Uuid,
+};
 use crate::hci::constants::{
-    EVT_CMD_COMPLETE, EVT_CMD_STATUS, EVT_DISCONN_COMPLETE, EVT_LE_CONN_COMPLETE,
-    EVT_LE_META_EVENT, OCF_LE_CREATE_CONNECTION, OCF_LE_SET_SCAN_PARAMETERS, OGF_LE,
+    hci_opcode, EVT_CMD_STATUS, EVT_DATA_BUFFER_OVERFLOW, EVT_DISCONN_COMPLETE,
+    EVT_LE_CONN_COMPLETE, EVT_LE_META_EVENT, EVT_LE_PERIODIC_ADV_SYNC_ESTABLISHED,
+    EVT_LE_PHY_UPDATE_COMPLETE, OCF_LE_CREATE_CONNECTION, OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC,
+    OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC_CANCEL, OCF_LE_SET_SCAN_PARAMETERS, OGF_LE,
 };
-use crate::hci::{HciCommand, HciEvent, HciSocket};
-use crate::l2cap::{/*L2capError,*/ ConnectionType, L2capManager};
-use log::{debug, error, info, trace, warn};
-use std::collections::HashMap;
+#[cfg(feature = "tokio")]
+use crate::hci::HciSocketAsync;
+use crate::hci::{HciCommand, HciEvent, HciPacket, HciSocket, LeAdvertisingReport};
+use crate::l2cap::constants::L2CAP_RTX_TIMEOUT_MS;
+use crate::l2cap::{/*L2capError,*/ ConnectionParameterUpdate, L2capManager};
+use crate::logging::{debug, warn};
+use crate::smp::{SmpEvent, SmpResult};
+#[cfg(feature = "tokio")]
+use futures_core::Stream;
+#[cfg(feature = "tokio")]
 use std::collections::VecDeque;
-use std::io::{Cursor, Read};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
 use std::time::Duration;
 use std::time::Instant;
+#[cfg(feature = "tokio")]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 /// Error types specific to GATT operations
 #[derive(Debug, thiserror::Error)]
@@ -36,6 +49,9 @@ pub enum GattError {
     #[error("HCI error: {0}")]
     HciError(String),
 
+    #[error("HCI I/O error: {0}")]
+    HciIo(#[source] std::io::Error),
+
     #[error("Device not connected")]
     NotConnected,
 
@@ -55,10 +71,112 @@ pub enum GattError {
     InvalidData,
 
     #[error("ATT error: {0}")]
-    AttError(#[from] AttError),
+    AttError(AttError),
 
     #[error("L2CAP error: {0}")]
     L2capError(String),
+
+    #[error("Waiting for the link to be encrypted")]
+    EncryptionPending,
+
+    #[error("An indication is already awaiting confirmation")]
+    Busy,
+
+    #[error("Value of {value_len} bytes exceeds the maximum of {max_len} bytes")]
+    MtuExceeded { value_len: usize, max_len: usize },
+
+    #[error("Encryption required")]
+    EncryptionRequired,
+
+    #[error("Invalid parameters: {0}")]
+    InvalidParameters(String),
+
+    #[error("Authentication required")]
+    AuthenticationRequired,
+
+    #[error("Authorization required")]
+    AuthorizationRequired,
+
+    #[error("Write to handle 0x{handle:04X} failed: {source}")]
+    WriteFailed {
+        handle: u16,
+        #[source]
+        source: Box<GattError>,
+    },
+}
+
+impl GattError {
+    /// Whether the connection can't be used after this error
+    ///
+    /// Timeouts and interrupted or would-block reads are transient, so the
+    /// caller can retry. A closed HCI socket, a lost connection or a
+    /// client in the wrong state are fatal and the connection should be
+    /// torn down.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            GattError::Timeout | GattError::Busy | GattError::EncryptionPending => false,
+            GattError::HciIo(err) => !matches!(
+                err.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::TimedOut
+            ),
+            GattError::NotConnected | GattError::AttError(AttError::InvalidState) => true,
+            GattError::WriteFailed { source, .. } => source.is_fatal(),
+            _ => false,
+        }
+    }
+}
+
+impl From<AttError> for GattError {
+    fn from(err: AttError) -> Self {
+        match err {
+            AttError::Busy => GattError::Busy,
+            AttError::Timeout => GattError::Timeout,
+            AttError::InsufficientEncryption
+            | AttError::Protocol(AttErrorCode::InsufficientEncryption, _) => {
+                GattError::EncryptionRequired
+            }
+            AttError::InsufficientAuthentication
+            | AttError::Protocol(AttErrorCode::InsufficientAuthentication, _) => {
+                GattError::AuthenticationRequired
+            }
+            AttError::InsufficientAuthorization
+            | AttError::Protocol(AttErrorCode::InsufficientAuthorization, _) => {
+                GattError::AuthorizationRequired
+            }
+            AttError::ReadNotPermitted | AttError::WriteNotPermitted => GattError::NotPermitted,
+            AttError::UnexpectedResponse | AttError::UnknownResponse(_) => GattError::InvalidData,
+            AttError::ChannelNotFound | AttError::L2capDisconnected => GattError::NotConnected,
+            AttError::L2capMtuExceeded { requested, allowed } => GattError::MtuExceeded {
+                value_len: requested,
+                max_len: allowed,
+            },
+            AttError::L2capError(err) => GattError::L2capError(err.to_string()),
+            AttError::Protocol(..)
+            | AttError::AttributeNotFound
+            | AttError::InvalidHandle(_)
+            | AttError::InvalidPdu
+            | AttError::InvalidOffset(_)
+            | AttError::InvalidAttributeValueLength
+            | AttError::InsufficientEncryptionKeySize
+            | AttError::AttributeNotLong
+            | AttError::PrepareQueueFull
+            | AttError::Unlikely
+            | AttError::RequestNotSupported
+            | AttError::UnsupportedGroupType
+            | AttError::InsufficientResources
+            | AttError::DatabaseOutOfSync
+            | AttError::ValueNotAllowed
+            | AttError::ApplicationError(_)
+            | AttError::CommonProfileError(_)
+            | AttError::InvalidOpcode(_)
+            | AttError::UnsupportedOpcode(_)
+            | AttError::InvalidParameter(_)
+            | AttError::InvalidState
+            | AttError::Unknown(_) => GattError::AttError(err),
+        }
+    }
 }
 
 impl From<Error> for GattError {
@@ -73,7 +191,7 @@ impl From<Error> for GattError {
 }
 
 /// Defines the connection state of a GATT client
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionState {
     Disconnected,
     Connecting,
@@ -81,6 +199,165 @@ pub enum ConnectionState {
     Disconnecting,
 }
 
+/// Maximum number of entries kept in the connection history
+pub const CONNECTION_HISTORY_LEN: usize = 50;
+
+/// A connection state transition, passed to the connection callback and
+/// recorded for diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionEvent {
+    /// Previous and new connection state
+    pub transition: (ConnectionState, ConnectionState),
+    /// When the transition happened
+    pub timestamp: Instant,
+    /// HCI connection handle at the time of the transition
+    pub handle: u16,
+    /// Disconnect reason from a Disconnection Complete event
+    pub reason: Option<u8>,
+    /// Status from an LE Connection Complete event
+    pub status: Option<u8>,
+    /// Address of the remote device, if known
+    pub addr: Option<BdAddr>,
+}
+
+impl ConnectionEvent {
+    /// Get the new connection state
+    pub fn state(&self) -> ConnectionState {
+        self.transition.1
+    }
+}
+
+/// Maximum number of events held for `GattClient::process_next_event`
+///
+/// The oldest event is dropped when a new one arrives on a full queue.
+pub const GATT_EVENT_QUEUE_LEN: usize = 64;
+
+/// An event reported by `GattClient::process_next_event`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GattEvent {
+    /// A connection was established, with its HCI handle
+    Connected(u16),
+    /// A connection was closed, with its HCI handle and the reason code
+    Disconnected(u16, u8),
+    /// The server sent a notification
+    Notification { handle: u16, value: Vec<u8> },
+    /// The server sent an indication, which has been confirmed
+    Indication { handle: u16, value: Vec<u8> },
+    /// The server changed the attributes in this handle range
+    ServiceChanged { start: u16, end: u16 },
+}
+
+/// UUID of the Service Changed characteristic
+pub const SERVICE_CHANGED_UUID: u16 = 0x2A05;
+
+/// Value handle assumed for Service Changed before discovery finds it
+pub const SERVICE_CHANGED_HANDLE: u16 = 0x0002;
+
+/// Queue an event for `process_next_event`, dropping the oldest if full
+#[cfg(feature = "tokio")]
+fn push_gatt_event(events: &Mutex<VecDeque<GattEvent>>, event: GattEvent) {
+    let mut events = events.lock().unwrap();
+    if events.len() >= GATT_EVENT_QUEUE_LEN {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+/// Descriptor cache, by service handle and characteristic value handle
+type DescriptorCache = Arc<RwLock<HashMap<(u16, u16), Vec<Descriptor>>>>;
+
+/// Callback for notifications, set with `GattClient::set_notification_callback`
+type NotificationCallback =
+    Arc<Mutex<dyn Fn(u16, &[u8]) -> Result<(), GattError> + Send + Sync + 'static>>;
+
+/// Senders of the notification streams, by characteristic value handle
+#[cfg(feature = "tokio")]
+type NotificationStreams = Arc<Mutex<HashMap<u16, Vec<UnboundedSender<Vec<u8>>>>>>;
+
+/// Notifications of one characteristic, created by
+/// `GattClient::notification_stream`
+///
+/// Implements `futures_core::Stream`, so `futures` and `tokio-stream`
+/// combinators can be used on it.
+///
+/// Dropping the last stream of a characteristic disables its notifications
+/// again. The CCCD write is sent from a separate thread so that dropping
+/// never blocks the runtime.
+#[cfg(feature = "tokio")]
+pub struct NotificationStream {
+    receiver: UnboundedReceiver<Vec<u8>>,
+    value_handle: u16,
+    cccd_handle: u16,
+    att_client: Arc<AttClient>,
+    addr: BdAddr,
+    streams: NotificationStreams,
+}
+
+#[cfg(feature = "tokio")]
+impl NotificationStream {
+    /// Wait for the next notification value
+    ///
+    /// Returns `None` once the client no longer delivers notifications.
+    pub async fn next(&mut self) -> Option<Vec<u8>> {
+        self.receiver.recv().await
+    }
+
+    /// Value handle of the characteristic
+    pub fn value_handle(&self) -> u16 {
+        self.value_handle
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Stream for NotificationStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        self.receiver.close();
+
+        let last = {
+            let mut streams = self.streams.lock().unwrap();
+            let Some(senders) = streams.get_mut(&self.value_handle) else {
+                return;
+            };
+            senders.retain(|sender| !sender.is_closed());
+            if senders.is_empty() {
+                streams.remove(&self.value_handle);
+                true
+            } else {
+                false
+            }
+        };
+
+        if last {
+            let att_client = self.att_client.clone();
+            let (addr, cccd_handle) = (self.addr, self.cccd_handle);
+            std::thread::spawn(move || {
+                // Write to CCCD to disable notifications/indications (0x0000)
+                let _ = att_client.write(addr, cccd_handle, &[0x00, 0x00]);
+            });
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl std::fmt::Debug for NotificationStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotificationStream")
+            .field("value_handle", &self.value_handle)
+            .field("cccd_handle", &self.cccd_handle)
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
 /// LE Connection Complete Event data
 #[derive(Debug, Clone)]
 pub struct LeConnectionComplete {
@@ -137,6 +414,61 @@ impl LeConnectionComplete {
     }
 }
 
+/// Handle identifying a synchronization to a periodic advertising train
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SyncHandle(pub u16);
+
+/// Time to wait for a periodic advertising train to be found
+pub const PERIODIC_SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// LE Periodic Advertising Sync Established Event data
+#[derive(Debug, Clone)]
+pub struct PeriodicAdvertisingSyncEstablished {
+    pub status: u8,
+    pub sync_handle: u16,
+    pub advertising_sid: u8,
+    pub advertiser_address_type: u8,
+    pub advertiser_address: [u8; 6],
+    pub advertiser_phy: u8,
+    pub periodic_advertising_interval: u16,
+    pub advertiser_clock_accuracy: u8,
+}
+
+impl PeriodicAdvertisingSyncEstablished {
+    /// Parse an LE Periodic Advertising Sync Established event from an HCI
+    /// Meta Event
+    pub fn parse(event: &HciEvent) -> Option<Self> {
+        if event.event_code != EVT_LE_META_EVENT || event.parameters.is_empty() {
+            return None;
+        }
+
+        if event.parameters[0] != EVT_LE_PERIODIC_ADV_SYNC_ESTABLISHED {
+            return None;
+        }
+
+        if event.parameters.len() < 16 {
+            return None;
+        }
+
+        let mut advertiser_address = [0u8; 6];
+        advertiser_address.copy_from_slice(&event.parameters[6..12]);
+
+        Some(PeriodicAdvertisingSyncEstablished {
+            status: event.parameters[1],
+            sync_handle: u16::from_le_bytes([event.parameters[2], event.parameters[3]]),
+            advertising_sid: event.parameters[4],
+            advertiser_address_type: event.parameters[5],
+            advertiser_address,
+            advertiser_phy: event.parameters[12],
+            periodic_advertising_interval: u16::from_le_bytes([
+                event.parameters[13],
+                event.parameters[14],
+            ]),
+            advertiser_clock_accuracy: event.parameters[15],
+        })
+    }
+}
+
 /// Disconnection Complete Event data
 #[derive(Debug, Clone)]
 pub struct DisconnectionComplete {
@@ -168,22 +500,234 @@ impl DisconnectionComplete {
     }
 }
 
+/// LE PHY Update Complete Event data
+#[derive(Debug, Clone)]
+pub struct PhyUpdateComplete {
+    pub status: u8,
+    pub connection_handle: u16,
+    pub tx_phy: u8,
+    pub rx_phy: u8,
+}
+
+impl PhyUpdateComplete {
+    /// Parse an LE PHY Update Complete event from an HCI Meta Event
+    pub fn parse(event: &HciEvent) -> Option<Self> {
+        if event.event_code != EVT_LE_META_EVENT || event.parameters.is_empty() {
+            return None;
+        }
+
+        if event.parameters[0] != EVT_LE_PHY_UPDATE_COMPLETE {
+            return None;
+        }
+
+        if event.parameters.len() < 6 {
+            return None;
+        }
+
+        Some(PhyUpdateComplete {
+            status: event.parameters[1],
+            connection_handle: u16::from_le_bytes([event.parameters[2], event.parameters[3]]),
+            tx_phy: event.parameters[4],
+            rx_phy: event.parameters[5],
+        })
+    }
+}
+
 /// Event callback type for connection events
-pub type ConnectionCallback = Box<dyn Fn(ConnectionState, u16) + Send + 'static>;
-
-/// Represents the state of the discovery process.
-#[derive(Debug, Clone, PartialEq)]
-enum DiscoveryState {
-    Idle,
-    DiscoveringServices(usize), // Current service index being processed (for advancing)
-    DiscoveringCharacteristics(usize), // Index of the service whose characteristics are being discovered
-    DiscoveringDescriptors(usize, usize), // Service index, Characteristic index
+pub type ConnectionCallback = Box<dyn Fn(ConnectionEvent) + Send + 'static>;
+
+/// Callback type for PHY changes, called with the TX and RX PHY
+pub type PhyUpdateCallback = Box<dyn Fn(PhyType, PhyType) + Send + 'static>;
+
+/// Callback type for Service Changed indications, called with
+/// `GattEvent::ServiceChanged`
+pub type ServiceChangedCallback = Box<dyn Fn(GattEvent) + Send + 'static>;
+
+/// Parse the affected handle range from a Service Changed value
+pub(crate) fn parse_service_changed(value: &[u8]) -> Option<(u16, u16)> {
+    if value.len() < 4 {
+        return None;
+    }
+    let start = u16::from_le_bytes([value[0], value[1]]);
+    let end = u16::from_le_bytes([value[2], value[3]]);
+    (start <= end).then_some((start, end))
+}
+
+/// Drop cached services overlapping `start..=end`, with their
+/// characteristics and descriptors
+pub(crate) fn invalidate_handle_range(
+    services: &mut Vec<Service>,
+    characteristics: &mut HashMap<u16, Vec<Characteristic>>,
+    descriptors: &mut HashMap<(u16, u16), Vec<Descriptor>>,
+    start: u16,
+    end: u16,
+) {
+    services.retain(|service| {
+        let affected = service.start_handle <= end && service.end_handle >= start;
+        if affected {
+            characteristics.remove(&service.start_handle);
+            descriptors.retain(|&(service_handle, _), _| service_handle != service.start_handle);
+        }
+        !affected
+    });
+}
+
+/// Handles Service Changed indications from either PDU path
+///
+/// Holds shared handles to the caches of a `GattClient`, so it can run in
+/// the ATT client's indication callback.
+struct ServiceChangedHandler {
+    services: Arc<RwLock<Vec<Service>>>,
+    characteristics: Arc<RwLock<HashMap<u16, Vec<Characteristic>>>>,
+    descriptors: DescriptorCache,
+    callback: Arc<Mutex<Option<ServiceChangedCallback>>>,
+    #[cfg(feature = "tokio")]
+    pending_events: Arc<Mutex<VecDeque<GattEvent>>>,
+}
+
+impl ServiceChangedHandler {
+    /// Invalidate the affected services if `handle` is Service Changed
+    fn handle(&self, handle: u16, value: &[u8]) {
+        if handle != self.value_handle() {
+            return;
+        }
+        let Some((start, end)) = parse_service_changed(value) else {
+            warn!("Ignoring malformed Service Changed value {:?}", value);
+            return;
+        };
+        debug!("Service Changed: 0x{:04X}-0x{:04X}", start, end);
+
+        invalidate_handle_range(
+            &mut self.services.write().unwrap(),
+            &mut self.characteristics.write().unwrap(),
+            &mut self.descriptors.write().unwrap(),
+            start,
+            end,
+        );
+
+        let event = GattEvent::ServiceChanged { start, end };
+        #[cfg(feature = "tokio")]
+        push_gatt_event(&self.pending_events, event.clone());
+        if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+            callback(event);
+        }
+    }
+
+    /// Value handle of the discovered Service Changed characteristic
+    fn value_handle(&self) -> u16 {
+        let uuid = Uuid::from_u16(SERVICE_CHANGED_UUID);
+        self.characteristics
+            .read()
+            .unwrap()
+            .values()
+            .flatten()
+            .find(|characteristic| characteristic.uuid == uuid)
+            .map_or(SERVICE_CHANGED_HANDLE, |characteristic| {
+                characteristic.value_handle
+            })
+    }
+}
+
+/// Connection parameters requested by `GattClient::connect_with_params`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionParams {
+    /// Minimum connection interval (1.25 ms units)
+    pub interval_min: u16,
+    /// Maximum connection interval (1.25 ms units)
+    pub interval_max: u16,
+    /// Peripheral latency (number of connection events)
+    pub latency: u16,
+    /// Supervision timeout (10 ms units)
+    pub supervision_timeout: u16,
+}
+
+impl Default for ConnectionParams {
+    /// 20-40 ms interval, no latency and a 2 second supervision timeout
+    fn default() -> Self {
+        Self {
+            interval_min: 0x0010,
+            interval_max: 0x0020,
+            latency: 0,
+            supervision_timeout: 0x00C8,
+        }
+    }
+}
+
+impl ConnectionParams {
+    /// Check the parameters against the ranges allowed by the specification
+    ///
+    /// The supervision timeout must exceed `(1 + latency) * interval_max * 2`.
+    pub fn validate(&self) -> Result<(), GattError> {
+        let update = ConnectionParameterUpdate {
+            interval_min: self.interval_min,
+            interval_max: self.interval_max,
+            peripheral_latency: self.latency,
+            supervision_timeout: self.supervision_timeout,
+        };
+        match update.validation_error() {
+            Some(reason) => Err(GattError::InvalidParameters(reason.into())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// GATT Client configuration
+#[derive(Debug, Clone)]
+pub struct GattClientConfig {
+    /// Run service and characteristic discovery before a lookup by UUID
+    /// when nothing has been discovered yet
+    pub discover_if_needed: bool,
+    /// Scan parameters used by `scan_and_connect`
+    pub scan_and_connect: ScanAndConnectConfig,
+    /// Time a discovery call may take in total, including every ATT
+    /// request it sends
+    pub discovery_timeout: Duration,
+}
+
+impl Default for GattClientConfig {
+    fn default() -> Self {
+        Self {
+            discover_if_needed: true,
+            scan_and_connect: ScanAndConnectConfig::default(),
+            discovery_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Scan parameters used by `GattClient::scan_and_connect`
+#[derive(Debug, Clone)]
+pub struct ScanAndConnectConfig {
+    /// Scan type (0 = passive, 1 = active)
+    pub scan_type: u8,
+    /// Scan interval in 0.625 ms units
+    pub scan_interval: u16,
+    /// Scan window in 0.625 ms units
+    pub scan_window: u16,
+    /// Own address type
+    pub own_address_type: u8,
+    /// Scanning filter policy
+    pub filter_policy: u8,
+    /// Whether the controller should filter duplicate reports
+    pub filter_duplicates: bool,
+}
+
+impl Default for ScanAndConnectConfig {
+    fn default() -> Self {
+        Self {
+            scan_type: 0x01,        // Active scanning
+            scan_interval: 0x0010,  // 10 ms
+            scan_window: 0x0010,    // 10 ms
+            own_address_type: 0x00, // Public address
+            filter_policy: 0x00,    // Accept all
+            filter_duplicates: true,
+        }
+    }
 }
 
 /// A client for interacting with a GATT server
 pub struct GattClient {
     /// HCI socket for connecting to devices
-    socket: HciSocket,
+    socket: Arc<HciSocket>,
     /// L2CAP manager for ATT communication
     l2cap_manager: Arc<L2capManager>,
     /// ATT client for GATT operations
@@ -194,43 +738,57 @@ pub struct GattClient {
     remote_addr: Option<BdAddr>,
     /// Connection state
     state: ConnectionState,
+    /// Client configuration
+    config: GattClientConfig,
+    /// Most recent connection state transitions, oldest first
+    connection_history: Vec<ConnectionEvent>,
+    /// Defer MTU exchange and discovery until the link is encrypted
+    require_encryption_before_discovery: bool,
+    /// Set once SMP reports an encrypted link to the remote device
+    encrypted: Arc<AtomicBool>,
+    /// MTU exchange was deferred until the link is encrypted
+    mtu_exchange_pending: bool,
+    /// Value handles the server refused to write without encryption
+    encryption_required_handles: Mutex<HashSet<u16>>,
 
     /// Cache of discovered services and characteristics
-    services: RwLock<Vec<Service>>,
-    characteristics: RwLock<HashMap<u16, Vec<Characteristic>>>, // Service handle -> characteristics
-
-    /// Add fields for managing discovery state and pending requests if they were part of the deleted code
-    pending_discovery: Mutex<Option<DiscoveryState>>,
-    discovered_services: Mutex<Vec<Service>>, // Need temporary storage during discovery
-    pending_requests: Mutex<VecDeque<PendingRequest>>, // Assuming PendingRequest struct exists or needs definition
-    notification_callbacks: Mutex<HashMap<u16, NotificationCallback>>, // Assuming NotificationCallback type exists
-    indication_callbacks: Mutex<HashMap<u16, IndicationCallback>>, // Assuming IndicationCallback type exists
+    services: Arc<RwLock<Vec<Service>>>,
+    characteristics: Arc<RwLock<HashMap<u16, Vec<Characteristic>>>>, // Service handle -> characteristics
+    descriptors: DescriptorCache, // (Service handle, value handle) -> descriptors
 
     /// Connection event callback
     connection_callback: Option<ConnectionCallback>,
+    /// PHY update callback
+    phy_update_callback: Option<PhyUpdateCallback>,
+    /// TX and RX PHY reported by the last PHY update
+    current_phy: Option<(PhyType, PhyType)>,
+    /// Service Changed callback
+    service_changed_callback: Arc<Mutex<Option<ServiceChangedCallback>>>,
     /// Notification callback
-    notification_callback:
-        Option<Arc<Mutex<dyn Fn(u16, &[u8]) -> Result<(), GattError> + Send + Sync + 'static>>>,
-}
-
-// Define PendingRequest if needed
-struct PendingRequest {
-    opcode: AttOpcode,
-    callback: Option<Box<dyn FnOnce(AttResult<Vec<u8>>) -> AttResult<()>>>, // Assuming AttCallback type
-    timestamp: Instant,
+    notification_callback: Option<NotificationCallback>,
+
+    /// Notifications and indications waiting for `process_next_event`
+    #[cfg(feature = "tokio")]
+    pending_events: Arc<Mutex<VecDeque<GattEvent>>>,
+    /// Socket `process_next_event` waits on, registered on first use
+    #[cfg(feature = "tokio")]
+    async_socket: Option<HciSocketAsync>,
+    /// Senders of the streams returned by `notification_stream`
+    #[cfg(feature = "tokio")]
+    notification_streams: NotificationStreams,
 }
-// Define callback types if needed
-type AttCallback = Box<dyn FnOnce(AttResult<Vec<u8>>) -> AttResult<()>>;
-type NotificationCallback = Box<dyn Fn(Vec<u8>)>;
-type IndicationCallback = Box<dyn Fn(Vec<u8>)>;
 
 impl std::fmt::Debug for GattClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("GattClient")
             .field("connection_handle", &self.connection_handle)
             .field("state", &self.state)
+            .field("connection_history", &self.connection_history)
+            .field("current_phy", &self.current_phy)
+            .field("encrypted", &self.encrypted.load(Ordering::SeqCst))
             .field("services", &self.services)
             .field("characteristics", &self.characteristics)
+            .field("descriptors", &self.descriptors)
             .field(
                 "has_connection_callback",
                 &self.connection_callback.is_some(),
@@ -245,7 +803,10 @@ impl std::fmt::Debug for GattClient {
 
 impl GattClient {
     /// Create a new GATT client using the given HCI socket and L2CAP manager
-    pub fn new(socket: HciSocket, l2cap_manager: Arc<L2capManager>) -> Self {
+    ///
+    /// The socket is shared, so the same one can be handed to `SmpManager`,
+    /// `GapAdapter` or an `EventMultiplexer`.
+    pub fn new(socket: Arc<HciSocket>, l2cap_manager: Arc<L2capManager>) -> Self {
         GattClient {
             socket,
             l2cap_manager,
@@ -253,21 +814,195 @@ impl GattClient {
             connection_handle: None,
             remote_addr: None,
             state: ConnectionState::Disconnected,
-            services: RwLock::new(Vec::new()),
-            characteristics: RwLock::new(HashMap::new()),
-            pending_discovery: Mutex::new(None),
-            discovered_services: Mutex::new(Vec::new()),
-            pending_requests: Mutex::new(VecDeque::new()),
-            notification_callbacks: Mutex::new(HashMap::new()),
-            indication_callbacks: Mutex::new(HashMap::new()),
+            config: GattClientConfig::default(),
+            connection_history: Vec::with_capacity(CONNECTION_HISTORY_LEN),
+            require_encryption_before_discovery: false,
+            encrypted: Arc::new(AtomicBool::new(false)),
+            mtu_exchange_pending: false,
+            encryption_required_handles: Mutex::new(HashSet::new()),
+            services: Arc::new(RwLock::new(Vec::new())),
+            characteristics: Arc::new(RwLock::new(HashMap::new())),
+            descriptors: Arc::new(RwLock::new(HashMap::new())),
             connection_callback: None,
+            phy_update_callback: None,
+            current_phy: None,
+            service_changed_callback: Arc::new(Mutex::new(None)),
             notification_callback: None,
+            #[cfg(feature = "tokio")]
+            pending_events: Arc::new(Mutex::new(VecDeque::new())),
+            #[cfg(feature = "tokio")]
+            async_socket: None,
+            #[cfg(feature = "tokio")]
+            notification_streams: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Create a new GATT client with the given configuration
+    pub fn with_config(
+        socket: Arc<HciSocket>,
+        l2cap_manager: Arc<L2capManager>,
+        config: GattClientConfig,
+    ) -> Self {
+        let mut client = Self::new(socket, l2cap_manager);
+        client.config = config;
+        client
+    }
+
+    /// Set the client configuration
+    pub fn set_config(&mut self, config: GattClientConfig) {
+        self.config = config;
+    }
+
+    /// Get the client configuration
+    pub fn config(&self) -> &GattClientConfig {
+        &self.config
+    }
+
+    /// Wait for the link to be encrypted before exchanging the ATT MTU and
+    /// running discovery
+    ///
+    /// Useful when reconnecting to a bonded device whose GATT server
+    /// requires encryption. Encryption is reported by an `SmpManager` through
+    /// the callback returned by `security_event_handler`.
+    pub fn set_require_encryption_before_discovery(&mut self, enabled: bool) {
+        self.require_encryption_before_discovery = enabled;
+    }
+
+    /// Check if the link to the remote device is known to be encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted.load(Ordering::SeqCst)
+    }
+
+    /// Get the flag that is set once the link is encrypted
+    pub fn encryption_flag(&self) -> Arc<AtomicBool> {
+        self.encrypted.clone()
+    }
+
+    /// Create an SMP event callback that records encryption of this link
+    ///
+    /// Register the returned closure with `SmpManager::set_event_callback`
+    /// after calling `connect`. It sets the encryption flag when
+    /// `SecurityLevelChanged` reports an encrypted security level for the
+    /// device being connected to.
+    pub fn security_event_handler(
+        &self,
+    ) -> impl FnMut(SmpEvent) -> SmpResult<()> + Send + Sync + 'static {
+        let encrypted = self.encrypted.clone();
+        let remote_addr = self.remote_addr;
+        move |event| {
+            if let SmpEvent::SecurityLevelChanged(addr, level) = event {
+                if remote_addr.is_none_or(|remote| remote == addr) {
+                    encrypted.store(level.is_encrypted(), Ordering::SeqCst);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Finish the connection setup that was deferred until encryption
+    ///
+    /// Returns `EncryptionPending` if the link is still not encrypted.
+    fn complete_deferred_setup(&mut self) -> Result<(), GattError> {
+        if !self.mtu_exchange_pending {
+            return Ok(());
+        }
+
+        if !self.is_encrypted() {
+            return Err(GattError::EncryptionPending);
         }
+
+        self.mtu_exchange_pending = false;
+        if let Ok((att_client, addr)) = self.att_peer() {
+            let _ = att_client.exchange_mtu(addr, ATT_MAX_MTU);
+        }
+        Ok(())
     }
 
     /// Set a callback for connection state changes
-    pub fn set_connection_callback(&mut self, callback: ConnectionCallback) {
-        self.connection_callback = Some(callback);
+    ///
+    /// The callback gets the HCI status of failed connections and the
+    /// reason of disconnections along with the new state.
+    pub fn set_connection_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(ConnectionEvent) + Send + 'static,
+    {
+        self.connection_callback = Some(Box::new(callback));
+    }
+
+    /// Set a callback for PHY changes on the connection
+    pub fn set_phy_update_callback(&mut self, callback: PhyUpdateCallback) {
+        self.phy_update_callback = Some(callback);
+    }
+
+    /// Set a callback for Service Changed indications
+    ///
+    /// By the time the callback runs, the cached services overlapping the
+    /// changed range have been dropped; call `discover_services` again to
+    /// refresh them.
+    pub fn set_service_changed_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(GattEvent) + Send + 'static,
+    {
+        *self.service_changed_callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Get the TX and RX PHY of the connection
+    ///
+    /// Only known once the controller has reported a PHY update.
+    pub fn current_phy(&self) -> Option<(PhyType, PhyType)> {
+        self.current_phy
+    }
+
+    /// Request a change of the PHYs used by the connection
+    ///
+    /// Sends LE Set PHY with the preferred PHYs for each direction; an empty
+    /// preference leaves the choice to the controller. The result is
+    /// reported later by an LE PHY Update Complete event, which updates
+    /// `current_phy` and calls the PHY update callback.
+    pub fn request_phy_update(
+        &mut self,
+        tx_phy: PhyPreference,
+        rx_phy: PhyPreference,
+    ) -> Result<(), GattError> {
+        let handle = self.connection_handle.ok_or(GattError::NotConnected)?;
+
+        let mut all_phys = 0u8;
+        if tx_phy.is_empty() {
+            all_phys |= 0x01;
+        }
+        if rx_phy.is_empty() {
+            all_phys |= 0x02;
+        }
+
+        self.socket
+            .send_command(&HciCommand::LeSetPhy {
+                handle,
+                all_phys,
+                tx_phys: tx_phy.bits(),
+                rx_phys: rx_phy.bits(),
+                phy_options: 0x0000,
+            })
+            .map_err(|e| GattError::HciError(e.to_string()))?;
+
+        // For the LE Set PHY command, we get a Command Status event
+        let event = self
+            .socket
+            .read_event()
+            .map_err(|e| GattError::HciError(e.to_string()))?;
+
+        if event.event_code != EVT_CMD_STATUS || event.parameters.len() < 4 {
+            return Err(GattError::HciError("Unexpected event received".into()));
+        }
+
+        let status = event.parameters[0];
+        if status != 0 {
+            return Err(GattError::HciError(format!(
+                "Set PHY command failed with status: {}",
+                status
+            )));
+        }
+
+        Ok(())
     }
 
     /// Set a callback for characteristic notifications
@@ -279,19 +1014,77 @@ impl GattClient {
 
         // If we have an ATT client, set its notification callback
         if let Some(att_client) = &self.att_client {
-            let notification_callback = self.notification_callback.clone().unwrap();
+            self.install_att_callbacks(att_client);
+        }
+    }
 
-            att_client.set_notification_callback(move |handle, value| match notification_callback
-                .lock()
-                .unwrap()(
-                handle, value
-            ) {
+    /// Route notifications and indications from the ATT client
+    ///
+    /// Notifications go to the notification callback and Service Changed
+    /// indications invalidate the cache. With the `tokio` feature both are
+    /// also queued for `process_next_event`, and notifications are sent to
+    /// the streams of their characteristic.
+    fn install_att_callbacks(&self, att_client: &AttClient) {
+        let notification_callback = self.notification_callback.clone();
+        #[cfg(feature = "tokio")]
+        let pending_events = self.pending_events.clone();
+        #[cfg(feature = "tokio")]
+        let notification_streams = self.notification_streams.clone();
+
+        att_client.set_notification_callback(move |_, handle, value| {
+            #[cfg(feature = "tokio")]
+            {
+                push_gatt_event(
+                    &pending_events,
+                    GattEvent::Notification {
+                        handle,
+                        value: value.to_vec(),
+                    },
+                );
+
+                if let Some(senders) = notification_streams.lock().unwrap().get_mut(&handle) {
+                    senders.retain(|sender| sender.send(value.to_vec()).is_ok());
+                }
+            }
+
+            let Some(notification_callback) = &notification_callback else {
+                return Ok(());
+            };
+            match notification_callback.lock().unwrap()(handle, value) {
                 Ok(()) => Ok(()),
                 Err(err) => match err {
                     GattError::AttError(att_err) => Err(att_err),
                     _ => Err(AttError::Unknown("Notification callback error".into())),
                 },
-            });
+            }
+        });
+
+        let service_changed = self.service_changed_handler();
+        #[cfg(feature = "tokio")]
+        let pending_events = self.pending_events.clone();
+        att_client.set_indication_callback(move |_, handle, value| {
+            #[cfg(feature = "tokio")]
+            push_gatt_event(
+                &pending_events,
+                GattEvent::Indication {
+                    handle,
+                    value: value.to_vec(),
+                },
+            );
+            service_changed.handle(handle, value);
+            Ok(())
+        });
+    }
+
+    /// Create a Service Changed handler sharing this client's caches
+    fn service_changed_handler(&self) -> ServiceChangedHandler {
+        ServiceChangedHandler {
+            services: self.services.clone(),
+            characteristics: self.characteristics.clone(),
+            descriptors: self.descriptors.clone(),
+            callback: self.service_changed_callback.clone(),
+            #[cfg(feature = "tokio")]
+            pending_events: self.pending_events.clone(),
         }
     }
 
@@ -300,6 +1093,11 @@ impl GattClient {
         &self.socket
     }
 
+    /// Get a shared handle to the underlying HCI socket
+    pub fn shared_socket(&self) -> Arc<HciSocket> {
+        self.socket.clone()
+    }
+
     /// Get the current connection state
     pub fn connection_state(&self) -> ConnectionState {
         self.state
@@ -310,12 +1108,40 @@ impl GattClient {
         self.connection_handle
     }
 
+    /// Get the address of the device being connected or connected to
+    pub fn peer_address(&self) -> Option<BdAddr> {
+        self.remote_addr
+    }
+
     /// Connect to a Bluetooth LE device with the given address
+    ///
+    /// Uses the default `ConnectionParams`.
     pub fn connect(&mut self, addr: [u8; 6], addr_type: u8) -> Result<(), GattError> {
+        self.connect_with_params(addr, addr_type, ConnectionParams::default())
+    }
+
+    /// Connect to a Bluetooth LE device using the given connection parameters
+    ///
+    /// Fails with `GattError::InvalidParameters` before anything is sent if
+    /// the parameters are out of range.
+    pub fn connect_with_params(
+        &mut self,
+        addr: [u8; 6],
+        addr_type: u8,
+        params: ConnectionParams,
+    ) -> Result<(), GattError> {
         if self.state != ConnectionState::Disconnected {
             return Err(GattError::NotPermitted);
         }
 
+        params.validate()?;
+        let ConnectionParams {
+            interval_min,
+            interval_max,
+            latency,
+            supervision_timeout,
+        } = params;
+
         self.update_state(ConnectionState::Connecting, 0);
 
         // First set LE scan parameters
@@ -361,13 +1187,13 @@ impl GattClient {
                 // Own address type
                 params.push(0x00); // Public
                                    // Connection interval min/max
-                params.extend_from_slice(&0x0010u16.to_le_bytes()); // 20 ms min
-                params.extend_from_slice(&0x0020u16.to_le_bytes()); // 40 ms max
-                                                                    // Connection latency
-                params.extend_from_slice(&0x0000u16.to_le_bytes()); // 0 events
-                                                                    // Supervision timeout
-                params.extend_from_slice(&0x00C8u16.to_le_bytes()); // 2 seconds
-                                                                    // Min/max CE length
+                params.extend_from_slice(&interval_min.to_le_bytes());
+                params.extend_from_slice(&interval_max.to_le_bytes());
+                // Connection latency
+                params.extend_from_slice(&latency.to_le_bytes());
+                // Supervision timeout
+                params.extend_from_slice(&supervision_timeout.to_le_bytes());
+                // Min/max CE length
                 params.extend_from_slice(&0x0000u16.to_le_bytes()); // 0 ms min
                 params.extend_from_slice(&0x0000u16.to_le_bytes()); // 0 ms max
                 params
@@ -408,14 +1234,198 @@ impl GattClient {
         Ok(())
     }
 
-    /// Disconnect from the currently connected device
-    pub fn disconnect(&mut self) -> Result<(), GattError> {
-        if let Some(handle) = self.connection_handle {
-            self.update_state(ConnectionState::Disconnecting, handle);
+    /// Scan for a device advertising `target_uuid` and connect to it
+    ///
+    /// The scan uses the parameters from `GattClientConfig::scan_and_connect`
+    /// and stops as soon as a matching advertising report is received.
+    /// Returns `GattError::Timeout` if no device is found within `timeout`.
+    pub fn scan_and_connect(
+        &mut self,
+        target_uuid: &Uuid,
+        timeout: Duration,
+    ) -> Result<(), GattError> {
+        if self.state != ConnectionState::Disconnected {
+            return Err(GattError::NotPermitted);
+        }
+
+        let scan = self.config.scan_and_connect.clone();
+        self.execute_command(&HciCommand::LeSetScanParameters {
+            scan_type: scan.scan_type,
+            scan_interval: scan.scan_interval,
+            scan_window: scan.scan_window,
+            own_address_type: scan.own_address_type,
+            filter_policy: scan.filter_policy,
+        })?;
+        self.execute_command(&HciCommand::LeSetScanEnable {
+            enable: true,
+            filter_duplicates: scan.filter_duplicates,
+        })?;
+
+        let found = self.scan_for_service(target_uuid, timeout);
+
+        // Always stop scanning, even if reading events failed
+        self.execute_command(&HciCommand::LeSetScanEnable {
+            enable: false,
+            filter_duplicates: false,
+        })?;
+
+        let report = found?.ok_or(GattError::Timeout)?;
+        self.connect(report.address, report.address_type)
+    }
+
+    /// Read advertising reports until one advertises the given service
+    fn scan_for_service(
+        &self,
+        target_uuid: &Uuid,
+        timeout: Duration,
+    ) -> Result<Option<LeAdvertisingReport>, GattError> {
+        let start = Instant::now();
+        while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+            let event = match self.socket.read_event_timeout(Some(remaining)) {
+                Ok(event) => event,
+                Err(crate::error::HciError::ReceiveError(e))
+                    if e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                Err(e) => return Err(GattError::HciError(e.to_string())),
+            };
+
+            // Other events (e.g. command completes) are ignored
+            let Ok(reports) = LeAdvertisingReport::parse_from_event(&event) else {
+                continue;
+            };
+            for report in reports {
+                let ad = AdvertisingData::parse(&report.data);
+                if ad.service_uuids.contains(target_uuid) {
+                    return Ok(Some(report));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Send a command and wait for its Command Complete event
+    ///
+    /// Unrelated events received in the meantime, such as advertising
+    /// reports, are discarded.
+    fn execute_command(&self, command: &HciCommand) -> Result<(), GattError> {
+        let (ogf, ocf) = command.opcode_parts();
+        self.socket
+            .send_command(command)
+            .map_err(|e| GattError::HciError(e.to_string()))?;
+
+        loop {
+            let event = self
+                .socket
+                .read_event_timeout(Some(Duration::from_secs(1)))
+                .map_err(|e| GattError::HciError(e.to_string()))?;
+
+            if event.is_command_complete(ogf, ocf) {
+                return match event.get_status() {
+                    0 => Ok(()),
+                    status => Err(GattError::HciError(format!(
+                        "Command failed with status: {}",
+                        status
+                    ))),
+                };
+            }
+        }
+    }
+
+    /// Synchronize to the periodic advertising train of an advertiser
+    ///
+    /// Sends LE Periodic Advertising Create Sync for the public address
+    /// `address` and advertising set `sid`, then waits up to
+    /// `PERIODIC_SYNC_TIMEOUT` for the sync to be established. Scanning must
+    /// be enabled for the controller to find the advertiser. `skip` is the
+    /// number of periodic advertising events that may be skipped and
+    /// `sync_timeout` is in 10 ms units. Other events received while waiting
+    /// are handled as usual.
+    pub fn sync_to_periodic_advertiser(
+        &mut self,
+        address: BdAddr,
+        sid: u8,
+        skip: u16,
+        sync_timeout: u16,
+    ) -> Result<SyncHandle, GattError> {
+        self.socket
+            .send_command(&HciCommand::LePeriodicAdvertisingCreateSync {
+                options: 0x00,
+                advertising_sid: sid,
+                advertiser_address_type: 0x00, // Public address
+                advertiser_address: address.bytes,
+                skip,
+                sync_timeout,
+                sync_cte_type: 0x00,
+            })
+            .map_err(|e| GattError::HciError(e.to_string()))?;
+
+        let deadline = Instant::now() + PERIODIC_SYNC_TIMEOUT;
+        let mut command_accepted = false;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = match self.socket.read_event_timeout(Some(remaining)) {
+                Ok(event) => event,
+                Err(crate::error::HciError::ReceiveError(io_err))
+                    if io_err.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    // Stop the controller from looking for the advertiser
+                    if command_accepted {
+                        let _ = self.execute_command(&HciCommand::new(
+                            OGF_LE,
+                            OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC_CANCEL,
+                            Vec::new(),
+                        ));
+                    }
+                    return Err(GattError::Timeout);
+                }
+                Err(e) => return Err(GattError::HciError(e.to_string())),
+            };
+
+            // For the Create Sync command, we get a Command Status event
+            if !command_accepted && event.event_code == EVT_CMD_STATUS {
+                if event.parameters.len() < 4 {
+                    return Err(GattError::HciError("Unexpected event received".into()));
+                }
+
+                let opcode = u16::from_le_bytes([event.parameters[2], event.parameters[3]]);
+                if opcode == hci_opcode(OGF_LE, OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC) {
+                    let status = event.parameters[0];
+                    if status != 0 {
+                        return Err(GattError::HciError(format!(
+                            "Create sync command failed with status: {}",
+                            status
+                        )));
+                    }
+                    command_accepted = true;
+                    continue;
+                }
+            }
+
+            if let Some(established) = PeriodicAdvertisingSyncEstablished::parse(&event) {
+                if established.status != 0 {
+                    return Err(GattError::HciError(format!(
+                        "Periodic advertising sync failed with status: {}",
+                        established.status
+                    )));
+                }
+                return Ok(SyncHandle(established.sync_handle));
+            }
+
+            self.handle_event(&event)?;
+        }
+    }
+
+    /// Disconnect from the currently connected device
+    pub fn disconnect(&mut self) -> Result<(), GattError> {
+        if let Some(handle) = self.connection_handle {
+            self.update_state(ConnectionState::Disconnecting, handle);
 
             // Disconnect the ATT client first
-            if let Some(att_client) = &self.att_client {
-                if let Err(e) = att_client.disconnect() {
+            if let Ok((att_client, addr)) = self.att_peer() {
+                if let Err(e) = att_client.disconnect(addr) {
                     return Err(GattError::AttError(e));
                 }
             }
@@ -455,26 +1465,149 @@ impl GattClient {
         }
     }
 
-    /// Process incoming HCI events, handling connection events automatically
-    pub fn process_events(&mut self, timeout: Option<Duration>) -> Result<(), GattError> {
-        // Process ATT client timeouts
+    /// Expire ATT requests that have not been answered in time
+    ///
+    /// Unanswered L2CAP signaling requests are retransmitted, and failed
+    /// once their retries run out.
+    pub fn process_timeouts(&self) -> Result<(), GattError> {
         if let Some(att_client) = &self.att_client {
-            att_client.process_timeouts().map_err(GattError::AttError)?;
+            att_client.process_timeouts().map_err(GattError::from)?;
         }
 
+        self.l2cap_manager
+            .process_timeouts(Duration::from_millis(L2CAP_RTX_TIMEOUT_MS))
+            .map_err(|e| GattError::L2capError(e.to_string()))
+    }
+
+    /// Process incoming HCI events, handling connection events automatically
+    ///
+    /// ACL data is passed to the L2CAP manager, which delivers ATT, SMP and
+    /// signaling traffic to their channels. Errors reading from the socket
+    /// are returned as `GattError::HciIo`. Use `GattError::is_fatal` to
+    /// decide whether to keep processing.
+    #[cfg(feature = "blocking")]
+    pub fn process_events(&mut self, timeout: Option<Duration>) -> Result<(), GattError> {
+        self.process_timeouts()?;
+
         // Process HCI events
-        let event = match self.socket.read_event_timeout(timeout) {
-            Ok(evt) => evt,
-            Err(e) => {
-                if let crate::error::HciError::ReceiveError(io_err) = &e {
-                    if io_err.kind() == std::io::ErrorKind::TimedOut {
-                        return Ok(());
-                    }
+        let packet = match self.socket.read_packet_timeout(timeout) {
+            Ok(packet) => packet,
+            Err(crate::error::HciError::ReceiveError(io_err)) => {
+                if io_err.kind() == std::io::ErrorKind::TimedOut {
+                    return Ok(());
                 }
-                return Err(GattError::HciError(e.to_string()));
+                return Err(GattError::HciIo(io_err));
             }
+            Err(e) => return Err(GattError::HciError(e.to_string())),
         };
 
+        self.handle_packet(&packet)
+    }
+
+    /// Handle an event or ACL data packet read from this client's socket
+    pub fn handle_packet(&mut self, packet: &HciPacket) -> Result<(), GattError> {
+        match packet {
+            HciPacket::Event(event) => self.handle_event(event),
+            HciPacket::Acl(acl) => self
+                .l2cap_manager
+                .handle_acl_data(acl)
+                .map_err(|e| GattError::L2capError(e.to_string())),
+        }
+    }
+
+    /// Wait for the next connection change, notification or indication
+    ///
+    /// Unlike `process_events` this does not block the thread, so it can be
+    /// used in `tokio::select!` alongside other futures. Queued notifications
+    /// and indications are returned first. Otherwise one HCI event or ACL
+    /// packet is read and handled; `Ok(None)` is returned if it doesn't map
+    /// to a `GattEvent`. Dropping the future never loses an event.
+    ///
+    /// Must be called from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub async fn process_next_event(&mut self) -> Result<Option<GattEvent>, GattError> {
+        if let Some(event) = self.pending_events.lock().unwrap().pop_front() {
+            return Ok(Some(event));
+        }
+
+        self.process_timeouts()?;
+
+        if self.async_socket.is_none() {
+            let async_socket = HciSocketAsync::new(self.socket.clone())
+                .map_err(|e| GattError::HciError(e.to_string()))?;
+            self.async_socket = Some(async_socket);
+        }
+
+        let packet = match self.async_socket.as_ref().unwrap().read_packet().await {
+            Ok(packet) => packet,
+            // Neither an event nor ACL data, nothing to report
+            Err(crate::error::HciError::InvalidPacketFormat) => return Ok(None),
+            Err(e) => return Err(GattError::HciError(e.to_string())),
+        };
+
+        self.handle_packet(&packet)?;
+        let HciPacket::Event(event) = packet else {
+            // Notifications and indications were queued by the ATT client
+            return Ok(self.pending_events.lock().unwrap().pop_front());
+        };
+
+        if let Some(conn_complete) = LeConnectionComplete::parse(&event) {
+            if conn_complete.status == 0 {
+                return Ok(Some(GattEvent::Connected(conn_complete.connection_handle)));
+            }
+        } else if let Some(disc_complete) = DisconnectionComplete::parse(&event) {
+            if disc_complete.status == 0 {
+                return Ok(Some(GattEvent::Disconnected(
+                    disc_complete.connection_handle,
+                    disc_complete.reason,
+                )));
+            }
+        }
+
+        Ok(self.pending_events.lock().unwrap().pop_front())
+    }
+
+    /// Process incoming HCI events until `cancel` completes
+    ///
+    /// The async counterpart of calling `process_events` in a loop: events
+    /// are handled as they arrive, invoking the registered connection and
+    /// notification callbacks, without blocking the thread. Any future can
+    /// serve as the cancellation signal, e.g. a `tokio::sync::oneshot`
+    /// receiver or `CancellationToken::cancelled()`.
+    ///
+    /// Must be called from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub async fn process_events_async<C>(&mut self, cancel: C) -> Result<(), GattError>
+    where
+        C: std::future::Future,
+    {
+        tokio::pin!(cancel);
+        loop {
+            tokio::select! {
+                _ = &mut cancel => return Ok(()),
+                result = self.process_next_event() => {
+                    result?;
+                }
+            }
+        }
+    }
+
+    /// Handle an HCI event read from this client's socket
+    ///
+    /// `process_events` calls this for every event it reads. It is public so
+    /// that events read elsewhere, e.g. by a `ConnectionManager`, can be
+    /// dispatched to the client.
+    pub fn handle_event(&mut self, event: &HciEvent) -> Result<(), GattError> {
+        match self.complete_deferred_setup() {
+            Ok(()) | Err(GattError::EncryptionPending) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Let L2CAP reclaim controller buffers and send queued data
+        self.l2cap_manager
+            .handle_hci_event(event)
+            .map_err(|e| GattError::L2capError(e.to_string()))?;
+
         // Handle specific events of interest
         match event.event_code {
             EVT_LE_META_EVENT => {
@@ -485,16 +1618,21 @@ impl GattClient {
                 let subevent = event.parameters[0];
                 match subevent {
                     EVT_LE_CONN_COMPLETE => {
-                        if let Some(conn_complete) = LeConnectionComplete::parse(&event) {
+                        if let Some(conn_complete) = LeConnectionComplete::parse(event) {
                             self.handle_connection_complete(conn_complete)?;
                         }
                     }
+                    EVT_LE_PHY_UPDATE_COMPLETE => {
+                        if let Some(phy_update) = PhyUpdateComplete::parse(event) {
+                            self.handle_phy_update_complete(phy_update);
+                        }
+                    }
                     // Handle other LE meta events as needed
                     _ => {}
                 }
             }
             EVT_DISCONN_COMPLETE => {
-                if let Some(disc_complete) = DisconnectionComplete::parse(&event) {
+                if let Some(disc_complete) = DisconnectionComplete::parse(event) {
                     self.handle_disconnection_complete(disc_complete);
                 }
             }
@@ -517,44 +1655,63 @@ impl GattClient {
 
             // Create ATT client for this connection
             if let Some(addr) = self.remote_addr {
-                let att_client = Arc::new(AttClient::new(addr, self.l2cap_manager.clone()));
-
-                // Set notification callback if we have one
-                if let Some(notification_callback) = &self.notification_callback {
-                    let nc = notification_callback.clone();
-                    att_client.set_notification_callback(move |handle, value| {
-                        match nc.lock().unwrap()(handle, value) {
-                            Ok(()) => Ok(()),
-                            Err(err) => match err {
-                                GattError::AttError(att_err) => Err(att_err),
-                                _ => Err(AttError::Unknown("Notification callback error".into())),
-                            },
-                        }
-                    });
-                }
+                self.l2cap_manager
+                    .connection_established(event.connection_handle, addr);
+
+                let att_client = Arc::new(AttClient::new(self.l2cap_manager.clone()));
+                self.install_att_callbacks(&att_client);
 
                 // Connect ATT channel
                 att_client
-                    .connect(event.connection_handle)
-                    .map_err(GattError::AttError)?;
+                    .connect(addr, event.connection_handle)
+                    .map_err(GattError::from)?;
 
-                // Exchange MTU (request larger MTU if server supports it)
-                let _ = att_client.exchange_mtu(ATT_MAX_MTU);
+                // Exchange MTU (request larger MTU if server supports it),
+                // unless the server must see an encrypted link first
+                if self.require_encryption_before_discovery && !self.is_encrypted() {
+                    self.mtu_exchange_pending = true;
+                } else {
+                    let _ = att_client.exchange_mtu(addr, ATT_MAX_MTU);
+                }
 
                 self.att_client = Some(att_client);
             }
 
-            self.update_state(ConnectionState::Connected, event.connection_handle);
+            self.record_state(
+                ConnectionState::Connected,
+                event.connection_handle,
+                Some(event.status),
+                None,
+            );
         } else {
             // Connection failed
             self.connection_handle = None;
             self.att_client = None;
-            self.update_state(ConnectionState::Disconnected, 0);
+            self.record_state(ConnectionState::Disconnected, 0, Some(event.status), None);
         }
 
         Ok(())
     }
 
+    /// Handle a PHY update complete event
+    fn handle_phy_update_complete(&mut self, event: PhyUpdateComplete) {
+        if event.status != 0 || self.connection_handle != Some(event.connection_handle) {
+            return;
+        }
+
+        let (Some(tx_phy), Some(rx_phy)) = (
+            PhyType::from_u8(event.tx_phy),
+            PhyType::from_u8(event.rx_phy),
+        ) else {
+            return;
+        };
+
+        self.current_phy = Some((tx_phy, rx_phy));
+        if let Some(callback) = &self.phy_update_callback {
+            callback(tx_phy, rx_phy);
+        }
+    }
+
     /// Handle a disconnection complete event
     fn handle_disconnection_complete(&mut self, event: DisconnectionComplete) {
         if let Some(handle) = self.connection_handle {
@@ -562,7 +1719,15 @@ impl GattClient {
                 // This is a disconnection for our connection
                 self.connection_handle = None;
                 self.att_client = None;
-                self.remote_addr = None;
+                self.mtu_exchange_pending = false;
+                self.current_phy = None;
+                self.encrypted.store(false, Ordering::SeqCst);
+                self.encryption_required_handles.lock().unwrap().clear();
+
+                // Tear down channels and notify the layers above L2CAP
+                let _ = self
+                    .l2cap_manager
+                    .handle_connection_closed(event.connection_handle);
 
                 {
                     let mut services = self.services.write().unwrap();
@@ -574,26 +1739,65 @@ impl GattClient {
                     characteristics.clear();
                 }
 
-                self.update_state(ConnectionState::Disconnected, 0);
+                {
+                    let mut descriptors = self.descriptors.write().unwrap();
+                    descriptors.clear();
+                }
+
+                // Report the address of the device that went away
+                self.record_state(ConnectionState::Disconnected, 0, None, Some(event.reason));
+                self.remote_addr = None;
             }
         }
     }
 
+    /// Get the most recent connection state transitions, oldest first
+    ///
+    /// At most `CONNECTION_HISTORY_LEN` transitions are kept.
+    pub fn connection_history(&self) -> &[ConnectionEvent] {
+        &self.connection_history
+    }
+
     /// Update the connection state and call the callback if registered
     fn update_state(&mut self, state: ConnectionState, handle: u16) {
+        self.record_state(state, handle, None, None);
+    }
+
+    /// Update the connection state with the status or reason the controller
+    /// reported, and call the callback if registered
+    fn record_state(
+        &mut self,
+        state: ConnectionState,
+        handle: u16,
+        status: Option<u8>,
+        reason: Option<u8>,
+    ) {
+        let event = ConnectionEvent {
+            transition: (self.state, state),
+            timestamp: Instant::now(),
+            handle,
+            reason,
+            status,
+            addr: self.remote_addr,
+        };
+
+        if self.connection_history.len() == CONNECTION_HISTORY_LEN {
+            self.connection_history.remove(0);
+        }
+        self.connection_history.push(event);
+
         self.state = state;
         if let Some(callback) = &self.connection_callback {
-            callback(state, handle);
+            callback(event);
         }
     }
 
-    /// Discover all services on the connected device
+    /// Discover all primary and secondary services on the connected device
     pub fn discover_services(&mut self) -> Result<Vec<Service>, GattError> {
         if self.state != ConnectionState::Connected {
             return Err(GattError::NotConnected);
         }
-
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        self.complete_deferred_setup()?;
 
         // Clear existing services
         {
@@ -606,26 +1810,84 @@ impl GattClient {
             characteristics.clear();
         }
 
-        // Read all primary services using Read By Group Type Request
+        {
+            let mut descriptors = self.descriptors.write().unwrap();
+            descriptors.clear();
+        }
+
+        let deadline = self.discovery_deadline();
+
+        // Primary services first, then secondary services
         let mut services = Vec::new();
+        let result = self
+            .discover_services_of_type(PRIMARY_SERVICE_UUID, deadline, &mut services)
+            .and_then(|_| {
+                self.discover_services_of_type(SECONDARY_SERVICE_UUID, deadline, &mut services)
+            });
+
+        // Store the discovered services, even if discovery timed out
+        {
+            let mut services_lock = self.services.write().unwrap();
+            *services_lock = services.clone();
+        }
+
+        result.map(|_| services)
+    }
+
+    /// Discover only the secondary services on the connected device
+    ///
+    /// Previously discovered secondary services are replaced in the cache,
+    /// primary services are kept.
+    pub fn discover_secondary_services(&mut self) -> Result<Vec<Service>, GattError> {
+        if self.state != ConnectionState::Connected {
+            return Err(GattError::NotConnected);
+        }
+        self.complete_deferred_setup()?;
+
+        let deadline = self.discovery_deadline();
+        let mut secondary = Vec::new();
+        let result =
+            self.discover_services_of_type(SECONDARY_SERVICE_UUID, deadline, &mut secondary);
+
+        {
+            let mut services = self.services.write().unwrap();
+            services.retain(|service| service.is_primary);
+            services.extend(secondary.iter().cloned());
+        }
+
+        result.map(|_| secondary)
+    }
+
+    /// Read all service declarations of the given type (primary or secondary)
+    ///
+    /// Services are appended to `services` as they are found, so they are
+    /// kept when a later request fails.
+    fn discover_services_of_type(
+        &self,
+        service_type: u16,
+        deadline: Instant,
+        services: &mut Vec<Service>,
+    ) -> Result<(), GattError> {
         let mut start_handle = ATT_HANDLE_MIN;
         let end_handle = ATT_HANDLE_MAX;
 
-        // Iterate through all primary services
+        // Iterate through all services using Read By Group Type Request
         loop {
-            // Read primary services
-            let result = match att_client.read_by_group_type(
-                start_handle,
-                end_handle,
-                &Uuid::from_u16(PRIMARY_SERVICE_UUID),
-            ) {
+            let result = match self.discovery_request(deadline, |att_client, addr| {
+                att_client.read_by_group_type(
+                    addr,
+                    start_handle,
+                    end_handle,
+                    &Uuid::from_u16(service_type),
+                )
+            }) {
                 Ok(result) => result,
                 Err(e) => {
                     // If we get Attribute Not Found, we've read all services
                     if let AttError::AttributeNotFound = e {
                         break;
                     }
-                    return Err(GattError::AttError(e));
+                    return Err(GattError::from(e));
                 }
             };
 
@@ -634,7 +1896,7 @@ impl GattClient {
                 break;
             }
 
-            // Process the discovered services
+            let mut last_end_handle = start_handle;
             for (handle, end_group_handle, value) in result {
                 // Parse the UUID from the value
                 let uuid = if value.len() == 2 {
@@ -645,45 +1907,28 @@ impl GattClient {
                     // 128-bit UUID
                     let mut uuid_bytes = [0u8; 16];
                     uuid_bytes.copy_from_slice(&value[0..16]);
-                    Uuid::from_bytes(&uuid_bytes)
+                    Uuid::from_bytes_le(uuid_bytes)
                 } else {
                     continue; // Invalid UUID length
                 };
 
-                // Create service
-                let service = Service {
+                services.push(Service {
                     uuid,
-                    is_primary: true,
+                    is_primary: service_type == PRIMARY_SERVICE_UUID,
                     start_handle: handle,
                     end_handle: end_group_handle,
-                };
-
-                // Add to our list
-                services.push(service);
-
-                // Update start handle for next iteration
-                if end_group_handle == ATT_HANDLE_MAX {
-                    break;
-                }
-                start_handle = end_group_handle + 1;
+                });
+                last_end_handle = last_end_handle.max(end_group_handle);
             }
 
-            // If we've reached the end, break out
-            if start_handle > end_handle {
+            // Continue after the last group, unless we've reached the end
+            if last_end_handle >= end_handle || last_end_handle < start_handle {
                 break;
             }
+            start_handle = last_end_handle + 1;
         }
 
-        // Now do the same for secondary services if needed
-        // (skipped for brevity)
-
-        // Store the discovered services
-        {
-            let mut services_lock = self.services.write().unwrap();
-            *services_lock = services.clone();
-        }
-
-        Ok(services)
+        Ok(())
     }
 
     /// Discover characteristics for a specific service
@@ -694,35 +1939,56 @@ impl GattClient {
         if self.state != ConnectionState::Connected {
             return Err(GattError::NotConnected);
         }
+        self.complete_deferred_setup()?;
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        let deadline = self.discovery_deadline();
+        self.discover_characteristics_until(service, deadline)
+    }
 
+    /// Discover the characteristics of a service before `deadline`
+    ///
+    /// The characteristics found so far are cached even if a request fails.
+    fn discover_characteristics_until(
+        &self,
+        service: &Service,
+        deadline: Instant,
+    ) -> Result<Vec<Characteristic>, GattError> {
         // Clear existing characteristics for this service
         {
             let mut characteristics = self.characteristics.write().unwrap();
             characteristics.remove(&service.start_handle);
         }
 
+        {
+            let mut descriptors = self.descriptors.write().unwrap();
+            descriptors.retain(|&(service_handle, _), _| service_handle != service.start_handle);
+        }
+
         // Read all characteristics using Read By Type Request
         let mut characteristics = Vec::new();
         let mut start_handle = service.start_handle;
         let end_handle = service.end_handle;
 
         // Iterate through all characteristics
+        let mut outcome = Ok(());
         loop {
             // Read characteristic declarations
-            let result = match att_client.read_by_type(
-                start_handle,
-                end_handle,
-                &Uuid::from_u16(CHARACTERISTIC_UUID),
-            ) {
+            let result = match self.discovery_request(deadline, |att_client, addr| {
+                att_client.read_by_type(
+                    addr,
+                    start_handle,
+                    end_handle,
+                    &Uuid::from_u16(CHARACTERISTIC_UUID),
+                )
+            }) {
                 Ok(result) => result,
                 Err(e) => {
                     // If we get Attribute Not Found, we've read all characteristics
                     if let AttError::AttributeNotFound = e {
                         break;
                     }
-                    return Err(GattError::AttError(e));
+                    outcome = Err(GattError::from(e));
+                    break;
                 }
             };
 
@@ -752,7 +2018,7 @@ impl GattClient {
                     // 128-bit UUID
                     let mut uuid_bytes = [0u8; 16];
                     uuid_bytes.copy_from_slice(&value[3..19]);
-                    Uuid::from_bytes(&uuid_bytes)
+                    Uuid::from_bytes_le(uuid_bytes)
                 } else {
                     continue; // Invalid UUID length
                 };
@@ -784,7 +2050,283 @@ impl GattClient {
             chars_lock.insert(service.start_handle, characteristics.clone());
         }
 
-        Ok(characteristics)
+        outcome.map(|_| characteristics)
+    }
+
+    /// Discover the descriptors of a characteristic
+    ///
+    /// The descriptors lie between the characteristic value and the next
+    /// characteristic declaration, or the end of the service. The
+    /// characteristics of the service should already be discovered so the
+    /// range can be bounded.
+    pub fn discover_descriptors(
+        &mut self,
+        service: &Service,
+        characteristic: &Characteristic,
+    ) -> Result<Vec<Descriptor>, GattError> {
+        if self.state != ConnectionState::Connected {
+            return Err(GattError::NotConnected);
+        }
+        self.complete_deferred_setup()?;
+
+        let deadline = self.discovery_deadline();
+        self.discover_descriptors_until(service, characteristic, deadline)
+    }
+
+    /// Discover the descriptors of a characteristic before `deadline`
+    ///
+    /// The descriptors found so far are cached even if a request fails.
+    fn discover_descriptors_until(
+        &self,
+        service: &Service,
+        characteristic: &Characteristic,
+        deadline: Instant,
+    ) -> Result<Vec<Descriptor>, GattError> {
+        let end_handle = {
+            let characteristics = self.characteristics.read().unwrap();
+            characteristics
+                .get(&service.start_handle)
+                .and_then(|chars| {
+                    chars
+                        .iter()
+                        .map(|c| c.declaration_handle)
+                        .filter(|&handle| handle > characteristic.value_handle)
+                        .min()
+                })
+                .map_or(service.end_handle, |handle| handle - 1)
+        };
+
+        let mut descriptors = Vec::new();
+        let mut start_handle = characteristic.value_handle.saturating_add(1);
+
+        let mut outcome = Ok(());
+        while start_handle != 0 && start_handle <= end_handle {
+            let result = match self.discovery_request(deadline, |att_client, addr| {
+                att_client.find_information(addr, start_handle, end_handle)
+            }) {
+                Ok(result) => result,
+                Err(AttError::AttributeNotFound) => break,
+                Err(e) => {
+                    outcome = Err(GattError::from(e));
+                    break;
+                }
+            };
+
+            let Some(&(last_handle, _)) = result.last() else {
+                break;
+            };
+
+            descriptors.extend(
+                result
+                    .into_iter()
+                    .map(|(handle, uuid)| Descriptor { handle, uuid }),
+            );
+            start_handle = last_handle.wrapping_add(1);
+        }
+
+        {
+            let mut cache = self.descriptors.write().unwrap();
+            cache.insert(
+                (service.start_handle, characteristic.value_handle),
+                descriptors.clone(),
+            );
+        }
+
+        outcome.map(|_| descriptors)
+    }
+
+    /// Discover all services, characteristics and descriptors
+    ///
+    /// Everything found is cached, so later lookups such as the CCCD handle
+    /// used by `enable_notifications` don't need another round trip. The
+    /// discovery timeout covers the whole walk; on timeout whatever was found
+    /// stays cached.
+    pub fn discover_all(&mut self) -> Result<Vec<Service>, GattError> {
+        let deadline = self.discovery_deadline();
+        let services = self.discover_services()?;
+
+        for service in &services {
+            for characteristic in self.discover_characteristics_until(service, deadline)? {
+                self.discover_descriptors_until(service, &characteristic, deadline)?;
+            }
+        }
+
+        Ok(services)
+    }
+
+    /// Discover all services, characteristics and descriptors, reporting progress
+    ///
+    /// Works like `discover_all`, but returns the whole tree and calls
+    /// `progress` once the services are found, after the characteristics of
+    /// each service, and after the descriptors of each service.
+    pub fn discover_all_with_progress<F>(
+        &mut self,
+        progress: F,
+    ) -> Result<Vec<FullService>, GattError>
+    where
+        F: Fn(DiscoveryProgress) + Send,
+    {
+        let deadline = self.discovery_deadline();
+        let services = self.discover_services()?;
+
+        let mut report = DiscoveryProgress {
+            services_found: services.len(),
+            characteristics_found: 0,
+            phase: DiscoveryPhase::Services,
+        };
+        progress(report);
+
+        let mut full_services = Vec::with_capacity(services.len());
+        for (index, service) in services.into_iter().enumerate() {
+            let characteristics = self.discover_characteristics_until(&service, deadline)?;
+            report.characteristics_found += characteristics.len();
+            report.phase = DiscoveryPhase::Characteristics(index);
+            progress(report);
+
+            let mut full_characteristics = Vec::with_capacity(characteristics.len());
+            for characteristic in characteristics {
+                let descriptors =
+                    self.discover_descriptors_until(&service, &characteristic, deadline)?;
+                full_characteristics.push(FullCharacteristic {
+                    characteristic,
+                    descriptors,
+                });
+            }
+            report.phase = DiscoveryPhase::Descriptors;
+            progress(report);
+
+            full_services.push(FullService {
+                service,
+                characteristics: full_characteristics,
+            });
+        }
+
+        Ok(full_services)
+    }
+
+    /// Discover all services, characteristics and descriptors, reporting progress
+    ///
+    /// Async version of `discover_all_with_progress`. The ATT requests
+    /// themselves block, but the task yields to the runtime after each of
+    /// them so other tasks, e.g. a UI showing the progress, keep running.
+    #[cfg(feature = "tokio")]
+    pub async fn discover_all_with_progress_async<F>(
+        &mut self,
+        progress: F,
+    ) -> Result<Vec<FullService>, GattError>
+    where
+        F: Fn(DiscoveryProgress) + Send,
+    {
+        let deadline = self.discovery_deadline();
+        let services = self.discover_services()?;
+
+        let mut report = DiscoveryProgress {
+            services_found: services.len(),
+            characteristics_found: 0,
+            phase: DiscoveryPhase::Services,
+        };
+        progress(report);
+        tokio::task::yield_now().await;
+
+        let mut full_services = Vec::with_capacity(services.len());
+        for (index, service) in services.into_iter().enumerate() {
+            let characteristics = self.discover_characteristics_until(&service, deadline)?;
+            report.characteristics_found += characteristics.len();
+            report.phase = DiscoveryPhase::Characteristics(index);
+            progress(report);
+            tokio::task::yield_now().await;
+
+            let mut full_characteristics = Vec::with_capacity(characteristics.len());
+            for characteristic in characteristics {
+                let descriptors =
+                    self.discover_descriptors_until(&service, &characteristic, deadline)?;
+                full_characteristics.push(FullCharacteristic {
+                    characteristic,
+                    descriptors,
+                });
+                tokio::task::yield_now().await;
+            }
+            report.phase = DiscoveryPhase::Descriptors;
+            progress(report);
+
+            full_services.push(FullService {
+                service,
+                characteristics: full_characteristics,
+            });
+        }
+
+        Ok(full_services)
+    }
+
+    /// Get the time by which a discovery started now has to finish
+    fn discovery_deadline(&self) -> Instant {
+        Instant::now() + self.config.discovery_timeout
+    }
+
+    /// Send an ATT request of a discovery that has to finish by `deadline`
+    ///
+    /// The request may wait at most the time left, and fails with
+    /// `AttError::Timeout` once the deadline has passed.
+    fn discovery_request<T>(
+        &self,
+        deadline: Instant,
+        request: impl FnOnce(&AttClient, BdAddr) -> AttResult<T>,
+    ) -> AttResult<T> {
+        let (att_client, addr) = self.att_peer().map_err(|_| AttError::InvalidState)?;
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(AttError::Timeout);
+        }
+
+        let timeout = att_client.timeout();
+        att_client.set_timeout(timeout.min(remaining));
+        let result = request(att_client, addr);
+        att_client.set_timeout(timeout);
+        result
+    }
+
+    /// Get the cached descriptors of a characteristic
+    ///
+    /// Returns `None` if the descriptors haven't been discovered.
+    pub fn cached_descriptors(&self, characteristic: &Characteristic) -> Option<Vec<Descriptor>> {
+        let descriptors = self.descriptors.read().unwrap();
+        descriptors
+            .iter()
+            .find(|((_, value_handle), _)| *value_handle == characteristic.value_handle)
+            .map(|(_, descriptors)| descriptors.clone())
+    }
+
+    /// Find the Client Characteristic Configuration descriptor of a characteristic
+    ///
+    /// Descriptors cached by `discover_descriptors` are used when available,
+    /// otherwise the server is asked with a Find Information Request.
+    fn cccd_handle(&self, characteristic: &Characteristic) -> Result<u16, GattError> {
+        let cccd_uuid = Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID);
+
+        if let Some(descriptors) = self.cached_descriptors(characteristic) {
+            return descriptors
+                .iter()
+                .find(|descriptor| descriptor.uuid == cccd_uuid)
+                .map(|descriptor| descriptor.handle)
+                .ok_or(GattError::CharacteristicNotFound);
+        }
+
+        let (att_client, addr) = self.att_peer()?;
+        let result = att_client
+            .find_information(
+                addr,
+                characteristic.value_handle + 1,
+                characteristic.value_handle + 10, // Arbitrary range to search
+            )
+            .map_err(GattError::from)?;
+
+        // Look for the CCCD UUID (0x2902)
+        result
+            .iter()
+            .find(|(_, uuid)| uuid == &cccd_uuid)
+            .map(|(handle, _)| *handle)
+            .ok_or(GattError::CharacteristicNotFound)
     }
 
     /// Read a characteristic's value
@@ -800,12 +2342,12 @@ impl GattClient {
             return Err(GattError::NotPermitted);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        let (att_client, addr) = self.att_peer()?;
 
         // Read the characteristic value using ATT Read Request
         let value = att_client
-            .read(characteristic.value_handle)
-            .map_err(GattError::AttError)?;
+            .read(addr, characteristic.value_handle)
+            .map_err(GattError::from)?;
 
         Ok(value)
     }
@@ -824,14 +2366,65 @@ impl GattClient {
             return Err(GattError::NotPermitted);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        let (att_client, addr) = self.att_peer()?;
+        Self::check_value_len(att_client, addr, data)?;
+
+        // Fail early if the server already refused this handle on an
+        // unencrypted link
+        let handle = characteristic.value_handle;
+        if !self.is_encrypted()
+            && self
+                .encryption_required_handles
+                .lock()
+                .unwrap()
+                .contains(&handle)
+        {
+            return Err(GattError::EncryptionRequired);
+        }
 
         // Write the characteristic value using ATT Write Request
-        att_client
-            .write(characteristic.value_handle, data)
-            .map_err(GattError::AttError)?;
+        match att_client
+            .write(addr, handle, data)
+            .map_err(GattError::from)
+        {
+            Err(GattError::EncryptionRequired) => {
+                self.encryption_required_handles
+                    .lock()
+                    .unwrap()
+                    .insert(handle);
+                Err(GattError::EncryptionRequired)
+            }
+            result => result,
+        }
+    }
 
-        Ok(())
+    /// Write a value of any length to a characteristic
+    ///
+    /// Values that do not fit in a single Prepare Write Request
+    /// (`mtu - 5` bytes) are written in chunks with prepared writes and then
+    /// committed. If any chunk is refused, the queued writes are cancelled
+    /// and the error is returned. Shorter values use a plain write.
+    pub fn write_long_characteristic(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+    ) -> Result<(), GattError> {
+        if self.state != ConnectionState::Connected {
+            return Err(GattError::NotConnected);
+        }
+
+        if !characteristic.properties.can_write() {
+            return Err(GattError::NotPermitted);
+        }
+
+        let (att_client, addr) = self.att_peer()?;
+        if data.len() <= att_client.mtu(addr) as usize - 5 {
+            return self.write_characteristic(characteristic, data);
+        }
+
+        att_client
+            .write_long(addr, characteristic.value_handle, data)
+            .map_err(GattError::from)
     }
 
     /// Write to a characteristic without response
@@ -848,20 +2441,46 @@ impl GattClient {
             return Err(GattError::NotPermitted);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        let (att_client, addr) = self.att_peer()?;
+        Self::check_value_len(att_client, addr, data)?;
 
         // Write the characteristic value using ATT Write Command
         att_client
-            .write_command(characteristic.value_handle, data)
-            .map_err(GattError::AttError)?;
+            .write_command(addr, characteristic.value_handle, data)
+            .map_err(GattError::from)?;
 
         Ok(())
     }
 
+    /// Check that a value fits in a single write PDU for the current MTU
+    fn check_value_len(att_client: &AttClient, addr: BdAddr, data: &[u8]) -> Result<(), GattError> {
+        let max_len = att_client.mtu(addr) as usize - 3;
+        if data.len() > max_len {
+            return Err(GattError::MtuExceeded {
+                value_len: data.len(),
+                max_len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Get the ATT client and the address of the connected peer
+    fn att_peer(&self) -> Result<(&Arc<AttClient>, BdAddr), GattError> {
+        match (&self.att_client, self.remote_addr) {
+            (Some(att_client), Some(addr)) => Ok((att_client, addr)),
+            _ => Err(GattError::NotConnected),
+        }
+    }
+
     /// Find a service by UUID
-    pub fn find_service(&self, uuid: &Uuid) -> Option<Service> {
+    ///
+    /// Secondary services are searched too unless `primary_only` is set.
+    pub fn find_service(&self, uuid: &Uuid, primary_only: bool) -> Option<Service> {
         let services = self.services.read().unwrap();
-        services.iter().find(|s| &s.uuid == uuid).cloned()
+        services
+            .iter()
+            .find(|s| &s.uuid == uuid && (s.is_primary || !primary_only))
+            .cloned()
     }
 
     /// Find a characteristic by UUID within a service
@@ -872,6 +2491,114 @@ impl GattClient {
             .and_then(|chars| chars.iter().find(|c| &c.uuid == uuid).cloned())
     }
 
+    /// Find the first cached characteristic with the given UUID in any service
+    ///
+    /// Only the in-memory cache is searched; no ATT requests are sent.
+    pub fn find_characteristic_by_uuid(&self, uuid: &Uuid) -> Option<(Service, Characteristic)> {
+        let services = self.services.read().unwrap();
+        let characteristics = self.characteristics.read().unwrap();
+
+        services.iter().find_map(|service| {
+            characteristics
+                .get(&service.start_handle)
+                .and_then(|chars| chars.iter().find(|c| &c.uuid == uuid))
+                .map(|characteristic| (service.clone(), characteristic.clone()))
+        })
+    }
+
+    /// Find every cached characteristic with the given UUID across all services
+    ///
+    /// Useful when a characteristic appears in multiple service instances.
+    /// Only the in-memory cache is searched; no ATT requests are sent.
+    pub fn find_all_characteristics_by_uuid(&self, uuid: &Uuid) -> Vec<(Service, Characteristic)> {
+        let services = self.services.read().unwrap();
+        let characteristics = self.characteristics.read().unwrap();
+
+        services
+            .iter()
+            .flat_map(|service| {
+                characteristics
+                    .get(&service.start_handle)
+                    .into_iter()
+                    .flatten()
+                    .filter(|c| &c.uuid == uuid)
+                    .map(move |characteristic| (service.clone(), characteristic.clone()))
+            })
+            .collect()
+    }
+
+    /// Read the first characteristic with the given UUID
+    ///
+    /// If nothing has been discovered yet and `discover_if_needed` is set,
+    /// services and characteristics are discovered first.
+    pub fn read_by_uuid(&mut self, uuid: &Uuid) -> Result<Vec<u8>, GattError> {
+        let (_, characteristic) = self.lookup_characteristic(uuid)?;
+        self.read_characteristic(&characteristic)
+    }
+
+    /// Write to the first characteristic with the given UUID
+    ///
+    /// If nothing has been discovered yet and `discover_if_needed` is set,
+    /// services and characteristics are discovered first.
+    pub fn write_by_uuid(&mut self, uuid: &Uuid, data: &[u8]) -> Result<(), GattError> {
+        let (_, characteristic) = self.lookup_characteristic(uuid)?;
+        self.write_characteristic(&characteristic, data)
+    }
+
+    /// Write a value to every characteristic with the given UUID
+    ///
+    /// Useful for characteristics that appear in several service instances.
+    /// Services whose characteristics haven't been discovered are discovered
+    /// first, and characteristics without the write property are skipped.
+    /// Stops at the first failed write with `GattError::WriteFailed`.
+    /// Returns the number of characteristics written.
+    pub fn write_all_by_uuid(&mut self, uuid: &Uuid, value: &[u8]) -> Result<usize, GattError> {
+        let services = self.services.read().unwrap().clone();
+        for service in &services {
+            let cached = self
+                .characteristics
+                .read()
+                .unwrap()
+                .contains_key(&service.start_handle);
+            if !cached {
+                self.discover_characteristics(service)?;
+            }
+        }
+
+        let mut written = 0;
+        for (_, characteristic) in self.find_all_characteristics_by_uuid(uuid) {
+            if !characteristic.properties.can_write() {
+                continue;
+            }
+
+            self.write_characteristic(&characteristic, value)
+                .map_err(|e| GattError::WriteFailed {
+                    handle: characteristic.value_handle,
+                    source: Box::new(e),
+                })?;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    /// Find a characteristic by UUID, discovering the database first if needed
+    fn lookup_characteristic(
+        &mut self,
+        uuid: &Uuid,
+    ) -> Result<(Service, Characteristic), GattError> {
+        let cache_empty = self.services.read().unwrap().is_empty();
+
+        if cache_empty && self.config.discover_if_needed {
+            for service in self.discover_services()? {
+                self.discover_characteristics(&service)?;
+            }
+        }
+
+        self.find_characteristic_by_uuid(uuid)
+            .ok_or(GattError::CharacteristicNotFound)
+    }
+
     /// Enable notifications for a characteristic
     pub fn enable_notifications(&self, characteristic: &Characteristic) -> Result<(), GattError> {
         if self.state != ConnectionState::Connected {
@@ -882,32 +2609,68 @@ impl GattClient {
             return Err(GattError::NotPermitted);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
-
-        // Find the Client Characteristic Configuration descriptor
-        let result = att_client
-            .find_information(
-                characteristic.value_handle + 1,
-                characteristic.value_handle + 10, // Arbitrary range to search
-            )
-            .map_err(GattError::AttError)?;
+        let (att_client, addr) = self.att_peer()?;
 
-        // Look for the CCCD UUID (0x2902)
-        let cccd_handle = result
-            .iter()
-            .find(|(_, uuid)| uuid == &Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID))
-            .map(|(handle, _)| *handle)
-            .ok_or(GattError::CharacteristicNotFound)?;
+        let cccd_handle = self.cccd_handle(characteristic)?;
 
         // Write to CCCD to enable notifications (0x0001)
         att_client
-            .write(cccd_handle, &[0x01, 0x00])
-            .map_err(GattError::AttError)?;
+            .write(addr, cccd_handle, &[0x01, 0x00])
+            .map_err(GattError::from)?;
 
         Ok(())
     }
 
+    /// Receive the notifications of a characteristic as a stream
+    ///
+    /// Notifications are enabled before this returns and disabled again
+    /// when the last stream of the characteristic is dropped. Values are
+    /// delivered while events are processed, e.g. by `process_next_event`
+    /// running in another task.
+    #[cfg(feature = "tokio")]
+    pub fn notification_stream(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<NotificationStream, GattError> {
+        let (att_client, addr) = self.att_peer()?;
+        let cccd_handle = self.cccd_handle(characteristic)?;
+
+        // Register first so that notifications sent as soon as the CCCD is
+        // written aren't lost
+        let (sender, receiver) = unbounded_channel();
+        self.notification_streams
+            .lock()
+            .unwrap()
+            .entry(characteristic.value_handle)
+            .or_default()
+            .push(sender);
+
+        if let Err(e) = self.enable_notifications(characteristic) {
+            drop(receiver);
+            let mut streams = self.notification_streams.lock().unwrap();
+            if let Some(senders) = streams.get_mut(&characteristic.value_handle) {
+                senders.retain(|sender| !sender.is_closed());
+                if senders.is_empty() {
+                    streams.remove(&characteristic.value_handle);
+                }
+            }
+            return Err(e);
+        }
+
+        Ok(NotificationStream {
+            receiver,
+            value_handle: characteristic.value_handle,
+            cccd_handle,
+            att_client: att_client.clone(),
+            addr,
+            streams: self.notification_streams.clone(),
+        })
+    }
+
     /// Enable indications for a characteristic
+    ///
+    /// Every indication received afterwards is confirmed automatically once
+    /// the indication callback has run.
     pub fn enable_indications(&self, characteristic: &Characteristic) -> Result<(), GattError> {
         if self.state != ConnectionState::Connected {
             return Err(GattError::NotConnected);
@@ -917,27 +2680,14 @@ impl GattClient {
             return Err(GattError::NotPermitted);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
+        let (att_client, addr) = self.att_peer()?;
 
-        // Find the Client Characteristic Configuration descriptor
-        let result = att_client
-            .find_information(
-                characteristic.value_handle + 1,
-                characteristic.value_handle + 10, // Arbitrary range to search
-            )
-            .map_err(GattError::AttError)?;
-
-        // Look for the CCCD UUID (0x2902)
-        let cccd_handle = result
-            .iter()
-            .find(|(_, uuid)| uuid == &Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID))
-            .map(|(handle, _)| *handle)
-            .ok_or(GattError::CharacteristicNotFound)?;
+        let cccd_handle = self.cccd_handle(characteristic)?;
 
         // Write to CCCD to enable indications (0x0002)
         att_client
-            .write(cccd_handle, &[0x02, 0x00])
-            .map_err(GattError::AttError)?;
+            .write(addr, cccd_handle, &[0x02, 0x00])
+            .map_err(GattError::from)?;
 
         Ok(())
     }
@@ -951,346 +2701,104 @@ impl GattClient {
             return Err(GattError::NotConnected);
         }
 
-        let att_client = self.att_client.as_ref().ok_or(GattError::NotConnected)?;
-
-        // Find the Client Characteristic Configuration descriptor
-        let result = att_client
-            .find_information(
-                characteristic.value_handle + 1,
-                characteristic.value_handle + 10, // Arbitrary range to search
-            )
-            .map_err(GattError::AttError)?;
+        let (att_client, addr) = self.att_peer()?;
 
-        // Look for the CCCD UUID (0x2902)
-        let cccd_handle = result
-            .iter()
-            .find(|(_, uuid)| uuid == &Uuid::from_u16(CLIENT_CHAR_CONFIG_UUID))
-            .map(|(handle, _)| *handle)
-            .ok_or(GattError::CharacteristicNotFound)?;
+        let cccd_handle = self.cccd_handle(characteristic)?;
 
         // Write to CCCD to disable notifications/indications (0x0000)
         att_client
-            .write(cccd_handle, &[0x00, 0x00])
-            .map_err(GattError::AttError)?;
+            .write(addr, cccd_handle, &[0x00, 0x00])
+            .map_err(GattError::from)?;
 
         Ok(())
     }
 
-    fn handle_att_pdu(&mut self, pdu: &[u8]) -> AttResult<()> {
-        if pdu.is_empty() {
-            return Err(AttError::InvalidPdu);
+    /// Read the Characteristic Presentation Format descriptor of a characteristic
+    ///
+    /// Returns `None` if the characteristic has no presentation format descriptor.
+    pub fn read_presentation_format(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<Option<CharacteristicPresentationFormat>, GattError> {
+        if self.state != ConnectionState::Connected {
+            return Err(GattError::NotConnected);
         }
-        let opcode_byte = pdu[0];
-        let data = &pdu[1..];
 
-        trace!(
-            "Received ATT PDU: Opcode=0x{:02X}, Data={:?}",
-            opcode_byte,
-            data
-        );
+        let (att_client, addr) = self.att_peer()?;
 
-        match AttOpcode::try_from(opcode_byte) {
-            Ok(AttOpcode::ReadByTypeResponse) => {
-                if data.len() < 1 {
-                    return Err(AttError::InvalidPdu);
-                }
-                let length = data[0] as usize;
-                if (length < 7) || (data.len() < 1 + length) {
-                    return Err(AttError::InvalidPdu);
-                }
-                let mut characteristics = Vec::new();
-                let mut current_pos = 1;
-                while current_pos + length <= data.len() {
-                    let decl_handle =
-                        u16::from_le_bytes([data[current_pos], data[current_pos + 1]]);
-                    let decl_value = &data[current_pos + 2..current_pos + length];
-                    if decl_value.len() < 3 {
-                        warn!("Characteristic declaration value too short");
-                        current_pos += length;
-                        continue;
-                    }
-                    let properties = CharacteristicProperty::from_bits_truncate(decl_value[0]);
-                    let value_handle = u16::from_le_bytes([decl_value[1], decl_value[2]]);
-                    let uuid_bytes = &decl_value[3..];
-                    let uuid_result: Result<Uuid, AttError> = if uuid_bytes.len() == 2 {
-                        let uuid16 = u16::from_le_bytes([uuid_bytes[0], uuid_bytes[1]]);
-                        Ok(Uuid::from_u16(uuid16))
-                    } else if uuid_bytes.len() == 16 {
-                        Uuid::from_bytes(uuid_bytes).ok_or(AttError::InvalidPdu)
-                    } else {
-                        Err(AttError::InvalidPdu)
-                    };
-
-                    match uuid_result {
-                        Ok(uuid) => {
-                            characteristics.push(Characteristic {
-                                declaration_handle: decl_handle,
-                                value_handle,
-                                properties,
-                                uuid,
-                            });
-                        }
-                        Err(_) => {
-                            warn!("Invalid UUID found in characteristic declaration");
-                        }
-                    }
-                    current_pos += length;
-                }
+        // Find the Characteristic Presentation Format descriptor
+        let result = att_client
+            .find_information(
+                addr,
+                characteristic.value_handle + 1,
+                characteristic.value_handle + 10, // Arbitrary range to search
+            )
+            .map_err(GattError::from)?;
 
-                let mut pending_discovery_guard = self.pending_discovery.lock().unwrap();
-                if let Some(discovery_state) = pending_discovery_guard.as_mut() {
-                    if let DiscoveryState::DiscoveringCharacteristics(service_idx) = discovery_state
-                    {
-                        let mut discovered_services_guard =
-                            self.discovered_services.lock().unwrap();
-                        if let Some(service) = discovered_services_guard.get_mut(*service_idx) {
-                            drop(discovered_services_guard);
-                            let mut characteristics_cache = self.characteristics.write().unwrap();
-                            characteristics_cache.insert(service.start_handle, characteristics);
-                        } else {
-                            warn!("Service index out of bounds during characteristic discovery");
-                            drop(discovered_services_guard);
-                        }
-                        drop(pending_discovery_guard);
-                        self.advance_discovery_state()?;
-                    } else {
-                        warn!(
-                            "Received ReadByTypeResponse for characteristics in unexpected state"
-                        );
-                    }
-                } else {
-                    warn!("Received ReadByTypeResponse with no pending discovery state");
-                }
-                Ok(())
-            }
-            Ok(AttOpcode::ReadByGroupTypeResponse) => {
-                if data.len() < 1 {
-                    return Err(AttError::InvalidPdu);
-                }
-                let length = data[0] as usize;
-                if (length < 6) || (data.len() < 1 + length) {
-                    return Err(AttError::InvalidPdu);
-                }
-                let mut services = Vec::new();
-                let mut current_pos = 1;
-                while current_pos + length <= data.len() {
-                    let start_handle =
-                        u16::from_le_bytes([data[current_pos], data[current_pos + 1]]);
-                    let end_handle =
-                        u16::from_le_bytes([data[current_pos + 2], data[current_pos + 3]]);
-                    let uuid_bytes = &data[current_pos + 4..current_pos + length];
-                    let uuid_result: Result<Uuid, AttError> = if uuid_bytes.len() == 2 {
-                        let uuid16 = u16::from_le_bytes([uuid_bytes[0], uuid_bytes[1]]);
-                        Ok(Uuid::from_u16(uuid16))
-                    } else if uuid_bytes.len() == 16 {
-                        Uuid::from_bytes(uuid_bytes).ok_or(AttError::InvalidPdu)
-                    } else {
-                        Err(AttError::InvalidPdu)
-                    };
-                    match uuid_result {
-                        Ok(uuid) => {
-                            services.push(Service {
-                                start_handle,
-                                end_handle,
-                                uuid,
-                                is_primary: true,
-                            });
-                        }
-                        Err(_) => {
-                            warn!("Invalid UUID found in group response");
-                        }
-                    }
-                    current_pos += length;
-                }
-                let mut discovered_services_guard = self.discovered_services.lock().unwrap();
-                *discovered_services_guard = services;
-                let mut pending_discovery_guard = self.pending_discovery.lock().unwrap();
-                if !discovered_services_guard.is_empty() {
-                    *pending_discovery_guard = Some(DiscoveryState::DiscoveringCharacteristics(0));
-                    drop(discovered_services_guard);
-                    drop(pending_discovery_guard);
-                    self.advance_discovery_state()?;
-                } else {
-                    *pending_discovery_guard = Some(DiscoveryState::Idle);
-                    info!("No primary services found.");
-                }
-                Ok(())
-            }
-            Ok(AttOpcode::PrepareWriteResponse) => {
-                let response = PrepareWriteResponse::parse(pdu)?;
-                // Find pending request and verify response
-                let mut requests = self.pending_requests.lock().unwrap();
-                if let Some(req) = requests.front_mut() {
-                    if req.opcode == AttOpcode::PrepareWriteRequest {
-                        // TODO: Verify response matches request data if needed
-                        let callback = req.callback.take().ok_or(AttError::InvalidState)?;
-                        // Prepare Write Response itself doesn't carry substantial data for the callback usually
-                        callback(Ok(Vec::new()))?;
-                        requests.pop_front();
-                        Ok(())
-                    } else {
-                        warn!(
-                            "Received PrepareWriteResponse for non-prepare-write request: {:?}",
-                            req.opcode
-                        );
-                        Err(AttError::UnexpectedResponse)
-                    }
-                } else {
-                    warn!("Received PrepareWriteResponse with no pending request");
-                    Err(AttError::UnexpectedResponse)
-                }
-            }
-            Ok(AttOpcode::ExecuteWriteResponse) => {
-                // Find pending request and complete it
-                let mut requests = self.pending_requests.lock().unwrap();
-                if let Some(req) = requests.front_mut() {
-                    if req.opcode == AttOpcode::ExecuteWriteRequest {
-                        let callback = req.callback.take().ok_or(AttError::InvalidState)?;
-                        callback(Ok(Vec::new()))?; // Success, no data
-                        requests.pop_front();
-                        Ok(())
-                    } else {
-                        warn!(
-                            "Received ExecuteWriteResponse for non-execute-write request: {:?}",
-                            req.opcode
-                        );
-                        Err(AttError::UnexpectedResponse)
-                    }
-                } else {
-                    warn!("Received ExecuteWriteResponse with no pending request");
-                    Err(AttError::UnexpectedResponse)
-                }
-            }
-            Ok(AttOpcode::HandleValueNotification) => {
-                if pdu.len() < 3 {
-                    // Opcode + Handle (2) + Value (at least 0)
-                    return Err(AttError::InvalidPdu);
-                }
-                let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
-                let value = &pdu[3..];
-                info!(
-                    "Received Notification: Handle=0x{:04X}, Value={:?}",
-                    handle, value
-                );
-                let callbacks = self.notification_callbacks.lock().unwrap();
-                if let Some(callback) = callbacks.get(&handle) {
-                    callback(value.to_vec()); // Call the registered callback
-                }
-                Ok(())
-            }
-            Ok(AttOpcode::HandleValueIndication) => {
-                if pdu.len() < 3 {
-                    return Err(AttError::InvalidPdu);
-                }
-                let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
-                let value = &pdu[3..];
-                info!(
-                    "Received Indication: Handle=0x{:04X}, Value={:?}",
-                    handle, value
-                );
-                let callbacks = self.indication_callbacks.lock().unwrap();
-                if let Some(callback) = callbacks.get(&handle) {
-                    callback(value.to_vec()); // Call the registered callback
-                }
-                // Send confirmation
-                // Need access to att_client or send_att_pdu method
-                // self.send_att_pdu(&[AttOpcode::HandleValueConfirmation as u8])?;
-                Ok(())
-            }
-            Ok(AttOpcode::ErrorResponse) => {
-                // Parse ErrorResponse
-                if data.len() < 4 {
-                    return Err(AttError::InvalidPdu);
-                }
-                let req_opcode_byte = data[0];
-                let handle = u16::from_le_bytes([data[1], data[2]]);
-                let error_code_byte = data[3];
-                let error_code = AttErrorCode::from(error_code_byte);
-                error!(
-                    "ATT Error Response: ReqOpcode=0x{:02X}, Handle=0x{:04X}, ErrorCode={:?}",
-                    req_opcode_byte, handle, error_code
-                );
+        // Look for the presentation format UUID (0x2904)
+        let format_handle = match result
+            .iter()
+            .find(|(_, uuid)| uuid == &Uuid::from_u16(CHAR_FORMAT_UUID))
+        {
+            Some((handle, _)) => *handle,
+            None => return Ok(None),
+        };
 
-                // Find pending request and complete it with error
-                let mut requests = self.pending_requests.lock().unwrap();
-                if let Some(req) = requests.front_mut() {
-                    // Check if the req_opcode matches the pending request
-                    if req.opcode as u8 == req_opcode_byte {
-                        let callback = req.callback.take().ok_or(AttError::InvalidState)?;
-                        callback(Err(AttError::Protocol(error_code, handle)))?;
-                        requests.pop_front();
-                    } else {
-                        warn!("Received ErrorResponse for opcode 0x{:02X}, but pending request is {:?}", req_opcode_byte, req.opcode);
-                        // Don't remove the pending request, maybe the error is for something else?
-                    }
-                } else {
-                    warn!("Received ErrorResponse with no pending request");
-                }
-                // Propagate the error if needed, or just log it?
-                Err(AttError::Protocol(error_code, handle))
-            }
-            Ok(unhandled_opcode) => {
-                warn!("Unhandled ATT Opcode received: {:?}", unhandled_opcode);
-                let mut requests = self.pending_requests.lock().unwrap();
-                if let Some(req) = requests.front_mut() {
-                    if req.opcode != AttOpcode::ErrorResponse {
-                        warn!(
-                            "Received unexpected opcode {:?} while waiting for {:?}",
-                            unhandled_opcode, req.opcode
-                        );
-                        let callback = req.callback.take().ok_or(AttError::InvalidState)?;
-                        callback(Err(AttError::UnknownResponse("Unexpected opcode".into())))?;
-                        requests.pop_front();
-                    }
-                }
-                Err(AttError::UnsupportedOpcode(opcode_byte))
-            }
-            Err(_) => {
-                error!("Invalid ATT Opcode received: 0x{:02X}", opcode_byte);
-                let mut requests = self.pending_requests.lock().unwrap();
-                if let Some(req) = requests.front_mut() {
-                    let callback = req.callback.take().ok_or(AttError::InvalidState)?;
-                    callback(Err(AttError::InvalidOpcode(opcode_byte)))?;
-                    requests.pop_front();
-                }
-                Err(AttError::InvalidOpcode(opcode_byte))
-            }
-        }
+        let value = att_client
+            .read(addr, format_handle)
+            .map_err(GattError::from)?;
+        let data: &[u8; 7] = value
+            .as_slice()
+            .try_into()
+            .map_err(|_| GattError::InvalidData)?;
+
+        Ok(Some(CharacteristicPresentationFormat::parse(data)))
     }
 
-    fn advance_discovery_state(&mut self) -> AttResult<()> {
-        let mut pending_discovery_guard = self.pending_discovery.lock().unwrap();
-        let current_state = pending_discovery_guard.clone();
+    /// Read the Characteristic Extended Properties descriptor of a characteristic
+    ///
+    /// Characteristics without the `EXTENDED_PROPERTIES` property have no
+    /// extended properties, so no request is sent for them.
+    pub fn read_extended_properties(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Result<CharacteristicExtendedProperties, GattError> {
+        if !characteristic
+            .properties
+            .contains(CharacteristicProperty::EXTENDED_PROPERTIES)
+        {
+            return Ok(CharacteristicExtendedProperties::default());
+        }
 
-        match current_state {
-            Some(DiscoveryState::DiscoveringServices(_)) => {
-                let discovered_services = self.discovered_services.lock().unwrap();
-                if !discovered_services.is_empty() {
-                    *pending_discovery_guard = Some(DiscoveryState::DiscoveringCharacteristics(0));
-                    let first_service = discovered_services[0].clone();
-                    drop(discovered_services);
-                    drop(pending_discovery_guard);
-                } else {
-                    *pending_discovery_guard = Some(DiscoveryState::Idle);
-                    info!("Discovery complete (no services).");
-                }
-            }
-            Some(DiscoveryState::DiscoveringCharacteristics(service_idx)) => {
-                let discovered_services = self.discovered_services.lock().unwrap();
-                let next_service_idx = service_idx + 1;
-                if next_service_idx < discovered_services.len() {
-                    *pending_discovery_guard =
-                        Some(DiscoveryState::DiscoveringCharacteristics(next_service_idx));
-                    let next_service = discovered_services[next_service_idx].clone();
-                    drop(discovered_services);
-                    drop(pending_discovery_guard);
-                } else {
-                    *pending_discovery_guard = Some(DiscoveryState::Idle);
-                    info!("Discovery complete (all services).");
-                }
-            }
-            _ => {}
+        if self.state != ConnectionState::Connected {
+            return Err(GattError::NotConnected);
         }
-        Ok(())
+
+        let (att_client, addr) = self.att_peer()?;
+
+        // Find the Characteristic Extended Properties descriptor
+        let result = att_client
+            .find_information(
+                addr,
+                characteristic.value_handle + 1,
+                characteristic.value_handle + 10, // Arbitrary range to search
+            )
+            .map_err(GattError::from)?;
+
+        // The property is set, so the server must provide the descriptor (0x2900)
+        let (descriptor_handle, _) = result
+            .iter()
+            .find(|(_, uuid)| uuid == &Uuid::from_u16(CHAR_EXTENDED_PROPS_UUID))
+            .ok_or(GattError::InvalidData)?;
+
+        let value = att_client
+            .read(addr, *descriptor_handle)
+            .map_err(GattError::from)?;
+        let data: &[u8; 2] = value
+            .as_slice()
+            .try_into()
+            .map_err(|_| GattError::InvalidData)?;
+
+        Ok(CharacteristicExtendedProperties::parse(data))
     }
 }
@@ -0,0 +1,136 @@
+//! Multi-connection GATT client management
+//!
+//! This module drives several `GattClient`s from a single thread, waiting on
+//! all of their HCI sockets at once and dispatching each event to the client
+//! whose connection it is about. Clients may share a socket.
+
+use crate::error::HciError;
+use crate::gap::BdAddr;
+use crate::gatt::client::{GattClient, GattError, LeConnectionComplete};
+use crate::hci::constants::*;
+use crate::hci::{EventMultiplexer, HciEvent};
+use std::time::Duration;
+
+/// Drives a set of GATT clients without a thread per connection
+#[derive(Debug, Default)]
+pub struct ConnectionManager {
+    clients: Vec<GattClient>,
+    /// Multiplexer index of each client's socket
+    socket_indices: Vec<usize>,
+    multiplexer: EventMultiplexer,
+}
+
+impl ConnectionManager {
+    /// Create an empty connection manager
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a client, returning its index
+    pub fn add_client(&mut self, client: GattClient) -> usize {
+        self.socket_indices
+            .push(self.multiplexer.add_socket(client.shared_socket()));
+        self.clients.push(client);
+        self.clients.len() - 1
+    }
+
+    /// Get the client at the given index
+    pub fn client(&self, index: usize) -> Option<&GattClient> {
+        self.clients.get(index)
+    }
+
+    /// Get the client at the given index mutably
+    pub fn client_mut(&mut self, index: usize) -> Option<&mut GattClient> {
+        self.clients.get_mut(index)
+    }
+
+    /// Get all managed clients
+    pub fn clients(&self) -> &[GattClient] {
+        &self.clients
+    }
+
+    /// Wait for an event on any client's socket and dispatch it
+    ///
+    /// ATT timeouts are processed for every client first. Events about a
+    /// connection go to the client that owns it, and anything else to the
+    /// first client on the socket it arrived on. Returns the index of the
+    /// client that handled an event, or `None` if the timeout expired with
+    /// nothing to do.
+    pub fn process_events(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<usize>, GattError> {
+        for client in &self.clients {
+            client.process_timeouts()?;
+        }
+
+        if self.clients.is_empty() {
+            return Ok(None);
+        }
+
+        let (socket_index, event) = match self.multiplexer.wait_event(timeout) {
+            Ok(result) => result,
+            Err(HciError::ReceiveError(io_err))
+                if io_err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(GattError::HciError(e.to_string())),
+        };
+
+        let Some(index) = self.route_event(socket_index, &event) else {
+            return Ok(None);
+        };
+        self.clients[index].handle_event(&event)?;
+        Ok(Some(index))
+    }
+
+    /// Pick the client an event read from the given socket belongs to
+    fn route_event(&self, socket_index: usize, event: &HciEvent) -> Option<usize> {
+        let mut candidates =
+            (0..self.clients.len()).filter(|&i| self.socket_indices[i] == socket_index);
+
+        let owner = if let Some(conn_complete) = LeConnectionComplete::parse(event) {
+            // Not connected yet, so match the peer being connected to
+            let peer = BdAddr::new(conn_complete.peer_address);
+            candidates.clone().find(|&i| {
+                self.clients[i].connection_handle().is_none()
+                    && self.clients[i].peer_address() == Some(peer)
+            })
+        } else {
+            event_connection_handle(event).and_then(|handle| {
+                candidates
+                    .clone()
+                    .find(|&i| self.clients[i].connection_handle() == Some(handle))
+            })
+        };
+
+        owner.or_else(|| candidates.next())
+    }
+}
+
+/// Get the connection handle an event is about, if it names one
+///
+/// For Number Of Completed Packets this is the first handle reported.
+fn event_connection_handle(event: &HciEvent) -> Option<u16> {
+    let offset = match event.event_code {
+        // Status or handle count first
+        EVT_DISCONN_COMPLETE
+        | EVT_ENCRYPTION_CHANGE
+        | EVT_ENCRYPT_KEY_REFRESH_COMPLETE
+        | EVT_NUM_COMP_PKTS => 1,
+        EVT_LE_META_EVENT => match *event.parameters.first()? {
+            // Subevent code and status first
+            EVT_LE_CONN_UPDATE_COMPLETE
+            | EVT_LE_READ_REMOTE_FEATURES_COMPLETE
+            | EVT_LE_PHY_UPDATE_COMPLETE => 2,
+            // Subevent code only
+            EVT_LE_LTK_REQUEST | EVT_LE_REMOTE_CONN_PARAM_REQUEST | EVT_LE_DATA_LENGTH_CHANGE => 1,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let bytes = event.parameters.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) & 0x0FFF)
+}
@@ -0,0 +1,113 @@
+//! Generic Access Service
+//!
+//! This module provides the mandatory Generic Access Service (0x1800), which
+//! exposes the device name and appearance to GATT clients.
+
+use super::server::GattServer;
+use super::types::CharacteristicProperty;
+use crate::att::{AttPermissions, AttResult};
+use crate::uuid::Uuid;
+use std::sync::Arc;
+
+/// Generic Access Service UUID
+pub const GENERIC_ACCESS_SERVICE_UUID: u16 = 0x1800;
+/// Device Name characteristic UUID
+pub const DEVICE_NAME_UUID: u16 = 0x2A00;
+/// Appearance characteristic UUID
+pub const APPEARANCE_UUID: u16 = 0x2A01;
+
+/// Maximum length of the device name in bytes
+pub const DEVICE_NAME_MAX_LEN: usize = 248;
+
+/// The Generic Access Service of a GATT server
+pub struct GenericAccessService {
+    server: Arc<GattServer>,
+    service_handle: u16,
+    device_name_handle: u16,
+    appearance_handle: u16,
+}
+
+impl GenericAccessService {
+    /// Add the Generic Access Service to a GATT server
+    ///
+    /// Both characteristics are readable but not writable by clients.
+    pub fn register(server: Arc<GattServer>, name: &str, appearance: u16) -> AttResult<Self> {
+        let service_handle =
+            server.add_service(Uuid::from_u16(GENERIC_ACCESS_SERVICE_UUID), true)?;
+        let device_name_handle = server.add_characteristic(
+            service_handle,
+            Uuid::from_u16(DEVICE_NAME_UUID),
+            CharacteristicProperty::READ,
+            AttPermissions::read_only(),
+            truncate_name(name).to_vec(),
+        )?;
+        let appearance_handle = server.add_characteristic(
+            service_handle,
+            Uuid::from_u16(APPEARANCE_UUID),
+            CharacteristicProperty::READ,
+            AttPermissions::read_only(),
+            appearance.to_le_bytes().to_vec(),
+        )?;
+
+        Ok(Self {
+            server,
+            service_handle,
+            device_name_handle,
+            appearance_handle,
+        })
+    }
+
+    /// Get the service declaration handle
+    pub fn service_handle(&self) -> u16 {
+        self.service_handle
+    }
+
+    /// Get the Device Name characteristic value handle
+    pub fn device_name_handle(&self) -> u16 {
+        self.device_name_handle
+    }
+
+    /// Get the Appearance characteristic value handle
+    pub fn appearance_handle(&self) -> u16 {
+        self.appearance_handle
+    }
+
+    /// Set the device name, truncated to `DEVICE_NAME_MAX_LEN` bytes
+    pub fn set_name(&self, name: &str) -> AttResult<()> {
+        self.server
+            .set_characteristic_value(self.device_name_handle, truncate_name(name))
+    }
+
+    /// Get the device name
+    pub fn name(&self) -> AttResult<String> {
+        let value = self
+            .server
+            .get_characteristic_value(self.device_name_handle)?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    /// Set the appearance
+    pub fn set_appearance(&self, appearance: u16) -> AttResult<()> {
+        self.server
+            .set_characteristic_value(self.appearance_handle, &appearance.to_le_bytes())
+    }
+
+    /// Get the appearance
+    pub fn appearance(&self) -> AttResult<u16> {
+        let value = self
+            .server
+            .get_characteristic_value(self.appearance_handle)?;
+        Ok(value
+            .get(..2)
+            .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]])))
+    }
+}
+
+/// Truncate a name to the maximum length without splitting a character
+fn truncate_name(name: &str) -> &[u8] {
+    let mut len = name.len().min(DEVICE_NAME_MAX_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    &name.as_bytes()[..len]
+}
@@ -4,12 +4,34 @@
 //! and characteristics on Bluetooth LE devices.
 
 pub mod client;
+#[cfg(feature = "blocking")]
+pub mod connection_manager;
+pub mod gap_service;
+pub mod profiles;
 pub mod server;
 pub mod types;
 
 #[cfg(test)]
 mod tests;
 
-pub use client::{ConnectionState, GattClient, GattError};
-pub use server::{GattServer, GattServerConfig, GattService};
-pub use types::{Characteristic, CharacteristicProperty, Service, Uuid};
+#[cfg(feature = "tokio")]
+pub use client::NotificationStream;
+pub use client::{
+    ConnectionCallback, ConnectionEvent, ConnectionParams, ConnectionState, GattClient,
+    GattClientConfig, GattError, GattEvent, PeriodicAdvertisingSyncEstablished, PhyUpdateCallback,
+    ScanAndConnectConfig, ServiceChangedCallback, SyncHandle, PERIODIC_SYNC_TIMEOUT,
+    SERVICE_CHANGED_HANDLE, SERVICE_CHANGED_UUID,
+};
+#[cfg(feature = "blocking")]
+pub use connection_manager::ConnectionManager;
+pub use gap_service::GenericAccessService;
+pub use profiles::{HeartRateMeasurement, HeartRateServer};
+pub use server::{
+    CharacteristicBuilder, GattServer, GattServerBuilder, GattServerConfig, GattService,
+    ServiceBuilder,
+};
+pub use types::{
+    Characteristic, CharacteristicExtendedProperties, CharacteristicPresentationFormat,
+    CharacteristicProperty, Descriptor, DiscoveryPhase, DiscoveryProgress, FullCharacteristic,
+    FullService, PhyPreference, PhyType, Service, Uuid,
+};
@@ -0,0 +1,190 @@
+//! Heart Rate Profile
+//!
+//! This module provides the Heart Rate Service (0x180D) and the encoding of
+//! its Heart Rate Measurement characteristic (0x2A37).
+
+use crate::att::{AttPermissions, AttResult};
+use crate::gap::BdAddr;
+use crate::gatt::client::GattError;
+use crate::gatt::server::GattServer;
+use crate::gatt::types::CharacteristicProperty;
+use crate::uuid::Uuid;
+use std::sync::Arc;
+
+/// Heart Rate Service UUID
+pub const HEART_RATE_SERVICE_UUID: u16 = 0x180D;
+/// Heart Rate Measurement characteristic UUID
+pub const HEART_RATE_MEASUREMENT_UUID: u16 = 0x2A37;
+
+/// Flag: heart rate value is a UINT16 rather than a UINT8
+pub const HR_FLAG_VALUE_FORMAT_UINT16: u8 = 0x01;
+/// Flag: sensor contact is detected
+pub const HR_FLAG_SENSOR_CONTACT_DETECTED: u8 = 0x02;
+/// Flag: sensor contact detection is supported
+pub const HR_FLAG_SENSOR_CONTACT_SUPPORTED: u8 = 0x04;
+/// Flag: energy expended field is present
+pub const HR_FLAG_ENERGY_EXPENDED_PRESENT: u8 = 0x08;
+/// Flag: one or more RR-interval fields are present
+pub const HR_FLAG_RR_INTERVAL_PRESENT: u8 = 0x10;
+
+/// A Heart Rate Measurement characteristic value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartRateMeasurement {
+    /// Heart rate in beats per minute
+    pub bpm: u16,
+    /// Whether the sensor is in contact, if the sensor can detect it
+    pub sensor_contact: Option<bool>,
+    /// Energy expended in kilojoules since the last reset
+    pub energy_expended: Option<u16>,
+    /// RR-intervals in units of 1/1024 second, oldest first
+    pub rr_intervals: Vec<u16>,
+}
+
+impl HeartRateMeasurement {
+    /// Create a measurement without sensor contact information
+    pub fn new(bpm: u16, energy_expended: Option<u16>, rr_intervals: &[u16]) -> Self {
+        Self {
+            bpm,
+            sensor_contact: None,
+            energy_expended,
+            rr_intervals: rr_intervals.to_vec(),
+        }
+    }
+
+    /// Encode the measurement in the characteristic's wire format
+    ///
+    /// The heart rate is sent as a UINT8 when it fits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let mut data = vec![0];
+
+        if self.bpm > u8::MAX as u16 {
+            flags |= HR_FLAG_VALUE_FORMAT_UINT16;
+            data.extend_from_slice(&self.bpm.to_le_bytes());
+        } else {
+            data.push(self.bpm as u8);
+        }
+
+        match self.sensor_contact {
+            Some(true) => {
+                flags |= HR_FLAG_SENSOR_CONTACT_SUPPORTED | HR_FLAG_SENSOR_CONTACT_DETECTED
+            }
+            Some(false) => flags |= HR_FLAG_SENSOR_CONTACT_SUPPORTED,
+            None => {}
+        }
+
+        if let Some(energy_expended) = self.energy_expended {
+            flags |= HR_FLAG_ENERGY_EXPENDED_PRESENT;
+            data.extend_from_slice(&energy_expended.to_le_bytes());
+        }
+
+        if !self.rr_intervals.is_empty() {
+            flags |= HR_FLAG_RR_INTERVAL_PRESENT;
+            for rr_interval in &self.rr_intervals {
+                data.extend_from_slice(&rr_interval.to_le_bytes());
+            }
+        }
+
+        data[0] = flags;
+        data
+    }
+
+    /// Decode a measurement from the characteristic's wire format
+    pub fn parse(data: &[u8]) -> Result<Self, GattError> {
+        let (&flags, mut rest) = data.split_first().ok_or(GattError::InvalidData)?;
+
+        let bpm = if flags & HR_FLAG_VALUE_FORMAT_UINT16 != 0 {
+            take_u16(&mut rest)?
+        } else {
+            let (&bpm, tail) = rest.split_first().ok_or(GattError::InvalidData)?;
+            rest = tail;
+            bpm as u16
+        };
+
+        let sensor_contact = if flags & HR_FLAG_SENSOR_CONTACT_SUPPORTED != 0 {
+            Some(flags & HR_FLAG_SENSOR_CONTACT_DETECTED != 0)
+        } else {
+            None
+        };
+
+        let energy_expended = if flags & HR_FLAG_ENERGY_EXPENDED_PRESENT != 0 {
+            Some(take_u16(&mut rest)?)
+        } else {
+            None
+        };
+
+        let mut rr_intervals = Vec::new();
+        if flags & HR_FLAG_RR_INTERVAL_PRESENT != 0 {
+            if rest.is_empty() {
+                return Err(GattError::InvalidData);
+            }
+            while !rest.is_empty() {
+                rr_intervals.push(take_u16(&mut rest)?);
+            }
+        }
+
+        Ok(Self {
+            bpm,
+            sensor_contact,
+            energy_expended,
+            rr_intervals,
+        })
+    }
+}
+
+/// Take a little-endian u16 off the front of a buffer
+fn take_u16(data: &mut &[u8]) -> Result<u16, GattError> {
+    if data.len() < 2 {
+        return Err(GattError::InvalidData);
+    }
+    let value = u16::from_le_bytes([data[0], data[1]]);
+    *data = &data[2..];
+    Ok(value)
+}
+
+/// The Heart Rate Service of a GATT server
+pub struct HeartRateServer {
+    server: Arc<GattServer>,
+    service_handle: u16,
+    measurement_handle: u16,
+}
+
+impl HeartRateServer {
+    /// Add the Heart Rate Service to a GATT server
+    ///
+    /// The measurement characteristic can only be notified, so clients
+    /// subscribe through its Client Characteristic Configuration descriptor.
+    pub fn register(server: Arc<GattServer>) -> AttResult<Self> {
+        let service_handle = server.add_service(Uuid::from_u16(HEART_RATE_SERVICE_UUID), true)?;
+        let measurement_handle = server.add_characteristic(
+            service_handle,
+            Uuid::from_u16(HEART_RATE_MEASUREMENT_UUID),
+            CharacteristicProperty::NOTIFY,
+            AttPermissions::none(),
+            Vec::new(),
+        )?;
+        server.add_cccd(measurement_handle)?;
+
+        Ok(Self {
+            server,
+            service_handle,
+            measurement_handle,
+        })
+    }
+
+    /// Get the service declaration handle
+    pub fn service_handle(&self) -> u16 {
+        self.service_handle
+    }
+
+    /// Get the Heart Rate Measurement characteristic value handle
+    pub fn measurement_handle(&self) -> u16 {
+        self.measurement_handle
+    }
+
+    /// Send a measurement to a client
+    pub fn notify(&self, client: BdAddr, measurement: &HeartRateMeasurement) -> AttResult<()> {
+        self.server
+            .notify(client, self.measurement_handle, &measurement.to_bytes())
+    }
+}
@@ -0,0 +1,8 @@
+//! GATT profiles
+//!
+//! This module provides services defined by Bluetooth SIG profiles on top
+//! of the GATT server.
+
+pub mod heart_rate;
+
+pub use heart_rate::{HeartRateMeasurement, HeartRateServer};
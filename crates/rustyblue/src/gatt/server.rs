@@ -2,16 +2,18 @@
 //!
 //! This module provides a server for GATT services, building on top of the ATT layer.
 
-use super::types::{Characteristic, CharacteristicProperty, Service};
+use super::types::{
+    Characteristic, CharacteristicExtendedProperties, CharacteristicProperty, Service,
+};
 use crate::att::{
-    AttError, AttPermissions, AttResult, AttServer, Attribute, AttributeDatabase, SecurityLevel,
-    ATT_DEFAULT_MTU, CHARACTERISTIC_UUID, CLIENT_CHAR_CONFIG_UUID, PRIMARY_SERVICE_UUID,
-    SECONDARY_SERVICE_UUID,
+    AttError, AttErrorCode, AttPermissions, AttResult, AttServer, AttributeDatabase, SecurityLevel,
+    ATT_DEFAULT_MTU, CHARACTERISTIC_UUID, CHAR_EXTENDED_PROPS_UUID, CLIENT_CHAR_CONFIG_UUID,
+    PRIMARY_SERVICE_UUID, SECONDARY_SERVICE_UUID,
 };
 use crate::gap::BdAddr;
+use crate::l2cap::L2capManager;
 use crate::uuid::Uuid;
 use std::collections::{BTreeMap, HashMap};
-use std::io::{Cursor, Read};
 use std::sync::{Arc, RwLock};
 
 /// GATT Server configuration
@@ -153,7 +155,7 @@ impl GattServer {
         if let Some(uuid16) = uuid.as_u16() {
             value.extend_from_slice(&uuid16.to_le_bytes());
         } else {
-            value.extend_from_slice(&uuid.as_bytes());
+            value.extend_from_slice(uuid.as_bytes_le());
         }
 
         let handle = self.database.add_attribute_with_next_handle(
@@ -211,7 +213,7 @@ impl GattServer {
 
         // Now add the characteristic value attribute
         let value_handle = self.database.add_attribute_with_next_handle(
-            uuid.clone(),
+            uuid,
             initial_value.clone(),
             permissions,
         )?;
@@ -222,13 +224,8 @@ impl GattServer {
         declaration_value[2] = declaration_bytes[1];
 
         // Update the declaration attribute
-        let decl_attr = Attribute::new(
-            declaration_handle,
-            Uuid::from_u16(CHARACTERISTIC_UUID),
-            declaration_value,
-            AttPermissions::read_only(),
-        );
-        self.database.add_attribute(decl_attr)?;
+        self.database
+            .set_value(declaration_handle, &declaration_value)?;
 
         // Create a characteristic object
         let characteristic = GattCharacteristic {
@@ -249,9 +246,49 @@ impl GattServer {
 
         service.end_handle = value_handle; // Update end handle
 
+        drop(characteristics_map);
+        drop(services);
+
+        // Extended properties default to none until set_extended_properties
+        if properties.contains(CharacteristicProperty::EXTENDED_PROPERTIES) {
+            self.add_descriptor(
+                value_handle,
+                Uuid::from_u16(CHAR_EXTENDED_PROPS_UUID),
+                AttPermissions::read_only(),
+                CharacteristicExtendedProperties::default()
+                    .serialize()
+                    .to_vec(),
+            )?;
+        }
+
         Ok(value_handle)
     }
 
+    /// Set the value of a characteristic's Extended Properties descriptor
+    ///
+    /// The descriptor is added by `add_characteristic` when the
+    /// characteristic has the `EXTENDED_PROPERTIES` property.
+    pub fn set_extended_properties(
+        &self,
+        characteristic_value_handle: u16,
+        extended_properties: CharacteristicExtendedProperties,
+    ) -> AttResult<()> {
+        let mut characteristics = self.characteristics.write().unwrap();
+        let descriptor = characteristics
+            .get_mut(&characteristic_value_handle)
+            .ok_or(AttError::AttributeNotFound)?
+            .descriptors
+            .iter_mut()
+            .find(|descriptor| descriptor.uuid == Uuid::from_u16(CHAR_EXTENDED_PROPS_UUID))
+            .ok_or(AttError::AttributeNotFound)?;
+
+        let value = extended_properties.serialize();
+        self.database.set_value(descriptor.handle, &value)?;
+        descriptor.value = value.to_vec();
+
+        Ok(())
+    }
+
     /// Add a descriptor to a characteristic
     pub fn add_descriptor(
         &self,
@@ -268,7 +305,7 @@ impl GattServer {
 
         // Add descriptor attribute to database
         let handle = self.database.add_attribute_with_next_handle(
-            uuid.clone(),
+            uuid,
             initial_value.clone(),
             permissions,
         )?;
@@ -302,17 +339,19 @@ impl GattServer {
 
     /// Add the standard Client Characteristic Configuration descriptor to a characteristic
     pub fn add_cccd(&self, characteristic_value_handle: u16) -> AttResult<u16> {
-        // Find the characteristic
-        let characteristics = self.characteristics.read().unwrap();
-        let characteristic = characteristics
-            .get(&characteristic_value_handle)
-            .ok_or(AttError::AttributeNotFound)?;
+        // Check properties, releasing the lock before add_descriptor takes it for writing
+        {
+            let characteristics = self.characteristics.read().unwrap();
+            let characteristic = characteristics
+                .get(&characteristic_value_handle)
+                .ok_or(AttError::AttributeNotFound)?;
 
-        // Check properties
-        if !characteristic.properties.can_notify() && !characteristic.properties.can_indicate() {
-            return Err(AttError::InvalidParameter(
-                "Characteristic does not support notifications or indications".into(),
-            ));
+            if !characteristic.properties.can_notify() && !characteristic.properties.can_indicate()
+            {
+                return Err(AttError::InvalidParameter(
+                    "Characteristic does not support notifications or indications".into(),
+                ));
+            }
         }
 
         // Add CCCD
@@ -346,7 +385,7 @@ impl GattServer {
                 }
 
                 // Update in-memory value
-                let attr = database.get_attribute(handle)?;
+                database.get_attribute(handle)?;
 
                 // Process CCCD value
                 server.process_cccd_write(characteristic_value_handle, value)?;
@@ -359,14 +398,14 @@ impl GattServer {
     }
 
     /// Process a write to a Client Characteristic Configuration descriptor
-    fn process_cccd_write(&self, char_handle: u16, value: &[u8]) -> AttResult<()> {
+    fn process_cccd_write(&self, _char_handle: u16, value: &[u8]) -> AttResult<()> {
         if value.len() != 2 {
             return Err(AttError::InvalidAttributeValueLength);
         }
 
         let flags = u16::from_le_bytes([value[0], value[1]]);
-        let notifications_enabled = (flags & 0x0001) != 0;
-        let indications_enabled = (flags & 0x0002) != 0;
+        let _notifications_enabled = (flags & 0x0001) != 0;
+        let _indications_enabled = (flags & 0x0002) != 0;
 
         // Currently we'd need the client address to properly track this
         // For now, just update the local state
@@ -374,6 +413,34 @@ impl GattServer {
         Ok(())
     }
 
+    /// Check values clients write to a characteristic
+    ///
+    /// `validator` returns the application error code (0x80-0x9F) sent to
+    /// the client when it rejects a value, e.g. one outside the value domain
+    /// of the characteristic. Rejected values are not written.
+    pub fn set_write_validator<F>(&self, characteristic: &Characteristic, validator: F)
+    where
+        F: Fn(&[u8]) -> Result<(), AttErrorCode> + Send + Sync + 'static,
+    {
+        self.att_server
+            .set_write_validator(characteristic.value_handle, Arc::new(validator));
+    }
+
+    /// Produce the value of a characteristic when clients read it
+    ///
+    /// `handler` is called with the offset into the value and the number of
+    /// bytes that fit into the response for the client's MTU. It returns at
+    /// most that many bytes; fewer bytes end the value, a full response
+    /// lets the client read on with a Read Blob Request. The handler does
+    /// not need to know the MTU itself.
+    pub fn set_read_handler<F>(&self, characteristic: &Characteristic, handler: F) -> AttResult<()>
+    where
+        F: Fn(u16, usize) -> AttResult<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.database
+            .register_read_handler(characteristic.value_handle, Arc::new(handler))
+    }
+
     /// Update a characteristic value and notify/indicate clients if configured
     pub fn update_characteristic(
         &self,
@@ -415,7 +482,9 @@ impl GattServer {
             if let Some(clients) = indications.get(&handle) {
                 for client in clients {
                     // Skip errors, client might be disconnected
-                    let _ = self.att_server.send_indication(*client, handle, value);
+                    let _ = self
+                        .att_server
+                        .send_indication_queued(*client, handle, value);
                 }
             }
         }
@@ -423,6 +492,42 @@ impl GattServer {
         Ok(())
     }
 
+    /// Send a notification for a characteristic to one client
+    ///
+    /// The stored characteristic value is left unchanged.
+    pub fn notify(&self, client: BdAddr, handle: u16, value: &[u8]) -> AttResult<()> {
+        {
+            let characteristics = self.characteristics.read().unwrap();
+            let characteristic = characteristics
+                .get(&handle)
+                .ok_or(AttError::AttributeNotFound)?;
+
+            if !characteristic.properties.can_notify() {
+                return Err(AttError::InvalidParameter(
+                    "Characteristic does not support notifications".into(),
+                ));
+            }
+        }
+
+        self.att_server.send_notification(client, handle, value)
+    }
+
+    /// Set a characteristic value locally without notifying clients
+    ///
+    /// Permissions only restrict remote clients, so this also updates
+    /// read-only characteristics.
+    pub fn set_characteristic_value(&self, handle: u16, value: &[u8]) -> AttResult<()> {
+        let characteristics = self.characteristics.read().unwrap();
+        let characteristic = characteristics
+            .get(&handle)
+            .ok_or(AttError::AttributeNotFound)?;
+
+        self.database.set_value(handle, value)?;
+        *characteristic.value.write().unwrap() = value.to_vec();
+
+        Ok(())
+    }
+
     /// Get a characteristic value by handle
     pub fn get_characteristic_value(&self, handle: u16) -> AttResult<Vec<u8>> {
         // Find the characteristic
@@ -444,7 +549,7 @@ impl GattServer {
         services
             .values()
             .map(|svc| Service {
-                uuid: svc.uuid.clone(),
+                uuid: svc.uuid,
                 is_primary: svc.is_primary,
                 start_handle: svc.handle,
                 end_handle: svc.end_handle,
@@ -469,20 +574,20 @@ impl GattServer {
                 let characteristic = characteristics
                     .get(&value_handle)
                     .ok_or(AttError::AttributeNotFound)?;
-                Characteristic {
-                    uuid: characteristic.uuid.clone(),
+                Ok(Characteristic {
+                    uuid: characteristic.uuid,
                     declaration_handle: characteristic.declaration_handle,
                     value_handle,
                     properties: characteristic.properties,
-                }
+                })
             })
-            .collect();
+            .collect::<AttResult<Vec<_>>>()?;
 
         Ok(characteristics)
     }
 
     /// Register a client (called when a client connects)
-    pub fn register_client(&self, addr: BdAddr, security_level: SecurityLevel) -> AttResult<()> {
+    pub fn register_client(&self, _addr: BdAddr, _security_level: SecurityLevel) -> AttResult<()> {
         // Nothing to do here yet, but would be used for security and connection tracking
         Ok(())
     }
@@ -521,3 +626,206 @@ impl Clone for GattServer {
         }
     }
 }
+
+/// Builder for a GATT server and its services
+///
+/// Services and characteristics get consecutive handles from 0x0001 in the
+/// order they are added, and a Client Characteristic Configuration
+/// descriptor is added for every characteristic that can notify or
+/// indicate:
+///
+/// ```ignore
+/// let (server, handles) = GattServerBuilder::new()
+///     .add_service(Uuid::from_u16(0x180F), |svc| {
+///         svc.add_characteristic(Uuid::from_u16(0x2A19))
+///             .notify()
+///             .read()
+///             .value(vec![100u8])
+///     })
+///     .build(l2cap_manager)?;
+/// let battery_level = handles[&Uuid::from_u16(0x2A19)];
+/// ```
+#[derive(Debug, Default)]
+pub struct GattServerBuilder {
+    services: Vec<ServiceBuilder>,
+}
+
+impl GattServerBuilder {
+    /// Start a server without services
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a primary service, adding its characteristics in `build_service`
+    ///
+    /// `build_service` may return the service builder or the builder of
+    /// its last characteristic.
+    pub fn add_service<F, S>(mut self, uuid: Uuid, build_service: F) -> Self
+    where
+        F: FnOnce(ServiceBuilder) -> S,
+        S: Into<ServiceBuilder>,
+    {
+        self.services
+            .push(build_service(ServiceBuilder::new(uuid)).into());
+        self
+    }
+
+    /// Create the server on a new attribute database
+    ///
+    /// Returns the server and the value handle of each characteristic by
+    /// UUID. If several characteristics share a UUID, the map holds the
+    /// last one added.
+    pub fn build(
+        self,
+        l2cap_manager: Arc<L2capManager>,
+    ) -> AttResult<(GattServer, HashMap<Uuid, u16>)> {
+        let database = Arc::new(AttributeDatabase::new());
+        let att_server = Arc::new(AttServer::new(l2cap_manager, database.clone()));
+        let server = GattServer::new(att_server, database);
+
+        let mut value_handles = HashMap::new();
+        for service in self.services {
+            let service_handle = server.add_service(service.uuid, true)?;
+            for characteristic in service.characteristics {
+                let value_handle = server.add_characteristic(
+                    service_handle,
+                    characteristic.uuid,
+                    characteristic.properties,
+                    characteristic.permissions(),
+                    characteristic.value,
+                )?;
+                if characteristic.properties.can_notify()
+                    || characteristic.properties.can_indicate()
+                {
+                    server.add_cccd(value_handle)?;
+                }
+                value_handles.insert(characteristic.uuid, value_handle);
+            }
+        }
+
+        Ok((server, value_handles))
+    }
+}
+
+/// A service being added with `GattServerBuilder::add_service`
+#[derive(Debug)]
+pub struct ServiceBuilder {
+    uuid: Uuid,
+    characteristics: Vec<CharacteristicDefinition>,
+}
+
+impl ServiceBuilder {
+    fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            characteristics: Vec::new(),
+        }
+    }
+
+    /// Add a characteristic, initially without properties or value
+    pub fn add_characteristic(self, uuid: Uuid) -> CharacteristicBuilder {
+        CharacteristicBuilder {
+            service: self,
+            characteristic: CharacteristicDefinition {
+                uuid,
+                properties: CharacteristicProperty::empty(),
+                permissions: None,
+                value: Vec::new(),
+            },
+        }
+    }
+}
+
+impl From<CharacteristicBuilder> for ServiceBuilder {
+    fn from(builder: CharacteristicBuilder) -> Self {
+        let mut service = builder.service;
+        service.characteristics.push(builder.characteristic);
+        service
+    }
+}
+
+/// A characteristic being added with `ServiceBuilder::add_characteristic`
+#[derive(Debug)]
+pub struct CharacteristicBuilder {
+    service: ServiceBuilder,
+    characteristic: CharacteristicDefinition,
+}
+
+impl CharacteristicBuilder {
+    /// Allow clients to read the value
+    pub fn read(self) -> Self {
+        self.property(CharacteristicProperty::READ)
+    }
+
+    /// Allow clients to write the value with a Write Request
+    pub fn write(self) -> Self {
+        self.property(CharacteristicProperty::WRITE)
+    }
+
+    /// Allow clients to write the value with a Write Command
+    pub fn write_without_response(self) -> Self {
+        self.property(CharacteristicProperty::WRITE_WITHOUT_RESPONSE)
+    }
+
+    /// Allow the server to notify the value; adds a CCCD
+    pub fn notify(self) -> Self {
+        self.property(CharacteristicProperty::NOTIFY)
+    }
+
+    /// Allow the server to indicate the value; adds a CCCD
+    pub fn indicate(self) -> Self {
+        self.property(CharacteristicProperty::INDICATE)
+    }
+
+    /// Set the initial value
+    pub fn value(mut self, value: Vec<u8>) -> Self {
+        self.characteristic.value = value;
+        self
+    }
+
+    /// Set the value permissions
+    ///
+    /// By default the value is readable if the characteristic can be read
+    /// and writable if it can be written.
+    pub fn permissions(mut self, permissions: AttPermissions) -> Self {
+        self.characteristic.permissions = Some(permissions);
+        self
+    }
+
+    /// Finish this characteristic and add another to the same service
+    pub fn add_characteristic(self, uuid: Uuid) -> CharacteristicBuilder {
+        ServiceBuilder::from(self).add_characteristic(uuid)
+    }
+
+    fn property(mut self, property: CharacteristicProperty) -> Self {
+        self.characteristic.properties |= property;
+        self
+    }
+}
+
+/// A characteristic as described to `CharacteristicBuilder`
+#[derive(Debug)]
+struct CharacteristicDefinition {
+    uuid: Uuid,
+    properties: CharacteristicProperty,
+    permissions: Option<AttPermissions>,
+    value: Vec<u8>,
+}
+
+impl CharacteristicDefinition {
+    /// The explicit permissions, or those implied by the properties
+    fn permissions(&self) -> AttPermissions {
+        if let Some(permissions) = self.permissions {
+            return permissions;
+        }
+
+        let readable = self.properties.can_read();
+        let writable = self.properties.can_write() || self.properties.can_write_without_response();
+        match (readable, writable) {
+            (true, true) => AttPermissions::read_write(),
+            (true, false) => AttPermissions::read_only(),
+            (false, true) => AttPermissions::write_only(),
+            (false, false) => AttPermissions::none(),
+        }
+    }
+}
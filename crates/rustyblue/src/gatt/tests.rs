@@ -2,134 +2,7 @@
 
 use crate::gatt::client::{DisconnectionComplete, LeConnectionComplete};
 use crate::hci::constants::*;
-use crate::hci::{HciEvent, HciSocket};
-use std::os::unix::io::RawFd;
-
-/// Mock HCI socket for testing
-struct MockHciSocket {
-    fd: RawFd,
-    queued_events: Vec<HciEvent>,
-    next_event_index: usize,
-}
-
-impl MockHciSocket {
-    fn new() -> Self {
-        Self {
-            fd: 999, // Dummy fd
-            queued_events: Vec::new(),
-            next_event_index: 0,
-        }
-    }
-
-    fn queue_event(&mut self, event: HciEvent) {
-        self.queued_events.push(event);
-    }
-
-    fn queue_connection_complete(&mut self, status: u8, handle: u16) {
-        let mut params = vec![EVT_LE_CONN_COMPLETE, status];
-        params.extend_from_slice(&handle.to_le_bytes());
-        // Role (central)
-        params.push(0x00);
-        // Peer address type (public)
-        params.push(0x00);
-        // Peer address (00:11:22:33:44:55)
-        params.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
-        // Connection interval (10 ms)
-        params.extend_from_slice(&0x0008u16.to_le_bytes());
-        // Connection latency
-        params.extend_from_slice(&0x0000u16.to_le_bytes());
-        // Supervision timeout
-        params.extend_from_slice(&0x00C8u16.to_le_bytes());
-        // Master clock accuracy
-        params.push(0x00);
-
-        self.queue_event(HciEvent {
-            event_code: EVT_LE_META_EVENT,
-            parameter_total_length: params.len() as u8,
-            parameters: params,
-        });
-    }
-
-    fn queue_disconnection_complete(&mut self, status: u8, handle: u16, reason: u8) {
-        let mut params = vec![status];
-        params.extend_from_slice(&handle.to_le_bytes());
-        params.push(reason);
-
-        self.queue_event(HciEvent {
-            event_code: EVT_DISCONN_COMPLETE,
-            parameter_total_length: params.len() as u8,
-            parameters: params,
-        });
-    }
-
-    fn queue_command_complete(&mut self, ogf: u8, ocf: u16, status: u8) {
-        let opcode = ((ogf as u16) << 10) | (ocf & 0x3ff);
-        let mut params = vec![1]; // Num_HCI_Command_Packets
-        params.extend_from_slice(&opcode.to_le_bytes());
-        params.push(status);
-
-        self.queue_event(HciEvent {
-            event_code: EVT_CMD_COMPLETE,
-            parameter_total_length: params.len() as u8,
-            parameters: params,
-        });
-    }
-
-    fn queue_command_status(&mut self, status: u8, ogf: u8, ocf: u16) {
-        let opcode = ((ogf as u16) << 10) | (ocf & 0x3ff);
-        let mut params = vec![status, 1]; // Status, Num_HCI_Command_Packets
-        params.extend_from_slice(&opcode.to_le_bytes());
-
-        self.queue_event(HciEvent {
-            event_code: EVT_CMD_STATUS,
-            parameter_total_length: params.len() as u8,
-            parameters: params,
-        });
-    }
-}
-
-// Implement necessary traits for the mock socket
-impl std::os::unix::io::AsRawFd for MockHciSocket {
-    fn as_raw_fd(&self) -> RawFd {
-        self.fd
-    }
-}
-
-// Fake HciSocket trait to match what's expected by the GattClient
-impl MockHciSocket {
-    fn send_command(
-        &self,
-        _command: &crate::hci::HciCommand,
-    ) -> Result<(), crate::error::HciError> {
-        Ok(())
-    }
-
-    fn read_event(&mut self) -> Result<HciEvent, crate::error::HciError> {
-        if self.next_event_index < self.queued_events.len() {
-            let event = self.queued_events[self.next_event_index].clone();
-            self.next_event_index += 1;
-            Ok(event)
-        } else {
-            Err(crate::error::HciError::ReceiveError(std::io::Error::new(
-                std::io::ErrorKind::TimedOut,
-                "No more events",
-            )))
-        }
-    }
-
-    fn read_event_timeout(
-        &mut self,
-        _timeout: Option<std::time::Duration>,
-    ) -> Result<HciEvent, crate::error::HciError> {
-        self.read_event()
-    }
-}
-
-impl From<MockHciSocket> for HciSocket {
-    fn from(_mock: MockHciSocket) -> Self {
-        unreachable!("This is just a trick for the tests - not actually called");
-    }
-}
+use crate::hci::HciEvent;
 
 #[test]
 fn test_le_connection_complete_parsing() {
@@ -239,3 +112,667 @@ fn test_disconnection_complete_parsing() {
 }
 
 // More tests can be added for GATT client functionality when it's more complete
+
+#[test]
+fn test_presentation_format_round_trip() {
+    use crate::gatt::CharacteristicPresentationFormat;
+
+    // Temperature in 0.01 degrees Celsius as a signed 16-bit integer
+    let format = CharacteristicPresentationFormat::new(
+        CharacteristicPresentationFormat::FORMAT_SINT16,
+        -2,
+        CharacteristicPresentationFormat::UNIT_CELSIUS,
+    );
+
+    let bytes = format.serialize();
+    assert_eq!(bytes, [0x0E, 0xFE, 0x2F, 0x27, 0x01, 0x00, 0x00]);
+
+    let parsed = CharacteristicPresentationFormat::parse(&bytes);
+    assert_eq!(parsed, format);
+    assert_eq!(parsed.exponent, -2);
+    assert_eq!(
+        parsed.namespace,
+        CharacteristicPresentationFormat::NAMESPACE_BLUETOOTH_SIG
+    );
+}
+
+#[test]
+fn test_generic_access_service_name_and_appearance() {
+    use crate::att::{AttServer, AttributeDatabase};
+    use crate::gatt::gap_service::DEVICE_NAME_MAX_LEN;
+    use crate::gatt::{GattServer, GenericAccessService};
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use std::sync::Arc;
+
+    let database = Arc::new(AttributeDatabase::new());
+    let att_server = Arc::new(AttServer::new(
+        Arc::new(L2capManager::new(ConnectionType::LE)),
+        database.clone(),
+    ));
+    let server = Arc::new(GattServer::new(att_server, database.clone()));
+
+    let service = GenericAccessService::register(server, "rustyblue", 0x03C1).unwrap();
+    assert_eq!(service.name().unwrap(), "rustyblue");
+    assert_eq!(service.appearance().unwrap(), 0x03C1);
+
+    // Read-only for clients, but the local device can still change them
+    service.set_name("renamed").unwrap();
+    service.set_appearance(0x0000).unwrap();
+    assert_eq!(
+        database
+            .get_attribute(service.device_name_handle())
+            .unwrap()
+            .value,
+        b"renamed"
+    );
+    assert_eq!(service.appearance().unwrap(), 0x0000);
+
+    service.set_name(&"x".repeat(300)).unwrap();
+    assert_eq!(service.name().unwrap().len(), DEVICE_NAME_MAX_LEN);
+}
+
+#[test]
+fn test_extended_properties_descriptor() {
+    use crate::att::{AttPermissions, AttServer, AttributeDatabase, CHAR_EXTENDED_PROPS_UUID};
+    use crate::gatt::{CharacteristicExtendedProperties, CharacteristicProperty, GattServer};
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::uuid::Uuid;
+    use std::sync::Arc;
+
+    let extended = CharacteristicExtendedProperties {
+        reliable_write: true,
+        writable_auxiliaries: false,
+    };
+    assert_eq!(extended.serialize(), [0x01, 0x00]);
+    let parsed = CharacteristicExtendedProperties::parse(&[0x03, 0x80]);
+    assert!(parsed.reliable_write && parsed.writable_auxiliaries);
+
+    let database = Arc::new(AttributeDatabase::new());
+    let att_server = Arc::new(AttServer::new(
+        Arc::new(L2capManager::new(ConnectionType::LE)),
+        database.clone(),
+    ));
+    let server = GattServer::new(att_server, database.clone());
+    let service = server.add_service(Uuid::from_u16(0x180A), true).unwrap();
+    let value_handle = server
+        .add_characteristic(
+            service,
+            Uuid::from_u16(0x2A29),
+            CharacteristicProperty::READ | CharacteristicProperty::EXTENDED_PROPERTIES,
+            AttPermissions::read_only(),
+            Vec::new(),
+        )
+        .unwrap();
+
+    // The descriptor directly follows the value
+    let descriptor = database.get_attribute(value_handle + 1).unwrap();
+    assert_eq!(descriptor.type_, Uuid::from_u16(CHAR_EXTENDED_PROPS_UUID));
+    assert_eq!(descriptor.value, vec![0x00, 0x00]);
+
+    server
+        .set_extended_properties(value_handle, extended)
+        .unwrap();
+    assert_eq!(
+        database.get_attribute(value_handle + 1).unwrap().value,
+        vec![0x01, 0x00]
+    );
+}
+
+#[test]
+fn test_gatt_error_from_att_error() {
+    use crate::att::{AttError, AttErrorCode};
+    use crate::gatt::GattError;
+    use crate::l2cap::L2capError;
+
+    assert!(matches!(GattError::from(AttError::Busy), GattError::Busy));
+    assert!(matches!(
+        GattError::from(AttError::Protocol(
+            AttErrorCode::InsufficientEncryption,
+            0x0010
+        )),
+        GattError::EncryptionRequired
+    ));
+    assert!(matches!(
+        GattError::from(AttError::InvalidPdu),
+        GattError::AttError(AttError::InvalidPdu)
+    ));
+    assert!(matches!(
+        GattError::from(AttError::Timeout),
+        GattError::Timeout
+    ));
+    assert!(matches!(
+        GattError::from(AttError::Protocol(
+            AttErrorCode::InsufficientAuthentication,
+            0x0010
+        )),
+        GattError::AuthenticationRequired
+    ));
+    assert!(matches!(
+        GattError::from(AttError::from(L2capError::NotConnected)),
+        GattError::NotConnected
+    ));
+    assert!(matches!(
+        GattError::from(AttError::from(L2capError::MtuExceeded {
+            requested: 30,
+            allowed: 23
+        })),
+        GattError::MtuExceeded {
+            value_len: 30,
+            max_len: 23
+        }
+    ));
+    assert!(matches!(
+        GattError::from(AttError::from(L2capError::QueueFull)),
+        GattError::L2capError(_)
+    ));
+
+    let err = GattError::WriteFailed {
+        handle: 0x002A,
+        source: Box::new(GattError::NotPermitted),
+    };
+    assert_eq!(
+        err.to_string(),
+        "Write to handle 0x002A failed: Attribute operation not permitted"
+    );
+
+    let err = GattError::MtuExceeded {
+        value_len: 30,
+        max_len: 20,
+    };
+    assert_eq!(
+        err.to_string(),
+        "Value of 30 bytes exceeds the maximum of 20 bytes"
+    );
+}
+
+#[test]
+fn test_periodic_advertising_sync_established_parsing() {
+    use crate::gatt::PeriodicAdvertisingSyncEstablished;
+
+    let event = HciEvent {
+        event_code: EVT_LE_META_EVENT,
+        parameter_total_length: 16,
+        parameters: vec![
+            EVT_LE_PERIODIC_ADV_SYNC_ESTABLISHED,
+            0x00, // Status
+            0x01,
+            0x00, // Sync handle
+            0x03, // SID
+            0x00, // Public address
+            0x11,
+            0x22,
+            0x33,
+            0x44,
+            0x55,
+            0x66,
+            0x02, // LE 2M PHY
+            0x50,
+            0x00, // 100 ms interval
+            0x05,
+        ],
+    };
+
+    let established = PeriodicAdvertisingSyncEstablished::parse(&event).unwrap();
+    assert_eq!(established.status, 0x00);
+    assert_eq!(established.sync_handle, 0x0001);
+    assert_eq!(established.advertising_sid, 0x03);
+    assert_eq!(
+        established.advertiser_address,
+        [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]
+    );
+    assert_eq!(established.periodic_advertising_interval, 0x0050);
+
+    // Other LE meta events are not sync established events
+    let other = HciEvent {
+        parameters: vec![EVT_LE_CONN_COMPLETE; 16],
+        ..event
+    };
+    assert!(PeriodicAdvertisingSyncEstablished::parse(&other).is_none());
+}
+
+#[test]
+fn test_phy_update_complete_parsing() {
+    use crate::gatt::client::PhyUpdateComplete;
+    use crate::gatt::PhyType;
+
+    let event = HciEvent {
+        event_code: EVT_LE_META_EVENT,
+        parameter_total_length: 6,
+        parameters: vec![EVT_LE_PHY_UPDATE_COMPLETE, 0x00, 0x40, 0x00, 0x02, 0x02],
+    };
+
+    let phy_update = PhyUpdateComplete::parse(&event).unwrap();
+    assert_eq!(phy_update.status, 0x00);
+    assert_eq!(phy_update.connection_handle, 0x0040);
+    assert_eq!(PhyType::from_u8(phy_update.tx_phy), Some(PhyType::Le2M));
+    assert_eq!(PhyType::from_u8(phy_update.rx_phy), Some(PhyType::Le2M));
+    assert_eq!(PhyType::from_u8(0x04), None);
+}
+
+#[test]
+fn test_heart_rate_measurement_encoding() {
+    use crate::gatt::profiles::heart_rate::*;
+
+    // UINT8 heart rate only
+    let measurement = HeartRateMeasurement::new(72, None, &[]);
+    assert_eq!(measurement.to_bytes(), vec![0x00, 72]);
+    assert_eq!(
+        HeartRateMeasurement::parse(&[0x00, 72]).unwrap(),
+        measurement
+    );
+
+    // UINT16 heart rate with energy expended and RR-intervals
+    let measurement = HeartRateMeasurement::new(300, Some(0x0102), &[1024, 512]);
+    let bytes = measurement.to_bytes();
+    assert_eq!(
+        bytes,
+        vec![
+            HR_FLAG_VALUE_FORMAT_UINT16
+                | HR_FLAG_ENERGY_EXPENDED_PRESENT
+                | HR_FLAG_RR_INTERVAL_PRESENT,
+            0x2C,
+            0x01,
+            0x02,
+            0x01,
+            0x00,
+            0x04,
+            0x00,
+            0x02,
+        ]
+    );
+    assert_eq!(HeartRateMeasurement::parse(&bytes).unwrap(), measurement);
+
+    // Sensor contact bits
+    let parsed = HeartRateMeasurement::parse(&[0x06, 60]).unwrap();
+    assert_eq!(parsed.sensor_contact, Some(true));
+    assert_eq!(parsed.to_bytes(), vec![0x06, 60]);
+
+    // Truncated fields
+    assert!(HeartRateMeasurement::parse(&[]).is_err());
+    assert!(HeartRateMeasurement::parse(&[HR_FLAG_VALUE_FORMAT_UINT16, 0x2C]).is_err());
+    assert!(HeartRateMeasurement::parse(&[HR_FLAG_ENERGY_EXPENDED_PRESENT, 60, 0x01]).is_err());
+    assert!(HeartRateMeasurement::parse(&[HR_FLAG_RR_INTERVAL_PRESENT, 60]).is_err());
+    assert!(
+        HeartRateMeasurement::parse(&[HR_FLAG_RR_INTERVAL_PRESENT, 60, 0x00, 0x04, 0x00]).is_err()
+    );
+}
+
+#[test]
+fn test_heart_rate_server_registration() {
+    use crate::att::{AttServer, AttributeDatabase};
+    use crate::gatt::profiles::heart_rate::HEART_RATE_SERVICE_UUID;
+    use crate::gatt::{GattServer, HeartRateServer};
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::uuid::Uuid;
+    use std::sync::Arc;
+
+    let database = Arc::new(AttributeDatabase::new());
+    let att_server = Arc::new(AttServer::new(
+        Arc::new(L2capManager::new(ConnectionType::LE)),
+        database.clone(),
+    ));
+    let server = Arc::new(GattServer::new(att_server, database));
+
+    let heart_rate = HeartRateServer::register(server.clone()).unwrap();
+    let services = server.get_services();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].uuid, Uuid::from_u16(HEART_RATE_SERVICE_UUID));
+
+    let characteristics = server
+        .get_characteristics(heart_rate.service_handle())
+        .unwrap();
+    assert_eq!(characteristics.len(), 1);
+    assert_eq!(
+        characteristics[0].value_handle,
+        heart_rate.measurement_handle()
+    );
+    assert!(characteristics[0].properties.can_notify());
+}
+
+#[test]
+fn test_connection_params_validation() {
+    use crate::gatt::{ConnectionParams, GattError};
+
+    assert!(ConnectionParams::default().validate().is_ok());
+
+    // Low-power sensor: 1 s interval, skipping up to 4 events
+    let sensor = ConnectionParams {
+        interval_min: 800,
+        interval_max: 800,
+        latency: 4,
+        supervision_timeout: 1100,
+    };
+    assert!(sensor.validate().is_ok());
+
+    let reversed = ConnectionParams {
+        interval_min: 0x0020,
+        interval_max: 0x0010,
+        ..ConnectionParams::default()
+    };
+    assert!(matches!(
+        reversed.validate(),
+        Err(GattError::InvalidParameters(_))
+    ));
+
+    let high_latency = ConnectionParams {
+        latency: 500,
+        ..ConnectionParams::default()
+    };
+    assert!(high_latency.validate().is_err());
+
+    // (1 + 4) * 1 s * 2 = 10 s, so 10 s is not enough
+    let short_timeout = ConnectionParams {
+        supervision_timeout: 1000,
+        ..sensor
+    };
+    assert!(short_timeout.validate().is_err());
+}
+
+#[test]
+fn test_gatt_error_is_fatal() {
+    use crate::att::AttError;
+    use crate::gatt::GattError;
+    use std::io::{Error, ErrorKind};
+
+    assert!(!GattError::Timeout.is_fatal());
+    assert!(!GattError::HciIo(Error::from(ErrorKind::Interrupted)).is_fatal());
+    assert!(!GattError::HciIo(Error::from(ErrorKind::WouldBlock)).is_fatal());
+    assert!(!GattError::AttError(AttError::InvalidPdu).is_fatal());
+
+    assert!(GattError::NotConnected.is_fatal());
+    assert!(GattError::HciIo(Error::from(ErrorKind::UnexpectedEof)).is_fatal());
+    assert!(GattError::HciIo(Error::from_raw_os_error(libc::EBADF)).is_fatal());
+    assert!(GattError::AttError(AttError::InvalidState).is_fatal());
+    assert!(GattError::WriteFailed {
+        handle: 0x0010,
+        source: Box::new(GattError::NotConnected),
+    }
+    .is_fatal());
+}
+
+#[test]
+fn test_discover_all_with_progress_requires_connection() {
+    use crate::gatt::{GattClient, GattError};
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::testing::MockHciSocket;
+    use std::sync::{Arc, Mutex};
+
+    let hci = MockHciSocket::new().unwrap();
+    let l2cap = Arc::new(L2capManager::new(ConnectionType::LE));
+    let mut client = GattClient::new(hci.socket(), l2cap);
+
+    // Nothing is discovered, so no progress is reported
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let reports_clone = reports.clone();
+    let result = client
+        .discover_all_with_progress(move |progress| reports_clone.lock().unwrap().push(progress));
+    assert!(matches!(result, Err(GattError::NotConnected)));
+    assert!(reports.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_gatt_server_builder_assigns_handles() {
+    use crate::gatt::GattServerBuilder;
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::uuid::Uuid;
+    use std::sync::Arc;
+
+    let (server, handles) = GattServerBuilder::new()
+        .add_service(Uuid::from_u16(0x180F), |svc| {
+            svc.add_characteristic(Uuid::from_u16(0x2A19))
+                .notify()
+                .read()
+                .value(vec![100u8])
+        })
+        .add_service(Uuid::from_u16(0x180A), |svc| {
+            svc.add_characteristic(Uuid::from_u16(0x2A29))
+                .read()
+                .value(b"Acme".to_vec())
+                .add_characteristic(Uuid::from_u16(0x2A24))
+                .read()
+        })
+        .build(Arc::new(L2capManager::new(ConnectionType::LE)))
+        .unwrap();
+
+    // Battery: declaration, characteristic declaration, value and CCCD
+    let services = server.get_services();
+    assert_eq!(services.len(), 2);
+    assert_eq!(services[0].uuid, Uuid::from_u16(0x180F));
+    assert_eq!(
+        (services[0].start_handle, services[0].end_handle),
+        (0x0001, 0x0004)
+    );
+    assert_eq!(handles[&Uuid::from_u16(0x2A19)], 0x0003);
+    assert_eq!(server.get_characteristic_value(0x0003).unwrap(), vec![100]);
+
+    // Device Information follows without CCCDs
+    assert_eq!(
+        (services[1].start_handle, services[1].end_handle),
+        (0x0005, 0x0009)
+    );
+    assert_eq!(handles[&Uuid::from_u16(0x2A29)], 0x0007);
+    assert_eq!(handles[&Uuid::from_u16(0x2A24)], 0x0009);
+
+    let characteristics = server.get_characteristics(0x0001).unwrap();
+    assert_eq!(characteristics.len(), 1);
+    assert!(characteristics[0].properties.can_notify());
+    assert!(characteristics[0].properties.can_read());
+}
+
+#[test]
+fn test_gatt_server_builder_adds_cccd_for_indications() {
+    use crate::gatt::GattServerBuilder;
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::uuid::Uuid;
+    use std::sync::Arc;
+
+    let (server, handles) = GattServerBuilder::new()
+        .add_service(Uuid::from_u16(0x1801), |svc| {
+            svc.add_characteristic(Uuid::from_u16(0x2A05)).indicate()
+        })
+        .build(Arc::new(L2capManager::new(ConnectionType::LE)))
+        .unwrap();
+
+    // Service Changed value at 0x0003 followed by its CCCD
+    assert_eq!(handles[&Uuid::from_u16(0x2A05)], 0x0003);
+    let services = server.get_services();
+    assert_eq!(
+        (services[0].start_handle, services[0].end_handle),
+        (0x0001, 0x0004)
+    );
+}
+
+#[test]
+fn test_service_changed_invalidates_overlapping_services() {
+    use crate::gatt::client::{invalidate_handle_range, parse_service_changed};
+    use crate::gatt::{Characteristic, CharacteristicProperty, Descriptor, Service};
+    use crate::uuid::Uuid;
+    use std::collections::HashMap;
+
+    assert_eq!(
+        parse_service_changed(&[0x10, 0x00, 0x20, 0x00]),
+        Some((0x0010, 0x0020))
+    );
+    assert_eq!(parse_service_changed(&[0x20, 0x00, 0x10, 0x00]), None);
+    assert_eq!(parse_service_changed(&[0x10, 0x00]), None);
+
+    let service = |start_handle, end_handle| Service {
+        uuid: Uuid::from_u16(0x180F),
+        is_primary: true,
+        start_handle,
+        end_handle,
+    };
+    let characteristic = |declaration_handle: u16| Characteristic {
+        uuid: Uuid::from_u16(0x2A19),
+        declaration_handle,
+        value_handle: declaration_handle + 1,
+        properties: CharacteristicProperty::READ,
+    };
+    let descriptor = |handle| Descriptor {
+        handle,
+        uuid: Uuid::from_u16(0x2902),
+    };
+
+    let mut services = vec![service(0x0001, 0x0005), service(0x0010, 0x0018)];
+    let mut characteristics = HashMap::new();
+    characteristics.insert(0x0001, vec![characteristic(0x0002)]);
+    characteristics.insert(0x0010, vec![characteristic(0x0011)]);
+    let mut descriptors = HashMap::new();
+    descriptors.insert((0x0001, 0x0003), vec![descriptor(0x0004)]);
+    descriptors.insert((0x0010, 0x0012), vec![descriptor(0x0013)]);
+
+    invalidate_handle_range(
+        &mut services,
+        &mut characteristics,
+        &mut descriptors,
+        0x0015,
+        0xFFFF,
+    );
+
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].start_handle, 0x0001);
+    assert!(characteristics.contains_key(&0x0001));
+    assert!(!characteristics.contains_key(&0x0010));
+    assert!(descriptors.contains_key(&(0x0001, 0x0003)));
+    assert!(!descriptors.contains_key(&(0x0010, 0x0012)));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_process_events_routes_acl_to_l2cap() {
+    use crate::gap::BdAddr;
+    use crate::gatt::GattClient;
+    use crate::l2cap::constants::L2CAP_ATT_CID;
+    use crate::l2cap::{ConnectionType, L2capManager};
+    use crate::testing::MockHciSocket;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let hci = MockHciSocket::new().unwrap();
+    let l2cap = Arc::new(L2capManager::new(ConnectionType::LE));
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    l2cap.register_connection(0x0040, addr);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    l2cap
+        .register_fixed_channel_callback(L2CAP_ATT_CID, move |from, data| {
+            received_clone.lock().unwrap().push((from, data.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+
+    let mut client = GattClient::new(hci.socket(), l2cap);
+
+    // A Handle Value Notification split over two ACL fragments
+    hci.push_acl(0x2040, &[0x05, 0x00, 0x04, 0x00, 0x1B])
+        .unwrap();
+    hci.push_acl(0x1040, &[0x03, 0x00, 0xAA, 0xBB]).unwrap();
+    client.process_events(Some(Duration::from_secs(1))).unwrap();
+    assert!(received.lock().unwrap().is_empty());
+    client.process_events(Some(Duration::from_secs(1))).unwrap();
+
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![(addr, vec![0x1B, 0x03, 0x00, 0xAA, 0xBB])]
+    );
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_connection_manager_routes_events_by_connection_handle() {
+    use crate::gap::BdAddr;
+    use crate::gatt::{ConnectionManager, ConnectionState, GattClient};
+    use crate::l2cap::ConnectionType;
+    use crate::testing::MockL2capManager;
+    use std::time::Duration;
+
+    // Command Complete for LE Set Scan Parameters, then Command Status for
+    // LE Create Connection
+    const CONNECT_SCRIPT: [u8; 14] = [
+        0x04, 0x0E, 0x04, 0x01, 0x0B, 0x20, 0x00, 0x04, 0x0F, 0x04, 0x00, 0x01, 0x0D, 0x20,
+    ];
+
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    let first = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+    let second = [0x11, 0x12, 0x13, 0x14, 0x15, 0x16];
+
+    let mut manager = ConnectionManager::new();
+    for addr in [first, second] {
+        let mut client = GattClient::new(mock.hci().socket(), mock.manager());
+        // Nothing answers the MTU exchange, so don't start it on connection
+        client.set_require_encryption_before_discovery(true);
+        mock.hci().push_events(&CONNECT_SCRIPT).unwrap();
+        client.connect(addr, 0x00).unwrap();
+        manager.add_client(client);
+    }
+
+    let connection_complete = |handle: u16, addr: [u8; 6]| {
+        let mut event = vec![0x04, EVT_LE_META_EVENT, 19, EVT_LE_CONN_COMPLETE, 0x00];
+        event.extend_from_slice(&handle.to_le_bytes());
+        event.extend_from_slice(&[0x00, 0x00]);
+        event.extend_from_slice(&addr);
+        event.extend_from_slice(&[0x10, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+        event
+    };
+
+    // The second client's connection completes first
+    mock.hci()
+        .push_events(&connection_complete(0x0041, second))
+        .unwrap();
+    mock.hci()
+        .push_events(&connection_complete(0x0040, first))
+        .unwrap();
+    let timeout = Some(Duration::from_secs(1));
+    assert_eq!(manager.process_events(timeout).unwrap(), Some(1));
+    assert_eq!(manager.process_events(timeout).unwrap(), Some(0));
+    assert_eq!(manager.client(0).unwrap().connection_handle(), Some(0x0040));
+    assert_eq!(manager.client(1).unwrap().connection_handle(), Some(0x0041));
+    assert_eq!(
+        manager.client(1).unwrap().peer_address(),
+        Some(BdAddr::new(second))
+    );
+
+    // Disconnection Complete for the second client's connection
+    mock.hci()
+        .push_events(&[0x04, EVT_DISCONN_COMPLETE, 0x04, 0x00, 0x41, 0x00, 0x13])
+        .unwrap();
+    assert_eq!(manager.process_events(timeout).unwrap(), Some(1));
+    assert_eq!(
+        manager.client(1).unwrap().connection_state(),
+        ConnectionState::Disconnected
+    );
+    assert_eq!(
+        manager.client(0).unwrap().connection_state(),
+        ConnectionState::Connected
+    );
+}
+
+#[test]
+fn test_process_timeouts_retransmits_l2cap_requests() {
+    use crate::gap::BdAddr;
+    use crate::gatt::GattClient;
+    use crate::l2cap::constants::{L2CAP_LE_SIGNALING_CID, L2CAP_RTX_TIMEOUT_MS};
+    use crate::l2cap::{ConnectionType, PSM};
+    use crate::testing::MockL2capManager;
+    use std::time::Duration;
+
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]));
+    let client = GattClient::new(mock.hci().socket(), mock.manager());
+
+    mock.manager()
+        .connect(PSM::Dynamic(0x0081), 0x0040)
+        .unwrap();
+    assert_eq!(mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID).len(), 1);
+
+    // The unanswered connection request is sent again after the RTX timeout
+    client.process_timeouts().unwrap();
+    assert_eq!(mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID).len(), 1);
+    std::thread::sleep(Duration::from_millis(L2CAP_RTX_TIMEOUT_MS + 50));
+    client.process_timeouts().unwrap();
+
+    let sent = mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID);
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0], sent[1]);
+}
@@ -2,9 +2,8 @@
 //!
 //! This module defines the common types used for GATT operations.
 
-use crate::uuid::Uuid;
+pub use crate::uuid::Uuid;
 use bitflags::bitflags;
-use std::fmt;
 
 /// A GATT service
 #[derive(Debug, Clone)]
@@ -19,6 +18,15 @@ pub struct Service {
     pub end_handle: u16,
 }
 
+/// A GATT characteristic descriptor found during discovery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Descriptor {
+    /// Descriptor handle
+    pub handle: u16,
+    /// Descriptor UUID
+    pub uuid: Uuid,
+}
+
 /// A GATT characteristic
 #[derive(Debug, Clone)]
 pub struct Characteristic {
@@ -32,6 +40,46 @@ pub struct Characteristic {
     pub properties: CharacteristicProperty,
 }
 
+/// A discovered characteristic together with its descriptors
+#[derive(Debug, Clone)]
+pub struct FullCharacteristic {
+    /// The characteristic
+    pub characteristic: Characteristic,
+    /// Descriptors of the characteristic
+    pub descriptors: Vec<Descriptor>,
+}
+
+/// A discovered service together with its characteristics and descriptors
+#[derive(Debug, Clone)]
+pub struct FullService {
+    /// The service
+    pub service: Service,
+    /// Characteristics of the service, in handle order
+    pub characteristics: Vec<FullCharacteristic>,
+}
+
+/// Step of a full discovery that has just completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryPhase {
+    /// All primary and secondary services were found
+    Services,
+    /// The characteristics of the service with this index were found
+    Characteristics(usize),
+    /// The descriptors of a service were found, completing the service
+    Descriptors,
+}
+
+/// Progress of a full discovery, see `GattClient::discover_all_with_progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveryProgress {
+    /// Number of services found
+    pub services_found: usize,
+    /// Number of characteristics found so far
+    pub characteristics_found: usize,
+    /// Step that has just completed
+    pub phase: DiscoveryPhase,
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct CharacteristicProperty: u8 {
@@ -63,3 +111,165 @@ impl CharacteristicProperty {
         self.contains(CharacteristicProperty::INDICATE)
     }
 }
+
+/// Characteristic Presentation Format descriptor value (0x2904)
+///
+/// Describes how a characteristic value is encoded and which unit it uses.
+/// The actual value is `raw * 10^exponent` in the given unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharacteristicPresentationFormat {
+    /// Format of the value (one of the `FORMAT_*` constants)
+    pub format: u8,
+    /// Base 10 exponent applied to integer values
+    pub exponent: i8,
+    /// Unit UUID (one of the `UNIT_*` constants)
+    pub unit: u16,
+    /// Namespace of the description field
+    pub namespace: u8,
+    /// Description within the namespace
+    pub description: u16,
+}
+
+impl CharacteristicPresentationFormat {
+    pub const FORMAT_BOOLEAN: u8 = 0x01;
+    pub const FORMAT_UINT8: u8 = 0x04;
+    pub const FORMAT_UINT16: u8 = 0x06;
+    pub const FORMAT_UINT24: u8 = 0x07;
+    pub const FORMAT_UINT32: u8 = 0x08;
+    pub const FORMAT_SINT8: u8 = 0x0C;
+    pub const FORMAT_SINT16: u8 = 0x0E;
+    pub const FORMAT_SINT32: u8 = 0x10;
+    pub const FORMAT_FLOAT32: u8 = 0x14;
+    pub const FORMAT_FLOAT64: u8 = 0x15;
+    pub const FORMAT_SFLOAT: u8 = 0x16;
+    pub const FORMAT_FLOAT: u8 = 0x17;
+    pub const FORMAT_UTF8S: u8 = 0x19;
+    pub const FORMAT_UTF16S: u8 = 0x1A;
+    pub const FORMAT_STRUCT: u8 = 0x1B;
+
+    pub const UNIT_UNITLESS: u16 = 0x2700;
+    pub const UNIT_METRE: u16 = 0x2701;
+    pub const UNIT_KILOGRAM: u16 = 0x2702;
+    pub const UNIT_SECOND: u16 = 0x2703;
+    pub const UNIT_AMPERE: u16 = 0x2704;
+    pub const UNIT_KELVIN: u16 = 0x2705;
+    pub const UNIT_PASCAL: u16 = 0x2724;
+    pub const UNIT_VOLT: u16 = 0x2728;
+    pub const UNIT_CELSIUS: u16 = 0x272F;
+    pub const UNIT_LUX: u16 = 0x2731;
+    pub const UNIT_BEATS_PER_MINUTE: u16 = 0x27A7;
+    pub const UNIT_FAHRENHEIT: u16 = 0x27AC;
+    pub const UNIT_PERCENT: u16 = 0x27AD;
+
+    pub const NAMESPACE_BLUETOOTH_SIG: u8 = 0x01;
+    pub const DESCRIPTION_UNKNOWN: u16 = 0x0000;
+
+    /// Create a format in the Bluetooth SIG namespace with an unknown description
+    pub fn new(format: u8, exponent: i8, unit: u16) -> Self {
+        Self {
+            format,
+            exponent,
+            unit,
+            namespace: Self::NAMESPACE_BLUETOOTH_SIG,
+            description: Self::DESCRIPTION_UNKNOWN,
+        }
+    }
+
+    /// Serialize to the 7 byte descriptor value
+    pub fn serialize(&self) -> [u8; 7] {
+        let unit = self.unit.to_le_bytes();
+        let description = self.description.to_le_bytes();
+        [
+            self.format,
+            self.exponent as u8,
+            unit[0],
+            unit[1],
+            self.namespace,
+            description[0],
+            description[1],
+        ]
+    }
+
+    /// Parse the 7 byte descriptor value
+    pub fn parse(data: &[u8; 7]) -> Self {
+        Self {
+            format: data[0],
+            exponent: data[1] as i8,
+            unit: u16::from_le_bytes([data[2], data[3]]),
+            namespace: data[4],
+            description: u16::from_le_bytes([data[5], data[6]]),
+        }
+    }
+}
+
+/// Characteristic Extended Properties descriptor value (0x2900)
+///
+/// Present when a characteristic has the `EXTENDED_PROPERTIES` property set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharacteristicExtendedProperties {
+    /// Reliable writes (queued writes with verification) are permitted
+    pub reliable_write: bool,
+    /// The Characteristic User Description descriptor is writable
+    pub writable_auxiliaries: bool,
+}
+
+impl CharacteristicExtendedProperties {
+    pub const RELIABLE_WRITE: u16 = 0x0001;
+    pub const WRITABLE_AUXILIARIES: u16 = 0x0002;
+
+    /// Serialize to the 2 byte descriptor value
+    pub fn serialize(&self) -> [u8; 2] {
+        let mut bits = 0;
+        if self.reliable_write {
+            bits |= Self::RELIABLE_WRITE;
+        }
+        if self.writable_auxiliaries {
+            bits |= Self::WRITABLE_AUXILIARIES;
+        }
+        u16::to_le_bytes(bits)
+    }
+
+    /// Parse the 2 byte descriptor value, ignoring reserved bits
+    pub fn parse(data: &[u8; 2]) -> Self {
+        let bits = u16::from_le_bytes(*data);
+        Self {
+            reliable_write: bits & Self::RELIABLE_WRITE != 0,
+            writable_auxiliaries: bits & Self::WRITABLE_AUXILIARIES != 0,
+        }
+    }
+}
+
+/// LE PHY used by a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhyType {
+    /// LE 1M PHY
+    Le1M = 0x01,
+    /// LE 2M PHY
+    Le2M = 0x02,
+    /// LE Coded PHY
+    LeCoded = 0x03,
+}
+
+impl PhyType {
+    /// Convert from the PHY value used in HCI events
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(PhyType::Le1M),
+            0x02 => Some(PhyType::Le2M),
+            0x03 => Some(PhyType::LeCoded),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// PHYs preferred for one direction of a connection
+    ///
+    /// An empty set means the host has no preference.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PhyPreference: u8 {
+        const LE_1M = 0x01;
+        const LE_2M = 0x02;
+        const LE_CODED = 0x04;
+    }
+}
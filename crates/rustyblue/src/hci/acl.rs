@@ -0,0 +1,267 @@
+//! HCI ACL data packet handling
+//!
+//! This module provides fragmentation of upper-layer PDUs into HCI ACL data
+//! packets that fit within the controller's advertised buffer size, and
+//! parsing of received ACL data packets.
+
+use crate::error::HciError;
+use crate::hci::constants::*;
+use crate::hci::packet::{HciEvent, ParsedHciEvent};
+use crate::hci::socket::HciSocket;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A received HCI ACL data packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclData {
+    /// Connection handle
+    pub handle: u16,
+    /// Packet boundary flag
+    pub pb_flag: u8,
+    /// Broadcast flag
+    pub bc_flag: u8,
+    /// Data, a complete L2CAP frame or a fragment of one
+    pub data: Vec<u8>,
+}
+
+impl AclData {
+    /// Parse an ACL data packet, after the packet type indicator
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < HCI_ACL_HEADER_SIZE {
+            return None;
+        }
+
+        let handle_and_flags = u16::from_le_bytes([packet[0], packet[1]]);
+        let length = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+        let data = packet.get(HCI_ACL_HEADER_SIZE..HCI_ACL_HEADER_SIZE + length)?;
+
+        Some(Self {
+            handle: handle_and_flags & 0x0FFF,
+            pb_flag: ((handle_and_flags >> 12) & 0x03) as u8,
+            bc_flag: ((handle_and_flags >> 14) & 0x03) as u8,
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Size and number of the controller's ACL data buffers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferInfo {
+    /// Maximum length of the data portion of each HCI ACL data packet
+    pub acl_data_packet_length: u16,
+    /// Total number of HCI ACL data packets the controller can buffer
+    pub total_num_acl_data_packets: u16,
+}
+
+impl BufferInfo {
+    /// Parse the return parameters of HCI Read Buffer Size, after the status
+    pub fn from_read_buffer_size(params: &[u8]) -> Option<Self> {
+        // ACL length (2), SCO length (1), ACL count (2), SCO count (2)
+        if params.len() < 7 {
+            return None;
+        }
+
+        Some(Self {
+            acl_data_packet_length: u16::from_le_bytes([params[0], params[1]]),
+            total_num_acl_data_packets: u16::from_le_bytes([params[3], params[4]]),
+        })
+    }
+
+    /// Parse the return parameters of HCI LE Read Buffer Size, after the status
+    pub fn from_le_read_buffer_size(params: &[u8]) -> Option<Self> {
+        // LE ACL length (2), LE ACL count (1)
+        if params.len() < 3 {
+            return None;
+        }
+
+        Some(Self {
+            acl_data_packet_length: u16::from_le_bytes([params[0], params[1]]),
+            total_num_acl_data_packets: params[2] as u16,
+        })
+    }
+
+    /// Whether the controller reported no buffers of this kind
+    pub fn is_empty(&self) -> bool {
+        self.acl_data_packet_length == 0 || self.total_num_acl_data_packets == 0
+    }
+}
+
+/// Buffer information reported by the local controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalControllerInfo {
+    /// BR/EDR ACL buffers, if the controller reported them
+    pub classic_buffer: Option<BufferInfo>,
+    /// Buffers used for ACL data on LE connections
+    ///
+    /// Controllers without dedicated LE buffers share the BR/EDR ones, in
+    /// which case this is a copy of `classic_buffer`.
+    pub le_buffer: BufferInfo,
+}
+
+/// Splits outgoing ACL payloads into controller-sized HCI ACL data packets
+#[derive(Debug, Clone, Copy)]
+pub struct AclFragmenter {
+    max_fragment_size: usize,
+}
+
+impl AclFragmenter {
+    /// Create a fragmenter using the ACL buffer size from the controller
+    pub fn new(info: &BufferInfo) -> Self {
+        Self {
+            max_fragment_size: info.acl_data_packet_length as usize,
+        }
+    }
+
+    /// Maximum number of payload bytes carried by each ACL packet
+    pub fn max_fragment_size(&self) -> usize {
+        self.max_fragment_size
+    }
+
+    /// Split `data` into complete HCI ACL data packets for `handle`
+    ///
+    /// The first packet is sent with PB=0x02 (first automatically flushable)
+    /// and every following packet with PB=0x01 (continuing fragment). Each
+    /// returned packet includes the HCI packet type indicator.
+    pub fn fragment(&self, handle: u16, data: &[u8]) -> Result<Vec<Vec<u8>>, HciError> {
+        if self.max_fragment_size == 0 {
+            return Err(HciError::InvalidParamLength(data.len()));
+        }
+
+        if handle > 0x0EFF {
+            return Err(HciError::InvalidPacketFormat);
+        }
+
+        if data.is_empty() {
+            return Ok(vec![Self::build_packet(
+                handle,
+                ACL_PB_FIRST_FLUSHABLE,
+                &[],
+            )]);
+        }
+
+        let packets = data
+            .chunks(self.max_fragment_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let pb_flag = if index == 0 {
+                    ACL_PB_FIRST_FLUSHABLE
+                } else {
+                    ACL_PB_CONTINUING
+                };
+                Self::build_packet(handle, pb_flag, chunk)
+            })
+            .collect();
+
+        Ok(packets)
+    }
+
+    /// Fragment `data` and write each ACL packet to the socket in order
+    pub fn send(&self, socket: &HciSocket, handle: u16, data: &[u8]) -> Result<(), HciError> {
+        for packet in self.fragment(handle, data)? {
+            socket.write_packet(&packet)?;
+        }
+        Ok(())
+    }
+
+    fn build_packet(handle: u16, pb_flag: u8, payload: &[u8]) -> Vec<u8> {
+        let handle_and_flags = (handle & 0x0FFF)
+            | ((pb_flag as u16 & 0x03) << 12)
+            | ((ACL_BC_POINT_TO_POINT as u16 & 0x03) << 14);
+
+        let mut packet = Vec::with_capacity(1 + HCI_ACL_HEADER_SIZE + payload.len());
+        packet.push(HCI_ACL_PKT);
+        packet.extend_from_slice(&handle_and_flags.to_le_bytes());
+        packet.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+/// Tracks the controller's free ACL data buffers
+///
+/// Each ACL packet sent to the controller uses one buffer until the
+/// controller reports it with a Number Of Completed Packets event.
+#[derive(Debug)]
+pub struct AclFlowController {
+    total_packets: u16,
+    /// Packets sent but not yet completed, by connection handle
+    in_flight: Mutex<HashMap<u16, u16>>,
+}
+
+impl AclFlowController {
+    /// Create a flow controller using the ACL buffer count from the controller
+    pub fn new(info: &BufferInfo) -> Self {
+        Self {
+            total_packets: info.total_num_acl_data_packets,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of ACL packets that can be sent right now
+    pub fn available(&self) -> u16 {
+        let in_flight = self.in_flight.lock().unwrap();
+        let used: u16 = in_flight.values().sum();
+        self.total_packets.saturating_sub(used)
+    }
+
+    /// Number of packets sent on a connection and not yet completed
+    pub fn in_flight(&self, handle: u16) -> u16 {
+        let in_flight = self.in_flight.lock().unwrap();
+        in_flight.get(&handle).copied().unwrap_or(0)
+    }
+
+    /// Take a buffer for one ACL packet on `handle`
+    ///
+    /// Returns `false` if the controller has no free buffers.
+    pub fn try_acquire(&self, handle: u16) -> bool {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let used: u16 = in_flight.values().sum();
+        if used >= self.total_packets {
+            return false;
+        }
+        *in_flight.entry(handle).or_insert(0) += 1;
+        true
+    }
+
+    /// Return buffers for packets the controller completed on `handle`
+    ///
+    /// Returns the number of buffers actually restored, which is capped at
+    /// the number in flight on that connection.
+    pub fn restore(&self, handle: u16, count: u16) -> u16 {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let Some(used) = in_flight.get_mut(&handle) else {
+            return 0;
+        };
+        let restored = count.min(*used);
+        *used -= restored;
+        if *used == 0 {
+            in_flight.remove(&handle);
+        }
+        restored
+    }
+
+    /// Return all buffers held by a connection that has been closed
+    ///
+    /// The controller flushes the packets of a disconnected link without
+    /// reporting them as completed.
+    pub fn connection_closed(&self, handle: u16) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.remove(&handle);
+    }
+
+    /// Restore buffers from a Number Of Completed Packets event
+    ///
+    /// Returns `true` if any buffers were restored. Other events are ignored.
+    pub fn handle_event(&self, event: &HciEvent) -> bool {
+        match event.parsed() {
+            Some(ParsedHciEvent::NumberOfCompletedPackets(completed)) => {
+                completed
+                    .into_iter()
+                    .map(|(handle, count)| self.restore(handle, count))
+                    .sum::<u16>()
+                    > 0
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! btsnoop packet capture
+//!
+//! Writes HCI traffic in the btsnoop format used by Android and BlueZ, which
+//! Wireshark opens directly. Packets are stored with their H4 packet type
+//! indicator, as datalink type 1002 (HCI UART) expects.
+
+use crate::hci::constants::{HCI_COMMAND_PKT, HCI_EVENT_PKT};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File identification pattern
+const BTSNOOP_MAGIC: &[u8; 8] = b"btsnoop\0";
+/// Format version
+const BTSNOOP_VERSION: u32 = 1;
+/// Datalink type for packets with an H4 packet type indicator
+const BTSNOOP_DATALINK_HCI_UART: u32 = 1002;
+/// Microseconds from midnight January 1st, 0 AD to the Unix epoch
+const BTSNOOP_EPOCH_OFFSET_US: i64 = 0x00DC_DDB3_0F2F_8000;
+
+/// Record flag set for packets received from the controller
+const FLAG_RECEIVED: u32 = 0x01;
+/// Record flag set for commands and events, clear for data
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+/// Writer for a btsnoop capture file
+#[derive(Debug)]
+pub(crate) struct BtsnoopWriter {
+    file: BufWriter<File>,
+}
+
+impl BtsnoopWriter {
+    /// Create or truncate the file at `path` and write the file header
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(BTSNOOP_MAGIC)?;
+        file.write_all(&BTSNOOP_VERSION.to_be_bytes())?;
+        file.write_all(&BTSNOOP_DATALINK_HCI_UART.to_be_bytes())?;
+        Ok(Self { file })
+    }
+
+    /// Append a packet, starting with its packet type indicator
+    pub(crate) fn write_packet(&mut self, packet: &[u8], received: bool) -> std::io::Result<()> {
+        let mut flags = 0;
+        if received {
+            flags |= FLAG_RECEIVED;
+        }
+        if matches!(
+            packet.first(),
+            Some(&HCI_COMMAND_PKT) | Some(&HCI_EVENT_PKT)
+        ) {
+            flags |= FLAG_COMMAND_OR_EVENT;
+        }
+
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = BTSNOOP_EPOCH_OFFSET_US + since_epoch.as_micros() as i64;
+
+        let length = packet.len() as u32;
+        self.file.write_all(&length.to_be_bytes())?; // Original length
+        self.file.write_all(&length.to_be_bytes())?; // Included length
+        self.file.write_all(&flags.to_be_bytes())?;
+        self.file.write_all(&0u32.to_be_bytes())?; // Cumulative drops
+        self.file.write_all(&timestamp.to_be_bytes())?;
+        self.file.write_all(packet)
+    }
+
+    /// Write buffered records to the file
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
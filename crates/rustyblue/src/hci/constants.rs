@@ -1,62 +1,333 @@
 //! HCI protocol constants
 //!
 //! This module contains constants used in the Bluetooth HCI protocol.
+//! Names follow the Bluetooth Core Specification, Vol 4, Part E.
 
 // HCI packet types
+/// HCI Command packet
 pub const HCI_COMMAND_PKT: u8 = 0x01;
+/// HCI ACL Data packet
 pub const HCI_ACL_PKT: u8 = 0x02;
+/// HCI Synchronous Data packet
 pub const HCI_SCO_PKT: u8 = 0x03;
+/// HCI Event packet
 pub const HCI_EVENT_PKT: u8 = 0x04;
+/// HCI ISO Data packet
 pub const HCI_ISO_PKT: u8 = 0x05;
 
-// Maximum size of HCI command parameters
+/// Maximum size of HCI command parameters
 pub const HCI_MAX_PARAM_LEN: usize = 255;
 
-// Common OGF (Opcode Group Field) values
+/// Maximum size of LE advertising and scan response data
+pub const LE_MAX_ADVERTISING_DATA_LEN: usize = 31;
+
+// ACL data packet header and flags
+/// Size of the ACL data header: handle and flags (2), data total length (2)
+pub const HCI_ACL_HEADER_SIZE: usize = 4;
+/// Packet Boundary flag: first non-automatically-flushable packet
+pub const ACL_PB_FIRST_NON_FLUSHABLE: u8 = 0x00;
+/// Packet Boundary flag: continuing fragment
+pub const ACL_PB_CONTINUING: u8 = 0x01;
+/// Packet Boundary flag: first automatically flushable packet
+pub const ACL_PB_FIRST_FLUSHABLE: u8 = 0x02;
+/// Broadcast flag: point-to-point
+pub const ACL_BC_POINT_TO_POINT: u8 = 0x00;
+
+// Command opcode layout
+/// Bit position of the OGF in a command opcode
+pub const HCI_OPCODE_OGF_SHIFT: u16 = 10;
+/// Mask of the OCF in a command opcode
+pub const HCI_OPCODE_OCF_MASK: u16 = 0x03FF;
+
+/// Combine an OGF and OCF into a command opcode
+pub const fn hci_opcode(ogf: u8, ocf: u16) -> u16 {
+    ((ogf as u16) << HCI_OPCODE_OGF_SHIFT) | (ocf & HCI_OPCODE_OCF_MASK)
+}
+
+// OGF (Opcode Group Field) values
+/// Link Control commands
 pub const OGF_LINK_CTL: u8 = 0x01;
+/// Link Policy commands
 pub const OGF_LINK_POLICY: u8 = 0x02;
+/// Controller & Baseband commands
 pub const OGF_HOST_CTL: u8 = 0x03;
+/// Informational Parameters commands
 pub const OGF_INFO_PARAM: u8 = 0x04;
+/// Status Parameters commands
 pub const OGF_STATUS_PARAM: u8 = 0x05;
+/// Testing commands
+pub const OGF_TESTING: u8 = 0x06;
+/// LE Controller commands
 pub const OGF_LE: u8 = 0x08;
+/// Vendor-specific commands
+pub const OGF_VENDOR: u8 = 0x3F;
 
 // Link Control Commands (OGF: 0x01)
+/// Inquiry command
 pub const OCF_INQUIRY: u16 = 0x0001;
+/// Inquiry Cancel command
 pub const OCF_INQUIRY_CANCEL: u16 = 0x0002;
+/// Create Connection command
 pub const OCF_CREATE_CONNECTION: u16 = 0x0005;
+/// Disconnect command
 pub const OCF_DISCONNECT: u16 = 0x0006;
 
 // Link Policy Commands (OGF: 0x02)
+/// Sniff Mode command
 pub const OCF_SNIFF_MODE: u16 = 0x0003;
+/// Exit Sniff Mode command
 pub const OCF_EXIT_SNIFF_MODE: u16 = 0x0004;
 
-// Host Controller Commands (OGF: 0x03)
-pub const OCF_RESET: u16 = 0x0003;
+// Controller & Baseband Commands (OGF: 0x03)
+/// Set Event Mask command
 pub const OCF_SET_EVENT_MASK: u16 = 0x0001;
+/// Reset command
+pub const OCF_RESET: u16 = 0x0003;
+/// Write Local Name command
+pub const OCF_WRITE_LOCAL_NAME: u16 = 0x0013;
+/// Read Local Name command
+pub const OCF_READ_LOCAL_NAME: u16 = 0x0014;
+/// Write Scan Enable command
+pub const OCF_WRITE_SCAN_ENABLE: u16 = 0x001A;
+
+// Informational Parameters (OGF: 0x04)
+/// Read Local Version Information command
+pub const OCF_READ_LOCAL_VERSION: u16 = 0x0001;
+/// Read Local Supported Commands command
+pub const OCF_READ_LOCAL_COMMANDS: u16 = 0x0002;
+/// Read Local Supported Features command
+pub const OCF_READ_LOCAL_FEATURES: u16 = 0x0003;
+/// Read Buffer Size command
+pub const OCF_READ_BUFFER_SIZE: u16 = 0x0005;
+/// Read BD_ADDR command
+pub const OCF_READ_BD_ADDR: u16 = 0x0009;
+
+// Status Parameters (OGF: 0x05)
+/// Read RSSI command
+pub const OCF_READ_RSSI: u16 = 0x0005;
 
-// LE Command OCF values (OGF: 0x08)
+// LE Controller Commands (OGF: 0x08)
+/// LE Set Event Mask command
 pub const OCF_LE_SET_EVENT_MASK: u16 = 0x0001;
+/// LE Read Buffer Size command
 pub const OCF_LE_READ_BUFFER_SIZE: u16 = 0x0002;
+/// LE Read Local Supported Features command
 pub const OCF_LE_READ_LOCAL_SUPPORTED_FEATURES: u16 = 0x0003;
+/// LE Set Random Address command
 pub const OCF_LE_SET_RANDOM_ADDRESS: u16 = 0x0005;
+/// LE Set Advertising Parameters command
 pub const OCF_LE_SET_ADVERTISING_PARAMETERS: u16 = 0x0006;
+/// LE Read Advertising Physical Channel Tx Power command
 pub const OCF_LE_READ_ADVERTISING_PHYSICAL_CHANNEL_TX_POWER: u16 = 0x0007;
+/// LE Set Advertising Data command
 pub const OCF_LE_SET_ADVERTISING_DATA: u16 = 0x0008;
+/// LE Set Scan Response Data command
 pub const OCF_LE_SET_SCAN_RESPONSE_DATA: u16 = 0x0009;
+/// LE Set Advertising Enable command
 pub const OCF_LE_SET_ADVERTISING_ENABLE: u16 = 0x000A;
+/// LE Set Scan Parameters command
 pub const OCF_LE_SET_SCAN_PARAMETERS: u16 = 0x000B;
+/// LE Set Scan Enable command
 pub const OCF_LE_SET_SCAN_ENABLE: u16 = 0x000C;
+/// LE Create Connection command
 pub const OCF_LE_CREATE_CONNECTION: u16 = 0x000D;
+/// LE Create Connection Cancel command
 pub const OCF_LE_CREATE_CONNECTION_CANCEL: u16 = 0x000E;
+/// LE Connection Update command
+pub const OCF_LE_CONNECTION_UPDATE: u16 = 0x0013;
+/// LE Encrypt command
+pub const OCF_LE_ENCRYPT: u16 = 0x0017;
+/// LE Rand command
+pub const OCF_LE_RAND: u16 = 0x0018;
+/// LE Enable Encryption command
+pub const OCF_LE_ENABLE_ENCRYPTION: u16 = 0x0019;
+/// LE Long Term Key Request Reply command
+pub const OCF_LE_LTK_REQUEST_REPLY: u16 = 0x001A;
+/// LE Long Term Key Request Negative Reply command
+pub const OCF_LE_LTK_REQUEST_NEGATIVE_REPLY: u16 = 0x001B;
+/// LE Set PHY command
+pub const OCF_LE_SET_PHY: u16 = 0x0032;
+/// LE Set Periodic Advertising Parameters command
+pub const OCF_LE_SET_PERIODIC_ADVERTISING_PARAMETERS: u16 = 0x003E;
+/// LE Set Periodic Advertising Data command
+pub const OCF_LE_SET_PERIODIC_ADVERTISING_DATA: u16 = 0x003F;
+/// LE Set Periodic Advertising Enable command
+pub const OCF_LE_SET_PERIODIC_ADVERTISING_ENABLE: u16 = 0x0040;
+/// LE Periodic Advertising Create Sync command
+pub const OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC: u16 = 0x0044;
+/// LE Periodic Advertising Create Sync Cancel command
+pub const OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC_CANCEL: u16 = 0x0045;
+/// LE Periodic Advertising Terminate Sync command
+pub const OCF_LE_PERIODIC_ADVERTISING_TERMINATE_SYNC: u16 = 0x0046;
 
 // HCI Events
+/// Inquiry Complete event
+pub const EVT_INQUIRY_COMPLETE: u8 = 0x01;
+/// Inquiry Result event
+pub const EVT_INQUIRY_RESULT: u8 = 0x02;
+/// Connection Complete event
+pub const EVT_CONN_COMPLETE: u8 = 0x03;
+/// Connection Request event
+pub const EVT_CONN_REQUEST: u8 = 0x04;
+/// Disconnection Complete event
 pub const EVT_DISCONN_COMPLETE: u8 = 0x05;
+/// Authentication Complete event
+pub const EVT_AUTH_COMPLETE: u8 = 0x06;
+/// Remote Name Request Complete event
+pub const EVT_REMOTE_NAME_REQ_COMPLETE: u8 = 0x07;
+/// Encryption Change [v1] event
 pub const EVT_ENCRYPTION_CHANGE: u8 = 0x08;
+/// Change Connection Link Key Complete event
+pub const EVT_CHANGE_CONN_LINK_KEY_COMPLETE: u8 = 0x09;
+/// Link Key Type Changed event
+pub const EVT_LINK_KEY_TYPE_CHANGED: u8 = 0x0A;
+/// Read Remote Supported Features Complete event
+pub const EVT_READ_REMOTE_FEATURES_COMPLETE: u8 = 0x0B;
+/// Read Remote Version Information Complete event
+pub const EVT_READ_REMOTE_VERSION_COMPLETE: u8 = 0x0C;
+/// QoS Setup Complete event
+pub const EVT_QOS_SETUP_COMPLETE: u8 = 0x0D;
+/// Command Complete event
 pub const EVT_CMD_COMPLETE: u8 = 0x0E;
+/// Command Status event
 pub const EVT_CMD_STATUS: u8 = 0x0F;
+/// Hardware Error event
+pub const EVT_HARDWARE_ERROR: u8 = 0x10;
+/// Flush Occurred event
+pub const EVT_FLUSH_OCCURRED: u8 = 0x11;
+/// Role Change event
+pub const EVT_ROLE_CHANGE: u8 = 0x12;
+/// Number Of Completed Packets event
+pub const EVT_NUM_COMP_PKTS: u8 = 0x13;
+/// Mode Change event
+pub const EVT_MODE_CHANGE: u8 = 0x14;
+/// Return Link Keys event
+pub const EVT_RETURN_LINK_KEYS: u8 = 0x15;
+/// PIN Code Request event
+pub const EVT_PIN_CODE_REQ: u8 = 0x16;
+/// Link Key Request event
+pub const EVT_LINK_KEY_REQ: u8 = 0x17;
+/// Link Key Notification event
+pub const EVT_LINK_KEY_NOTIFY: u8 = 0x18;
+/// Loopback Command event
+pub const EVT_LOOPBACK_COMMAND: u8 = 0x19;
+/// Data Buffer Overflow event
+pub const EVT_DATA_BUFFER_OVERFLOW: u8 = 0x1A;
+/// Max Slots Change event
+pub const EVT_MAX_SLOTS_CHANGE: u8 = 0x1B;
+/// Read Clock Offset Complete event
+pub const EVT_READ_CLOCK_OFFSET_COMPLETE: u8 = 0x1C;
+/// Connection Packet Type Changed event
+pub const EVT_CONN_PTYPE_CHANGED: u8 = 0x1D;
+/// QoS Violation event
+pub const EVT_QOS_VIOLATION: u8 = 0x1E;
+/// Page Scan Repetition Mode Change event
+pub const EVT_PSCAN_REP_MODE_CHANGE: u8 = 0x20;
+/// Flow Specification Complete event
+pub const EVT_FLOW_SPEC_COMPLETE: u8 = 0x21;
+/// Inquiry Result with RSSI event
+pub const EVT_INQUIRY_RESULT_WITH_RSSI: u8 = 0x22;
+/// Read Remote Extended Features Complete event
+pub const EVT_READ_REMOTE_EXT_FEATURES_COMPLETE: u8 = 0x23;
+/// Synchronous Connection Complete event
+pub const EVT_SYNC_CONN_COMPLETE: u8 = 0x2C;
+/// Synchronous Connection Changed event
+pub const EVT_SYNC_CONN_CHANGED: u8 = 0x2D;
+/// Sniff Subrating event
+pub const EVT_SNIFF_SUBRATING: u8 = 0x2E;
+/// Extended Inquiry Result event
+pub const EVT_EXTENDED_INQUIRY_RESULT: u8 = 0x2F;
+/// Encryption Key Refresh Complete event
+pub const EVT_ENCRYPT_KEY_REFRESH_COMPLETE: u8 = 0x30;
+/// IO Capability Request event
+pub const EVT_IO_CAPABILITY_REQUEST: u8 = 0x31;
+/// IO Capability Response event
+pub const EVT_IO_CAPABILITY_RESPONSE: u8 = 0x32;
+/// User Confirmation Request event
+pub const EVT_USER_CONFIRM_REQUEST: u8 = 0x33;
+/// User Passkey Request event
+pub const EVT_USER_PASSKEY_REQUEST: u8 = 0x34;
+/// Remote OOB Data Request event
+pub const EVT_REMOTE_OOB_DATA_REQUEST: u8 = 0x35;
+/// Simple Pairing Complete event
+pub const EVT_SIMPLE_PAIRING_COMPLETE: u8 = 0x36;
+/// Link Supervision Timeout Changed event
+pub const EVT_LINK_SUPERVISION_TIMEOUT_CHANGED: u8 = 0x38;
+/// Enhanced Flush Complete event
+pub const EVT_ENHANCED_FLUSH_COMPLETE: u8 = 0x39;
+/// User Passkey Notification event
+pub const EVT_USER_PASSKEY_NOTIFY: u8 = 0x3B;
+/// Keypress Notification event
+pub const EVT_KEYPRESS_NOTIFY: u8 = 0x3C;
+/// Remote Host Supported Features Notification event
+pub const EVT_REMOTE_HOST_FEATURES_NOTIFY: u8 = 0x3D;
+/// LE Meta event
 pub const EVT_LE_META_EVENT: u8 = 0x3E;
+/// Number Of Completed Data Blocks event
+pub const EVT_NUM_COMP_BLOCKS: u8 = 0x48;
+/// Triggered Clock Capture event
+pub const EVT_TRIGGERED_CLOCK_CAPTURE: u8 = 0x4E;
+/// Synchronization Train Complete event
+pub const EVT_SYNC_TRAIN_COMPLETE: u8 = 0x4F;
+/// Synchronization Train Received event
+pub const EVT_SYNC_TRAIN_RECEIVED: u8 = 0x50;
+/// Connectionless Peripheral Broadcast Receive event
+pub const EVT_CPB_RECEIVE: u8 = 0x51;
+/// Connectionless Peripheral Broadcast Timeout event
+pub const EVT_CPB_TIMEOUT: u8 = 0x52;
+/// Truncated Page Complete event
+pub const EVT_TRUNCATED_PAGE_COMPLETE: u8 = 0x53;
+/// Peripheral Page Response Timeout event
+pub const EVT_PERIPHERAL_PAGE_RESPONSE_TIMEOUT: u8 = 0x54;
+/// Connectionless Peripheral Broadcast Channel Map Change event
+pub const EVT_CPB_CHANNEL_MAP_CHANGE: u8 = 0x55;
+/// Inquiry Response Notification event
+pub const EVT_INQUIRY_RESPONSE_NOTIFY: u8 = 0x56;
+/// Authenticated Payload Timeout Expired event
+pub const EVT_AUTH_PAYLOAD_TIMEOUT_EXPIRED: u8 = 0x57;
+/// SAM Status Change event
+pub const EVT_SAM_STATUS_CHANGE: u8 = 0x58;
+/// Encryption Change [v2] event
+pub const EVT_ENCRYPTION_CHANGE_V2: u8 = 0x59;
+/// Vendor-specific event
+pub const EVT_VENDOR: u8 = 0xFF;
 
 // LE Meta Events
+/// LE Connection Complete subevent
 pub const EVT_LE_CONN_COMPLETE: u8 = 0x01;
+/// LE Advertising Report subevent
 pub const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
+/// LE Connection Update Complete subevent
 pub const EVT_LE_CONN_UPDATE_COMPLETE: u8 = 0x03;
+/// LE Read Remote Features Complete subevent
+pub const EVT_LE_READ_REMOTE_FEATURES_COMPLETE: u8 = 0x04;
+/// LE Long Term Key Request subevent
+pub const EVT_LE_LTK_REQUEST: u8 = 0x05;
+/// LE Remote Connection Parameter Request subevent
+pub const EVT_LE_REMOTE_CONN_PARAM_REQUEST: u8 = 0x06;
+/// LE Data Length Change subevent
+pub const EVT_LE_DATA_LENGTH_CHANGE: u8 = 0x07;
+/// LE Enhanced Connection Complete [v1] subevent
+pub const EVT_LE_ENHANCED_CONN_COMPLETE: u8 = 0x0A;
+/// LE Directed Advertising Report subevent
+pub const EVT_LE_DIRECTED_ADVERTISING_REPORT: u8 = 0x0B;
+/// LE PHY Update Complete subevent
+pub const EVT_LE_PHY_UPDATE_COMPLETE: u8 = 0x0C;
+/// LE Extended Advertising Report subevent
+pub const EVT_LE_EXTENDED_ADVERTISING_REPORT: u8 = 0x0D;
+/// LE Periodic Advertising Sync Established [v1] subevent
+pub const EVT_LE_PERIODIC_ADV_SYNC_ESTABLISHED: u8 = 0x0E;
+/// LE Periodic Advertising Report [v1] subevent
+pub const EVT_LE_PERIODIC_ADV_REPORT: u8 = 0x0F;
+/// LE Periodic Advertising Sync Lost subevent
+pub const EVT_LE_PERIODIC_ADV_SYNC_LOST: u8 = 0x10;
+
+/// LE Advertising Report event types
+pub const LE_ADV_REPORT_ADV_IND: u8 = 0x00;
+pub const LE_ADV_REPORT_ADV_DIRECT_IND: u8 = 0x01;
+pub const LE_ADV_REPORT_ADV_SCAN_IND: u8 = 0x02;
+pub const LE_ADV_REPORT_ADV_NONCONN_IND: u8 = 0x03;
+pub const LE_ADV_REPORT_SCAN_RSP: u8 = 0x04;
+
+/// LE Advertising Report RSSI value meaning "not available"
+pub const LE_RSSI_NOT_AVAILABLE: u8 = 0x7F;
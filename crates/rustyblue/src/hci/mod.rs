@@ -2,14 +2,26 @@
 //!
 //! This module provides functionality for interacting with HCI interfaces.
 
+pub mod acl;
+#[cfg(feature = "capture")]
+mod capture;
 pub mod constants;
+#[cfg(feature = "blocking")]
+pub mod multiplexer;
 pub mod packet;
 pub mod socket;
 // pub mod types; // Removed - types.rs does not exist
-// pub mod acl;   // Removed - acl.rs does not exist
 
 #[cfg(test)]
 mod tests;
 
-pub use packet::{HciCommand, HciEvent, LeAdvertisingReport};
+pub use acl::{AclData, AclFlowController, AclFragmenter, BufferInfo, LocalControllerInfo};
+#[cfg(feature = "blocking")]
+pub use multiplexer::EventMultiplexer;
+pub use packet::{
+    EncryptionChange, HciCommand, HciEvent, HciPacket, LeAdvertisingReport, LeConnectionEvent,
+    ParsedHciEvent,
+};
 pub use socket::HciSocket;
+#[cfg(feature = "tokio")]
+pub use socket::HciSocketAsync;
@@ -0,0 +1,117 @@
+//! HCI event multiplexing
+//!
+//! This module waits for HCI events on several sockets at once, so a single
+//! thread can service multiple adapters or connections.
+
+use crate::error::HciError;
+use crate::hci::packet::HciEvent;
+use crate::hci::socket::HciSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Waits for events on a set of HCI sockets using `poll`
+#[derive(Debug, Default)]
+pub struct EventMultiplexer {
+    sockets: Vec<Arc<HciSocket>>,
+    /// Index to start scanning from, so a busy socket can't starve the others
+    next_index: usize,
+}
+
+impl EventMultiplexer {
+    /// Create a multiplexer over the given sockets
+    ///
+    /// Sockets are indexed in the order given, with repeats of the same
+    /// `Arc` sharing the index of its first occurrence.
+    pub fn new(sockets: Vec<Arc<HciSocket>>) -> Self {
+        let mut multiplexer = Self::default();
+        for socket in sockets {
+            multiplexer.add_socket(socket);
+        }
+        multiplexer
+    }
+
+    /// Add a socket, returning the index its events will be reported with
+    ///
+    /// A socket that is already being multiplexed keeps its index, so its
+    /// fd is only polled once.
+    pub fn add_socket(&mut self, socket: Arc<HciSocket>) -> usize {
+        if let Some(index) = self.sockets.iter().position(|s| Arc::ptr_eq(s, &socket)) {
+            return index;
+        }
+        self.sockets.push(socket);
+        self.sockets.len() - 1
+    }
+
+    /// Get the sockets being multiplexed
+    pub fn sockets(&self) -> &[Arc<HciSocket>] {
+        &self.sockets
+    }
+
+    /// Wait for the next event on any of the sockets
+    ///
+    /// Returns the index of the socket the event was read from along with
+    /// the event. A `None` timeout waits indefinitely. On timeout a
+    /// `ReceiveError` with `ErrorKind::TimedOut` is returned, as with
+    /// `HciSocket::read_event_timeout`.
+    pub fn wait_event(&mut self, timeout: Option<Duration>) -> Result<(usize, HciEvent), HciError> {
+        if self.sockets.is_empty() {
+            return Err(HciError::Unsupported);
+        }
+
+        // Packets kept by execute_command don't make the socket readable
+        let count = self.sockets.len();
+        if let Some(index) = (0..count)
+            .map(|offset| (self.next_index + offset) % count)
+            .find(|&index| self.sockets[index].has_deferred_packets())
+        {
+            self.next_index = (index + 1) % count;
+            let event = self.sockets[index].read_event()?;
+            return Ok((index, event));
+        }
+
+        let mut poll_fds: Vec<libc::pollfd> = self
+            .sockets
+            .iter()
+            .map(|socket| libc::pollfd {
+                fd: socket.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        let timeout_ms = match timeout {
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+
+        let result = unsafe {
+            libc::poll(
+                poll_fds.as_mut_ptr(),
+                poll_fds.len() as libc::nfds_t,
+                timeout_ms,
+            )
+        };
+
+        if result < 0 {
+            return Err(HciError::ReceiveError(std::io::Error::last_os_error()));
+        }
+
+        if result == 0 {
+            return Err(HciError::ReceiveError(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Timed out waiting for HCI event",
+            )));
+        }
+
+        let index = (0..count)
+            .map(|offset| (self.next_index + offset) % count)
+            .find(|&index| poll_fds[index].revents != 0)
+            .ok_or(HciError::InvalidPacketFormat)?;
+
+        self.next_index = (index + 1) % count;
+
+        // Errors and hangups are reported by the read itself
+        let event = self.sockets[index].read_event()?;
+        Ok((index, event))
+    }
+}
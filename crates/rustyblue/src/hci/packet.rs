@@ -2,7 +2,11 @@
 //!
 //! This module contains structures and methods for handling HCI packets.
 
+use crate::error::HciError;
+use crate::gap::BdAddr;
+use crate::hci::acl::AclData;
 use crate::hci::constants::*;
+use std::fmt;
 
 /// HCI command header structure
 #[repr(C, packed)]
@@ -12,7 +16,7 @@ pub struct HciCommandHeader {
 }
 
 /// Common HCI Commands
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 #[non_exhaustive]
 pub enum HciCommand {
     // Link Control Commands (OGF: 0x01)
@@ -43,6 +47,10 @@ pub enum HciCommand {
         event_mask: u64,
     },
 
+    // Informational Parameters (OGF: 0x04)
+    ReadBufferSize,
+    ReadBdAddr,
+
     // LE Commands (OGF: 0x08)
     LeSetEventMask {
         event_mask: u64,
@@ -53,24 +61,26 @@ pub enum HciCommand {
         address: [u8; 6],
     },
     LeSetAdvertisingParameters {
-        min_interval: u16,
-        max_interval: u16,
+        advertising_interval_min: u16,
+        advertising_interval_max: u16,
         advertising_type: u8,
         own_address_type: u8,
         peer_address_type: u8,
         peer_address: [u8; 6],
-        channel_map: u8,
-        filter_policy: u8,
+        advertising_channel_map: u8,
+        advertising_filter_policy: u8,
     },
     LeReadAdvertisingPhysicalChannelTxPower,
     LeSetAdvertisingData {
-        data: Vec<u8>,
+        advertising_data_length: u8,
+        advertising_data: [u8; 31],
     },
     LeSetScanResponseData {
-        data: Vec<u8>,
+        scan_response_data_length: u8,
+        scan_response_data: [u8; 31],
     },
-    LeSetAdvertisingEnable {
-        enable: bool,
+    LeSetAdvertiseEnable {
+        advertising_enable: bool,
     },
     LeSetScanParameters {
         scan_type: u8,
@@ -95,6 +105,48 @@ pub enum HciCommand {
         max_ce_length: u16,
     },
     LeCreateConnectionCancel,
+    LeEncrypt {
+        key: [u8; 16],
+        plaintext_data: [u8; 16],
+    },
+    LeEnableEncryption {
+        handle: u16,
+        rand: [u8; 8],
+        ediv: u16,
+        ltk: [u8; 16],
+    },
+    LeRand,
+    LeSetPhy {
+        handle: u16,
+        all_phys: u8,
+        tx_phys: u8,
+        rx_phys: u8,
+        phy_options: u16,
+    },
+    LeSetPeriodicAdvertisingParameters {
+        advertising_handle: u8,
+        periodic_advertising_interval_min: u16,
+        periodic_advertising_interval_max: u16,
+        periodic_advertising_properties: u16,
+    },
+    LeSetPeriodicAdvertisingData {
+        advertising_handle: u8,
+        operation: u8,
+        data: Vec<u8>,
+    },
+    LeSetPeriodicAdvertisingEnable {
+        enable: bool,
+        advertising_handle: u8,
+    },
+    LePeriodicAdvertisingCreateSync {
+        options: u8,
+        advertising_sid: u8,
+        advertiser_address_type: u8,
+        advertiser_address: [u8; 6],
+        skip: u16,
+        sync_timeout: u16,
+        sync_cte_type: u8,
+    },
 
     // Raw command
     Raw {
@@ -114,6 +166,40 @@ impl HciCommand {
         }
     }
 
+    /// Create an LE Set Advertising Data command from raw AD structures
+    ///
+    /// The data is zero-padded to the 31 byte advertising data field.
+    pub fn le_set_advertising_data(data: &[u8]) -> Result<Self, HciError> {
+        if data.len() > LE_MAX_ADVERTISING_DATA_LEN {
+            return Err(HciError::InvalidParamLength(data.len()));
+        }
+
+        let mut advertising_data = [0u8; LE_MAX_ADVERTISING_DATA_LEN];
+        advertising_data[..data.len()].copy_from_slice(data);
+
+        Ok(Self::LeSetAdvertisingData {
+            advertising_data_length: data.len() as u8,
+            advertising_data,
+        })
+    }
+
+    /// Create an LE Set Scan Response Data command from raw AD structures
+    ///
+    /// The data is zero-padded to the 31 byte scan response data field.
+    pub fn le_set_scan_response_data(data: &[u8]) -> Result<Self, HciError> {
+        if data.len() > LE_MAX_ADVERTISING_DATA_LEN {
+            return Err(HciError::InvalidParamLength(data.len()));
+        }
+
+        let mut scan_response_data = [0u8; LE_MAX_ADVERTISING_DATA_LEN];
+        scan_response_data[..data.len()].copy_from_slice(data);
+
+        Ok(Self::LeSetScanResponseData {
+            scan_response_data_length: data.len() as u8,
+            scan_response_data,
+        })
+    }
+
     /// Get the OGF and OCF for this command
     pub fn opcode_parts(&self) -> (u8, u16) {
         match self {
@@ -131,6 +217,10 @@ impl HciCommand {
             Self::Reset => (OGF_HOST_CTL, OCF_RESET),
             Self::SetEventMask { .. } => (OGF_HOST_CTL, OCF_SET_EVENT_MASK),
 
+            // Informational Parameters
+            Self::ReadBufferSize => (OGF_INFO_PARAM, OCF_READ_BUFFER_SIZE),
+            Self::ReadBdAddr => (OGF_INFO_PARAM, OCF_READ_BD_ADDR),
+
             // LE Commands
             Self::LeSetEventMask { .. } => (OGF_LE, OCF_LE_SET_EVENT_MASK),
             Self::LeReadBufferSize => (OGF_LE, OCF_LE_READ_BUFFER_SIZE),
@@ -142,11 +232,27 @@ impl HciCommand {
             }
             Self::LeSetAdvertisingData { .. } => (OGF_LE, OCF_LE_SET_ADVERTISING_DATA),
             Self::LeSetScanResponseData { .. } => (OGF_LE, OCF_LE_SET_SCAN_RESPONSE_DATA),
-            Self::LeSetAdvertisingEnable { .. } => (OGF_LE, OCF_LE_SET_ADVERTISING_ENABLE),
+            Self::LeSetAdvertiseEnable { .. } => (OGF_LE, OCF_LE_SET_ADVERTISING_ENABLE),
             Self::LeSetScanParameters { .. } => (OGF_LE, OCF_LE_SET_SCAN_PARAMETERS),
             Self::LeSetScanEnable { .. } => (OGF_LE, OCF_LE_SET_SCAN_ENABLE),
             Self::LeCreateConnection { .. } => (OGF_LE, OCF_LE_CREATE_CONNECTION),
             Self::LeCreateConnectionCancel => (OGF_LE, OCF_LE_CREATE_CONNECTION_CANCEL),
+            Self::LeEncrypt { .. } => (OGF_LE, OCF_LE_ENCRYPT),
+            Self::LeEnableEncryption { .. } => (OGF_LE, OCF_LE_ENABLE_ENCRYPTION),
+            Self::LeRand => (OGF_LE, OCF_LE_RAND),
+            Self::LeSetPhy { .. } => (OGF_LE, OCF_LE_SET_PHY),
+            Self::LeSetPeriodicAdvertisingParameters { .. } => {
+                (OGF_LE, OCF_LE_SET_PERIODIC_ADVERTISING_PARAMETERS)
+            }
+            Self::LeSetPeriodicAdvertisingData { .. } => {
+                (OGF_LE, OCF_LE_SET_PERIODIC_ADVERTISING_DATA)
+            }
+            Self::LeSetPeriodicAdvertisingEnable { .. } => {
+                (OGF_LE, OCF_LE_SET_PERIODIC_ADVERTISING_ENABLE)
+            }
+            Self::LePeriodicAdvertisingCreateSync { .. } => {
+                (OGF_LE, OCF_LE_PERIODIC_ADVERTISING_CREATE_SYNC)
+            }
 
             // Raw command
             Self::Raw { ogf, ocf, .. } => (*ogf, *ocf),
@@ -160,6 +266,8 @@ impl HciCommand {
             Self::Inquiry
             | Self::InquiryCancel
             | Self::Reset
+            | Self::ReadBufferSize
+            | Self::ReadBdAddr
             | Self::LeReadBufferSize
             | Self::LeReadLocalSupportedFeatures
             | Self::LeReadAdvertisingPhysicalChannelTxPower
@@ -169,7 +277,7 @@ impl HciCommand {
             Self::SetEventMask { event_mask } => event_mask.to_le_bytes().to_vec(),
             Self::LeSetEventMask { event_mask } => event_mask.to_le_bytes().to_vec(),
             Self::LeSetRandomAddress { address } => address.to_vec(),
-            Self::LeSetAdvertisingEnable { enable } => vec![*enable as u8],
+            Self::LeSetAdvertiseEnable { advertising_enable } => vec![*advertising_enable as u8],
 
             // Commands with complex parameters
             Self::CreateConnection {
@@ -204,44 +312,46 @@ impl HciCommand {
             Self::ExitSniffMode { handle } => handle.to_le_bytes().to_vec(),
 
             Self::LeSetAdvertisingParameters {
-                min_interval,
-                max_interval,
+                advertising_interval_min,
+                advertising_interval_max,
                 advertising_type,
                 own_address_type,
                 peer_address_type,
                 peer_address,
-                channel_map,
-                filter_policy,
+                advertising_channel_map,
+                advertising_filter_policy,
             } => {
                 let mut params = Vec::with_capacity(15);
-                params.extend_from_slice(&min_interval.to_le_bytes());
-                params.extend_from_slice(&max_interval.to_le_bytes());
+                params.extend_from_slice(&advertising_interval_min.to_le_bytes());
+                params.extend_from_slice(&advertising_interval_max.to_le_bytes());
                 params.push(*advertising_type);
                 params.push(*own_address_type);
                 params.push(*peer_address_type);
                 params.extend_from_slice(peer_address);
-                params.push(*channel_map);
-                params.push(*filter_policy);
+                params.push(*advertising_channel_map);
+                params.push(*advertising_filter_policy);
                 params
             }
 
-            Self::LeSetAdvertisingData { data } => {
-                if data.len() > HCI_MAX_PARAM_LEN {
-                    panic!("Advertising data too long");
-                }
-                let mut params = Vec::with_capacity(data.len() + 1);
-                params.push(data.len() as u8);
-                params.extend_from_slice(data);
+            Self::LeSetAdvertisingData {
+                advertising_data_length,
+                advertising_data,
+            } => {
+                // The command always carries the full 31 byte data field
+                let mut params = Vec::with_capacity(32);
+                params.push(*advertising_data_length);
+                params.extend_from_slice(advertising_data);
                 params
             }
 
-            Self::LeSetScanResponseData { data } => {
-                if data.len() > HCI_MAX_PARAM_LEN {
-                    panic!("Scan response data too long");
-                }
-                let mut params = Vec::with_capacity(data.len() + 1);
-                params.push(data.len() as u8);
-                params.extend_from_slice(data);
+            Self::LeSetScanResponseData {
+                scan_response_data_length,
+                scan_response_data,
+            } => {
+                // Like advertising data, always the full 31 byte field
+                let mut params = Vec::with_capacity(32);
+                params.push(*scan_response_data_length);
+                params.extend_from_slice(scan_response_data);
                 params
             }
 
@@ -292,6 +402,100 @@ impl HciCommand {
                 params
             }
 
+            Self::LeEncrypt {
+                key,
+                plaintext_data,
+            } => {
+                let mut params = Vec::with_capacity(32);
+                params.extend_from_slice(key);
+                params.extend_from_slice(plaintext_data);
+                params
+            }
+
+            Self::LeEnableEncryption {
+                handle,
+                rand,
+                ediv,
+                ltk,
+            } => {
+                let mut params = Vec::with_capacity(28);
+                params.extend_from_slice(&handle.to_le_bytes());
+                params.extend_from_slice(rand);
+                params.extend_from_slice(&ediv.to_le_bytes());
+                params.extend_from_slice(ltk);
+                params
+            }
+
+            Self::LeRand => Vec::new(),
+
+            Self::LeSetPhy {
+                handle,
+                all_phys,
+                tx_phys,
+                rx_phys,
+                phy_options,
+            } => {
+                let mut params = Vec::with_capacity(7);
+                params.extend_from_slice(&handle.to_le_bytes());
+                params.push(*all_phys);
+                params.push(*tx_phys);
+                params.push(*rx_phys);
+                params.extend_from_slice(&phy_options.to_le_bytes());
+                params
+            }
+
+            Self::LeSetPeriodicAdvertisingParameters {
+                advertising_handle,
+                periodic_advertising_interval_min,
+                periodic_advertising_interval_max,
+                periodic_advertising_properties,
+            } => {
+                let mut params = Vec::with_capacity(7);
+                params.push(*advertising_handle);
+                params.extend_from_slice(&periodic_advertising_interval_min.to_le_bytes());
+                params.extend_from_slice(&periodic_advertising_interval_max.to_le_bytes());
+                params.extend_from_slice(&periodic_advertising_properties.to_le_bytes());
+                params
+            }
+
+            Self::LeSetPeriodicAdvertisingData {
+                advertising_handle,
+                operation,
+                data,
+            } => {
+                let mut params = Vec::with_capacity(data.len() + 3);
+                params.push(*advertising_handle);
+                params.push(*operation);
+                params.push(data.len() as u8);
+                params.extend_from_slice(data);
+                params
+            }
+
+            Self::LeSetPeriodicAdvertisingEnable {
+                enable,
+                advertising_handle,
+            } => vec![*enable as u8, *advertising_handle],
+
+            Self::LePeriodicAdvertisingCreateSync {
+                options,
+                advertising_sid,
+                advertiser_address_type,
+                advertiser_address,
+                skip,
+                sync_timeout,
+                sync_cte_type,
+            } => {
+                let mut params = Vec::with_capacity(14);
+                params.push(*options);
+                params.push(*advertising_sid);
+                params.push(*advertiser_address_type);
+                params.extend_from_slice(advertiser_address);
+                params.extend_from_slice(&skip.to_le_bytes());
+                params.extend_from_slice(&sync_timeout.to_le_bytes());
+                params.push(*sync_cte_type);
+                params
+            }
+
             Self::Raw { parameters, .. } => parameters.clone(),
         }
     }
@@ -299,7 +503,7 @@ impl HciCommand {
     /// Convert the command to a raw HCI packet
     pub fn to_packet(&self) -> Vec<u8> {
         let (ogf, ocf) = self.opcode_parts();
-        let opcode = ((ogf as u16) << 10) | (ocf & 0x3ff);
+        let opcode = hci_opcode(ogf, ocf);
         let params = self.parameters();
 
         let mut packet = vec![HCI_COMMAND_PKT];
@@ -310,8 +514,458 @@ impl HciCommand {
     }
 }
 
-/// HCI Event packet
+impl fmt::Debug for HciCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HciCommand::Inquiry => f.write_str("Inquiry"),
+            HciCommand::InquiryCancel => f.write_str("InquiryCancel"),
+            HciCommand::CreateConnection {
+                bd_addr,
+                packet_type,
+            } => f
+                .debug_struct("CreateConnection")
+                .field("bd_addr", &DebugAddr(bd_addr))
+                .field("packet_type", &DebugHex16(*packet_type))
+                .finish(),
+            HciCommand::Disconnect { handle, reason } => f
+                .debug_struct("Disconnect")
+                .field("handle", &DebugHex16(*handle))
+                .field("reason", &DebugHex8(*reason))
+                .finish(),
+            HciCommand::SniffMode {
+                handle,
+                max_interval,
+                min_interval,
+            } => f
+                .debug_struct("SniffMode")
+                .field("handle", &DebugHex16(*handle))
+                .field("max_interval", &DebugDuration::slots(*max_interval))
+                .field("min_interval", &DebugDuration::slots(*min_interval))
+                .finish(),
+            HciCommand::ExitSniffMode { handle } => f
+                .debug_struct("ExitSniffMode")
+                .field("handle", &DebugHex16(*handle))
+                .finish(),
+            HciCommand::Reset => f.write_str("Reset"),
+            HciCommand::SetEventMask { event_mask } => f
+                .debug_struct("SetEventMask")
+                .field("event_mask", &format_args!("0x{:016X}", event_mask))
+                .finish(),
+            HciCommand::LeSetEventMask { event_mask } => f
+                .debug_struct("LeSetEventMask")
+                .field("event_mask", &format_args!("0x{:016X}", event_mask))
+                .finish(),
+            HciCommand::ReadBufferSize => f.write_str("ReadBufferSize"),
+            HciCommand::ReadBdAddr => f.write_str("ReadBdAddr"),
+            HciCommand::LeReadBufferSize => f.write_str("LeReadBufferSize"),
+            HciCommand::LeReadLocalSupportedFeatures => f.write_str("LeReadLocalSupportedFeatures"),
+            HciCommand::LeSetRandomAddress { address } => f
+                .debug_struct("LeSetRandomAddress")
+                .field("address", &DebugAddr(address))
+                .finish(),
+            HciCommand::LeSetAdvertisingParameters {
+                advertising_interval_min,
+                advertising_interval_max,
+                advertising_type,
+                own_address_type,
+                peer_address_type,
+                peer_address,
+                advertising_channel_map,
+                advertising_filter_policy,
+            } => f
+                .debug_struct("LeSetAdvertisingParameters")
+                .field(
+                    "advertising_interval_min",
+                    &DebugDuration::slots(*advertising_interval_min),
+                )
+                .field(
+                    "advertising_interval_max",
+                    &DebugDuration::slots(*advertising_interval_max),
+                )
+                .field("advertising_type", &DebugHex8(*advertising_type))
+                .field("own_address_type", &DebugHex8(*own_address_type))
+                .field("peer_address_type", &DebugHex8(*peer_address_type))
+                .field("peer_address", &DebugAddr(peer_address))
+                .field(
+                    "advertising_channel_map",
+                    &format_args!("0b{:03b}", advertising_channel_map),
+                )
+                .field(
+                    "advertising_filter_policy",
+                    &DebugHex8(*advertising_filter_policy),
+                )
+                .finish(),
+            HciCommand::LeReadAdvertisingPhysicalChannelTxPower => {
+                f.write_str("LeReadAdvertisingPhysicalChannelTxPower")
+            }
+            HciCommand::LeSetAdvertisingData {
+                advertising_data_length,
+                advertising_data,
+            } => {
+                let length = (*advertising_data_length as usize).min(advertising_data.len());
+                f.debug_struct("LeSetAdvertisingData")
+                    .field("advertising_data_length", advertising_data_length)
+                    .field("advertising_data", &DebugBytes(&advertising_data[..length]))
+                    .finish()
+            }
+            HciCommand::LeSetScanResponseData {
+                scan_response_data_length,
+                scan_response_data,
+            } => {
+                let length = (*scan_response_data_length as usize).min(scan_response_data.len());
+                f.debug_struct("LeSetScanResponseData")
+                    .field("scan_response_data_length", scan_response_data_length)
+                    .field(
+                        "scan_response_data",
+                        &DebugBytes(&scan_response_data[..length]),
+                    )
+                    .finish()
+            }
+            HciCommand::LeSetAdvertiseEnable { advertising_enable } => f
+                .debug_struct("LeSetAdvertiseEnable")
+                .field("advertising_enable", advertising_enable)
+                .finish(),
+            HciCommand::LeSetScanParameters {
+                scan_type,
+                scan_interval,
+                scan_window,
+                own_address_type,
+                filter_policy,
+            } => f
+                .debug_struct("LeSetScanParameters")
+                .field("scan_type", &DebugHex8(*scan_type))
+                .field("scan_interval", &DebugDuration::slots(*scan_interval))
+                .field("scan_window", &DebugDuration::slots(*scan_window))
+                .field("own_address_type", &DebugHex8(*own_address_type))
+                .field("filter_policy", &DebugHex8(*filter_policy))
+                .finish(),
+            HciCommand::LeSetScanEnable {
+                enable,
+                filter_duplicates,
+            } => f
+                .debug_struct("LeSetScanEnable")
+                .field("enable", enable)
+                .field("filter_duplicates", filter_duplicates)
+                .finish(),
+            HciCommand::LeCreateConnection {
+                peer_addr,
+                peer_addr_type,
+                own_address_type,
+                conn_interval_min,
+                conn_interval_max,
+                conn_latency,
+                supervision_timeout,
+                min_ce_length,
+                max_ce_length,
+            } => f
+                .debug_struct("LeCreateConnection")
+                .field("peer_addr", &DebugAddr(peer_addr))
+                .field("peer_addr_type", &DebugHex8(*peer_addr_type))
+                .field("own_address_type", &DebugHex8(*own_address_type))
+                .field(
+                    "conn_interval_min",
+                    &DebugDuration::connection_interval(*conn_interval_min),
+                )
+                .field(
+                    "conn_interval_max",
+                    &DebugDuration::connection_interval(*conn_interval_max),
+                )
+                .field("conn_latency", conn_latency)
+                .field(
+                    "supervision_timeout",
+                    &DebugDuration::supervision_timeout(*supervision_timeout),
+                )
+                .field("min_ce_length", &DebugDuration::slots(*min_ce_length))
+                .field("max_ce_length", &DebugDuration::slots(*max_ce_length))
+                .finish(),
+            HciCommand::LeCreateConnectionCancel => f.write_str("LeCreateConnectionCancel"),
+            HciCommand::LeEncrypt {
+                key,
+                plaintext_data,
+            } => f
+                .debug_struct("LeEncrypt")
+                .field("key", &DebugBytes(key))
+                .field("plaintext_data", &DebugBytes(plaintext_data))
+                .finish(),
+            HciCommand::LeEnableEncryption {
+                handle,
+                rand,
+                ediv,
+                ltk,
+            } => f
+                .debug_struct("LeEnableEncryption")
+                .field("handle", &DebugHex16(*handle))
+                .field("rand", &DebugBytes(rand))
+                .field("ediv", &DebugHex16(*ediv))
+                .field("ltk", &DebugBytes(ltk))
+                .finish(),
+            HciCommand::LeRand => f.write_str("LeRand"),
+            HciCommand::LeSetPhy {
+                handle,
+                all_phys,
+                tx_phys,
+                rx_phys,
+                phy_options,
+            } => f
+                .debug_struct("LeSetPhy")
+                .field("handle", &DebugHex16(*handle))
+                .field("all_phys", &DebugHex8(*all_phys))
+                .field("tx_phys", &DebugHex8(*tx_phys))
+                .field("rx_phys", &DebugHex8(*rx_phys))
+                .field("phy_options", &DebugHex16(*phy_options))
+                .finish(),
+            HciCommand::LeSetPeriodicAdvertisingParameters {
+                advertising_handle,
+                periodic_advertising_interval_min,
+                periodic_advertising_interval_max,
+                periodic_advertising_properties,
+            } => f
+                .debug_struct("LeSetPeriodicAdvertisingParameters")
+                .field("advertising_handle", &DebugHex8(*advertising_handle))
+                .field(
+                    "periodic_advertising_interval_min",
+                    &DebugDuration::connection_interval(*periodic_advertising_interval_min),
+                )
+                .field(
+                    "periodic_advertising_interval_max",
+                    &DebugDuration::connection_interval(*periodic_advertising_interval_max),
+                )
+                .field(
+                    "periodic_advertising_properties",
+                    &DebugHex16(*periodic_advertising_properties),
+                )
+                .finish(),
+            HciCommand::LeSetPeriodicAdvertisingData {
+                advertising_handle,
+                operation,
+                data,
+            } => f
+                .debug_struct("LeSetPeriodicAdvertisingData")
+                .field("advertising_handle", &DebugHex8(*advertising_handle))
+                .field("operation", &DebugHex8(*operation))
+                .field("data", &DebugBytes(data))
+                .finish(),
+            HciCommand::LeSetPeriodicAdvertisingEnable {
+                enable,
+                advertising_handle,
+            } => f
+                .debug_struct("LeSetPeriodicAdvertisingEnable")
+                .field("enable", enable)
+                .field("advertising_handle", &DebugHex8(*advertising_handle))
+                .finish(),
+            HciCommand::LePeriodicAdvertisingCreateSync {
+                options,
+                advertising_sid,
+                advertiser_address_type,
+                advertiser_address,
+                skip,
+                sync_timeout,
+                sync_cte_type,
+            } => f
+                .debug_struct("LePeriodicAdvertisingCreateSync")
+                .field("options", &DebugHex8(*options))
+                .field("advertising_sid", advertising_sid)
+                .field(
+                    "advertiser_address_type",
+                    &DebugHex8(*advertiser_address_type),
+                )
+                .field("advertiser_address", &DebugAddr(advertiser_address))
+                .field("skip", skip)
+                .field(
+                    "sync_timeout",
+                    &DebugDuration::supervision_timeout(*sync_timeout),
+                )
+                .field("sync_cte_type", &DebugHex8(*sync_cte_type))
+                .finish(),
+            HciCommand::Raw {
+                ogf,
+                ocf,
+                parameters,
+            } => f
+                .debug_struct("Raw")
+                .field("ogf", &DebugHex8(*ogf))
+                .field("ocf", &DebugHex16(*ocf))
+                .field("parameters", &DebugBytes(parameters))
+                .finish(),
+        }
+    }
+}
+
+/// Debug helper printing a byte as `0xNN`
+struct DebugHex8(u8);
+
+impl fmt::Debug for DebugHex8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02X}", self.0)
+    }
+}
+
+/// Debug helper printing a 16-bit value as `0xNNNN`
+struct DebugHex16(u16);
+
+impl fmt::Debug for DebugHex16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04X}", self.0)
+    }
+}
+
+/// Debug helper printing an HCI (little-endian) address as colon-hex
+struct DebugAddr<'a>(&'a [u8; 6]);
+
+impl fmt::Debug for DebugAddr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let a = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a[5], a[4], a[3], a[2], a[1], a[0]
+        )
+    }
+}
+
+/// Debug helper printing a byte slice as space separated hex
+struct DebugBytes<'a>(&'a [u8]);
+
+impl fmt::Debug for DebugBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Debug helper printing a time value in controller units along with its
+/// length in milliseconds
+struct DebugDuration {
+    value: u16,
+    unit_us: u32,
+}
+
+impl DebugDuration {
+    /// 0.625 ms units, used by advertising, scan and sniff parameters
+    fn slots(value: u16) -> Self {
+        Self {
+            value,
+            unit_us: 625,
+        }
+    }
+
+    /// 1.25 ms units, used by connection intervals
+    fn connection_interval(value: u16) -> Self {
+        Self {
+            value,
+            unit_us: 1250,
+        }
+    }
+
+    /// 10 ms units, used by the supervision timeout
+    fn supervision_timeout(value: u16) -> Self {
+        Self {
+            value,
+            unit_us: 10_000,
+        }
+    }
+}
+
+impl fmt::Debug for DebugDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let us = self.value as u32 * self.unit_us;
+        write!(f, "0x{:04X} ({}", self.value, us / 1000)?;
+        if !us.is_multiple_of(1000) {
+            write!(f, ".{:03}", us % 1000)?;
+        }
+        f.write_str(" ms)")
+    }
+}
+
+/// Symbolic name of an HCI event code, if it is a known event
+fn event_code_name(event_code: u8) -> Option<&'static str> {
+    match event_code {
+        EVT_INQUIRY_COMPLETE => Some("INQUIRY_COMPLETE"),
+        EVT_INQUIRY_RESULT => Some("INQUIRY_RESULT"),
+        EVT_CONN_COMPLETE => Some("CONN_COMPLETE"),
+        EVT_CONN_REQUEST => Some("CONN_REQUEST"),
+        EVT_DISCONN_COMPLETE => Some("DISCONN_COMPLETE"),
+        EVT_AUTH_COMPLETE => Some("AUTH_COMPLETE"),
+        EVT_REMOTE_NAME_REQ_COMPLETE => Some("REMOTE_NAME_REQ_COMPLETE"),
+        EVT_ENCRYPTION_CHANGE => Some("ENCRYPTION_CHANGE"),
+        EVT_CHANGE_CONN_LINK_KEY_COMPLETE => Some("CHANGE_CONN_LINK_KEY_COMPLETE"),
+        EVT_LINK_KEY_TYPE_CHANGED => Some("LINK_KEY_TYPE_CHANGED"),
+        EVT_READ_REMOTE_FEATURES_COMPLETE => Some("READ_REMOTE_FEATURES_COMPLETE"),
+        EVT_READ_REMOTE_VERSION_COMPLETE => Some("READ_REMOTE_VERSION_COMPLETE"),
+        EVT_QOS_SETUP_COMPLETE => Some("QOS_SETUP_COMPLETE"),
+        EVT_CMD_COMPLETE => Some("CMD_COMPLETE"),
+        EVT_CMD_STATUS => Some("CMD_STATUS"),
+        EVT_HARDWARE_ERROR => Some("HARDWARE_ERROR"),
+        EVT_FLUSH_OCCURRED => Some("FLUSH_OCCURRED"),
+        EVT_ROLE_CHANGE => Some("ROLE_CHANGE"),
+        EVT_NUM_COMP_PKTS => Some("NUM_COMP_PKTS"),
+        EVT_MODE_CHANGE => Some("MODE_CHANGE"),
+        EVT_RETURN_LINK_KEYS => Some("RETURN_LINK_KEYS"),
+        EVT_PIN_CODE_REQ => Some("PIN_CODE_REQ"),
+        EVT_LINK_KEY_REQ => Some("LINK_KEY_REQ"),
+        EVT_LINK_KEY_NOTIFY => Some("LINK_KEY_NOTIFY"),
+        EVT_LOOPBACK_COMMAND => Some("LOOPBACK_COMMAND"),
+        EVT_DATA_BUFFER_OVERFLOW => Some("DATA_BUFFER_OVERFLOW"),
+        EVT_MAX_SLOTS_CHANGE => Some("MAX_SLOTS_CHANGE"),
+        EVT_READ_CLOCK_OFFSET_COMPLETE => Some("READ_CLOCK_OFFSET_COMPLETE"),
+        EVT_CONN_PTYPE_CHANGED => Some("CONN_PTYPE_CHANGED"),
+        EVT_QOS_VIOLATION => Some("QOS_VIOLATION"),
+        EVT_PSCAN_REP_MODE_CHANGE => Some("PSCAN_REP_MODE_CHANGE"),
+        EVT_FLOW_SPEC_COMPLETE => Some("FLOW_SPEC_COMPLETE"),
+        EVT_INQUIRY_RESULT_WITH_RSSI => Some("INQUIRY_RESULT_WITH_RSSI"),
+        EVT_READ_REMOTE_EXT_FEATURES_COMPLETE => Some("READ_REMOTE_EXT_FEATURES_COMPLETE"),
+        EVT_SYNC_CONN_COMPLETE => Some("SYNC_CONN_COMPLETE"),
+        EVT_SYNC_CONN_CHANGED => Some("SYNC_CONN_CHANGED"),
+        EVT_SNIFF_SUBRATING => Some("SNIFF_SUBRATING"),
+        EVT_EXTENDED_INQUIRY_RESULT => Some("EXTENDED_INQUIRY_RESULT"),
+        EVT_ENCRYPT_KEY_REFRESH_COMPLETE => Some("ENCRYPT_KEY_REFRESH_COMPLETE"),
+        EVT_IO_CAPABILITY_REQUEST => Some("IO_CAPABILITY_REQUEST"),
+        EVT_IO_CAPABILITY_RESPONSE => Some("IO_CAPABILITY_RESPONSE"),
+        EVT_USER_CONFIRM_REQUEST => Some("USER_CONFIRM_REQUEST"),
+        EVT_USER_PASSKEY_REQUEST => Some("USER_PASSKEY_REQUEST"),
+        EVT_REMOTE_OOB_DATA_REQUEST => Some("REMOTE_OOB_DATA_REQUEST"),
+        EVT_SIMPLE_PAIRING_COMPLETE => Some("SIMPLE_PAIRING_COMPLETE"),
+        EVT_LINK_SUPERVISION_TIMEOUT_CHANGED => Some("LINK_SUPERVISION_TIMEOUT_CHANGED"),
+        EVT_ENHANCED_FLUSH_COMPLETE => Some("ENHANCED_FLUSH_COMPLETE"),
+        EVT_USER_PASSKEY_NOTIFY => Some("USER_PASSKEY_NOTIFY"),
+        EVT_KEYPRESS_NOTIFY => Some("KEYPRESS_NOTIFY"),
+        EVT_REMOTE_HOST_FEATURES_NOTIFY => Some("REMOTE_HOST_FEATURES_NOTIFY"),
+        EVT_LE_META_EVENT => Some("LE_META_EVENT"),
+        EVT_NUM_COMP_BLOCKS => Some("NUM_COMP_BLOCKS"),
+        EVT_TRIGGERED_CLOCK_CAPTURE => Some("TRIGGERED_CLOCK_CAPTURE"),
+        EVT_SYNC_TRAIN_COMPLETE => Some("SYNC_TRAIN_COMPLETE"),
+        EVT_SYNC_TRAIN_RECEIVED => Some("SYNC_TRAIN_RECEIVED"),
+        EVT_CPB_RECEIVE => Some("CPB_RECEIVE"),
+        EVT_CPB_TIMEOUT => Some("CPB_TIMEOUT"),
+        EVT_TRUNCATED_PAGE_COMPLETE => Some("TRUNCATED_PAGE_COMPLETE"),
+        EVT_PERIPHERAL_PAGE_RESPONSE_TIMEOUT => Some("PERIPHERAL_PAGE_RESPONSE_TIMEOUT"),
+        EVT_CPB_CHANNEL_MAP_CHANGE => Some("CPB_CHANNEL_MAP_CHANGE"),
+        EVT_INQUIRY_RESPONSE_NOTIFY => Some("INQUIRY_RESPONSE_NOTIFY"),
+        EVT_AUTH_PAYLOAD_TIMEOUT_EXPIRED => Some("AUTH_PAYLOAD_TIMEOUT_EXPIRED"),
+        EVT_SAM_STATUS_CHANGE => Some("SAM_STATUS_CHANGE"),
+        EVT_ENCRYPTION_CHANGE_V2 => Some("ENCRYPTION_CHANGE_V2"),
+        _ => None,
+    }
+}
+
+/// A packet received from the controller, see `HciSocket::read_packet`
 #[derive(Debug, Clone)]
+pub enum HciPacket {
+    /// An HCI event
+    Event(HciEvent),
+    /// ACL data of a connection, to be passed to
+    /// `L2capManager::handle_acl_data`
+    Acl(AclData),
+}
+
+/// HCI Event packet
+#[derive(Clone)]
 pub struct HciEvent {
     pub event_code: u8,
     pub parameter_total_length: u8,
@@ -351,6 +1005,14 @@ impl HciEvent {
         &self.parameters
     }
 
+    /// Parse every report in an LE Advertising Report event
+    ///
+    /// A single event may carry several reports. Events of any other kind
+    /// yield no reports.
+    pub fn parse_le_advertising_reports(event: &HciEvent) -> Vec<LeAdvertisingReport> {
+        LeAdvertisingReport::parse_from_event(event).unwrap_or_default()
+    }
+
     /// Check if this event is a command complete for the given opcode
     pub fn is_command_complete(&self, ogf: u8, ocf: u16) -> bool {
         if self.event_code != EVT_CMD_COMPLETE || self.parameters.len() < 3 {
@@ -358,10 +1020,17 @@ impl HciEvent {
         }
 
         let opcode = u16::from_le_bytes([self.parameters[1], self.parameters[2]]);
-        let command_ogf = (opcode >> 10) as u8;
-        let command_ocf = opcode & 0x3FF;
+        opcode == hci_opcode(ogf, ocf)
+    }
 
-        command_ogf == ogf && command_ocf == ocf
+    /// Check if this event is a command status for the given opcode
+    pub fn is_command_status(&self, ogf: u8, ocf: u16) -> bool {
+        if self.event_code != EVT_CMD_STATUS || self.parameters.len() < 4 {
+            return false;
+        }
+
+        let opcode = u16::from_le_bytes([self.parameters[2], self.parameters[3]]);
+        opcode == hci_opcode(ogf, ocf)
     }
 
     /// Get the status from a command complete event
@@ -376,17 +1045,153 @@ impl HciEvent {
             _ => 0xFF, // Error code for invalid event
         }
     }
+
+    /// Decode the parameters of events the crate understands
+    ///
+    /// Returns `None` for other events and for malformed parameters.
+    pub fn parsed(&self) -> Option<ParsedHciEvent> {
+        match self.event_code {
+            EVT_NUM_COMP_PKTS => {
+                let (&num_handles, entries) = self.parameters.split_first()?;
+                if entries.len() < num_handles as usize * 4 {
+                    return None;
+                }
+
+                let completed = entries
+                    .chunks_exact(4)
+                    .take(num_handles as usize)
+                    .map(|entry| {
+                        let handle = u16::from_le_bytes([entry[0], entry[1]]) & 0x0FFF;
+                        let count = u16::from_le_bytes([entry[2], entry[3]]);
+                        (handle, count)
+                    })
+                    .collect();
+                Some(ParsedHciEvent::NumberOfCompletedPackets(completed))
+            }
+            EVT_ENCRYPTION_CHANGE => {
+                if self.parameters.len() < 4 {
+                    return None;
+                }
+                Some(ParsedHciEvent::EncryptionChange(EncryptionChange {
+                    status: self.parameters[0],
+                    handle: u16::from_le_bytes([self.parameters[1], self.parameters[2]]) & 0x0FFF,
+                    enabled: self.parameters[3],
+                }))
+            }
+            EVT_ENCRYPT_KEY_REFRESH_COMPLETE => {
+                if self.parameters.len() < 3 {
+                    return None;
+                }
+                Some(ParsedHciEvent::EncryptionKeyRefreshComplete {
+                    status: self.parameters[0],
+                    handle: u16::from_le_bytes([self.parameters[1], self.parameters[2]]) & 0x0FFF,
+                })
+            }
+            EVT_LE_META_EVENT => {
+                let params = &self.parameters;
+                let enhanced = match *params.first()? {
+                    EVT_LE_CONN_COMPLETE if params.len() >= 19 => false,
+                    EVT_LE_ENHANCED_CONN_COMPLETE if params.len() >= 31 => true,
+                    _ => return None,
+                };
+
+                // An all-zero RPA means the controller didn't use one
+                let rpa = |offset: usize| {
+                    if !enhanced {
+                        return None;
+                    }
+                    BdAddr::from_slice(&params[offset..offset + 6])
+                        .filter(|rpa| rpa.bytes != [0; 6])
+                };
+                Some(ParsedHciEvent::LeConnectionComplete(LeConnectionEvent {
+                    status: params[1],
+                    handle: u16::from_le_bytes([params[2], params[3]]) & 0x0FFF,
+                    role: params[4],
+                    peer_address_type: params[5],
+                    peer_address: BdAddr::from_slice(&params[6..12])?,
+                    local_resolvable_private_address: rpa(12),
+                    peer_resolvable_private_address: rpa(18),
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An HCI event with its parameters decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParsedHciEvent {
+    /// Number Of Completed Packets as (connection handle, packet count) pairs
+    NumberOfCompletedPackets(Vec<(u16, u16)>),
+    /// Encryption Change
+    EncryptionChange(EncryptionChange),
+    /// Encryption Key Refresh Complete
+    EncryptionKeyRefreshComplete {
+        /// Status of the key refresh
+        status: u8,
+        /// Connection handle
+        handle: u16,
+    },
+    /// LE Connection Complete or LE Enhanced Connection Complete
+    LeConnectionComplete(LeConnectionEvent),
+}
+
+/// Parameters of an LE (Enhanced) Connection Complete event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeConnectionEvent {
+    /// Status of the connection attempt
+    pub status: u8,
+    /// Connection handle
+    pub handle: u16,
+    /// Local role (0x00 = central, 0x01 = peripheral)
+    pub role: u8,
+    /// Peer address type
+    pub peer_address_type: u8,
+    /// Peer address
+    pub peer_address: BdAddr,
+    /// Resolvable private address the controller used locally, if any
+    pub local_resolvable_private_address: Option<BdAddr>,
+    /// Resolvable private address the peer used, if the controller resolved
+    /// it to `peer_address`
+    pub peer_resolvable_private_address: Option<BdAddr>,
+}
+
+/// Parameters of an Encryption Change event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionChange {
+    /// Status of the encryption change
+    pub status: u8,
+    /// Connection handle
+    pub handle: u16,
+    /// Encryption enabled (0x00 = off, 0x01 = AES-CCM on LE links)
+    pub enabled: u8,
+}
+
+impl fmt::Debug for HciEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event_code = match event_code_name(self.event_code) {
+            Some(name) => format!("{}(0x{:02X})", name, self.event_code),
+            None => format!("0x{:02X}", self.event_code),
+        };
+
+        f.debug_struct("HciEvent")
+            .field("event_code", &event_code)
+            .field("parameter_total_length", &self.parameter_total_length)
+            .field("parameters", &DebugBytes(&self.parameters))
+            .finish()
+    }
 }
 
 /// LE Advertising Report Event
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LeAdvertisingReport {
     pub event_type: u8,
     pub address_type: u8,
     pub address: [u8; 6],
     pub data_length: u8,
     pub data: Vec<u8>,
-    pub rssi: i8,
+    /// Signal strength in dBm, or `None` if the controller did not report it
+    pub rssi: Option<i8>,
 }
 
 impl LeAdvertisingReport {
@@ -405,7 +1210,7 @@ impl LeAdvertisingReport {
             ));
         }
 
-        let num_reports = event.parameters[1];
+        let num_reports = event.parameters.get(1).copied().unwrap_or(0);
         if num_reports == 0 {
             return Ok(Vec::new());
         }
@@ -414,7 +1219,8 @@ impl LeAdvertisingReport {
         let mut offset = 2; // Skip subevent code and num reports
 
         for _ in 0..num_reports {
-            if offset + 10 >= event.parameters.len() {
+            // Event type, address type, address and data length
+            if offset + 9 > event.parameters.len() {
                 break;
             }
 
@@ -428,25 +1234,21 @@ impl LeAdvertisingReport {
             address.copy_from_slice(&event.parameters[offset..offset + 6]);
             offset += 6;
 
-            if offset >= event.parameters.len() {
-                break;
-            }
-
             let data_length = event.parameters[offset];
             offset += 1;
 
-            if offset + data_length as usize >= event.parameters.len() {
+            // Data followed by the RSSI
+            if offset + data_length as usize + 1 > event.parameters.len() {
                 break;
             }
 
             let data = event.parameters[offset..offset + data_length as usize].to_vec();
             offset += data_length as usize;
 
-            if offset >= event.parameters.len() {
-                break;
-            }
-
-            let rssi = event.parameters[offset] as i8;
+            let rssi = match event.parameters[offset] {
+                LE_RSSI_NOT_AVAILABLE => None,
+                value => Some(value as i8),
+            };
             offset += 1;
 
             reports.push(LeAdvertisingReport {
@@ -461,4 +1263,16 @@ impl LeAdvertisingReport {
 
         Ok(reports)
     }
+
+    /// Name of the report's event type, e.g. `"ADV_IND"` or `"SCAN_RSP"`
+    pub fn event_type_name(&self) -> &'static str {
+        match self.event_type {
+            LE_ADV_REPORT_ADV_IND => "ADV_IND",
+            LE_ADV_REPORT_ADV_DIRECT_IND => "ADV_DIRECT_IND",
+            LE_ADV_REPORT_ADV_SCAN_IND => "ADV_SCAN_IND",
+            LE_ADV_REPORT_ADV_NONCONN_IND => "ADV_NONCONN_IND",
+            LE_ADV_REPORT_SCAN_RSP => "SCAN_RSP",
+            _ => "UNKNOWN",
+        }
+    }
 }
@@ -4,9 +4,18 @@
 //! allowing for communication with Bluetooth controllers.
 
 use crate::error::HciError;
-use crate::hci::packet::{HciCommand, HciEvent};
+use crate::gap::BdAddr;
+use crate::hci::acl::{AclData, BufferInfo, LocalControllerInfo};
+#[cfg(feature = "capture")]
+use crate::hci::capture::BtsnoopWriter;
+use crate::hci::constants::{HCI_ACL_HEADER_SIZE, HCI_ACL_PKT};
+use crate::hci::packet::{HciCommand, HciEvent, HciPacket};
+use std::collections::VecDeque;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::time::Duration;
+#[cfg(feature = "capture")]
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 // Bluetooth socket constants
 const AF_BLUETOOTH: i32 = 31;
@@ -14,10 +23,25 @@ const BTPROTO_HCI: i32 = 1;
 const HCI_CHANNEL_RAW: i32 = 0;
 const HCI_EVENT_PKT: u8 = 0x04;
 
+// HCI device ioctls: _IOW('H', 201, int) and _IOW('H', 202, int)
+const HCIDEVUP: libc::c_ulong = 0x400448C9;
+const HCIDEVDOWN: libc::c_ulong = 0x400448CA;
+
+/// How long `execute_command` waits for the controller to answer
+const HCI_COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Represents an HCI socket
 #[derive(Debug)]
 pub struct HciSocket {
     fd: RawFd,
+    /// Packets read while waiting for a command to complete, delivered to
+    /// the next reads
+    deferred: Mutex<VecDeque<HciPacket>>,
+    /// Serializes `execute_command` so each waits for its own completion
+    command_lock: Mutex<()>,
+    /// btsnoop file every packet is written to, if capturing
+    #[cfg(feature = "capture")]
+    capture: Mutex<Option<BtsnoopWriter>>,
 }
 
 // Define the sockaddr_hci structure
@@ -29,6 +53,22 @@ struct SockaddrHci {
 }
 
 impl HciSocket {
+    /// Wrap an already open file descriptor, e.g. one end of a socket pair
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn from_raw_fd(fd: RawFd) -> Self {
+        HciSocket::with_fd(fd)
+    }
+
+    fn with_fd(fd: RawFd) -> Self {
+        HciSocket {
+            fd,
+            deferred: Mutex::new(VecDeque::new()),
+            command_lock: Mutex::new(()),
+            #[cfg(feature = "capture")]
+            capture: Mutex::new(None),
+        }
+    }
+
     /// Gets the raw file descriptor for the socket
     pub fn as_raw_fd(&self) -> RawFd {
         self.fd
@@ -71,14 +111,45 @@ impl HciSocket {
             return Err(HciError::BindError(std::io::Error::last_os_error()));
         }
 
-        Ok(HciSocket { fd })
+        Ok(HciSocket::with_fd(fd))
     }
 
-    /// Read an HCI event from the socket
-    pub fn read_event(&self) -> Result<HciEvent, HciError> {
-        let mut buffer = [0u8; 258]; // Max HCI event packet size
+    /// Write all HCI traffic of this socket to a btsnoop file
+    ///
+    /// Commands and ACL data sent, and events and ACL data read, are
+    /// appended with a timestamp until `disable_capture` is called. The file
+    /// at `path` is replaced and can be opened in Wireshark. A capture
+    /// already in progress is closed first.
+    #[cfg(feature = "capture")]
+    pub fn enable_capture(&self, path: &Path) -> Result<(), HciError> {
+        let writer = BtsnoopWriter::create(path).map_err(HciError::CaptureError)?;
+        if let Some(mut previous) = self.capture.lock().unwrap().replace(writer) {
+            previous.flush().map_err(HciError::CaptureError)?;
+        }
+        Ok(())
+    }
+
+    /// Stop capturing, flushing and closing the btsnoop file
+    #[cfg(feature = "capture")]
+    pub fn disable_capture(&self) -> Result<(), HciError> {
+        match self.capture.lock().unwrap().take() {
+            Some(mut writer) => writer.flush().map_err(HciError::CaptureError),
+            None => Ok(()),
+        }
+    }
+
+    /// Append a packet to the capture file, if capturing
+    ///
+    /// Failing to write the capture does not fail the packet itself.
+    #[cfg(feature = "capture")]
+    fn capture_packet(&self, packet: &[u8], received: bool) {
+        if let Some(writer) = self.capture.lock().unwrap().as_mut() {
+            let _ = writer.write_packet(packet, received);
+        }
+    }
 
-        // Read packet type and header
+    /// Read one packet from the socket, returning its length
+    fn read_raw(&self, buffer: &mut [u8]) -> Result<usize, HciError> {
         let bytes_read = unsafe {
             libc::read(
                 self.fd,
@@ -91,19 +162,101 @@ impl HciSocket {
             return Err(HciError::ReceiveError(std::io::Error::last_os_error()));
         }
 
-        if bytes_read < 3 || buffer[0] != HCI_EVENT_PKT {
-            return Err(HciError::InvalidPacketFormat);
+        if bytes_read == 0 {
+            return Err(HciError::ReceiveError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "HCI socket closed",
+            )));
+        }
+
+        #[cfg(feature = "capture")]
+        self.capture_packet(&buffer[..bytes_read as usize], true);
+        Ok(bytes_read as usize)
+    }
+
+    /// Read an HCI event from the socket
+    ///
+    /// Fails with `HciError::InvalidPacketFormat` if the next packet is not
+    /// an event; that packet is consumed.
+    pub fn read_event(&self) -> Result<HciEvent, HciError> {
+        match self.read_packet()? {
+            HciPacket::Event(event) => Ok(event),
+            HciPacket::Acl(_) => Err(HciError::InvalidPacketFormat),
+        }
+    }
+
+    /// Read an HCI ACL data packet from the socket
+    ///
+    /// Fails with `HciError::InvalidPacketFormat` if the next packet is not
+    /// ACL data; that packet is consumed. Event loops that need both should
+    /// use `read_packet` instead.
+    pub fn read_acl(&self) -> Result<AclData, HciError> {
+        match self.read_packet()? {
+            HciPacket::Acl(acl) => Ok(acl),
+            HciPacket::Event(_) => Err(HciError::InvalidPacketFormat),
+        }
+    }
+
+    /// Read the next HCI event or ACL data packet from the socket
+    ///
+    /// Packets that arrived while `execute_command` was waiting come first.
+    /// Other packet types fail with `HciError::InvalidPacketFormat`.
+    pub fn read_packet(&self) -> Result<HciPacket, HciError> {
+        if let Some(packet) = self.deferred.lock().unwrap().pop_front() {
+            return Ok(packet);
+        }
+        self.read_socket_packet()
+    }
+
+    /// Check whether packets read by `execute_command` are waiting
+    pub(crate) fn has_deferred_packets(&self) -> bool {
+        !self.deferred.lock().unwrap().is_empty()
+    }
+
+    /// Read the next packet from the socket itself
+    fn read_socket_packet(&self) -> Result<HciPacket, HciError> {
+        let mut buffer = vec![0u8; 1 + HCI_ACL_HEADER_SIZE + u16::MAX as usize];
+
+        let bytes_read = self.read_raw(&mut buffer)?;
+        let packet = &buffer[1..bytes_read];
+        let packet = match buffer[0] {
+            HCI_EVENT_PKT => HciEvent::parse(packet).map(HciPacket::Event),
+            HCI_ACL_PKT => AclData::parse(packet).map(HciPacket::Acl),
+            _ => None,
         }
+        .ok_or(HciError::InvalidPacketFormat)?;
 
-        // Parse event
-        match HciEvent::parse(&buffer[1..bytes_read as usize]) {
-            Some(event) => Ok(event),
-            None => Err(HciError::InvalidPacketFormat),
+        #[cfg(feature = "log")]
+        if let HciPacket::Event(event) = &packet {
+            log::trace!("HCI event: {:?}", event);
         }
+        Ok(packet)
+    }
+
+    /// Read the next HCI event or ACL data packet with a timeout
+    ///
+    /// Fails with a `TimedOut` receive error if nothing arrives in time.
+    pub fn read_packet_timeout(&self, timeout: Option<Duration>) -> Result<HciPacket, HciError> {
+        self.wait_readable(timeout)?;
+        self.read_packet()
     }
 
     /// Read an HCI event from the socket with a timeout
     pub fn read_event_timeout(&self, timeout: Option<Duration>) -> Result<HciEvent, HciError> {
+        self.wait_readable(timeout)?;
+        self.read_event()
+    }
+
+    /// Wait until a packet can be read, or fail with a `TimedOut` error
+    fn wait_readable(&self, timeout: Option<Duration>) -> Result<(), HciError> {
+        if self.has_deferred_packets() {
+            return Ok(());
+        }
+        self.wait_socket_readable(timeout)
+    }
+
+    /// Wait until the socket itself is readable
+    fn wait_socket_readable(&self, timeout: Option<Duration>) -> Result<(), HciError> {
         if let Some(timeout) = timeout {
             // Set up the fd_set for select()
             let mut read_fds: libc::fd_set = unsafe { std::mem::zeroed() };
@@ -141,13 +294,221 @@ impl HciSocket {
             }
         }
 
-        // Read the event
-        self.read_event()
+        Ok(())
     }
 
     /// Sends an HCI command to the controller
+    ///
+    /// Fails with `HciError::InvalidParamLength` if the parameters do not fit
+    /// the one-byte length field of a command packet.
     pub fn send_command(&self, command: &HciCommand) -> Result<(), HciError> {
+        let packet = Self::command_packet(command)?;
+        self.write_packet(&packet)
+    }
+
+    /// Frame an HCI command, checking that its parameters fit
+    fn command_packet(command: &HciCommand) -> Result<Vec<u8>, HciError> {
+        // Indicator, opcode and length precede the parameters
         let packet = command.to_packet();
+        let params_len = packet.len() - 4;
+        if params_len > u8::MAX as usize {
+            return Err(HciError::InvalidParamLength(params_len));
+        }
+
+        #[cfg(feature = "log")]
+        log::trace!("HCI command: {:?}", command);
+        Ok(packet)
+    }
+
+    /// Send an HCI command and wait for its Command Complete event
+    ///
+    /// Returns the Command Complete event. A failed Command Complete or
+    /// Command Status is reported as `HciError::CommandFailed`. Other packets
+    /// that arrive meanwhile are kept and returned by the next reads, so an
+    /// event loop on the same socket doesn't lose them. Commands sent this
+    /// way are executed one at a time; a thread blocked in a read on the
+    /// same socket can still take the completion first.
+    pub fn execute_command(&self, command: &HciCommand) -> Result<HciEvent, HciError> {
+        let (ogf, ocf) = command.opcode_parts();
+        let _command = self.command_lock.lock().unwrap();
+        self.send_command(command)?;
+
+        let deadline = Instant::now() + HCI_COMMAND_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.wait_socket_readable(Some(remaining))?;
+
+            let packet = self.read_socket_packet()?;
+            if let HciPacket::Event(event) = &packet {
+                if event.is_command_complete(ogf, ocf) {
+                    return match event.get_status() {
+                        0 => Ok(event.clone()),
+                        status => Err(HciError::CommandFailed(status)),
+                    };
+                }
+                if event.is_command_status(ogf, ocf) && event.get_status() != 0 {
+                    return Err(HciError::CommandFailed(event.get_status()));
+                }
+            }
+            self.deferred.lock().unwrap().push_back(packet);
+        }
+    }
+
+    /// Power up an HCI device with the HCIDEVUP ioctl
+    ///
+    /// A device that is already up is not an error.
+    pub fn device_up(&self, dev_id: u16) -> Result<(), HciError> {
+        match unsafe { libc::ioctl(self.fd, HCIDEVUP as _, dev_id as libc::c_ulong) } {
+            -1 => match std::io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::EALREADY) => Ok(()),
+                e => Err(HciError::SocketError(e)),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Power down an HCI device with the HCIDEVDOWN ioctl
+    pub fn device_down(&self, dev_id: u16) -> Result<(), HciError> {
+        match unsafe { libc::ioctl(self.fd, HCIDEVDOWN as _, dev_id as libc::c_ulong) } {
+            -1 => Err(HciError::SocketError(std::io::Error::last_os_error())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the controller's public device address (HCI Read BD_ADDR)
+    pub fn read_bd_addr(&self) -> Result<BdAddr, HciError> {
+        let event = self.execute_command(&HciCommand::ReadBdAddr)?;
+
+        // Parameters: num packets, opcode, status, BD_ADDR
+        event
+            .get_parameters()
+            .get(4..10)
+            .and_then(BdAddr::from_slice)
+            .ok_or(HciError::InvalidPacketFormat)
+    }
+
+    /// Read the controller's ACL buffer sizes
+    ///
+    /// The LE buffers are read with HCI LE Read Buffer Size. A controller
+    /// that reports no LE buffers shares its BR/EDR buffers with LE, which
+    /// are then read with HCI Read Buffer Size.
+    pub fn initialize(&self) -> Result<LocalControllerInfo, HciError> {
+        let le_buffer = self.read_buffer_info(
+            HciCommand::LeReadBufferSize,
+            BufferInfo::from_le_read_buffer_size,
+        )?;
+
+        // LE-only controllers need not support the BR/EDR command
+        let classic_buffer = match self.read_buffer_info(
+            HciCommand::ReadBufferSize,
+            BufferInfo::from_read_buffer_size,
+        ) {
+            Ok(buffer) => Some(buffer),
+            Err(HciError::CommandFailed(_)) if !le_buffer.is_empty() => None,
+            Err(e) => return Err(e),
+        };
+
+        let le_buffer = match classic_buffer {
+            Some(classic_buffer) if le_buffer.is_empty() => classic_buffer,
+            _ => le_buffer,
+        };
+
+        Ok(LocalControllerInfo {
+            classic_buffer,
+            le_buffer,
+        })
+    }
+
+    /// Send a buffer size command and parse its return parameters
+    fn read_buffer_info(
+        &self,
+        command: HciCommand,
+        parse: fn(&[u8]) -> Option<BufferInfo>,
+    ) -> Result<BufferInfo, HciError> {
+        let (ogf, ocf) = command.opcode_parts();
+        self.send_command(&command)?;
+
+        let event = self.read_event()?;
+        if !event.is_command_complete(ogf, ocf) {
+            return Err(HciError::InvalidPacketFormat);
+        }
+
+        match event.get_status() {
+            0 => {}
+            status => return Err(HciError::CommandFailed(status)),
+        }
+
+        // Parameters: num packets, opcode, status, buffer sizes
+        event
+            .get_parameters()
+            .get(4..)
+            .and_then(parse)
+            .ok_or(HciError::InvalidPacketFormat)
+    }
+
+    /// Encrypt a block with the controller's AES-128 engine (HCI LE Encrypt)
+    ///
+    /// Like all HCI parameters, the key, plaintext and returned ciphertext
+    /// are least significant octet first, i.e. byte-reversed compared to
+    /// FIPS-197 notation.
+    pub fn le_encrypt(&self, key: &[u8; 16], plaintext: &[u8; 16]) -> Result<[u8; 16], HciError> {
+        let event = self.execute_command(&HciCommand::LeEncrypt {
+            key: *key,
+            plaintext_data: *plaintext,
+        })?;
+
+        // Return parameters: status followed by the encrypted data
+        event
+            .parameters
+            .get(4..20)
+            .and_then(|data| data.try_into().ok())
+            .ok_or(HciError::InvalidPacketFormat)
+    }
+
+    /// Get 8 random bytes from the controller (HCI LE Rand)
+    pub fn le_rand(&self) -> Result<[u8; 8], HciError> {
+        let event = self.execute_command(&HciCommand::LeRand)?;
+
+        // Return parameters: status followed by the random number
+        event
+            .parameters
+            .get(4..12)
+            .and_then(|data| data.try_into().ok())
+            .ok_or(HciError::InvalidPacketFormat)
+    }
+
+    /// Sends a single HCI ACL data packet
+    ///
+    /// `data` must fit in the controller's ACL buffer; larger payloads
+    /// should be split with `AclFragmenter`.
+    pub fn send_acl(
+        &self,
+        handle: u16,
+        pb_flag: u8,
+        bc_flag: u8,
+        data: &[u8],
+    ) -> Result<(), HciError> {
+        if handle > 0x0EFF {
+            return Err(HciError::InvalidPacketFormat);
+        }
+
+        if data.len() > u16::MAX as usize {
+            return Err(HciError::InvalidParamLength(data.len()));
+        }
+
+        let handle_and_flags =
+            handle | ((pb_flag as u16 & 0x03) << 12) | ((bc_flag as u16 & 0x03) << 14);
+
+        let mut packet = Vec::with_capacity(1 + HCI_ACL_HEADER_SIZE + data.len());
+        packet.push(HCI_ACL_PKT);
+        packet.extend_from_slice(&handle_and_flags.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        self.write_packet(&packet)
+    }
+
+    /// Writes a fully framed HCI packet (including the packet type indicator)
+    pub(crate) fn write_packet(&self, packet: &[u8]) -> Result<(), HciError> {
         match unsafe {
             libc::write(
                 self.fd,
@@ -156,7 +517,123 @@ impl HciSocket {
             )
         } {
             -1 => Err(HciError::SendError(std::io::Error::last_os_error())),
-            _ => Ok(()),
+            _ => {
+                #[cfg(feature = "capture")]
+                self.capture_packet(packet, false);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write a fully framed HCI packet without blocking
+    ///
+    /// Fails with `ErrorKind::WouldBlock` if the socket can't take the
+    /// packet yet.
+    #[cfg(feature = "tokio")]
+    fn try_write_packet(&self, packet: &[u8]) -> std::io::Result<()> {
+        let written = unsafe {
+            libc::send(
+                self.fd,
+                packet.as_ptr() as *const libc::c_void,
+                packet.len(),
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if written < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        #[cfg(feature = "capture")]
+        self.capture_packet(packet, false);
+        Ok(())
+    }
+}
+
+/// An HCI socket whose events can be awaited on a tokio runtime
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct HciSocketAsync {
+    socket: std::sync::Arc<HciSocket>,
+    fd: tokio::io::unix::AsyncFd<RawFd>,
+}
+
+#[cfg(feature = "tokio")]
+impl HciSocketAsync {
+    /// Register an HCI socket with the current tokio runtime
+    ///
+    /// Must be called from within a runtime. The socket can still be used
+    /// for blocking reads and for sending commands.
+    pub fn new(socket: std::sync::Arc<HciSocket>) -> Result<Self, HciError> {
+        let fd =
+            tokio::io::unix::AsyncFd::new(socket.as_raw_fd()).map_err(HciError::SocketError)?;
+        Ok(Self { socket, fd })
+    }
+
+    /// Get the underlying HCI socket
+    pub fn socket(&self) -> &std::sync::Arc<HciSocket> {
+        &self.socket
+    }
+
+    /// Wait for the next HCI event
+    ///
+    /// The event is read only once the socket is readable, so dropping the
+    /// future (e.g. in `tokio::select!`) never loses an event.
+    pub async fn read_event(&self) -> Result<HciEvent, HciError> {
+        loop {
+            // Packets kept by execute_command don't make the socket readable
+            if self.socket.has_deferred_packets() {
+                return self.socket.read_event();
+            }
+
+            let mut guard = self.fd.readable().await.map_err(HciError::ReceiveError)?;
+
+            // Readiness can be stale when the socket is also read elsewhere,
+            // so poll instead of blocking in read()
+            match self.socket.read_event_timeout(Some(Duration::ZERO)) {
+                Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    guard.clear_ready();
+                }
+                Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Wait for the next HCI event or ACL data packet
+    ///
+    /// Like `read_event`, dropping the future never loses a packet.
+    pub async fn read_packet(&self) -> Result<HciPacket, HciError> {
+        loop {
+            if self.socket.has_deferred_packets() {
+                return self.socket.read_packet();
+            }
+
+            let mut guard = self.fd.readable().await.map_err(HciError::ReceiveError)?;
+
+            match self.socket.read_packet_timeout(Some(Duration::ZERO)) {
+                Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    guard.clear_ready();
+                }
+                Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Send an HCI command once the socket is writable
+    ///
+    /// The write never blocks the runtime thread.
+    pub async fn send_command(&self, command: &HciCommand) -> Result<(), HciError> {
+        let packet = HciSocket::command_packet(command)?;
+        loop {
+            let mut guard = self.fd.writable().await.map_err(HciError::SendError)?;
+
+            // A full socket clears the readiness, so the next wait sleeps
+            // until the controller has taken some packets
+            match guard.try_io(|_| self.socket.try_write_packet(&packet)) {
+                Ok(result) => return result.map_err(HciError::SendError),
+                Err(_would_block) => continue,
+            }
         }
     }
 }
@@ -1,7 +1,13 @@
 //! Unit tests for HCI packet parsing and serialization
 
+use super::acl::*;
 use super::constants::*;
 use super::packet::*;
+use super::socket::HciSocket;
+use crate::error::HciError;
+use crate::gap::BdAddr;
+use crate::testing::MockHciSocket;
+use std::time::Duration;
 
 #[test]
 fn test_hci_command_serialization() {
@@ -150,26 +156,6 @@ fn test_hci_event_parsing() {
 #[test]
 fn test_le_advertising_report_parsing() {
     // Create an LE Advertising Report event
-    let data = [
-        EVT_LE_META_EVENT,         // Event code
-        16,                        // Parameter length
-        EVT_LE_ADVERTISING_REPORT, // Subevent code
-        1,                         // Num_Reports
-        0,                         // Event_Type
-        0,                         // Address_Type
-        0x01,
-        0x02,
-        0x03,
-        0x04,
-        0x05,
-        0x06, // Address
-        3,    // Data_Length
-        0x09,
-        0x54,
-        0x65, // Data (Type: Complete Local Name, Value: "Te")
-        0xC3, // RSSI (-61 dBm)
-    ];
-
     let event = HciEvent {
         event_code: EVT_LE_META_EVENT,
         parameter_total_length: 16,
@@ -203,7 +189,13 @@ fn test_le_advertising_report_parsing() {
     assert_eq!(report.address, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
     assert_eq!(report.data_length, 3);
     assert_eq!(report.data, vec![0x09, 0x54, 0x65]);
-    assert_eq!(report.rssi, -61);
+    assert_eq!(report.rssi, Some(-61));
+
+    // RSSI of 0x7F means the controller could not measure it
+    let mut unavailable = event.clone();
+    *unavailable.parameters.last_mut().unwrap() = LE_RSSI_NOT_AVAILABLE;
+    let reports = LeAdvertisingReport::parse_from_event(&unavailable).unwrap();
+    assert_eq!(reports[0].rssi, None);
 
     // Test invalid cases
 
@@ -239,6 +231,66 @@ fn test_le_advertising_report_parsing() {
     assert_eq!(result.unwrap().len(), 0);
 }
 
+#[test]
+fn test_parse_multiple_le_advertising_reports() {
+    let event = HciEvent {
+        event_code: EVT_LE_META_EVENT,
+        parameter_total_length: 25,
+        parameters: vec![
+            EVT_LE_ADVERTISING_REPORT,
+            2, // Num_Reports
+            // First report: ADV_IND without data
+            LE_ADV_REPORT_ADV_IND,
+            0,
+            0x01,
+            0x02,
+            0x03,
+            0x04,
+            0x05,
+            0x06,
+            0,    // Data_Length
+            0xC3, // RSSI
+            // Second report: SCAN_RSP carrying a name
+            LE_ADV_REPORT_SCAN_RSP,
+            1,
+            0x11,
+            0x12,
+            0x13,
+            0x14,
+            0x15,
+            0x16,
+            3, // Data_Length
+            0x02,
+            0x09,
+            0x41,
+            LE_RSSI_NOT_AVAILABLE,
+        ],
+    };
+
+    let reports = HciEvent::parse_le_advertising_reports(&event);
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].event_type_name(), "ADV_IND");
+    assert!(reports[0].data.is_empty());
+    assert_eq!(reports[0].rssi, Some(-61));
+    assert_eq!(reports[1].event_type_name(), "SCAN_RSP");
+    assert_eq!(reports[1].address, [0x11, 0x12, 0x13, 0x14, 0x15, 0x16]);
+    assert_eq!(reports[1].data, vec![0x02, 0x09, 0x41]);
+    assert_eq!(reports[1].rssi, None);
+
+    // A truncated second report is dropped
+    let mut truncated = event.clone();
+    truncated.parameters.truncate(20);
+    assert_eq!(HciEvent::parse_le_advertising_reports(&truncated).len(), 1);
+
+    // Other events carry no reports
+    let other = HciEvent {
+        event_code: EVT_CMD_COMPLETE,
+        parameter_total_length: 4,
+        parameters: vec![1, 0x03, 0x0C, 0x00],
+    };
+    assert!(HciEvent::parse_le_advertising_reports(&other).is_empty());
+}
+
 // Test GATT connection event parsing
 #[test]
 fn test_gatt_connection_event_parsing() {
@@ -328,3 +380,683 @@ fn test_gatt_connection_event_parsing() {
     let result = DisconnectionComplete::parse(&invalid_event);
     assert!(result.is_none());
 }
+
+#[test]
+fn test_le_advertising_command_serialization() {
+    let command = HciCommand::LeSetAdvertisingParameters {
+        advertising_interval_min: 0x0020,
+        advertising_interval_max: 0x0040,
+        advertising_type: 0x00,
+        own_address_type: 0x01,
+        peer_address_type: 0x00,
+        peer_address: [0; 6],
+        advertising_channel_map: 0x07,
+        advertising_filter_policy: 0x00,
+    };
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2006); // OGF_LE << 10 | OCF_LE_SET_ADVERTISING_PARAMETERS
+    assert_eq!(packet[3], 15);
+    assert_eq!(u16::from_le_bytes([packet[4], packet[5]]), 0x0020);
+    assert_eq!(u16::from_le_bytes([packet[6], packet[7]]), 0x0040);
+    assert_eq!(packet[9], 0x01); // own_address_type
+    assert_eq!(packet[17], 0x07); // advertising_channel_map
+
+    // Advertising data is always sent as a full 31 byte field
+    let command = HciCommand::le_set_advertising_data(&[0x02, 0x01, 0x06]).unwrap();
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2008); // OGF_LE << 10 | OCF_LE_SET_ADVERTISING_DATA
+    assert_eq!(packet[3], 32);
+    assert_eq!(packet[4], 3); // advertising_data_length
+    assert_eq!(&packet[5..8], &[0x02, 0x01, 0x06]);
+    assert!(packet[8..].iter().all(|&b| b == 0));
+
+    assert!(HciCommand::le_set_advertising_data(&[0; 32]).is_err());
+
+    let command = HciCommand::le_set_scan_response_data(&[0x03, 0x09, b'h', b'i']).unwrap();
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2009); // OGF_LE << 10 | OCF_LE_SET_SCAN_RESPONSE_DATA
+    assert_eq!(packet[3], 32);
+    assert_eq!(packet[4], 4); // scan_response_data_length
+    assert_eq!(&packet[5..9], &[0x03, 0x09, b'h', b'i']);
+    assert!(packet[9..].iter().all(|&b| b == 0));
+
+    assert!(HciCommand::le_set_scan_response_data(&[0; 32]).is_err());
+
+    let command = HciCommand::LeSetAdvertiseEnable {
+        advertising_enable: true,
+    };
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x200A); // OGF_LE << 10 | OCF_LE_SET_ADVERTISING_ENABLE
+    assert_eq!(&packet[3..], &[1, 0x01]);
+}
+
+#[test]
+fn test_acl_fragmentation() {
+    let info = BufferInfo {
+        acl_data_packet_length: 27,
+        total_num_acl_data_packets: 8,
+    };
+    let fragmenter = AclFragmenter::new(&info);
+    assert_eq!(fragmenter.max_fragment_size(), 27);
+
+    // A 200-byte SDU needs 8 packets of at most 27 bytes
+    let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let packets = fragmenter.fragment(0x0040, &data).unwrap();
+    assert_eq!(packets.len(), 8);
+
+    let mut reassembled = Vec::new();
+    for (index, packet) in packets.iter().enumerate() {
+        assert_eq!(packet[0], HCI_ACL_PKT);
+
+        let handle_and_flags = u16::from_le_bytes([packet[1], packet[2]]);
+        assert_eq!(handle_and_flags & 0x0FFF, 0x0040);
+
+        // PB=0x02 for the first fragment, PB=0x01 for continuations
+        let pb_flag = ((handle_and_flags >> 12) & 0x03) as u8;
+        if index == 0 {
+            assert_eq!(pb_flag, ACL_PB_FIRST_FLUSHABLE);
+        } else {
+            assert_eq!(pb_flag, ACL_PB_CONTINUING);
+        }
+        assert_eq!((handle_and_flags >> 14) as u8, ACL_BC_POINT_TO_POINT);
+
+        let length = u16::from_le_bytes([packet[3], packet[4]]) as usize;
+        assert!(length <= 27);
+        assert_eq!(packet.len(), 1 + HCI_ACL_HEADER_SIZE + length);
+
+        reassembled.extend_from_slice(&packet[5..]);
+    }
+    assert_eq!(reassembled, data);
+
+    // Payloads that fit in one buffer are not fragmented
+    let packets = fragmenter.fragment(0x0040, &data[..27]).unwrap();
+    assert_eq!(packets.len(), 1);
+
+    // A zero buffer size from the controller is rejected
+    let fragmenter = AclFragmenter::new(&BufferInfo {
+        acl_data_packet_length: 0,
+        total_num_acl_data_packets: 0,
+    });
+    assert!(fragmenter.fragment(0x0040, &data).is_err());
+}
+
+#[test]
+fn test_le_encrypt_command_serialization() {
+    let key = [0x01; 16];
+    let plaintext_data = [0x02; 16];
+    let command = HciCommand::LeEncrypt {
+        key,
+        plaintext_data,
+    };
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2017); // OGF_LE << 10 | OCF_LE_ENCRYPT
+    assert_eq!(packet[3], 32);
+    assert_eq!(&packet[4..20], &key);
+    assert_eq!(&packet[20..36], &plaintext_data);
+}
+
+#[test]
+fn test_le_enable_encryption_command_serialization() {
+    let command = HciCommand::LeEnableEncryption {
+        handle: 0x0040,
+        rand: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+        ediv: 0x1234,
+        ltk: [0xAA; 16],
+    };
+    let packet = command.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2019); // OGF_LE << 10 | OCF_LE_ENABLE_ENCRYPTION
+    assert_eq!(packet[3], 28);
+    assert_eq!(&packet[4..6], &[0x40, 0x00]);
+    assert_eq!(
+        &packet[6..14],
+        &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]
+    );
+    assert_eq!(&packet[14..16], &[0x34, 0x12]);
+    assert_eq!(&packet[16..32], &[0xAA; 16]);
+}
+
+#[test]
+fn test_le_rand_command_serialization() {
+    let packet = HciCommand::LeRand.to_packet();
+
+    let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+    assert_eq!(opcode, 0x2018); // OGF_LE << 10 | OCF_LE_RAND
+    assert_eq!(packet[3], 0);
+    assert_eq!(format!("{:?}", HciCommand::LeRand), "LeRand");
+}
+
+#[test]
+fn test_hci_debug_formatting() {
+    let command = HciCommand::LeSetScanParameters {
+        scan_type: 0x01,
+        scan_interval: 0x0010,
+        scan_window: 0x0011,
+        own_address_type: 0x00,
+        filter_policy: 0x00,
+    };
+    let output = format!("{:?}", command);
+    assert!(output.starts_with("LeSetScanParameters {"));
+    assert!(output.contains("scan_interval: 0x0010 (10 ms)"));
+    assert!(output.contains("scan_window: 0x0011 (10.625 ms)"));
+
+    let command = HciCommand::LeCreateConnection {
+        peer_addr: [0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+        peer_addr_type: 0x00,
+        own_address_type: 0x00,
+        conn_interval_min: 0x0018,
+        conn_interval_max: 0x0028,
+        conn_latency: 0,
+        supervision_timeout: 0x01F4,
+        min_ce_length: 0,
+        max_ce_length: 0,
+    };
+    let output = format!("{:?}", command);
+    assert!(output.contains("peer_addr: 11:22:33:44:55:66"));
+    assert!(output.contains("conn_interval_min: 0x0018 (30 ms)"));
+    assert!(output.contains("supervision_timeout: 0x01F4 (5000 ms)"));
+
+    assert_eq!(format!("{:?}", HciCommand::Reset), "Reset");
+
+    let event = HciEvent::parse(&[EVT_LE_META_EVENT, 0x02, 0x01, 0x00]).unwrap();
+    let output = format!("{:?}", event);
+    assert!(output.contains("event_code: \"LE_META_EVENT(0x3E)\""));
+    assert!(output.contains("parameters: [01 00]"));
+
+    let event = HciEvent::parse(&[0xFF, 0x00]).unwrap();
+    assert!(format!("{:?}", event).contains("event_code: \"0xFF\""));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_event_multiplexer_without_sockets() {
+    use super::multiplexer::EventMultiplexer;
+    use std::time::Duration;
+
+    let mut multiplexer = EventMultiplexer::new(Vec::new());
+    assert!(multiplexer.sockets().is_empty());
+    assert!(matches!(
+        multiplexer.wait_event(Some(Duration::from_millis(1))),
+        Err(HciError::Unsupported)
+    ));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_event_multiplexer_polls_shared_sockets_once() {
+    use super::multiplexer::EventMultiplexer;
+    use crate::testing::MockHciSocket;
+    use std::time::Duration;
+
+    let first = MockHciSocket::new().unwrap();
+    let second = MockHciSocket::new().unwrap();
+    let mut multiplexer = EventMultiplexer::new(vec![first.socket(), first.socket()]);
+    assert_eq!(multiplexer.sockets().len(), 1);
+    assert_eq!(multiplexer.add_socket(second.socket()), 1);
+    assert_eq!(multiplexer.add_socket(first.socket()), 0);
+
+    second
+        .push_events(&[HCI_EVENT_PKT, EVT_HARDWARE_ERROR, 0x01, 0x00])
+        .unwrap();
+    let (index, event) = multiplexer
+        .wait_event(Some(Duration::from_secs(1)))
+        .unwrap();
+    assert_eq!(index, 1);
+    assert_eq!(event.event_code, EVT_HARDWARE_ERROR);
+}
+
+#[test]
+fn test_read_bd_addr_command_serialization() {
+    let packet = HciCommand::ReadBdAddr.to_packet();
+    assert_eq!(packet, vec![HCI_COMMAND_PKT, 0x09, 0x10, 0x00]);
+    assert_eq!(
+        HciCommand::ReadBdAddr.opcode_parts(),
+        (OGF_INFO_PARAM, OCF_READ_BD_ADDR)
+    );
+}
+
+#[test]
+fn test_acl_flow_control_credits() {
+    let flow = AclFlowController::new(&BufferInfo {
+        acl_data_packet_length: 27,
+        total_num_acl_data_packets: 2,
+    });
+
+    assert!(flow.try_acquire(0x0040));
+    assert!(flow.try_acquire(0x0041));
+    assert!(!flow.try_acquire(0x0040));
+    assert_eq!(flow.available(), 0);
+
+    // Two handles, the first with the broadcast flags set
+    let event = HciEvent::parse(&[
+        EVT_NUM_COMP_PKTS,
+        0x09,
+        0x02,
+        0x40,
+        0x20,
+        0x01,
+        0x00,
+        0x41,
+        0x00,
+        0x05,
+        0x00,
+    ])
+    .unwrap();
+    assert_eq!(
+        event.parsed(),
+        Some(ParsedHciEvent::NumberOfCompletedPackets(vec![
+            (0x0040, 1),
+            (0x0041, 5)
+        ]))
+    );
+
+    // Counts beyond what is in flight are ignored
+    assert!(flow.handle_event(&event));
+    assert_eq!(flow.available(), 2);
+    assert_eq!(flow.in_flight(0x0041), 0);
+    assert!(!flow.handle_event(&event));
+
+    assert!(flow.try_acquire(0x0040));
+    flow.connection_closed(0x0040);
+    assert_eq!(flow.available(), 2);
+}
+
+#[test]
+fn test_encryption_event_parsing() {
+    let event = HciEvent::parse(&[EVT_ENCRYPTION_CHANGE, 0x04, 0x00, 0x40, 0x00, 0x01]).unwrap();
+    assert_eq!(
+        event.parsed(),
+        Some(ParsedHciEvent::EncryptionChange(EncryptionChange {
+            status: 0x00,
+            handle: 0x0040,
+            enabled: 0x01,
+        }))
+    );
+
+    let event =
+        HciEvent::parse(&[EVT_ENCRYPT_KEY_REFRESH_COMPLETE, 0x03, 0x00, 0x41, 0x00]).unwrap();
+    assert_eq!(
+        event.parsed(),
+        Some(ParsedHciEvent::EncryptionKeyRefreshComplete {
+            status: 0x00,
+            handle: 0x0041,
+        })
+    );
+
+    // Truncated parameters
+    let event = HciEvent::parse(&[EVT_ENCRYPTION_CHANGE, 0x03, 0x00, 0x40, 0x00]).unwrap();
+    assert_eq!(event.parsed(), None);
+}
+
+#[test]
+fn test_le_connection_complete_parsing() {
+    let peer = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+    let mut params = vec![EVT_LE_CONN_COMPLETE, 0x00, 0x40, 0x00, 0x01, 0x01];
+    params.extend_from_slice(&peer);
+    params.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+    let mut packet = vec![EVT_LE_META_EVENT, params.len() as u8];
+    packet.extend_from_slice(&params);
+
+    let expected = LeConnectionEvent {
+        status: 0x00,
+        handle: 0x0040,
+        role: 0x01,
+        peer_address_type: 0x01,
+        peer_address: BdAddr::new(peer),
+        local_resolvable_private_address: None,
+        peer_resolvable_private_address: None,
+    };
+    assert_eq!(
+        HciEvent::parse(&packet).unwrap().parsed(),
+        Some(ParsedHciEvent::LeConnectionComplete(expected))
+    );
+
+    // The enhanced event also reports the local resolvable private address
+    let rpa = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+    let mut params = vec![EVT_LE_ENHANCED_CONN_COMPLETE, 0x00, 0x40, 0x00, 0x01, 0x01];
+    params.extend_from_slice(&peer);
+    params.extend_from_slice(&rpa);
+    params.extend_from_slice(&[0; 6]); // Peer address was not resolved
+    params.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+    let mut packet = vec![EVT_LE_META_EVENT, params.len() as u8];
+    packet.extend_from_slice(&params);
+    assert_eq!(
+        HciEvent::parse(&packet).unwrap().parsed(),
+        Some(ParsedHciEvent::LeConnectionComplete(LeConnectionEvent {
+            local_resolvable_private_address: Some(BdAddr::new(rpa)),
+            ..expected
+        }))
+    );
+}
+
+#[test]
+fn test_periodic_advertising_commands() {
+    let command = HciCommand::LeSetPeriodicAdvertisingData {
+        advertising_handle: 0x01,
+        operation: 0x03,
+        data: vec![0x02, 0x01, 0x06],
+    };
+    assert_eq!(
+        command.to_packet(),
+        vec![
+            HCI_COMMAND_PKT,
+            0x3F,
+            0x20,
+            0x06,
+            0x01,
+            0x03,
+            0x03,
+            0x02,
+            0x01,
+            0x06
+        ]
+    );
+
+    let command = HciCommand::LeSetPeriodicAdvertisingEnable {
+        enable: true,
+        advertising_handle: 0x01,
+    };
+    assert_eq!(
+        command.to_packet(),
+        vec![HCI_COMMAND_PKT, 0x40, 0x20, 0x02, 0x01, 0x01]
+    );
+
+    let command = HciCommand::LePeriodicAdvertisingCreateSync {
+        options: 0x00,
+        advertising_sid: 0x02,
+        advertiser_address_type: 0x00,
+        advertiser_address: [0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+        skip: 0x0000,
+        sync_timeout: 0x00C8,
+        sync_cte_type: 0x00,
+    };
+    let packet = command.to_packet();
+    assert_eq!(&packet[..4], &[HCI_COMMAND_PKT, 0x44, 0x20, 0x0E]);
+    assert_eq!(&packet[13..], &[0x00, 0x00, 0xC8, 0x00, 0x00]);
+}
+
+#[test]
+fn test_buffer_size_return_parameters() {
+    // LE Read Buffer Size: 251-byte packets, 12 buffers
+    let le = BufferInfo::from_le_read_buffer_size(&[0xFB, 0x00, 0x0C]).unwrap();
+    assert_eq!(le.acl_data_packet_length, 251);
+    assert_eq!(le.total_num_acl_data_packets, 12);
+    assert!(!le.is_empty());
+
+    // A controller sharing its BR/EDR buffers reports zeros
+    let shared = BufferInfo::from_le_read_buffer_size(&[0x00, 0x00, 0x00]).unwrap();
+    assert!(shared.is_empty());
+
+    // Read Buffer Size: 1021-byte ACL packets, 64-byte SCO, 8 ACL, 16 SCO buffers
+    let classic =
+        BufferInfo::from_read_buffer_size(&[0xFD, 0x03, 0x40, 0x08, 0x00, 0x10, 0x00]).unwrap();
+    assert_eq!(classic.acl_data_packet_length, 1021);
+    assert_eq!(classic.total_num_acl_data_packets, 8);
+
+    assert!(BufferInfo::from_read_buffer_size(&[0xFD, 0x03, 0x40]).is_none());
+    assert!(BufferInfo::from_le_read_buffer_size(&[0xFB, 0x00]).is_none());
+
+    let command = HciCommand::ReadBufferSize;
+    assert_eq!(
+        command.opcode_parts(),
+        (OGF_INFO_PARAM, OCF_READ_BUFFER_SIZE)
+    );
+}
+
+#[test]
+fn test_hci_opcode_and_command_complete() {
+    assert_eq!(hci_opcode(OGF_HOST_CTL, OCF_RESET), 0x0C03);
+    assert_eq!(hci_opcode(OGF_LE, OCF_LE_SET_SCAN_ENABLE), 0x200C);
+    assert_eq!(hci_opcode(OGF_VENDOR, HCI_OPCODE_OCF_MASK), 0xFFFF);
+
+    let event = HciEvent::parse(&[EVT_CMD_COMPLETE, 0x04, 0x01, 0x03, 0x0C, 0x00]).unwrap();
+    assert!(event.is_command_complete(OGF_HOST_CTL, OCF_RESET));
+    assert!(!event.is_command_complete(OGF_LE, OCF_RESET));
+
+    let event = HciEvent::parse(&[EVT_CMD_STATUS, 0x04, 0x00, 0x01, 0x03, 0x0C]).unwrap();
+    assert!(!event.is_command_complete(OGF_HOST_CTL, OCF_RESET));
+}
+
+#[test]
+fn test_device_power_ioctl_errors() {
+    // A socket that is not an HCI socket rejects the device ioctls
+    let mut fds = [0; 2];
+    let result = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(result, 0);
+    let socket = HciSocket::from_raw_fd(fds[0]);
+
+    assert!(matches!(
+        socket.device_down(0),
+        Err(HciError::SocketError(_))
+    ));
+    assert!(matches!(socket.device_up(0), Err(HciError::SocketError(_))));
+    unsafe { libc::close(fds[1]) };
+}
+
+#[test]
+fn test_open_invalid_adapter() {
+    // No adapter has the largest index, and without Bluetooth support the
+    // socket cannot be created at all
+    assert!(HciSocket::open(u16::MAX).is_err());
+}
+
+#[test]
+fn test_send_command_rejects_oversized_parameters() {
+    let mock = MockHciSocket::new().unwrap();
+    let command = HciCommand::new(OGF_VENDOR, 0x0001, vec![0; 256]);
+
+    assert!(matches!(
+        mock.socket().send_command(&command),
+        Err(HciError::InvalidParamLength(256))
+    ));
+    assert!(mock.sent_packets().is_empty());
+}
+
+#[test]
+fn test_read_event_timeout_expires() {
+    let mock = MockHciSocket::new().unwrap();
+
+    match mock
+        .socket()
+        .read_event_timeout(Some(Duration::from_millis(1)))
+    {
+        Err(HciError::ReceiveError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_as_raw_fd_is_open() {
+    let mock = MockHciSocket::new().unwrap();
+    let fd = mock.socket().as_raw_fd();
+
+    assert!(fd >= 0);
+    assert!(unsafe { libc::fcntl(fd, libc::F_GETFL) } >= 0);
+}
+
+#[test]
+fn test_read_acl() {
+    let mut fds = [0; 2];
+    let result =
+        unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+    assert_eq!(result, 0);
+    let socket = HciSocket::from_raw_fd(fds[0]);
+
+    let send = |packet: &[u8]| unsafe {
+        libc::send(
+            fds[1],
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+        )
+    };
+
+    // Continuing fragment of 2 bytes on handle 0x0040
+    send(&[HCI_ACL_PKT, 0x40, 0x10, 0x02, 0x00, 0xAA, 0xBB]);
+    assert_eq!(
+        socket.read_acl().unwrap(),
+        AclData {
+            handle: 0x0040,
+            pb_flag: ACL_PB_CONTINUING,
+            bc_flag: 0x00,
+            data: vec![0xAA, 0xBB],
+        }
+    );
+
+    // An event is not ACL data
+    send(&[HCI_EVENT_PKT, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00]);
+    assert!(matches!(
+        socket.read_acl(),
+        Err(HciError::InvalidPacketFormat)
+    ));
+
+    // Length field longer than the data
+    assert!(AclData::parse(&[0x40, 0x00, 0x03, 0x00, 0xAA]).is_none());
+    unsafe { libc::close(fds[1]) };
+}
+
+#[test]
+fn test_read_packet_returns_events_and_acl() {
+    let mock = MockHciSocket::with_script(vec![HCI_EVENT_PKT, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00])
+        .unwrap();
+    mock.push_acl(0x2040, &[0x01, 0x00, 0x04, 0x00, 0x0A])
+        .unwrap();
+    let socket = mock.socket();
+
+    match socket.read_packet().unwrap() {
+        HciPacket::Event(event) => assert_eq!(event.event_code, EVT_CMD_COMPLETE),
+        other => panic!("unexpected packet {:?}", other),
+    }
+    match socket
+        .read_packet_timeout(Some(Duration::from_secs(1)))
+        .unwrap()
+    {
+        HciPacket::Acl(acl) => {
+            assert_eq!(acl.handle, 0x0040);
+            assert_eq!(acl.pb_flag, ACL_PB_FIRST_FLUSHABLE);
+            assert_eq!(acl.data, vec![0x01, 0x00, 0x04, 0x00, 0x0A]);
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+
+    assert!(matches!(
+        socket.read_packet_timeout(Some(Duration::from_millis(1))),
+        Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::TimedOut
+    ));
+}
+
+#[test]
+fn test_execute_command_keeps_other_packets() {
+    // An advertising report and ACL data arrive before the LE Encrypt result
+    let mock = MockHciSocket::with_script(vec![
+        HCI_EVENT_PKT,
+        0x3E,
+        0x02,
+        0x02,
+        0x00, // LE Advertising Report
+    ])
+    .unwrap();
+    mock.push_acl(0x2040, &[0x01, 0x00, 0x04, 0x00, 0x0A])
+        .unwrap();
+    let mut complete = vec![
+        HCI_EVENT_PKT,
+        EVT_CMD_COMPLETE,
+        0x14,
+        0x01,
+        0x17,
+        0x20,
+        0x00,
+    ];
+    complete.extend(1..=16u8);
+    mock.push_events(&complete).unwrap();
+    let socket = mock.socket();
+
+    let expected: [u8; 16] = core::array::from_fn(|i| i as u8 + 1);
+    assert_eq!(socket.le_encrypt(&[0; 16], &[0; 16]).unwrap(), expected);
+
+    // The packets read while waiting are delivered in order afterwards
+    assert_eq!(socket.read_event().unwrap().event_code, 0x3E);
+    assert!(matches!(
+        socket.read_packet_timeout(Some(Duration::from_millis(1))),
+        Ok(HciPacket::Acl(_))
+    ));
+
+    // A failed Command Status ends the wait
+    mock.push_events(&[HCI_EVENT_PKT, EVT_CMD_STATUS, 0x04, 0x0C, 0x01, 0x17, 0x20])
+        .unwrap();
+    assert!(matches!(
+        socket.le_encrypt(&[0; 16], &[0; 16]),
+        Err(HciError::CommandFailed(0x0C))
+    ));
+}
+
+#[cfg(feature = "capture")]
+#[test]
+fn test_capture_writes_btsnoop_records() {
+    let mut fds = [0; 2];
+    let result =
+        unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+    assert_eq!(result, 0);
+    let socket = HciSocket::from_raw_fd(fds[0]);
+
+    let path = std::env::temp_dir().join(format!("rustyblue-{}.btsnoop", std::process::id()));
+    socket.enable_capture(&path).unwrap();
+
+    // Reset, its Command Complete and an ACL packet out
+    socket.send_command(&HciCommand::Reset).unwrap();
+    let event = [HCI_EVENT_PKT, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+    unsafe {
+        libc::send(
+            fds[1],
+            event.as_ptr() as *const libc::c_void,
+            event.len(),
+            0,
+        )
+    };
+    socket.read_event().unwrap();
+    socket
+        .send_acl(0x0040, ACL_PB_FIRST_FLUSHABLE, 0, &[0xAA])
+        .unwrap();
+    socket.disable_capture().unwrap();
+
+    // Not captured any more
+    socket.send_command(&HciCommand::Reset).unwrap();
+
+    let file = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    unsafe { libc::close(fds[1]) };
+
+    assert_eq!(&file[..8], b"btsnoop\0");
+    assert_eq!(&file[8..16], &[0, 0, 0, 1, 0, 0, 0x03, 0xEA]);
+
+    // Each record: original and included length, flags, drops, timestamp
+    let mut records = Vec::new();
+    let mut rest = &file[16..];
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        assert_eq!(&rest[0..4], &rest[4..8]);
+        let flags = u32::from_be_bytes(rest[8..12].try_into().unwrap());
+        records.push((flags, rest[24..24 + length].to_vec()));
+        rest = &rest[24 + length..];
+    }
+
+    assert_eq!(
+        records,
+        vec![
+            (0x02, vec![HCI_COMMAND_PKT, 0x03, 0x0C, 0x00]),
+            (0x03, event.to_vec()),
+            (0x00, vec![HCI_ACL_PKT, 0x40, 0x20, 0x01, 0x00, 0xAA]),
+        ]
+    );
+}
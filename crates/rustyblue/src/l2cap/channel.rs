@@ -3,6 +3,7 @@
 //! This module provides the L2CAP channel abstraction which represents
 //! a logical connection between two devices for a specific protocol or service.
 
+use std::collections::VecDeque;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
@@ -10,8 +11,9 @@ use std::time::{Duration, Instant};
 use super::constants::*;
 use super::packet::*;
 use super::psm::PSM;
-use super::signaling::SignalingMessage;
 use super::types::*;
+use crate::hci::constants::{ACL_BC_POINT_TO_POINT, ACL_PB_FIRST_NON_FLUSHABLE};
+use crate::hci::socket::HciSocket;
 
 /// Callback for received data on an L2CAP channel
 pub type DataCallback = Arc<Mutex<dyn FnMut(&[u8]) -> L2capResult<()> + Send + 'static>>;
@@ -63,6 +65,8 @@ pub struct L2capChannel {
     connection_type: ConnectionType,
     /// Credits (for LE Credit-based channels)
     credits: u16,
+    /// Credits granted to the peer when the channel was set up
+    initial_credits: u16,
     /// Remote credits (for LE Credit-based channels)
     remote_credits: u16,
     /// Maximum PDU size (for LE Credit-based channels)
@@ -73,16 +77,30 @@ pub struct L2capChannel {
     last_activity: Instant,
     /// Flush timeout (in milliseconds)
     flush_timeout: u16,
-    /// Sequence number for last received I-frame
-    last_received_seq: u8,
     /// Next expected sequence number
     expected_tx_seq: u8,
     /// Next sequence number to use for outgoing frames
     next_tx_seq: u8,
     /// Whether retransmission is enabled
     retransmission_enabled: bool,
+    /// Requested flow control mode (Classic channels only)
+    flow_control_mode: FlowControlMode,
     /// Segmentation and reassembly buffer
     reassembly_buffer: Option<(Vec<u8>, usize)>,
+    /// SDUs waiting for credits (for LE Credit-based channels)
+    pending_sdu_queue: VecDeque<Vec<u8>>,
+    /// K-frames of a partially sent SDU waiting for credits
+    pending_frames: VecDeque<Vec<u8>>,
+    /// Maximum number of SDUs waiting for credits
+    max_pending_sdus: usize,
+    /// Whether received SDUs are buffered instead of delivered immediately
+    buffer_received: bool,
+    /// Received SDUs waiting to be delivered to the data callback
+    receive_buffer: VecDeque<Vec<u8>>,
+    /// Maximum number of SDUs in the receive buffer
+    max_received_sdus: usize,
+    /// Whether the local device requested the channel
+    initiator: bool,
 }
 
 impl L2capChannel {
@@ -112,16 +130,24 @@ impl L2capChannel {
             data_callback: None,
             connection_type,
             credits: 0,
+            initial_credits: 0,
             remote_credits: 0,
             mps: mtu,
             remote_mps: mtu,
             last_activity: Instant::now(),
             flush_timeout: L2CAP_DEFAULT_FLUSH_TIMEOUT,
-            last_received_seq: 0,
             expected_tx_seq: 0,
             next_tx_seq: 0,
             retransmission_enabled: false,
+            flow_control_mode: FlowControlMode::Basic,
             reassembly_buffer: None,
+            pending_sdu_queue: VecDeque::new(),
+            pending_frames: VecDeque::new(),
+            max_pending_sdus: L2CAP_DEFAULT_MAX_PENDING_SDUS,
+            buffer_received: false,
+            receive_buffer: VecDeque::new(),
+            max_received_sdus: L2CAP_DEFAULT_MAX_RECEIVED_SDUS,
+            initiator: false,
         }
     }
 
@@ -134,6 +160,12 @@ impl L2capChannel {
         let mut channel = Self::new(local_cid, channel_type, connection_type);
         channel.remote_cid = local_cid; // Fixed channels have the same CID on both sides
         channel.state = L2capChannelState::Open; // Fixed channels are always open
+        if channel_type == L2capChannelType::SecurityManager
+            && connection_type == ConnectionType::LE
+        {
+            channel.mtu = L2CAP_LE_SMP_MTU;
+            channel.remote_mtu = L2CAP_LE_SMP_MTU;
+        }
         channel
     }
 
@@ -159,6 +191,7 @@ impl L2capChannel {
         channel.mtu = config.mtu;
         channel.mps = config.mps;
         channel.credits = config.initial_credits;
+        channel.initial_credits = config.initial_credits;
         channel
     }
 
@@ -192,11 +225,26 @@ impl L2capChannel {
         self.state = state;
     }
 
+    /// Check whether the local device requested the channel
+    pub fn is_initiator(&self) -> bool {
+        self.initiator
+    }
+
+    /// Mark the channel as requested by the local device
+    pub fn set_initiator(&mut self, initiator: bool) {
+        self.initiator = initiator;
+    }
+
     /// Get the channel type
     pub fn channel_type(&self) -> L2capChannelType {
         self.channel_type
     }
 
+    /// Get the type of connection the channel runs on
+    pub fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
     /// Get the Maximum Transmission Unit (MTU)
     pub fn mtu(&self) -> u16 {
         self.mtu
@@ -212,6 +260,16 @@ impl L2capChannel {
         self.remote_mtu = mtu;
     }
 
+    /// Get the remote device's MPS (for LE Credit-based channels)
+    pub fn remote_mps(&self) -> u16 {
+        self.remote_mps
+    }
+
+    /// Set the remote device's MPS (for LE Credit-based channels)
+    pub fn set_remote_mps(&mut self, mps: u16) {
+        self.remote_mps = mps;
+    }
+
     /// Get the effective MTU (minimum of local and remote)
     pub fn effective_mtu(&self) -> u16 {
         std::cmp::min(self.mtu, self.remote_mtu)
@@ -225,6 +283,11 @@ impl L2capChannel {
         self.data_callback = Some(Arc::new(Mutex::new(callback)));
     }
 
+    /// Get a handle to the data callback, if one is set
+    pub fn data_callback(&self) -> Option<DataCallback> {
+        self.data_callback.clone()
+    }
+
     /// Clear the data callback
     pub fn clear_data_callback(&mut self) {
         self.data_callback = None;
@@ -232,14 +295,14 @@ impl L2capChannel {
 
     /// Check if the channel is fixed
     pub fn is_fixed(&self) -> bool {
-        match self.channel_type {
+        matches!(
+            self.channel_type,
             L2capChannelType::Signaling
-            | L2capChannelType::Connectionless
-            | L2capChannelType::AmpManager
-            | L2capChannelType::AttributeProtocol
-            | L2capChannelType::SecurityManager => true,
-            _ => false,
-        }
+                | L2capChannelType::Connectionless
+                | L2capChannelType::AmpManager
+                | L2capChannelType::AttributeProtocol
+                | L2capChannelType::SecurityManager
+        )
     }
 
     /// Check if the channel uses retransmission mode
@@ -247,44 +310,114 @@ impl L2capChannel {
         self.retransmission_enabled
     }
 
-    /// Handle configuration options
-    pub fn configure(&mut self, options: &ConfigOptions) -> L2capResult<()> {
-        // Update channel configuration based on received options
-        if let Some(mtu) = options.mtu {
-            self.remote_mtu = mtu;
+    /// Get the flow control mode requested for this channel
+    pub fn flow_control_mode(&self) -> FlowControlMode {
+        self.flow_control_mode
+    }
+
+    /// Set the flow control mode to request for this channel
+    ///
+    /// Non-basic modes are sent in the Retransmission and Flow Control
+    /// option of the next Configuration Request. They only apply to Classic
+    /// connection-oriented channels.
+    pub fn set_flow_control_mode(&mut self, mode: FlowControlMode) -> L2capResult<()> {
+        if mode != FlowControlMode::Basic
+            && (self.connection_type != ConnectionType::Classic
+                || self.channel_type != L2capChannelType::ConnectionOriented)
+        {
+            return Err(L2capError::NotSupported);
         }
 
-        if let Some(flush_timeout) = options.flush_timeout {
-            self.flush_timeout = flush_timeout;
+        self.flow_control_mode = mode;
+        self.config.retransmission = mode.to_option();
+        Ok(())
+    }
+
+    /// Get the local configuration options
+    pub fn config(&self) -> &ConfigOptions {
+        &self.config
+    }
+
+    /// Handle configuration options from a Configuration Request
+    ///
+    /// Returns the result and the options to send in the Configuration
+    /// Response. A mode other than Basic is only accepted if it matches the
+    /// mode set with `set_flow_control_mode`; otherwise Basic mode is
+    /// suggested back to the remote device.
+    ///
+    /// Configuration only exists on Classic channels. LE Credit-based
+    /// channels fix their MTU and MPS in the connection request, so this
+    /// fails with `L2capError::NotSupported` for them. Channels that are not
+    /// connected yet or are disconnecting fail with
+    /// `L2capError::InvalidState`.
+    pub fn configure(
+        &mut self,
+        options: &ConfigOptions,
+    ) -> L2capResult<(ConfigureResult, ConfigOptions)> {
+        if self.connection_type != ConnectionType::Classic
+            || self.channel_type == L2capChannelType::LeCreditBased
+        {
+            return Err(L2capError::NotSupported);
         }
 
-        if let Some(qos) = options.qos {
-            self.qos = Some(qos);
+        match self.state {
+            L2capChannelState::WaitConfig
+            | L2capChannelState::WaitConfigReq
+            | L2capChannelState::WaitFinalConfig
+            | L2capChannelState::Open => {}
+            L2capChannelState::Closed
+            | L2capChannelState::WaitConnectRsp
+            | L2capChannelState::WaitDisconnect => return Err(L2capError::InvalidState),
         }
 
+        let mut response = ConfigOptions::default();
+
         if let Some(rfc) = options.retransmission {
+            let requested = FlowControlMode::from_option(&rfc);
+            let supported = match requested {
+                Some(FlowControlMode::Basic) => true,
+                Some(mode) => mode.mode() == self.flow_control_mode.mode(),
+                None => false,
+            };
+
+            if !supported {
+                // Suggest Basic mode, which every device supports
+                response.retransmission = Some(RetransmissionFlowControl::default());
+                return Ok((ConfigureResult::UnacceptableParameters, response));
+            }
+
             match rfc.mode {
-                RetransmissionMode::Basic => {
-                    self.retransmission_enabled = false;
-                }
-                RetransmissionMode::Retransmission | RetransmissionMode::EnhancedRetransmission => {
+                RetransmissionMode::EnhancedRetransmission => {
                     self.retransmission_enabled = true;
                     // Initialize retransmission parameters
                     self.next_tx_seq = 0;
                     self.expected_tx_seq = 0;
                 }
-                RetransmissionMode::FlowControl => {
+                _ => {
                     self.retransmission_enabled = false;
-                    // Initialize flow control parameters
-                }
-                RetransmissionMode::Streaming => {
-                    self.retransmission_enabled = false;
-                    // Initialize streaming parameters
                 }
             }
+
+            if rfc.mode != RetransmissionMode::Basic {
+                response.retransmission = Some(rfc);
+            }
         }
 
-        Ok(())
+        // Update channel configuration based on received options
+        if let Some(mtu) = options.mtu {
+            self.remote_mtu = mtu;
+        }
+
+        if let Some(flush_timeout) = options.flush_timeout {
+            self.flush_timeout = flush_timeout;
+        }
+
+        if let Some(qos) = options.qos {
+            self.qos = Some(qos);
+        }
+
+        self.remote_config = options.clone();
+        Ok((ConfigureResult::Success, response))
     }
 
     /// Handle received data for this channel
@@ -296,14 +429,88 @@ impl L2capChannel {
             return self.handle_retransmission_data(data);
         }
 
-        // If it's a regular channel, just pass the data to the callback
+        if self.channel_type == L2capChannelType::LeCreditBased {
+            return match self.reassemble_k_frame(data)? {
+                Some(sdu) => self.deliver_sdu(&sdu),
+                None => Ok(()),
+            };
+        }
+
+        // If it's a regular channel, just pass the data on
+        self.deliver_sdu(data)
+    }
+
+    /// Check whether received SDUs are buffered
+    pub fn receive_buffering(&self) -> bool {
+        self.buffer_received
+    }
+
+    /// Buffer received SDUs instead of delivering them immediately
+    ///
+    /// With buffering, `handle_data` only stores complete SDUs and returns,
+    /// so a slow data callback doesn't hold up the receiving thread. The
+    /// SDUs are delivered by `drain_receive_buffer`. Off by default.
+    pub fn set_receive_buffering(&mut self, enabled: bool) {
+        self.buffer_received = enabled;
+    }
+
+    /// Get the number of SDUs in the receive buffer
+    pub fn received_sdus(&self) -> usize {
+        self.receive_buffer.len()
+    }
+
+    /// Get the maximum number of SDUs in the receive buffer
+    pub fn max_received_sdus(&self) -> usize {
+        self.max_received_sdus
+    }
+
+    /// Set the maximum number of SDUs in the receive buffer
+    ///
+    /// SDUs already buffered are kept even if there are more than `max`.
+    pub fn set_max_received_sdus(&mut self, max: usize) {
+        self.max_received_sdus = max;
+    }
+
+    /// Take the oldest SDU out of the receive buffer
+    pub fn next_received_sdu(&mut self) -> Option<Vec<u8>> {
+        self.receive_buffer.pop_front()
+    }
+
+    /// Deliver the buffered SDUs to the data callback
+    ///
+    /// Returns the number of SDUs delivered. Without a callback the SDUs
+    /// stay buffered. An SDU whose callback fails is not delivered again.
+    pub fn drain_receive_buffer(&mut self) -> L2capResult<usize> {
+        let Some(callback) = self.data_callback.clone() else {
+            return Ok(0);
+        };
+
+        let mut delivered = 0;
+        while let Some(sdu) = self.receive_buffer.pop_front() {
+            let mut callback = callback.lock().unwrap();
+            (*callback)(&sdu)?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+
+    /// Buffer a complete SDU or pass it to the data callback
+    ///
+    /// Fails with `QueueFull` when the receive buffer has no room.
+    fn deliver_sdu(&mut self, sdu: &[u8]) -> L2capResult<()> {
+        if self.buffer_received {
+            if self.receive_buffer.len() >= self.max_received_sdus {
+                return Err(L2capError::QueueFull);
+            }
+            self.receive_buffer.push_back(sdu.to_vec());
+            return Ok(());
+        }
+
         if let Some(callback) = &self.data_callback {
             let mut callback = callback.lock().unwrap();
-            (*callback)(data)
-        } else {
-            // No callback registered
-            Ok(())
+            (*callback)(sdu)?;
         }
+        Ok(())
     }
 
     /// Handle data for channels in retransmission mode
@@ -379,10 +586,7 @@ impl L2capChannel {
         match control.sar {
             0 => {
                 // Unsegmented
-                if let Some(callback) = &self.data_callback {
-                    let mut callback = callback.lock().unwrap();
-                    (*callback)(payload)?;
-                }
+                self.deliver_sdu(payload)?;
             }
             1 => {
                 // Start
@@ -391,7 +595,7 @@ impl L2capChannel {
                 }
 
                 // First two bytes contain total SDU length
-                let mut sdu_length = ((payload[1] as u16) << 8) | (payload[0] as u16);
+                let sdu_length = ((payload[1] as u16) << 8) | (payload[0] as u16);
 
                 // Initialize reassembly buffer with the total length
                 let mut buffer = Vec::with_capacity(sdu_length as usize);
@@ -400,27 +604,20 @@ impl L2capChannel {
                 self.reassembly_buffer = Some((buffer, sdu_length as usize));
             }
             2 => {
-                // End
-                if let Some((ref mut buffer, total_length)) = self.reassembly_buffer {
+                // End, which also clears the reassembly buffer
+                if let Some((mut buffer, total_length)) = self.reassembly_buffer.take() {
                     // Add the final segment
                     buffer.extend_from_slice(payload);
 
                     // Check if we've received the expected total length
                     if buffer.len() != total_length {
-                        self.reassembly_buffer = None;
                         return Err(L2capError::ProtocolError(
                             "SDU length mismatch in reassembly".into(),
                         ));
                     }
 
-                    // Send complete PDU to callback
-                    if let Some(callback) = &self.data_callback {
-                        let mut callback = callback.lock().unwrap();
-                        (*callback)(buffer)?;
-                    }
-
-                    // Clear the reassembly buffer
-                    self.reassembly_buffer = None;
+                    // Pass on the complete SDU
+                    self.deliver_sdu(&buffer)?;
                 } else {
                     return Err(L2capError::ProtocolError(
                         "Received END segment without START".into(),
@@ -465,6 +662,212 @@ impl L2capChannel {
         Ok(())
     }
 
+    /// Get the number of credits the peer has granted
+    pub fn remote_credits(&self) -> u16 {
+        self.remote_credits
+    }
+
+    /// Check whether data can be sent now or has to wait for credits
+    ///
+    /// Data also waits while earlier SDUs are queued so that SDUs are sent
+    /// in order.
+    pub fn must_queue(&self) -> bool {
+        self.channel_type == L2capChannelType::LeCreditBased
+            && (self.remote_credits == 0
+                || !self.pending_sdu_queue.is_empty()
+                || !self.pending_frames.is_empty())
+    }
+
+    /// Queue an SDU until the peer grants credits
+    pub fn queue_sdu(&mut self, data: &[u8]) -> L2capResult<()> {
+        if self.channel_type != L2capChannelType::LeCreditBased {
+            return Err(L2capError::InvalidState);
+        }
+
+        if data.len() > self.remote_mtu as usize {
+            return Err(L2capError::MtuExceeded {
+                requested: data.len(),
+                allowed: self.remote_mtu as usize,
+            });
+        }
+
+        if self.pending_sdu_queue.len() >= self.max_pending_sdus {
+            return Err(L2capError::QueueFull);
+        }
+
+        self.pending_sdu_queue.push_back(data.to_vec());
+        Ok(())
+    }
+
+    /// Get the number of SDUs waiting for credits
+    pub fn pending_sdus(&self) -> usize {
+        self.pending_sdu_queue.len()
+    }
+
+    /// Get the maximum number of SDUs that can wait for credits
+    pub fn max_pending_sdus(&self) -> usize {
+        self.max_pending_sdus
+    }
+
+    /// Set the maximum number of SDUs that can wait for credits
+    ///
+    /// SDUs already queued are kept even if there are more than `max`.
+    pub fn set_max_pending_sdus(&mut self, max: usize) {
+        self.max_pending_sdus = max;
+    }
+
+    /// Take the queued K-frames the peer has granted credits for
+    ///
+    /// SDUs are segmented to the peer's MPS, the first K-frame carrying the
+    /// SDU length. Each K-frame uses one credit; the rest of a partially
+    /// sent SDU waits for more credits ahead of the queued SDUs.
+    pub fn take_sendable_frames(&mut self) -> L2capResult<Vec<L2capPacket>> {
+        if self.channel_type != L2capChannelType::LeCreditBased
+            || self.state != L2capChannelState::Open
+        {
+            return Err(L2capError::InvalidState);
+        }
+
+        if self.remote_cid == 0 {
+            return Err(L2capError::NotConnected);
+        }
+
+        let mut frames = Vec::new();
+        while self.remote_credits > 0 {
+            if self.pending_frames.is_empty() {
+                let Some(sdu) = self.pending_sdu_queue.pop_front() else {
+                    break;
+                };
+                self.pending_frames = self.segment_sdu(&sdu);
+            }
+
+            let Some(frame) = self.pending_frames.pop_front() else {
+                break;
+            };
+            frames.push(L2capPacket::new(self.remote_cid, frame));
+            self.remote_credits -= 1;
+        }
+
+        if !frames.is_empty() {
+            self.update_activity();
+        }
+        Ok(frames)
+    }
+
+    /// Send the queued K-frames the peer has granted credits for
+    ///
+    /// Each frame is written to `hci_socket` as a single ACL packet. Returns
+    /// the number of K-frames sent; on a send error the unsent frames and
+    /// their credits are kept for the next call.
+    pub fn drain_pending(&mut self, hci_socket: &HciSocket, hci_handle: u16) -> L2capResult<usize> {
+        let frames = self.take_sendable_frames()?;
+
+        for (sent, packet) in frames.iter().enumerate() {
+            let result = hci_socket.send_acl(
+                hci_handle,
+                ACL_PB_FIRST_NON_FLUSHABLE,
+                ACL_BC_POINT_TO_POINT,
+                &packet.to_bytes(),
+            );
+            if let Err(e) = result {
+                let unsent = &frames[sent..];
+                for packet in unsent.iter().rev() {
+                    self.pending_frames.push_front(packet.payload.clone());
+                }
+                self.remote_credits += unsent.len() as u16;
+                return Err(e.into());
+            }
+        }
+
+        Ok(frames.len())
+    }
+
+    /// Split an SDU into K-frame payloads no larger than the peer's MPS
+    fn segment_sdu(&self, sdu: &[u8]) -> VecDeque<Vec<u8>> {
+        let mps = (self.remote_mps as usize).max(L2CAP_SDU_LENGTH_SIZE + 1);
+        let first_len = sdu.len().min(mps - L2CAP_SDU_LENGTH_SIZE);
+
+        let mut first = Vec::with_capacity(L2CAP_SDU_LENGTH_SIZE + first_len);
+        first.extend_from_slice(&(sdu.len() as u16).to_le_bytes());
+        first.extend_from_slice(&sdu[..first_len]);
+
+        let mut frames = VecDeque::from([first]);
+        frames.extend(sdu[first_len..].chunks(mps).map(<[u8]>::to_vec));
+        frames
+    }
+
+    /// Handle a received K-frame
+    ///
+    /// Each K-frame uses one of the credits granted to the peer. Returns the
+    /// SDU once its last K-frame has arrived.
+    pub fn reassemble_k_frame(&mut self, frame: &[u8]) -> L2capResult<Option<Vec<u8>>> {
+        if self.credits == 0 {
+            return Err(L2capError::ProtocolError(
+                "K-frame received without credits".into(),
+            ));
+        }
+        self.credits -= 1;
+
+        if frame.len() > self.mps as usize {
+            self.reassembly_buffer = None;
+            return Err(L2capError::MtuExceeded {
+                requested: frame.len(),
+                allowed: self.mps as usize,
+            });
+        }
+
+        let (buffer, sdu_length) = match self.reassembly_buffer.take() {
+            Some((mut buffer, sdu_length)) => {
+                buffer.extend_from_slice(frame);
+                (buffer, sdu_length)
+            }
+            None => {
+                // The first K-frame of an SDU starts with the SDU length
+                if frame.len() < L2CAP_SDU_LENGTH_SIZE {
+                    return Err(L2capError::InvalidParameter(
+                        "K-frame too short for the SDU length".into(),
+                    ));
+                }
+
+                let sdu_length = u16::from_le_bytes([frame[0], frame[1]]) as usize;
+                if sdu_length > self.mtu as usize {
+                    return Err(L2capError::MtuExceeded {
+                        requested: sdu_length,
+                        allowed: self.mtu as usize,
+                    });
+                }
+                (frame[L2CAP_SDU_LENGTH_SIZE..].to_vec(), sdu_length)
+            }
+        };
+
+        match buffer.len().cmp(&sdu_length) {
+            std::cmp::Ordering::Less => {
+                self.reassembly_buffer = Some((buffer, sdu_length));
+                Ok(None)
+            }
+            std::cmp::Ordering::Equal => Ok(Some(buffer)),
+            std::cmp::Ordering::Greater => Err(L2capError::InvalidParameter(
+                "K-frames exceed the SDU length".into(),
+            )),
+        }
+    }
+
+    /// Take the credits to return to the peer
+    ///
+    /// Credits are returned in one batch once the peer has used half of
+    /// the credits it was granted.
+    pub fn take_returned_credits(&mut self) -> Option<u16> {
+        if self.channel_type != L2capChannelType::LeCreditBased
+            || self.credits > self.initial_credits / 2
+        {
+            return None;
+        }
+
+        let returned = self.initial_credits - self.credits;
+        self.credits = self.initial_credits;
+        (returned > 0).then_some(returned)
+    }
+
     /// Consume credits when sending data
     pub fn consume_credits(&mut self, count: u16) -> L2capResult<()> {
         if self.channel_type != L2capChannelType::LeCreditBased {
@@ -480,6 +883,9 @@ impl L2capChannel {
     }
 
     /// Create a data packet for this channel
+    ///
+    /// On LE Credit-based channels this is a single K-frame, so the SDU has
+    /// to fit in the peer's MPS.
     pub fn create_data_packet(&self, data: &[u8]) -> L2capResult<L2capPacket> {
         if self.state != L2capChannelState::Open {
             return Err(L2capError::InvalidState);
@@ -489,9 +895,13 @@ impl L2capChannel {
             return Err(L2capError::NotConnected);
         }
 
-        // Check if data exceeds MTU
+        // The remote MTU is the negotiated MTU for Classic channels and
+        // the peer's MTU for LE credit-based channels
         if data.len() > self.remote_mtu as usize {
-            return Err(L2capError::MtuExceeded);
+            return Err(L2capError::MtuExceeded {
+                requested: data.len(),
+                allowed: self.remote_mtu as usize,
+            });
         }
 
         // For LE Credit-based channels, check credits
@@ -499,6 +909,22 @@ impl L2capChannel {
             return Err(L2capError::ResourceLimitReached);
         }
 
+        if self.channel_type == L2capChannelType::LeCreditBased {
+            // Larger SDUs are segmented by `take_sendable_frames`
+            let allowed = (self.remote_mps as usize).saturating_sub(L2CAP_SDU_LENGTH_SIZE);
+            if data.len() > allowed {
+                return Err(L2capError::MtuExceeded {
+                    requested: data.len(),
+                    allowed,
+                });
+            }
+
+            let mut frame = Vec::with_capacity(L2CAP_SDU_LENGTH_SIZE + data.len());
+            frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            frame.extend_from_slice(data);
+            return Ok(L2capPacket::new(self.remote_cid, frame));
+        }
+
         let packet = if self.retransmission_enabled {
             // Create packet with control field for retransmission mode
             let control = L2capControlField::new_i_frame(
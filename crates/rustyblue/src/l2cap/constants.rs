@@ -27,6 +27,11 @@ pub const L2CAP_CONNECTION_PARAMETER_UPDATE_RESPONSE: u8 = 0x13;
 pub const L2CAP_LE_CREDIT_BASED_CONNECTION_REQUEST: u8 = 0x14;
 pub const L2CAP_LE_CREDIT_BASED_CONNECTION_RESPONSE: u8 = 0x15;
 pub const L2CAP_LE_FLOW_CONTROL_CREDIT: u8 = 0x16;
+pub const L2CAP_CREDIT_BASED_CONNECTION_REQUEST: u8 = 0x17;
+pub const L2CAP_CREDIT_BASED_CONNECTION_RESPONSE: u8 = 0x18;
+
+// Maximum number of channels in one Enhanced Credit Based Connection Request
+pub const L2CAP_CREDIT_BASED_MAX_CHANNELS: usize = 5;
 
 // Reserved Channel IDs
 pub const L2CAP_NULL_CID: u16 = 0x0000;
@@ -36,8 +41,10 @@ pub const L2CAP_AMP_MANAGER_CID: u16 = 0x0003;
 pub const L2CAP_ATTRIBUTE_PROTOCOL_CID: u16 = 0x0004;
 pub const L2CAP_LE_SIGNALING_CID: u16 = 0x0005;
 pub const L2CAP_SECURITY_MANAGER_PROTOCOL_CID: u16 = 0x0006;
+pub const L2CAP_ATT_CID: u16 = L2CAP_ATTRIBUTE_PROTOCOL_CID;
 pub const L2CAP_DYNAMIC_CID_MIN: u16 = 0x0040;
 pub const L2CAP_DYNAMIC_CID_MAX: u16 = 0xFFFF;
+pub const L2CAP_LE_DYNAMIC_CID_MAX: u16 = 0x007F;
 
 // Result codes for L2CAP signaling
 pub const L2CAP_RESULT_SUCCESS: u16 = 0x0000;
@@ -47,6 +54,8 @@ pub const L2CAP_RESULT_REFUSED_SECURITY_BLOCK: u16 = 0x0003;
 pub const L2CAP_RESULT_REFUSED_NO_RESOURCES: u16 = 0x0004;
 pub const L2CAP_RESULT_INVALID_SOURCE_CID: u16 = 0x0006;
 pub const L2CAP_RESULT_SOURCE_CID_ALREADY_ALLOCATED: u16 = 0x0007;
+pub const L2CAP_RESULT_CREDIT_BASED_INVALID_SOURCE_CID: u16 = 0x0009;
+pub const L2CAP_RESULT_CREDIT_BASED_SOURCE_CID_ALREADY_ALLOCATED: u16 = 0x000A;
 pub const L2CAP_RESULT_UNACCEPTABLE_PARAMETERS: u16 = 0x000B;
 pub const L2CAP_RESULT_INVALID_PARAMETERS: u16 = 0x000C;
 
@@ -62,14 +71,27 @@ pub const L2CAP_CONF_EXT_WINDOW: u8 = 0x07;
 // Default values
 pub const L2CAP_DEFAULT_MTU: u16 = 672;
 pub const L2CAP_LE_DEFAULT_MTU: u16 = 23;
+pub const L2CAP_LE_SMP_MTU: u16 = 65; // Fits the Secure Connections public key
+pub const L2CAP_CREDIT_BASED_MIN_MTU: u16 = 64; // Also the minimum MPS
 pub const L2CAP_LE_MAX_MPS: u16 = 65533;
+pub const L2CAP_SDU_LENGTH_SIZE: usize = 2; // SDU length field of the first K-frame
 pub const L2CAP_DEFAULT_FLUSH_TIMEOUT: u16 = 0xFFFF;
+pub const L2CAP_DEFAULT_MAX_PENDING_SDUS: usize = 32;
+pub const L2CAP_DEFAULT_MAX_RECEIVED_SDUS: usize = 8;
+pub const L2CAP_SIGNALING_MTU: u16 = 48; // Minimum BR/EDR signaling MTU
+pub const L2CAP_ECHO_TIMEOUT_MS: u64 = 5000;
+pub const L2CAP_RTX_TIMEOUT_MS: u64 = 1000; // Response timeout for signaling requests
+pub const L2CAP_DEFAULT_SIGNALING_RETRIES: u8 = 3;
 
 // Information Request types
 pub const L2CAP_CONNLESS_MTU: u16 = 0x0001;
 pub const L2CAP_EXTENDED_FEATURES: u16 = 0x0002;
 pub const L2CAP_FIXED_CHANNELS: u16 = 0x0003;
 
+// Information Response results
+pub const L2CAP_INFO_SUCCESS: u16 = 0x0000;
+pub const L2CAP_INFO_NOT_SUPPORTED: u16 = 0x0001;
+
 // Extended Features mask bits
 pub const L2CAP_FEATURE_FLOW_CONTROL: u32 = 0x00000001;
 pub const L2CAP_FEATURE_RETRANSMISSION: u32 = 0x00000002;
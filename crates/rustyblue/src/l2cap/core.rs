@@ -6,28 +6,39 @@
 //! - Signaling commands
 //! - Connection setup and teardown
 
-use crate::error::{Error, HciError};
+use crate::gap::BdAddr;
+use crate::hci::constants::{ACL_BC_POINT_TO_POINT, ACL_PB_CONTINUING, ACL_PB_FIRST_NON_FLUSHABLE};
 use crate::hci::socket::HciSocket;
-use crate::l2cap::channel::{DataCallback, L2capChannel};
+use crate::hci::{AclData, AclFlowController, AclFragmenter, HciEvent, LocalControllerInfo};
+use crate::l2cap::channel::{DataCallback, L2capChannel, L2capChannelType};
 use crate::l2cap::constants::*;
 use crate::l2cap::packet::L2capPacket;
 use crate::l2cap::psm::PSM;
 use crate::l2cap::signaling::SignalingMessage;
 use crate::l2cap::types::{
     ChannelId, ConfigOptions, ConfigureResult, ConnectionParameterUpdate, ConnectionPolicy,
-    ConnectionType, L2capChannelState, L2capError, L2capResult, LeCreditBasedConfig, SecurityLevel,
+    ConnectionType, ExtendedFeatures, L2capChannelState, L2capError, L2capResult,
+    LeCreditBasedConfig, RttStats,
 };
-use crate::l2cap::ChannelEventCallback;
-use log::{debug, error, info, trace, warn};
-use std::collections::{HashMap, VecDeque};
-use std::fmt;
-use std::sync::{Arc, Mutex, RwLock};
+use crate::logging::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 /// Callback for channel events like connect, disconnect, etc.
 pub type ChannelEventCallback =
     Arc<Mutex<dyn FnMut(ChannelEvent) -> L2capResult<()> + Send + 'static>>;
 
+/// Callback invoked with the HCI handle of a connection that has closed
+pub type ConnectionClosedCallback = Box<dyn Fn(u16) + Send + Sync>;
+
+/// Callback invoked with the HCI handle and peer address of a new connection
+pub type ConnectionOpenedCallback = Box<dyn Fn(u16, BdAddr) + Send + Sync>;
+
+/// Callback for data on a fixed channel, called with the peer address
+pub type FixedChannelCallback =
+    Arc<Mutex<dyn FnMut(BdAddr, &[u8]) -> L2capResult<()> + Send + 'static>>;
+
 /// Channel events for callbacks
 #[derive(Debug, Clone)]
 pub enum ChannelEvent {
@@ -75,16 +86,10 @@ pub enum ChannelEvent {
 /// Represents a registration for a specific PSM.
 #[derive(Clone)]
 struct PsmRegistration {
-    /// PSM value
-    psm: PSM,
     /// Data callback for this PSM
     data_callback: Option<DataCallback>,
     /// Event callback for this PSM
     event_callback: Option<ChannelEventCallback>,
-    /// Security requirements
-    security_level: SecurityLevel,
-    /// Whether authorization is required
-    authorization_required: bool,
     /// Whether to auto-accept connections
     auto_accept: bool,
 }
@@ -92,7 +97,7 @@ struct PsmRegistration {
 /// L2CAP Manager responsible for handling L2CAP operations
 pub struct L2capManager {
     /// Channels mapped by local CID
-    channels: RwLock<HashMap<ChannelId, L2capChannel>>,
+    pub(super) channels: RwLock<HashMap<ChannelId, L2capChannel>>,
 
     /// Registered PSMs
     psm_registrations: RwLock<HashMap<u16, PsmRegistration>>,
@@ -100,6 +105,19 @@ pub struct L2capManager {
     /// Map of remote HCI handles to local CIDs
     handle_to_cid: RwLock<HashMap<u16, Vec<ChannelId>>>,
 
+    /// Map of remote HCI handles to peer addresses
+    handle_to_addr: RwLock<HashMap<u16, BdAddr>>,
+
+    /// Extended features reported by the peer, by HCI handle
+    extended_features: RwLock<HashMap<u16, ExtendedFeatures>>,
+
+    /// Data callbacks of fixed channels for a single connection, by CID and
+    /// HCI handle
+    fixed_channel_callbacks: RwLock<HashMap<(ChannelId, u16), DataCallback>>,
+
+    /// Data callbacks of fixed channels for every connection, by CID
+    fixed_channel_handlers: RwLock<HashMap<ChannelId, FixedChannelCallback>>,
+
     /// Next available dynamic CID
     next_cid: Mutex<ChannelId>,
 
@@ -109,11 +127,46 @@ pub struct L2capManager {
     /// Next available signaling identifier
     next_signal_id: Mutex<u8>,
 
+    /// Times a timed out signaling request is sent again before failing
+    max_signaling_retries: Mutex<u8>,
+
     /// Connection type (Classic or LE)
     connection_type: ConnectionType,
 
     /// Event callback for all channels
     global_event_callback: Mutex<Option<ChannelEventCallback>>,
+
+    /// Callbacks invoked when an HCI connection closes
+    connection_closed_callbacks: Mutex<Vec<ConnectionClosedCallback>>,
+
+    /// Callbacks invoked when an HCI connection is registered
+    connection_opened_callbacks: Mutex<Vec<ConnectionOpenedCallback>>,
+
+    /// HCI socket signaling messages are sent on
+    hci_socket: Option<Arc<HciSocket>>,
+
+    /// ACL transport for outgoing packets, if attached
+    acl_transport: RwLock<Option<AclTransport>>,
+
+    /// ACL packets waiting for controller buffers, by HCI handle
+    send_queues: Mutex<HashMap<u16, VecDeque<Vec<u8>>>>,
+
+    /// Partially received L2CAP frames, by HCI handle
+    receive_fragments: Mutex<HashMap<u16, Vec<u8>>>,
+
+    /// Callers waiting for an Echo Response, by signaling identifier
+    echo_waiters: Mutex<HashMap<u8, mpsc::Sender<Vec<u8>>>>,
+
+    /// Enhanced Credit Based Connection Requests waiting for
+    /// `accept_connection`, as local CIDs by HCI handle and identifier
+    pending_enhanced_requests: Mutex<HashMap<(u16, u8), Vec<ChannelId>>>,
+}
+
+/// HCI socket and controller buffer state used to send ACL packets
+struct AclTransport {
+    socket: Arc<HciSocket>,
+    fragmenter: AclFragmenter,
+    flow: Arc<AclFlowController>,
 }
 
 /// Signaling transaction state
@@ -121,17 +174,23 @@ pub struct L2capManager {
 struct SignalingTransaction {
     /// Transaction type
     transaction_type: SignalingTransactionType,
-    /// Timestamp when the transaction was started
+    /// Timestamp when the request was last sent
     timestamp: Instant,
     /// Number of retries attempted
     retries: u8,
+    /// HCI handle of the connection the request was sent on
+    hci_handle: u16,
+    /// Request to send again on timeout
+    original_message: SignalingMessage,
 }
 
 /// Type of signaling transaction
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum SignalingTransactionType {
     /// Connection request
     Connect(PSM, ChannelId), // PSM, local CID
+    /// Enhanced Credit Based Connection request (LE only)
+    EnhancedConnect(PSM, Vec<ChannelId>), // PSM, local CIDs in request order
     /// Disconnection request
     Disconnect(ChannelId, ChannelId), // local CID, remote CID
     /// Configuration request
@@ -141,6 +200,7 @@ enum SignalingTransactionType {
     /// Echo request
     Echo,
     /// Connection parameter update request (LE only)
+    #[allow(dead_code)]
     ConnectionParameterUpdate,
 }
 
@@ -151,12 +211,136 @@ impl L2capManager {
             channels: RwLock::new(HashMap::new()),
             psm_registrations: RwLock::new(HashMap::new()),
             handle_to_cid: RwLock::new(HashMap::new()),
+            handle_to_addr: RwLock::new(HashMap::new()),
+            extended_features: RwLock::new(HashMap::new()),
+            fixed_channel_callbacks: RwLock::new(HashMap::new()),
+            fixed_channel_handlers: RwLock::new(HashMap::new()),
             next_cid: Mutex::new(L2CAP_DYNAMIC_CID_MIN),
             pending_transactions: RwLock::new(HashMap::new()),
             next_signal_id: Mutex::new(1), // Start from 1
+            max_signaling_retries: Mutex::new(L2CAP_DEFAULT_SIGNALING_RETRIES),
             connection_type,
             global_event_callback: Mutex::new(None),
+            connection_closed_callbacks: Mutex::new(Vec::new()),
+            connection_opened_callbacks: Mutex::new(Vec::new()),
+            hci_socket: None,
+            acl_transport: RwLock::new(None),
+            send_queues: Mutex::new(HashMap::new()),
+            receive_fragments: Mutex::new(HashMap::new()),
+            echo_waiters: Mutex::new(HashMap::new()),
+            pending_enhanced_requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a new L2CAP Manager that sends signaling on an HCI socket
+    pub fn with_hci_socket(connection_type: ConnectionType, hci_socket: Arc<HciSocket>) -> Self {
+        Self {
+            hci_socket: Some(hci_socket),
+            ..Self::new(connection_type)
+        }
+    }
+
+    /// Send outgoing packets over an HCI socket
+    ///
+    /// Packets are fragmented to the controller's ACL buffer size and held
+    /// in per-connection queues while the controller has no free buffers.
+    /// LE connections use the LE buffers, BR/EDR connections the BR/EDR
+    /// buffers when the controller reported them. Returns the flow controller
+    /// tracking those buffers.
+    pub fn set_acl_transport(
+        &self,
+        socket: Arc<HciSocket>,
+        info: &LocalControllerInfo,
+    ) -> Arc<AclFlowController> {
+        let buffer = match self.connection_type {
+            ConnectionType::LE => info.le_buffer,
+            ConnectionType::Classic => info.classic_buffer.unwrap_or(info.le_buffer),
+        };
+
+        let flow = Arc::new(AclFlowController::new(&buffer));
+        let mut transport = self.acl_transport.write().unwrap();
+        *transport = Some(AclTransport {
+            socket,
+            fragmenter: AclFragmenter::new(&buffer),
+            flow: flow.clone(),
+        });
+        flow
+    }
+
+    /// Handle an HCI event that affects outgoing data
+    ///
+    /// Completed packets return controller buffers, after which the send
+    /// queues are drained.
+    pub fn handle_hci_event(&self, event: &HciEvent) -> L2capResult<()> {
+        let restored = {
+            let transport = self.acl_transport.read().unwrap();
+            match transport.as_ref() {
+                Some(transport) => transport.flow.handle_event(event),
+                None => false,
+            }
+        };
+
+        if restored {
+            self.flush_send_queues()?;
+        }
+        Ok(())
+    }
+
+    /// Send queued ACL packets while the controller has free buffers
+    pub fn flush_send_queues(&self) -> L2capResult<()> {
+        let transport = self.acl_transport.read().unwrap();
+        let Some(transport) = transport.as_ref() else {
+            return Ok(());
+        };
+
+        let mut queues = self.send_queues.lock().unwrap();
+        for (&handle, queue) in queues.iter_mut() {
+            while !queue.is_empty() && transport.flow.try_acquire(handle) {
+                let packet = queue.pop_front().unwrap();
+                if let Err(e) = transport.socket.write_packet(&packet) {
+                    transport.flow.restore(handle, 1);
+                    queue.push_front(packet);
+                    return Err(e.into());
+                }
+            }
         }
+        queues.retain(|_, queue| !queue.is_empty());
+
+        Ok(())
+    }
+
+    /// Get the address of the peer a dynamic channel is connected to
+    pub fn channel_peer_address(&self, local_cid: ChannelId) -> Option<BdAddr> {
+        self.hci_handle_for_cid(local_cid)
+            .and_then(|hci_handle| self.peer_address(hci_handle))
+    }
+
+    /// Get the MTU data sent on a dynamic channel is limited to
+    ///
+    /// This is the smaller of the local and remote MTU.
+    pub fn channel_mtu(&self, local_cid: ChannelId) -> Option<u16> {
+        let channels = self.channels.read().unwrap();
+        channels.get(&local_cid).map(L2capChannel::effective_mtu)
+    }
+
+    /// Check whether the local device requested a channel
+    ///
+    /// PSM event callbacks are told about channels in both directions, so
+    /// services use this to tell the channels they accepted apart.
+    pub fn is_initiator(&self, local_cid: ChannelId) -> bool {
+        let channels = self.channels.read().unwrap();
+        channels
+            .get(&local_cid)
+            .is_some_and(L2capChannel::is_initiator)
+    }
+
+    /// Find the HCI handle of the connection a channel belongs to
+    fn hci_handle_for_cid(&self, local_cid: ChannelId) -> Option<u16> {
+        let handle_map = self.handle_to_cid.read().unwrap();
+        handle_map
+            .iter()
+            .find(|(_, cids)| cids.contains(&local_cid))
+            .map(|(&handle, _)| handle)
     }
 
     /// Register a PSM for handling incoming connections
@@ -185,11 +369,8 @@ impl L2capManager {
         registrations.insert(
             psm.value(),
             PsmRegistration {
-                psm,
                 data_callback,
                 event_callback,
-                security_level: policy.min_security_level,
-                authorization_required: policy.authorization_required,
                 auto_accept: policy.auto_accept,
             },
         );
@@ -217,6 +398,68 @@ impl L2capManager {
         *global_callback = Some(Arc::new(Mutex::new(callback)));
     }
 
+    /// Register a callback invoked when an HCI connection closes
+    ///
+    /// Callbacks are called from `handle_connection_closed` after the
+    /// connection's channels have been removed, while the peer address is
+    /// still available through `peer_address`.
+    pub fn set_connection_closed_callback(&self, callback: ConnectionClosedCallback) {
+        let mut callbacks = self.connection_closed_callbacks.lock().unwrap();
+        callbacks.push(callback);
+    }
+
+    /// Register a callback invoked when an HCI connection is registered
+    ///
+    /// Callbacks are called from `register_connection` once the peer
+    /// address is available through `peer_address`.
+    pub fn set_connection_opened_callback(&self, callback: ConnectionOpenedCallback) {
+        let mut callbacks = self.connection_opened_callbacks.lock().unwrap();
+        callbacks.push(callback);
+    }
+
+    /// Record the peer address of an HCI connection
+    pub fn register_connection(&self, hci_handle: u16, addr: BdAddr) {
+        {
+            let mut handle_map = self.handle_to_addr.write().unwrap();
+            handle_map.insert(hci_handle, addr);
+        }
+
+        let callbacks = self.connection_opened_callbacks.lock().unwrap();
+        for callback in callbacks.iter() {
+            callback(hci_handle, addr);
+        }
+    }
+
+    /// Record a new HCI connection from its LE Connection Complete event
+    ///
+    /// Same as `register_connection`; channels to `addr` can then be opened
+    /// and addressed without passing the handle around.
+    pub fn connection_established(&self, hci_handle: u16, addr: BdAddr) {
+        self.register_connection(hci_handle, addr);
+    }
+
+    /// Get the peer address of an HCI connection
+    pub fn peer_address(&self, hci_handle: u16) -> Option<BdAddr> {
+        let handle_map = self.handle_to_addr.read().unwrap();
+        handle_map.get(&hci_handle).copied()
+    }
+
+    /// Get the HCI handle of the connection to a peer
+    pub fn connection_handle(&self, addr: &BdAddr) -> Option<u16> {
+        let handle_map = self.handle_to_addr.read().unwrap();
+        handle_map
+            .iter()
+            .find(|(_, peer)| *peer == addr)
+            .map(|(&handle, _)| handle)
+    }
+
+    /// Get the HCI handle of the connection to a peer
+    ///
+    /// Same as `connection_handle`.
+    pub fn get_hci_handle_for_addr(&self, addr: BdAddr) -> Option<u16> {
+        self.connection_handle(&addr)
+    }
+
     /// Allocate a new dynamic CID
     fn allocate_cid(&self) -> L2capResult<ChannelId> {
         let mut next_cid = self.next_cid.lock().unwrap();
@@ -231,7 +474,7 @@ impl L2capManager {
 
                 // Increment for next time
                 *next_cid += 1;
-                if *next_cid >= L2CAP_DYNAMIC_CID_MAX || *next_cid < L2CAP_DYNAMIC_CID_MIN {
+                if *next_cid == L2CAP_DYNAMIC_CID_MAX || *next_cid < L2CAP_DYNAMIC_CID_MIN {
                     *next_cid = L2CAP_DYNAMIC_CID_MIN;
                 }
 
@@ -239,7 +482,7 @@ impl L2capManager {
             }
 
             *next_cid += 1;
-            if *next_cid >= L2CAP_DYNAMIC_CID_MAX || *next_cid < L2CAP_DYNAMIC_CID_MIN {
+            if *next_cid == L2CAP_DYNAMIC_CID_MAX || *next_cid < L2CAP_DYNAMIC_CID_MIN {
                 *next_cid = L2CAP_DYNAMIC_CID_MIN;
             }
 
@@ -263,6 +506,40 @@ impl L2capManager {
         id
     }
 
+    /// Track a signaling request until its response arrives
+    ///
+    /// The request is kept so `process_timeouts` can send it again.
+    fn track_transaction(
+        &self,
+        hci_handle: u16,
+        transaction_type: SignalingTransactionType,
+        original_message: SignalingMessage,
+    ) {
+        let mut transactions = self.pending_transactions.write().unwrap();
+        transactions.insert(
+            original_message.identifier(),
+            SignalingTransaction {
+                transaction_type,
+                timestamp: Instant::now(),
+                retries: 0,
+                hci_handle,
+                original_message,
+            },
+        );
+    }
+
+    /// Connect to a peer for a specific PSM
+    ///
+    /// The HCI handle is looked up from the connection recorded with
+    /// `register_connection` when the controller reported it, so callers
+    /// only need the peer address.
+    pub fn connect_to_peer(&self, psm: PSM, addr: &BdAddr) -> L2capResult<ChannelId> {
+        let hci_handle = self
+            .connection_handle(addr)
+            .ok_or(L2capError::NotConnected)?;
+        self.connect(psm, hci_handle)
+    }
+
     /// Connect to a remote device for a specific PSM
     pub fn connect(&self, psm: PSM, hci_handle: u16) -> L2capResult<ChannelId> {
         if !psm.is_valid() {
@@ -273,11 +550,12 @@ impl L2capManager {
         let local_cid = self.allocate_cid()?;
 
         // Create a new channel
-        let channel = if self.connection_type == ConnectionType::LE {
+        let mut channel = if self.connection_type == ConnectionType::LE {
             L2capChannel::new_le_credit_based(local_cid, psm, LeCreditBasedConfig::default())
         } else {
             L2capChannel::new_dynamic(local_cid, psm, self.connection_type)
         };
+        channel.set_initiator(true);
 
         // Add the channel to our map
         {
@@ -288,28 +566,12 @@ impl L2capManager {
         // Associate the channel with the HCI handle
         {
             let mut handle_map = self.handle_to_cid.write().unwrap();
-            handle_map
-                .entry(hci_handle)
-                .or_insert_with(Vec::new)
-                .push(local_cid);
+            handle_map.entry(hci_handle).or_default().push(local_cid);
         }
 
         // Create a connection request
         let signal_id = self.allocate_signal_id();
 
-        // Store the transaction for tracking
-        {
-            let mut transactions = self.pending_transactions.write().unwrap();
-            transactions.insert(
-                signal_id,
-                SignalingTransaction {
-                    transaction_type: SignalingTransactionType::Connect(psm, local_cid),
-                    timestamp: Instant::now(),
-                    retries: 0,
-                },
-            );
-        }
-
         // Create the signaling message
         let message = if self.connection_type == ConnectionType::LE {
             SignalingMessage::LeCreditBasedConnectionRequest {
@@ -328,6 +590,13 @@ impl L2capManager {
             }
         };
 
+        // Store the transaction for tracking
+        self.track_transaction(
+            hci_handle,
+            SignalingTransactionType::Connect(psm, local_cid),
+            message.clone(),
+        );
+
         // Update channel state
         {
             let mut channels = self.channels.write().unwrap();
@@ -336,15 +605,275 @@ impl L2capManager {
             }
         }
 
-        // Send the connection request (would be sent through HCI in a real implementation)
-        // This would typically involve converting to an L2CAP packet and sending via HCI ACL
+        // Send the connection request
+        self.send_signaling_message(hci_handle, message)?;
 
         Ok(local_cid)
     }
 
+    /// Connect several LE Credit-based channels to the same PSM at once
+    ///
+    /// Sends one Enhanced Credit Based Connection Request for
+    /// `num_channels` channels (1 to 5), e.g. for EATT. All channels use
+    /// `LeCreditBasedConfig::enhanced()`. The local CIDs are returned in
+    /// request order; each channel opens or is removed when the response
+    /// arrives. Their events go to the global event callback, not to a
+    /// callback registered for the same PSM.
+    pub fn connect_enhanced(
+        &self,
+        psm: PSM,
+        hci_handle: u16,
+        num_channels: u8,
+    ) -> L2capResult<Vec<ChannelId>> {
+        if self.connection_type != ConnectionType::LE {
+            return Err(L2capError::NotSupported);
+        }
+
+        if !psm.is_valid() {
+            return Err(L2capError::InvalidParameter("Invalid PSM".into()));
+        }
+
+        let num_channels = num_channels as usize;
+        if num_channels == 0 || num_channels > L2CAP_CREDIT_BASED_MAX_CHANNELS {
+            return Err(L2capError::InvalidParameter(format!(
+                "Cannot request {} channels, 1 to {} are allowed",
+                num_channels, L2CAP_CREDIT_BASED_MAX_CHANNELS
+            )));
+        }
+
+        let config = LeCreditBasedConfig::enhanced();
+
+        // Allocate a local CID and channel for each requested channel
+        let mut local_cids = Vec::with_capacity(num_channels);
+        for _ in 0..num_channels {
+            let local_cid = match self.allocate_cid() {
+                Ok(cid) => cid,
+                Err(e) => {
+                    let mut channels = self.channels.write().unwrap();
+                    for cid in &local_cids {
+                        channels.remove(cid);
+                    }
+                    return Err(e);
+                }
+            };
+
+            let mut channel = L2capChannel::new_le_credit_based(local_cid, psm, config);
+            channel.set_state(L2capChannelState::WaitConnectRsp);
+            channel.set_initiator(true);
+
+            let mut channels = self.channels.write().unwrap();
+            channels.insert(local_cid, channel);
+            local_cids.push(local_cid);
+        }
+
+        // Associate the channels with the HCI handle
+        {
+            let mut handle_map = self.handle_to_cid.write().unwrap();
+            handle_map
+                .entry(hci_handle)
+                .or_default()
+                .extend_from_slice(&local_cids);
+        }
+
+        let signal_id = self.allocate_signal_id();
+        let message = SignalingMessage::EnhancedCreditBasedConnectionRequest {
+            identifier: signal_id,
+            spsm: psm.value(),
+            mtu: config.mtu,
+            mps: config.mps,
+            initial_credits: config.initial_credits,
+            source_cids: local_cids.clone(),
+        };
+
+        // Store the transaction for tracking
+        self.track_transaction(
+            hci_handle,
+            SignalingTransactionType::EnhancedConnect(psm, local_cids.clone()),
+            message.clone(),
+        );
+
+        self.send_signaling_message(hci_handle, message)?;
+
+        Ok(local_cids)
+    }
+
+    /// Get (or open) a fixed channel such as ATT or SMP for an HCI connection
+    ///
+    /// Fixed channels need no signaling; the channel is created in the open
+    /// state on first use and associated with `hci_handle`.
+    pub fn get_fixed_channel(&self, cid: ChannelId, hci_handle: u16) -> L2capResult<ChannelId> {
+        let channel_type = Self::fixed_channel_type(cid)?;
+
+        {
+            let mut channels = self.channels.write().unwrap();
+            channels.entry(cid).or_insert_with(|| {
+                L2capChannel::new_fixed(cid, channel_type, self.connection_type)
+            });
+        }
+
+        {
+            let mut handle_map = self.handle_to_cid.write().unwrap();
+            let cids = handle_map.entry(hci_handle).or_default();
+            if !cids.contains(&cid) {
+                cids.push(cid);
+            }
+        }
+
+        Ok(cid)
+    }
+
+    /// Get the channel type of a fixed channel CID
+    fn fixed_channel_type(cid: ChannelId) -> L2capResult<L2capChannelType> {
+        match cid {
+            L2CAP_SIGNALING_CID | L2CAP_LE_SIGNALING_CID => Ok(L2capChannelType::Signaling),
+            L2CAP_CONNECTIONLESS_CID => Ok(L2capChannelType::Connectionless),
+            L2CAP_AMP_MANAGER_CID => Ok(L2capChannelType::AmpManager),
+            L2CAP_ATTRIBUTE_PROTOCOL_CID => Ok(L2capChannelType::AttributeProtocol),
+            L2CAP_SECURITY_MANAGER_PROTOCOL_CID => Ok(L2capChannelType::SecurityManager),
+            _ => Err(L2capError::InvalidParameter(format!(
+                "CID 0x{:04X} is not a fixed channel",
+                cid
+            ))),
+        }
+    }
+
+    /// Set the data callback for an existing channel
+    pub fn set_channel_data_callback<F>(&self, local_cid: ChannelId, callback: F) -> L2capResult<()>
+    where
+        F: FnMut(&[u8]) -> L2capResult<()> + Send + 'static,
+    {
+        let mut channels = self.channels.write().unwrap();
+        let channel = channels
+            .get_mut(&local_cid)
+            .ok_or(L2capError::ChannelNotFound)?;
+        channel.set_data_callback(callback);
+        Ok(())
+    }
+
+    /// Remove the data callback from an existing channel
+    pub fn clear_channel_data_callback(&self, local_cid: ChannelId) -> L2capResult<()> {
+        let mut channels = self.channels.write().unwrap();
+        let channel = channels
+            .get_mut(&local_cid)
+            .ok_or(L2capError::ChannelNotFound)?;
+        channel.clear_data_callback();
+        Ok(())
+    }
+
+    /// Buffer the SDUs received on a channel until `process_received`
+    ///
+    /// Received data is then only stored while packets are handled, so the
+    /// data callback runs on the thread that calls `process_received`.
+    /// At most `max_sdus` SDUs are buffered; further SDUs are refused with
+    /// `QueueFull`. `None` turns buffering off again.
+    pub fn set_channel_receive_buffer(
+        &self,
+        local_cid: ChannelId,
+        max_sdus: Option<usize>,
+    ) -> L2capResult<()> {
+        let mut channels = self.channels.write().unwrap();
+        let channel = channels
+            .get_mut(&local_cid)
+            .ok_or(L2capError::ChannelNotFound)?;
+
+        channel.set_receive_buffering(max_sdus.is_some());
+        if let Some(max_sdus) = max_sdus {
+            channel.set_max_received_sdus(max_sdus);
+        }
+        Ok(())
+    }
+
+    /// Deliver the SDUs buffered on a channel to its data callback
+    ///
+    /// The callback runs without the channel lock held, so it can send on
+    /// the channel. Returns the number of SDUs delivered; SDUs stay buffered
+    /// while the channel has no callback.
+    pub fn process_received(&self, local_cid: ChannelId) -> L2capResult<usize> {
+        let mut delivered = 0;
+
+        loop {
+            let (sdu, callback) = {
+                let mut channels = self.channels.write().unwrap();
+                let channel = channels
+                    .get_mut(&local_cid)
+                    .ok_or(L2capError::ChannelNotFound)?;
+
+                let Some(callback) = channel.data_callback() else {
+                    return Ok(delivered);
+                };
+                let Some(sdu) = channel.next_received_sdu() else {
+                    return Ok(delivered);
+                };
+                (sdu, callback)
+            };
+
+            let mut callback = callback.lock().unwrap();
+            (*callback)(&sdu)?;
+            delivered += 1;
+        }
+    }
+
+    /// Set the data callback of a fixed channel for one connection
+    ///
+    /// Fixed channels share their CID between connections, so this lets
+    /// several peers be served on the same channel. Packets from `hci_handle`
+    /// go to this callback instead of the channel's data callback.
+    pub fn set_fixed_channel_callback<F>(
+        &self,
+        cid: ChannelId,
+        hci_handle: u16,
+        callback: F,
+    ) -> L2capResult<()>
+    where
+        F: FnMut(&[u8]) -> L2capResult<()> + Send + 'static,
+    {
+        if !self.channels.read().unwrap().contains_key(&cid) {
+            return Err(L2capError::ChannelNotFound);
+        }
+
+        let mut callbacks = self.fixed_channel_callbacks.write().unwrap();
+        callbacks.insert((cid, hci_handle), Arc::new(Mutex::new(callback)));
+        Ok(())
+    }
+
+    /// Set the data callback of a fixed channel for every connection
+    ///
+    /// The callback gets the address of the peer each packet came from, so
+    /// packets are only delivered for connections passed to
+    /// `register_connection`. Callbacks set for a single connection with
+    /// `set_fixed_channel_callback` take precedence, except on the ATT
+    /// channel: there requests, commands and confirmations still go to this
+    /// callback, and only responses, notifications and indications go to the
+    /// connection's callback.
+    pub fn register_fixed_channel_callback<F>(&self, cid: ChannelId, callback: F) -> L2capResult<()>
+    where
+        F: FnMut(BdAddr, &[u8]) -> L2capResult<()> + Send + 'static,
+    {
+        Self::fixed_channel_type(cid)?;
+
+        let mut handlers = self.fixed_channel_handlers.write().unwrap();
+        handlers.insert(cid, Arc::new(Mutex::new(callback)));
+        Ok(())
+    }
+
+    /// Remove the data callback of a fixed channel for every connection
+    pub fn unregister_fixed_channel_callback(&self, cid: ChannelId) -> L2capResult<()> {
+        let mut handlers = self.fixed_channel_handlers.write().unwrap();
+        handlers
+            .remove(&cid)
+            .map(|_| ())
+            .ok_or(L2capError::ChannelNotFound)
+    }
+
+    /// Remove the data callback of a fixed channel for one connection
+    pub fn clear_fixed_channel_callback(&self, cid: ChannelId, hci_handle: u16) {
+        let mut callbacks = self.fixed_channel_callbacks.write().unwrap();
+        callbacks.remove(&(cid, hci_handle));
+    }
+
     /// Disconnect a channel
     pub fn disconnect(&self, local_cid: ChannelId) -> L2capResult<()> {
-        let (remote_cid, handle) = {
+        let remote_cid = {
             let channels = self.channels.read().unwrap();
 
             let channel = channels
@@ -355,8 +884,11 @@ impl L2capManager {
                 return Err(L2capError::InvalidState);
             }
 
-            (channel.remote_cid(), 0) // We would get the handle from somewhere
+            channel.remote_cid()
         };
+        let hci_handle = self
+            .hci_handle_for_cid(local_cid)
+            .ok_or(L2capError::NotConnected)?;
 
         if remote_cid == 0 {
             return Err(L2capError::NotConnected);
@@ -365,19 +897,6 @@ impl L2capManager {
         // Create a disconnection request
         let signal_id = self.allocate_signal_id();
 
-        // Store the transaction
-        {
-            let mut transactions = self.pending_transactions.write().unwrap();
-            transactions.insert(
-                signal_id,
-                SignalingTransaction {
-                    transaction_type: SignalingTransactionType::Disconnect(local_cid, remote_cid),
-                    timestamp: Instant::now(),
-                    retries: 0,
-                },
-            );
-        }
-
         // Create the signaling message
         let message = SignalingMessage::DisconnectionRequest {
             identifier: signal_id,
@@ -385,6 +904,13 @@ impl L2capManager {
             source_cid: local_cid,
         };
 
+        // Store the transaction
+        self.track_transaction(
+            hci_handle,
+            SignalingTransactionType::Disconnect(local_cid, remote_cid),
+            message.clone(),
+        );
+
         // Update channel state
         {
             let mut channels = self.channels.write().unwrap();
@@ -393,13 +919,25 @@ impl L2capManager {
             }
         }
 
-        // Send the disconnection request (would be sent via HCI)
+        // Send the disconnection request
+        self.send_signaling_message(hci_handle, message)?;
 
         Ok(())
     }
 
     /// Configure a channel with specific options
-    pub fn configure(&self, local_cid: ChannelId, options: ConfigOptions) -> L2capResult<()> {
+    ///
+    /// If the channel's flow control mode is not Basic and `options` has no
+    /// Retransmission and Flow Control option, one is added for that mode.
+    ///
+    /// Only Classic channels are configured. LE Credit-based channels
+    /// negotiate their MTU and MPS in the connection request, so this fails
+    /// with `L2capError::NotSupported` on LE.
+    pub fn configure(&self, local_cid: ChannelId, mut options: ConfigOptions) -> L2capResult<()> {
+        if self.connection_type == ConnectionType::LE {
+            return Err(L2capError::NotSupported);
+        }
+
         let remote_cid = {
             let channels = self.channels.read().unwrap();
 
@@ -407,6 +945,12 @@ impl L2capManager {
                 .get(&local_cid)
                 .ok_or(L2capError::ChannelNotFound)?;
 
+            if channel.connection_type() != ConnectionType::Classic
+                || channel.channel_type() == L2capChannelType::LeCreditBased
+            {
+                return Err(L2capError::NotSupported);
+            }
+
             if channel.state() != L2capChannelState::WaitConfig
                 && channel.state() != L2capChannelState::WaitConfigReq
                 && channel.state() != L2capChannelState::Open
@@ -414,29 +958,23 @@ impl L2capManager {
                 return Err(L2capError::InvalidState);
             }
 
+            if options.retransmission.is_none() {
+                options.retransmission = channel.flow_control_mode().to_option();
+            }
+
             channel.remote_cid()
         };
 
         if remote_cid == 0 {
             return Err(L2capError::NotConnected);
         }
+        let hci_handle = self
+            .hci_handle_for_cid(local_cid)
+            .ok_or(L2capError::NotConnected)?;
 
         // Create a configuration request
         let signal_id = self.allocate_signal_id();
 
-        // Store the transaction
-        {
-            let mut transactions = self.pending_transactions.write().unwrap();
-            transactions.insert(
-                signal_id,
-                SignalingTransaction {
-                    transaction_type: SignalingTransactionType::Configure(remote_cid),
-                    timestamp: Instant::now(),
-                    retries: 0,
-                },
-            );
-        }
-
         // Create the signaling message
         let message = SignalingMessage::ConfigureRequest {
             identifier: signal_id,
@@ -445,42 +983,146 @@ impl L2capManager {
             options,
         };
 
-        // Send the configuration request (would be sent via HCI)
+        // Store the transaction
+        self.track_transaction(
+            hci_handle,
+            SignalingTransactionType::Configure(remote_cid),
+            message.clone(),
+        );
+
+        // Send the configuration request
+        self.send_signaling_message(hci_handle, message)?;
 
         Ok(())
     }
 
     /// Send data on a channel
+    ///
+    /// Fails with `L2capError::MtuExceeded` if `data` is larger than the
+    /// channel's remote MTU; data is never truncated. On LE Credit-based
+    /// channels without credits the data is queued until the peer grants
+    /// more, failing with `L2capError::QueueFull` once the queue is full.
     pub fn send_data(&self, local_cid: ChannelId, data: &[u8]) -> L2capResult<()> {
-        let packet = {
-            let channels = self.channels.read().unwrap();
+        let Some(hci_handle) = self.hci_handle_for_cid(local_cid) else {
+            return Err(L2capError::NotConnected);
+        };
+
+        self.send_data_on(local_cid, hci_handle, data)
+    }
+
+    /// Send data on a channel of a specific connection
+    ///
+    /// Needed for fixed channels, whose CID is used by every connection.
+    pub fn send_data_on(
+        &self,
+        local_cid: ChannelId,
+        hci_handle: u16,
+        data: &[u8],
+    ) -> L2capResult<()> {
+        let packets = {
+            let mut channels = self.channels.write().unwrap();
 
             let channel = channels
-                .get(&local_cid)
+                .get_mut(&local_cid)
                 .ok_or(L2capError::ChannelNotFound)?;
 
             if channel.state() != L2capChannelState::Open {
                 return Err(L2capError::InvalidState);
             }
 
-            channel.create_data_packet(data)?
+            // SDUs on LE Credit-based channels are queued and sent as
+            // K-frames while the peer has credits
+            if channel.channel_type() == L2capChannelType::LeCreditBased {
+                channel.queue_sdu(data)?;
+                channel.take_sendable_frames()?
+            } else {
+                vec![channel.create_data_packet(data)?]
+            }
         };
 
-        // Send the packet (would be sent via HCI)
-        // The actual sending would depend on the underlying transport
-
+        for packet in &packets {
+            self.send_frame(hci_handle, packet)?;
+        }
         Ok(())
     }
 
-    /// Handle a received L2CAP packet
-    pub fn handle_packet(&self, packet: L2capPacket, hci_handle: u16) -> L2capResult<()> {
-        match packet.header.channel_id {
-            L2CAP_SIGNALING_CID => self.handle_signaling_packet(packet, hci_handle, false),
-            L2CAP_LE_SIGNALING_CID => self.handle_signaling_packet(packet, hci_handle, true),
-            _ => {
-                // Data packet for a specific channel
-                self.handle_data_packet(packet, hci_handle)
-            }
+    /// Send an L2CAP frame on a connection
+    ///
+    /// With an ACL transport the frame is fragmented and queued for the
+    /// controller's buffers. Otherwise it is written to the HCI socket as a
+    /// single ACL packet. Fails with `L2capError::NotConnected` without
+    /// either.
+    fn send_frame(&self, hci_handle: u16, packet: &L2capPacket) -> L2capResult<()> {
+        {
+            let transport = self.acl_transport.read().unwrap();
+            let Some(transport) = transport.as_ref() else {
+                let Some(socket) = &self.hci_socket else {
+                    return Err(L2capError::NotConnected);
+                };
+
+                socket.send_acl(
+                    hci_handle,
+                    ACL_PB_FIRST_NON_FLUSHABLE,
+                    ACL_BC_POINT_TO_POINT,
+                    &packet.to_bytes(),
+                )?;
+                return Ok(());
+            };
+
+            let fragments = transport
+                .fragmenter
+                .fragment(hci_handle, &packet.to_bytes())?;
+            let mut queues = self.send_queues.lock().unwrap();
+            queues.entry(hci_handle).or_default().extend(fragments);
+        }
+
+        self.flush_send_queues()
+    }
+
+    /// Handle a received HCI ACL data packet
+    ///
+    /// Fragments are reassembled into L2CAP frames, which are passed to
+    /// `handle_packet` once complete.
+    pub fn handle_acl_data(&self, acl: &AclData) -> L2capResult<()> {
+        let frame = {
+            let mut fragments = self.receive_fragments.lock().unwrap();
+            let buffer = fragments.entry(acl.handle).or_default();
+            if acl.pb_flag == ACL_PB_CONTINUING {
+                if buffer.is_empty() {
+                    return Err(L2capError::ProtocolError(
+                        "Continuing fragment without a start".into(),
+                    ));
+                }
+            } else {
+                // A start fragment discards anything left unfinished
+                buffer.clear();
+            }
+            buffer.extend_from_slice(&acl.data);
+
+            if buffer.len() < L2CAP_BASIC_HEADER_SIZE {
+                return Ok(());
+            }
+            let length = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+            if buffer.len() < L2CAP_BASIC_HEADER_SIZE + length {
+                return Ok(());
+            }
+            fragments.remove(&acl.handle).unwrap_or_default()
+        };
+
+        let packet = L2capPacket::parse(&frame)
+            .ok_or_else(|| L2capError::ProtocolError("Malformed L2CAP frame".into()))?;
+        self.handle_packet(packet, acl.handle)
+    }
+
+    /// Handle a received L2CAP packet
+    pub fn handle_packet(&self, packet: L2capPacket, hci_handle: u16) -> L2capResult<()> {
+        match packet.header.channel_id {
+            L2CAP_SIGNALING_CID => self.handle_signaling_packet(packet, hci_handle, false),
+            L2CAP_LE_SIGNALING_CID => self.handle_signaling_packet(packet, hci_handle, true),
+            _ => {
+                // Data packet for a specific channel
+                self.handle_data_packet(packet, hci_handle)
+            }
         }
     }
 
@@ -589,6 +1231,54 @@ impl L2capManager {
                 cid,
                 credits,
             } => self.handle_le_flow_control_credit(identifier, cid, credits),
+            SignalingMessage::EnhancedCreditBasedConnectionRequest {
+                identifier,
+                spsm,
+                mtu,
+                mps,
+                initial_credits,
+                source_cids,
+            } => self.handle_enhanced_credit_based_connection_request(
+                identifier,
+                spsm,
+                LeCreditBasedConfig {
+                    mtu,
+                    mps,
+                    initial_credits,
+                },
+                &source_cids,
+                hci_handle,
+            ),
+            SignalingMessage::EnhancedCreditBasedConnectionResponse {
+                identifier,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+                destination_cids,
+            } => self.handle_enhanced_credit_based_connection_response(
+                identifier,
+                LeCreditBasedConfig {
+                    mtu,
+                    mps,
+                    initial_credits,
+                },
+                result,
+                &destination_cids,
+            ),
+            SignalingMessage::InformationResponse {
+                identifier,
+                info_type,
+                result,
+                data,
+            } => self.handle_information_response(identifier, info_type, result, &data, hci_handle),
+            SignalingMessage::EchoRequest { identifier, data } => self.send_signaling_message(
+                hci_handle,
+                SignalingMessage::EchoResponse { identifier, data },
+            ),
+            SignalingMessage::EchoResponse { identifier, data } => {
+                self.handle_echo_response(identifier, data)
+            }
             // Handle other signaling messages
             _ => {
                 // For now, reject unhandled messages
@@ -604,34 +1294,82 @@ impl L2capManager {
 
     /// Handle a received data packet
     fn handle_data_packet(&self, packet: L2capPacket, hci_handle: u16) -> L2capResult<()> {
-        // Find the channel for this packet
-        let local_cid = {
-            let channels = self.channels.read().unwrap();
+        let fixed_callback = {
+            let callbacks = self.fixed_channel_callbacks.read().unwrap();
+            callbacks
+                .get(&(packet.header.channel_id, hci_handle))
+                .cloned()
+        };
+        let handler = {
+            let handlers = self.fixed_channel_handlers.read().unwrap();
+            handlers.get(&packet.header.channel_id).cloned()
+        };
 
-            // Look for a channel with matching remote CID
-            let mut found_cid = None;
-            for (local_cid, channel) in channels.iter() {
-                if channel.remote_cid() == packet.header.channel_id {
-                    found_cid = Some(*local_cid);
-                    break;
-                }
-            }
+        // The ATT channel carries both roles: PDUs for the local server go to
+        // the handler even when a client is attached to the connection
+        let to_server = packet.header.channel_id == L2CAP_ATTRIBUTE_PROTOCOL_CID
+            && handler.is_some()
+            && is_att_server_pdu(&packet.payload);
 
-            found_cid.ok_or(L2capError::ChannelNotFound)?
-        };
+        if let Some(callback) = fixed_callback.filter(|_| !to_server) {
+            let mut callback = callback.lock().unwrap();
+            return (*callback)(&packet.payload);
+        }
 
-        // Process the data packet
-        {
+        if let Some(handler) = handler {
+            let addr = self
+                .peer_address(hci_handle)
+                .ok_or(L2capError::NotConnected)?;
+            let mut handler = handler.lock().unwrap();
+            return (*handler)(addr, &packet.payload);
+        }
+
+        // Received frames carry the destination CID, which is our local CID
+        let local_cid = packet.header.channel_id;
+        let (sdu, callback, returned_credits) = {
             let mut channels = self.channels.write().unwrap();
-            if let Some(channel) = channels.get_mut(&local_cid) {
-                if channel.state() != L2capChannelState::Open {
-                    return Err(L2capError::InvalidState);
-                }
+            let channel = channels
+                .get_mut(&local_cid)
+                .ok_or(L2capError::ChannelNotFound)?;
 
+            if channel.state() != L2capChannelState::Open {
+                return Err(L2capError::InvalidState);
+            }
+
+            if channel.uses_retransmission() || channel.receive_buffering() {
                 channel.handle_data(&packet.payload)?;
+                (None, None, channel.take_returned_credits())
             } else {
-                return Err(L2capError::ChannelNotFound);
+                let sdu = if channel.channel_type() == L2capChannelType::LeCreditBased {
+                    channel.reassemble_k_frame(&packet.payload)?
+                } else {
+                    Some(packet.payload)
+                };
+                channel.update_activity();
+                (
+                    sdu,
+                    channel.data_callback(),
+                    channel.take_returned_credits(),
+                )
             }
+        };
+
+        if let Some(credits) = returned_credits {
+            self.send_signaling_message(
+                hci_handle,
+                SignalingMessage::LeFlowControlCredit {
+                    identifier: self.allocate_signal_id(),
+                    cid: local_cid,
+                    credits,
+                },
+            )?;
+        }
+
+        // Invoke the callback without holding the channel lock so that it
+        // can send data back on the same channel
+        if let (Some(sdu), Some(callback)) = (sdu, callback) {
+            let mut callback = callback.lock().unwrap();
+            (*callback)(&sdu)?;
         }
 
         Ok(())
@@ -664,6 +1402,7 @@ impl L2capManager {
 
         // Set data callback if registered
         if let Some(ref callback) = registration.data_callback {
+            let callback = callback.clone();
             channel.set_data_callback(move |data| {
                 let mut callback = callback.lock().unwrap();
                 (*callback)(data)
@@ -679,10 +1418,7 @@ impl L2capManager {
         // Associate the channel with the HCI handle
         {
             let mut handle_map = self.handle_to_cid.write().unwrap();
-            handle_map
-                .entry(hci_handle)
-                .or_insert_with(Vec::new)
-                .push(local_cid);
+            handle_map.entry(hci_handle).or_default().push(local_cid);
         }
 
         // Notify event handlers
@@ -711,7 +1447,8 @@ impl L2capManager {
                 }
             }
 
-            // Send the response (would be sent via HCI)
+            // Send the response
+            self.send_signaling_message(hci_handle, response)?;
 
             // Notify event handlers of connection
             self.notify_event_handlers(ChannelEvent::Connected {
@@ -727,12 +1464,24 @@ impl L2capManager {
     }
 
     /// Accept a pending connection request
+    ///
+    /// The channels of an Enhanced Credit Based Connection Request share
+    /// one response, so accepting any of them accepts the whole request.
     pub fn accept_connection(
         &self,
         identifier: u8,
         local_cid: ChannelId,
         hci_handle: u16,
     ) -> L2capResult<()> {
+        if let Some(local_cids) = self.take_enhanced_request(identifier, local_cid, hci_handle) {
+            let psm = {
+                let channels = self.channels.read().unwrap();
+                channels.get(&local_cid).and_then(|channel| channel.psm())
+            };
+            let psm = psm.ok_or(L2capError::ChannelNotFound)?;
+            return self.accept_enhanced_channels(identifier, psm, &local_cids, hci_handle);
+        }
+
         let (source_cid, psm) = {
             let mut channels = self.channels.write().unwrap();
 
@@ -762,7 +1511,8 @@ impl L2capManager {
             status: 0,
         };
 
-        // Send the response (would be sent via HCI)
+        // Send the response
+        self.send_signaling_message(hci_handle, response)?;
 
         // Notify event handlers
         if let Some(psm) = psm {
@@ -776,6 +1526,9 @@ impl L2capManager {
     }
 
     /// Reject a pending connection request
+    ///
+    /// Like `accept_connection`, this refuses every channel of an Enhanced
+    /// Credit Based Connection Request at once.
     pub fn reject_connection(
         &self,
         identifier: u8,
@@ -784,6 +1537,34 @@ impl L2capManager {
         reason: u16,
         hci_handle: u16,
     ) -> L2capResult<()> {
+        if let Some(local_cids) = self.take_enhanced_request(identifier, local_cid, hci_handle) {
+            {
+                let mut channels = self.channels.write().unwrap();
+                for cid in &local_cids {
+                    channels.remove(cid);
+                }
+            }
+            {
+                let mut handle_map = self.handle_to_cid.write().unwrap();
+                if let Some(cids) = handle_map.get_mut(&hci_handle) {
+                    cids.retain(|cid| !local_cids.contains(cid));
+                }
+            }
+
+            let local_config = LeCreditBasedConfig::enhanced();
+            return self.send_signaling_message(
+                hci_handle,
+                SignalingMessage::EnhancedCreditBasedConnectionResponse {
+                    identifier,
+                    mtu: local_config.mtu,
+                    mps: local_config.mps,
+                    initial_credits: 0,
+                    result: reason,
+                    destination_cids: vec![0; local_cids.len()],
+                },
+            );
+        }
+
         // Send connection response with failure
         let response = SignalingMessage::ConnectionResponse {
             identifier,
@@ -793,7 +1574,8 @@ impl L2capManager {
             status: 0,
         };
 
-        // Send the response (would be sent via HCI)
+        // Send the response
+        self.send_signaling_message(hci_handle, response)?;
 
         // Remove the channel
         {
@@ -804,6 +1586,22 @@ impl L2capManager {
         Ok(())
     }
 
+    /// Remove the pending Enhanced Credit Based Connection Request that
+    /// `local_cid` belongs to, returning the local CIDs of all its channels
+    fn take_enhanced_request(
+        &self,
+        identifier: u8,
+        local_cid: ChannelId,
+        hci_handle: u16,
+    ) -> Option<Vec<ChannelId>> {
+        let mut pending = self.pending_enhanced_requests.lock().unwrap();
+        let key = (hci_handle, identifier);
+        if !pending.get(&key)?.contains(&local_cid) {
+            return None;
+        }
+        pending.remove(&key)
+    }
+
     /// Handle a connection response
     fn handle_connection_response(
         &self,
@@ -903,7 +1701,7 @@ impl L2capManager {
                 result: response_result.to_result_code(),
                 options: response_options,
             };
-            self.send_signaling_message(hci_handle, channel.local_cid(), response)?;
+            self.send_signaling_message(hci_handle, response)?;
 
             if response_result == ConfigureResult::Success && flags == 0 {
                 if channel.state() == L2capChannelState::WaitConfig {
@@ -936,7 +1734,7 @@ impl L2capManager {
 
         if let Some(transaction) = transaction {
             match transaction.transaction_type {
-                SignalingTransactionType::Configure(remote_cid) => {
+                SignalingTransactionType::Configure(_remote_cid) => {
                     let local_cid = source_cid;
                     if result == L2CAP_RESULT_SUCCESS {
                         {
@@ -1000,7 +1798,7 @@ impl L2capManager {
     ) -> L2capResult<()> {
         // Find the channel
         let (local_cid, psm) = {
-            let mut channels = self.channels.write().unwrap();
+            let channels = self.channels.read().unwrap();
 
             // Look for a channel with matching remote CID
             let mut found_channel = None;
@@ -1021,7 +1819,8 @@ impl L2capManager {
             source_cid,
         };
 
-        // Send the response (would be sent via HCI)
+        // Send the response
+        self.send_signaling_message(hci_handle, response)?;
 
         // Remove the channel
         {
@@ -1112,7 +1911,8 @@ impl L2capManager {
                 result: L2CAP_CONN_PARAM_UPDATE_REJECTED,
             };
 
-            // Send the response (would be sent via HCI)
+            // Send the response
+            self.send_signaling_message(hci_handle, response)?;
 
             return Ok(());
         }
@@ -1130,12 +1930,73 @@ impl L2capManager {
             result: L2CAP_CONN_PARAM_UPDATE_ACCEPTED,
         };
 
-        // Send the response (would be sent via HCI)
+        // Send the response
+        self.send_signaling_message(hci_handle, response)?;
 
         Ok(())
     }
 
     /// Handle a connection parameter update response (LE only)
+    fn handle_information_response(
+        &self,
+        identifier: u8,
+        info_type: u16,
+        result: u16,
+        data: &[u8],
+        hci_handle: u16,
+    ) -> L2capResult<()> {
+        {
+            let mut transactions = self.pending_transactions.write().unwrap();
+            match transactions.get(&identifier) {
+                Some(SignalingTransaction {
+                    transaction_type: SignalingTransactionType::Information(requested),
+                    ..
+                }) if *requested == info_type => {
+                    transactions.remove(&identifier);
+                }
+                _ => {
+                    return Err(L2capError::ProtocolError(
+                        "Unexpected information response".into(),
+                    ))
+                }
+            }
+        }
+
+        if info_type == L2CAP_EXTENDED_FEATURES && result == L2CAP_INFO_SUCCESS {
+            let features = ExtendedFeatures::from_bytes(data).ok_or_else(|| {
+                L2capError::ProtocolError("Extended features mask too short".into())
+            })?;
+
+            let mut extended_features = self.extended_features.write().unwrap();
+            extended_features.insert(hci_handle, features);
+        }
+
+        Ok(())
+    }
+
+    /// Handle an echo response
+    fn handle_echo_response(&self, identifier: u8, data: Vec<u8>) -> L2capResult<()> {
+        let transaction = {
+            let mut transactions = self.pending_transactions.write().unwrap();
+            transactions.remove(&identifier)
+        };
+
+        match transaction.map(|t| t.transaction_type) {
+            Some(SignalingTransactionType::Echo) => {
+                let waiter = {
+                    let mut waiters = self.echo_waiters.lock().unwrap();
+                    waiters.remove(&identifier)
+                };
+                if let Some(waiter) = waiter {
+                    // The caller may have given up waiting
+                    let _ = waiter.send(data);
+                }
+                Ok(())
+            }
+            _ => Err(L2capError::ProtocolError("Unexpected echo response".into())),
+        }
+    }
+
     fn handle_connection_parameter_update_response(
         &self,
         identifier: u8,
@@ -1178,6 +2039,7 @@ impl L2capManager {
     }
 
     /// Handle an LE Credit Based Connection Request
+    #[allow(clippy::too_many_arguments)]
     fn handle_le_credit_based_connection_request(
         &self,
         identifier: u8,
@@ -1224,6 +2086,7 @@ impl L2capManager {
 
         // Set data callback if registered
         if let Some(ref callback) = registration.data_callback {
+            let callback = callback.clone();
             channel.set_data_callback(move |data| {
                 let mut callback = callback.lock().unwrap();
                 (*callback)(data)
@@ -1239,10 +2102,7 @@ impl L2capManager {
         // Associate the channel with the HCI handle
         {
             let mut handle_map = self.handle_to_cid.write().unwrap();
-            handle_map
-                .entry(hci_handle)
-                .or_insert_with(Vec::new)
-                .push(local_cid);
+            handle_map.entry(hci_handle).or_default().push(local_cid);
         }
 
         // If the connection is auto-accepted, send response immediately
@@ -1265,7 +2125,8 @@ impl L2capManager {
                 }
             }
 
-            // Send the response (would be sent via HCI)
+            // Send the response
+            self.send_signaling_message(hci_handle, response)?;
 
             // Notify event handlers of connection
             self.notify_event_handlers(ChannelEvent::Connected {
@@ -1290,8 +2151,8 @@ impl L2capManager {
         identifier: u8,
         destination_cid: ChannelId,
         mtu: u16,
-        mps: u16,
-        initial_credits: u16,
+        _mps: u16,
+        _initial_credits: u16,
         result: u16,
     ) -> L2capResult<()> {
         if self.connection_type != ConnectionType::LE {
@@ -1357,10 +2218,289 @@ impl L2capManager {
         Ok(())
     }
 
+    /// Handle an Enhanced Credit Based Connection Request
+    ///
+    /// `remote_config` holds the MTU, MPS and initial credits the peer
+    /// requested for all of its channels. The channels use
+    /// `LeCreditBasedConfig::enhanced()` locally and the peer's values for
+    /// sending. Every channel is accepted or the whole request is refused.
+    /// Without auto-accept the response is sent by `accept_connection` or
+    /// `reject_connection`.
+    fn handle_enhanced_credit_based_connection_request(
+        &self,
+        identifier: u8,
+        spsm: u16,
+        remote_config: LeCreditBasedConfig,
+        source_cids: &[ChannelId],
+        hci_handle: u16,
+    ) -> L2capResult<()> {
+        if self.connection_type != ConnectionType::LE {
+            return Err(L2capError::NotSupported);
+        }
+
+        let local_config = LeCreditBasedConfig::enhanced();
+        let refuse = |result: u16| {
+            self.send_signaling_message(
+                hci_handle,
+                SignalingMessage::EnhancedCreditBasedConnectionResponse {
+                    identifier,
+                    mtu: local_config.mtu,
+                    mps: local_config.mps,
+                    initial_credits: 0,
+                    result,
+                    destination_cids: vec![0; source_cids.len()],
+                },
+            )
+        };
+
+        // Look up the PSM registration
+        let registration = PSM::from_value(spsm).and_then(|psm| {
+            let registrations = self.psm_registrations.read().unwrap();
+            registrations
+                .get(&psm.value())
+                .cloned()
+                .map(|registration| (psm, registration))
+        });
+        let Some((psm, registration)) = registration else {
+            return refuse(L2CAP_RESULT_REFUSED_PSM_UNSUPPORTED);
+        };
+
+        if remote_config.mtu < L2CAP_CREDIT_BASED_MIN_MTU
+            || remote_config.mps < L2CAP_CREDIT_BASED_MIN_MTU
+        {
+            return refuse(L2CAP_RESULT_UNACCEPTABLE_PARAMETERS);
+        }
+
+        if let Some(result) = self.check_source_cids(source_cids, hci_handle) {
+            return refuse(result);
+        }
+
+        let mut local_cids = Vec::with_capacity(source_cids.len());
+        for &source_cid in source_cids {
+            let Ok(local_cid) = self.allocate_cid() else {
+                // Drop the channels created so far
+                {
+                    let mut channels = self.channels.write().unwrap();
+                    for cid in &local_cids {
+                        channels.remove(cid);
+                    }
+                }
+                return refuse(L2CAP_RESULT_REFUSED_NO_RESOURCES);
+            };
+
+            let mut channel = L2capChannel::new_le_credit_based(local_cid, psm, local_config);
+            channel.set_remote_cid(source_cid);
+            channel.set_remote_mtu(remote_config.mtu);
+            channel.set_remote_mps(remote_config.mps);
+            channel.add_credits(remote_config.initial_credits)?;
+
+            // Set data callback if registered
+            if let Some(ref callback) = registration.data_callback {
+                let callback = callback.clone();
+                channel.set_data_callback(move |data| {
+                    let mut callback = callback.lock().unwrap();
+                    (*callback)(data)
+                });
+            }
+
+            if registration.auto_accept {
+                channel.set_state(L2capChannelState::Open);
+            }
+
+            let mut channels = self.channels.write().unwrap();
+            channels.insert(local_cid, channel);
+            local_cids.push(local_cid);
+        }
+
+        // Associate the channels with the HCI handle
+        {
+            let mut handle_map = self.handle_to_cid.write().unwrap();
+            handle_map
+                .entry(hci_handle)
+                .or_default()
+                .extend_from_slice(&local_cids);
+        }
+
+        if !registration.auto_accept {
+            self.pending_enhanced_requests
+                .lock()
+                .unwrap()
+                .insert((hci_handle, identifier), local_cids);
+
+            // Let the application decide
+            for &source_cid in source_cids {
+                self.notify_event_handlers(ChannelEvent::ConnectionRequest {
+                    identifier,
+                    psm,
+                    source_cid,
+                });
+            }
+            return Ok(());
+        }
+
+        self.accept_enhanced_channels(identifier, psm, &local_cids, hci_handle)
+    }
+
+    /// Check the source CIDs of an Enhanced Credit Based Connection Request
+    ///
+    /// Returns the result refusing the request if a CID is outside the LE
+    /// dynamic range, repeated, or already used by the peer on this
+    /// connection.
+    fn check_source_cids(&self, source_cids: &[ChannelId], hci_handle: u16) -> Option<u16> {
+        let valid = L2CAP_DYNAMIC_CID_MIN..=L2CAP_LE_DYNAMIC_CID_MAX;
+        if source_cids.iter().any(|cid| !valid.contains(cid)) {
+            return Some(L2CAP_RESULT_CREDIT_BASED_INVALID_SOURCE_CID);
+        }
+
+        let handle_map = self.handle_to_cid.read().unwrap();
+        let channels = self.channels.read().unwrap();
+        let mut used: HashSet<ChannelId> = handle_map
+            .get(&hci_handle)
+            .into_iter()
+            .flatten()
+            .filter_map(|cid| channels.get(cid))
+            .filter(|channel| !channel.is_fixed())
+            .map(|channel| channel.remote_cid())
+            .collect();
+        if source_cids.iter().any(|&cid| !used.insert(cid)) {
+            return Some(L2CAP_RESULT_CREDIT_BASED_SOURCE_CID_ALREADY_ALLOCATED);
+        }
+
+        None
+    }
+
+    /// Open the channels of an Enhanced Credit Based Connection Request and
+    /// send the response
+    fn accept_enhanced_channels(
+        &self,
+        identifier: u8,
+        psm: PSM,
+        local_cids: &[ChannelId],
+        hci_handle: u16,
+    ) -> L2capResult<()> {
+        let local_config = LeCreditBasedConfig::enhanced();
+        {
+            let mut channels = self.channels.write().unwrap();
+            for cid in local_cids {
+                if let Some(channel) = channels.get_mut(cid) {
+                    channel.set_state(L2capChannelState::Open);
+                }
+            }
+        }
+
+        self.send_signaling_message(
+            hci_handle,
+            SignalingMessage::EnhancedCreditBasedConnectionResponse {
+                identifier,
+                mtu: local_config.mtu,
+                mps: local_config.mps,
+                initial_credits: local_config.initial_credits,
+                result: L2CAP_RESULT_SUCCESS,
+                destination_cids: local_cids.to_vec(),
+            },
+        )?;
+
+        for &local_cid in local_cids {
+            self.notify_event_handlers(ChannelEvent::Connected {
+                cid: local_cid,
+                psm,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Handle an Enhanced Credit Based Connection Response
+    ///
+    /// Channels the peer refused have a destination CID of 0x0000 and are
+    /// removed; the others are opened even if `result` reports a partial
+    /// refusal. `remote_config` holds the peer's MTU, MPS and initial credits
+    /// for all of them.
+    fn handle_enhanced_credit_based_connection_response(
+        &self,
+        identifier: u8,
+        remote_config: LeCreditBasedConfig,
+        result: u16,
+        destination_cids: &[ChannelId],
+    ) -> L2capResult<()> {
+        if self.connection_type != ConnectionType::LE {
+            return Err(L2capError::NotSupported);
+        }
+
+        // Find the pending transaction
+        let transaction = {
+            let mut transactions = self.pending_transactions.write().unwrap();
+            transactions.remove(&identifier)
+        };
+
+        let (psm, local_cids) = match transaction.map(|t| t.transaction_type) {
+            Some(SignalingTransactionType::EnhancedConnect(psm, local_cids)) => (psm, local_cids),
+            _ => {
+                return Err(L2capError::ProtocolError(
+                    "Unexpected enhanced credit based connection response".into(),
+                ));
+            }
+        };
+
+        // A refused request may carry no CIDs at all
+        let destination_cids = if destination_cids.is_empty() {
+            vec![0; local_cids.len()]
+        } else if destination_cids.len() == local_cids.len() {
+            destination_cids.to_vec()
+        } else {
+            return Err(L2capError::ProtocolError(format!(
+                "Expected {} destination CIDs, got {}",
+                local_cids.len(),
+                destination_cids.len()
+            )));
+        };
+
+        for (local_cid, destination_cid) in local_cids.into_iter().zip(destination_cids) {
+            if destination_cid == 0 {
+                {
+                    let mut channels = self.channels.write().unwrap();
+                    channels.remove(&local_cid);
+                }
+                {
+                    let mut handle_map = self.handle_to_cid.write().unwrap();
+                    for cids in handle_map.values_mut() {
+                        cids.retain(|&cid| cid != local_cid);
+                    }
+                }
+
+                self.notify_event_handlers(ChannelEvent::Disconnected {
+                    cid: local_cid,
+                    psm: Some(psm),
+                    reason: format!("Connection refused: result={}", result),
+                });
+                continue;
+            }
+
+            {
+                let mut channels = self.channels.write().unwrap();
+                let channel = channels
+                    .get_mut(&local_cid)
+                    .ok_or(L2capError::ChannelNotFound)?;
+                channel.set_remote_cid(destination_cid);
+                channel.set_remote_mtu(remote_config.mtu);
+                channel.set_remote_mps(remote_config.mps);
+                channel.add_credits(remote_config.initial_credits)?;
+                channel.set_state(L2capChannelState::Open);
+            }
+
+            self.notify_event_handlers(ChannelEvent::Connected {
+                cid: local_cid,
+                psm,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Handle an LE Flow Control Credit
     fn handle_le_flow_control_credit(
         &self,
-        identifier: u8,
+        _identifier: u8,
         cid: ChannelId,
         credits: u16,
     ) -> L2capResult<()> {
@@ -1384,14 +2524,36 @@ impl L2capManager {
             found_cid.ok_or(L2capError::ChannelNotFound)?
         };
 
-        // Add the credits to the channel
-        {
+        // Add the credits to the channel and send what was waiting for them
+        let packets = {
             let mut channels = self.channels.write().unwrap();
-            if let Some(channel) = channels.get_mut(&local_cid) {
-                channel.add_credits(credits)?;
+            let channel = channels
+                .get_mut(&local_cid)
+                .ok_or(L2capError::ChannelNotFound)?;
+            channel.add_credits(credits)?;
+
+            if channel.state() != L2capChannelState::Open {
+                return Ok(());
             }
+            channel.take_sendable_frames()?
+        };
+
+        if packets.is_empty() {
+            return Ok(());
         }
 
+        let hci_handle = self
+            .hci_handle_for_cid(local_cid)
+            .ok_or(L2capError::NotConnected)?;
+        for packet in &packets {
+            self.send_frame(hci_handle, packet)?;
+        }
+        trace!(
+            "Sent {} queued K-frames on channel 0x{:04X}",
+            packets.len(),
+            local_cid
+        );
+
         Ok(())
     }
 
@@ -1409,9 +2571,7 @@ impl L2capManager {
             data: data.to_vec(),
         };
 
-        // Send the message (would be sent via HCI)
-
-        Ok(())
+        self.send_signaling_message(hci_handle, message)
     }
 
     /// Notify event handlers of a channel event
@@ -1438,37 +2598,124 @@ impl L2capManager {
         }
     }
 
+    /// Get how many times a timed out signaling request is sent again
+    pub fn max_signaling_retries(&self) -> u8 {
+        *self.max_signaling_retries.lock().unwrap()
+    }
+
+    /// Set how many times a timed out signaling request is sent again
+    ///
+    /// Defaults to `L2CAP_DEFAULT_SIGNALING_RETRIES`.
+    pub fn set_max_signaling_retries(&self, max_retries: u8) {
+        *self.max_signaling_retries.lock().unwrap() = max_retries;
+    }
+
     /// Process timeouts for pending transactions
+    ///
+    /// A request without a response after `timeout` is sent again with the
+    /// same identifier, up to `max_signaling_retries` times. After that the
+    /// transaction fails: channels being connected, configured or
+    /// disconnected by it are closed locally and reported with
+    /// `ChannelEvent::Disconnected` with reason "RTX timeout". Call this
+    /// periodically with a timeout of `L2CAP_RTX_TIMEOUT_MS`.
     pub fn process_timeouts(&self, timeout: Duration) -> L2capResult<()> {
-        let mut expired_transactions = Vec::new();
+        let max_retries = self.max_signaling_retries();
+        let mut retransmissions = Vec::new();
+        let mut failed = Vec::new();
 
         {
             let mut transactions = self.pending_transactions.write().unwrap();
 
-            // Find expired transactions
-            for (id, transaction) in transactions.iter() {
-                if transaction.timestamp.elapsed() > timeout {
-                    expired_transactions.push(*id);
+            let mut expired = Vec::new();
+            for (id, transaction) in transactions.iter_mut() {
+                if transaction.timestamp.elapsed() <= timeout {
+                    continue;
+                }
+
+                if transaction.retries < max_retries {
+                    transaction.retries += 1;
+                    transaction.timestamp = Instant::now();
+                    retransmissions
+                        .push((transaction.hci_handle, transaction.original_message.clone()));
+                } else {
+                    expired.push(*id);
                 }
             }
 
-            // Remove expired transactions
-            for id in &expired_transactions {
-                transactions.remove(id);
+            for id in expired {
+                if let Some(transaction) = transactions.remove(&id) {
+                    failed.push(transaction);
+                }
             }
         }
 
-        // Process each expired transaction
-        for id in expired_transactions {
-            // Handle the timeout for the specific transaction
-            // For now, we'll just report a disconnection for connection requests
+        for transaction in failed {
+            self.fail_transaction(transaction);
+        }
 
-            // TODO: Implement retries and proper timeout handling
+        for (hci_handle, message) in retransmissions {
+            debug!("Retransmitting signaling request: {:?}", message);
+            self.send_signaling_message(hci_handle, message)?;
         }
 
         Ok(())
     }
 
+    /// Give up on a signaling transaction that ran out of retries
+    fn fail_transaction(&self, transaction: SignalingTransaction) {
+        warn!(
+            "Signaling request timed out: {:?}",
+            transaction.original_message
+        );
+
+        let local_cids = match transaction.transaction_type {
+            SignalingTransactionType::Connect(_, local_cid)
+            | SignalingTransactionType::Disconnect(local_cid, _) => vec![local_cid],
+            SignalingTransactionType::EnhancedConnect(_, local_cids) => local_cids,
+            SignalingTransactionType::Configure(remote_cid) => {
+                let handle_map = self.handle_to_cid.read().unwrap();
+                let channels = self.channels.read().unwrap();
+                handle_map
+                    .get(&transaction.hci_handle)
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .filter(|cid| channels.get(cid).map(|c| c.remote_cid()) == Some(remote_cid))
+                    .collect()
+            }
+            SignalingTransactionType::Echo => {
+                let mut waiters = self.echo_waiters.lock().unwrap();
+                waiters.remove(&transaction.original_message.identifier());
+                Vec::new()
+            }
+            SignalingTransactionType::Information(_)
+            | SignalingTransactionType::ConnectionParameterUpdate => Vec::new(),
+        };
+
+        for cid in local_cids {
+            let psm = {
+                let mut channels = self.channels.write().unwrap();
+                match channels.remove(&cid) {
+                    Some(channel) => channel.psm(),
+                    None => continue,
+                }
+            };
+
+            {
+                let mut handle_map = self.handle_to_cid.write().unwrap();
+                if let Some(cids) = handle_map.get_mut(&transaction.hci_handle) {
+                    cids.retain(|&c| c != cid);
+                }
+            }
+
+            self.notify_event_handlers(ChannelEvent::Disconnected {
+                cid,
+                psm,
+                reason: "RTX timeout".into(),
+            });
+        }
+    }
+
     /// Remove channels associated with a disconnected HCI handle
     pub fn handle_connection_closed(&self, hci_handle: u16) -> L2capResult<()> {
         let cids = {
@@ -1476,7 +2723,22 @@ impl L2capManager {
             handle_map.remove(&hci_handle).unwrap_or_default()
         };
 
+        {
+            let mut callbacks = self.fixed_channel_callbacks.write().unwrap();
+            callbacks.retain(|&(_, handle), _| handle != hci_handle);
+        }
+
+        {
+            let mut pending = self.pending_enhanced_requests.lock().unwrap();
+            pending.retain(|&(handle, _), _| handle != hci_handle);
+        }
+
         for cid in cids {
+            // Fixed channels stay open while other connections use them
+            if self.hci_handle_for_cid(cid).is_some() {
+                continue;
+            }
+
             let psm = {
                 let channels = self.channels.read().unwrap();
                 channels.get(&cid).and_then(|c| c.psm())
@@ -1496,20 +2758,181 @@ impl L2capManager {
             });
         }
 
+        // Let upper layers drop their per-connection state
+        {
+            let callbacks = self.connection_closed_callbacks.lock().unwrap();
+            for callback in callbacks.iter() {
+                callback(hci_handle);
+            }
+        }
+
+        {
+            let mut handle_map = self.handle_to_addr.write().unwrap();
+            handle_map.remove(&hci_handle);
+        }
+
+        {
+            let mut extended_features = self.extended_features.write().unwrap();
+            extended_features.remove(&hci_handle);
+        }
+
+        // Frames left half received can no longer be completed
+        {
+            let mut fragments = self.receive_fragments.lock().unwrap();
+            fragments.remove(&hci_handle);
+        }
+
+        // The controller drops unsent packets of a closed link
+        {
+            let mut queues = self.send_queues.lock().unwrap();
+            queues.remove(&hci_handle);
+        }
+        if let Some(transport) = self.acl_transport.read().unwrap().as_ref() {
+            transport.flow.connection_closed(hci_handle);
+        }
+
         Ok(())
     }
 
-    // Add placeholder for send_signaling_message if it was missing
+    /// Ask the peer for its extended features mask
+    ///
+    /// The answer is available from `supported_features` once the
+    /// Information Response has been handled.
+    pub fn request_extended_features(&self, hci_handle: u16) -> L2capResult<()> {
+        let message = SignalingMessage::InformationRequest {
+            identifier: self.allocate_signal_id(),
+            info_type: L2CAP_EXTENDED_FEATURES,
+        };
+
+        self.track_transaction(
+            hci_handle,
+            SignalingTransactionType::Information(L2CAP_EXTENDED_FEATURES),
+            message.clone(),
+        );
+
+        self.send_signaling_message(hci_handle, message)
+    }
+
+    /// Measure the round-trip time of an echo request
+    ///
+    /// Sends an Echo Request carrying `payload_size` random bytes and blocks
+    /// until the peer echoes them back. Echo is only available on BR/EDR
+    /// connections, and the payload must fit the signaling MTU.
+    pub fn measure_rtt(&self, hci_handle: u16, payload_size: usize) -> L2capResult<Duration> {
+        if self.connection_type != ConnectionType::Classic {
+            return Err(L2capError::NotSupported);
+        }
+        if self.hci_socket.is_none() {
+            return Err(L2capError::NotConnected);
+        }
+
+        let max_payload = L2CAP_SIGNALING_MTU as usize - 4;
+        if payload_size > max_payload {
+            return Err(L2capError::MtuExceeded {
+                requested: payload_size,
+                allowed: max_payload,
+            });
+        }
+
+        let payload: Vec<u8> = (0..payload_size).map(|_| rand::random::<u8>()).collect();
+        let signal_id = self.allocate_signal_id();
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let mut waiters = self.echo_waiters.lock().unwrap();
+            waiters.insert(signal_id, sender);
+        }
+        let message = SignalingMessage::EchoRequest {
+            identifier: signal_id,
+            data: payload.clone(),
+        };
+        self.track_transaction(hci_handle, SignalingTransactionType::Echo, message.clone());
+
+        let start = Instant::now();
+        let result = self
+            .send_signaling_message(hci_handle, message)
+            .and_then(|_| {
+                receiver
+                    .recv_timeout(Duration::from_millis(L2CAP_ECHO_TIMEOUT_MS))
+                    .map_err(|_| L2capError::Timeout)
+            });
+        let rtt = start.elapsed();
+
+        {
+            let mut waiters = self.echo_waiters.lock().unwrap();
+            waiters.remove(&signal_id);
+        }
+        {
+            let mut transactions = self.pending_transactions.write().unwrap();
+            transactions.remove(&signal_id);
+        }
+
+        if result? != payload {
+            return Err(L2capError::ProtocolError(
+                "Echo response data does not match the request".into(),
+            ));
+        }
+
+        Ok(rtt)
+    }
+
+    /// Measure the round-trip time of `count` echo requests
+    ///
+    /// The requests are sent one after another, see `measure_rtt`.
+    pub fn measure_rtt_stats(
+        &self,
+        hci_handle: u16,
+        payload_size: usize,
+        count: u8,
+    ) -> L2capResult<RttStats> {
+        if count == 0 {
+            return Err(L2capError::InvalidParameter(
+                "At least one echo request is needed".into(),
+            ));
+        }
+
+        let mut samples = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            samples.push(self.measure_rtt(hci_handle, payload_size)?);
+        }
+
+        Ok(RttStats {
+            min: *samples.iter().min().unwrap(),
+            max: *samples.iter().max().unwrap(),
+            mean: samples.iter().sum::<Duration>() / count as u32,
+        })
+    }
+
+    /// Get the extended features the peer on a connection reported
+    pub fn supported_features(&self, hci_handle: u16) -> Option<ExtendedFeatures> {
+        let extended_features = self.extended_features.read().unwrap();
+        extended_features.get(&hci_handle).copied()
+    }
+
+    /// Send a signaling message on the signaling channel of a connection
+    ///
+    /// The message is sent like any other frame, see `send_frame`.
     fn send_signaling_message(
         &self,
-        _hci_handle: u16,
-        _channel_id: ChannelId,
+        hci_handle: u16,
         message: SignalingMessage,
     ) -> L2capResult<()> {
-        warn!(
-            "Sending signaling message (needs HCI integration): {:?}",
-            message
-        );
-        Ok(())
+        let signaling_cid = match self.connection_type {
+            ConnectionType::Classic => L2CAP_SIGNALING_CID,
+            ConnectionType::LE => L2CAP_LE_SIGNALING_CID,
+        };
+
+        trace!("Sending signaling message: {:?}", message);
+        let packet = L2capPacket::new(signaling_cid, message.serialize());
+        self.send_frame(hci_handle, &packet)
     }
 }
+
+/// Check whether an ATT PDU is meant for the local server
+///
+/// Requests, commands and the Handle Value Confirmation have even opcodes;
+/// responses, notifications and indications, which go to the client, have odd
+/// ones.
+fn is_att_server_pdu(payload: &[u8]) -> bool {
+    payload.first().is_some_and(|opcode| opcode & 0x01 == 0)
+}
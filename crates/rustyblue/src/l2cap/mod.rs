@@ -19,7 +19,10 @@ pub mod types;
 
 // Re-export the public API
 pub use self::channel::{L2capChannel, L2capChannelType};
-pub use self::core::{ChannelEventCallback, L2capManager};
+pub use self::core::{
+    ChannelEvent, ChannelEventCallback, ConnectionClosedCallback, ConnectionOpenedCallback,
+    FixedChannelCallback, L2capManager,
+};
 pub use self::psm::{obtain_dynamic_psm, PSM};
 pub use self::types::ConnectionPolicy;
 pub use self::types::*;
@@ -6,7 +6,7 @@ use super::constants::*;
 use super::types::*;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::convert::TryFrom;
-use std::io::{Cursor, Read, Write};
+use std::io::Cursor;
 
 /// L2CAP Packet header
 #[derive(Debug, Clone, Copy)]
@@ -210,6 +210,9 @@ impl L2capPacket {
     }
 
     /// Parse an L2CAP packet from raw bytes
+    ///
+    /// The channel mode is not known from the frame alone, so everything after
+    /// the basic header is kept as payload.
     pub fn parse(data: &[u8]) -> Option<Self> {
         if data.len() < L2CAP_BASIC_HEADER_SIZE {
             return None;
@@ -218,35 +221,27 @@ impl L2capPacket {
         let header = L2capHeader::parse(data)?;
 
         // Make sure we have enough data for the payload
-        if data.len() < L2CAP_BASIC_HEADER_SIZE + header.length as usize {
+        let payload_end = L2CAP_BASIC_HEADER_SIZE + header.length as usize;
+        if data.len() < payload_end {
             return None;
         }
 
-        // Check if this might be a packet with control field
-        let (control, payload_start) = if header.channel_id > L2CAP_ATTRIBUTE_PROTOCOL_CID {
-            // Try to parse control field for dynamic channels
-            let control_data = &data[L2CAP_BASIC_HEADER_SIZE..L2CAP_BASIC_HEADER_SIZE + 2];
-            if let Some(control) = L2capControlField::parse(control_data) {
-                (Some(control), L2CAP_BASIC_HEADER_SIZE + 2)
-            } else {
-                (None, L2CAP_BASIC_HEADER_SIZE)
-            }
-        } else {
-            // Fixed channels don't use control field
-            (None, L2CAP_BASIC_HEADER_SIZE)
-        };
-
-        // Extract payload
-        let payload_end = L2CAP_BASIC_HEADER_SIZE + header.length as usize;
-        let payload = data[payload_start..payload_end].to_vec();
-
         Some(Self {
             header,
-            control,
-            payload,
+            control: None,
+            payload: data[L2CAP_BASIC_HEADER_SIZE..payload_end].to_vec(),
         })
     }
 
+    /// Parse an L2CAP packet carrying a control field (I-frame or S-frame)
+    pub fn parse_with_control(data: &[u8]) -> Option<Self> {
+        let mut packet = Self::parse(data)?;
+        let control = L2capControlField::parse(&packet.payload)?;
+        packet.payload.drain(..2);
+        packet.control = Some(control);
+        Some(packet)
+    }
+
     /// Serialize the L2CAP packet to a byte vector
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut result = Vec::with_capacity(L2CAP_BASIC_HEADER_SIZE + self.header.length as usize);
@@ -1,58 +1,90 @@
 //! Protocol/Service Multiplexer (PSM) handling for L2CAP
 //!
 //! This module manages PSM values for L2CAP connections.
+//!
+//! PSMs select the protocol on a connection-oriented channel. Protocols on
+//! fixed channels, such as SMP (CID 0x0006) or ATT on LE (CID 0x0004), are
+//! addressed by their channel ID instead and have no PSM; see the `*_CID`
+//! constants in `l2cap::constants`.
 
 use std::fmt;
 use std::sync::atomic::{AtomicU16, Ordering};
 
 /// Protocol/Service Multiplexer (PSM) values used in L2CAP.
 ///
-/// See Bluetooth Core Specification Vol 3, Part A, Section 4.
-/// And assigned numbers: https://www.bluetooth.com/specifications/assigned-numbers/logical-link-control/
+/// See Bluetooth Core Specification Vol 3, Part A, Section 4.2, and the
+/// assigned numbers: https://www.bluetooth.com/specifications/assigned-numbers/
+/// (Logical Link Control). On LE the same values are used as SPSMs for
+/// LE credit based channels.
+#[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u16)]
 pub enum PSM {
     // Fixed PSM values for standard protocols
-    /// Service Discovery Protocol
+    /// Service Discovery Protocol (Core Specification Vol 3, Part B)
     SDP = 0x0001,
-    /// RFCOMM protocol
+    /// RFCOMM with TS 07.10 (RFCOMM specification)
     RFCOMM = 0x0003,
-    /// Telephony Control Protocol
+    /// Telephony Control Specification, TCS Binary (deprecated)
     TCS_BIN = 0x0005,
-    /// TCS_BIN_CORDLESS
+    /// Telephony Control Specification, TCS Binary cordless (deprecated)
     TCS_BIN_CORDLESS = 0x0007,
-    /// BNEP protocol
+    /// Bluetooth Network Encapsulation Protocol (BNEP specification)
     BNEP = 0x000F,
-    /// HID Control
+    /// HID Control channel (Human Interface Device Profile)
     HID_CONTROL = 0x0011,
-    /// HID Interrupt
+    /// HID Interrupt channel (Human Interface Device Profile)
     HID_INTERRUPT = 0x0013,
-    /// UPnP protocol (ESDP)
+    /// Universal Plug and Play, ESDP (ESDP for UPnP specification)
     UPNP = 0x0015,
-    /// AVCTP protocol
+    /// Audio/Video Control Transport Protocol (AVCTP specification)
     AVCTP = 0x0017,
-    /// AVDTP protocol
+    /// Audio/Video Distribution Transport Protocol (AVDTP specification)
     AVDTP = 0x0019,
-    /// AVCTP Browsing
+    /// AVCTP browsing channel (Audio/Video Remote Control Profile)
     AVCTP_BROWSING = 0x001B,
-    /// ATT protocol (fixed on LE only)
+    /// Unrestricted Digital Information control plane (UDI Profile, deprecated)
+    UDI_C_PLANE = 0x001D,
+    /// Attribute Protocol over BR/EDR (Core Specification Vol 3, Part F);
+    /// LE uses the fixed ATT channel instead
     ATT = 0x001F,
-    /// 3DSP protocol
+    /// 3D Synchronization Profile (3DSP specification)
     _3DSP = 0x0021,
+    /// Internet Protocol Support Profile, LE only (IPSP specification)
+    IPSP = 0x0023,
+    /// Object Transfer Service (OTS specification)
+    OTS = 0x0025,
+    /// Enhanced ATT bearer (Core Specification Vol 3, Part F, Section 3.2.11)
+    EATT = 0x0027,
 
     // Dynamic PSM (assigned at runtime)
     /// Dynamically assigned PSM
     Dynamic(u16),
 }
 
+/// Check the format rules every PSM value has to follow
+///
+/// The least significant octet must be odd and the least significant bit
+/// of the most significant octet must be zero.
+fn is_valid_psm_value(value: u16) -> bool {
+    value & 0x0001 == 0x0001 && value & 0x0100 == 0
+}
+
+/// Check if a value lies in a dynamic PSM range
+///
+/// BR/EDR dynamic PSMs start at 0x1001, LE dynamic SPSMs are 0x0080-0x00FF.
+fn is_dynamic_psm_value(value: u16) -> bool {
+    is_valid_psm_value(value) && (value >= 0x1001 || (0x0080..=0x00FF).contains(&value))
+}
+
 impl PSM {
     /// Check if the PSM is valid
+    ///
+    /// Assigned PSMs are always valid. Dynamic PSMs must follow the PSM
+    /// format rules and lie in the BR/EDR or LE dynamic range.
     pub fn is_valid(&self) -> bool {
         match self {
-            PSM::Dynamic(value) => {
-                // Dynamic PSMs must be odd and in the valid range
-                (*value % 2 == 1) && (*value >= 0x1001) && (*value <= 0xFFFF)
-            }
+            PSM::Dynamic(value) => is_dynamic_psm_value(*value),
             _ => true, // All fixed PSMs are valid
         }
     }
@@ -71,8 +103,12 @@ impl PSM {
             PSM::AVCTP => 0x0017,
             PSM::AVDTP => 0x0019,
             PSM::AVCTP_BROWSING => 0x001B,
+            PSM::UDI_C_PLANE => 0x001D,
             PSM::ATT => 0x001F,
             PSM::_3DSP => 0x0021,
+            PSM::IPSP => 0x0023,
+            PSM::OTS => 0x0025,
+            PSM::EATT => 0x0027,
             PSM::Dynamic(value) => *value,
         }
     }
@@ -91,10 +127,14 @@ impl PSM {
             0x0017 => Some(PSM::AVCTP),
             0x0019 => Some(PSM::AVDTP),
             0x001B => Some(PSM::AVCTP_BROWSING),
+            0x001D => Some(PSM::UDI_C_PLANE),
             0x001F => Some(PSM::ATT),
             0x0021 => Some(PSM::_3DSP),
-            // Dynamic PSMs must be odd and in the dynamic range
-            _ if value % 2 == 1 && value >= 0x1001 && value <= 0xFFFF => Some(PSM::Dynamic(value)),
+            0x0023 => Some(PSM::IPSP),
+            0x0025 => Some(PSM::OTS),
+            0x0027 => Some(PSM::EATT),
+            // Dynamic PSMs must be well formed and in a dynamic range
+            _ if is_dynamic_psm_value(value) => Some(PSM::Dynamic(value)),
             _ => None,
         }
     }
@@ -114,8 +154,12 @@ impl fmt::Display for PSM {
             PSM::AVCTP => write!(f, "AVCTP (0x0017)"),
             PSM::AVDTP => write!(f, "AVDTP (0x0019)"),
             PSM::AVCTP_BROWSING => write!(f, "AVCTP-Browsing (0x001B)"),
+            PSM::UDI_C_PLANE => write!(f, "UDI-C-Plane (0x001D)"),
             PSM::ATT => write!(f, "ATT (0x001F)"),
             PSM::_3DSP => write!(f, "3DSP (0x0021)"),
+            PSM::IPSP => write!(f, "IPSP (0x0023)"),
+            PSM::OTS => write!(f, "OTS (0x0025)"),
+            PSM::EATT => write!(f, "EATT (0x0027)"),
             PSM::Dynamic(value) => write!(f, "Dynamic PSM (0x{:04X})", value),
         }
     }
@@ -127,16 +171,22 @@ static NEXT_DYNAMIC_PSM: AtomicU16 = AtomicU16::new(0x1001);
 /// Obtain a new dynamic PSM value
 ///
 /// This function allocates a new dynamic PSM value that isn't currently in use.
-/// Dynamic PSMs must be odd values in the range 0x1001-0xFFFF.
+/// Dynamic PSMs must be odd values in the range 0x1001-0xFFFF, with the
+/// least significant bit of the upper octet cleared.
 pub fn obtain_dynamic_psm() -> PSM {
-    // Get the next PSM, ensuring it's odd
-    let mut next_psm = NEXT_DYNAMIC_PSM.fetch_add(2, Ordering::SeqCst);
+    loop {
+        // Get the next PSM, ensuring it's odd
+        let mut next_psm = NEXT_DYNAMIC_PSM.fetch_add(2, Ordering::SeqCst);
 
-    // If we've wrapped around, reset to 0x1001
-    if next_psm > 0xFFFF || next_psm < 0x1001 {
-        next_psm = 0x1001;
-        NEXT_DYNAMIC_PSM.store(0x1003, Ordering::SeqCst);
-    }
+        // If we've wrapped around, reset to 0x1001
+        if next_psm < 0x1001 {
+            next_psm = 0x1001;
+            NEXT_DYNAMIC_PSM.store(0x1003, Ordering::SeqCst);
+        }
 
-    PSM::Dynamic(next_psm)
+        // Skip values with an odd upper octet, e.g. 0x1101-0x11FF
+        if is_valid_psm_value(next_psm) {
+            return PSM::Dynamic(next_psm);
+        }
+    }
 }
@@ -7,9 +7,8 @@ use super::constants::*;
 use super::packet::*;
 use super::psm::PSM;
 use super::types::*;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::convert::TryFrom;
-use std::io::{Cursor, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Cursor;
 
 /// Handle for identifying signaling transactions
 pub type SignalId = u8;
@@ -126,6 +125,29 @@ pub enum SignalingMessage {
         cid: u16,
         credits: u16,
     },
+
+    /// Enhanced Credit Based Connection Request (up to 5 channels)
+    EnhancedCreditBasedConnectionRequest {
+        identifier: SignalId,
+        spsm: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        source_cids: Vec<u16>,
+    },
+
+    /// Enhanced Credit Based Connection Response
+    ///
+    /// `destination_cids` is in the order of the request's source CIDs,
+    /// with 0x0000 for each refused channel.
+    EnhancedCreditBasedConnectionResponse {
+        identifier: SignalId,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        result: u16,
+        destination_cids: Vec<u16>,
+    },
 }
 
 impl SignalingMessage {
@@ -146,6 +168,12 @@ impl SignalingMessage {
             SignalingMessage::LeCreditBasedConnectionRequest { identifier, .. } => *identifier,
             SignalingMessage::LeCreditBasedConnectionResponse { identifier, .. } => *identifier,
             SignalingMessage::LeFlowControlCredit { identifier, .. } => *identifier,
+            SignalingMessage::EnhancedCreditBasedConnectionRequest { identifier, .. } => {
+                *identifier
+            }
+            SignalingMessage::EnhancedCreditBasedConnectionResponse { identifier, .. } => {
+                *identifier
+            }
             _ => 0, // Default for any not covered
         }
     }
@@ -174,6 +202,12 @@ impl SignalingMessage {
                 L2CAP_LE_CREDIT_BASED_CONNECTION_RESPONSE
             }
             Self::LeFlowControlCredit { .. } => L2CAP_LE_FLOW_CONTROL_CREDIT,
+            Self::EnhancedCreditBasedConnectionRequest { .. } => {
+                L2CAP_CREDIT_BASED_CONNECTION_REQUEST
+            }
+            Self::EnhancedCreditBasedConnectionResponse { .. } => {
+                L2CAP_CREDIT_BASED_CONNECTION_RESPONSE
+            }
         }
     }
 
@@ -196,221 +230,13 @@ impl SignalingMessage {
             Self::LeCreditBasedConnectionRequest { identifier, .. } => *identifier,
             Self::LeCreditBasedConnectionResponse { identifier, .. } => *identifier,
             Self::LeFlowControlCredit { identifier, .. } => *identifier,
+            Self::EnhancedCreditBasedConnectionRequest { identifier, .. } => *identifier,
+            Self::EnhancedCreditBasedConnectionResponse { identifier, .. } => *identifier,
         }
     }
 
-    /// Parse configuration options from raw bytes
-    fn parse_config_options(data: &[u8]) -> ConfigOptions {
-        let mut options = ConfigOptions::default();
-        let mut offset = 0;
-
-        while offset + 2 <= data.len() {
-            let option_type = data[offset] & 0x7F; // Mask out hint bit
-            let option_length = data[offset + 1];
-
-            // Ensure we have enough data for this option
-            if offset + 2 + option_length as usize > data.len() {
-                break;
-            }
-
-            let option_data = &data[offset + 2..offset + 2 + option_length as usize];
-
-            match option_type {
-                L2CAP_CONF_MTU => {
-                    if option_length == 2 {
-                        let mut cursor = Cursor::new(option_data);
-                        if let Ok(mtu) = cursor.read_u16::<LittleEndian>() {
-                            options.mtu = Some(mtu);
-                        }
-                    }
-                }
-                L2CAP_CONF_FLUSH_TIMEOUT => {
-                    if option_length == 2 {
-                        let mut cursor = Cursor::new(option_data);
-                        if let Ok(timeout) = cursor.read_u16::<LittleEndian>() {
-                            options.flush_timeout = Some(timeout);
-                        }
-                    }
-                }
-                L2CAP_CONF_QOS => {
-                    if option_length == 22 {
-                        // Parse QoS flow spec
-                        let service_type = option_data[0];
-                        let mut cursor = Cursor::new(&option_data[1..]);
-
-                        if let (
-                            Ok(token_rate),
-                            Ok(token_bucket_size),
-                            Ok(peak_bandwidth),
-                            Ok(latency),
-                            Ok(delay_variation),
-                        ) = (
-                            cursor.read_u32::<LittleEndian>(),
-                            cursor.read_u32::<LittleEndian>(),
-                            cursor.read_u32::<LittleEndian>(),
-                            cursor.read_u32::<LittleEndian>(),
-                            cursor.read_u32::<LittleEndian>(),
-                        ) {
-                            options.qos = Some(QosFlowSpec {
-                                service_type,
-                                token_rate,
-                                token_bucket_size,
-                                peak_bandwidth,
-                                latency,
-                                delay_variation,
-                            });
-                        }
-                    }
-                }
-                L2CAP_CONF_RFC => {
-                    if option_length >= 3 {
-                        // Parse retransmission & flow control
-                        let mode = match option_data[0] {
-                            0 => RetransmissionMode::Basic,
-                            1 => RetransmissionMode::Retransmission,
-                            2 => RetransmissionMode::FlowControl,
-                            3 => RetransmissionMode::EnhancedRetransmission,
-                            4 => RetransmissionMode::Streaming,
-                            _ => RetransmissionMode::Basic,
-                        };
-
-                        let mut rfc = RetransmissionFlowControl {
-                            mode,
-                            ..Default::default()
-                        };
-
-                        if option_data.len() >= 9
-                            && (mode == RetransmissionMode::Retransmission
-                                || mode == RetransmissionMode::EnhancedRetransmission)
-                        {
-                            rfc.tx_window_size = option_data[1];
-                            rfc.max_retransmit = option_data[2];
-
-                            let mut cursor = Cursor::new(&option_data[3..]);
-                            if let (Ok(monitor), Ok(retransmit)) = (
-                                cursor.read_u16::<LittleEndian>(),
-                                cursor.read_u16::<LittleEndian>(),
-                            ) {
-                                rfc.monitor_timeout = monitor;
-                                rfc.retransmit_timeout = retransmit;
-                            }
-                        } else if option_data.len() >= 5 && mode == RetransmissionMode::FlowControl
-                        {
-                            rfc.tx_window_size = option_data[1];
-
-                            let mut cursor = Cursor::new(&option_data[2..]);
-                            if let Ok(retransmit) = cursor.read_u16::<LittleEndian>() {
-                                rfc.retransmit_timeout = retransmit;
-                            }
-                        }
-
-                        options.retransmission = Some(rfc);
-                    }
-                }
-                L2CAP_CONF_FCS => {
-                    if option_length == 1 {
-                        options.fcs = Some(option_data[0]);
-                    }
-                }
-                L2CAP_CONF_EXT_WINDOW => {
-                    if option_length == 2 {
-                        let mut cursor = Cursor::new(option_data);
-                        if let Ok(window) = cursor.read_u16::<LittleEndian>() {
-                            options.ext_window_size = Some(window);
-                        }
-                    }
-                }
-                // TODO: Handle other config options
-                _ => {}
-            }
-
-            offset += 2 + option_length as usize;
-        }
-
-        options
-    }
-
-    /// Serialize configuration options to bytes
-    fn serialize_config_options(options: &ConfigOptions) -> Vec<u8> {
-        let mut result = Vec::new();
-
-        // MTU option
-        if let Some(mtu) = options.mtu {
-            result.push(L2CAP_CONF_MTU);
-            result.push(2); // Length
-            result.extend_from_slice(&mtu.to_le_bytes());
-        }
-
-        // Flush timeout option
-        if let Some(timeout) = options.flush_timeout {
-            result.push(L2CAP_CONF_FLUSH_TIMEOUT);
-            result.push(2); // Length
-            result.extend_from_slice(&timeout.to_le_bytes());
-        }
-
-        // QoS option
-        if let Some(qos) = options.qos {
-            result.push(L2CAP_CONF_QOS);
-            result.push(22); // Length
-            result.push(qos.service_type);
-            result.extend_from_slice(&qos.token_rate.to_le_bytes());
-            result.extend_from_slice(&qos.token_bucket_size.to_le_bytes());
-            result.extend_from_slice(&qos.peak_bandwidth.to_le_bytes());
-            result.extend_from_slice(&qos.latency.to_le_bytes());
-            result.extend_from_slice(&qos.delay_variation.to_le_bytes());
-        }
-
-        // Retransmission & flow control option
-        if let Some(rfc) = options.retransmission {
-            result.push(L2CAP_CONF_RFC);
-
-            match rfc.mode {
-                RetransmissionMode::Basic => {
-                    result.push(1); // Length
-                    result.push(0); // Mode
-                }
-                RetransmissionMode::Retransmission | RetransmissionMode::EnhancedRetransmission => {
-                    result.push(9); // Length
-                    result.push(rfc.mode as u8); // Mode
-                    result.push(rfc.tx_window_size);
-                    result.push(rfc.max_retransmit);
-                    result.extend_from_slice(&rfc.monitor_timeout.to_le_bytes());
-                    result.extend_from_slice(&rfc.retransmit_timeout.to_le_bytes());
-                }
-                RetransmissionMode::FlowControl => {
-                    result.push(5); // Length
-                    result.push(2); // Mode
-                    result.push(rfc.tx_window_size);
-                    result.extend_from_slice(&rfc.retransmit_timeout.to_le_bytes());
-                }
-                RetransmissionMode::Streaming => {
-                    result.push(1); // Length
-                    result.push(4); // Mode
-                }
-            }
-        }
-
-        // FCS option
-        if let Some(fcs) = options.fcs {
-            result.push(L2CAP_CONF_FCS);
-            result.push(1); // Length
-            result.push(fcs);
-        }
-
-        // Extended window size option
-        if let Some(window) = options.ext_window_size {
-            result.push(L2CAP_CONF_EXT_WINDOW);
-            result.push(2); // Length
-            result.extend_from_slice(&window.to_le_bytes());
-        }
-
-        // TODO: Add other config options
-
-        result
-    }
-
     /// Parse a signaling message from raw bytes
-    pub fn parse(data: &[u8], is_le: bool) -> Result<Self, L2capError> {
+    pub fn parse(data: &[u8], _is_le: bool) -> Result<Self, L2capError> {
         if data.len() < 4 {
             return Err(L2capError::InvalidParameter(
                 "Signaling data too short".into(),
@@ -531,11 +357,7 @@ impl SignalingMessage {
                     .read_u16::<LittleEndian>()
                     .map_err(|_| L2capError::InvalidParameter("Failed to read flags".into()))?;
 
-                let options = if params.len() > 4 {
-                    Self::parse_config_options(&params[4..])
-                } else {
-                    ConfigOptions::default()
-                };
+                let options = ConfigOptions::parse(&params[4..])?;
 
                 Ok(Self::ConfigureRequest {
                     identifier: cmd_header.identifier,
@@ -567,11 +389,7 @@ impl SignalingMessage {
                     .read_u16::<LittleEndian>()
                     .map_err(|_| L2capError::InvalidParameter("Failed to read result".into()))?;
 
-                let options = if params.len() > 6 {
-                    Self::parse_config_options(&params[6..])
-                } else {
-                    ConfigOptions::default()
-                };
+                let options = ConfigOptions::parse(&params[6..])?;
 
                 Ok(Self::ConfigureResponse {
                     identifier: cmd_header.identifier,
@@ -630,6 +448,98 @@ impl SignalingMessage {
                 })
             }
 
+            L2CAP_INFORMATION_REQUEST => {
+                if params.len() < 2 {
+                    return Err(L2capError::InvalidParameter(
+                        "Information request parameters too short".into(),
+                    ));
+                }
+
+                Ok(Self::InformationRequest {
+                    identifier: cmd_header.identifier,
+                    info_type: u16::from_le_bytes([params[0], params[1]]),
+                })
+            }
+
+            L2CAP_INFORMATION_RESPONSE => {
+                if cmd_header.length < 4 {
+                    return Err(L2capError::InvalidParameter(
+                        "Information response parameters too short".into(),
+                    ));
+                }
+
+                Ok(Self::InformationResponse {
+                    identifier: cmd_header.identifier,
+                    info_type: u16::from_le_bytes([params[0], params[1]]),
+                    result: u16::from_le_bytes([params[2], params[3]]),
+                    data: params[4..cmd_header.length as usize].to_vec(),
+                })
+            }
+
+            L2CAP_ECHO_REQUEST => Ok(Self::EchoRequest {
+                identifier: cmd_header.identifier,
+                data: params[..cmd_header.length as usize].to_vec(),
+            }),
+
+            L2CAP_ECHO_RESPONSE => Ok(Self::EchoResponse {
+                identifier: cmd_header.identifier,
+                data: params[..cmd_header.length as usize].to_vec(),
+            }),
+
+            L2CAP_CREDIT_BASED_CONNECTION_REQUEST => {
+                let length = cmd_header.length as usize;
+                if length < 10 || !(length - 8).is_multiple_of(2) {
+                    return Err(L2capError::InvalidParameter(
+                        "Enhanced credit based connection request length invalid".into(),
+                    ));
+                }
+
+                let source_cids = read_cid_list(&params[8..length])?;
+
+                Ok(Self::EnhancedCreditBasedConnectionRequest {
+                    identifier: cmd_header.identifier,
+                    spsm: u16::from_le_bytes([params[0], params[1]]),
+                    mtu: u16::from_le_bytes([params[2], params[3]]),
+                    mps: u16::from_le_bytes([params[4], params[5]]),
+                    initial_credits: u16::from_le_bytes([params[6], params[7]]),
+                    source_cids,
+                })
+            }
+
+            L2CAP_CREDIT_BASED_CONNECTION_RESPONSE => {
+                let length = cmd_header.length as usize;
+                if length < 8 || !(length - 8).is_multiple_of(2) {
+                    return Err(L2capError::InvalidParameter(
+                        "Enhanced credit based connection response length invalid".into(),
+                    ));
+                }
+
+                let destination_cids = read_cid_list(&params[8..length])?;
+
+                Ok(Self::EnhancedCreditBasedConnectionResponse {
+                    identifier: cmd_header.identifier,
+                    mtu: u16::from_le_bytes([params[0], params[1]]),
+                    mps: u16::from_le_bytes([params[2], params[3]]),
+                    initial_credits: u16::from_le_bytes([params[4], params[5]]),
+                    result: u16::from_le_bytes([params[6], params[7]]),
+                    destination_cids,
+                })
+            }
+
+            L2CAP_LE_FLOW_CONTROL_CREDIT => {
+                if cmd_header.length < 4 {
+                    return Err(L2capError::InvalidParameter(
+                        "LE flow control credit parameters too short".into(),
+                    ));
+                }
+
+                Ok(Self::LeFlowControlCredit {
+                    identifier: cmd_header.identifier,
+                    cid: u16::from_le_bytes([params[0], params[1]]),
+                    credits: u16::from_le_bytes([params[2], params[3]]),
+                })
+            }
+
             // More message types to implement...
             // TODO: Implement remaining message parsing
             _ => Err(L2capError::NotSupported),
@@ -679,7 +589,7 @@ impl SignalingMessage {
                 options,
                 ..
             } => {
-                let option_bytes = Self::serialize_config_options(options);
+                let option_bytes = options.serialize();
                 let mut params = Vec::with_capacity(4 + option_bytes.len());
                 params.extend_from_slice(&destination_cid.to_le_bytes());
                 params.extend_from_slice(&flags.to_le_bytes());
@@ -694,7 +604,7 @@ impl SignalingMessage {
                 options,
                 ..
             } => {
-                let option_bytes = Self::serialize_config_options(options);
+                let option_bytes = options.serialize();
                 let mut params = Vec::with_capacity(6 + option_bytes.len());
                 params.extend_from_slice(&source_cid.to_le_bytes());
                 params.extend_from_slice(&flags.to_le_bytes());
@@ -751,9 +661,9 @@ impl SignalingMessage {
                 ..
             } => {
                 let mut params = Vec::with_capacity(8);
-                params.extend_from_slice(&conn_params.conn_interval_min.to_le_bytes());
-                params.extend_from_slice(&conn_params.conn_interval_max.to_le_bytes());
-                params.extend_from_slice(&conn_params.conn_latency.to_le_bytes());
+                params.extend_from_slice(&conn_params.interval_min.to_le_bytes());
+                params.extend_from_slice(&conn_params.interval_max.to_le_bytes());
+                params.extend_from_slice(&conn_params.peripheral_latency.to_le_bytes());
                 params.extend_from_slice(&conn_params.supervision_timeout.to_le_bytes());
                 params
             }
@@ -804,6 +714,44 @@ impl SignalingMessage {
                 params.extend_from_slice(&credits.to_le_bytes());
                 params
             }
+
+            Self::EnhancedCreditBasedConnectionRequest {
+                spsm,
+                mtu,
+                mps,
+                initial_credits,
+                source_cids,
+                ..
+            } => {
+                let mut params = Vec::with_capacity(8 + 2 * source_cids.len());
+                params.extend_from_slice(&spsm.to_le_bytes());
+                params.extend_from_slice(&mtu.to_le_bytes());
+                params.extend_from_slice(&mps.to_le_bytes());
+                params.extend_from_slice(&initial_credits.to_le_bytes());
+                for cid in source_cids {
+                    params.extend_from_slice(&cid.to_le_bytes());
+                }
+                params
+            }
+
+            Self::EnhancedCreditBasedConnectionResponse {
+                mtu,
+                mps,
+                initial_credits,
+                result,
+                destination_cids,
+                ..
+            } => {
+                let mut params = Vec::with_capacity(8 + 2 * destination_cids.len());
+                params.extend_from_slice(&mtu.to_le_bytes());
+                params.extend_from_slice(&mps.to_le_bytes());
+                params.extend_from_slice(&initial_credits.to_le_bytes());
+                params.extend_from_slice(&result.to_le_bytes());
+                for cid in destination_cids {
+                    params.extend_from_slice(&cid.to_le_bytes());
+                }
+                params
+            }
         };
 
         let length = params.len() as u16;
@@ -828,3 +776,20 @@ impl SignalingMessage {
         L2capPacket::new(channel_id, payload)
     }
 }
+
+/// Read the CID list of an Enhanced Credit Based Connection PDU
+fn read_cid_list(data: &[u8]) -> Result<Vec<u16>, L2capError> {
+    let cids: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|cid| u16::from_le_bytes([cid[0], cid[1]]))
+        .collect();
+
+    if cids.len() > L2CAP_CREDIT_BASED_MAX_CHANNELS {
+        return Err(L2capError::InvalidParameter(format!(
+            "Too many channels in credit based connection: {}",
+            cids.len()
+        )));
+    }
+
+    Ok(cids)
+}
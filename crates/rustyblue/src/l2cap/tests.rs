@@ -1,6 +1,7 @@
 //! Tests for the L2CAP implementation
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use super::super::channel::*;
     use super::super::constants::*;
@@ -9,8 +10,10 @@ mod tests {
     use super::super::psm::*;
     use super::super::signaling::*;
     use super::super::types::*;
-    use super::super::*;
+
+    use crate::testing::MockL2capManager;
     use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[test]
     fn test_psm_value_conversion() {
@@ -52,14 +55,31 @@ mod tests {
 
         // Should be odd values in the dynamic range (0x1001-0xFFFF)
         assert!(psm1.value() >= 0x1001);
-        assert!(psm1.value() <= 0xFFFF);
         assert_eq!(psm1.value() % 2, 1); // Odd value
 
         assert!(psm2.value() >= 0x1001);
-        assert!(psm2.value() <= 0xFFFF);
         assert_eq!(psm2.value() % 2, 1); // Odd value
     }
 
+    #[test]
+    fn test_assigned_psm_values() {
+        assert_eq!(PSM::from_value(0x001D), Some(PSM::UDI_C_PLANE));
+        assert_eq!(PSM::from_value(0x0023), Some(PSM::IPSP));
+        assert_eq!(PSM::from_value(0x0025), Some(PSM::OTS));
+        assert_eq!(PSM::from_value(0x0027), Some(PSM::EATT));
+        assert_eq!(PSM::EATT.value(), 0x0027);
+        assert_eq!(PSM::IPSP.to_string(), "IPSP (0x0023)");
+
+        // LE dynamic SPSMs are 0x0080-0x00FF
+        assert_eq!(PSM::from_value(0x0081), Some(PSM::Dynamic(0x0081)));
+        assert!(PSM::Dynamic(0x00FF).is_valid());
+
+        // The low bit of the upper octet must be zero
+        assert_eq!(PSM::from_value(0x1101), None);
+        assert!(!PSM::Dynamic(0x1101).is_valid());
+        assert!(PSM::Dynamic(0x1201).is_valid());
+    }
+
     #[test]
     fn test_l2cap_header() {
         // Create a header
@@ -116,14 +136,14 @@ mod tests {
 
         // Check control field
         let control = packet.control.unwrap();
-        assert_eq!(control.frame_type, false); // I-frame
+        assert!(!control.frame_type); // I-frame
         assert_eq!(control.tx_seq, 5);
         assert_eq!(control.req_seq, 10);
-        assert_eq!(control.poll, false);
+        assert!(!control.poll);
 
         // Serialize and parse
         let bytes = packet.to_bytes();
-        let parsed = L2capPacket::parse(&bytes).unwrap();
+        let parsed = L2capPacket::parse_with_control(&bytes).unwrap();
 
         // Check parsed values
         assert_eq!(parsed.header.length, 6);
@@ -132,10 +152,10 @@ mod tests {
 
         // Check parsed control field
         let parsed_control = parsed.control.unwrap();
-        assert_eq!(parsed_control.frame_type, false); // I-frame
+        assert!(!parsed_control.frame_type); // I-frame
         assert_eq!(parsed_control.tx_seq, 5);
         assert_eq!(parsed_control.req_seq, 10);
-        assert_eq!(parsed_control.poll, false);
+        assert!(!parsed_control.poll);
     }
 
     #[test]
@@ -175,8 +195,10 @@ mod tests {
     #[test]
     fn test_signaling_message_config_request() {
         // Create a config request
-        let mut options = ConfigOptions::default();
-        options.mtu = Some(128);
+        let options = ConfigOptions {
+            mtu: Some(128),
+            ..Default::default()
+        };
 
         let request = SignalingMessage::ConfigureRequest {
             identifier: 2,
@@ -212,6 +234,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_config_options_tlv() {
+        let options = ConfigOptions {
+            mtu: Some(672),
+            flush_timeout: Some(0xFFFF),
+            retransmission: Some(RetransmissionFlowControl {
+                mode: RetransmissionMode::EnhancedRetransmission,
+                tx_window_size: 10,
+                max_retransmit: 3,
+                retransmit_timeout: 2000,
+                monitor_timeout: 12000,
+                max_pdu_size: 1010,
+            }),
+            fcs: Some(0x00),
+            ..Default::default()
+        };
+
+        let bytes = options.serialize();
+        assert_eq!(&bytes[0..4], &[L2CAP_CONF_MTU, 2, 0xA0, 0x02]);
+        assert_eq!(&bytes[4..8], &[L2CAP_CONF_FLUSH_TIMEOUT, 2, 0xFF, 0xFF]);
+        assert_eq!(
+            &bytes[8..19],
+            &[
+                L2CAP_CONF_RFC,
+                9,
+                0x03,
+                10,
+                3,
+                0xD0,
+                0x07,
+                0xE0,
+                0x2E,
+                0xF2,
+                0x03
+            ]
+        );
+        assert_eq!(&bytes[19..], &[L2CAP_CONF_FCS, 1, 0x00]);
+
+        let parsed = ConfigOptions::parse(&bytes).unwrap();
+        assert_eq!(parsed.mtu, Some(672));
+        assert_eq!(parsed.flush_timeout, Some(0xFFFF));
+        assert_eq!(parsed.fcs, Some(0x00));
+        let rfc = parsed.retransmission.unwrap();
+        assert_eq!(rfc.mode, RetransmissionMode::EnhancedRetransmission);
+        assert_eq!(rfc.retransmit_timeout, 2000);
+        assert_eq!(rfc.monitor_timeout, 12000);
+        assert_eq!(rfc.max_pdu_size, 1010);
+        assert!(parsed.qos.is_none());
+
+        // Unknown hint options are skipped
+        let parsed =
+            ConfigOptions::parse(&[0x80 | 0x7E, 1, 0x00, L2CAP_CONF_MTU, 2, 0x40, 0x00]).unwrap();
+        assert_eq!(parsed.mtu, Some(64));
+
+        // Truncated and malformed options are rejected
+        assert!(ConfigOptions::parse(&[L2CAP_CONF_MTU, 2, 0x40]).is_err());
+        assert!(ConfigOptions::parse(&[L2CAP_CONF_MTU, 1, 0x40]).is_err());
+    }
+
     #[test]
     fn test_l2cap_channel() {
         // Create a channel
@@ -240,6 +321,16 @@ mod tests {
         channel.set_remote_mtu(128);
         assert_eq!(channel.remote_mtu(), 128);
         assert_eq!(channel.effective_mtu(), 128); // Min of local and remote
+
+        // Outbound data is limited to the remote MTU
+        assert!(channel.create_data_packet(&[0u8; 128]).is_ok());
+        match channel.create_data_packet(&[0u8; 129]) {
+            Err(L2capError::MtuExceeded { requested, allowed }) => {
+                assert_eq!(requested, 129);
+                assert_eq!(allowed, 128);
+            }
+            other => panic!("Expected MtuExceeded, got {:?}", other),
+        }
     }
 
     #[test]
@@ -265,7 +356,7 @@ mod tests {
             PSM::RFCOMM,
             Some(data_callback),
             Some(event_callback),
-            policy,
+            policy.clone(),
         );
         assert!(result.is_ok());
 
@@ -327,10 +418,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_connection_closed_callback() {
+        let manager = Arc::new(L2capManager::new(ConnectionType::LE));
+        let addr = crate::gap::BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        manager.register_connection(0x0040, addr);
+
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let closed_clone = closed.clone();
+        let manager_weak = Arc::downgrade(&manager);
+        manager.set_connection_closed_callback(Box::new(move |hci_handle| {
+            // The peer address is still known while callbacks run
+            let peer = manager_weak.upgrade().unwrap().peer_address(hci_handle);
+            closed_clone.lock().unwrap().push((hci_handle, peer));
+        }));
+
+        manager.handle_connection_closed(0x0040).unwrap();
+
+        assert_eq!(*closed.lock().unwrap(), vec![(0x0040, Some(addr))]);
+        assert_eq!(manager.peer_address(0x0040), None);
+    }
+
     #[test]
     fn test_l2cap_integration() {
         // Create a manager
-        let manager = L2capManager::new(ConnectionType::Classic);
+        let mock = MockL2capManager::new(ConnectionType::Classic).unwrap();
+        let manager = mock.manager();
 
         // Create a mock connection
         let conn = MockConnection::new(&manager, PSM::RFCOMM);
@@ -355,10 +468,902 @@ mod tests {
         let result = manager.disconnect(conn.local_cid);
         assert!(result.is_ok());
 
-        // Channel should be removed
+        // The channel stays until the peer answers the disconnection request
         {
             let channels = manager.channels.read().unwrap();
-            assert!(!channels.contains_key(&conn.local_cid));
+            let channel = channels.get(&conn.local_cid).unwrap();
+            assert_eq!(channel.state(), L2capChannelState::WaitDisconnect);
+        }
+    }
+
+    #[test]
+    fn test_connection_parameter_update_validation() {
+        let params = ConnectionParameterUpdate {
+            interval_min: 0x0018,
+            interval_max: 0x0028,
+            peripheral_latency: 4,
+            supervision_timeout: 0x0064,
+        };
+        assert!(params.validate());
+        assert_eq!(params.validation_error(), None);
+
+        let invalid = [
+            ConnectionParameterUpdate {
+                interval_min: 0x0005,
+                ..params
+            },
+            ConnectionParameterUpdate {
+                interval_max: 0x0C81,
+                ..params
+            },
+            ConnectionParameterUpdate {
+                interval_min: 0x0030,
+                ..params
+            },
+            ConnectionParameterUpdate {
+                peripheral_latency: 0x01F4,
+                ..params
+            },
+            ConnectionParameterUpdate {
+                supervision_timeout: 0x0009,
+                ..params
+            },
+            // (1 + 4) * 40 * 1.25 ms * 2 = 500 ms, which must be exceeded
+            ConnectionParameterUpdate {
+                supervision_timeout: 50,
+                ..params
+            },
+        ];
+        for params in invalid {
+            assert!(!params.validate());
+            assert!(params.validation_error().is_some());
+        }
+
+        assert!(ConnectionParameterUpdate {
+            supervision_timeout: 51,
+            ..params
+        }
+        .validate());
+    }
+
+    #[test]
+    fn test_flow_control_mode_negotiation() {
+        let mut channel = L2capChannel::new_dynamic(0x0040, PSM::RFCOMM, ConnectionType::Classic);
+        channel.set_state(L2capChannelState::WaitConfig);
+        let ertm = FlowControlMode::Enhanced(ErtmConfig::default());
+        channel.set_flow_control_mode(ertm).unwrap();
+        assert_eq!(
+            channel.config().retransmission.unwrap().mode,
+            RetransmissionMode::EnhancedRetransmission
+        );
+
+        let request = |mode: RetransmissionMode| ConfigOptions {
+            retransmission: Some(RetransmissionFlowControl {
+                mode,
+                ..RetransmissionFlowControl::default()
+            }),
+            ..ConfigOptions::default()
+        };
+
+        // Streaming wasn't requested locally, so Basic is suggested instead
+        let (result, response) = channel
+            .configure(&request(RetransmissionMode::Streaming))
+            .unwrap();
+        assert_eq!(result, ConfigureResult::UnacceptableParameters);
+        assert_eq!(
+            response.retransmission.unwrap().mode,
+            RetransmissionMode::Basic
+        );
+
+        let (result, _) = channel
+            .configure(&request(RetransmissionMode::FlowControl))
+            .unwrap();
+        assert_eq!(result, ConfigureResult::UnacceptableParameters);
+
+        let (result, response) = channel
+            .configure(&request(RetransmissionMode::EnhancedRetransmission))
+            .unwrap();
+        assert_eq!(result, ConfigureResult::Success);
+        assert!(response.retransmission.is_some());
+        assert!(channel.uses_retransmission());
+
+        let mut le_channel = L2capChannel::new_dynamic(0x0041, PSM::RFCOMM, ConnectionType::LE);
+        assert!(le_channel.set_flow_control_mode(ertm).is_err());
+    }
+
+    const ALL_CHANNEL_STATES: [L2capChannelState; 7] = [
+        L2capChannelState::Closed,
+        L2capChannelState::WaitConnectRsp,
+        L2capChannelState::WaitConfig,
+        L2capChannelState::WaitConfigReq,
+        L2capChannelState::WaitFinalConfig,
+        L2capChannelState::Open,
+        L2capChannelState::WaitDisconnect,
+    ];
+
+    /// Check that data can only be sent while the channel is open
+    fn assert_data_only_when_open(channel: &mut L2capChannel) {
+        for state in ALL_CHANNEL_STATES {
+            channel.set_state(state);
+            let result = channel.create_data_packet(&[0x01, 0x02]);
+            if state == L2capChannelState::Open {
+                assert!(result.is_ok(), "sending failed in state {}", state);
+            } else {
+                assert!(
+                    matches!(result, Err(L2capError::InvalidState)),
+                    "sending allowed in state {}",
+                    state
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_classic_channel_state_transitions() {
+        let mut channel = L2capChannel::new_dynamic(0x0040, PSM::RFCOMM, ConnectionType::Classic);
+        assert_eq!(channel.state(), L2capChannelState::Closed);
+        channel.set_remote_cid(0x0050);
+        assert_data_only_when_open(&mut channel);
+
+        // Configuration is only exchanged once the channel is connected
+        for state in ALL_CHANNEL_STATES {
+            channel.set_state(state);
+            let result = channel.configure(&ConfigOptions::default());
+            match state {
+                L2capChannelState::Closed
+                | L2capChannelState::WaitConnectRsp
+                | L2capChannelState::WaitDisconnect => {
+                    assert!(
+                        matches!(result, Err(L2capError::InvalidState)),
+                        "configure allowed in state {}",
+                        state
+                    );
+                }
+                _ => assert_eq!(result.unwrap().0, ConfigureResult::Success),
+            }
+        }
+
+        // Closed -> WaitConnectRsp -> WaitConfig -> WaitConfigReq -> Open
+        // -> WaitDisconnect -> Closed
+        channel.set_state(L2capChannelState::Closed);
+        channel.set_state(L2capChannelState::WaitConnectRsp);
+        assert!(channel.create_data_packet(&[0x01]).is_err());
+        channel.set_state(L2capChannelState::WaitConfig);
+        let options = ConfigOptions {
+            mtu: Some(100),
+            ..ConfigOptions::default()
+        };
+        channel.configure(&options).unwrap();
+        assert_eq!(channel.remote_mtu(), 100);
+        channel.set_state(L2capChannelState::WaitConfigReq);
+        channel.set_state(L2capChannelState::Open);
+        assert!(channel.create_data_packet(&[0x01]).is_ok());
+        assert!(matches!(
+            channel.create_data_packet(&[0; 101]),
+            Err(L2capError::MtuExceeded { .. })
+        ));
+        channel.set_state(L2capChannelState::WaitDisconnect);
+        assert!(channel.create_data_packet(&[0x01]).is_err());
+        channel.set_state(L2capChannelState::Closed);
+        assert!(channel.create_data_packet(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_le_credit_based_channel_state_transitions() {
+        let mut channel = L2capChannel::new_le_credit_based(
+            0x0040,
+            PSM::Dynamic(0x0081),
+            LeCreditBasedConfig::default(),
+        );
+        assert_eq!(channel.state(), L2capChannelState::Closed);
+        channel.set_remote_cid(0x0041);
+        channel.add_credits(1).unwrap();
+        assert_data_only_when_open(&mut channel);
+
+        // LE channels never go through configuration
+        for state in ALL_CHANNEL_STATES {
+            channel.set_state(state);
+            assert!(matches!(
+                channel.configure(&ConfigOptions::default()),
+                Err(L2capError::NotSupported)
+            ));
+        }
+
+        // Closed -> WaitConnectRsp -> Open -> WaitDisconnect -> Closed
+        channel.set_state(L2capChannelState::Closed);
+        channel.set_state(L2capChannelState::WaitConnectRsp);
+        assert!(channel.create_data_packet(&[0x01]).is_err());
+        channel.set_state(L2capChannelState::Open);
+        assert!(channel.create_data_packet(&[0x01]).is_ok());
+
+        // An open channel still needs credits
+        channel.consume_credits(1).unwrap();
+        assert!(matches!(
+            channel.create_data_packet(&[0x01]),
+            Err(L2capError::ResourceLimitReached)
+        ));
+
+        channel.set_state(L2capChannelState::WaitDisconnect);
+        assert!(matches!(
+            channel.create_data_packet(&[0x01]),
+            Err(L2capError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn test_configure_rejected_on_le() {
+        // LE Credit-based channels have no configuration procedure
+        let mut channel = L2capChannel::new_le_credit_based(
+            0x0040,
+            PSM::Dynamic(0x0081),
+            LeCreditBasedConfig::default(),
+        );
+        assert!(matches!(
+            channel.configure(&ConfigOptions::default()),
+            Err(L2capError::NotSupported)
+        ));
+
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        let manager = mock.manager();
+        let local_cid = manager.connect(PSM::Dynamic(0x0081), 0x0040).unwrap();
+        assert!(matches!(
+            manager.configure(local_cid, ConfigOptions::default()),
+            Err(L2capError::NotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_extended_features_cached_per_connection() {
+        let mock = MockL2capManager::new(ConnectionType::Classic).unwrap();
+        let manager = mock.manager();
+        manager.request_extended_features(0x0040).unwrap();
+        assert_eq!(manager.supported_features(0x0040), None);
+
+        // The first request uses signaling identifier 1
+        let mask = L2CAP_FEATURE_ENHANCED_RETRANSMISSION
+            | L2CAP_FEATURE_STREAMING
+            | L2CAP_FEATURE_FCS
+            | L2CAP_FEATURE_FIXED_CHANNELS;
+        let response = SignalingMessage::InformationResponse {
+            identifier: 1,
+            info_type: L2CAP_EXTENDED_FEATURES,
+            result: L2CAP_INFO_SUCCESS,
+            data: mask.to_le_bytes().to_vec(),
+        };
+        manager
+            .handle_packet(response.to_packet(false), 0x0040)
+            .unwrap();
+
+        let features = manager.supported_features(0x0040).unwrap();
+        assert!(features.supports_ertm());
+        assert!(features.supports_streaming_mode());
+        assert!(features.supports_fcs());
+        assert!(features.supports_fixed_channels());
+        assert!(!features.supports_retransmission_mode());
+        assert!(!features.supports_l2cap_flow_control());
+        assert!(!features.supports_bidirectional_qos());
+        assert_eq!(manager.supported_features(0x0041), None);
+
+        manager.handle_connection_closed(0x0040).unwrap();
+        assert_eq!(manager.supported_features(0x0040), None);
+    }
+
+    #[test]
+    fn test_le_credit_based_queue_without_credits() {
+        let mut channel = L2capChannel::new_le_credit_based(
+            0x0040,
+            PSM::Dynamic(0x0081),
+            LeCreditBasedConfig::default(),
+        );
+        channel.set_remote_cid(0x0041);
+        channel.set_state(L2capChannelState::Open);
+
+        assert_eq!(channel.remote_credits(), 0);
+        assert!(channel.must_queue());
+        assert_eq!(channel.max_pending_sdus(), L2CAP_DEFAULT_MAX_PENDING_SDUS);
+
+        channel.set_max_pending_sdus(2);
+        channel.queue_sdu(&[1]).unwrap();
+        channel.queue_sdu(&[2]).unwrap();
+        assert!(matches!(
+            channel.queue_sdu(&[3]),
+            Err(L2capError::QueueFull)
+        ));
+        assert_eq!(channel.pending_sdus(), 2);
+
+        // Queued SDUs keep later data waiting even once credits arrive
+        channel.add_credits(1).unwrap();
+        assert!(channel.must_queue());
+
+        // Basic channels never queue
+        let mut basic =
+            L2capChannel::new_dynamic(0x0042, PSM::Dynamic(0x1001), ConnectionType::Classic);
+        assert!(!basic.must_queue());
+        assert!(matches!(
+            basic.queue_sdu(&[1]),
+            Err(L2capError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn test_le_credit_based_k_frames() {
+        let mut channel = L2capChannel::new_le_credit_based(
+            0x0040,
+            PSM::Dynamic(0x0081),
+            LeCreditBasedConfig {
+                mtu: 10,
+                mps: 4,
+                initial_credits: 4,
+            },
+        );
+        channel.set_remote_cid(0x0041);
+        channel.set_remote_mps(5);
+        channel.set_state(L2capChannelState::Open);
+
+        // The first K-frame carries the SDU length, each K-frame uses a credit
+        channel.add_credits(2).unwrap();
+        channel.queue_sdu(&[1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        let frames = channel.take_sendable_frames().unwrap();
+        let payloads: Vec<_> = frames.iter().map(|frame| frame.payload.clone()).collect();
+        assert_eq!(payloads, vec![vec![9, 0, 1, 2, 3], vec![4, 5, 6, 7, 8]]);
+        assert!(frames.iter().all(|frame| frame.header.channel_id == 0x0041));
+        assert_eq!(channel.remote_credits(), 0);
+        assert!(channel.must_queue());
+
+        channel.add_credits(1).unwrap();
+        let frames = channel.take_sendable_frames().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].payload, vec![9]);
+        assert_eq!(channel.pending_sdus(), 0);
+
+        // Received K-frames are reassembled up to the SDU length
+        assert_eq!(channel.reassemble_k_frame(&[5, 0, 1, 2]).unwrap(), None);
+        assert_eq!(channel.take_returned_credits(), None);
+        assert_eq!(channel.reassemble_k_frame(&[3, 4]).unwrap(), None);
+        assert_eq!(
+            channel.reassemble_k_frame(&[5]).unwrap(),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+
+        // Credits are returned once the peer has used half of them
+        assert_eq!(channel.take_returned_credits(), Some(3));
+        assert_eq!(channel.take_returned_credits(), None);
+
+        assert!(matches!(
+            channel.reassemble_k_frame(&[11, 0, 1]),
+            Err(L2capError::MtuExceeded {
+                requested: 11,
+                allowed: 10
+            })
+        ));
+        assert!(matches!(
+            channel.reassemble_k_frame(&[1, 0, 1, 2, 3]),
+            Err(L2capError::MtuExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_le_credit_based_drain_pending() {
+        use crate::testing::MockHciSocket;
+
+        let hci = MockHciSocket::new().unwrap();
+        let mut channel = L2capChannel::new_le_credit_based(
+            0x0040,
+            PSM::Dynamic(0x0081),
+            LeCreditBasedConfig {
+                mtu: 10,
+                mps: 4,
+                initial_credits: 4,
+            },
+        );
+        channel.set_remote_cid(0x0041);
+        channel.set_remote_mps(5);
+        channel.set_state(L2capChannelState::Open);
+
+        // Nothing is sent without credits
+        channel.queue_sdu(&[1, 2, 3]).unwrap();
+        assert_eq!(channel.drain_pending(&hci.socket(), 0x0040).unwrap(), 0);
+        assert!(hci.sent_acl_packets().is_empty());
+
+        channel.add_credits(1).unwrap();
+        assert_eq!(channel.drain_pending(&hci.socket(), 0x0040).unwrap(), 1);
+        assert_eq!(
+            hci.sent_acl_packets(),
+            vec![(0x0040, vec![0x05, 0x00, 0x41, 0x00, 3, 0, 1, 2, 3])]
+        );
+        assert_eq!(channel.pending_sdus(), 0);
+    }
+    #[test]
+    fn test_le_credit_based_credits_returned() {
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        let manager = mock.manager();
+        let local_cid = manager.connect_enhanced(PSM::EATT, 0x0040, 1).unwrap()[0];
+        let response = SignalingMessage::EnhancedCreditBasedConnectionResponse {
+            identifier: 1,
+            mtu: 128,
+            mps: 64,
+            initial_credits: 5,
+            result: L2CAP_RESULT_SUCCESS,
+            destination_cids: vec![0x0080],
+        };
+        manager
+            .handle_packet(response.to_packet(true), 0x0040)
+            .unwrap();
+
+        // SDUs are segmented to the peer's MPS
+        manager.send_data(local_cid, &[0xAB; 100]).unwrap();
+        let sent = mock.sent_pdus(0x0040, 0x0080);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0][..2], [100, 0]);
+        assert_eq!(sent[0].len(), 64);
+        assert_eq!(sent[1], vec![0xAB; 38]);
+
+        // Credits go back to the peer once it has used half of them
+        let signaling_pdus = mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID).len();
+        let granted = LeCreditBasedConfig::enhanced().initial_credits;
+        for _ in 0..granted / 2 {
+            mock.inject(0x0040, local_cid, &[0x01, 0x00, 0x0A]).unwrap();
+        }
+        let sent = mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID);
+        assert_eq!(sent.len(), signaling_pdus + 1);
+        match SignalingMessage::parse(sent.last().unwrap(), true).unwrap() {
+            SignalingMessage::LeFlowControlCredit { cid, credits, .. } => {
+                assert_eq!(cid, local_cid);
+                assert_eq!(credits, granted / 2);
+            }
+            other => panic!("unexpected signaling message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_signaling_message_echo() {
+        let request = SignalingMessage::EchoRequest {
+            identifier: 7,
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(request.command_code(), L2CAP_ECHO_REQUEST);
+
+        match SignalingMessage::parse(&request.serialize(), false).unwrap() {
+            SignalingMessage::EchoRequest { identifier, data } => {
+                assert_eq!(identifier, 7);
+                assert_eq!(data, vec![1, 2, 3]);
+            }
+            parsed => panic!("Expected EchoRequest, got {:?}", parsed),
+        }
+
+        let response = SignalingMessage::EchoResponse {
+            identifier: 7,
+            data: Vec::new(),
+        };
+        match SignalingMessage::parse(&response.serialize(), false).unwrap() {
+            SignalingMessage::EchoResponse { identifier, data } => {
+                assert_eq!(identifier, 7);
+                assert!(data.is_empty());
+            }
+            parsed => panic!("Expected EchoResponse, got {:?}", parsed),
+        }
+    }
+
+    #[test]
+    fn test_measure_rtt_requirements() {
+        let le_manager = L2capManager::new(ConnectionType::LE);
+        assert!(matches!(
+            le_manager.measure_rtt(0x0001, 4),
+            Err(L2capError::NotSupported)
+        ));
+
+        // Without an HCI socket the request could never be answered
+        let manager = L2capManager::new(ConnectionType::Classic);
+        assert!(matches!(
+            manager.measure_rtt(0x0001, 4),
+            Err(L2capError::NotConnected)
+        ));
+        assert!(matches!(
+            manager.measure_rtt_stats(0x0001, 4, 0),
+            Err(L2capError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_enhanced_credit_based_signaling() {
+        let request = SignalingMessage::EnhancedCreditBasedConnectionRequest {
+            identifier: 3,
+            spsm: PSM::EATT.value(),
+            mtu: 64,
+            mps: 64,
+            initial_credits: 10,
+            source_cids: vec![0x0040, 0x0041],
+        };
+        let bytes = request.serialize();
+        assert_eq!(bytes[0], L2CAP_CREDIT_BASED_CONNECTION_REQUEST);
+        assert_eq!(bytes.len(), 4 + 8 + 4);
+
+        match SignalingMessage::parse(&bytes, true).unwrap() {
+            SignalingMessage::EnhancedCreditBasedConnectionRequest {
+                identifier,
+                spsm,
+                source_cids,
+                ..
+            } => {
+                assert_eq!(identifier, 3);
+                assert_eq!(spsm, 0x0027);
+                assert_eq!(source_cids, vec![0x0040, 0x0041]);
+            }
+            _ => panic!("Parsed wrong message type"),
+        }
+
+        // At most five channels fit in one request
+        let too_many = SignalingMessage::EnhancedCreditBasedConnectionRequest {
+            identifier: 4,
+            spsm: PSM::EATT.value(),
+            mtu: 64,
+            mps: 64,
+            initial_credits: 10,
+            source_cids: vec![0x0040; 6],
+        };
+        assert!(SignalingMessage::parse(&too_many.serialize(), true).is_err());
+    }
+
+    #[test]
+    fn test_connect_enhanced() {
+        let classic = L2capManager::new(ConnectionType::Classic);
+        assert!(matches!(
+            classic.connect_enhanced(PSM::EATT, 0x0040, 2),
+            Err(L2capError::NotSupported)
+        ));
+
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        let manager = mock.manager();
+        assert!(manager.connect_enhanced(PSM::EATT, 0x0040, 0).is_err());
+        assert!(manager.connect_enhanced(PSM::EATT, 0x0040, 6).is_err());
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        manager.set_global_event_callback(move |event| {
+            events_clone.lock().unwrap().push(event);
+            Ok(())
+        });
+
+        // The first request uses signaling identifier 1
+        let local_cids = manager.connect_enhanced(PSM::EATT, 0x0040, 3).unwrap();
+        assert_eq!(local_cids.len(), 3);
+
+        // The peer accepts all but the second channel
+        let response = SignalingMessage::EnhancedCreditBasedConnectionResponse {
+            identifier: 1,
+            mtu: 128,
+            mps: 64,
+            initial_credits: 5,
+            result: L2CAP_RESULT_REFUSED_NO_RESOURCES,
+            destination_cids: vec![0x0080, 0x0000, 0x0082],
+        };
+        manager
+            .handle_packet(response.to_packet(true), 0x0040)
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        let connected: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ChannelEvent::Connected { cid, .. } => Some(*cid),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(connected, vec![local_cids[0], local_cids[2]]);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ChannelEvent::Disconnected { cid, .. } if *cid == local_cids[1]
+        )));
+
+        // Only the opened channels remain
+        assert!(manager.send_data(local_cids[0], &[1]).is_ok());
+        assert!(matches!(
+            manager.send_data(local_cids[1], &[1]),
+            Err(L2capError::NotConnected)
+        ));
+    }
+
+    #[test]
+    fn test_enhanced_credit_based_connection_request_handled() {
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        let manager = mock.manager();
+        let addr = crate::gap::BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        mock.connect(0x0040, addr);
+        for (psm, auto_accept) in [(PSM::EATT, true), (PSM::Dynamic(0x0081), false)] {
+            let policy = ConnectionPolicy {
+                min_security_level: SecurityLevel::None,
+                authorization_required: false,
+                auto_accept,
+            };
+            manager.register_psm(psm, None, None, policy).unwrap();
+        }
+
+        let request = |identifier, spsm, source_cids| {
+            SignalingMessage::EnhancedCreditBasedConnectionRequest {
+                identifier,
+                spsm,
+                mtu: 100,
+                mps: 64,
+                initial_credits: 2,
+                source_cids,
+            }
+            .serialize()
+        };
+        let response = |index: usize| {
+            let pdu = &mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID)[index];
+            match SignalingMessage::parse(pdu, true).unwrap() {
+                SignalingMessage::EnhancedCreditBasedConnectionResponse {
+                    result,
+                    destination_cids,
+                    ..
+                } => (result, destination_cids),
+                other => panic!("unexpected response {:?}", other),
+            }
+        };
+
+        // Accepted channels can send with the credits the peer granted
+        mock.inject(
+            0x0040,
+            L2CAP_LE_SIGNALING_CID,
+            &request(1, PSM::EATT.value(), vec![0x0040, 0x0041]),
+        )
+        .unwrap();
+        let (result, local_cids) = response(0);
+        assert_eq!(result, L2CAP_RESULT_SUCCESS);
+        assert_eq!(local_cids.len(), 2);
+        manager.send_data(local_cids[0], &[0x01]).unwrap();
+        assert_eq!(mock.sent_pdus(0x0040, 0x0040).len(), 1);
+
+        // A CID the peer already uses, a repeated CID and one out of range
+        for (identifier, source_cids, expected) in [
+            (
+                2,
+                vec![0x0041],
+                L2CAP_RESULT_CREDIT_BASED_SOURCE_CID_ALREADY_ALLOCATED,
+            ),
+            (
+                3,
+                vec![0x0050, 0x0050],
+                L2CAP_RESULT_CREDIT_BASED_SOURCE_CID_ALREADY_ALLOCATED,
+            ),
+            (
+                4,
+                vec![0x0080],
+                L2CAP_RESULT_CREDIT_BASED_INVALID_SOURCE_CID,
+            ),
+        ] {
+            mock.inject(
+                0x0040,
+                L2CAP_LE_SIGNALING_CID,
+                &request(identifier, PSM::EATT.value(), source_cids.clone()),
+            )
+            .unwrap();
+            let (result, destination_cids) = response(identifier as usize - 1);
+            assert_eq!(result, expected);
+            assert_eq!(destination_cids, vec![0; source_cids.len()]);
+        }
+
+        // Without auto-accept, accepting one channel answers the request
+        mock.inject(
+            0x0040,
+            L2CAP_LE_SIGNALING_CID,
+            &request(5, 0x0081, vec![0x0050, 0x0051]),
+        )
+        .unwrap();
+        assert_eq!(mock.sent_pdus(0x0040, L2CAP_LE_SIGNALING_CID).len(), 4);
+        let pending_cid = local_cids[1] + 1;
+        manager.accept_connection(5, pending_cid, 0x0040).unwrap();
+        let (result, destination_cids) = response(4);
+        assert_eq!(result, L2CAP_RESULT_SUCCESS);
+        assert_eq!(destination_cids, vec![pending_cid, pending_cid + 1]);
+        manager.send_data(pending_cid + 1, &[0x02]).unwrap();
+        assert_eq!(mock.sent_pdus(0x0040, 0x0051).len(), 1);
+    }
+
+    #[test]
+    fn test_connect_to_peer_uses_registered_handle() {
+        let mock = MockL2capManager::new(ConnectionType::Classic).unwrap();
+        let manager = mock.manager();
+        let addr = crate::gap::BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+
+        assert!(matches!(
+            manager.connect_to_peer(PSM::SDP, &addr),
+            Err(L2capError::NotConnected)
+        ));
+
+        manager.register_connection(0x0040, addr);
+        assert_eq!(manager.connection_handle(&addr), Some(0x0040));
+
+        let cid = manager.connect_to_peer(PSM::SDP, &addr).unwrap();
+        assert!(cid >= L2CAP_DYNAMIC_CID_MIN);
+    }
+
+    #[test]
+    fn test_channel_receive_buffer() {
+        let mut channel = L2capChannel::new(
+            0x0040,
+            L2capChannelType::ConnectionOriented,
+            ConnectionType::Classic,
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        channel.set_data_callback(move |data| {
+            received_clone.lock().unwrap().push(data.to_vec());
+            Ok(())
+        });
+
+        // Buffered SDUs wait for the drain
+        assert!(!channel.receive_buffering());
+        assert_eq!(channel.max_received_sdus(), L2CAP_DEFAULT_MAX_RECEIVED_SDUS);
+        channel.set_receive_buffering(true);
+        channel.set_max_received_sdus(2);
+        channel.handle_data(&[0x01]).unwrap();
+        channel.handle_data(&[0x02]).unwrap();
+        assert!(matches!(
+            channel.handle_data(&[0x03]),
+            Err(L2capError::QueueFull)
+        ));
+        assert!(received.lock().unwrap().is_empty());
+        assert_eq!(channel.received_sdus(), 2);
+
+        assert_eq!(channel.drain_receive_buffer().unwrap(), 2);
+        assert_eq!(*received.lock().unwrap(), vec![vec![0x01], vec![0x02]]);
+        assert_eq!(channel.received_sdus(), 0);
+
+        // Without buffering data goes straight to the callback
+        channel.set_receive_buffering(false);
+        channel.handle_data(&[0x04]).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_process_received() {
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        let manager = mock.manager();
+        let local_cid = manager.connect_enhanced(PSM::EATT, 0x0040, 1).unwrap()[0];
+        let response = SignalingMessage::EnhancedCreditBasedConnectionResponse {
+            identifier: 1,
+            mtu: 128,
+            mps: 64,
+            initial_credits: 5,
+            result: L2CAP_RESULT_SUCCESS,
+            destination_cids: vec![0x0080],
+        };
+        manager
+            .handle_packet(response.to_packet(true), 0x0040)
+            .unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        manager
+            .set_channel_data_callback(local_cid, move |data| {
+                received_clone.lock().unwrap().push(data.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        manager
+            .set_channel_receive_buffer(local_cid, Some(4))
+            .unwrap();
+
+        // Packets are only stored while they are handled
+        for value in [0x0A, 0x0B] {
+            manager
+                .handle_packet(L2capPacket::new(local_cid, vec![0x01, 0x00, value]), 0x0040)
+                .unwrap();
+        }
+        assert!(received.lock().unwrap().is_empty());
+
+        assert_eq!(manager.process_received(local_cid).unwrap(), 2);
+        assert_eq!(*received.lock().unwrap(), vec![vec![0x0A], vec![0x0B]]);
+        assert_eq!(manager.process_received(local_cid).unwrap(), 0);
+        assert!(matches!(
+            manager.process_received(0x0FFF),
+            Err(L2capError::ChannelNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_signaling_request_retransmitted_then_failed() {
+        let mock = MockL2capManager::new(ConnectionType::Classic).unwrap();
+        let manager = mock.manager();
+        assert_eq!(
+            manager.max_signaling_retries(),
+            L2CAP_DEFAULT_SIGNALING_RETRIES
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        manager.set_global_event_callback(move |event| {
+            events_clone.lock().unwrap().push(event);
+            Ok(())
+        });
+
+        let addr = crate::gap::BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        manager.register_connection(0x0040, addr);
+        let local_cid = manager.connect(PSM::RFCOMM, 0x0040).unwrap();
+
+        // Each timeout sends the request again until the retries run out
+        for _ in 0..L2CAP_DEFAULT_SIGNALING_RETRIES {
+            std::thread::sleep(Duration::from_millis(1));
+            manager.process_timeouts(Duration::ZERO).unwrap();
+            assert_eq!(manager.channel_peer_address(local_cid), Some(addr));
+            assert!(events.lock().unwrap().is_empty());
         }
+
+        // The next timeout fails the request and closes the channel
+        std::thread::sleep(Duration::from_millis(1));
+        manager.process_timeouts(Duration::ZERO).unwrap();
+        assert_eq!(manager.channel_peer_address(local_cid), None);
+        assert!(matches!(
+            manager.disconnect(local_cid),
+            Err(L2capError::ChannelNotFound)
+        ));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            ChannelEvent::Disconnected { cid, ref reason, .. }
+                if cid == local_cid && reason == "RTX timeout"
+        ));
+    }
+
+    #[test]
+    fn test_acl_data_reassembled_and_signaling_sent() {
+        use crate::hci::constants::{ACL_PB_CONTINUING, ACL_PB_FIRST_FLUSHABLE};
+        use crate::hci::AclData;
+
+        let mock = MockL2capManager::new(ConnectionType::Classic).unwrap();
+        let manager = mock.manager();
+        let addr = crate::gap::BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        mock.connect(0x0040, addr);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        manager
+            .register_fixed_channel_callback(L2CAP_ATTRIBUTE_PROTOCOL_CID, move |from, data| {
+                received_clone.lock().unwrap().push((from, data.to_vec()));
+                Ok(())
+            })
+            .unwrap();
+
+        // A 3 byte ATT PDU split over two ACL packets
+        manager
+            .handle_acl_data(&AclData {
+                handle: 0x0040,
+                pb_flag: ACL_PB_FIRST_FLUSHABLE,
+                bc_flag: 0,
+                data: vec![0x03, 0x00, 0x04, 0x00, 0x0A],
+            })
+            .unwrap();
+        assert!(received.lock().unwrap().is_empty());
+        manager
+            .handle_acl_data(&AclData {
+                handle: 0x0040,
+                pb_flag: ACL_PB_CONTINUING,
+                bc_flag: 0,
+                data: vec![0x01, 0x00],
+            })
+            .unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(addr, vec![0x0A, 0x01, 0x00])]
+        );
+
+        // A continuing fragment needs a start
+        assert!(manager
+            .handle_acl_data(&AclData {
+                handle: 0x0040,
+                pb_flag: ACL_PB_CONTINUING,
+                bc_flag: 0,
+                data: vec![0x00],
+            })
+            .is_err());
+
+        // Signaling requests go out over the ACL transport
+        manager.connect(PSM::RFCOMM, 0x0040).unwrap();
+        let sent = mock.sent_pdus(0x0040, L2CAP_SIGNALING_CID);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0][0], L2CAP_CONNECTION_REQUEST);
     }
 }
@@ -2,7 +2,9 @@
 //!
 //! This module contains core data structures used in L2CAP operations.
 
+use crate::l2cap::constants::*;
 use std::fmt;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Error types specific to L2CAP operations
@@ -29,8 +31,8 @@ pub enum L2capError {
     #[error("Invalid state for operation")]
     InvalidState,
 
-    #[error("MTU exceeded")]
-    MtuExceeded,
+    #[error("MTU exceeded: {requested} bytes requested, {allowed} allowed")]
+    MtuExceeded { requested: usize, allowed: usize },
 
     #[error("Resource limit reached")]
     ResourceLimitReached,
@@ -52,11 +54,17 @@ pub enum L2capError {
 
     #[error("Connection not established")]
     NotConnected,
+
+    #[error("Send queue full")]
+    QueueFull,
 }
 
 /// Result type for L2CAP operations
 pub type L2capResult<T> = std::result::Result<T, L2capError>;
 
+/// L2CAP Channel Identifier
+pub type ChannelId = u16;
+
 /// Quality of Service (QoS) Flow Specification
 #[derive(Debug, Clone, Copy)]
 pub struct QosFlowSpec {
@@ -88,7 +96,7 @@ impl Default for QosFlowSpec {
 }
 
 /// L2CAP Configuration Options
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ConfigOptions {
     /// Maximum Transmission Unit
     pub mtu: Option<u16>,
@@ -106,17 +114,182 @@ pub struct ConfigOptions {
     pub ext_window_size: Option<u16>,
 }
 
-impl Default for ConfigOptions {
-    fn default() -> Self {
-        Self {
-            mtu: None,
-            flush_timeout: None,
-            qos: None,
-            retransmission: None,
-            fcs: None,
-            ext_flow_spec: None,
-            ext_window_size: None,
+impl ConfigOptions {
+    /// Encode the present options as a sequence of configuration TLVs
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        // MTU option
+        if let Some(mtu) = self.mtu {
+            result.push(L2CAP_CONF_MTU);
+            result.push(2); // Length
+            result.extend_from_slice(&mtu.to_le_bytes());
+        }
+
+        // Flush timeout option
+        if let Some(timeout) = self.flush_timeout {
+            result.push(L2CAP_CONF_FLUSH_TIMEOUT);
+            result.push(2); // Length
+            result.extend_from_slice(&timeout.to_le_bytes());
+        }
+
+        // QoS option
+        if let Some(qos) = self.qos {
+            result.push(L2CAP_CONF_QOS);
+            result.push(22); // Length
+            result.push(0); // Flags (reserved)
+            result.push(qos.service_type);
+            result.extend_from_slice(&qos.token_rate.to_le_bytes());
+            result.extend_from_slice(&qos.token_bucket_size.to_le_bytes());
+            result.extend_from_slice(&qos.peak_bandwidth.to_le_bytes());
+            result.extend_from_slice(&qos.latency.to_le_bytes());
+            result.extend_from_slice(&qos.delay_variation.to_le_bytes());
+        }
+
+        // Retransmission & flow control option, always the full 9 bytes
+        if let Some(rfc) = self.retransmission {
+            result.push(L2CAP_CONF_RFC);
+            result.push(9); // Length
+            result.push(rfc.mode as u8);
+            result.push(rfc.tx_window_size);
+            result.push(rfc.max_retransmit);
+            result.extend_from_slice(&rfc.retransmit_timeout.to_le_bytes());
+            result.extend_from_slice(&rfc.monitor_timeout.to_le_bytes());
+            result.extend_from_slice(&rfc.max_pdu_size.to_le_bytes());
+        }
+
+        // FCS option
+        if let Some(fcs) = self.fcs {
+            result.push(L2CAP_CONF_FCS);
+            result.push(1); // Length
+            result.push(fcs);
         }
+
+        // Extended flow specification option
+        if let Some(spec) = self.ext_flow_spec {
+            result.push(L2CAP_CONF_EXT_FLOW);
+            result.push(16); // Length
+            result.push(spec.identifier);
+            result.push(spec.service_type);
+            result.extend_from_slice(&spec.max_sdu_size.to_le_bytes());
+            result.extend_from_slice(&spec.sdu_inter_arrival_time.to_le_bytes());
+            result.extend_from_slice(&spec.access_latency.to_le_bytes());
+            result.extend_from_slice(&spec.flush_timeout.to_le_bytes());
+        }
+
+        // Extended window size option
+        if let Some(window) = self.ext_window_size {
+            result.push(L2CAP_CONF_EXT_WINDOW);
+            result.push(2); // Length
+            result.extend_from_slice(&window.to_le_bytes());
+        }
+
+        result
+    }
+
+    /// Decode a sequence of configuration TLVs
+    ///
+    /// Unknown options are skipped. Truncated options and known options with
+    /// an unexpected length are rejected.
+    pub fn parse(data: &[u8]) -> L2capResult<Self> {
+        let mut options = ConfigOptions::default();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            if offset + 2 > data.len() {
+                return Err(L2capError::InvalidParameter(
+                    "Truncated configuration option header".into(),
+                ));
+            }
+
+            let option_type = data[offset] & 0x7F; // Mask out hint bit
+            let option_length = data[offset + 1] as usize;
+            let value = data
+                .get(offset + 2..offset + 2 + option_length)
+                .ok_or_else(|| {
+                    L2capError::InvalidParameter(format!(
+                        "Truncated configuration option 0x{:02X}",
+                        option_type
+                    ))
+                })?;
+
+            let expected_length = match option_type {
+                L2CAP_CONF_MTU | L2CAP_CONF_FLUSH_TIMEOUT | L2CAP_CONF_EXT_WINDOW => Some(2),
+                L2CAP_CONF_QOS => Some(22),
+                L2CAP_CONF_RFC => Some(9),
+                L2CAP_CONF_FCS => Some(1),
+                L2CAP_CONF_EXT_FLOW => Some(16),
+                _ => None,
+            };
+            if let Some(expected) = expected_length {
+                if option_length != expected {
+                    return Err(L2capError::InvalidParameter(format!(
+                        "Invalid length {} for configuration option 0x{:02X}",
+                        option_length, option_type
+                    )));
+                }
+            }
+
+            let u16_at = |i: usize| u16::from_le_bytes([value[i], value[i + 1]]);
+            let u32_at =
+                |i: usize| u32::from_le_bytes([value[i], value[i + 1], value[i + 2], value[i + 3]]);
+
+            match option_type {
+                L2CAP_CONF_MTU => options.mtu = Some(u16_at(0)),
+                L2CAP_CONF_FLUSH_TIMEOUT => options.flush_timeout = Some(u16_at(0)),
+                L2CAP_CONF_QOS => {
+                    options.qos = Some(QosFlowSpec {
+                        service_type: value[1],
+                        token_rate: u32_at(2),
+                        token_bucket_size: u32_at(6),
+                        peak_bandwidth: u32_at(10),
+                        latency: u32_at(14),
+                        delay_variation: u32_at(18),
+                    })
+                }
+                L2CAP_CONF_RFC => {
+                    let mode = match value[0] {
+                        0 => RetransmissionMode::Basic,
+                        1 => RetransmissionMode::Retransmission,
+                        2 => RetransmissionMode::FlowControl,
+                        3 => RetransmissionMode::EnhancedRetransmission,
+                        4 => RetransmissionMode::Streaming,
+                        mode => {
+                            return Err(L2capError::InvalidParameter(format!(
+                                "Unknown retransmission mode {}",
+                                mode
+                            )))
+                        }
+                    };
+
+                    options.retransmission = Some(RetransmissionFlowControl {
+                        mode,
+                        tx_window_size: value[1],
+                        max_retransmit: value[2],
+                        retransmit_timeout: u16_at(3),
+                        monitor_timeout: u16_at(5),
+                        max_pdu_size: u16_at(7),
+                    });
+                }
+                L2CAP_CONF_FCS => options.fcs = Some(value[0]),
+                L2CAP_CONF_EXT_FLOW => {
+                    options.ext_flow_spec = Some(ExtendedFlowSpec {
+                        identifier: value[0],
+                        service_type: value[1],
+                        max_sdu_size: u16_at(2),
+                        sdu_inter_arrival_time: u32_at(4),
+                        access_latency: u32_at(8),
+                        flush_timeout: u32_at(12),
+                    })
+                }
+                L2CAP_CONF_EXT_WINDOW => options.ext_window_size = Some(u16_at(0)),
+                _ => {}
+            }
+
+            offset += 2 + option_length;
+        }
+
+        Ok(options)
     }
 }
 
@@ -148,6 +321,8 @@ pub struct RetransmissionFlowControl {
     pub monitor_timeout: u16,
     /// Acknowledgment timeout (ms)
     pub retransmit_timeout: u16,
+    /// Maximum PDU payload size
+    pub max_pdu_size: u16,
 }
 
 impl Default for RetransmissionFlowControl {
@@ -158,10 +333,219 @@ impl Default for RetransmissionFlowControl {
             max_retransmit: 0,
             monitor_timeout: 0,
             retransmit_timeout: 0,
+            max_pdu_size: 0,
         }
     }
 }
 
+/// Enhanced Retransmission Mode parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErtmConfig {
+    /// Transmission Window size (1-63)
+    pub tx_window_size: u8,
+    /// Maximum number of transmissions of an I-frame
+    pub max_transmit: u8,
+    /// Retransmission timeout (ms)
+    pub retransmission_timeout: u16,
+    /// Monitor timeout (ms)
+    pub monitor_timeout: u16,
+    /// Maximum PDU payload size
+    pub max_pdu_size: u16,
+}
+
+impl Default for ErtmConfig {
+    fn default() -> Self {
+        Self {
+            tx_window_size: 63,
+            max_transmit: 3,
+            retransmission_timeout: 2000,
+            monitor_timeout: 12000,
+            max_pdu_size: L2CAP_DEFAULT_MTU,
+        }
+    }
+}
+
+/// Streaming Mode parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Maximum PDU payload size
+    pub max_pdu_size: u16,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            max_pdu_size: L2CAP_DEFAULT_MTU,
+        }
+    }
+}
+
+/// Flow control mode of a Classic connection-oriented channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlowControlMode {
+    /// Basic L2CAP mode
+    #[default]
+    Basic,
+    /// Enhanced Retransmission Mode
+    Enhanced(ErtmConfig),
+    /// Streaming Mode
+    Streaming(StreamConfig),
+}
+
+impl FlowControlMode {
+    /// Get the mode value used in the Retransmission and Flow Control option
+    pub fn mode(&self) -> RetransmissionMode {
+        match self {
+            FlowControlMode::Basic => RetransmissionMode::Basic,
+            FlowControlMode::Enhanced(_) => RetransmissionMode::EnhancedRetransmission,
+            FlowControlMode::Streaming(_) => RetransmissionMode::Streaming,
+        }
+    }
+
+    /// Build the Retransmission and Flow Control option for this mode
+    ///
+    /// Returns `None` for Basic mode, which doesn't need the option.
+    pub fn to_option(&self) -> Option<RetransmissionFlowControl> {
+        match self {
+            FlowControlMode::Basic => None,
+            FlowControlMode::Enhanced(config) => Some(RetransmissionFlowControl {
+                mode: RetransmissionMode::EnhancedRetransmission,
+                tx_window_size: config.tx_window_size,
+                max_retransmit: config.max_transmit,
+                retransmit_timeout: config.retransmission_timeout,
+                monitor_timeout: config.monitor_timeout,
+                max_pdu_size: config.max_pdu_size,
+            }),
+            // Streaming mode ignores the window and timeout fields
+            FlowControlMode::Streaming(config) => Some(RetransmissionFlowControl {
+                mode: RetransmissionMode::Streaming,
+                max_pdu_size: config.max_pdu_size,
+                ..RetransmissionFlowControl::default()
+            }),
+        }
+    }
+
+    /// Get the mode described by a Retransmission and Flow Control option
+    ///
+    /// Returns `None` for the legacy Retransmission and Flow Control modes,
+    /// which are not supported.
+    pub fn from_option(option: &RetransmissionFlowControl) -> Option<Self> {
+        match option.mode {
+            RetransmissionMode::Basic => Some(FlowControlMode::Basic),
+            RetransmissionMode::EnhancedRetransmission => {
+                Some(FlowControlMode::Enhanced(ErtmConfig {
+                    tx_window_size: option.tx_window_size,
+                    max_transmit: option.max_retransmit,
+                    retransmission_timeout: option.retransmit_timeout,
+                    monitor_timeout: option.monitor_timeout,
+                    max_pdu_size: option.max_pdu_size,
+                }))
+            }
+            RetransmissionMode::Streaming => Some(FlowControlMode::Streaming(StreamConfig {
+                max_pdu_size: option.max_pdu_size,
+            })),
+            RetransmissionMode::Retransmission | RetransmissionMode::FlowControl => None,
+        }
+    }
+}
+
+/// Result of processing a Configuration Request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigureResult {
+    /// The options were accepted
+    Success,
+    /// One or more option values were not acceptable
+    UnacceptableParameters,
+    /// The request was rejected
+    Rejected,
+    /// The request contained unknown options
+    UnknownOptions,
+}
+
+impl ConfigureResult {
+    /// Get the result code used in the Configuration Response
+    pub fn to_result_code(self) -> u16 {
+        match self {
+            ConfigureResult::Success => 0x0000,
+            ConfigureResult::UnacceptableParameters => 0x0001,
+            ConfigureResult::Rejected => 0x0002,
+            ConfigureResult::UnknownOptions => 0x0003,
+        }
+    }
+}
+
+/// Round-trip times of repeated echo requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttStats {
+    /// Shortest round-trip time
+    pub min: Duration,
+    /// Longest round-trip time
+    pub max: Duration,
+    /// Average round-trip time
+    pub mean: Duration,
+}
+
+/// Extended features mask from an Information Response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtendedFeatures {
+    /// Raw feature mask
+    pub bits: u32,
+}
+
+impl ExtendedFeatures {
+    /// Parse the data of an Extended Features Information Response
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let bytes: [u8; 4] = data.get(..4)?.try_into().ok()?;
+        Some(Self {
+            bits: u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn has(&self, feature: u32) -> bool {
+        self.bits & feature != 0
+    }
+
+    /// Flow control mode (L2CAP_FEATURE_FLOW_CONTROL)
+    pub fn supports_l2cap_flow_control(&self) -> bool {
+        self.has(L2CAP_FEATURE_FLOW_CONTROL)
+    }
+
+    /// Retransmission mode (L2CAP_FEATURE_RETRANSMISSION)
+    pub fn supports_retransmission_mode(&self) -> bool {
+        self.has(L2CAP_FEATURE_RETRANSMISSION)
+    }
+
+    /// Bi-directional QoS (L2CAP_FEATURE_BIDIRECTIONAL_QOS)
+    pub fn supports_bidirectional_qos(&self) -> bool {
+        self.has(L2CAP_FEATURE_BIDIRECTIONAL_QOS)
+    }
+
+    /// Enhanced Retransmission Mode (L2CAP_FEATURE_ENHANCED_RETRANSMISSION)
+    pub fn supports_ertm(&self) -> bool {
+        self.has(L2CAP_FEATURE_ENHANCED_RETRANSMISSION)
+    }
+
+    /// Streaming mode (L2CAP_FEATURE_STREAMING)
+    pub fn supports_streaming_mode(&self) -> bool {
+        self.has(L2CAP_FEATURE_STREAMING)
+    }
+
+    /// FCS option (L2CAP_FEATURE_FCS)
+    pub fn supports_fcs_option(&self) -> bool {
+        self.has(L2CAP_FEATURE_FCS)
+    }
+
+    /// Whether the FCS can be negotiated, same as `supports_fcs_option`
+    pub fn supports_fcs(&self) -> bool {
+        self.supports_fcs_option()
+    }
+
+    /// Fixed channels (L2CAP_FEATURE_FIXED_CHANNELS)
+    pub fn supports_fixed_channels(&self) -> bool {
+        self.has(L2CAP_FEATURE_FIXED_CHANNELS)
+    }
+}
+
 /// Extended Flow Specification
 #[derive(Debug, Clone, Copy)]
 pub struct ExtendedFlowSpec {
@@ -225,35 +609,55 @@ pub enum ConnectionType {
 #[derive(Debug, Clone, Copy)]
 pub struct ConnectionParameterUpdate {
     /// Minimum connection interval (1.25ms units)
-    pub conn_interval_min: u16,
+    pub interval_min: u16,
     /// Maximum connection interval (1.25ms units)
-    pub conn_interval_max: u16,
+    pub interval_max: u16,
     /// Peripheral latency (number of events)
-    pub conn_latency: u16,
+    pub peripheral_latency: u16,
     /// Connection supervision timeout (10ms units)
     pub supervision_timeout: u16,
 }
 
 impl ConnectionParameterUpdate {
     /// Validates that the parameters are within acceptable ranges
+    ///
+    /// See Core Spec 5.3 Vol 3 Part A, 4.20.
     pub fn validate(&self) -> bool {
-        // Check individual ranges
-        let interval_ok = self.conn_interval_min >= super::constants::L2CAP_LE_CONN_INTERVAL_MIN
-            && self.conn_interval_max <= super::constants::L2CAP_LE_CONN_INTERVAL_MAX
-            && self.conn_interval_min <= self.conn_interval_max;
+        self.validation_error().is_none()
+    }
 
-        let latency_ok = self.conn_latency <= super::constants::L2CAP_LE_CONN_LATENCY_MAX;
+    /// Describe the first parameter that is out of range, if any
+    pub fn validation_error(&self) -> Option<&'static str> {
+        use super::constants::*;
 
-        let timeout_ok = self.supervision_timeout
-            >= super::constants::L2CAP_LE_SUPERVISION_TIMEOUT_MIN
-            && self.supervision_timeout <= super::constants::L2CAP_LE_SUPERVISION_TIMEOUT_MAX;
+        if self.interval_min < L2CAP_LE_CONN_INTERVAL_MIN {
+            return Some("Minimum connection interval below 7.5 ms");
+        }
+        if self.interval_max > L2CAP_LE_CONN_INTERVAL_MAX {
+            return Some("Maximum connection interval above 4 s");
+        }
+        if self.interval_min > self.interval_max {
+            return Some("Minimum connection interval above maximum");
+        }
+        if self.peripheral_latency > L2CAP_LE_CONN_LATENCY_MAX {
+            return Some("Peripheral latency above 499 events");
+        }
+        if self.supervision_timeout < L2CAP_LE_SUPERVISION_TIMEOUT_MIN {
+            return Some("Supervision timeout below 100 ms");
+        }
+        if self.supervision_timeout > L2CAP_LE_SUPERVISION_TIMEOUT_MAX {
+            return Some("Supervision timeout above 32 s");
+        }
 
-        // Check the relationship between parameters
-        // Supervision timeout must be larger than max interval * (latency + 1) * 2
-        let relation_ok = (self.supervision_timeout as u32)
-            > ((self.conn_interval_max as u32) * (self.conn_latency as u32 + 1) * 2) / 10;
+        // timeout * 10 ms > (1 + latency) * interval_max * 1.25 ms * 2,
+        // scaled by 4 to stay in integers
+        let timeout = self.supervision_timeout as u32 * 4;
+        let minimum = (1 + self.peripheral_latency as u32) * self.interval_max as u32;
+        if timeout <= minimum {
+            return Some("Supervision timeout too short for interval and latency");
+        }
 
-        interval_ok && latency_ok && timeout_ok && relation_ok
+        None
     }
 }
 
@@ -268,6 +672,20 @@ pub struct LeCreditBasedConfig {
     pub initial_credits: u16,
 }
 
+impl LeCreditBasedConfig {
+    /// Configuration for Enhanced Credit Based channels
+    ///
+    /// Enhanced Credit Based Flow Control requires an MTU and MPS of at
+    /// least 64 bytes.
+    pub fn enhanced() -> Self {
+        Self {
+            mtu: super::constants::L2CAP_CREDIT_BASED_MIN_MTU,
+            mps: super::constants::L2CAP_CREDIT_BASED_MIN_MTU,
+            initial_credits: 10,
+        }
+    }
+}
+
 impl Default for LeCreditBasedConfig {
     fn default() -> Self {
         Self {
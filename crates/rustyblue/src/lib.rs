@@ -11,9 +11,12 @@ pub mod gap;
 pub mod gatt;
 pub mod hci;
 pub mod l2cap;
+mod logging;
 pub mod scan;
 pub mod sdp;
 pub mod smp;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod uuid;
 
 // Re-export common types for convenience
@@ -25,7 +28,10 @@ pub use gatt::{
 };
 pub use hci::{HciCommand, HciEvent, HciSocket, LeAdvertisingReport};
 pub use l2cap::{L2capChannel, L2capChannelType, L2capError, L2capManager};
-pub use scan::{parse_advertising_data, scan_le};
+pub use scan::{
+    parse_advertising_data, scan_le, BackgroundScanHandle, ParsedAdvertisingData, ScanFilter,
+    ScanIter, ScanResult, Scanner,
+};
 pub use sdp::{SdpClient, SdpServer, ServiceRecord};
 pub use smp::{AuthRequirements, IoCapability, KeyDistribution, SecurityLevel, SmpManager};
 // pub use uuid::Uuid; // Removed re-export to fix privacy issues
@@ -43,7 +49,7 @@ mod tests {
         // We don't assert here because the test might fail in environments
         // without Bluetooth hardware or sufficient privileges
         if let Ok(socket) = result {
-            assert!(socket.as_raw_fd() > 0);
+            assert!(socket.as_raw_fd() >= 0);
         }
     }
 }
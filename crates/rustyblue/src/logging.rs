@@ -0,0 +1,21 @@
+//! Logging macros
+//!
+//! With the `log` feature these are the `log` crate's macros. Without it
+//! they only check their format arguments and log nothing.
+
+#[cfg(feature = "log")]
+#[allow(unused_imports)]
+pub(crate) use log::{debug, error, info, trace, warn};
+
+#[cfg(not(feature = "log"))]
+macro_rules! disabled {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+#[cfg(not(feature = "log"))]
+#[allow(unused_imports)]
+pub(crate) use {
+    disabled as debug, disabled as error, disabled as info, disabled as trace, disabled as warn,
+};
@@ -2,10 +2,25 @@
 //!
 //! This module provides functions for scanning for Bluetooth LE devices.
 
-use crate::error::HciError;
-use crate::hci::{HciCommand, HciSocket, LeAdvertisingReport};
-use std::thread;
-use std::time::Duration;
+use crate::error::{Error, HciError};
+use crate::gap::{
+    AdStructure, BdAddr, ADV_FLAG_LE_GENERAL_DISC, ADV_FLAG_LE_LIMITED_DISC,
+    ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE, ADV_TYPE_16BIT_SERVICE_UUID_PARTIAL,
+    ADV_TYPE_32BIT_SERVICE_UUID_COMPLETE, ADV_TYPE_32BIT_SERVICE_UUID_PARTIAL,
+    ADV_TYPE_SERVICE_DATA_16BIT,
+};
+#[cfg(feature = "v2")]
+use crate::gap::{AddressType, AdvertisingData};
+use crate::hci::{HciCommand, HciEvent, HciSocket, LeAdvertisingReport};
+use crate::uuid::Uuid;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the background scan thread checks whether it should stop
+const BACKGROUND_SCAN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Scan for Bluetooth LE devices
 ///
@@ -16,15 +31,107 @@ use std::time::Duration;
 ///
 /// * `socket` - The HCI socket to use for scanning
 /// * `duration` - How long to scan for
-/// * `callback` - Function to call for each advertisement
+/// * `callback` - Function to call for each advertisement, including its RSSI
 ///
 /// # Returns
 ///
 /// A result indicating success or failure
-pub fn scan_le<F>(socket: &HciSocket, duration: Duration, _callback: F) -> Result<(), HciError>
+///
+/// Scanning is disabled again before this returns, also when the callback
+/// panics.
+#[cfg(not(feature = "v2"))]
+pub fn scan_le<F>(socket: &HciSocket, duration: Duration, callback: F) -> Result<(), HciError>
+where
+    F: FnMut(&LeAdvertisingReport),
+{
+    scan_le_reports(socket, duration, callback)
+}
+
+/// Scan for Bluetooth LE devices
+///
+/// Scans for `duration` and returns every advertisement received, in order,
+/// with its address and advertising data decoded. Scanning is disabled again
+/// before this returns.
+#[cfg(feature = "v2")]
+pub fn scan_le(socket: &HciSocket, duration: Duration) -> Result<Vec<ScanResult>, HciError> {
+    let mut results = Vec::new();
+    scan_le_reports(socket, duration, |report| {
+        results.push(ScanResult::from(report))
+    })?;
+    Ok(results)
+}
+
+/// Enable scanning and pass each advertising report to a callback
+fn scan_le_reports<F>(
+    socket: &HciSocket,
+    duration: Duration,
+    mut callback: F,
+) -> Result<(), HciError>
 where
     F: FnMut(&LeAdvertisingReport),
 {
+    let guard = ScanGuard::start(socket, true)?;
+
+    // Deliver advertising reports until the scan duration elapses
+    let start = Instant::now();
+    let mut result = Ok(());
+    while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+        match socket.read_event_timeout(Some(remaining)) {
+            Ok(event) => {
+                // Other events (e.g. command completes) carry no reports
+                for report in &HciEvent::parse_le_advertising_reports(&event) {
+                    callback(report);
+                }
+            }
+            Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::TimedOut => break,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    guard.stop()?;
+
+    result
+}
+
+/// Keeps scanning enabled while alive and disables it when dropped
+#[derive(Debug)]
+struct ScanGuard<'a> {
+    socket: &'a HciSocket,
+    active: bool,
+}
+
+impl<'a> ScanGuard<'a> {
+    /// Set the scan parameters and enable scanning
+    fn start(socket: &'a HciSocket, filter_duplicates: bool) -> Result<Self, HciError> {
+        // Dropping the guard on failure undoes a partial start
+        let guard = Self {
+            socket,
+            active: true,
+        };
+        enable_scanning(socket, filter_duplicates)?;
+        Ok(guard)
+    }
+
+    /// Disable scanning, reporting whether the controller accepted it
+    fn stop(mut self) -> Result<(), HciError> {
+        self.active = false;
+        disable_scanning(self.socket)
+    }
+}
+
+impl Drop for ScanGuard<'_> {
+    fn drop(&mut self) {
+        if self.active {
+            let _ = disable_scanning(self.socket);
+        }
+    }
+}
+
+/// Set the scan parameters and enable scanning
+fn enable_scanning(socket: &HciSocket, filter_duplicates: bool) -> Result<(), HciError> {
     // Set scan parameters (active scanning, 10ms interval, 10ms window)
     socket.send_command(&HciCommand::LeSetScanParameters {
         scan_type: 1,          // 0 = passive, 1 = active
@@ -37,23 +144,324 @@ where
     // Enable scanning
     socket.send_command(&HciCommand::LeSetScanEnable {
         enable: true,
-        filter_duplicates: true,
-    })?;
-
-    // We need to implement a read function to read events from the socket
-    // This is a simplified approach for now
-    // TODO: Implement proper async event handling
-
-    // Start the scan for the specified duration
-    thread::sleep(duration);
+        filter_duplicates,
+    })
+}
 
-    // Disable scanning
+/// Disable scanning
+fn disable_scanning(socket: &HciSocket) -> Result<(), HciError> {
     socket.send_command(&HciCommand::LeSetScanEnable {
         enable: false,
         filter_duplicates: false,
-    })?;
+    })
+}
+
+/// An advertisement received while scanning
+#[cfg(not(feature = "v2"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    /// Advertiser address
+    pub address: BdAddr,
+    /// Advertiser address type
+    pub address_type: u8,
+    /// Advertising event type
+    pub event_type: u8,
+    /// Signal strength in dBm, if reported
+    pub rssi: Option<i8>,
+    /// Advertising or scan response data
+    pub data: Vec<u8>,
+}
+
+#[cfg(not(feature = "v2"))]
+impl From<&LeAdvertisingReport> for ScanResult {
+    fn from(report: &LeAdvertisingReport) -> Self {
+        Self {
+            address: BdAddr::new(report.address),
+            address_type: report.address_type,
+            event_type: report.event_type,
+            rssi: report.rssi,
+            data: report.data.clone(),
+        }
+    }
+}
+
+/// An advertisement received while scanning, with its contents decoded
+#[cfg(feature = "v2")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    /// Advertiser address
+    pub address: BdAddr,
+    /// Advertiser address type
+    pub address_type: AddressType,
+    /// Signal strength in dBm, if reported
+    pub rssi: Option<i8>,
+    /// Decoded advertising or scan response data
+    pub ad_data: AdvertisingData,
+    /// The report as received from the controller
+    pub raw_report: LeAdvertisingReport,
+}
+
+#[cfg(feature = "v2")]
+impl From<&LeAdvertisingReport> for ScanResult {
+    fn from(report: &LeAdvertisingReport) -> Self {
+        Self {
+            address: BdAddr::new(report.address),
+            address_type: AddressType::from(report.address_type),
+            rssi: report.rssi,
+            ad_data: AdvertisingData::parse(&report.data),
+            raw_report: report.clone(),
+        }
+    }
+}
+
+/// Criteria for the advertisements a `Scanner` reports
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilter {
+    /// Only report these advertisers, or all of them if empty
+    pub addresses: Vec<BdAddr>,
+    /// Only report advertisements at least this strong, in dBm
+    ///
+    /// Advertisements without an RSSI are dropped when this is set.
+    pub min_rssi: Option<i8>,
+    /// Report each advertiser only once
+    pub deduplicate: bool,
+}
+
+impl ScanFilter {
+    /// Check whether an advertisement passes the address and RSSI criteria
+    ///
+    /// Deduplication depends on what was reported before, so it is applied
+    /// by the scanner rather than here.
+    pub fn matches(&self, result: &ScanResult) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&result.address) {
+            return false;
+        }
+
+        match self.min_rssi {
+            Some(min_rssi) => result.rssi.is_some_and(|rssi| rssi >= min_rssi),
+            None => true,
+        }
+    }
+}
+
+/// Scans for Bluetooth LE devices on an HCI socket
+#[derive(Debug)]
+pub struct Scanner {
+    socket: Arc<HciSocket>,
+    filter: ScanFilter,
+}
+
+impl Scanner {
+    /// Create a scanner using the given HCI socket
+    pub fn new(socket: Arc<HciSocket>) -> Self {
+        Self {
+            socket,
+            filter: ScanFilter::default(),
+        }
+    }
+
+    /// Set the filter applied to scan results
+    pub fn set_filter(&mut self, filter: ScanFilter) {
+        self.filter = filter;
+    }
+
+    /// Get the filter applied to scan results
+    pub fn filter(&self) -> &ScanFilter {
+        &self.filter
+    }
+
+    /// Scan on the calling thread, yielding results passing the filter
+    ///
+    /// Scanning is enabled before this returns and disabled when the
+    /// iterator is dropped.
+    pub fn scan_iter(&self) -> Result<ScanIter<'_>, HciError> {
+        Ok(ScanIter {
+            guard: ScanGuard::start(&self.socket, self.filter.deduplicate)?,
+            filter: self.filter.clone(),
+            reported: HashSet::new(),
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Scan on a background thread until the returned handle is stopped
+    ///
+    /// Scanning is enabled before this returns. The callback runs on the
+    /// scan thread for each result passing the current filter. Signals
+    /// interrupting the wait for events don't end the scan.
+    pub fn start_background_scan<F>(&self, callback: F) -> Result<BackgroundScanHandle, HciError>
+    where
+        F: Fn(ScanResult) + Send + 'static,
+    {
+        // The controller filters repeats too when the caller wants them dropped
+        enable_scanning(&self.socket, self.filter.deduplicate)?;
+
+        let socket = self.socket.clone();
+        let filter = self.filter.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let last_seen = Arc::new(Mutex::new(HashMap::new()));
+
+        let thread = {
+            let stop = stop.clone();
+            let last_seen = last_seen.clone();
+            std::thread::spawn(move || {
+                let mut reported = HashSet::new();
+                let result = loop {
+                    if stop.load(Ordering::SeqCst) {
+                        break Ok(());
+                    }
+
+                    let event = match socket.read_event_timeout(Some(BACKGROUND_SCAN_POLL_INTERVAL))
+                    {
+                        Ok(event) => event,
+                        Err(HciError::ReceiveError(e))
+                            if matches!(
+                                e.kind(),
+                                std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted
+                            ) =>
+                        {
+                            continue
+                        }
+                        Err(e) => break Err(e),
+                    };
+
+                    // Other events (e.g. command completes) are ignored
+                    let Ok(reports) = LeAdvertisingReport::parse_from_event(&event) else {
+                        continue;
+                    };
+
+                    for report in &reports {
+                        let result = ScanResult::from(report);
+                        if !filter.matches(&result) {
+                            continue;
+                        }
+
+                        last_seen
+                            .lock()
+                            .unwrap()
+                            .insert(result.address, Instant::now());
+
+                        if filter.deduplicate && !reported.insert(result.address) {
+                            continue;
+                        }
+                        callback(result);
+                    }
+                };
+
+                let disabled = disable_scanning(&socket);
+                result.and(disabled)
+            })
+        };
+
+        Ok(BackgroundScanHandle {
+            stop,
+            last_seen,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Blocking iterator over scan results, created by `Scanner::scan_iter`
+///
+/// Scanning stays enabled until the iterator is dropped or stopped. The
+/// iterator ends after yielding the first error from the socket.
+#[derive(Debug)]
+pub struct ScanIter<'a> {
+    guard: ScanGuard<'a>,
+    filter: ScanFilter,
+    reported: HashSet<BdAddr>,
+    pending: VecDeque<ScanResult>,
+    done: bool,
+}
+
+impl ScanIter<'_> {
+    /// Disable scanning, returning an error if the controller refused
+    pub fn stop(self) -> Result<(), HciError> {
+        self.guard.stop()
+    }
+}
+
+impl Iterator for ScanIter<'_> {
+    type Item = Result<ScanResult, HciError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(Ok(result));
+            }
+            if self.done {
+                return None;
+            }
 
-    Ok(())
+            let event = match self.guard.socket.read_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+
+            // Other events (e.g. command completes) carry no reports
+            for report in &HciEvent::parse_le_advertising_reports(&event) {
+                let result = ScanResult::from(report);
+                if !self.filter.matches(&result) {
+                    continue;
+                }
+                if self.filter.deduplicate && !self.reported.insert(result.address) {
+                    continue;
+                }
+                self.pending.push_back(result);
+            }
+        }
+    }
+}
+
+/// Handle to a scan started with `Scanner::start_background_scan`
+///
+/// Dropping the handle stops the scan as well.
+#[derive(Debug)]
+pub struct BackgroundScanHandle {
+    stop: Arc<AtomicBool>,
+    last_seen: Arc<Mutex<HashMap<BdAddr, Instant>>>,
+    thread: Option<JoinHandle<Result<(), HciError>>>,
+}
+
+impl BackgroundScanHandle {
+    /// Get when an advertisement from `addr` passing the filter was last seen
+    ///
+    /// Repeated advertisements update this even when deduplication keeps
+    /// them from the callback, so it can be used to expire absent devices.
+    pub fn last_seen(&self, addr: &BdAddr) -> Option<Instant> {
+        self.last_seen.lock().unwrap().get(addr).copied()
+    }
+
+    /// Check whether the scan thread is still running
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stop scanning and wait for the scan thread to exit
+    ///
+    /// Returns the error that ended the scan early, if any.
+    pub fn stop(mut self) -> Result<(), HciError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), HciError> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Err(HciError::Unsupported)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BackgroundScanHandle {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
 }
 
 /// Parse advertisement data from a LE Advertising Report
@@ -65,7 +473,143 @@ where
 /// # Returns
 ///
 /// A vector of (type, data) tuples
+#[cfg(not(feature = "v2"))]
 pub fn parse_advertising_data(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    parse_ad_structures(data)
+}
+
+/// Parse the advertisement data of a LE Advertising Report
+///
+/// Fails if an AD structure runs past the end of the data.
+#[cfg(feature = "v2")]
+pub fn parse_advertising_data(
+    report: &LeAdvertisingReport,
+) -> Result<ParsedAdvertisingData, Error> {
+    ParsedAdvertisingData::try_from(report.data.as_slice())
+}
+
+/// Advertising data split into typed fields
+///
+/// AD types without a field of their own, and typed AD structures with a
+/// malformed payload, are kept in `unknown`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedAdvertisingData {
+    /// Complete local name
+    pub local_name: Option<String>,
+    /// Shortened local name
+    pub short_name: Option<String>,
+    /// TX power level in dBm
+    pub tx_power: Option<i8>,
+    /// 16-bit service UUIDs, from complete and incomplete lists
+    pub uuids_16: Vec<Uuid>,
+    /// 32-bit service UUIDs, from complete and incomplete lists
+    pub uuids_32: Vec<Uuid>,
+    /// 128-bit service UUIDs, from complete and incomplete lists
+    pub uuids_128: Vec<Uuid>,
+    /// First manufacturer specific data, as company identifier and payload
+    pub manufacturer_data: Option<(u16, Vec<u8>)>,
+    /// Service data for 16-bit service UUIDs
+    pub service_data_16: Vec<(Uuid, Vec<u8>)>,
+    /// Appearance value
+    pub appearance: Option<u16>,
+    /// Flags
+    pub flags: Option<u8>,
+    /// Every other AD structure as (type, data)
+    pub unknown: Vec<(u8, Vec<u8>)>,
+}
+
+impl ParsedAdvertisingData {
+    /// Whether the flags advertise LE Limited or General Discoverable mode
+    ///
+    /// Peripherals that accept connections advertise in a discoverable
+    /// mode; the advertising event type of the report is authoritative.
+    pub fn is_connectable(&self) -> bool {
+        self.flags
+            .is_some_and(|flags| flags & (ADV_FLAG_LE_LIMITED_DISC | ADV_FLAG_LE_GENERAL_DISC) != 0)
+    }
+
+    /// Whether the service is listed or has service data
+    pub fn contains_service(&self, uuid: &Uuid) -> bool {
+        self.uuids_16
+            .iter()
+            .chain(&self.uuids_32)
+            .chain(&self.uuids_128)
+            .chain(self.service_data_16.iter().map(|(service, _)| service))
+            .any(|service| service == uuid)
+    }
+
+    /// Company identifier of the manufacturer specific data
+    pub fn manufacturer_id(&self) -> Option<u16> {
+        self.manufacturer_data
+            .as_ref()
+            .map(|(company_id, _)| *company_id)
+    }
+}
+
+impl TryFrom<&[u8]> for ParsedAdvertisingData {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let mut parsed = Self::default();
+        let mut i = 0;
+
+        while i < data.len() {
+            // A zero length ends the significant part of the data
+            let length = data[i] as usize;
+            if length == 0 {
+                break;
+            }
+            if i + length >= data.len() {
+                return Err(Error::InvalidPacket(format!(
+                    "AD structure at offset {} runs past the end of the data",
+                    i
+                )));
+            }
+
+            let ad_type = data[i + 1];
+            let payload = &data[i + 2..i + 1 + length];
+            match AdStructure::parse(ad_type, payload) {
+                AdStructure::Flags(flags) => parsed.flags = Some(flags),
+                AdStructure::ServiceUuids { uuids, .. } => {
+                    match ad_type {
+                        ADV_TYPE_16BIT_SERVICE_UUID_PARTIAL
+                        | ADV_TYPE_16BIT_SERVICE_UUID_COMPLETE => parsed.uuids_16.extend(uuids),
+                        ADV_TYPE_32BIT_SERVICE_UUID_PARTIAL
+                        | ADV_TYPE_32BIT_SERVICE_UUID_COMPLETE => parsed.uuids_32.extend(uuids),
+                        _ => parsed.uuids_128.extend(uuids),
+                    }
+                }
+                AdStructure::LocalName { name, complete } => {
+                    if complete {
+                        parsed.local_name = Some(name);
+                    } else {
+                        parsed.short_name = Some(name);
+                    }
+                }
+                AdStructure::TxPowerLevel(tx_power) => parsed.tx_power = Some(tx_power),
+                AdStructure::Appearance(appearance) => parsed.appearance = Some(appearance),
+                AdStructure::ServiceData { uuid, data }
+                    if ad_type == ADV_TYPE_SERVICE_DATA_16BIT =>
+                {
+                    parsed.service_data_16.push((uuid, data))
+                }
+                AdStructure::ManufacturerData { company_id, data }
+                    if parsed.manufacturer_data.is_none() =>
+                {
+                    parsed.manufacturer_data = Some((company_id, data))
+                }
+                _ => parsed.unknown.push((ad_type, payload.to_vec())),
+            }
+
+            i += 1 + length;
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Split advertising data into (type, data) tuples
+pub(crate) fn parse_ad_structures(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
     let mut result = Vec::new();
     let mut i = 0;
 
@@ -85,3 +629,147 @@ pub fn parse_advertising_data(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::RawFd;
+
+    /// HCI socket backed by a socket pair, with the controller end returned
+    fn socket_pair() -> (HciSocket, RawFd) {
+        let mut fds = [0; 2];
+        let result =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(result, 0);
+        (HciSocket::from_raw_fd(fds[0]), fds[1])
+    }
+
+    /// Collect the packets written to the socket so far
+    fn sent_packets(controller: RawFd) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        loop {
+            let mut buffer = [0u8; 260];
+            let len = unsafe {
+                libc::recv(
+                    controller,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len <= 0 {
+                return packets;
+            }
+            packets.push(buffer[..len as usize].to_vec());
+        }
+    }
+
+    /// LE Set Scan Enable with scanning disabled
+    const SCAN_DISABLE: [u8; 6] = [0x01, 0x0C, 0x20, 0x02, 0x00, 0x00];
+
+    #[cfg(not(feature = "v2"))]
+    #[test]
+    fn test_scan_le_disables_scanning_on_timeout() {
+        let (socket, controller) = socket_pair();
+
+        scan_le(&socket, Duration::ZERO, |_| {}).unwrap();
+
+        let packets = sent_packets(controller);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1], [0x01, 0x0C, 0x20, 0x02, 0x01, 0x01]);
+        assert_eq!(packets[2], SCAN_DISABLE);
+        unsafe { libc::close(controller) };
+    }
+
+    #[cfg(feature = "v2")]
+    #[test]
+    fn test_scan_le_returns_decoded_results() {
+        let (socket, controller) = socket_pair();
+
+        // LE Advertising Report from a random address named "Tag", RSSI -60
+        let event = [
+            0x04, 0x3E, 0x11, 0x02, 0x01, 0x00, 0x01, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x05,
+            0x04, 0x09, b'T', b'a', b'g', 0xC4,
+        ];
+        let sent = unsafe {
+            libc::send(
+                controller,
+                event.as_ptr() as *const libc::c_void,
+                event.len(),
+                0,
+            )
+        };
+        assert_eq!(sent, event.len() as isize);
+
+        let results = scan_le(&socket, Duration::from_millis(50)).unwrap();
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        assert_eq!(
+            result.address,
+            BdAddr::new([0x06, 0x05, 0x04, 0x03, 0x02, 0x01])
+        );
+        assert_eq!(result.address_type, AddressType::Random);
+        assert_eq!(result.rssi, Some(-60));
+        assert_eq!(result.ad_data.local_name.as_deref(), Some("Tag"));
+        assert_eq!(
+            parse_advertising_data(&result.raw_report)
+                .unwrap()
+                .local_name
+                .as_deref(),
+            Some("Tag")
+        );
+
+        assert_eq!(sent_packets(controller).last().unwrap(), &SCAN_DISABLE);
+        unsafe { libc::close(controller) };
+    }
+
+    #[test]
+    fn test_parsed_advertising_data() {
+        let data = [
+            0x02, 0x01, 0x06, // Flags: LE General Discoverable, no BR/EDR
+            0x05, 0x03, 0x0D, 0x18, 0x0F, 0x18, // Heart Rate and Battery services
+            0x04, 0x08, b'H', b'R', b'M', // Shortened name
+            0x02, 0x0A, 0xF4, // TX power -12 dBm
+            0x05, 0x16, 0x0F, 0x18, 0x55, 0x01, // Battery service data
+            0x05, 0xFF, 0x4C, 0x00, 0x02, 0x15, // Apple manufacturer data
+            0x03, 0x2A, 0x01, 0x02, // Mesh message, not decoded
+            0x00, 0x00, // Padding
+        ];
+
+        let parsed = ParsedAdvertisingData::try_from(&data[..]).unwrap();
+        assert_eq!(parsed.flags, Some(0x06));
+        assert!(parsed.is_connectable());
+        assert_eq!(
+            parsed.uuids_16,
+            vec![Uuid::from_u16(0x180D), Uuid::from_u16(0x180F)]
+        );
+        assert_eq!(parsed.short_name.as_deref(), Some("HRM"));
+        assert_eq!(parsed.local_name, None);
+        assert_eq!(parsed.tx_power, Some(-12));
+        assert_eq!(
+            parsed.service_data_16,
+            vec![(Uuid::from_u16(0x180F), vec![0x55, 0x01])]
+        );
+        assert_eq!(parsed.manufacturer_id(), Some(0x004C));
+        assert_eq!(parsed.unknown, vec![(0x2A, vec![0x01, 0x02])]);
+        assert!(parsed.contains_service(&Uuid::from_u16(0x180D)));
+        assert!(!parsed.contains_service(&Uuid::from_u16(0x1800)));
+
+        // A structure claiming more bytes than remain is rejected
+        assert!(ParsedAdvertisingData::try_from(&[0x05, 0x09, b'A'][..]).is_err());
+        assert!(!ParsedAdvertisingData::default().is_connectable());
+    }
+
+    #[test]
+    fn test_scan_iter_disables_scanning_on_drop() {
+        let (socket, controller) = socket_pair();
+        let scanner = Scanner::new(Arc::new(socket));
+
+        let iter = scanner.scan_iter().unwrap();
+        assert_eq!(sent_packets(controller).len(), 2);
+
+        drop(iter);
+        assert_eq!(sent_packets(controller), vec![SCAN_DISABLE.to_vec()]);
+        unsafe { libc::close(controller) };
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::sdp::protocol::{decode_data_element, encode_service_search_request, SdpPacket};
+use crate::sdp::protocol::{encode_service_search_request, SdpPacket};
 use crate::sdp::types::{SdpPdu, ServiceRecord, Uuid};
 use std::collections::HashMap;
 
@@ -13,6 +13,12 @@ struct L2capConnection {
     // connection details will go here
 }
 
+impl Default for SdpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SdpClient {
     pub fn new() -> Self {
         Self {
@@ -40,7 +46,7 @@ impl SdpClient {
 
         self.transaction_id = (self.transaction_id + 1) % 0xFFFF;
 
-        let request = encode_service_search_request(self.transaction_id, uuids, 10);
+        let _request = encode_service_search_request(self.transaction_id, uuids, 10);
 
         // TODO: Actually send request over L2CAP and get response
         // For now, this is just a placeholder
@@ -53,8 +59,8 @@ impl SdpClient {
 
     pub fn get_service_attributes(
         &mut self,
-        handle: u32,
-        attributes: &[u16],
+        _handle: u32,
+        _attributes: &[u16],
     ) -> Result<HashMap<u16, Vec<u8>>, Error> {
         if self.connection.is_none() {
             return Err(Error::NotConnected);
@@ -70,8 +76,8 @@ impl SdpClient {
 
     pub fn search_and_get_attributes(
         &mut self,
-        uuids: &[Uuid],
-        attributes: &[u16],
+        _uuids: &[Uuid],
+        _attributes: &[u16],
     ) -> Result<Vec<ServiceRecord>, Error> {
         if self.connection.is_none() {
             return Err(Error::NotConnected);
@@ -87,6 +93,7 @@ impl SdpClient {
         Ok(records)
     }
 
+    #[allow(dead_code)]
     fn parse_service_search_response(&self, response: &SdpPacket) -> Result<Vec<u32>, Error> {
         if response.pdu_id != SdpPdu::ServiceSearchResponse {
             return Err(Error::InvalidPacket("Not a service search response".into()));
@@ -98,7 +105,7 @@ impl SdpClient {
             ));
         }
 
-        let total_records = u16::from_be_bytes([response.parameters[0], response.parameters[1]]);
+        let _total_records = u16::from_be_bytes([response.parameters[0], response.parameters[1]]);
         let record_count = u16::from_be_bytes([response.parameters[2], response.parameters[3]]);
 
         let mut handles = Vec::with_capacity(record_count as usize);
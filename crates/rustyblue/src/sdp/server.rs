@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::sdp::protocol::SdpPacket;
-use crate::sdp::types::{DataElement, SdpPdu, ServiceRecord, Uuid};
+use crate::sdp::types::{SdpPdu, ServiceRecord, Uuid};
 use std::collections::HashMap;
 
 pub struct SdpServer {
@@ -8,6 +8,12 @@ pub struct SdpServer {
     next_handle: u32,
 }
 
+impl Default for SdpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SdpServer {
     pub fn new() -> Self {
         Self {
@@ -76,6 +82,7 @@ impl SdpServer {
         ))
     }
 
+    #[allow(dead_code)]
     fn find_matching_services(&self, uuids: &[Uuid]) -> Vec<u32> {
         let mut matching_handles = Vec::new();
 
@@ -0,0 +1,124 @@
+//! Software AES-128 block cipher
+//!
+//! A small AES-128 encryption-only implementation (FIPS-197) used by the
+//! SMP security functions. The S-box is computed arithmetically rather than
+//! looked up in a table so that no memory access depends on secret data.
+
+/// Number of rounds for AES-128
+const ROUNDS: usize = 10;
+
+/// Multiply by x in GF(2^8)
+fn xtime(a: u8) -> u8 {
+    (a << 1) ^ (0x1B & 0u8.wrapping_sub(a >> 7))
+}
+
+/// Multiply two elements of GF(2^8) without data-dependent branches
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+/// AES S-box: multiplicative inverse followed by the affine transform
+fn sub_byte(x: u8) -> u8 {
+    // x^254 is the inverse of x in GF(2^8), with 0 mapping to 0
+    let mut square = gf_mul(x, x);
+    let mut inverse = square;
+    for _ in 0..6 {
+        square = gf_mul(square, square);
+        inverse = gf_mul(inverse, square);
+    }
+
+    inverse
+        ^ inverse.rotate_left(1)
+        ^ inverse.rotate_left(2)
+        ^ inverse.rotate_left(3)
+        ^ inverse.rotate_left(4)
+        ^ 0x63
+}
+
+fn expand_key(key: &[u8; 16]) -> [[u8; 16]; ROUNDS + 1] {
+    let mut round_keys = [[0u8; 16]; ROUNDS + 1];
+    round_keys[0] = *key;
+
+    let mut rcon = 0x01u8;
+    for round in 1..=ROUNDS {
+        let prev = round_keys[round - 1];
+        let mut word = [
+            sub_byte(prev[13]) ^ rcon,
+            sub_byte(prev[14]),
+            sub_byte(prev[15]),
+            sub_byte(prev[12]),
+        ];
+
+        let mut next = [0u8; 16];
+        for column in 0..4 {
+            for row in 0..4 {
+                word[row] ^= prev[column * 4 + row];
+                next[column * 4 + row] = word[row];
+            }
+        }
+
+        round_keys[round] = next;
+        rcon = xtime(rcon);
+    }
+
+    round_keys
+}
+
+fn add_round_key(state: &mut [u8; 16], round_key: &[u8; 16]) {
+    for (byte, key) in state.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+fn sub_bytes(state: &mut [u8; 16]) {
+    for byte in state.iter_mut() {
+        *byte = sub_byte(*byte);
+    }
+}
+
+fn shift_rows(state: &mut [u8; 16]) {
+    let old = *state;
+    for column in 0..4 {
+        for row in 0..4 {
+            state[column * 4 + row] = old[((column + row) % 4) * 4 + row];
+        }
+    }
+}
+
+fn mix_columns(state: &mut [u8; 16]) {
+    for column in state.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+/// Encrypt a single 16 byte block with AES-128
+pub(crate) fn encrypt_block(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    let round_keys = expand_key(key);
+    let mut state = *block;
+
+    add_round_key(&mut state, &round_keys[0]);
+
+    for round_key in &round_keys[1..ROUNDS] {
+        sub_bytes(&mut state);
+        shift_rows(&mut state);
+        mix_columns(&mut state);
+        add_round_key(&mut state, round_key);
+    }
+
+    sub_bytes(&mut state);
+    shift_rows(&mut state);
+    add_round_key(&mut state, &round_keys[ROUNDS]);
+
+    state
+}
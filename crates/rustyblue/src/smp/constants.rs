@@ -32,7 +32,6 @@ pub const SMP_AUTH_REQ_MITM: u8 = 0x04;
 pub const SMP_AUTH_REQ_SC: u8 = 0x08;
 pub const SMP_AUTH_REQ_KEYPRESS: u8 = 0x10;
 pub const SMP_AUTH_REQ_CT2: u8 = 0x20;
-pub const SMP_AUTH_REQ_RFU: u8 = 0xC0;
 
 // Pairing Failed reason codes
 pub const SMP_REASON_PASSKEY_ENTRY_FAILED: u8 = 0x01;
@@ -56,8 +55,7 @@ pub const SMP_KEY_DIST_ID_KEY: u8 = 0x02;
 pub const SMP_KEY_DIST_SIGN_KEY: u8 = 0x04;
 pub const SMP_KEY_DIST_LINK_KEY: u8 = 0x08;
 
-// SMP encryption key size limits
-pub const SMP_MIN_ENCRYPTION_KEY_SIZE: u8 = 7;
+// SMP encryption key size limit
 pub const SMP_MAX_ENCRYPTION_KEY_SIZE: u8 = 16;
 
 // SMP pairing methods
@@ -77,12 +75,5 @@ pub const SMP_KEYPRESS_ENTRY_COMPLETED: u8 = 0x04;
 pub const SMP_TRANSPORT_LE: u8 = 0x00;
 pub const SMP_TRANSPORT_BR_EDR: u8 = 0x01;
 
-// SMP timeout values (in milliseconds)
+// SMP timeout (in milliseconds)
 pub const SMP_TIMEOUT_GENERAL: u64 = 30000; // 30 seconds general timeout
-pub const SMP_TIMEOUT_PASSKEY: u64 = 60000; // 60 seconds for passkey entry
-pub const SMP_TIMEOUT_NUMERIC_COMPARISON: u64 = 30000; // 30 seconds for numeric comparison
-pub const SMP_TIMEOUT_USER_AUTHORIZATION: u64 = 60000; // 60 seconds for user authorization
-
-// SMP address types
-pub const SMP_ADDR_TYPE_PUBLIC: u8 = 0x00;
-pub const SMP_ADDR_TYPE_RANDOM: u8 = 0x01;
@@ -4,12 +4,64 @@
 //! Bluetooth LE security, including key generation, encryption, and
 //! cryptographic checksum functions.
 
-use super::types::*;
+use super::aes;
+use super::types::{SmpError, SmpResult};
+use crate::hci::HciSocket;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use std::convert::TryInto;
+use std::sync::Arc;
+
+// Note: AES-128 and AES-CMAC are implemented in software, or by the
+// controller through an `AesBackend`. The ECDH and signing functions are
+// still placeholder implementations.
+
+/// AES-128 block cipher used by the security functions
+///
+/// Keys, plaintext and ciphertext are in the byte order of FIPS-197.
+pub trait AesBackend: Send + Sync {
+    /// Encrypt a single block
+    fn encrypt(&self, key: &[u8; 16], data: &[u8; 16]) -> SmpResult<[u8; 16]>;
+}
+
+/// AES-128 in software
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoftwareAes;
+
+impl AesBackend for SoftwareAes {
+    fn encrypt(&self, key: &[u8; 16], data: &[u8; 16]) -> SmpResult<[u8; 16]> {
+        Ok(aes::encrypt_block(key, data))
+    }
+}
 
-// Note: In a real implementation, we would use a cryptographic library like
-// ring, openssl, or crypto-rs. For this example, we'll include placeholder
-// implementations of the necessary functions.
+/// AES-128 by the controller's encryption engine (HCI LE Encrypt)
+pub struct ControllerAes {
+    socket: Arc<HciSocket>,
+}
+
+impl ControllerAes {
+    /// Use the controller behind the given HCI socket
+    pub fn new(socket: Arc<HciSocket>) -> Self {
+        Self { socket }
+    }
+}
+
+impl AesBackend for ControllerAes {
+    fn encrypt(&self, key: &[u8; 16], data: &[u8; 16]) -> SmpResult<[u8; 16]> {
+        // HCI parameters are least significant octet first
+        let mut key_le = *key;
+        key_le.reverse();
+        let mut data_le = *data;
+        data_le.reverse();
+
+        let mut result = self
+            .socket
+            .le_encrypt(&key_le, &data_le)
+            .map_err(|e| SmpError::HciError(e.to_string()))?;
+        result.reverse();
+        Ok(result)
+    }
+}
 
 /// Generate a random number of specified length
 pub fn generate_random(length: usize) -> Vec<u8> {
@@ -30,20 +82,137 @@ pub fn generate_random_128() -> [u8; 16] {
     rand_vec.try_into().expect("Convert vec to fixed array")
 }
 
+/// Generate a 128-bit random number with the controller (HCI LE Rand)
+///
+/// Each LE Rand command returns 8 bytes, so two are sent.
+pub fn generate_random_128_hardware(hci_socket: &HciSocket) -> SmpResult<[u8; 16]> {
+    let mut result = [0u8; 16];
+    for half in result.chunks_exact_mut(8) {
+        let random = hci_socket
+            .le_rand()
+            .map_err(|e| SmpError::HciError(e.to_string()))?;
+        half.copy_from_slice(&random);
+    }
+    Ok(result)
+}
+
+/// Source of the random numbers used for pairing
+pub trait SecureRng: Send + Sync {
+    /// Generate a 128-bit random number
+    fn random_128(&self) -> SmpResult<[u8; 16]>;
+}
+
+/// Random numbers from the operating system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsSecureRng;
+
+impl SecureRng for OsSecureRng {
+    fn random_128(&self) -> SmpResult<[u8; 16]> {
+        let mut result = [0u8; 16];
+        OsRng
+            .try_fill_bytes(&mut result)
+            .map_err(|e| SmpError::CryptoError(e.to_string()))?;
+        Ok(result)
+    }
+}
+
+/// Random numbers from the controller's hardware entropy source
+pub struct HciSecureRng {
+    socket: Arc<HciSocket>,
+}
+
+impl HciSecureRng {
+    /// Use the controller behind the given HCI socket
+    pub fn new(socket: Arc<HciSocket>) -> Self {
+        Self { socket }
+    }
+}
+
+impl SecureRng for HciSecureRng {
+    fn random_128(&self) -> SmpResult<[u8; 16]> {
+        generate_random_128_hardware(&self.socket)
+    }
+}
+
 /// Generate a random passkey (0-999999)
 pub fn generate_passkey() -> u32 {
     rand::random::<u32>() % 1_000_000
 }
 
 /// AES-CMAC function (BT Core Spec Vol 3, Part H, 2.2.5)
-pub fn aes_cmac(_key: &[u8; 16], _message: &[u8]) -> [u8; 16] {
-    // In a real implementation, this would use a crypto library
-    // For this example, we'll return a placeholder value
-    [0u8; 16]
+///
+/// Computes the CMAC of `message` as defined in NIST SP 800-38B and
+/// RFC 4493. Key, message and result are in the byte order used by the RFC.
+pub fn aes_cmac(aes: &dyn AesBackend, key: &[u8; 16], message: &[u8]) -> SmpResult<[u8; 16]> {
+    // Subkey generation
+    let l = aes.encrypt(key, &[0u8; 16])?;
+    let k1 = cmac_double(&l);
+    let k2 = cmac_double(&k1);
+
+    let block_count = message.len().div_ceil(16).max(1);
+    let last_start = (block_count - 1) * 16;
+    let remaining = &message[last_start..];
+
+    // Pad the last block if needed and mask it with K1 or K2
+    let mut last_block = [0u8; 16];
+    last_block[..remaining.len()].copy_from_slice(remaining);
+    if remaining.len() == 16 {
+        xor_in_place(&mut last_block, &k1);
+    } else {
+        last_block[remaining.len()] = 0x80;
+        xor_in_place(&mut last_block, &k2);
+    }
+
+    let mut x = [0u8; 16];
+    for block in message[..last_start].chunks_exact(16) {
+        xor_in_place(&mut x, block.try_into().expect("16 byte block"));
+        x = aes.encrypt(key, &x)?;
+    }
+
+    xor_in_place(&mut x, &last_block);
+    aes.encrypt(key, &x)
+}
+
+/// Left shift a 128-bit value by one bit, reducing by the CMAC polynomial
+fn cmac_double(value: &[u8; 16]) -> [u8; 16] {
+    let mut result = [0u8; 16];
+    for i in 0..16 {
+        let carry = if i < 15 { value[i + 1] >> 7 } else { 0 };
+        result[i] = (value[i] << 1) | carry;
+    }
+    result[15] ^= 0x87 & 0u8.wrapping_sub(value[0] >> 7);
+    result
+}
+
+fn xor_in_place(target: &mut [u8; 16], other: &[u8; 16]) {
+    for (a, b) in target.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+/// Security function e (BT Core Spec Vol 3, Part H, 2.2.1)
+///
+/// SMP carries values least significant octet first while AES works most
+/// significant octet first, so the inputs and output are byte-reversed.
+fn e(aes: &dyn AesBackend, key: &[u8; 16], plaintext: &[u8; 16]) -> SmpResult<[u8; 16]> {
+    let mut key_be = *key;
+    key_be.reverse();
+    let mut data_be = *plaintext;
+    data_be.reverse();
+
+    let mut result = aes.encrypt(&key_be, &data_be)?;
+    result.reverse();
+    Ok(result)
 }
 
 /// Function c1 for LE Legacy Pairing (BT Core Spec Vol 3, Part H, 2.2.3)
+///
+/// All values are in SMP byte order (least significant octet first), so
+/// `preq` and `pres` are the 7 byte Pairing Request and Pairing Response
+/// PDUs exactly as sent over the air.
+#[allow(clippy::too_many_arguments)]
 pub fn c1(
+    aes: &dyn AesBackend,
     temp_key: &[u8; 16],
     rand: &[u8; 16],
     preq: &[u8],
@@ -52,50 +221,57 @@ pub fn c1(
     init_addr: &[u8; 6],
     resp_addr_type: u8,
     resp_addr: &[u8; 6],
-) -> [u8; 16] {
+) -> SmpResult<[u8; 16]> {
     // p1 = pres || preq || rat || iat
     let mut p1 = [0u8; 16];
-    p1[0..7].copy_from_slice(pres);
-    p1[7..14].copy_from_slice(preq);
-    p1[14] = resp_addr_type;
-    p1[15] = init_addr_type;
+    p1[0] = init_addr_type;
+    p1[1] = resp_addr_type;
+    p1[2..9].copy_from_slice(preq);
+    p1[9..16].copy_from_slice(pres);
 
-    // p2 = pad_16(ra || ia)
+    // p2 = padding || ia || ra
     let mut p2 = [0u8; 16];
     p2[0..6].copy_from_slice(resp_addr);
     p2[6..12].copy_from_slice(init_addr);
 
-    // Calculate r' = r XOR p1
-    let mut r_prime = *rand;
-    for i in 0..16 {
-        r_prime[i] ^= p1[i];
-    }
-
-    // Calculate AES_128(k, r')
-    let mut res = aes_encrypt(temp_key, &r_prime);
-
-    // XOR with p2
-    for i in 0..16 {
-        res[i] ^= p2[i];
-    }
-
-    // Final AES_128 encryption
-    aes_encrypt(temp_key, &res)
+    // e(k, e(k, r XOR p1) XOR p2)
+    let mut res = *rand;
+    xor_in_place(&mut res, &p1);
+    let mut res = e(aes, temp_key, &res)?;
+    xor_in_place(&mut res, &p2);
+    e(aes, temp_key, &res)
 }
 
 /// Function s1 for LE Legacy Pairing (BT Core Spec Vol 3, Part H, 2.2.4)
-pub fn s1(temp_key: &[u8; 16], r1: &[u8; 16], r2: &[u8; 16]) -> [u8; 16] {
-    // r' = r1[0..8] || r2[0..8]
+///
+/// `r1` is the initiator's random value and `r2` the responder's, both in
+/// SMP byte order.
+pub fn s1(
+    aes: &dyn AesBackend,
+    temp_key: &[u8; 16],
+    r1: &[u8; 16],
+    r2: &[u8; 16],
+) -> SmpResult<[u8; 16]> {
+    // r' = r1' || r2', the least significant 64 bits of each
     let mut r_prime = [0u8; 16];
-    r_prime[0..8].copy_from_slice(&r1[0..8]);
-    r_prime[8..16].copy_from_slice(&r2[0..8]);
+    r_prime[0..8].copy_from_slice(&r2[0..8]);
+    r_prime[8..16].copy_from_slice(&r1[0..8]);
 
-    // Return AES_128(temp_key, r')
-    aes_encrypt(temp_key, &r_prime)
+    e(aes, temp_key, &r_prime)
 }
 
 /// Function f4 for LE Secure Connections (BT Core Spec Vol 3, Part H, 2.2.7)
-pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
+///
+/// Like the other Secure Connections functions built on AES-CMAC, the
+/// inputs and result are most significant octet first, as written in the
+/// specification. Values received over the air must be byte-reversed.
+pub fn f4(
+    aes: &dyn AesBackend,
+    u: &[u8; 32],
+    v: &[u8; 32],
+    x: &[u8; 16],
+    z: u8,
+) -> SmpResult<[u8; 16]> {
     // Concatenate: u || v || z (65 bytes total)
     let mut message = Vec::with_capacity(65);
     message.extend_from_slice(u);
@@ -103,17 +279,22 @@ pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
     message.push(z);
 
     // Return AES-CMAC(x, message)
-    aes_cmac(x, &message)
+    aes_cmac(aes, x, &message)
 }
 
 /// Function f5 for LE Secure Connections (BT Core Spec Vol 3, Part H, 2.2.8)
+///
+/// Derives `(MacKey, LTK)` from the DHKey `w`, the nonces `n1` and `n2` and
+/// the 56-bit addresses `a1` and `a2` (address type followed by the
+/// address). All values are most significant octet first.
 pub fn f5(
+    aes: &dyn AesBackend,
     w: &[u8; 32],
     n1: &[u8; 16],
     n2: &[u8; 16],
     a1: &[u8; 7],
     a2: &[u8; 7],
-) -> ([u8; 16], [u8; 16]) {
+) -> SmpResult<([u8; 16], [u8; 16])> {
     // Salt for f5
     let salt = [
         0x6C, 0x88, 0x83, 0x91, 0xAA, 0xF5, 0xA5, 0x38, 0x60, 0x37, 0x0B, 0xDB, 0x5A, 0x60, 0x83,
@@ -121,41 +302,35 @@ pub fn f5(
     ];
 
     // Calculate T = AES-CMAC(salt, w)
-    let t = aes_cmac(&salt, w);
-
-    // Calculate MacKey and LTK using T as the key
-
-    // Counter for MacKey = 0, keyID = "btle"
-    let mut mac_key_msg = Vec::with_capacity(53);
-    mac_key_msg.push(0); // Counter = 0
-    mac_key_msg.extend_from_slice(b"btle"); // keyID = "btle"
-    mac_key_msg.extend_from_slice(a2); // a2
-    mac_key_msg.extend_from_slice(a1); // a1
-    mac_key_msg.extend_from_slice(n2); // n2
-    mac_key_msg.extend_from_slice(n1); // n1
-    mac_key_msg.push(1); // Length = 1
-
-    // MacKey = AES-CMAC(T, mac_key_msg)
-    let mac_key = aes_cmac(&t, &mac_key_msg);
-
-    // Counter for LTK = 1, keyID = "btle"
-    let mut ltk_msg = Vec::with_capacity(53);
-    ltk_msg.push(1); // Counter = 1
-    ltk_msg.extend_from_slice(b"btle"); // keyID = "btle"
-    ltk_msg.extend_from_slice(a2); // a2
-    ltk_msg.extend_from_slice(a1); // a1
-    ltk_msg.extend_from_slice(n2); // n2
-    ltk_msg.extend_from_slice(n1); // n1
-    ltk_msg.push(1); // Length = 1
-
-    // LTK = AES-CMAC(T, ltk_msg)
-    let ltk = aes_cmac(&t, &ltk_msg);
-
-    (mac_key, ltk)
+    let t = aes_cmac(aes, &salt, w)?;
+
+    // Counter || keyID || N1 || N2 || A1 || A2 || Length (53 bytes total)
+    let message = |counter: u8| {
+        let mut message = Vec::with_capacity(53);
+        message.push(counter);
+        message.extend_from_slice(b"btle");
+        message.extend_from_slice(n1);
+        message.extend_from_slice(n2);
+        message.extend_from_slice(a1);
+        message.extend_from_slice(a2);
+        message.extend_from_slice(&256u16.to_be_bytes());
+        message
+    };
+
+    // MacKey uses counter 0, LTK counter 1
+    let mac_key = aes_cmac(aes, &t, &message(0))?;
+    let ltk = aes_cmac(aes, &t, &message(1))?;
+
+    Ok((mac_key, ltk))
 }
 
 /// Function f6 for LE Secure Connections (BT Core Spec Vol 3, Part H, 2.2.9)
+///
+/// Computes the DHKey check value. `io_cap` is AuthReq || OOB data flag ||
+/// IO capability. All values are most significant octet first.
+#[allow(clippy::too_many_arguments)]
 pub fn f6(
+    aes: &dyn AesBackend,
     w: &[u8; 16],
     n1: &[u8; 16],
     n2: &[u8; 16],
@@ -163,7 +338,7 @@ pub fn f6(
     io_cap: &[u8; 3],
     a1: &[u8; 7],
     a2: &[u8; 7],
-) -> [u8; 16] {
+) -> SmpResult<[u8; 16]> {
     // Concatenate: n1 || n2 || r || io_cap || a1 || a2 (65 bytes total)
     let mut message = Vec::with_capacity(65);
     message.extend_from_slice(n1);
@@ -174,45 +349,48 @@ pub fn f6(
     message.extend_from_slice(a2);
 
     // Return AES-CMAC(w, message)
-    aes_cmac(w, &message)
+    aes_cmac(aes, w, &message)
 }
 
 /// Function g2 for LE Secure Connections (BT Core Spec Vol 3, Part H, 2.2.10)
-pub fn g2(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], y: &[u8; 16]) -> u32 {
+///
+/// Returns the six digit numeric comparison value. All inputs are most
+/// significant octet first.
+pub fn g2(
+    aes: &dyn AesBackend,
+    u: &[u8; 32],
+    v: &[u8; 32],
+    x: &[u8; 16],
+    y: &[u8; 16],
+) -> SmpResult<u32> {
     // Concatenate: u || v || y (80 bytes total)
     let mut message = Vec::with_capacity(80);
     message.extend_from_slice(u);
     message.extend_from_slice(v);
     message.extend_from_slice(y);
 
-    // Calculate AES-CMAC(x, message)
-    let cmac = aes_cmac(x, &message);
-
-    // Return 32 LSB - extract the last 4 bytes and convert to u32
-    let mut passkey = 0u32;
-    for i in 0..4 {
-        passkey |= (cmac[12 + i] as u32) << (8 * i);
-    }
+    // g2 is AES-CMAC(x, message) mod 2^32, the last four octets
+    let cmac = aes_cmac(aes, x, &message)?;
+    let value = u32::from_be_bytes(cmac[12..16].try_into().expect("4 byte tail"));
 
     // Return only 6 decimal digits
-    passkey % 1_000_000
+    Ok(value % 1_000_000)
 }
 
-/// AES-128 encrypt function
-pub fn aes_encrypt(key: &[u8; 16], data: &[u8; 16]) -> [u8; 16] {
-    // In a real implementation, this would use a crypto library
-    // For this example, we'll return a placeholder value
-
-    // Placeholder for real implementation
-    // In reality, this would perform AES-128 encryption
-
-    let mut output = [0u8; 16];
-    // Simple XOR for demonstration (NOT secure, just a placeholder)
-    for i in 0..16 {
-        output[i] = data[i] ^ key[i];
-    }
+/// Link key conversion function h6 (BT Core Spec Vol 3, Part H, 2.2.11)
+///
+/// Computes AES-CMAC(w, keyID). Used for cross-transport key derivation,
+/// with `keyid` given as the four ASCII octets, e.g. `b"tmp1"`.
+pub fn h6(aes: &dyn AesBackend, w: &[u8; 16], keyid: &[u8; 4]) -> SmpResult<[u8; 16]> {
+    aes_cmac(aes, w, keyid)
+}
 
-    output
+/// Link key conversion function h7 (BT Core Spec Vol 3, Part H, 2.2.12)
+///
+/// Computes AES-CMAC(salt, w). Replaces the first h6 step of cross-transport
+/// key derivation when both devices support CT2.
+pub fn h7(aes: &dyn AesBackend, salt: &[u8; 16], w: &[u8; 16]) -> SmpResult<[u8; 16]> {
+    aes_cmac(aes, salt, w)
 }
 
 /// Generate DHKey from our private key and remote public key
@@ -236,6 +414,17 @@ pub fn generate_keypair() -> ([u8; 32], [u8; 64]) {
     (private_key, public_key)
 }
 
+/// Diversifying function d1 (BT Core Spec Vol 3, Part H, Appendix B.2.1)
+///
+/// Computes e(k, padding || r || d). Used to derive the IRK from the
+/// identity root as d1(IR, 1, 0). `k` and the result are in SMP byte order.
+pub fn d1(aes: &dyn AesBackend, k: &[u8; 16], d: u16, r: u16) -> SmpResult<[u8; 16]> {
+    let mut d_prime = [0u8; 16];
+    d_prime[0..2].copy_from_slice(&d.to_le_bytes());
+    d_prime[2..4].copy_from_slice(&r.to_le_bytes());
+    e(aes, k, &d_prime)
+}
+
 /// Generate a local Identity Resolving Key (IRK)
 pub fn generate_irk() -> [u8; 16] {
     generate_random_128()
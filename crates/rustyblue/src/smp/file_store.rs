@@ -0,0 +1,174 @@
+//! File backed key storage for Security Manager Protocol
+//!
+//! `FileKeyStore` keeps bonds in a JSON file so they survive a restart.
+//! The file holds the serde representation of each device's address and
+//! `DeviceKeys`:
+//!
+//! ```json
+//! {"devices": [{"address": {"bytes": [6, 5, 4, 3, 2, 1]},
+//!   "ltk": {"key": [...], "ediv": 4660, "rand": [...],
+//!   "secure_connections": false, "authenticated": true},
+//!   "local_ltk": null, "irk": null, "local_csrk": null,
+//!   "remote_csrk": null, "link_key": null}]}
+//! ```
+
+use super::keys::*;
+use super::types::*;
+use crate::gap::BdAddr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Key store that persists bonds to a JSON file
+///
+/// The whole file is rewritten on every change. It is written to
+/// `<path>.tmp` and synced first, then renamed over `<path>` and the
+/// directory synced, so a crash leaves either the old or the new file
+/// behind. A change that cannot be written is not kept in memory either.
+/// On Unix the file is only readable by its owner.
+#[derive(Debug)]
+pub struct FileKeyStore {
+    /// Path of the JSON file
+    path: PathBuf,
+    /// Device key storage
+    keys: HashMap<BdAddr, DeviceKeys>,
+}
+
+/// Contents of the key store file
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    devices: Vec<StoredDevice>,
+}
+
+/// The keys of one bonded device
+#[derive(Serialize, Deserialize)]
+struct StoredDevice {
+    address: BdAddr,
+    #[serde(flatten)]
+    keys: DeviceKeys,
+}
+
+impl FileKeyStore {
+    /// Open a key store, loading the bonds already saved at `path`
+    ///
+    /// A missing file is an empty key store; it is created on the first
+    /// save.
+    pub fn open(path: impl AsRef<Path>) -> SmpResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let keys = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let file: StoreFile = serde_json::from_str(&contents)
+                    .map_err(|e| SmpError::IoError(format!("Invalid key store file: {}", e)))?;
+                file.devices
+                    .into_iter()
+                    .map(|device| (device.address, device.keys))
+                    .collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(SmpError::IoError(e.to_string())),
+        };
+
+        Ok(Self { path, keys })
+    }
+
+    /// Path of the JSON file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write all bonds to the file
+    fn persist(&self) -> SmpResult<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        // Sort by address so the file does not change needlessly
+        let mut devices: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(address, keys)| StoredDevice {
+                address: *address,
+                keys: keys.clone(),
+            })
+            .collect();
+        devices.sort_by_key(|device| device.address.bytes);
+        let contents = serde_json::to_string_pretty(&StoreFile { devices })
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
+
+        // A leftover file would keep its permissions, so start afresh
+        let _ = fs::remove_file(&tmp_path);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options
+            .open(&tmp_path)
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
+        file.write_all(contents.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| SmpError::IoError(e.to_string()))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| SmpError::IoError(e.to_string()))?;
+
+        // The rename itself is only durable once the directory is synced
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| SmpError::IoError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Undo a change to `address` after it failed to persist
+    fn restore(&mut self, address: &BdAddr, previous: Option<DeviceKeys>) {
+        match previous {
+            Some(keys) => self.keys.insert(*address, keys),
+            None => self.keys.remove(address),
+        };
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn save_keys(&mut self, address: &BdAddr, keys: &DeviceKeys) -> SmpResult<()> {
+        let previous = self.keys.insert(*address, keys.clone());
+        if let Err(e) = self.persist() {
+            self.restore(address, previous);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn load_keys(&self, address: &BdAddr) -> SmpResult<Option<DeviceKeys>> {
+        Ok(self.keys.get(address).cloned())
+    }
+
+    fn delete_keys(&mut self, address: &BdAddr) -> SmpResult<()> {
+        if let Some(previous) = self.keys.remove(address) {
+            if let Err(e) = self.persist() {
+                self.restore(address, Some(previous));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_identity(&self, _random_address: &BdAddr) -> SmpResult<Option<BdAddr>> {
+        // Resolution is not implemented yet, as in MemoryKeyStore
+        Ok(None)
+    }
+
+    fn get_paired_devices(&self) -> SmpResult<Vec<BdAddr>> {
+        Ok(self.keys.keys().cloned().collect())
+    }
+}
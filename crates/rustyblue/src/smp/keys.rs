@@ -7,10 +7,11 @@
 use super::types::*;
 use crate::gap::BdAddr;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 
 /// Long Term Key (LTK) information
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LongTermKey {
     /// Key value
     pub key: [u8; 16],
@@ -54,8 +55,11 @@ impl LongTermKey {
     }
 
     /// Get the security level provided by this key
+    ///
+    /// Secure Connections only counts for authenticated keys; an
+    /// unauthenticated key gives encryption only.
     pub fn security_level(&self) -> SecurityLevel {
-        if self.secure_connections {
+        if self.secure_connections && self.authenticated {
             SecurityLevel::SecureConnections
         } else if self.authenticated {
             SecurityLevel::EncryptionWithAuthentication
@@ -67,6 +71,7 @@ impl LongTermKey {
 
 /// Identity Resolving Key (IRK)
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IdentityResolvingKey {
     /// Key value
     pub key: [u8; 16],
@@ -89,6 +94,7 @@ impl IdentityResolvingKey {
 
 /// Connection Signature Resolving Key (CSRK)
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectionSignatureResolvingKey {
     /// Key value
     pub key: [u8; 16],
@@ -117,9 +123,12 @@ impl ConnectionSignatureResolvingKey {
 
 /// Device keys containing all security keys for a device
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeviceKeys {
     /// Long Term Key
     pub ltk: Option<LongTermKey>,
+    /// Long Term Key distributed by the local device
+    pub local_ltk: Option<LongTermKey>,
     /// Identity Resolving Key
     pub irk: Option<IdentityResolvingKey>,
     /// Local Connection Signature Resolving Key
@@ -130,11 +139,18 @@ pub struct DeviceKeys {
     pub link_key: Option<[u8; 16]>,
 }
 
+impl Default for DeviceKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DeviceKeys {
     /// Create a new empty device keys structure
     pub fn new() -> Self {
         Self {
             ltk: None,
+            local_ltk: None,
             irk: None,
             local_csrk: None,
             remote_csrk: None,
@@ -154,6 +170,7 @@ impl DeviceKeys {
     /// Check if any keys are stored
     pub fn has_keys(&self) -> bool {
         self.ltk.is_some()
+            || self.local_ltk.is_some()
             || self.irk.is_some()
             || self.local_csrk.is_some()
             || self.remote_csrk.is_some()
@@ -213,11 +230,8 @@ impl KeyStore for MemoryKeyStore {
         Ok(())
     }
 
-    fn resolve_identity(&self, random_address: &BdAddr) -> SmpResult<Option<BdAddr>> {
+    fn resolve_identity(&self, _random_address: &BdAddr) -> SmpResult<Option<BdAddr>> {
         // This would actually perform the cryptographic resolution
-        // For now we just do a simple lookup
-        let store = self.keys.read().unwrap();
-
         // In a real implementation, we would use the IRK to resolve random addresses
         // Here we're just returning None as a placeholder
         Ok(None)
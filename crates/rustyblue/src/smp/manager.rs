@@ -8,11 +8,11 @@ use super::crypto::*;
 use super::keys::*;
 use super::pairing::*;
 use super::types::*;
-use crate::gap::BdAddr;
-use crate::hci::{HciCommand, HciEvent, HciSocket};
-use crate::l2cap::{
-    L2capChannel, L2capError, L2capManager, L2capResult, SecurityLevel as L2capSecurityLevel,
-}; // Import L2cap SecurityLevel
+use crate::error::HciError;
+use crate::gap::{BdAddr, PUBLIC_DEVICE_ADDRESS, RANDOM_DEVICE_ADDRESS};
+use crate::hci::constants::{hci_opcode, EVT_CMD_STATUS};
+use crate::hci::{HciCommand, HciEvent, HciSocket, LeConnectionEvent, ParsedHciEvent};
+use crate::l2cap::L2capManager;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
@@ -37,9 +37,16 @@ pub struct SmpManager {
     /// Security levels of connected devices
     security_levels: RwLock<HashMap<BdAddr, SecurityLevel>>,
 
+    /// Peer addresses of connections SMP has been used on, by HCI handle
+    handle_to_addr: RwLock<HashMap<u16, BdAddr>>,
+
     /// Event callback
     event_callback: Mutex<Option<SmpEventCallback>>,
 
+    /// Channels of event subscribers
+    #[cfg(feature = "tokio")]
+    event_subscribers: Mutex<Vec<tokio::sync::mpsc::UnboundedSender<SmpEvent>>>,
+
     /// Passkey callback
     passkey_callback: Mutex<Option<PasskeyCallback>>,
 
@@ -57,6 +64,36 @@ pub struct SmpManager {
 
     /// Local OOB data
     local_oob_data: RwLock<Option<OobData>>,
+
+    /// Key pair the local OOB data commits to, reused for Secure Connections
+    local_oob_keypair: RwLock<Option<([u8; 32], [u8; 64])>>,
+
+    /// Identity root (IR) the local IRK is derived from
+    identity_root: [u8; 16],
+
+    /// Local identity address sent with the IRK
+    local_identity: Option<IdentityAddressInfo>,
+
+    /// Source of the random numbers used for pairing
+    rng: RwLock<Arc<dyn SecureRng>>,
+
+    /// AES-128 implementation used by the security functions
+    aes: RwLock<Arc<dyn AesBackend>>,
+
+    /// Local address type and address, read from the controller if not set
+    local_address: RwLock<Option<(u8, BdAddr)>>,
+
+    /// Addresses used on each LE connection, by remote device
+    link_addresses: RwLock<HashMap<BdAddr, LinkAddresses>>,
+}
+
+/// Addresses of an LE connection as (address type, address), as used on air
+#[derive(Debug, Clone, Copy)]
+struct LinkAddresses {
+    /// Local address, if the controller used a resolvable private address
+    local: Option<(u8, BdAddr)>,
+    /// Remote address
+    remote: (u8, BdAddr),
 }
 
 impl SmpManager {
@@ -66,6 +103,16 @@ impl SmpManager {
         hci_socket: Arc<HciSocket>,
         key_store: KeyStoreHandle,
     ) -> Self {
+        // The controller already does the crypto when software AES is off
+        #[cfg(feature = "software-aes")]
+        let (rng, aes): (Arc<dyn SecureRng>, Arc<dyn AesBackend>) =
+            (Arc::new(OsSecureRng), Arc::new(SoftwareAes));
+        #[cfg(not(feature = "software-aes"))]
+        let (rng, aes): (Arc<dyn SecureRng>, Arc<dyn AesBackend>) = (
+            Arc::new(HciSecureRng::new(hci_socket.clone())),
+            Arc::new(ControllerAes::new(hci_socket.clone())),
+        );
+
         // Default features
         let features = PairingFeatures {
             io_capability: IoCapability::NoInputNoOutput,
@@ -80,14 +127,123 @@ impl SmpManager {
             features,
             pairing_processes: RwLock::new(HashMap::new()),
             security_levels: RwLock::new(HashMap::new()),
+            handle_to_addr: RwLock::new(HashMap::new()),
             event_callback: Mutex::new(None),
+            #[cfg(feature = "tokio")]
+            event_subscribers: Mutex::new(Vec::new()),
             passkey_callback: Mutex::new(None),
             comparison_callback: Mutex::new(None),
             key_store: RwLock::new(key_store),
             l2cap_manager,
             hci_socket,
             local_oob_data: RwLock::new(None),
+            local_oob_keypair: RwLock::new(None),
+            identity_root: generate_random_128(),
+            local_identity: None,
+            rng: RwLock::new(rng),
+            aes: RwLock::new(aes),
+            local_address: RwLock::new(None),
+            link_addresses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Set the local address used on LE connections
+    ///
+    /// Secure Connections mixes both addresses into the DHKey check. Defaults
+    /// to the controller's public address (HCI Read BD_ADDR); set this when
+    /// advertising or connecting with a random address. A resolvable
+    /// private address reported in the connection event takes precedence.
+    pub fn set_local_address(&self, addr_type: u8, bd_addr: BdAddr) {
+        *self.local_address.write().unwrap() = Some((addr_type, bd_addr));
+    }
+
+    /// Get the local address, reading it from the controller the first time
+    fn local_address(&self) -> SmpResult<(u8, BdAddr)> {
+        if let Some(address) = *self.local_address.read().unwrap() {
+            return Ok(address);
         }
+
+        let bd_addr = self
+            .hci_socket
+            .read_bd_addr()
+            .map_err(|e| SmpError::HciError(e.to_string()))?;
+        let address = (PUBLIC_DEVICE_ADDRESS, bd_addr);
+        *self.local_address.write().unwrap() = Some(address);
+        Ok(address)
+    }
+
+    /// Get the (local, remote) addresses of the connection to a device
+    ///
+    /// Without an LE Connection Complete event for the connection, the remote
+    /// device is assumed to use a public address.
+    fn connection_addresses(
+        &self,
+        remote_addr: &BdAddr,
+    ) -> SmpResult<((u8, BdAddr), (u8, BdAddr))> {
+        let link = self
+            .link_addresses
+            .read()
+            .unwrap()
+            .get(remote_addr)
+            .copied();
+        let local = match link.and_then(|link| link.local) {
+            Some(local) => local,
+            None => self.local_address()?,
+        };
+        let remote = link.map_or((PUBLIC_DEVICE_ADDRESS, *remote_addr), |link| link.remote);
+        Ok((local, remote))
+    }
+
+    /// Record the handle and addresses of a new LE connection
+    fn handle_connection_complete(&self, connection: &LeConnectionEvent) {
+        if connection.status != 0 {
+            return;
+        }
+        self.register_connection(connection.handle, connection.peer_address);
+
+        // Resolved addresses were carried on air as resolvable private ones
+        let remote = match connection.peer_resolvable_private_address {
+            Some(rpa) => (RANDOM_DEVICE_ADDRESS, rpa),
+            None => (connection.peer_address_type & 0x01, connection.peer_address),
+        };
+        let local = connection
+            .local_resolvable_private_address
+            .map(|rpa| (RANDOM_DEVICE_ADDRESS, rpa));
+
+        let mut link_addresses = self.link_addresses.write().unwrap();
+        link_addresses.insert(connection.peer_address, LinkAddresses { local, remote });
+    }
+
+    /// Set the AES-128 implementation used for pairing and key derivation
+    ///
+    /// Defaults to `SoftwareAes` with the `software-aes` feature and to
+    /// `ControllerAes` on this manager's HCI socket otherwise.
+    pub fn set_aes_backend(&self, aes: Arc<dyn AesBackend>) {
+        *self.aes.write().unwrap() = aes;
+    }
+
+    /// Get the AES-128 implementation
+    fn aes(&self) -> Arc<dyn AesBackend> {
+        self.aes.read().unwrap().clone()
+    }
+
+    /// Set the source of the random numbers used for pairing
+    ///
+    /// Defaults to `HciSecureRng` when the controller does AES-128, i.e.
+    /// without the `software-aes` feature, and to `OsSecureRng` otherwise.
+    pub fn set_rng(&self, rng: Arc<dyn SecureRng>) {
+        *self.rng.write().unwrap() = rng;
+    }
+
+    /// Generate a 128-bit random number for pairing
+    ///
+    /// Falls back to the operating system if the configured source fails,
+    /// e.g. because the controller doesn't answer LE Rand.
+    fn random_128(&self) -> [u8; 16] {
+        let rng = self.rng.read().unwrap().clone();
+        rng.random_128()
+            .or_else(|_| OsSecureRng.random_128())
+            .unwrap_or_else(|_| generate_random_128())
     }
 
     /// Set the event callback
@@ -99,6 +255,19 @@ impl SmpManager {
         *event_callback = Some(Arc::new(Mutex::new(callback)));
     }
 
+    /// Subscribe to SMP events through a channel
+    ///
+    /// Every subscriber receives all events. Unlike the event callback, a
+    /// slow subscriber never blocks SMP processing. The subscription ends
+    /// when the receiver is dropped.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe_events(&self) -> tokio::sync::mpsc::UnboundedReceiver<SmpEvent> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut subscribers = self.event_subscribers.lock().unwrap();
+        subscribers.push(sender);
+        receiver
+    }
+
     /// Set the passkey callback
     pub fn set_passkey_callback<F>(&self, callback: F)
     where
@@ -117,6 +286,27 @@ impl SmpManager {
         *comparison_callback = Some(Arc::new(Mutex::new(callback)));
     }
 
+    /// Abort pairing when the underlying HCI connection closes
+    ///
+    /// Registers a connection closed callback on the L2CAP manager that
+    /// removes any pairing process for the address mapped to the closed handle.
+    pub fn register_connection_closed_handler(self: &Arc<Self>) {
+        let manager = Arc::downgrade(self);
+        self.l2cap_manager
+            .set_connection_closed_callback(Box::new(move |hci_handle| {
+                if let Some(manager) = manager.upgrade() {
+                    if let Some(addr) = manager.l2cap_manager.peer_address(hci_handle) {
+                        let mut pairing_processes = manager.pairing_processes.write().unwrap();
+                        pairing_processes.remove(&addr);
+                    }
+                    let mut handle_to_addr = manager.handle_to_addr.write().unwrap();
+                    if let Some(addr) = handle_to_addr.remove(&hci_handle) {
+                        manager.link_addresses.write().unwrap().remove(&addr);
+                    }
+                }
+            }));
+    }
+
     /// Set local device features
     pub fn set_features(&mut self, features: PairingFeatures) {
         self.features = features;
@@ -142,25 +332,93 @@ impl SmpManager {
         self.features.auth_req = auth_req;
     }
 
-    /// Generate local OOB data
-    pub fn generate_oob_data(&self) -> SmpResult<OobData> {
-        let r = generate_random_128();
+    /// Set the identity root (IR) the local IRK is derived from
+    ///
+    /// A random IR is generated on creation. Set a persistent one so that the
+    /// IRK handed to peers stays the same across restarts.
+    pub fn set_identity_root(&mut self, identity_root: [u8; 16]) {
+        self.identity_root = identity_root;
+    }
 
-        // In a real implementation, we would calculate c properly
-        // For now, we'll just use zeros
-        let c = [0u8; 16];
+    /// Set the local identity address distributed along with the IRK
+    pub fn set_local_identity_address(&mut self, addr_type: u8, bd_addr: BdAddr) {
+        self.local_identity = Some(IdentityAddressInfo { addr_type, bd_addr });
+    }
 
-        let oob_data = OobData { r, c };
+    /// Generate local OOB data
+    ///
+    /// A new key pair is generated and `c = f4(PKx, PKx, r, 0)` is computed
+    /// over its public key. The key pair is used for the next Secure
+    /// Connections pairing so the peer can check it against the OOB data.
+    pub fn generate_oob_data(&self) -> SmpResult<OobData> {
+        let (private_key, public_key) = generate_keypair();
+        let oob_data = OobData::new(self.aes().as_ref(), &public_key, self.random_128())?;
 
         // Store locally
+        *self.local_oob_keypair.write().unwrap() = Some((private_key, public_key));
         let mut local_oob_data = self.local_oob_data.write().unwrap();
         *local_oob_data = Some(oob_data.clone());
 
         Ok(oob_data)
     }
 
+    /// Derive an LE Long Term Key from a BR/EDR link key
+    ///
+    /// Implements cross-transport key derivation (BT Core Spec Vol 3,
+    /// Part H, 2.4.2.4). The intermediate key is `h7(SALT, link key)` if CT2
+    /// is enabled and `h6(link key, "tmp2")` otherwise, and the LTK is
+    /// `h6(ILK, "brle")`. Only a Secure Connections link key can be
+    /// converted, so legacy pairing is rejected.
+    pub fn derive_le_ltk_from_breder(&self, link_key: &[u8; 16]) -> SmpResult<[u8; 16]> {
+        let auth_req = &self.features.auth_req;
+        if !auth_req.secure_connections {
+            return Err(SmpError::AuthenticationRequirements);
+        }
+
+        let aes = self.aes();
+        let ilk = if auth_req.ct2 {
+            let mut salt = [0u8; 16];
+            salt[12..].copy_from_slice(b"tmp2");
+            h7(aes.as_ref(), &salt, link_key)?
+        } else {
+            h6(aes.as_ref(), link_key, b"tmp2")?
+        };
+
+        h6(aes.as_ref(), &ilk, b"brle")
+    }
+
+    /// Get the key pair for a Secure Connections pairing
+    ///
+    /// This is the one committed to by the local OOB data if there is any,
+    /// otherwise a fresh one.
+    fn local_keypair(&self) -> ([u8; 32], [u8; 64]) {
+        match *self.local_oob_keypair.read().unwrap() {
+            Some(keypair) => keypair,
+            None => generate_keypair(),
+        }
+    }
+
+    /// Record the HCI handle of the connection to a remote device
+    ///
+    /// Encryption events only carry the connection handle, so this is how
+    /// they are attributed to a device. Pairing records the handle known to
+    /// the L2CAP manager automatically.
+    pub fn register_connection(&self, hci_handle: u16, remote_addr: BdAddr) {
+        let mut handle_to_addr = self.handle_to_addr.write().unwrap();
+        handle_to_addr.insert(hci_handle, remote_addr);
+    }
+
+    /// Record the HCI handle the L2CAP manager has for a remote device
+    fn remember_connection(&self, remote_addr: BdAddr) {
+        if let Some(hci_handle) = self.l2cap_manager.connection_handle(&remote_addr) {
+            self.register_connection(hci_handle, remote_addr);
+        }
+    }
+
     /// Initiate pairing with a remote device
     pub fn initiate_pairing(&self, remote_addr: BdAddr) -> SmpResult<()> {
+        self.remember_connection(remote_addr);
+
         // Check if we're already pairing with this device
         {
             let pairing_processes = self.pairing_processes.read().unwrap();
@@ -213,6 +471,123 @@ impl SmpManager {
         self.initiate_pairing(remote_addr)
     }
 
+    /// Distribute the local keys to the remote device
+    ///
+    /// Sends the keys agreed for the local role during pairing feature
+    /// exchange, in the order required by the specification: Encryption
+    /// Information and Master Identification (LE legacy pairing only),
+    /// Identity Information and Identity Address Information, then Signing
+    /// Information. A fresh LTK with random EDIV and RAND and a fresh CSRK are
+    /// generated, and the IRK is derived from the identity root. The
+    /// distributed keys are saved in the key store.
+    pub fn distribute_keys(&self, remote_addr: BdAddr) -> SmpResult<()> {
+        let (dist, secure_connections, authenticated) = {
+            let pairing_processes = self.pairing_processes.read().unwrap();
+            let process = pairing_processes
+                .get(&remote_addr)
+                .ok_or(SmpError::InvalidState)?;
+            let remote_features = process
+                .remote_features
+                .as_ref()
+                .ok_or(SmpError::InvalidState)?;
+
+            // Only keys both sides asked for are distributed
+            let (local_dist, remote_dist) = match process.role {
+                PairingRole::Initiator => (
+                    process.local_features.initiator_key_dist,
+                    remote_features.initiator_key_dist,
+                ),
+                PairingRole::Responder => (
+                    process.local_features.responder_key_dist,
+                    remote_features.responder_key_dist,
+                ),
+            };
+
+            (
+                KeyDistribution::from_u8(local_dist.to_u8() & remote_dist.to_u8()),
+                process.secure_connections,
+                !matches!(process.method, Some(PairingMethod::JustWorks) | None),
+            )
+        };
+
+        // The IRK must be followed by the identity address
+        let local_identity = if dist.identity_key {
+            let identity = self.local_identity.clone().ok_or_else(|| {
+                SmpError::InvalidParameter("No local identity address set".into())
+            })?;
+            Some(identity)
+        } else {
+            None
+        };
+
+        let mut keys = {
+            let key_store = self.key_store.read().unwrap();
+            key_store
+                .load_keys(&remote_addr)?
+                .unwrap_or_else(DeviceKeys::new)
+        };
+
+        // With Secure Connections the LTK is derived on both sides instead
+        if dist.encryption_key && !secure_connections {
+            let ltk = self.random_128();
+            let ediv = rand::random::<u16>();
+            let mut rand = [0u8; 8];
+            rand.copy_from_slice(&generate_random(8));
+
+            self.send_encryption_information(remote_addr, ltk)?;
+            self.send_master_identification(remote_addr, ediv, rand)?;
+            keys.local_ltk = Some(LongTermKey::new(ltk, ediv, rand, false, authenticated));
+        }
+
+        if let Some(identity) = local_identity {
+            let irk = d1(self.aes().as_ref(), &self.identity_root, 1, 0)?;
+            self.send_identity_information(remote_addr, irk)?;
+            self.send_identity_address_information(
+                remote_addr,
+                identity.addr_type,
+                identity.bd_addr,
+            )?;
+        }
+
+        if dist.signing_key {
+            let csrk = generate_csrk();
+            self.send_signing_information(remote_addr, csrk)?;
+            keys.local_csrk = Some(ConnectionSignatureResolvingKey::new(csrk, authenticated));
+        }
+
+        let mut key_store = self.key_store.write().unwrap();
+        key_store.save_keys(&remote_addr, &keys)
+    }
+
+    /// Describe an in-flight pairing process, for diagnosing failures
+    ///
+    /// Returns `None` if no pairing with the device is in progress. Key
+    /// material is redacted unless `RUSTYBLUE_SHOW_KEYS=1` is set in a
+    /// debug build.
+    pub fn dump_pairing_state(&self, addr: &BdAddr) -> Option<String> {
+        let pairing_processes = self.pairing_processes.read().unwrap();
+        let process = pairing_processes.get(addr)?;
+
+        let method = match process.method {
+            Some(method) => format!("{:?}", method),
+            None => "not selected".into(),
+        };
+        Some(format!(
+            "Pairing with {} as {:?}: state {:?}, method {}, {}, running for {:?}\n{:#?}",
+            addr,
+            process.role,
+            process.state,
+            method,
+            if process.secure_connections {
+                "Secure Connections"
+            } else {
+                "legacy pairing"
+            },
+            process.timestamp.elapsed(),
+            process
+        ))
+    }
+
     /// Check if a device is paired
     pub fn is_paired(&self, remote_addr: &BdAddr) -> SmpResult<bool> {
         let key_store = self.key_store.read().unwrap();
@@ -285,12 +660,183 @@ impl SmpManager {
     }
 
     /// Handle an HCI event
+    ///
+    /// Encryption Change and Encryption Key Refresh Complete events update
+    /// the security level of the device on that connection and fire
+    /// `SmpEvent::SecurityLevelChanged`. LE (Enhanced) Connection Complete
+    /// events record the connection's handle and addresses for pairing.
+    /// Other events are ignored.
     pub fn handle_hci_event(&self, event: &HciEvent) -> SmpResult<()> {
-        // Handle encryption changed event
-        // Handle encryption key refresh event
-        // Handle other relevant HCI events
+        let (status, handle, enabled) = match event.parsed() {
+            Some(ParsedHciEvent::LeConnectionComplete(connection)) => {
+                self.handle_connection_complete(&connection);
+                return Ok(());
+            }
+            Some(ParsedHciEvent::EncryptionChange(change)) => {
+                (change.status, change.handle, change.enabled != 0)
+            }
+            Some(ParsedHciEvent::EncryptionKeyRefreshComplete { status, handle }) => {
+                (status, handle, true)
+            }
+            _ => return Ok(()),
+        };
 
-        Ok(())
+        // A failed change leaves the previous encryption state in place
+        if status != 0 {
+            return Ok(());
+        }
+
+        let remote_addr = {
+            let handle_to_addr = self.handle_to_addr.read().unwrap();
+            handle_to_addr.get(&handle).copied()
+        };
+        let Some(remote_addr) = remote_addr.or_else(|| self.l2cap_manager.peer_address(handle))
+        else {
+            return Ok(());
+        };
+
+        let security_level = if enabled {
+            self.encrypted_security_level(&remote_addr)?
+        } else {
+            SecurityLevel::None
+        };
+
+        {
+            let mut security_levels = self.security_levels.write().unwrap();
+            security_levels.insert(remote_addr, security_level);
+        }
+
+        self.notify_event(SmpEvent::SecurityLevelChanged(remote_addr, security_level))
+    }
+
+    /// Encrypt the link to a device (HCI LE Enable Encryption)
+    ///
+    /// Uses the STK or LTK of a pairing that just finished, otherwise the LTK
+    /// stored for the device. Blocks until the controller reports the
+    /// Encryption Change for the connection, reading events from the HCI
+    /// socket; events for other connections are discarded, so nothing else
+    /// should read the socket meanwhile. Only the central can start
+    /// encryption.
+    pub fn start_encryption(&self, remote_addr: BdAddr) -> SmpResult<()> {
+        let handle = {
+            let handle_to_addr = self.handle_to_addr.read().unwrap();
+            handle_to_addr
+                .iter()
+                .find(|(_, addr)| **addr == remote_addr)
+                .map(|(handle, _)| *handle)
+        };
+        let handle = handle
+            .or_else(|| self.l2cap_manager.connection_handle(&remote_addr))
+            .ok_or(SmpError::ConnectionNotFound)?;
+
+        let (ltk, ediv, rand) = self.encryption_key(&remote_addr)?;
+        let command = HciCommand::LeEnableEncryption {
+            handle,
+            rand,
+            ediv,
+            ltk,
+        };
+        let (ogf, ocf) = command.opcode_parts();
+        let opcode = hci_opcode(ogf, ocf);
+        self.hci_socket
+            .send_command(&command)
+            .map_err(|e| SmpError::HciError(e.to_string()))?;
+
+        let deadline = Instant::now() + Duration::from_millis(SMP_TIMEOUT_GENERAL);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(SmpError::Timeout);
+            }
+
+            let event = match self.hci_socket.read_event_timeout(Some(remaining)) {
+                Ok(event) => event,
+                Err(HciError::ReceiveError(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    return Err(SmpError::Timeout)
+                }
+                Err(e) => return Err(SmpError::HciError(e.to_string())),
+            };
+
+            // The controller rejects the command with a failed Command Status
+            if event.event_code == EVT_CMD_STATUS
+                && event.parameters.len() >= 4
+                && u16::from_le_bytes([event.parameters[2], event.parameters[3]]) == opcode
+            {
+                match event.get_status() {
+                    0 => continue,
+                    status => {
+                        return Err(SmpError::HciError(format!(
+                            "LE Enable Encryption failed with status 0x{:02X}",
+                            status
+                        )))
+                    }
+                }
+            }
+
+            if let Some(ParsedHciEvent::EncryptionChange(change)) = event.parsed() {
+                if change.handle != handle {
+                    continue;
+                }
+
+                self.handle_hci_event(&event)?;
+                return match (change.status, change.enabled) {
+                    (0, 0) => Err(SmpError::HciError("Encryption was not enabled".to_string())),
+                    (0, _) => Ok(()),
+                    (status, _) => Err(SmpError::HciError(format!(
+                        "Encryption change failed with status 0x{:02X}",
+                        status
+                    ))),
+                };
+            }
+        }
+    }
+
+    /// Key, EDIV and Rand to encrypt the link to a device with
+    fn encryption_key(&self, remote_addr: &BdAddr) -> SmpResult<([u8; 16], u16, [u8; 8])> {
+        // An STK or Secure Connections LTK has zero EDIV and Rand
+        {
+            let pairing_processes = self.pairing_processes.read().unwrap();
+            if let Some(ltk) = pairing_processes
+                .get(remote_addr)
+                .and_then(|process| process.ltk)
+            {
+                return Ok((ltk, 0, [0; 8]));
+            }
+        }
+
+        let key_store = self.key_store.read().unwrap();
+        let keys = key_store
+            .load_keys(remote_addr)?
+            .ok_or(SmpError::NotPaired)?;
+        let ltk = keys.ltk.or(keys.local_ltk).ok_or(SmpError::NotPaired)?;
+        Ok((ltk.key, ltk.ediv, ltk.rand))
+    }
+
+    /// Security level of an encrypted link, based on the key in use
+    fn encrypted_security_level(&self, remote_addr: &BdAddr) -> SmpResult<SecurityLevel> {
+        // A pairing that just finished knows how its key was generated
+        {
+            let pairing_processes = self.pairing_processes.read().unwrap();
+            if let Some(process) = pairing_processes.get(remote_addr) {
+                // Just Works keys are unauthenticated even with Secure Connections
+                let authenticated =
+                    !matches!(process.method, Some(PairingMethod::JustWorks) | None);
+                return Ok(if authenticated && process.secure_connections {
+                    SecurityLevel::SecureConnections
+                } else if authenticated {
+                    SecurityLevel::EncryptionWithAuthentication
+                } else {
+                    SecurityLevel::EncryptionOnly
+                });
+            }
+        }
+
+        let key_store = self.key_store.read().unwrap();
+        let level = match key_store.load_keys(remote_addr)? {
+            Some(keys) => keys.security_level(),
+            None => SecurityLevel::None,
+        };
+        Ok(level.max(SecurityLevel::EncryptionOnly))
     }
 
     /// Process timeouts
@@ -325,6 +871,8 @@ impl SmpManager {
 
     /// Handle a pairing request
     fn handle_pairing_request(&self, remote_addr: BdAddr, data: &[u8]) -> SmpResult<()> {
+        self.remember_connection(remote_addr);
+
         // Parse the pairing request
         let pairing_req = PairingRequest::parse(data)?;
         let features = pairing_req.to_features();
@@ -374,14 +922,15 @@ impl SmpManager {
             if let Some(process) = pairing_processes.get_mut(&remote_addr) {
                 if process.secure_connections {
                     // Generate keypair for Secure Connections
-                    let (private_key, public_key) = generate_keypair();
+                    let (private_key, public_key) = self.local_keypair();
                     process.local_private_key = Some(private_key);
                     process.local_public_key = Some(public_key);
 
                     // Wait for public key
                     process.state = PairingState::WaitingPublicKey;
                 } else {
-                    // For legacy pairing, generate TK, random and confirm
+                    // For legacy pairing, generate TK and random. Our confirm
+                    // value is only sent in reply to the initiator's.
                     match process.method {
                         Some(PairingMethod::JustWorks) => {
                             // TK is all zeros for Just Works
@@ -406,7 +955,7 @@ impl SmpManager {
                                 // Notify application to display passkey
                                 self.notify_event(SmpEvent::DisplayPasskey(remote_addr, passkey))?;
                             } else {
-                                // Will request passkey later
+                                self.request_passkey(process)?;
                             }
                         }
                         Some(PairingMethod::OutOfBand) => {
@@ -430,10 +979,13 @@ impl SmpManager {
                     }
 
                     // Generate random value
-                    process.local_random = Some(generate_random_128());
+                    process.local_random = Some(self.random_128());
 
-                    // Wait for pairing confirm
-                    process.state = PairingState::WaitingPairingConfirm;
+                    // Wait for pairing confirm, which is only answered once
+                    // the user has entered the passkey
+                    if process.state != PairingState::WaitingPasskeyInput {
+                        process.state = PairingState::WaitingPairingConfirm;
+                    }
                 }
             }
         }
@@ -483,7 +1035,7 @@ impl SmpManager {
         // Process based on pairing method
         if process.secure_connections {
             // Generate keypair for Secure Connections
-            let (private_key, public_key) = generate_keypair();
+            let (private_key, public_key) = self.local_keypair();
             process.local_private_key = Some(private_key);
             process.local_public_key = Some(public_key);
 
@@ -519,10 +1071,7 @@ impl SmpManager {
                         // Notify application to display passkey
                         self.notify_event(SmpEvent::DisplayPasskey(remote_addr, passkey))?;
                     } else {
-                        // Request passkey from user
-                        self.notify_event(SmpEvent::PasskeyRequest(remote_addr))?;
-
-                        // Will be set when the user provides the passkey
+                        self.request_passkey(&mut process)?;
                     }
                 }
                 Some(PairingMethod::OutOfBand) => {
@@ -542,10 +1091,12 @@ impl SmpManager {
             }
 
             // Generate random value
-            process.local_random = Some(generate_random_128());
+            process.local_random = Some(self.random_128());
 
             // Calculate confirm value
-            if let (Some(tk), Some(local_random)) = (&process.tk, &process.local_random) {
+            if process.state == PairingState::WaitingPasskeyInput {
+                // Sent by input_passkey once the TK is known
+            } else if let (Some(tk), Some(local_random)) = (&process.tk, &process.local_random) {
                 // Get preq and pres
                 let preq = PairingRequest::from_features(&self.features).serialize(true);
                 let pres = data.to_vec();
@@ -557,7 +1108,8 @@ impl SmpManager {
                 let resp_addr_type = 0; // Public address
                 let resp_addr = [0u8; 6]; // Remote address
 
-                let confirm_value = c1(
+                let Ok(confirm_value) = c1(
+                    self.aes().as_ref(),
                     tk,
                     local_random,
                     &preq,
@@ -566,7 +1118,9 @@ impl SmpManager {
                     &init_addr,
                     resp_addr_type,
                     &resp_addr,
-                );
+                ) else {
+                    return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+                };
 
                 process.local_confirm = Some(confirm_value);
 
@@ -604,6 +1158,30 @@ impl SmpManager {
                 .ok_or(SmpError::InvalidState)?
         };
 
+        // In legacy pairing the initiator sends its confirm first and the
+        // responder answers with its own, so each side takes exactly one
+        // confirm, and only before the random values. In Secure Connections
+        // only the responder sends a confirm, after the public keys.
+        let expected = match process.role {
+            PairingRole::Initiator => process.state == PairingState::WaitingPairingConfirm,
+            PairingRole::Responder if process.secure_connections => false,
+            PairingRole::Responder => {
+                process.remote_confirm.is_none()
+                    && matches!(
+                        process.state,
+                        PairingState::WaitingPairingConfirm | PairingState::WaitingPasskeyInput
+                    )
+            }
+        };
+
+        if !expected {
+            // Put the process back
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            pairing_processes.insert(remote_addr, process);
+
+            return Err(SmpError::InvalidState);
+        }
+
         // Store the remote confirm value
         process.remote_confirm = Some(pairing_confirm.confirm_value);
 
@@ -620,11 +1198,12 @@ impl SmpManager {
                 // Missing random
                 return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
             }
+        } else if process.state == PairingState::WaitingPasskeyInput {
+            // Our confirm value is sent by input_passkey once the TK is known
         } else {
             // As responder, we calculate our confirm value if we haven't already
-            if process.local_confirm.is_none()
-                && process.tk.is_some()
-                && process.local_random.is_some()
+            if let (None, Some(tk), Some(local_random)) =
+                (process.local_confirm, process.tk, process.local_random)
             {
                 // Get preq and pres
                 if let Some(remote_features) = &process.remote_features {
@@ -637,16 +1216,20 @@ impl SmpManager {
                     let resp_addr_type = 0; // Public address
                     let resp_addr = [0u8; 6]; // Local address
 
-                    let confirm_value = c1(
-                        process.tk.as_ref().unwrap(),
-                        process.local_random.as_ref().unwrap(),
+                    let Ok(confirm_value) = c1(
+                        self.aes().as_ref(),
+                        &tk,
+                        &local_random,
                         &preq,
                         &pres,
                         init_addr_type,
                         &init_addr,
                         resp_addr_type,
                         &resp_addr,
-                    );
+                    ) else {
+                        return self
+                            .send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+                    };
 
                     process.local_confirm = Some(confirm_value);
                 }
@@ -687,8 +1270,46 @@ impl SmpManager {
                 .ok_or(SmpError::InvalidState)?
         };
 
+        // Legacy random values follow the exchange of both confirm values;
+        // in Secure Connections the responder has only sent its own
+        if process.state != PairingState::WaitingPairingRandom
+            || (!process.secure_connections && process.remote_confirm.is_none())
+        {
+            // Put the process back
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            pairing_processes.insert(remote_addr, process);
+
+            return Err(SmpError::InvalidState);
+        }
+
         // Store the remote random value
         process.remote_random = Some(pairing_random.random_value);
+        let mut comparison_value = None;
+        let mut local_dhkey_check = None;
+
+        // The initiator checks Cb against the responder's nonce, and the
+        // responder reveals its nonce in reply to Na
+        if process.secure_connections {
+            match process.role {
+                PairingRole::Initiator => match self.secure_connections_confirm(&process) {
+                    Some(confirm) if Some(confirm) == process.remote_confirm => {}
+                    Some(_) => {
+                        return self
+                            .send_pairing_failed(remote_addr, SMP_REASON_CONFIRM_VALUE_FAILED)
+                    }
+                    None => {
+                        return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON)
+                    }
+                },
+                PairingRole::Responder => {
+                    let Some(local_random) = process.local_random else {
+                        return self
+                            .send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+                    };
+                    self.send_pairing_random(remote_addr, PairingRandom::new(local_random))?;
+                }
+            }
+        }
 
         // For legacy pairing, verify the confirm value
         if !process.secure_connections {
@@ -709,16 +1330,13 @@ impl SmpManager {
                         PairingRequest::from_features(&self.features).serialize(false)
                     };
 
-                    // Set addresses based on role
+                    // Addresses aren't tracked yet, so both roles use the same ones
                     let (init_addr_type, init_addr, resp_addr_type, resp_addr) =
-                        if process.role == PairingRole::Initiator {
-                            (0, [0u8; 6], 0, [0u8; 6]) // Local is initiator, remote is responder
-                        } else {
-                            (0, [0u8; 6], 0, [0u8; 6]) // Remote is initiator, local is responder
-                        };
+                        (0, [0u8; 6], 0, [0u8; 6]);
 
                     // Calculate expected confirm value
-                    let expected_confirm = c1(
+                    let Ok(expected_confirm) = c1(
+                        self.aes().as_ref(),
                         tk,
                         remote_random,
                         &preq,
@@ -727,7 +1345,10 @@ impl SmpManager {
                         &init_addr,
                         resp_addr_type,
                         &resp_addr,
-                    );
+                    ) else {
+                        return self
+                            .send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+                    };
 
                     // Verify the confirm value
                     if expected_confirm != *remote_confirm {
@@ -756,20 +1377,41 @@ impl SmpManager {
             {
                 // Calculate STK
                 let stk = if process.role == PairingRole::Initiator {
-                    s1(tk, local_random, remote_random)
+                    s1(self.aes().as_ref(), tk, local_random, remote_random)
                 } else {
-                    s1(tk, remote_random, local_random)
+                    s1(self.aes().as_ref(), tk, remote_random, local_random)
+                };
+                let Ok(stk) = stk else {
+                    return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
                 };
 
                 // Store the LTK
                 process.ltk = Some(stk);
 
-                // Encrypt the link using STK
-                // In a real implementation, this would use HciCommand::EncryptionStart
+                // The initiator encrypts the link with the STK through
+                // start_encryption()
 
                 // Move to key distribution phase
                 process.state = PairingState::WaitingKeyDistribution;
             }
+        } else if process.method == Some(PairingMethod::NumericComparison) {
+            // The user compares the value derived from both random values
+            if let Some(value) = self.numeric_comparison_value(&process) {
+                process.state = PairingState::WaitingNumericComparison;
+                comparison_value = Some(value);
+            }
+        } else if process.method == Some(PairingMethod::JustWorks) {
+            // Just Works needs no user confirmation, so the initiator sends
+            // its check value straight away
+            process.state = PairingState::WaitingDhKeyCheck;
+            if process.role == PairingRole::Initiator {
+                match self.dhkey_check_values(remote_addr, &mut process) {
+                    Some((local_check, _)) => local_dhkey_check = Some(local_check),
+                    None => {
+                        return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON)
+                    }
+                }
+            }
         } else {
             // For Secure Connections, handle based on method
             // This is a placeholder for SC random handling
@@ -781,9 +1423,328 @@ impl SmpManager {
             pairing_processes.insert(remote_addr, process);
         }
 
+        if let Some(value) = comparison_value {
+            self.request_numeric_comparison(remote_addr, value)?;
+        }
+        if let Some(check) = local_dhkey_check {
+            self.send_pairing_dhkey_check(remote_addr, PairingDhKeyCheck::new(check))?;
+        }
+
+        Ok(())
+    }
+
+    /// Provide the passkey the user entered for a pairing
+    ///
+    /// Call this after `SmpEvent::PasskeyRequest` when no passkey callback
+    /// is set. The passkey becomes the TK, and our confirm value is sent as
+    /// soon as the pairing has reached that step. Fails with
+    /// `SmpError::InvalidState` unless the pairing is waiting for the
+    /// passkey.
+    pub fn input_passkey(&self, remote_addr: BdAddr, passkey: u32) -> SmpResult<()> {
+        if passkey > 999_999 {
+            return Err(SmpError::InvalidParameter(format!(
+                "Passkey {} has more than 6 digits",
+                passkey
+            )));
+        }
+
+        let mut process = {
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            let process = pairing_processes
+                .get(&remote_addr)
+                .ok_or(SmpError::InvalidState)?;
+            if process.state != PairingState::WaitingPasskeyInput {
+                return Err(SmpError::InvalidState);
+            }
+            pairing_processes.remove(&remote_addr).unwrap()
+        };
+
+        set_passkey(&mut process, passkey);
+
+        // The initiator sends its confirm first; the responder answers the
+        // initiator's confirm if it arrived while the user was typing
+        let send_confirm =
+            process.role == PairingRole::Initiator || process.remote_confirm.is_some();
+        let confirm = if send_confirm {
+            let Some(confirm_value) = self.legacy_confirm_value(&process) else {
+                return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+            };
+            process.local_confirm = Some(confirm_value);
+            process.state = match process.role {
+                PairingRole::Initiator => PairingState::WaitingPairingConfirm,
+                PairingRole::Responder => PairingState::WaitingPairingRandom,
+            };
+            Some(PairingConfirm::new(confirm_value))
+        } else {
+            process.state = PairingState::WaitingPairingConfirm;
+            None
+        };
+
+        {
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            pairing_processes.insert(remote_addr, process);
+        }
+
+        match confirm {
+            Some(confirm) => self.send_pairing_confirm(remote_addr, confirm),
+            None => Ok(()),
+        }
+    }
+
+    /// Answer a numeric comparison for a pairing
+    ///
+    /// Call this after `SmpEvent::NumericComparisonRequest` when no
+    /// comparison callback is set. Pairing fails if the user reports that
+    /// the values differ. Fails with `SmpError::InvalidState` unless the
+    /// pairing is waiting for the comparison.
+    pub fn confirm_numeric_comparison(
+        &self,
+        remote_addr: BdAddr,
+        confirmed: bool,
+    ) -> SmpResult<()> {
+        let check = {
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            let process = pairing_processes
+                .get_mut(&remote_addr)
+                .ok_or(SmpError::InvalidState)?;
+            if process.state != PairingState::WaitingNumericComparison {
+                return Err(SmpError::InvalidState);
+            }
+
+            if confirmed {
+                process.state = PairingState::WaitingDhKeyCheck;
+
+                // The responder answers the initiator's check value
+                if process.role == PairingRole::Responder {
+                    return Ok(());
+                }
+                Some(self.dhkey_check_values(remote_addr, process))
+            } else {
+                None
+            }
+        };
+
+        match check {
+            Some(Some((local_check, _))) => {
+                self.send_pairing_dhkey_check(remote_addr, PairingDhKeyCheck::new(local_check))
+            }
+            Some(None) => self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON),
+            None => self.send_pairing_failed(remote_addr, SMP_REASON_NUMERIC_COMPARISON_FAILED),
+        }
+    }
+
+    /// Get the passkey the user enters on this device
+    ///
+    /// Uses the passkey callback if one is set. Otherwise the application is
+    /// sent `SmpEvent::PasskeyRequest` and the pairing waits in
+    /// `PairingState::WaitingPasskeyInput` until `input_passkey` is called.
+    fn request_passkey(&self, process: &mut PairingProcess) -> SmpResult<()> {
+        let passkey_callback = self.passkey_callback.lock().unwrap().clone();
+        match passkey_callback {
+            Some(callback) => {
+                let passkey = (*callback.lock().unwrap())(process.remote_addr)?;
+                if passkey > 999_999 {
+                    return Err(SmpError::PasskeyEntryFailed);
+                }
+                set_passkey(process, passkey);
+            }
+            None => {
+                self.notify_event(SmpEvent::PasskeyRequest(process.remote_addr))?;
+                process.state = PairingState::WaitingPasskeyInput;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the user to compare the numeric comparison value
+    ///
+    /// Uses the comparison callback if one is set, otherwise the answer is
+    /// passed to `confirm_numeric_comparison` by the application.
+    fn request_numeric_comparison(&self, remote_addr: BdAddr, value: u32) -> SmpResult<()> {
+        self.notify_event(SmpEvent::NumericComparisonRequest(remote_addr, value))?;
+
+        let comparison_callback = self.comparison_callback.lock().unwrap().clone();
+        if let Some(callback) = comparison_callback {
+            let confirmed = (*callback.lock().unwrap())(remote_addr, value)?;
+            self.confirm_numeric_comparison(remote_addr, confirmed)?;
+        }
         Ok(())
     }
 
+    /// Calculate our LE legacy pairing confirm value
+    ///
+    /// Returns `None` if a value it depends on is missing or AES fails.
+    fn legacy_confirm_value(&self, process: &PairingProcess) -> Option<[u8; 16]> {
+        let tk = process.tk.as_ref()?;
+        let local_random = process.local_random.as_ref()?;
+        let remote_features = process.remote_features.as_ref()?;
+
+        let (preq, pres) = match process.role {
+            PairingRole::Initiator => (
+                PairingRequest::from_features(&self.features).serialize(true),
+                PairingRequest::from_features(remote_features).serialize(false),
+            ),
+            PairingRole::Responder => (
+                PairingRequest::from_features(remote_features).serialize(true),
+                PairingRequest::from_features(&self.features).serialize(false),
+            ),
+        };
+
+        // Addresses are not tracked yet, as in the rest of legacy pairing
+        c1(
+            self.aes().as_ref(),
+            tk,
+            local_random,
+            &preq,
+            &pres,
+            0,
+            &[0u8; 6],
+            0,
+            &[0u8; 6],
+        )
+        .ok()
+    }
+
+    /// Calculate the responder's Secure Connections confirm value
+    ///
+    /// Cb = f4(PKbx, PKax, Nb, 0), in SMP byte order. The responder calls
+    /// this with its own nonce, the initiator once Nb has arrived.
+    fn secure_connections_confirm(&self, process: &PairingProcess) -> Option<[u8; 16]> {
+        let local_public_key = process.local_public_key.as_ref()?;
+        let remote_public_key = process.remote_public_key.as_ref()?;
+
+        let (pka, pkb, nb) = match process.role {
+            PairingRole::Initiator => (
+                local_public_key,
+                remote_public_key,
+                process.remote_random.as_ref()?,
+            ),
+            PairingRole::Responder => (
+                remote_public_key,
+                local_public_key,
+                process.local_random.as_ref()?,
+            ),
+        };
+
+        let pkax: [u8; 32] = pka[..32].try_into().unwrap();
+        let pkbx: [u8; 32] = pkb[..32].try_into().unwrap();
+        f4(
+            self.aes().as_ref(),
+            &reversed(&pkbx),
+            &reversed(&pkax),
+            &reversed(nb),
+            0,
+        )
+        .ok()
+        .map(|confirm| reversed(&confirm))
+    }
+
+    /// Calculate the Secure Connections numeric comparison value
+    fn numeric_comparison_value(&self, process: &PairingProcess) -> Option<u32> {
+        let local_public_key = process.local_public_key.as_ref()?;
+        let remote_public_key = process.remote_public_key.as_ref()?;
+        let local_random = process.local_random.as_ref()?;
+        let remote_random = process.remote_random.as_ref()?;
+
+        let (pka, pkb, na, nb) = match process.role {
+            PairingRole::Initiator => (
+                local_public_key,
+                remote_public_key,
+                local_random,
+                remote_random,
+            ),
+            PairingRole::Responder => (
+                remote_public_key,
+                local_public_key,
+                remote_random,
+                local_random,
+            ),
+        };
+
+        // g2 takes the X coordinates of both public keys
+        let pkax: [u8; 32] = pka[..32].try_into().unwrap();
+        let pkbx: [u8; 32] = pkb[..32].try_into().unwrap();
+        g2(
+            self.aes().as_ref(),
+            &reversed(&pkax),
+            &reversed(&pkbx),
+            &reversed(na),
+            &reversed(nb),
+        )
+        .ok()
+    }
+
+    /// Derive the MacKey and LTK and calculate the DHKey check values
+    ///
+    /// Returns our check value and the one expected from the remote device,
+    /// in SMP byte order, and stores the MacKey and LTK in the process.
+    /// The addresses are those the connection uses on air.
+    fn dhkey_check_values(
+        &self,
+        remote_addr: BdAddr,
+        process: &mut PairingProcess,
+    ) -> Option<([u8; 16], [u8; 16])> {
+        let dhkey = process.dhkey.as_ref()?;
+        let local_random = process.local_random.as_ref()?;
+        let remote_random = process.remote_random.as_ref()?;
+        let remote_features = process.remote_features.as_ref()?;
+
+        // 56-bit addresses: address type followed by the address
+        let (local, remote) = self.connection_addresses(&remote_addr).ok()?;
+        let address = |(addr_type, bd_addr): (u8, BdAddr)| {
+            let mut address = [0u8; 7];
+            address[0] = addr_type;
+            address[1..].copy_from_slice(&reversed(&bd_addr.bytes));
+            address
+        };
+        let local_address = address(local);
+        let remote_address = address(remote);
+
+        // IOcap is AuthReq || OOB data flag || IO capability
+        let io_cap = |features: &PairingFeatures| {
+            let pdu = PairingRequest::from_features(features).serialize(true);
+            [pdu[3], pdu[2], pdu[1]]
+        };
+        let local_io_cap = io_cap(&self.features);
+        let remote_io_cap = io_cap(remote_features);
+
+        // Passkey entry mixes the passkey into the check; the other methods
+        // use zero
+        let mut r = [0u8; 16];
+        if process.method == Some(PairingMethod::PasskeyEntry) {
+            r[12..16].copy_from_slice(&process.passkey?.to_be_bytes());
+        }
+
+        let local_random = reversed(local_random);
+        let remote_random = reversed(remote_random);
+        let (na, nb, a, b) = match process.role {
+            PairingRole::Initiator => (local_random, remote_random, local_address, remote_address),
+            PairingRole::Responder => (remote_random, local_random, remote_address, local_address),
+        };
+
+        let aes = self.aes();
+        let aes = aes.as_ref();
+        let (mackey, ltk) = f5(aes, &reversed(dhkey), &na, &nb, &a, &b).ok()?;
+
+        // Ea = f6(MacKey, Na, Nb, rb, IOcapA, A, B)
+        // Eb = f6(MacKey, Nb, Na, ra, IOcapB, B, A)
+        let local_check = match process.role {
+            PairingRole::Initiator => f6(aes, &mackey, &na, &nb, &r, &local_io_cap, &a, &b),
+            PairingRole::Responder => f6(aes, &mackey, &nb, &na, &r, &local_io_cap, &b, &a),
+        }
+        .ok()?;
+        let remote_check = match process.role {
+            PairingRole::Initiator => f6(aes, &mackey, &nb, &na, &r, &remote_io_cap, &b, &a),
+            PairingRole::Responder => f6(aes, &mackey, &na, &nb, &r, &remote_io_cap, &a, &b),
+        }
+        .ok()?;
+
+        process.mackey = Some(reversed(&mackey));
+        process.ltk = Some(reversed(&ltk));
+
+        Some((reversed(&local_check), reversed(&remote_check)))
+    }
+
     /// Handle a pairing failed
     fn handle_pairing_failed(&self, remote_addr: BdAddr, data: &[u8]) -> SmpResult<()> {
         // Parse the pairing failed
@@ -847,10 +1808,7 @@ impl SmpManager {
             let mut keys = DeviceKeys::new();
 
             // Create LTK
-            let authenticated = match process.method {
-                Some(PairingMethod::JustWorks) => false,
-                _ => true,
-            };
+            let authenticated = !matches!(process.method, Some(PairingMethod::JustWorks));
 
             if process.secure_connections {
                 keys.ltk = Some(LongTermKey::new_secure_connections(*ltk, authenticated));
@@ -1007,30 +1965,37 @@ impl SmpManager {
 
             // Handle Secure Connections method
             match process.method {
-                Some(PairingMethod::JustWorks) => {
-                    // Just Works - No user input
-                    // This is a placeholder for SC Just Works handling
-                }
-                Some(PairingMethod::NumericComparison) => {
-                    // Numeric Comparison
-                    // This is a placeholder for SC Numeric Comparison handling
+                Some(PairingMethod::JustWorks) | Some(PairingMethod::NumericComparison) => {
+                    // The responder commits to its nonce with Cb, and the
+                    // initiator answers with Na once Cb has arrived
+                    process.local_random = Some(self.random_128());
+                    if process.role == PairingRole::Responder {
+                        let Some(confirm) = self.secure_connections_confirm(&process) else {
+                            return self
+                                .send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+                        };
+                        self.send_pairing_confirm(remote_addr, PairingConfirm::new(confirm))?;
+                        process.local_confirm = Some(confirm);
+                        process.state = PairingState::WaitingPairingRandom;
+                    } else {
+                        process.state = PairingState::WaitingPairingConfirm;
+                    }
                 }
                 Some(PairingMethod::PasskeyEntry) => {
                     // Passkey Entry
                     // This is a placeholder for SC Passkey Entry handling
+                    process.state = PairingState::WaitingDhKeyCheck;
                 }
                 Some(PairingMethod::OutOfBand) => {
                     // Out of Band
                     // This is a placeholder for SC OOB handling
+                    process.state = PairingState::WaitingDhKeyCheck;
                 }
                 None => {
                     // No method selected
                     return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
                 }
             }
-
-            // Update state
-            process.state = PairingState::WaitingDhKeyCheck;
         }
 
         // Store the updated process
@@ -1055,21 +2020,28 @@ impl SmpManager {
                 .ok_or(SmpError::InvalidState)?
         };
 
-        // Verify DHKey check
-        // This is a placeholder for SC DHKey verification
+        if !process.secure_connections || process.state != PairingState::WaitingDhKeyCheck {
+            // Put the process back
+            let mut pairing_processes = self.pairing_processes.write().unwrap();
+            pairing_processes.insert(remote_addr, process);
 
-        // Send our DHKey check if we're responder
-        if process.role == PairingRole::Responder {
-            // Generate DHKey check
-            // This is a placeholder for SC DHKey check generation
-            let check = [0u8; 16]; // Placeholder
+            return Err(SmpError::InvalidState);
+        }
 
-            let dhkey_check = PairingDhKeyCheck::new(check);
-            self.send_pairing_dhkey_check(remote_addr, dhkey_check)?;
+        // Verify the remote check value against the derived MacKey
+        let Some((local_check, remote_check)) = self.dhkey_check_values(remote_addr, &mut process)
+        else {
+            return self.send_pairing_failed(remote_addr, SMP_REASON_UNSPECIFIED_REASON);
+        };
+        if dhkey_check.check != remote_check {
+            return self.send_pairing_failed(remote_addr, SMP_REASON_DHKEY_CHECK_FAILED);
         }
 
-        // Complete the SC pairing
-        // This is a placeholder for SC pairing completion
+        // The responder answers with its own check value
+        if process.role == PairingRole::Responder {
+            let dhkey_check = PairingDhKeyCheck::new(local_check);
+            self.send_pairing_dhkey_check(remote_addr, dhkey_check)?;
+        }
 
         // Complete pairing
         process.state = PairingState::Complete;
@@ -1188,17 +2160,6 @@ impl SmpManager {
         self.send_smp_packet(remote_addr, &packet)
     }
 
-    /// Send security request
-    fn send_security_request(
-        &self,
-        remote_addr: BdAddr,
-        auth_req: AuthRequirements,
-    ) -> SmpResult<()> {
-        let sec_req = SecurityRequest::new(auth_req);
-        let packet = sec_req.serialize();
-        self.send_smp_packet(remote_addr, &packet)
-    }
-
     /// Send pairing public key
     fn send_pairing_public_key(
         &self,
@@ -1219,75 +2180,28 @@ impl SmpManager {
         self.send_smp_packet(remote_addr, &packet)
     }
 
-    /// Send keypress notification
-    fn send_keypress_notification(
-        &self,
-        remote_addr: BdAddr,
-        notification_type: KeypressNotificationType,
-    ) -> SmpResult<()> {
-        let keypress = KeypressNotification::new(notification_type);
-        let packet = keypress.serialize();
-        self.send_smp_packet(remote_addr, &packet)
-    }
-
-    /// Send an SMP packet via L2CAP
+    /// Send an SMP packet on the SMP channel of the connection to a device
     fn send_smp_packet(&self, remote_addr: BdAddr, packet: &[u8]) -> SmpResult<()> {
-        // Get the L2CAP channel ID for the device
-        // In a real implementation, we would look up the L2CAP connection
-        // based on the BD_ADDR, but for simplicity we'll assume it exists
-
-        // Look up the HCI handle for this device
-        // This would typically be stored in a map maintained by the GAP layer
-        // For now, we'll use a placeholder
-        let hci_handle = 0; // Placeholder
-
-        // Create a map of HCI handles to L2CAP connections
-        let mut handle_to_channel = HashMap::new();
-
-        // Look up or create the SMP channel
-        if let Ok(channel_id) = self.get_or_create_smp_channel(remote_addr, hci_handle) {
-            // Send the data via L2CAP
-            match self.l2cap_manager.send_data(channel_id, packet) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(SmpError::L2capError(e.to_string())),
-            }
-        } else {
-            Err(SmpError::ConnectionNotFound)
-        }
-    }
-
-    /// Get or create an SMP channel for a device
-    fn get_or_create_smp_channel(&self, remote_addr: BdAddr, hci_handle: u16) -> SmpResult<u16> {
-        // In a real implementation, we would either look up an existing channel
-        // or create a new one if it doesn't exist.
-
-        // For simplicity, we'll create a new channel each time
-        // In a real implementation, we would maintain a map of BD_ADDR to channel IDs
-
-        // Register a PSM for SMP if not already registered
-        let data_callback = Arc::new(Mutex::new(move |data: &[u8]| -> L2capResult<()> {
-            // This is where we would process incoming SMP packets
-            println!("Received SMP packet: {:?}", data);
-            Ok(())
-        }));
-
-        // Create a connection policy
-        let policy = crate::l2cap::ConnectionPolicy {
-            min_security_level: SecurityLevel::None,
-            authorization_required: false,
-            auto_accept: true,
-        };
-
-        // Try to get the SMP fixed channel (CID = 6)
-        // Normally, this would involve looking up the L2CAP connection
-        // and finding the channel with the SMP CID
-
-        // For simplicity, we'll just return a fixed channel ID
-        Ok(SMP_CID)
+        let hci_handle = self
+            .l2cap_manager
+            .connection_handle(&remote_addr)
+            .ok_or(SmpError::ConnectionNotFound)?;
+
+        self.l2cap_manager
+            .get_fixed_channel(SMP_CID, hci_handle)
+            .and_then(|cid| self.l2cap_manager.send_data_on(cid, hci_handle, packet))
+            .map_err(|e| SmpError::L2capError(e.to_string()))
     }
 
     /// Notify the application of an SMP event
     fn notify_event(&self, event: SmpEvent) -> SmpResult<()> {
+        #[cfg(feature = "tokio")]
+        {
+            // Drop subscribers whose receiver has gone away
+            let mut subscribers = self.event_subscribers.lock().unwrap();
+            subscribers.retain(|sender| sender.send(event.clone()).is_ok());
+        }
+
         let event_callback = self.event_callback.lock().unwrap();
         if let Some(ref callback) = *event_callback {
             let mut callback = callback.lock().unwrap();
@@ -1333,3 +2247,19 @@ impl SmpManager {
         Ok(())
     }
 }
+
+/// Convert between SMP byte order and the most significant octet first
+/// order of the Secure Connections functions
+fn reversed<const N: usize>(value: &[u8; N]) -> [u8; N] {
+    let mut value = *value;
+    value.reverse();
+    value
+}
+
+/// Use a 6-digit passkey as the TK of a pairing
+fn set_passkey(process: &mut PairingProcess, passkey: u32) {
+    let mut tk = [0u8; 16];
+    tk[0..4].copy_from_slice(&passkey.to_le_bytes());
+    process.tk = Some(tk);
+    process.passkey = Some(passkey);
+}
@@ -8,14 +8,22 @@
 //!
 //! The SMP module provides both LE and Classic Bluetooth security features.
 
+mod aes;
 mod constants;
-mod crypto;
+pub mod crypto;
+#[cfg(feature = "serde")]
+mod file_store;
 mod keys;
 mod manager;
 mod pairing;
 mod types;
 
+#[cfg(test)]
+mod tests;
+
 // Re-export public API
+#[cfg(feature = "serde")]
+pub use self::file_store::FileKeyStore;
 pub use self::keys::KeyStore;
 pub use self::keys::*;
 pub use self::manager::SmpManager;
@@ -4,15 +4,12 @@
 //! for both legacy pairing and LE Secure Connections.
 
 use super::constants::*;
-use super::crypto::*;
 use super::keys::*;
 use super::types::*;
 use crate::gap::BdAddr;
-use crate::l2cap::*;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::collections::HashMap;
-use std::convert::TryInto;
-use std::io::{Cursor, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::fmt;
+use std::io::{Cursor, Read};
 use std::time::{Duration, Instant};
 
 /// Pairing request/response packet
@@ -217,12 +214,7 @@ impl PairingFailed {
 
     /// Serialize to raw packet
     pub fn serialize(&self) -> Vec<u8> {
-        let mut packet = Vec::with_capacity(2);
-
-        packet.push(SMP_PAIRING_FAILED);
-        packet.push(self.reason);
-
-        packet
+        vec![SMP_PAIRING_FAILED, self.reason]
     }
 
     /// Convert reason code to SmpError
@@ -482,12 +474,7 @@ impl SecurityRequest {
 
     /// Serialize to raw packet
     pub fn serialize(&self) -> Vec<u8> {
-        let mut packet = Vec::with_capacity(2);
-
-        packet.push(SMP_SECURITY_REQUEST);
-        packet.push(self.auth_req);
-
-        packet
+        vec![SMP_SECURITY_REQUEST, self.auth_req]
     }
 
     /// Convert to AuthRequirements
@@ -629,12 +616,7 @@ impl KeypressNotification {
 
     /// Serialize to raw packet
     pub fn serialize(&self) -> Vec<u8> {
-        let mut packet = Vec::with_capacity(2);
-
-        packet.push(SMP_PAIRING_KEYPRESS_NOTIFICATION);
-        packet.push(self.notification_type);
-
-        packet
+        vec![SMP_PAIRING_KEYPRESS_NOTIFICATION, self.notification_type]
     }
 
     /// Convert to KeypressNotificationType
@@ -654,6 +636,10 @@ pub enum PairingState {
     WaitingPairingConfirm,
     /// Waiting for pairing random
     WaitingPairingRandom,
+    /// Waiting for the user to enter the passkey
+    WaitingPasskeyInput,
+    /// Waiting for the user to confirm the numeric comparison value
+    WaitingNumericComparison,
     /// Waiting for public key
     WaitingPublicKey,
     /// Waiting for DHKey check
@@ -667,6 +653,7 @@ pub enum PairingState {
 }
 
 /// Pairing process
+#[derive(Clone)]
 pub struct PairingProcess {
     /// Remote device address
     pub remote_addr: BdAddr,
@@ -718,6 +705,79 @@ pub struct PairingProcess {
     pub timestamp: Instant,
 }
 
+/// Environment variable that reveals key material in debug output
+pub const SMP_SHOW_KEYS_ENV: &str = "RUSTYBLUE_SHOW_KEYS";
+
+/// Whether key material may be shown in debug output
+///
+/// Only debug builds honour `RUSTYBLUE_SHOW_KEYS=1`; release builds always
+/// redact.
+fn show_keys() -> bool {
+    cfg!(debug_assertions) && std::env::var(SMP_SHOW_KEYS_ENV).is_ok_and(|v| v == "1")
+}
+
+/// Debug helper printing optional byte arrays as hex, or `[REDACTED]`
+struct DebugKey<'a>(Option<&'a [u8]>, bool);
+
+impl<'a> DebugKey<'a> {
+    fn new<const N: usize>(value: &'a Option<[u8; N]>, show: bool) -> Self {
+        DebugKey(value.as_ref().map(|v| &v[..]), show)
+    }
+}
+
+impl fmt::Debug for DebugKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            None => write!(f, "None"),
+            Some(_) if !self.1 => write!(f, "[REDACTED]"),
+            Some(bytes) => write!(f, "Some({})", hex::encode(bytes)),
+        }
+    }
+}
+
+impl fmt::Debug for PairingProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = show_keys();
+        let passkey = self.passkey.map(u32::to_le_bytes);
+
+        f.debug_struct("PairingProcess")
+            .field("remote_addr", &self.remote_addr)
+            .field("local_features", &self.local_features)
+            .field("remote_features", &self.remote_features)
+            .field("role", &self.role)
+            .field("secure_connections", &self.secure_connections)
+            .field("method", &self.method)
+            .field("state", &self.state)
+            .field("local_random", &DebugKey::new(&self.local_random, true))
+            .field("local_confirm", &DebugKey::new(&self.local_confirm, true))
+            .field("remote_random", &DebugKey::new(&self.remote_random, true))
+            .field("remote_confirm", &DebugKey::new(&self.remote_confirm, true))
+            .field("tk", &DebugKey::new(&self.tk, keys))
+            .field("passkey", &DebugKey::new(&passkey, keys))
+            .field("passkey_bits_used", &self.passkey_bits_used)
+            .field(
+                "local_private_key",
+                &DebugKey::new(&self.local_private_key, keys),
+            )
+            .field(
+                "local_public_key",
+                &DebugKey::new(&self.local_public_key, true),
+            )
+            .field(
+                "remote_public_key",
+                &DebugKey::new(&self.remote_public_key, true),
+            )
+            .field("dhkey", &DebugKey::new(&self.dhkey, keys))
+            .field("mackey", &DebugKey::new(&self.mackey, keys))
+            .field("ltk", &DebugKey::new(&self.ltk, keys))
+            .field("remote_irk", &DebugKey::new(&self.remote_irk, keys))
+            .field("remote_csrk", &DebugKey::new(&self.remote_csrk, keys))
+            .field("remote_identity", &self.remote_identity)
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
 impl PairingProcess {
     /// Create a new pairing process as initiator
     pub fn new_initiator(remote_addr: BdAddr, features: PairingFeatures) -> Self {
@@ -846,8 +906,6 @@ impl PairingProcess {
             let remote_io = remote_features.io_capability;
             let local_oob = self.local_features.oob_data_present;
             let remote_oob = remote_features.oob_data_present;
-            let local_mitm = self.local_features.auth_req.mitm;
-            let remote_mitm = remote_features.auth_req.mitm;
 
             // Check for OOB
             if local_oob && remote_oob {
@@ -863,16 +921,19 @@ impl PairingProcess {
             }
 
             // Check for Passkey Entry
-            if (local_io == IoCapability::KeyboardOnly && remote_io == IoCapability::DisplayOnly)
-                || (local_io == IoCapability::KeyboardOnly
-                    && remote_io == IoCapability::DisplayYesNo)
+            if local_io == IoCapability::KeyboardOnly
+                && matches!(
+                    remote_io,
+                    IoCapability::DisplayOnly | IoCapability::DisplayYesNo
+                )
             {
                 return Ok(PairingMethod::PasskeyEntry);
             }
 
-            if (local_io == IoCapability::DisplayOnly && remote_io == IoCapability::KeyboardOnly)
-                || (local_io == IoCapability::DisplayYesNo
-                    && remote_io == IoCapability::KeyboardOnly)
+            if matches!(
+                local_io,
+                IoCapability::DisplayOnly | IoCapability::DisplayYesNo
+            ) && remote_io == IoCapability::KeyboardOnly
             {
                 return Ok(PairingMethod::PasskeyEntry);
             }
@@ -910,10 +971,7 @@ impl PairingProcess {
 
         // Generate LTK
         if let Some(ltk) = &self.ltk {
-            let authenticated = match self.method {
-                Some(PairingMethod::JustWorks) => false,
-                _ => true,
-            };
+            let authenticated = !matches!(self.method, Some(PairingMethod::JustWorks));
 
             if self.secure_connections {
                 keys.ltk = Some(LongTermKey::new_secure_connections(*ltk, authenticated));
@@ -940,10 +998,7 @@ impl PairingProcess {
 
         // Include CSRK if received
         if let Some(csrk) = &self.remote_csrk {
-            let authenticated = match self.method {
-                Some(PairingMethod::JustWorks) => false,
-                _ => true,
-            };
+            let authenticated = !matches!(self.method, Some(PairingMethod::JustWorks));
 
             keys.remote_csrk = Some(ConnectionSignatureResolvingKey::new(*csrk, authenticated));
         }
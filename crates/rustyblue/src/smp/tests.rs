@@ -0,0 +1,779 @@
+//! Unit tests for SMP functionality
+
+use super::constants::SMP_CID;
+use super::crypto::*;
+use super::{
+    AuthRequirements, DeviceKeys, KeyStore, LongTermKey, MemoryKeyStore, PairingFeatures,
+    PairingProcess, SecurityLevel, SmpError, SmpEvent, SmpManager, SMP_SHOW_KEYS_ENV,
+};
+use crate::gap::{BdAddr, PUBLIC_DEVICE_ADDRESS, RANDOM_DEVICE_ADDRESS};
+use crate::hci::constants::{EVT_LE_CONN_COMPLETE, EVT_LE_META_EVENT};
+use crate::hci::HciEvent;
+use crate::l2cap::ConnectionType;
+use crate::testing::{MockHciSocket, MockL2capManager};
+use std::sync::{Arc, Mutex};
+
+fn hex16(s: &str) -> [u8; 16] {
+    hex::decode(s).unwrap().try_into().unwrap()
+}
+
+/// Convert a value written most significant octet first into SMP byte order
+fn le16(s: &str) -> [u8; 16] {
+    let mut bytes = hex16(s);
+    bytes.reverse();
+    bytes
+}
+
+/// An SMP manager on the mock that does its crypto in software, so pairing
+/// doesn't wait for LE Encrypt or LE Rand answers from the mock controller
+fn new_smp_manager(mock: &MockL2capManager, key_store: MemoryKeyStore) -> SmpManager {
+    let smp = SmpManager::new(mock.manager(), mock.hci().socket(), Box::new(key_store));
+    smp.set_aes_backend(Arc::new(SoftwareAes));
+    smp.set_rng(Arc::new(OsSecureRng));
+    smp
+}
+
+#[test]
+fn test_aes_cmac_rfc4493_vectors() {
+    // BT Core Spec Vol 3, Part H, Appendix D.1 (RFC 4493 Section 4)
+    let key = hex16("2b7e151628aed2a6abf7158809cf4f3c");
+    let message = hex::decode(
+        "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+         30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710",
+    )
+    .unwrap();
+
+    assert_eq!(
+        aes_cmac(&SoftwareAes, &key, &[]).unwrap(),
+        hex16("bb1d6929e95937287fa37d129b756746")
+    );
+    assert_eq!(
+        aes_cmac(&SoftwareAes, &key, &message[..16]).unwrap(),
+        hex16("070a16b46b4d4144f79bdd9dd04a287c")
+    );
+    assert_eq!(
+        aes_cmac(&SoftwareAes, &key, &message[..40]).unwrap(),
+        hex16("dfa66747de9ae63030ca32611497c827")
+    );
+    assert_eq!(
+        aes_cmac(&SoftwareAes, &key, &message).unwrap(),
+        hex16("51f0bebf7e3b9d92fc49741779363cfe")
+    );
+
+    // All-zero key with an empty message
+    assert_eq!(
+        aes_cmac(&SoftwareAes, &[0u8; 16], &[]).unwrap(),
+        hex16("4387c14b46ef7e176dceefa862d72ff9")
+    );
+}
+
+#[test]
+fn test_aes_encrypt_fips197_vector() {
+    // FIPS-197 Appendix C.1
+    assert_eq!(
+        SoftwareAes
+            .encrypt(
+                &hex16("000102030405060708090a0b0c0d0e0f"),
+                &hex16("00112233445566778899aabbccddeeff")
+            )
+            .unwrap(),
+        hex16("69c4e0d86a7b0430d8cdb78070b4c55a")
+    );
+}
+
+#[test]
+fn test_controller_aes_uses_le_encrypt() {
+    let mock = MockHciSocket::new().unwrap();
+    let key = hex16("000102030405060708090a0b0c0d0e0f");
+    let data = hex16("00112233445566778899aabbccddeeff");
+    let ciphertext = hex16("69c4e0d86a7b0430d8cdb78070b4c55a");
+
+    // The controller answers least significant octet first
+    let mut complete = vec![0x04, 0x0E, 0x14, 0x01, 0x17, 0x20, 0x00];
+    complete.extend(ciphertext.iter().rev());
+    mock.push_events(&complete).unwrap();
+
+    let aes = ControllerAes::new(mock.socket());
+    assert_eq!(aes.encrypt(&key, &data).unwrap(), ciphertext);
+
+    let mut params: Vec<u8> = key.iter().rev().copied().collect();
+    params.extend(data.iter().rev());
+    assert_eq!(mock.sent_commands(), vec![(0x2017, params)]);
+
+    // A failing controller is an error, not a panic
+    mock.push_events(&[0x04, 0x0E, 0x04, 0x01, 0x17, 0x20, 0x12])
+        .unwrap();
+    assert!(matches!(
+        aes.encrypt(&key, &data),
+        Err(SmpError::HciError(_))
+    ));
+}
+
+#[test]
+fn test_c1_spec_vector() {
+    // BT Core Spec Vol 3, Part H, 2.2.3
+    let k = [0u8; 16];
+    let r = le16("5783D52156AD6F0E6388274EC6702EE0");
+    let preq = [0x01, 0x01, 0x00, 0x00, 0x10, 0x07, 0x07];
+    let pres = [0x02, 0x03, 0x00, 0x00, 0x08, 0x00, 0x05];
+    let ia = [0xA6, 0xA5, 0xA4, 0xA3, 0xA2, 0xA1];
+    let ra = [0xB6, 0xB5, 0xB4, 0xB3, 0xB2, 0xB1];
+
+    let confirm = c1(&SoftwareAes, &k, &r, &preq, &pres, 0x01, &ia, 0x00, &ra).unwrap();
+
+    assert_eq!(confirm, le16("1e1e3fef878988ead2a74dc5bef13b86"));
+}
+
+#[test]
+fn test_s1_spec_vector() {
+    // BT Core Spec Vol 3, Part H, 2.2.4
+    let k = [0u8; 16];
+    let r1 = le16("000F0E0D0C0B0A091122334455667788");
+    let r2 = le16("010203040506070899AABBCCDDEEFF00");
+
+    assert_eq!(
+        s1(&SoftwareAes, &k, &r1, &r2).unwrap(),
+        le16("9a1fe1f0e8b0f49b5b4216ae796da062")
+    );
+}
+
+#[test]
+fn test_c1_s1_legacy_pairing_methods() {
+    // The specification only gives c1 and s1 samples for TK = 0 (Just
+    // Works). The Passkey Entry and OOB values below were computed with an
+    // independent AES implementation using the same PDUs and addresses.
+    let r = le16("5783D52156AD6F0E6388274EC6702EE0");
+    let r1 = le16("000F0E0D0C0B0A091122334455667788");
+    let r2 = le16("010203040506070899AABBCCDDEEFF00");
+    let preq = [0x01, 0x01, 0x00, 0x00, 0x10, 0x07, 0x07];
+    let pres = [0x02, 0x03, 0x00, 0x00, 0x08, 0x00, 0x05];
+    let ia = [0xA6, 0xA5, 0xA4, 0xA3, 0xA2, 0xA1];
+    let ra = [0xB6, 0xB5, 0xB4, 0xB3, 0xB2, 0xB1];
+
+    // Passkey 123456 zero-extended to 128 bits
+    let mut passkey_tk = [0u8; 16];
+    passkey_tk[..4].copy_from_slice(&123456u32.to_le_bytes());
+
+    let cases = [
+        (
+            "Just Works",
+            [0u8; 16],
+            "1e1e3fef878988ead2a74dc5bef13b86",
+            "9a1fe1f0e8b0f49b5b4216ae796da062",
+        ),
+        (
+            "Passkey Entry",
+            passkey_tk,
+            "e291fa937ed0b3fe22dc3712484e7acf",
+            "6e22999fda360d6492fa4f2bf0829e80",
+        ),
+        (
+            "OOB",
+            le16("00112233445566778899AABBCCDDEEFF"),
+            "68d7ede06512a7093848f871c0c0360b",
+            "75e9a5c4d571b6511ed0360219f12c80",
+        ),
+    ];
+
+    for (method, tk, confirm, stk) in cases {
+        assert_eq!(
+            c1(&SoftwareAes, &tk, &r, &preq, &pres, 0x01, &ia, 0x00, &ra).unwrap(),
+            le16(confirm),
+            "{method} confirm"
+        );
+        assert_eq!(
+            s1(&SoftwareAes, &tk, &r1, &r2).unwrap(),
+            le16(stk),
+            "{method} STK"
+        );
+    }
+}
+
+#[test]
+fn test_h6_h7_spec_vectors() {
+    // BT Core Spec Vol 3, Part H, D.8 and D.10
+    let w = hex16("ec0234a357c8ad05341010a60a397d9b");
+    assert_eq!(
+        h6(&SoftwareAes, &w, b"lebr").unwrap(),
+        hex16("2d9ae102e76dc91ce8d3a9e280b16399")
+    );
+
+    let salt = hex16("000000000000000000000000746d7031");
+    assert_eq!(
+        h7(&SoftwareAes, &salt, &w).unwrap(),
+        hex16("fb173597c6a3c0ecd2998c2a75a57011")
+    );
+}
+
+#[test]
+fn test_secure_connections_spec_vectors() {
+    // BT Core Spec Vol 3, Part H, D.2 to D.5
+    let u: [u8; 32] =
+        hex::decode("20b003d2f297be2c5e2c83a7e9f9a5b9eff49111acf4fddbcc0301480e359de6")
+            .unwrap()
+            .try_into()
+            .unwrap();
+    let v: [u8; 32] =
+        hex::decode("55188b3d32f6bb9a900afcfbeed4e72a59cb9ac2f19d7cfb6b4fdd49f47fc5fd")
+            .unwrap()
+            .try_into()
+            .unwrap();
+    let w: [u8; 32] =
+        hex::decode("ec0234a357c8ad05341010a60a397d9b99796b13b4f866f1868d34f373bfa698")
+            .unwrap()
+            .try_into()
+            .unwrap();
+    let n1 = hex16("d5cb8454d177733effffb2ec712baeab");
+    let n2 = hex16("a6e8e7cc25a75f6e216583f7ff3dc4cf");
+    let a1 = [0x00, 0x56, 0x12, 0x37, 0x37, 0xbf, 0xce];
+    let a2 = [0x00, 0xa7, 0x13, 0x70, 0x2d, 0xcf, 0xc1];
+
+    assert_eq!(
+        f4(&SoftwareAes, &u, &v, &n1, 0).unwrap(),
+        hex16("f2c916f107a9bd1cf1eda1bea974872d")
+    );
+
+    let (mackey, ltk) = f5(&SoftwareAes, &w, &n1, &n2, &a1, &a2).unwrap();
+    assert_eq!(mackey, hex16("2965f176a1084a02fd3f6a20ce636e20"));
+    assert_eq!(ltk, hex16("6986791169d7cd23980522b594750a38"));
+
+    let r = hex16("12a3343bb453bb5408da42d20c2d0fc8");
+    assert_eq!(
+        f6(
+            &SoftwareAes,
+            &mackey,
+            &n1,
+            &n2,
+            &r,
+            &[0x01, 0x01, 0x02],
+            &a1,
+            &a2
+        )
+        .unwrap(),
+        hex16("e3c473989cd0e8c5d26c0b09da958f61")
+    );
+
+    // g2 = 0x2f9ed5ba, shown as its six least significant digits
+    assert_eq!(
+        g2(&SoftwareAes, &u, &v, &n1, &n2).unwrap(),
+        0x2f9e_d5ba % 1_000_000
+    );
+}
+
+#[test]
+fn test_d1_derives_irk_from_identity_root() {
+    let ir = le16("000102030405060708090a0b0c0d0e0f");
+
+    // d1(IR, 1, 0) = e(IR, padding || 0x0000 || 0x0001)
+    let mut expected = SoftwareAes
+        .encrypt(
+            &hex16("000102030405060708090a0b0c0d0e0f"),
+            &hex16("00000000000000000000000000000001"),
+        )
+        .unwrap();
+    expected.reverse();
+
+    assert_eq!(d1(&SoftwareAes, &ir, 1, 0).unwrap(), expected);
+    assert_ne!(
+        d1(&SoftwareAes, &ir, 1, 0).unwrap(),
+        d1(&SoftwareAes, &ir, 3, 0).unwrap()
+    );
+}
+
+#[test]
+fn test_oob_confirm_value_matches_f4() {
+    use super::types::OobData;
+
+    let mut public_key = [0u8; 64];
+    for (i, byte) in public_key.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let r = le16("d5cb8454d177733effffb2ec712baeab");
+
+    let oob_data = OobData::new(&SoftwareAes, &public_key, r).unwrap();
+
+    // The recipient recomputes c from the sender's public key and r, both
+    // reversed from SMP byte order into the order f4 works in
+    let mut pkx: [u8; 32] = public_key[..32].try_into().unwrap();
+    pkx.reverse();
+    let mut r_msb = oob_data.r;
+    r_msb.reverse();
+    let mut c = f4(&SoftwareAes, &pkx, &pkx, &r_msb, 0).unwrap();
+    c.reverse();
+    assert_eq!(c, oob_data.c);
+    assert!(oob_data.matches(&SoftwareAes, &public_key).unwrap());
+
+    public_key[0] ^= 0x01;
+    assert!(!oob_data.matches(&SoftwareAes, &public_key).unwrap());
+}
+
+#[test]
+fn test_os_secure_rng() {
+    let rng: &dyn SecureRng = &OsSecureRng;
+    let first = rng.random_128().unwrap();
+    let second = rng.random_128().unwrap();
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_pairing_process_debug_redacts_keys() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mut process = PairingProcess::new_initiator(addr, PairingFeatures::default());
+    process.local_random = Some([0xAB; 16]);
+    process.ltk = Some([0xCD; 16]);
+
+    let output = format!("{:?}", process);
+    assert!(output.contains("state: Idle"));
+    assert!(output.contains(&"ab".repeat(16)));
+    if std::env::var(SMP_SHOW_KEYS_ENV).is_err() {
+        assert!(output.contains("ltk: [REDACTED]"));
+        assert!(!output.contains(&"cd".repeat(16)));
+    }
+    assert!(output.contains("dhkey: None"));
+}
+
+#[test]
+fn test_smp_manager_answers_pairing_request() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, addr);
+    mock.manager().get_fixed_channel(SMP_CID, 0x0040).unwrap();
+    let smp = new_smp_manager(&mock, MemoryKeyStore::new());
+
+    // Legacy Just Works request: NoInputNoOutput, bonding, 16 byte keys,
+    // encryption, identity and signing keys in both directions
+    let request = [0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07];
+    smp.handle_smp_packet(addr, &request).unwrap();
+    assert_eq!(
+        mock.sent_pdus(0x0040, SMP_CID),
+        vec![vec![0x02, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07]]
+    );
+
+    // A second request during pairing is refused without a response
+    assert!(matches!(
+        smp.handle_smp_packet(addr, &request),
+        Err(SmpError::InvalidState)
+    ));
+    assert_eq!(mock.sent_pdus(0x0040, SMP_CID).len(), 1);
+}
+
+#[test]
+fn test_smp_manager_answers_on_the_peers_connection() {
+    let first = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let second = BdAddr::new([0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, first);
+    mock.connect(0x0041, second);
+    let smp = new_smp_manager(&mock, MemoryKeyStore::new());
+
+    // The SMP channel is opened on the requesting peer's connection
+    smp.handle_smp_packet(second, &[0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07])
+        .unwrap();
+    assert!(mock.sent_pdus(0x0040, SMP_CID).is_empty());
+    assert_eq!(
+        mock.sent_pdus(0x0041, SMP_CID),
+        vec![vec![0x02, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07]]
+    );
+}
+
+#[test]
+fn test_smp_manager_start_encryption() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, addr);
+
+    let mut key_store = MemoryKeyStore::new();
+    let mut keys = DeviceKeys::new();
+    keys.ltk = Some(LongTermKey::new(
+        [0xAA; 16], 0x1234, [0x11; 8], false, false,
+    ));
+    key_store.save_keys(&addr, &keys).unwrap();
+
+    let smp = new_smp_manager(&mock, key_store);
+    smp.register_connection(0x0040, addr);
+
+    // Command Status for LE Enable Encryption, then Encryption Change
+    mock.hci()
+        .push_events(&[
+            0x04, 0x0F, 0x04, 0x00, 0x01, 0x19, 0x20, // Command Status
+            0x04, 0x08, 0x04, 0x00, 0x40, 0x00, 0x01, // Encryption Change
+        ])
+        .unwrap();
+    smp.start_encryption(addr).unwrap();
+
+    let mut params = vec![0x40, 0x00];
+    params.extend_from_slice(&[0x11; 8]);
+    params.extend_from_slice(&[0x34, 0x12]);
+    params.extend_from_slice(&[0xAA; 16]);
+    assert_eq!(mock.hci().sent_commands(), vec![(0x2019, params)]);
+
+    // The controller refusing the command is reported
+    mock.hci()
+        .push_events(&[0x04, 0x0F, 0x04, 0x06, 0x01, 0x19, 0x20])
+        .unwrap();
+    assert!(matches!(
+        smp.start_encryption(addr),
+        Err(SmpError::HciError(_))
+    ));
+
+    // Without keys there is nothing to encrypt with
+    let other = BdAddr::new([0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+    mock.connect(0x0041, other);
+    assert!(matches!(
+        smp.start_encryption(other),
+        Err(SmpError::NotPaired)
+    ));
+}
+
+#[test]
+fn test_smp_unauthenticated_secure_connections_key_gives_encryption_only() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, addr);
+
+    // A Just Works Secure Connections key is not authenticated
+    let mut key_store = MemoryKeyStore::new();
+    let mut keys = DeviceKeys::new();
+    keys.ltk = Some(LongTermKey::new_secure_connections([0xAA; 16], false));
+    key_store.save_keys(&addr, &keys).unwrap();
+    assert_eq!(keys.security_level(), SecurityLevel::EncryptionOnly);
+
+    let smp = new_smp_manager(&mock, key_store);
+    smp.register_connection(0x0040, addr);
+    mock.hci()
+        .push_events(&[
+            0x04, 0x0F, 0x04, 0x00, 0x01, 0x19, 0x20, // Command Status
+            0x04, 0x08, 0x04, 0x00, 0x40, 0x00, 0x01, // Encryption Change
+        ])
+        .unwrap();
+    smp.start_encryption(addr).unwrap();
+    assert_eq!(
+        smp.security_level(&addr).unwrap(),
+        SecurityLevel::EncryptionOnly
+    );
+
+    let authenticated = LongTermKey::new_secure_connections([0xAA; 16], true);
+    assert_eq!(
+        authenticated.security_level(),
+        SecurityLevel::SecureConnections
+    );
+}
+
+#[test]
+fn test_smp_responder_confirm_follows_initiator_confirm() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, addr);
+    mock.manager().get_fixed_channel(SMP_CID, 0x0040).unwrap();
+    let smp = new_smp_manager(&mock, MemoryKeyStore::new());
+
+    // Legacy Just Works: the responder only answers with a Pairing Response
+    let preq = [0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07];
+    let pres = [0x02, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07];
+    smp.handle_smp_packet(addr, &preq).unwrap();
+    assert_eq!(mock.sent_pdus(0x0040, SMP_CID), vec![pres.to_vec()]);
+
+    // A random before the confirm values are exchanged is refused
+    let mrand = [0x5A; 16];
+    let mut random = vec![0x04];
+    random.extend_from_slice(&mrand);
+    assert!(matches!(
+        smp.handle_smp_packet(addr, &random),
+        Err(SmpError::InvalidState)
+    ));
+    assert_eq!(mock.sent_pdus(0x0040, SMP_CID).len(), 1);
+
+    // The initiator's confirm is answered with the responder's
+    let mconfirm = c1(
+        &SoftwareAes,
+        &[0; 16],
+        &mrand,
+        &preq,
+        &pres,
+        0,
+        &[0; 6],
+        0,
+        &[0; 6],
+    )
+    .unwrap();
+    let mut confirm = vec![0x03];
+    confirm.extend_from_slice(&mconfirm);
+    smp.handle_smp_packet(addr, &confirm).unwrap();
+    let sent = mock.sent_pdus(0x0040, SMP_CID);
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1][0], 0x03);
+    assert_eq!(sent[1].len(), 17);
+
+    // Only one confirm is taken
+    assert!(matches!(
+        smp.handle_smp_packet(addr, &confirm),
+        Err(SmpError::InvalidState)
+    ));
+
+    // The initiator's random is verified and answered with the responder's
+    smp.handle_smp_packet(addr, &random).unwrap();
+    let sent = mock.sent_pdus(0x0040, SMP_CID);
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[2][0], 0x04);
+    assert_eq!(sent[2].len(), 17);
+}
+
+#[test]
+fn test_smp_dhkey_check_outside_secure_connections_rejected() {
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+    mock.connect(0x0040, addr);
+    mock.manager().get_fixed_channel(SMP_CID, 0x0040).unwrap();
+    let smp = new_smp_manager(&mock, MemoryKeyStore::new());
+
+    // Legacy Just Works pairing never exchanges DHKey checks
+    smp.handle_smp_packet(addr, &[0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07])
+        .unwrap();
+
+    let mut dhkey_check = vec![0x0D];
+    dhkey_check.extend_from_slice(&[0u8; 16]);
+    assert!(matches!(
+        smp.handle_smp_packet(addr, &dhkey_check),
+        Err(SmpError::InvalidState)
+    ));
+
+    // Pairing is not completed and nothing is sent in reply
+    assert_eq!(mock.sent_pdus(0x0040, SMP_CID).len(), 1);
+    assert!(!smp.is_paired(&addr).unwrap());
+}
+
+/// One side of an LE link for pairing two SMP managers with each other
+struct PairingPeer {
+    mock: MockL2capManager,
+    smp: SmpManager,
+    handle: u16,
+    completed: Arc<Mutex<Vec<bool>>>,
+}
+
+impl PairingPeer {
+    /// Connect to `peer_addr` with `role` (0 central, 1 peripheral), as
+    /// reported by the LE Connection Complete event
+    fn new(handle: u16, role: u8, local: (u8, BdAddr), peer: (u8, BdAddr)) -> Self {
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        mock.connect(handle, peer.1);
+        mock.manager().get_fixed_channel(SMP_CID, handle).unwrap();
+
+        let mut smp = new_smp_manager(&mock, MemoryKeyStore::new());
+        smp.set_auth_requirements(AuthRequirements::new(false, false, true));
+        smp.set_local_address(local.0, local.1);
+
+        let [handle_lo, handle_hi] = handle.to_le_bytes();
+        let mut params = vec![
+            EVT_LE_CONN_COMPLETE,
+            0x00,
+            handle_lo,
+            handle_hi,
+            role,
+            peer.0,
+        ];
+        params.extend_from_slice(&peer.1.bytes);
+        params.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+        let mut packet = vec![EVT_LE_META_EVENT, params.len() as u8];
+        packet.extend_from_slice(&params);
+        smp.handle_hci_event(&HciEvent::parse(&packet).unwrap())
+            .unwrap();
+
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let events = completed.clone();
+        smp.set_event_callback(move |event| {
+            if let SmpEvent::PairingComplete(_, success) = event {
+                events.lock().unwrap().push(success);
+            }
+            Ok(())
+        });
+
+        Self {
+            mock,
+            smp,
+            handle,
+            completed,
+        }
+    }
+
+    fn sent(&self) -> Vec<Vec<u8>> {
+        self.mock.sent_pdus(self.handle, SMP_CID)
+    }
+}
+
+/// Carry SMP PDUs between two peers until neither has anything more to say
+fn exchange_pdus(
+    central: &PairingPeer,
+    central_addr: BdAddr,
+    peripheral: &PairingPeer,
+    peripheral_addr: BdAddr,
+) {
+    let (mut to_peripheral, mut to_central) = (0, 0);
+    loop {
+        let from_central = central.sent();
+        let from_peripheral = peripheral.sent();
+        if from_central.len() == to_peripheral && from_peripheral.len() == to_central {
+            break;
+        }
+        for pdu in &from_central[to_peripheral..] {
+            peripheral.smp.handle_smp_packet(central_addr, pdu).unwrap();
+        }
+        to_peripheral = from_central.len();
+        for pdu in &from_peripheral[to_central..] {
+            central.smp.handle_smp_packet(peripheral_addr, pdu).unwrap();
+        }
+        to_central = from_peripheral.len();
+    }
+}
+
+fn opcodes(pdus: &[Vec<u8>]) -> Vec<u8> {
+    pdus.iter().map(|pdu| pdu[0]).collect()
+}
+
+#[test]
+fn test_secure_connections_just_works_dhkey_checks() {
+    // A central on a static random address and a peripheral on its public
+    // one, so a mixed up address or address type breaks the check values
+    let central_addr = BdAddr::new([0x11, 0x22, 0x33, 0x44, 0x55, 0xC6]);
+    let peripheral_addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let central = PairingPeer::new(
+        0x0040,
+        0x00,
+        (RANDOM_DEVICE_ADDRESS, central_addr),
+        (PUBLIC_DEVICE_ADDRESS, peripheral_addr),
+    );
+    let peripheral = PairingPeer::new(
+        0x0041,
+        0x01,
+        (PUBLIC_DEVICE_ADDRESS, peripheral_addr),
+        (RANDOM_DEVICE_ADDRESS, central_addr),
+    );
+
+    central.smp.initiate_pairing(peripheral_addr).unwrap();
+    exchange_pdus(&central, central_addr, &peripheral, peripheral_addr);
+
+    // Public keys, then Cb, Na and Nb, then Ea and Eb
+    assert_eq!(opcodes(&central.sent()), vec![0x01, 0x0C, 0x04, 0x0D]);
+    assert_eq!(
+        opcodes(&peripheral.sent()),
+        vec![0x02, 0x0C, 0x03, 0x04, 0x0D]
+    );
+    assert_eq!(*central.completed.lock().unwrap(), vec![true]);
+    assert_eq!(*peripheral.completed.lock().unwrap(), vec![true]);
+
+    // A responder that gets the initiator's address type wrong computes a
+    // different Ea and fails the pairing
+    let central = PairingPeer::new(
+        0x0040,
+        0x00,
+        (RANDOM_DEVICE_ADDRESS, central_addr),
+        (PUBLIC_DEVICE_ADDRESS, peripheral_addr),
+    );
+    let peripheral = PairingPeer::new(
+        0x0041,
+        0x01,
+        (PUBLIC_DEVICE_ADDRESS, peripheral_addr),
+        (PUBLIC_DEVICE_ADDRESS, central_addr),
+    );
+
+    central.smp.initiate_pairing(peripheral_addr).unwrap();
+    exchange_pdus(&central, central_addr, &peripheral, peripheral_addr);
+
+    let sent = peripheral.sent();
+    assert_eq!(opcodes(&sent), vec![0x02, 0x0C, 0x03, 0x04, 0x05]);
+    assert_eq!(sent[4], vec![0x05, 0x0B]); // DHKey Check Failed
+    assert!(peripheral.completed.lock().unwrap().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_file_key_store_survives_reopen() {
+    use super::{ConnectionSignatureResolvingKey, FileKeyStore, IdentityResolvingKey};
+
+    let path = std::env::temp_dir().join(format!("rustyblue-keys-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mut keys = DeviceKeys::new();
+    keys.ltk = Some(LongTermKey::new([0x11; 16], 0x1234, [0x22; 8], false, true));
+    keys.local_ltk = Some(LongTermKey::new_secure_connections([0x33; 16], false));
+    keys.irk = Some(IdentityResolvingKey::new(
+        [0x44; 16],
+        0x01,
+        BdAddr::new([0xC0, 0xFF, 0xEE, 0x00, 0x11, 0x22]),
+    ));
+    let mut csrk = ConnectionSignatureResolvingKey::new([0x55; 16], true);
+    csrk.increment_counter();
+    keys.remote_csrk = Some(csrk);
+
+    {
+        let mut store = FileKeyStore::open(&path).unwrap();
+        assert!(store.get_paired_devices().unwrap().is_empty());
+        store.save_keys(&addr, &keys).unwrap();
+    }
+
+    // Keys are secrets, so only the owner may read them
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // A fresh store reads the bond back from the file
+    let mut store = FileKeyStore::open(&path).unwrap();
+    assert_eq!(store.get_paired_devices().unwrap(), vec![addr]);
+    let loaded = store.load_keys(&addr).unwrap().unwrap();
+    let ltk = loaded.ltk.unwrap();
+    assert_eq!(ltk.key, [0x11; 16]);
+    assert_eq!(ltk.ediv, 0x1234);
+    assert_eq!(ltk.rand, [0x22; 8]);
+    assert!(!ltk.secure_connections && ltk.authenticated);
+    let local_ltk = loaded.local_ltk.unwrap();
+    assert!(local_ltk.secure_connections && !local_ltk.authenticated);
+    let irk = loaded.irk.unwrap();
+    assert_eq!(irk.key, [0x44; 16]);
+    assert_eq!(irk.identity_address_type, 0x01);
+    assert_eq!(
+        irk.identity_address,
+        BdAddr::new([0xC0, 0xFF, 0xEE, 0x00, 0x11, 0x22])
+    );
+    let csrk = loaded.remote_csrk.unwrap();
+    assert_eq!(
+        (csrk.key, csrk.sign_counter, csrk.authenticated),
+        ([0x55; 16], 1, true)
+    );
+    assert!(loaded.local_csrk.is_none() && loaded.link_key.is_none());
+
+    // Nothing is left behind by the atomic write, and deletes persist too
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    assert!(!std::path::Path::new(&tmp_path).exists());
+    store.delete_keys(&addr).unwrap();
+    drop(store);
+    assert!(FileKeyStore::open(&path)
+        .unwrap()
+        .load_keys(&addr)
+        .unwrap()
+        .is_none());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_file_key_store_keeps_memory_in_sync_on_write_failure() {
+    use super::FileKeyStore;
+
+    // The directory does not exist, so nothing can be written
+    let path = std::env::temp_dir()
+        .join(format!("rustyblue-missing-{}", std::process::id()))
+        .join("keys.json");
+    let mut store = FileKeyStore::open(&path).unwrap();
+
+    let addr = BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let mut keys = DeviceKeys::new();
+    keys.ltk = Some(LongTermKey::new([0x11; 16], 0x1234, [0x22; 8], false, true));
+    assert!(store.save_keys(&addr, &keys).is_err());
+    assert!(store.load_keys(&addr).unwrap().is_none());
+    assert!(store.get_paired_devices().unwrap().is_empty());
+}
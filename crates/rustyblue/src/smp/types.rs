@@ -1,4 +1,5 @@
 //! Type definitions for the Security Manager Protocol
+use super::crypto::AesBackend;
 use crate::gap::BdAddr;
 use std::fmt;
 use thiserror::Error;
@@ -210,24 +211,26 @@ pub struct AuthRequirements {
     pub ct2: bool,
 }
 
-impl AuthRequirements {
-    /// Create new authentication requirements
-    pub fn new(bonding: bool, mitm: bool, secure_connections: bool) -> Self {
+impl Default for AuthRequirements {
+    /// Bonding enabled, everything else disabled
+    fn default() -> Self {
         Self {
-            bonding,
-            mitm,
-            secure_connections,
+            bonding: true,
+            mitm: false,
+            secure_connections: false,
             keypress_notifications: false,
             ct2: false,
         }
     }
+}
 
-    /// Create with default values (bonding enabled, others disabled)
-    pub fn default() -> Self {
+impl AuthRequirements {
+    /// Create new authentication requirements
+    pub fn new(bonding: bool, mitm: bool, secure_connections: bool) -> Self {
         Self {
-            bonding: true,
-            mitm: false,
-            secure_connections: false,
+            bonding,
+            mitm,
+            secure_connections,
             keypress_notifications: false,
             ct2: false,
         }
@@ -367,7 +370,7 @@ impl KeyDistribution {
 }
 
 /// SMP OOB (Out of Band) data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct OobData {
     /// Random value (r)
     pub r: [u8; 16],
@@ -375,12 +378,40 @@ pub struct OobData {
     pub c: [u8; 16],
 }
 
-impl Default for OobData {
-    fn default() -> Self {
-        Self {
-            r: [0; 16],
-            c: [0; 16],
-        }
+impl OobData {
+    /// Create LE Secure Connections OOB data for a public key and random value
+    ///
+    /// `public_key` is the 64 byte X || Y key as sent in the Pairing Public
+    /// Key PDU. The confirm value commits to its X coordinate.
+    pub fn new(aes: &dyn AesBackend, public_key: &[u8; 64], r: [u8; 16]) -> SmpResult<Self> {
+        Ok(Self {
+            r,
+            c: Self::confirm_value(aes, public_key, &r)?,
+        })
+    }
+
+    /// Check that this OOB data was generated for the given public key
+    pub fn matches(&self, aes: &dyn AesBackend, public_key: &[u8; 64]) -> SmpResult<bool> {
+        Ok(Self::confirm_value(aes, public_key, &self.r)? == self.c)
+    }
+
+    /// c = f4(PKx, PKx, r, 0)
+    ///
+    /// The key and nonce are kept in SMP byte order, so they are reversed
+    /// into the most significant octet first order f4 works in.
+    fn confirm_value(
+        aes: &dyn AesBackend,
+        public_key: &[u8; 64],
+        r: &[u8; 16],
+    ) -> SmpResult<[u8; 16]> {
+        let mut x = [0u8; 32];
+        x.copy_from_slice(&public_key[..32]);
+        x.reverse();
+        let mut r = *r;
+        r.reverse();
+        let mut c = super::crypto::f4(aes, &x, &x, &r, 0)?;
+        c.reverse();
+        Ok(c)
     }
 }
 
@@ -0,0 +1,338 @@
+//! Mock HCI and L2CAP layers for testing
+//!
+//! `MockHciSocket` stands in for the controller: it feeds a script of HCI
+//! events to an `HciSocket` and records every packet the host writes.
+//! `MockL2capManager` puts an `L2capManager` on top of it, so ATT, GATT and
+//! SMP can be driven by injecting PDUs and checking the PDUs they send back,
+//! without a Bluetooth adapter.
+//!
+//! Available in the crate's own tests and with the `testing` feature.
+
+use crate::gap::BdAddr;
+use crate::hci::constants::{HCI_ACL_PKT, HCI_COMMAND_PKT, HCI_EVENT_PKT};
+use crate::hci::{AclFlowController, BufferInfo, HciSocket, LocalControllerInfo};
+use crate::l2cap::packet::L2capPacket;
+use crate::l2cap::{ConnectionType, L2capManager, L2capResult};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+/// Largest HCI packet: indicator, ACL header and a full-length payload
+const MAX_PACKET_SIZE: usize = 1 + 4 + u16::MAX as usize;
+
+/// Controller buffers reported by `MockL2capManager`
+const MOCK_ACL_BUFFER: BufferInfo = BufferInfo {
+    acl_data_packet_length: 251,
+    total_num_acl_data_packets: u16::MAX,
+};
+
+/// An HCI socket connected to a scripted controller
+///
+/// The host end is a regular `HciSocket`; the controller end is the other
+/// half of a socket pair, which delivers the scripted events and collects
+/// the commands and ACL data written by the host.
+pub struct MockHciSocket {
+    socket: Arc<HciSocket>,
+    controller: RawFd,
+    sent: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockHciSocket {
+    /// Create a mock controller with no pending events
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        let result =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            socket: Arc::new(HciSocket::from_raw_fd(fds[0])),
+            controller: fds[1],
+            sent: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Create a mock controller that delivers a script of HCI events
+    ///
+    /// The script is a sequence of complete event packets, each starting
+    /// with the HCI event indicator (0x04), as read from an HCI socket.
+    pub fn with_script(script: Vec<u8>) -> io::Result<Self> {
+        let mock = Self::new()?;
+        mock.push_events(&script)?;
+        Ok(mock)
+    }
+
+    /// Queue more HCI event packets for the host to read
+    ///
+    /// Fails with `InvalidData` if the script does not split into complete
+    /// event packets; events before the bad one are still queued.
+    pub fn push_events(&self, script: &[u8]) -> io::Result<()> {
+        let mut rest = script;
+        while !rest.is_empty() {
+            if rest.len() < 3 || rest[0] != HCI_EVENT_PKT {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Expected an HCI event packet",
+                ));
+            }
+
+            let len = 3 + rest[2] as usize;
+            if rest.len() < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated HCI event packet",
+                ));
+            }
+
+            let (packet, tail) = rest.split_at(len);
+            let written = unsafe {
+                libc::send(
+                    self.controller,
+                    packet.as_ptr() as *const libc::c_void,
+                    packet.len(),
+                    0,
+                )
+            };
+            if written < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            rest = tail;
+        }
+
+        Ok(())
+    }
+
+    /// Queue an ACL data packet for the host to read
+    ///
+    /// `handle_and_flags` carries the connection handle with the PB and BC
+    /// flags in the top four bits, as on the wire.
+    pub fn push_acl(&self, handle_and_flags: u16, data: &[u8]) -> io::Result<()> {
+        let mut packet = vec![HCI_ACL_PKT];
+        packet.extend_from_slice(&handle_and_flags.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+
+        let written = unsafe {
+            libc::send(
+                self.controller,
+                packet.as_ptr() as *const libc::c_void,
+                packet.len(),
+                0,
+            )
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Get the host end of the connection
+    pub fn socket(&self) -> Arc<HciSocket> {
+        self.socket.clone()
+    }
+
+    /// Get every packet written by the host so far, in order
+    ///
+    /// Each packet starts with its HCI packet type indicator.
+    pub fn sent_packets(&self) -> Vec<Vec<u8>> {
+        let mut sent = self.sent.lock().unwrap();
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.controller,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len <= 0 {
+                break;
+            }
+            sent.push(buffer[..len as usize].to_vec());
+        }
+        sent.clone()
+    }
+
+    /// Get the HCI commands sent so far as (opcode, parameters)
+    pub fn sent_commands(&self) -> Vec<(u16, Vec<u8>)> {
+        self.sent_packets()
+            .into_iter()
+            .filter(|packet| packet.len() >= 4 && packet[0] == HCI_COMMAND_PKT)
+            .map(|packet| {
+                let opcode = u16::from_le_bytes([packet[1], packet[2]]);
+                (opcode, packet[4..].to_vec())
+            })
+            .collect()
+    }
+
+    /// Get the HCI ACL data packets sent so far as (handle and flags, data)
+    pub fn sent_acl_packets(&self) -> Vec<(u16, Vec<u8>)> {
+        self.sent_packets()
+            .into_iter()
+            .filter(|packet| packet.len() >= 5 && packet[0] == HCI_ACL_PKT)
+            .map(|packet| {
+                let handle_and_flags = u16::from_le_bytes([packet[1], packet[2]]);
+                (handle_and_flags, packet[5..].to_vec())
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockHciSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.controller);
+        }
+    }
+}
+
+/// An L2CAP manager whose peer is driven by the test
+///
+/// Injected PDUs go through `L2capManager::handle_packet`, so they reach the
+/// callbacks registered by ATT, GATT or SMP exactly as received data would.
+/// Outgoing PDUs are sent over a `MockHciSocket` and reassembled per
+/// connection and channel.
+pub struct MockL2capManager {
+    manager: Arc<L2capManager>,
+    hci: MockHciSocket,
+    flow: Arc<AclFlowController>,
+}
+
+impl MockL2capManager {
+    /// Create a mock L2CAP layer for a type of connection
+    pub fn new(connection_type: ConnectionType) -> io::Result<Self> {
+        let hci = MockHciSocket::new()?;
+        let manager = Arc::new(L2capManager::with_hci_socket(connection_type, hci.socket()));
+        let flow = manager.set_acl_transport(
+            hci.socket(),
+            &LocalControllerInfo {
+                classic_buffer: Some(MOCK_ACL_BUFFER),
+                le_buffer: MOCK_ACL_BUFFER,
+            },
+        );
+
+        Ok(Self { manager, hci, flow })
+    }
+
+    /// Get the L2CAP manager to hand to the layer under test
+    pub fn manager(&self) -> Arc<L2capManager> {
+        self.manager.clone()
+    }
+
+    /// Get the mock controller underneath
+    pub fn hci(&self) -> &MockHciSocket {
+        &self.hci
+    }
+
+    /// Get the ACL flow controller of the mock transport
+    pub fn flow(&self) -> Arc<AclFlowController> {
+        self.flow.clone()
+    }
+
+    /// Register an HCI connection to a peer
+    pub fn connect(&self, hci_handle: u16, addr: BdAddr) {
+        self.manager.register_connection(hci_handle, addr);
+    }
+
+    /// Deliver a PDU from the peer on a channel of a connection
+    pub fn inject(&self, hci_handle: u16, cid: u16, data: &[u8]) -> L2capResult<()> {
+        self.manager
+            .handle_packet(L2capPacket::new(cid, data.to_vec()), hci_handle)
+    }
+
+    /// Get the PDUs sent so far on a channel of a connection, in order
+    pub fn sent_pdus(&self, hci_handle: u16, cid: u16) -> Vec<Vec<u8>> {
+        self.sent_frames()
+            .into_iter()
+            .filter(|&(handle, frame_cid, _)| handle == hci_handle && frame_cid == cid)
+            .map(|(_, _, payload)| payload)
+            .collect()
+    }
+
+    /// Reassemble the sent ACL packets into (handle, CID, payload) frames
+    fn sent_frames(&self) -> Vec<(u16, u16, Vec<u8>)> {
+        let mut frames = Vec::new();
+        let mut partial: HashMap<u16, Vec<u8>> = HashMap::new();
+
+        for (handle_and_flags, data) in self.hci.sent_acl_packets() {
+            let handle = handle_and_flags & 0x0FFF;
+            let pb_flag = (handle_and_flags >> 12) & 0x03;
+
+            let buffer = partial.entry(handle).or_default();
+            if pb_flag != 0x01 {
+                // A start fragment discards anything left unfinished
+                buffer.clear();
+            }
+            buffer.extend_from_slice(&data);
+
+            if buffer.len() < 4 {
+                continue;
+            }
+            let len = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+            if buffer.len() < 4 + len {
+                continue;
+            }
+
+            let cid = u16::from_le_bytes([buffer[2], buffer[3]]);
+            frames.push((handle, cid, buffer[4..4 + len].to_vec()));
+            buffer.clear();
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_hci_socket_plays_script() {
+        // Command Complete for HCI Reset followed by a Disconnection Complete
+        let script = vec![
+            0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00, // Command Complete
+            0x04, 0x05, 0x04, 0x00, 0x40, 0x00, 0x13, // Disconnection Complete
+        ];
+        let mock = MockHciSocket::with_script(script).unwrap();
+        let socket = mock.socket();
+
+        assert_eq!(socket.read_event().unwrap().event_code, 0x0E);
+        assert_eq!(socket.read_event().unwrap().event_code, 0x05);
+
+        // A script that does not split into events is rejected
+        assert!(mock.push_events(&[0x04, 0x0E, 0x04, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_mock_hci_socket_records_commands() {
+        let mock = MockHciSocket::new().unwrap();
+        mock.socket()
+            .write_packet(&[HCI_COMMAND_PKT, 0x03, 0x0C, 0x00])
+            .unwrap();
+        mock.socket().send_acl(0x0040, 0x02, 0x00, &[0xAA]).unwrap();
+
+        assert_eq!(mock.sent_packets().len(), 2);
+        assert_eq!(mock.sent_commands(), vec![(0x0C03, Vec::new())]);
+        assert_eq!(mock.sent_acl_packets(), vec![(0x2040, vec![0xAA])]);
+    }
+
+    #[test]
+    fn test_mock_l2cap_manager_reassembles_sent_pdus() {
+        let mock = MockL2capManager::new(ConnectionType::LE).unwrap();
+        mock.connect(0x0040, BdAddr::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]));
+
+        let socket = mock.hci().socket();
+        // A 6 byte L2CAP frame for CID 0x0004 split over two ACL packets
+        socket
+            .send_acl(0x0040, 0x02, 0x00, &[0x02, 0x00, 0x04, 0x00])
+            .unwrap();
+        socket.send_acl(0x0040, 0x01, 0x00, &[0x0A, 0x01]).unwrap();
+
+        assert_eq!(mock.sent_pdus(0x0040, 0x0004), vec![vec![0x0A, 0x01]]);
+        assert!(mock.sent_pdus(0x0040, 0x0006).is_empty());
+    }
+}
@@ -215,7 +215,7 @@ impl PartialEq<Uuid> for [u8; 16] {
 
 impl<'a> PartialEq<&'a [u8]> for Uuid {
     fn eq(&self, other: &&'a [u8]) -> bool {
-        Uuid::try_from_slice_le(other).map_or(false, |uuid| *self == uuid)
+        Uuid::try_from_slice_le(other) == Some(*self)
     }
 }
 